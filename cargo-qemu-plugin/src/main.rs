@@ -0,0 +1,102 @@
+//! `cargo qemu-plugin new`, which creates a plugin crate from the same template as
+//! `cargo generate`
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use std::{
+    fs::{create_dir, create_dir_all, write},
+    path::PathBuf,
+};
+
+/// The template's files, by their path in a new crate
+const TEMPLATE: &[(&str, &str)] = &[
+    ("Cargo.toml", include_str!("../template/Cargo.toml.liquid")),
+    (".gitignore", include_str!("../template/.gitignore")),
+    ("build.rs", include_str!("../template/build.rs")),
+    ("src/lib.rs", include_str!("../template/src/lib.rs")),
+];
+
+#[derive(Parser, Debug)]
+#[command(bin_name = "cargo")]
+enum Cargo {
+    #[command(subcommand, name = "qemu-plugin")]
+    /// Tools for QEMU plugin crates
+    QemuPlugin(Command),
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create a new QEMU plugin crate
+    New {
+        /// The directory to create the crate in, which must not exist
+        path: PathBuf,
+        #[clap(long)]
+        /// The name of the crate, otherwise the name of the directory
+        name: Option<String>,
+    },
+}
+
+/// Check that `name` is usable as a package name, and return the name of its library
+fn crate_name(name: &str) -> Result<String> {
+    if name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!(
+            "Invalid crate name '{name}': names use letters, digits, '-' and '_', and do not \
+             start with a digit"
+        ));
+    }
+
+    Ok(name.replace('-', "_"))
+}
+
+fn new(path: PathBuf, name: Option<String>) -> Result<()> {
+    let name = match name {
+        Some(name) => name,
+        None => path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| anyhow!("No crate name given and none in '{}'", path.display()))?,
+    };
+    let crate_name = crate_name(&name)?;
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        create_dir_all(parent)?;
+    }
+
+    create_dir(&path).map_err(|e| anyhow!("Failed to create '{}': {e}", path.display()))?;
+
+    for (file, contents) in TEMPLATE {
+        let file = path.join(file);
+
+        if let Some(parent) = file.parent() {
+            create_dir_all(parent)?;
+        }
+
+        write(
+            file,
+            contents
+                .replace("{{project-name}}", &name)
+                .replace("{{crate_name}}", &crate_name),
+        )?;
+    }
+
+    println!(
+        "Created QEMU plugin '{name}' in {}. Build it with `cargo build -r` and load it with \
+         `qemu-<arch> -plugin target/release/lib{crate_name}.so`.",
+        path.display()
+    );
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let Cargo::QemuPlugin(command) = Cargo::parse();
+
+    match command {
+        Command::New { path, name } => new(path, name),
+    }
+}
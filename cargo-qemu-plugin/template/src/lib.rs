@@ -0,0 +1,34 @@
+use anyhow::{anyhow, Result};
+use ctor::ctor;
+use qemu_plugin::{
+    plugin::{HasCallbacks, Plugin, Register, PLUGIN},
+    PluginId, TranslationBlock,
+};
+use std::sync::Mutex;
+
+/// Prints each instruction as it is translated
+struct Tracer;
+
+impl Plugin for Tracer {}
+impl Register for Tracer {}
+
+impl HasCallbacks for Tracer {
+    fn on_translation_block_translate(
+        &mut self,
+        _id: PluginId,
+        tb: TranslationBlock,
+    ) -> Result<()> {
+        tb.instructions().try_for_each(|insn| {
+            println!("{:08x}: {}", insn.vaddr(), insn.disas()?);
+            Ok(())
+        })
+    }
+}
+
+#[ctor]
+fn init() {
+    PLUGIN
+        .set(Mutex::new(Box::new(Tracer)))
+        .map_err(|_| anyhow!("Failed to set plugin"))
+        .expect("Failed to set plugin");
+}
@@ -0,0 +1,3 @@
+fn main() {
+    qemu_plugin_build::Build::new().emit();
+}
@@ -0,0 +1,147 @@
+//! Compares the cost of recording executed instructions as individual CBOR events against
+//! the compact pc batch encoding, and of sharing one map of encoders between vCPU threads
+//! against giving each vCPU its own slot
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use std::{collections::HashMap, sync::Mutex, thread::scope};
+#[cfg(not(feature = "plugin-api-v1"))]
+use tracer::Registers;
+use tracer::{
+    encoding::{PcBuffers, PcEncoder, BATCH_SIZE},
+    Event, InstructionEvent,
+};
+
+/// The number of vCPU threads logging pcs concurrently
+const VCPUS: u32 = 4;
+
+/// Addresses resembling a loop over a few basic blocks of variable-length instructions
+fn pcs() -> Vec<u64> {
+    let lengths = [1u64, 3, 2, 5, 4, 7, 2, 3];
+
+    (0..BATCH_SIZE)
+        .scan(0x401000u64, |pc, i| {
+            *pc = if i % 64 == 0 {
+                0x401000
+            } else {
+                *pc + lengths[i % lengths.len()]
+            };
+            Some(*pc)
+        })
+        .collect()
+}
+
+/// The event sent for each executed instruction without the compact encoding
+fn instruction_event(pc: u64) -> Event {
+    let event = InstructionEvent::builder()
+        .vaddr(pc)
+        .haddr(pc)
+        .disas("mov eax, dword ptr [rbp - 0x14]".to_string())
+        .symbol(None)
+        .data(vec![0x8b, 0x45, 0xec])
+        .build();
+
+    #[cfg(feature = "plugin-api-v1")]
+    {
+        Event::Instruction { event }
+    }
+    #[cfg(not(feature = "plugin-api-v1"))]
+    {
+        Event::Instruction {
+            event,
+            registers: Registers(HashMap::new()),
+        }
+    }
+}
+
+fn encoding(c: &mut Criterion) {
+    let pcs = pcs();
+    let mut group = c.benchmark_group("pcs");
+    group.throughput(Throughput::Elements(pcs.len() as u64));
+
+    group.bench_function("cbor_per_event", |b| {
+        let mut out = Vec::new();
+
+        b.iter(|| {
+            out.clear();
+
+            for pc in &pcs {
+                serde_cbor::to_writer(&mut out, &instruction_event(black_box(*pc)))
+                    .expect("Failed to encode event");
+            }
+        })
+    });
+
+    group.bench_function("varint_delta_batch", |b| {
+        let mut out = Vec::new();
+
+        b.iter(|| {
+            out.clear();
+            let mut encoder = PcEncoder::default();
+
+            for pc in &pcs {
+                encoder.push(black_box(*pc));
+            }
+
+            serde_cbor::to_writer(&mut out, &encoder.take(0)).expect("Failed to encode batch");
+        })
+    });
+
+    group.finish();
+}
+
+fn contention(c: &mut Criterion) {
+    let pcs = pcs();
+    let mut group = c.benchmark_group("pcs_per_vcpu");
+    group.throughput(Throughput::Elements(pcs.len() as u64 * VCPUS as u64));
+
+    group.bench_function("shared_map", |b| {
+        let encoders = Mutex::new(HashMap::<u32, PcEncoder>::new());
+
+        b.iter(|| {
+            scope(|s| {
+                for vcpu_index in 0..VCPUS {
+                    let (encoders, pcs) = (&encoders, &pcs);
+
+                    s.spawn(move || {
+                        for pc in pcs {
+                            let mut encoders = encoders.lock().expect("Failed to lock pcs");
+                            let encoder = encoders.entry(vcpu_index).or_default();
+                            encoder.push(black_box(*pc));
+
+                            if encoder.is_full() {
+                                black_box(encoder.take(vcpu_index));
+                            }
+                        }
+                    });
+                }
+            })
+        })
+    });
+
+    group.bench_function("buffers", |b| {
+        let buffers = PcBuffers::default();
+
+        b.iter(|| {
+            scope(|s| {
+                for vcpu_index in 0..VCPUS {
+                    let (buffers, pcs) = (&buffers, &pcs);
+
+                    s.spawn(move || {
+                        for pc in pcs {
+                            black_box(
+                                buffers
+                                    .push(vcpu_index, black_box(*pc))
+                                    .expect("Failed to push pc"),
+                            );
+                        }
+                    });
+                }
+            })
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, encoding, contention);
+criterion_main!(benches);
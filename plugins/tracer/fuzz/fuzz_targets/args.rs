@@ -0,0 +1,37 @@
+//! Parses arbitrary strings as the values of plugin arguments. The first byte picks the
+//! parser, as in the seeds.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::str::FromStr;
+use tracer::{
+    bookmarks::PcBookmark,
+    dump::DumpRange,
+    fuzz::{input::InputTarget, parse_markers},
+    limits::LimitPolicy,
+    memmap::parse_addr,
+    probes::EntropyProbe,
+    throttle::{EventClass, Throttle},
+};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((parser, value)) = data.split_first() else {
+        return;
+    };
+    let Ok(value) = std::str::from_utf8(value) else {
+        return;
+    };
+
+    match parser % 9 {
+        0 => drop(Throttle::parse(value)),
+        1 => drop(EventClass::from_str(value)),
+        2 => drop(DumpRange::parse_list(value)),
+        3 => drop(EntropyProbe::parse_list(value)),
+        4 => drop(PcBookmark::from_str(value)),
+        5 => drop(parse_markers(value)),
+        6 => drop(parse_addr(value)),
+        7 => drop(LimitPolicy::from_str(value)),
+        _ => drop(InputTarget::from_str(value)),
+    }
+});
@@ -0,0 +1,25 @@
+//! Parses arbitrary strings as the files the plugin loads its configuration from. The first
+//! byte picks the parser, as in the seeds.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tracer::{api::ApiProfile, memmap::MemoryMap, redact::RedactionPolicy, yara::parse_rules};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((parser, value)) = data.split_first() else {
+        return;
+    };
+    let Ok(value) = std::str::from_utf8(value) else {
+        return;
+    };
+
+    match parser % 6 {
+        0 => drop(ApiProfile::parse(value, false)),
+        1 => drop(ApiProfile::parse(value, true)),
+        2 => drop(RedactionPolicy::parse(value, false)),
+        3 => drop(RedactionPolicy::parse(value, true)),
+        4 => drop(MemoryMap::parse_mtree(value)),
+        _ => drop(parse_rules(value)),
+    }
+});
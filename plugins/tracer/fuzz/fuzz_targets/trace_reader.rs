@@ -0,0 +1,12 @@
+//! Reads arbitrary bytes as a trace file, as a crashed capture may have left it
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tracer::{tracefile::TraceReader, Event};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(reader) = TraceReader::from_bytes(data.to_vec()) {
+        let _ = reader.records::<Event>();
+    }
+});
@@ -0,0 +1,119 @@
+//! Writes seed corpora for the fuzz targets with the tracer's own writers, so that fuzzing
+//! starts from well-formed traces and configurations. Run from this directory with
+//! `cargo run --example seeds` before the first `cargo fuzz run`.
+
+use anyhow::Result;
+use std::{
+    fs::{create_dir_all, read, write},
+    path::Path,
+};
+use tracer::{
+    api::ApiProfile,
+    bookmarks::{BookmarkEvent, BookmarkSource},
+    redact::RedactionPolicy,
+    throttle::{DroppedEvent, EventClass},
+    tracefile::TraceFile,
+    Event,
+};
+
+const CORPUS: &str = "corpus";
+
+/// A trace file with a few events, as the plugin writes it
+fn trace(path: &Path, events: u64) -> Result<()> {
+    let mut trace = TraceFile::create(path)?;
+
+    for icount in 0..events {
+        trace.write_record(&Event::Bookmark(
+            BookmarkEvent::builder()
+                .vcpu_index(0)
+                .name(format!("bookmark-{icount}"))
+                .source(BookmarkSource::Hypercall)
+                .icount(icount)
+                .build(),
+        ))?;
+        trace.write_record(&Event::Dropped(DroppedEvent {
+            class: EventClass::Memory,
+            vcpu_index: 1,
+            count: icount,
+            icount,
+        }))?;
+    }
+
+    trace.finish()
+}
+
+fn trace_reader() -> Result<()> {
+    let dir = Path::new(CORPUS).join("trace_reader");
+    create_dir_all(&dir)?;
+
+    trace(&dir.join("empty"), 0)?;
+    trace(&dir.join("events"), 8)?;
+
+    // A capture which crashed partway through writing its last record
+    let data = read(dir.join("events"))?;
+    write(dir.join("truncated"), &data[..data.len() - 5])?;
+
+    Ok(())
+}
+
+/// Writes each seed prefixed by the byte which picks its parser
+fn seeds(target: &str, seeds: &[(u8, String)]) -> Result<()> {
+    let dir = Path::new(CORPUS).join(target);
+    create_dir_all(&dir)?;
+
+    for (i, (parser, seed)) in seeds.iter().enumerate() {
+        write(
+            dir.join(format!("seed-{i}")),
+            [&[*parser], seed.as_bytes()].concat(),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    trace_reader()?;
+
+    seeds(
+        "args",
+        &[
+            (0, "memory:10000:1000;instruction:50000".to_string()),
+            (1, "syscall".to_string()),
+            (2, "0x400000-0x401000;0x600000-0x600100".to_string()),
+            (3, "0x401000:rdi:rsi;0x402000:0x601000:64".to_string()),
+            (4, "main@0x401136".to_string()),
+            (5, "0x401000;4198400".to_string()),
+            (6, "0x7fff0000".to_string()),
+            (7, "stop".to_string()),
+            (8, "/tmp/input".to_string()),
+        ],
+    )?;
+
+    let mut configs = Vec::new();
+
+    for name in ["libc", "openssl"] {
+        let profile = ApiProfile::load(name)?;
+        configs.push((0, toml::to_string(&profile)?));
+        configs.push((1, serde_json::to_string(&profile)?));
+    }
+
+    let policy = RedactionPolicy::default();
+    configs.push((2, toml::to_string(&policy)?));
+    configs.push((3, serde_json::to_string(&policy)?));
+    configs.push((
+        4,
+        "FlatView #0\n AS \"memory\", root: system\n Root memory region: system\n  \
+         0000000000000000-000000000009ffff (prio 0, ram): pc.ram @0000000000000000 KVM\n  \
+         00000000000a0000-00000000000bffff (prio 1, i/o): vga-lowmem\n"
+            .to_string(),
+    ));
+    configs.push((
+        5,
+        "rule upx_packed : packer {\n    meta:\n        author = \"analyst\"\n    strings:\n        \
+         $magic = \"UPX!\"\n        $stub = { 60 be ?? ?? ?? ?? 8d be }\n        \
+         $name = \"upx\" nocase wide\n    condition:\n        $magic and ($stub or $name)\n}\n"
+            .to_string(),
+    ));
+
+    seeds("configs", &configs)
+}
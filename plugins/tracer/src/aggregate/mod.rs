@@ -0,0 +1,298 @@
+//! Aggregation of the traces of many plugin instances, for running the tracer across a
+//! fuzzing or test cluster
+//!
+//! With `aggregator=host:port`, the plugin connects to the `aggregator` server over TCP and
+//! sends its events there instead of to the `tracer` binary. The stream is the same CBOR
+//! event stream as a trace, framed by reports which are not events: a [`Report::Hello`]
+//! naming the instance first, a [`Report::Run`] with its run manifest, if it writes one, and
+//! a [`Report::Coverage`] with its block and edge coverage at exit.
+//!
+//! The server merges the coverage of every instance, and deduplicates the crashes their
+//! events report: shadow stack violations, ROP detections and hangs. A crash's signature is
+//! a hash of its kind, its PC and the innermost frames of its call stack, so the same bug
+//! reached by many inputs or instances is reported once, with a count.
+
+use crate::{coverage::CoverageSet, session::RunManifest, Event};
+use anyhow::{anyhow, Result};
+use qemu_plugin::qemu_plugin_path_to_binary;
+use serde::{Deserialize, Serialize};
+use serde_cbor::to_writer;
+use serde_cbor::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    net::TcpStream,
+    path::PathBuf,
+    process::id,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The call stack frames included in a crash's signature
+const SIGNATURE_FRAMES: usize = 3;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Identifies a plugin instance to the server
+pub struct Hello {
+    pub name: String,
+    pub program: Option<PathBuf>,
+    pub pid: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A record sent to the server which is not an event
+pub enum Report {
+    /// Sent first, when the instance connects
+    Hello(Hello),
+    /// The instance's coverage, sent at exit
+    Coverage(CoverageSet),
+    /// The instance's run manifest, sent after the hello and again at exit
+    Run(Box<RunManifest>),
+}
+
+impl Report {
+    /// The names of the variants, which no event shares
+    const VARIANTS: &'static [&'static str] = &["Hello", "Coverage", "Run"];
+}
+
+/// Connect to the server at `address`, introducing the instance as `name`, by default its
+/// process ID
+pub fn connect(address: &str, name: Option<String>) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect(address)?;
+
+    to_writer(
+        &mut stream,
+        &Report::Hello(Hello {
+            name: name.unwrap_or_else(|| id().to_string()),
+            program: qemu_plugin_path_to_binary()?,
+            pid: id(),
+        }),
+    )?;
+
+    Ok(stream)
+}
+
+#[derive(Clone, Debug)]
+/// A record read from an instance's stream
+pub enum Record {
+    Report(Report),
+    Event(Box<Event>),
+}
+
+impl Record {
+    /// Decode a record from its CBOR value
+    pub fn decode(value: Value) -> Result<Self> {
+        let is_report = match &value {
+            Value::Map(map) if map.len() == 1 => map.keys().next().is_some_and(
+                |k| matches!(k, Value::Text(k) if Report::VARIANTS.contains(&k.as_str())),
+            ),
+            _ => false,
+        };
+
+        Ok(if is_report {
+            Self::Report(serde_cbor::value::from_value(value)?)
+        } else {
+            Self::Event(Box::new(serde_cbor::value::from_value(value)?))
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A crash reported by an event
+pub struct Crash {
+    pub kind: String,
+    pub pc: u64,
+    /// The return addresses on the call stack, innermost first, if known
+    pub call_stack: Vec<u64>,
+}
+
+impl Crash {
+    /// Returns the crash an event reports, if it reports one
+    pub fn from_event(event: &Event) -> Option<Self> {
+        match event {
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Integrity(integrity) => Some(Self {
+                kind: "integrity".to_string(),
+                pc: integrity.pc,
+                call_stack: integrity.call_stack.clone(),
+            }),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Rop(rop) => Some(Self {
+                kind: "rop".to_string(),
+                pc: rop.pc,
+                call_stack: rop.call_stack.clone(),
+            }),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Hang(hang) => Some(Self {
+                kind: "hang".to_string(),
+                pc: hang.pc,
+                call_stack: Vec::new(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The signature crashes are deduplicated by
+    pub fn signature(&self) -> String {
+        let mut hasher = Sha256::new()
+            .chain_update(self.kind.as_bytes())
+            .chain_update(self.pc.to_le_bytes());
+
+        for frame in self.call_stack.iter().take(SIGNATURE_FRAMES) {
+            hasher.update(frame.to_le_bytes());
+        }
+
+        hasher.finalize()[..8]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A deduplicated crash
+pub struct CrashSummary {
+    pub signature: String,
+    pub crash: Crash,
+    /// The number of times the crash was reported, by any instance
+    pub count: u64,
+    /// The instances which reported the crash
+    pub instances: Vec<u64>,
+    /// Milliseconds since the Unix epoch when the crash was first reported
+    pub first_seen_ms: u128,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The state of a plugin instance
+pub struct InstanceStatus {
+    pub id: u64,
+    pub peer: String,
+    pub name: Option<String>,
+    pub program: Option<PathBuf>,
+    pub pid: Option<u32>,
+    pub connected: bool,
+    pub events: u64,
+    pub blocks: u64,
+    pub edges: u64,
+    pub run: Option<RunManifest>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The state of the server, as served by its status API
+pub struct Status {
+    pub instances: Vec<InstanceStatus>,
+    /// The blocks and edges covered by all instances together
+    pub blocks: u64,
+    pub edges: u64,
+    pub crashes: Vec<CrashSummary>,
+}
+
+#[derive(Debug, Default)]
+/// Merges the streams of many plugin instances
+pub struct Aggregator {
+    next: u64,
+    instances: BTreeMap<u64, InstanceStatus>,
+    coverage: CoverageSet,
+    crashes: BTreeMap<String, CrashSummary>,
+}
+
+impl Aggregator {
+    /// Record that an instance connected from `peer`, returning its id
+    pub fn connect(&mut self, peer: String) -> u64 {
+        let id = self.next;
+
+        self.next += 1;
+        self.instances.insert(
+            id,
+            InstanceStatus {
+                id,
+                peer,
+                name: None,
+                program: None,
+                pid: None,
+                connected: true,
+                events: 0,
+                blocks: 0,
+                edges: 0,
+                run: None,
+            },
+        );
+
+        id
+    }
+
+    /// Record that an instance disconnected
+    pub fn disconnect(&mut self, id: u64) {
+        if let Some(instance) = self.instances.get_mut(&id) {
+            instance.connected = false;
+        }
+    }
+
+    /// Add a record from instance `id`. Returns the crash's summary if the record is an
+    /// event reporting a crash not seen before.
+    pub fn on_record(&mut self, id: u64, record: &Record) -> Result<Option<CrashSummary>> {
+        let instance = self
+            .instances
+            .get_mut(&id)
+            .ok_or_else(|| anyhow!("Unknown instance {id}"))?;
+
+        match record {
+            Record::Report(Report::Hello(hello)) => {
+                instance.name = Some(hello.name.clone());
+                instance.program = hello.program.clone();
+                instance.pid = Some(hello.pid);
+            }
+            Record::Report(Report::Run(run)) => instance.run = Some(run.as_ref().clone()),
+            Record::Report(Report::Coverage(coverage)) => {
+                instance.blocks = coverage.block_count();
+                instance.edges = coverage.edge_count();
+                self.coverage.merge(coverage)?;
+            }
+            Record::Event(event) => {
+                instance.events += 1;
+
+                if let Some(crash) = Crash::from_event(event) {
+                    let signature = crash.signature();
+
+                    if let Some(summary) = self.crashes.get_mut(&signature) {
+                        summary.count += 1;
+
+                        if !summary.instances.contains(&id) {
+                            summary.instances.push(id);
+                        }
+                    } else {
+                        let summary = CrashSummary {
+                            signature: signature.clone(),
+                            crash,
+                            count: 1,
+                            instances: vec![id],
+                            first_seen_ms: SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap_or_default()
+                                .as_millis(),
+                        };
+
+                        self.crashes.insert(signature, summary.clone());
+
+                        return Ok(Some(summary));
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The coverage of all instances together
+    pub fn coverage(&self) -> &CoverageSet {
+        &self.coverage
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            instances: self.instances.values().cloned().collect(),
+            blocks: self.coverage.block_count(),
+            edges: self.coverage.edge_count(),
+            crashes: self.crashes.values().cloned().collect(),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! A report of the instruction set extensions a run executed, such as AVX2, SVE or NEON, and
+//! how often in each module, to check that a guest build uses the extensions it is expected
+//! to under emulation
+//!
+//! Each translated block's instructions are classified by their disassembly, as by
+//! [`Arch::extension`], and each execution of the block adds its counts to the module it
+//! starts in: the program in user mode, and each library the guest maps when libraries are
+//! followed with `symbolize_libraries`. Other code, and all code in system mode, is counted
+//! as `[unknown]`. Blocks which stop early, on a fault or an interrupt, are counted in full.
+//!
+//! Extensions are recognized on every target the tracer knows. On RISC-V these are RVV, the
+//! bit manipulation and the scalar and vector crypto extensions; F and D are part of the
+//! RV64GC base Linux distributions build for, so are not counted.
+
+use crate::arch::{Arch, Extension};
+use anyhow::Result;
+use qemu_plugin::path::create_sink;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+/// The name given to code outside any known module
+const UNKNOWN: &str = "[unknown]";
+
+#[derive(Clone, Debug, Default)]
+/// The instructions each execution of a block adds to its module
+pub struct BlockUsage {
+    module: usize,
+    instructions: u64,
+    extensions: Vec<(Extension, u64)>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Counts {
+    instructions: u64,
+    extensions: HashMap<Extension, u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The instructions executed from one extension
+pub struct ExtensionCount {
+    pub extension: Extension,
+    pub instructions: u64,
+    /// The extension's share of the instructions executed in the module, or in the run
+    pub share: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The extensions executed in one module, most executed first
+pub struct ModuleUsage {
+    pub module: String,
+    pub instructions: u64,
+    pub extensions: Vec<ExtensionCount>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The extensions a run executed, across the run and in each module, most executed first
+pub struct ExtensionReport {
+    pub instructions: u64,
+    pub extensions: Vec<ExtensionCount>,
+    pub modules: Vec<ModuleUsage>,
+}
+
+/// Order extensions most executed first
+fn extension_counts(extensions: &HashMap<Extension, u64>, total: u64) -> Vec<ExtensionCount> {
+    let mut counts = extensions
+        .iter()
+        .map(|(extension, instructions)| ExtensionCount {
+            extension: *extension,
+            instructions: *instructions,
+            share: *instructions as f64 / total.max(1) as f64,
+        })
+        .collect::<Vec<_>>();
+
+    counts.sort_by(|a, b| {
+        b.instructions
+            .cmp(&a.instructions)
+            .then_with(|| a.extension.cmp(&b.extension))
+    });
+
+    counts
+}
+
+#[derive(Debug)]
+/// Counts the instructions executed from each extension in each module
+pub struct ExtensionProfiler {
+    arch: Arch,
+    /// The name, start and end of the code of each loaded module
+    modules: Vec<(String, u64, u64)>,
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    counts: Vec<Counts>,
+}
+
+impl ExtensionProfiler {
+    pub fn new(arch: Arch) -> Self {
+        Self {
+            arch,
+            modules: Vec::new(),
+            names: Vec::new(),
+            ids: HashMap::new(),
+            counts: Vec::new(),
+        }
+    }
+
+    /// Add a module whose code was loaded from `start` to `end`, replacing any it overlaps
+    pub fn add_module(&mut self, name: &str, start: u64, end: u64) {
+        self.modules.retain(|(_, s, e)| *e <= start || *s >= end);
+        self.modules.push((name.to_string(), start, end));
+    }
+
+    fn id(&mut self, vaddr: u64) -> usize {
+        let name = self
+            .modules
+            .iter()
+            .find(|(_, start, end)| (*start..*end).contains(&vaddr))
+            .map_or(UNKNOWN, |(name, _, _)| name.as_str());
+
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+
+        let id = self.names.len();
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        self.counts.push(Counts::default());
+        id
+    }
+
+    /// Classify the instructions of a block translated at `vaddr` from the disassembly and
+    /// number of instructions of each plugin instruction, which is more than one for a
+    /// Hexagon packet
+    pub fn on_translate<I>(&mut self, vaddr: u64, instructions: I) -> BlockUsage
+    where
+        I: IntoIterator<Item = (String, u64)>,
+    {
+        let mut usage = BlockUsage {
+            module: self.id(vaddr),
+            ..Default::default()
+        };
+
+        for (disas, count) in instructions {
+            usage.instructions += count;
+
+            let Some(extension) = self.arch.extension(&disas) else {
+                continue;
+            };
+
+            match usage.extensions.iter_mut().find(|(e, _)| *e == extension) {
+                Some((_, instructions)) => *instructions += count,
+                None => usage.extensions.push((extension, count)),
+            }
+        }
+
+        usage
+    }
+
+    /// Record an execution of a block
+    pub fn on_block(&mut self, usage: &BlockUsage) {
+        let counts = &mut self.counts[usage.module];
+
+        counts.instructions += usage.instructions;
+
+        for (extension, instructions) in &usage.extensions {
+            *counts.extensions.entry(*extension).or_default() += instructions;
+        }
+    }
+
+    pub fn report(&self) -> ExtensionReport {
+        let instructions = self.counts.iter().map(|c| c.instructions).sum::<u64>();
+        let mut extensions = HashMap::<Extension, u64>::new();
+
+        for (extension, count) in self.counts.iter().flat_map(|c| &c.extensions) {
+            *extensions.entry(*extension).or_default() += count;
+        }
+
+        let mut modules = self
+            .names
+            .iter()
+            .zip(&self.counts)
+            .filter(|(_, counts)| counts.instructions > 0)
+            .map(|(name, counts)| ModuleUsage {
+                module: name.clone(),
+                instructions: counts.instructions,
+                extensions: extension_counts(&counts.extensions, counts.instructions),
+            })
+            .collect::<Vec<_>>();
+
+        modules.sort_by(|a, b| {
+            b.instructions
+                .cmp(&a.instructions)
+                .then_with(|| a.module.cmp(&b.module))
+        });
+
+        ExtensionReport {
+            instructions,
+            extensions: extension_counts(&extensions, instructions),
+            modules,
+        }
+    }
+
+    /// Write the report to `path` as JSON
+    pub fn write_report<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        serde_json::to_writer_pretty(create_sink(path)?, &self.report())?;
+
+        Ok(())
+    }
+}
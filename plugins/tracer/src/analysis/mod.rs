@@ -0,0 +1,200 @@
+//! Analysis modules loaded at runtime through a small, stable C ABI
+//!
+//! With `analysis_modules=a.so;b.so`, the plugin loads each shared library and passes it
+//! every event it records, after throttling, as a CBOR-encoded [`crate::Event`]. Modules do
+//! not link against `qemu-plugin` or this crate, so they can be rebuilt and swapped without
+//! relinking the plugin, and keep working across QEMU plugin API versions. With
+//! `analysis_only=true`, events go only to the modules and no trace is written, making the
+//! installed plugin a thin loader for them.
+//!
+//! A module exports a function named `tracer_analysis_module` returning a pointer to a
+//! static [`AnalysisModuleV1`]:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn tracer_analysis_module() -> *const AnalysisModuleV1 {
+//!     static MODULE: AnalysisModuleV1 = AnalysisModuleV1 {
+//!         abi_version: 1,
+//!         name: c"syscall-counter".as_ptr(),
+//!         init: Some(init),
+//!         on_event: Some(on_event),
+//!         on_exit: Some(on_exit),
+//!     };
+//!
+//!     &MODULE
+//! }
+//! ```
+//!
+//! `init` is passed the plugin's arguments, comma-separated, so a module can read its own
+//! options from them, and returns the module's state, which is passed to the other
+//! functions. Calls into each module are serialized, so it needs no locking of its own.
+//! `on_exit` returns zero on success, and the state is not used again after it.
+
+use crate::{log, Event};
+use anyhow::{anyhow, Result};
+use libloading::Library;
+use qemu_plugin::VCPUIndex;
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    path::Path,
+    sync::Mutex,
+};
+
+pub mod extensions;
+pub mod srcline;
+pub mod symprof;
+
+/// The version of the ABI described by [`AnalysisModuleV1`]
+pub const ABI_VERSION: u32 = 1;
+
+/// The symbol a module exports
+pub const ENTRY_POINT: &[u8] = b"tracer_analysis_module\0";
+
+/// Creates a module's state from the plugin's arguments
+pub type InitFn = unsafe extern "C" fn(args: *const c_char) -> *mut c_void;
+/// Passes a module an event encoded as CBOR
+pub type OnEventFn =
+    unsafe extern "C" fn(state: *mut c_void, vcpu_index: u32, data: *const u8, len: usize);
+/// Tells a module the run finished, returning zero on success
+pub type OnExitFn = unsafe extern "C" fn(state: *mut c_void) -> c_int;
+
+#[repr(C)]
+/// The functions a module implements, any of which may be omitted
+pub struct AnalysisModuleV1 {
+    /// Must be [`ABI_VERSION`]
+    pub abi_version: u32,
+    /// The module's name, as a NUL-terminated string
+    pub name: *const c_char,
+    pub init: Option<InitFn>,
+    pub on_event: Option<OnEventFn>,
+    pub on_exit: Option<OnExitFn>,
+}
+
+// SAFETY: The description is immutable, and its name points to a static string
+unsafe impl Sync for AnalysisModuleV1 {}
+
+#[derive(Debug)]
+/// A loaded module
+struct Module {
+    name: String,
+    state: *mut c_void,
+    on_event: Option<OnEventFn>,
+    on_exit: Option<OnExitFn>,
+    /// Kept loaded for as long as its functions may be called
+    _library: Library,
+}
+
+// SAFETY: Modules are only called with their lock held, and the ABI requires their state to
+// be usable from any thread
+unsafe impl Send for Module {}
+
+impl Module {
+    fn load(path: &Path, args: &CStr) -> Result<Self> {
+        // SAFETY: Loading a library runs its initializers, which the user trusts by naming it
+        let library = unsafe { Library::new(path) }
+            .map_err(|e| anyhow!("Failed to load analysis module {}: {e}", path.display()))?;
+        // SAFETY: The entry point has the signature the ABI specifies
+        let entry = unsafe {
+            library.get::<unsafe extern "C" fn() -> *const AnalysisModuleV1>(ENTRY_POINT)
+        }
+        .map_err(|e| anyhow!("{} is not an analysis module: {e}", path.display()))?;
+        // SAFETY: The entry point returns a pointer to a static description of the module
+        let module = unsafe { entry().as_ref() }
+            .ok_or_else(|| anyhow!("Analysis module {} is null", path.display()))?;
+
+        if module.abi_version != ABI_VERSION {
+            return Err(anyhow!(
+                "Analysis module {} has ABI version {}, expected {ABI_VERSION}",
+                path.display(),
+                module.abi_version
+            ));
+        }
+
+        let name = if module.name.is_null() {
+            path.display().to_string()
+        } else {
+            // SAFETY: The name is a NUL-terminated string
+            unsafe { CStr::from_ptr(module.name) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        let state = match module.init {
+            // SAFETY: The arguments outlive the call
+            Some(init) => unsafe { init(args.as_ptr()) },
+            None => std::ptr::null_mut(),
+        };
+
+        Ok(Self {
+            name,
+            state,
+            on_event: module.on_event,
+            on_exit: module.on_exit,
+            _library: library,
+        })
+    }
+}
+
+#[derive(Debug)]
+/// The analysis modules loaded
+pub struct Analyses {
+    modules: Vec<Mutex<Module>>,
+}
+
+impl Analyses {
+    /// Load the modules at `paths`, separated by `;`, initializing them with `args`, the
+    /// plugin's arguments
+    pub fn load(paths: &str, args: &str) -> Result<Self> {
+        let args = CString::new(args)?;
+
+        Ok(Self {
+            modules: paths
+                .split(';')
+                .filter(|p| !p.is_empty())
+                .map(|p| Module::load(Path::new(p), &args).map(Mutex::new))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Pass an event to every module
+    pub fn dispatch(&self, vcpu_index: VCPUIndex, event: &Event) -> Result<()> {
+        let data = serde_cbor::to_vec(event)?;
+
+        for module in &self.modules {
+            let module = module
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock analysis module: {e}"))?;
+
+            if let Some(on_event) = module.on_event {
+                // SAFETY: The event data outlives the call
+                unsafe { on_event(module.state, vcpu_index, data.as_ptr(), data.len()) };
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tell every module the run finished
+    pub fn finish(&self) -> Result<()> {
+        for module in &self.modules {
+            let mut module = module
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock analysis module: {e}"))?;
+
+            module.on_event = None;
+
+            if let Some(on_exit) = module.on_exit.take() {
+                // SAFETY: The state came from the module's `init`
+                let status = unsafe { on_exit(module.state) };
+
+                if status != 0 {
+                    log(format!(
+                        "Analysis module {} failed with {status}",
+                        module.name
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+//! A profile of the instructions executed on each source line, like `perf annotate` for the
+//! guest
+//!
+//! Executed instructions are mapped to source lines with the DWARF line tables of the
+//! program, or of the ELF file given with `source_binary`, such as firmware run in system
+//! mode. The report lists each source file, most executed first, with its executed lines in
+//! source order. Each line shows its share of the executed instructions and its text, if the
+//! source file can be read, followed by its instructions with their execution counts and
+//! disassembly:
+//!
+//! ```text
+//! src/main.c: 1200 (80.00%)
+//!     Percent      Count   Line
+//!      60.00%        900     17  for (i = 0; i < n; i++) {
+//!                    300         0x8000100  adds r0, #1
+//!                    300         0x8000102  cmp r0, r1
+//!                    300         0x8000104  blt 0x8000100
+//! ```
+//!
+//! Blocks which stop early, on a fault or an interrupt, are counted in full, and instructions
+//! without line information are counted only in the totals.
+
+use crate::modules::{big_endian, section_data};
+use anyhow::{anyhow, Result};
+use gimli::{DwarfSections, EndianSlice, RunTimeEndian, SectionId};
+use qemu_plugin::path::create_sink;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fs::{read, read_to_string},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Copy, Debug)]
+/// A row of a line table: the line of the instructions from `address` to the next row's
+struct Row {
+    address: u64,
+    /// The file and line, or `None` past the end of a sequence
+    line: Option<(usize, u64)>,
+}
+
+#[derive(Clone, Debug, Default)]
+/// The DWARF line tables of an ELF file, merged and sorted by address
+pub struct LineTable {
+    files: Vec<String>,
+    rows: Vec<Row>,
+}
+
+impl LineTable {
+    /// Read the line tables of the ELF file `data`
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let sections = DwarfSections::load(|id: SectionId| -> Result<Cow<[u8]>> {
+            Ok(section_data(data, id.name())?.unwrap_or_default())
+        })?;
+        let endian = if big_endian(data)? {
+            RunTimeEndian::Big
+        } else {
+            RunTimeEndian::Little
+        };
+        let dwarf = sections.borrow(|section| EndianSlice::new(section, endian));
+        let mut table = Self::default();
+        let mut ids = HashMap::<String, usize>::new();
+        let mut units = dwarf.units();
+
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+            // The id of each of the unit's files, by index in its line program
+            let mut files = HashMap::<u64, usize>::new();
+            let mut rows = program.rows();
+
+            while let Some((header, row)) = rows.next_row()? {
+                if row.end_sequence() {
+                    table.rows.push(Row {
+                        address: row.address(),
+                        line: None,
+                    });
+                    continue;
+                }
+
+                let file = match files.get(&row.file_index()) {
+                    Some(file) => *file,
+                    None => {
+                        let mut path = PathBuf::new();
+
+                        if let Some(dir) = unit.comp_dir {
+                            path.push(&*dir.to_string_lossy());
+                        }
+
+                        if let Some(entry) = row.file(header) {
+                            if let Some(dir) = entry.directory(header) {
+                                path.push(&*dwarf.attr_string(&unit, dir)?.to_string_lossy());
+                            }
+
+                            path.push(
+                                &*dwarf
+                                    .attr_string(&unit, entry.path_name())?
+                                    .to_string_lossy(),
+                            );
+                        }
+
+                        let name = path.to_string_lossy().into_owned();
+                        let next = table.files.len();
+                        let file = *ids.entry(name.clone()).or_insert(next);
+
+                        if file == next {
+                            table.files.push(name);
+                        }
+
+                        files.insert(row.file_index(), file);
+                        file
+                    }
+                };
+
+                table.rows.push(Row {
+                    address: row.address(),
+                    line: row.line().map(|line| (file, line.get())),
+                });
+            }
+        }
+
+        // Stable, so a sequence's end stays before a sequence starting at the same address
+        table.rows.sort_by_key(|row| row.address);
+
+        Ok(table)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Returns the file and line of the instruction at `address` in the file
+    pub fn lookup(&self, address: u64) -> Option<(&str, u64)> {
+        let index = self.rows.partition_point(|row| row.address <= address);
+        let (file, line) = self.rows.get(index.checked_sub(1)?)?.line?;
+
+        Some((&self.files[file], line))
+    }
+}
+
+#[derive(Clone, Debug)]
+/// An instruction of a translated block
+struct Instruction {
+    vaddr: u64,
+    disas: String,
+}
+
+#[derive(Debug)]
+/// Counts the executions of each instruction, to attribute them to source lines
+pub struct SourceProfiler {
+    lines: LineTable,
+    /// The difference between the addresses code runs at and those in the line tables
+    bias: u64,
+    blocks: Vec<Vec<Instruction>>,
+    executions: Vec<u64>,
+}
+
+impl SourceProfiler {
+    /// Profile the code of the ELF file at `path`, loaded at `bias`. Fails if it has no line
+    /// tables.
+    pub fn load<P>(path: P, bias: u64) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let lines = LineTable::parse(&read(path)?)
+            .map_err(|e| anyhow!("Failed to read line tables of {}: {e}", path.display()))?;
+
+        if lines.is_empty() {
+            return Err(anyhow!("{} has no DWARF line tables", path.display()));
+        }
+
+        Ok(Self {
+            lines,
+            bias,
+            blocks: Vec::new(),
+            executions: Vec::new(),
+        })
+    }
+
+    /// Record a translated block from the address and disassembly of each instruction,
+    /// returning the id its executions are recorded under
+    pub fn on_translate<I>(&mut self, instructions: I) -> usize
+    where
+        I: IntoIterator<Item = (u64, String)>,
+    {
+        self.blocks.push(
+            instructions
+                .into_iter()
+                .map(|(vaddr, disas)| Instruction { vaddr, disas })
+                .collect(),
+        );
+        self.executions.push(0);
+        self.blocks.len() - 1
+    }
+
+    /// Record an execution of the block with id `block`
+    pub fn on_block(&mut self, block: usize) {
+        self.executions[block] += 1;
+    }
+
+    /// Write the report to `path`
+    pub fn write_report<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        // The instructions of each line, with their execution counts, by file and line
+        let mut files = HashMap::<&str, BTreeMap<u64, BTreeMap<u64, (&str, u64)>>>::new();
+        let mut executed = 0;
+        let mut attributed = 0;
+
+        for (block, executions) in self.blocks.iter().zip(&self.executions) {
+            for insn in block {
+                executed += executions;
+
+                let Some((file, line)) = self.lines.lookup(insn.vaddr.wrapping_sub(self.bias))
+                else {
+                    continue;
+                };
+
+                attributed += executions;
+                files
+                    .entry(file)
+                    .or_default()
+                    .entry(line)
+                    .or_default()
+                    .entry(insn.vaddr)
+                    .or_insert((&insn.disas, 0))
+                    .1 += executions;
+            }
+        }
+
+        let share = |count: u64| count as f64 * 100.0 / attributed.max(1) as f64;
+        let mut files = files
+            .into_iter()
+            .map(|(file, lines)| {
+                let count = lines
+                    .values()
+                    .flat_map(|insns| insns.values().map(|(_, count)| count))
+                    .sum::<u64>();
+
+                (file, lines, count)
+            })
+            .filter(|(_, _, count)| *count > 0)
+            .collect::<Vec<_>>();
+
+        files.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(b.0)));
+
+        let mut sink = create_sink(path)?;
+
+        writeln!(
+            sink,
+            "{attributed} of {executed} executed instructions have line information"
+        )?;
+
+        for (file, lines, count) in files {
+            let source = read_to_string(file).ok();
+            let source = source.as_deref().map(|s| s.lines().collect::<Vec<_>>());
+
+            writeln!(sink)?;
+            writeln!(sink, "{file}: {count} ({:.2}%)", share(count))?;
+            writeln!(sink, "    Percent      Count   Line")?;
+
+            for (line, insns) in lines {
+                let count = insns.values().map(|(_, count)| count).sum::<u64>();
+
+                if count == 0 {
+                    continue;
+                }
+
+                let text = source
+                    .as_ref()
+                    .and_then(|source| source.get(line as usize - 1))
+                    .map_or("", |text| text.trim());
+
+                writeln!(
+                    sink,
+                    "    {:6.2}%  {count:>9}  {line:>5}  {text}",
+                    share(count)
+                )?;
+
+                for (vaddr, (disas, count)) in insns {
+                    writeln!(sink, "             {count:>9}         {vaddr:#x}  {disas}")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
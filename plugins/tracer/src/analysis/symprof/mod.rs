@@ -0,0 +1,276 @@
+//! A flat profile of the instructions executed in each symbol
+//!
+//! Each translated block is split into runs of instructions by the symbol QEMU names for them,
+//! or, in code without symbols, by the section of the program they fall in. Each execution of
+//! the block adds its runs' instruction counts to their symbols. Blocks which stop early, on a
+//! fault or an interrupt, are counted in full.
+//!
+//! With weights for classes of instructions, each symbol is also given a cycle estimate, the
+//! sum of the weights of the instructions it executed, so that a symbol heavy in atomics or
+//! memory accesses ranks above one which executes more, cheaper instructions. Weights are
+//! given as `class:weight` pairs separated by `;`, such as `memory:4;atomic:20`, and classes
+//! not given weigh 1.
+
+use crate::{
+    arch::{decoder::Decoder, Arch},
+    modules::Section,
+};
+use anyhow::{anyhow, Error, Result};
+use qemu_plugin::path::create_sink;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path, str::FromStr};
+
+/// The name given to instructions with no symbol outside any known section
+const UNKNOWN: &str = "[unknown]";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+/// A class of instructions with its own weight in cycle estimates
+pub enum InsnClass {
+    /// Atomic and ordered memory accesses
+    Atomic,
+    /// Instructions of cryptographic extensions
+    Crypto,
+    /// Calls, returns and the last instruction of each block, which transfers control
+    Branch,
+    /// Other instructions with a memory operand, on targets with a decoder
+    Memory,
+    Other,
+}
+
+const CLASSES: &[(InsnClass, &str)] = &[
+    (InsnClass::Atomic, "atomic"),
+    (InsnClass::Crypto, "crypto"),
+    (InsnClass::Branch, "branch"),
+    (InsnClass::Memory, "memory"),
+    (InsnClass::Other, "other"),
+];
+
+impl InsnClass {
+    /// Classify an instruction from its encoding and disassembly. `last` is whether it ends
+    /// its block.
+    pub fn classify(arch: Arch, decoder: &Decoder, data: &[u8], disas: &str, last: bool) -> Self {
+        if arch.is_atomic(disas) {
+            Self::Atomic
+        } else if arch.crypto(disas).is_some() {
+            Self::Crypto
+        } else if last || arch.branch(disas).is_some() {
+            Self::Branch
+        } else if decoder.address_breakdown(data).is_some() {
+            Self::Memory
+        } else {
+            Self::Other
+        }
+    }
+}
+
+impl FromStr for InsnClass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        CLASSES
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(class, _)| *class)
+            .ok_or_else(|| anyhow!("Unknown instruction class {s}"))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The cycle estimate of each class of instruction
+pub struct Weights(HashMap<InsnClass, u64>);
+
+impl Weights {
+    pub fn get(&self, class: InsnClass) -> u64 {
+        self.0.get(&class).copied().unwrap_or(1)
+    }
+}
+
+impl FromStr for Weights {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split(';')
+            .filter(|pair| !pair.trim().is_empty())
+            .map(|pair| {
+                let (class, weight) = pair
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("Expected class:weight, got {pair}"))?;
+
+                Ok((class.trim().parse()?, weight.trim().parse()?))
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+/// Consecutive instructions of a block in one symbol
+pub struct Run {
+    symbol: usize,
+    instructions: u64,
+    cycles: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Counts {
+    instructions: u64,
+    cycles: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The instructions executed in one symbol
+pub struct SymbolCount {
+    pub symbol: String,
+    pub instructions: u64,
+    /// The cycle estimate, if weights were given
+    pub cycles: Option<u64>,
+    /// The symbol's share of the run's instructions, or of its cycles if weights were given
+    pub share: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A flat profile of a run, most expensive symbols first
+pub struct SymbolProfile {
+    pub instructions: u64,
+    pub cycles: Option<u64>,
+    pub symbols: Vec<SymbolCount>,
+}
+
+#[derive(Debug)]
+/// Counts the instructions executed in each symbol
+pub struct SymbolProfiler {
+    weights: Option<Weights>,
+    /// The sections of the program, to name code without symbols
+    sections: Vec<Section>,
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    counts: Vec<Counts>,
+}
+
+impl SymbolProfiler {
+    pub fn new(weights: Option<Weights>, sections: Vec<Section>) -> Self {
+        Self {
+            weights,
+            sections,
+            names: Vec::new(),
+            ids: HashMap::new(),
+            counts: Vec::new(),
+        }
+    }
+
+    /// Whether instructions must be classified for cycle estimates
+    pub fn is_weighted(&self) -> bool {
+        self.weights.is_some()
+    }
+
+    fn id(&mut self, vaddr: u64, symbol: Option<String>) -> usize {
+        let name = symbol.filter(|s| !s.is_empty()).unwrap_or_else(|| {
+            self.sections
+                .iter()
+                .find(|s| s.contains(vaddr))
+                .map(|s| format!("[{}]", s.name))
+                .unwrap_or_else(|| UNKNOWN.to_string())
+        });
+
+        if let Some(id) = self.ids.get(&name) {
+            return *id;
+        }
+
+        let id = self.names.len();
+        self.names.push(name.clone());
+        self.ids.insert(name, id);
+        self.counts.push(Counts::default());
+        id
+    }
+
+    /// Split a translated block into the runs its executions add, from the address, symbol,
+    /// class and number of instructions of each plugin instruction, which is more than one
+    /// for a Hexagon packet. The class is only needed when weights were given.
+    pub fn on_translate<I>(&mut self, instructions: I) -> Vec<Run>
+    where
+        I: IntoIterator<Item = (u64, Option<String>, Option<InsnClass>, u64)>,
+    {
+        let mut runs = Vec::<Run>::new();
+
+        for (vaddr, symbol, class, count) in instructions {
+            let symbol = self.id(vaddr, symbol);
+            let cycles = match (self.weights.as_ref(), class) {
+                (Some(weights), Some(class)) => weights.get(class) * count,
+                _ => count,
+            };
+
+            match runs.last_mut() {
+                Some(run) if run.symbol == symbol => {
+                    run.instructions += count;
+                    run.cycles += cycles;
+                }
+                _ => runs.push(Run {
+                    symbol,
+                    instructions: count,
+                    cycles,
+                }),
+            }
+        }
+
+        runs
+    }
+
+    /// Record an execution of a block split into `runs`
+    pub fn on_block(&mut self, runs: &[Run]) {
+        for run in runs {
+            let counts = &mut self.counts[run.symbol];
+            counts.instructions += run.instructions;
+            counts.cycles += run.cycles;
+        }
+    }
+
+    pub fn report(&self) -> SymbolProfile {
+        let weighted = self.is_weighted();
+        let instructions = self.counts.iter().map(|c| c.instructions).sum::<u64>();
+        let cycles = self.counts.iter().map(|c| c.cycles).sum::<u64>();
+        let total = if weighted { cycles } else { instructions };
+
+        let mut symbols = self
+            .names
+            .iter()
+            .zip(&self.counts)
+            .filter(|(_, counts)| counts.instructions > 0)
+            .map(|(name, counts)| {
+                let cost = if weighted {
+                    counts.cycles
+                } else {
+                    counts.instructions
+                };
+
+                SymbolCount {
+                    symbol: name.clone(),
+                    instructions: counts.instructions,
+                    cycles: weighted.then_some(counts.cycles),
+                    share: cost as f64 / total.max(1) as f64,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        symbols.sort_by(|a, b| {
+            (b.cycles, b.instructions)
+                .cmp(&(a.cycles, a.instructions))
+                .then_with(|| a.symbol.cmp(&b.symbol))
+        });
+
+        SymbolProfile {
+            instructions,
+            cycles: weighted.then_some(cycles),
+            symbols,
+        }
+    }
+
+    /// Write the report to `path` as JSON
+    pub fn write_report<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        serde_json::to_writer_pretty(create_sink(path)?, &self.report())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,457 @@
+//! Tracing of library calls with readable arguments, driven by API profiles
+//!
+//! A profile is a TOML or JSON file describing functions by symbol name, with a formatting
+//! hint for each argument and, optionally, for the return value:
+//!
+//! ```toml
+//! name = "libc"
+//!
+//! [[functions]]
+//! name = "open"
+//! aliases = ["open64", "__open"]
+//! args = [{ name = "path", format = "str" }, "hex", "uint"]
+//! returns = "int"
+//! ```
+//!
+//! Profiles for common libc and OpenSSL functions are built in and loaded by name; any
+//! other profile is loaded from a path, so new libraries can be traced without changes to
+//! the tracer.
+//!
+//! Functions are hooked at their first block, where their arguments are read as by the heap
+//! tracker. A call to a function without a return format is logged there. Otherwise its
+//! return address is read too, from the stack on x86 or the link register on ARM, and the
+//! call is logged with its return value once a block at the return address executes, which
+//! is also when output buffers are read. As with the heap tracker, functions in a
+//! dynamically linked library must be given by address, unless `resolve_plt` is given, when
+//! calls through the program's PLT stubs are traced as calls to the functions they import,
+//! as described in [`crate::plt`].
+
+use crate::{
+    arch::Arch,
+    guest::{read_cstring, read_pointer},
+    heap::{read_argument, read_register},
+    memmap::parse_addr,
+};
+use anyhow::{anyhow, Error, Result};
+use qemu_plugin::{qemu_plugin_read_memory_vaddr, RegisterDescriptor, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs::read_to_string, path::Path, str::FromStr};
+use typed_builder::TypedBuilder;
+
+/// The most bytes of a string or buffer argument which are logged
+const MAX_LEN: usize = 256;
+
+/// The deepest the stack of calls awaiting their return may grow before the oldest are
+/// dropped, bounding the cost of calls which never return
+const MAX_PENDING: usize = 4096;
+
+/// Profiles shipped with the tracer, by name
+const BUILTIN: &[(&str, &str)] = &[
+    ("libc", include_str!("../../profiles/libc.toml")),
+    ("openssl", include_str!("../../profiles/openssl.toml")),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// Where the length of a buffer argument comes from
+pub enum Length {
+    /// The argument with this index
+    Arg(usize),
+    /// The function's return value
+    Return,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String")]
+/// How an argument or return value is formatted
+pub enum Format {
+    /// A 32-bit signed integer, as C's `int`
+    Int,
+    /// A 32-bit unsigned integer
+    Uint,
+    /// A pointer-sized signed integer, as C's `long` or `ssize_t`
+    Long,
+    /// A pointer-sized unsigned integer, as C's `size_t`
+    Ulong,
+    Hex,
+    /// A pointer, or `NULL`
+    Ptr,
+    Bool,
+    /// A NUL-terminated string
+    Str,
+    /// A buffer read on entry, whose length is the argument with this index
+    Buf(usize),
+    /// A buffer the function fills, read on return
+    Out(Length),
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    /// Parse a format hint: `int`, `uint`, `long`, `ulong`, `hex`, `ptr`, `bool`, `str`,
+    /// `buf:N` for a buffer whose length is argument `N`, or `out:N` or `out:ret` for an
+    /// output buffer whose length is argument `N` or the return value
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.trim() {
+            "int" => Self::Int,
+            "uint" => Self::Uint,
+            "long" => Self::Long,
+            "ulong" => Self::Ulong,
+            "hex" => Self::Hex,
+            "ptr" => Self::Ptr,
+            "bool" => Self::Bool,
+            "str" => Self::Str,
+            "out:ret" => Self::Out(Length::Return),
+            s => match s.split_once(':') {
+                Some(("buf", n)) => Self::Buf(n.parse()?),
+                Some(("out", n)) => Self::Out(Length::Arg(n.parse()?)),
+                _ => return Err(anyhow!("Unknown format {s}")),
+            },
+        })
+    }
+}
+
+impl TryFrom<String> for Format {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+/// An argument, given as a bare format or with a name
+enum ArgumentSpec {
+    Format(Format),
+    Named { name: String, format: Format },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(from = "ArgumentSpec")]
+/// An argument of a profiled function
+pub struct Argument {
+    pub name: Option<String>,
+    pub format: Format,
+}
+
+impl From<ArgumentSpec> for Argument {
+    fn from(spec: ArgumentSpec) -> Self {
+        match spec {
+            ArgumentSpec::Format(format) => Self { name: None, format },
+            ArgumentSpec::Named { name, format } => Self {
+                name: Some(name),
+                format,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A function described by a profile
+pub struct ApiFunction {
+    pub name: String,
+    /// Other symbols for the function, such as glibc's internal aliases
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub args: Vec<Argument>,
+    /// The format of the return value, or `None` to log calls on entry
+    #[serde(default)]
+    pub returns: Option<Format>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A set of functions to trace, usually those of one library
+pub struct ApiProfile {
+    pub name: String,
+    pub functions: Vec<ApiFunction>,
+}
+
+impl ApiProfile {
+    /// Parse a profile in TOML, or in JSON if `json` is set
+    pub fn parse(data: &str, json: bool) -> Result<Self> {
+        if json {
+            Ok(serde_json::from_str(data)?)
+        } else {
+            Ok(toml::from_str(data)?)
+        }
+    }
+
+    /// Load a built-in profile by name, or a profile file, which is JSON if its extension is
+    /// `.json` and TOML otherwise
+    pub fn load(profile: &str) -> Result<Self> {
+        if let Some((_, data)) = BUILTIN.iter().find(|(name, _)| *name == profile) {
+            return Self::parse(data, false);
+        }
+
+        let path = Path::new(profile);
+
+        Self::parse(
+            &read_to_string(path)
+                .map_err(|e| anyhow!("Failed to read API profile {profile}: {e}"))?,
+            path.extension().is_some_and(|e| e == "json"),
+        )
+    }
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// A call to a profiled function
+pub struct ApiCallEvent {
+    pub vcpu_index: VCPUIndex,
+    pub function: String,
+    /// The address of the function
+    pub address: u64,
+    /// The formatted arguments, as `name=value` for named arguments
+    pub args: Vec<String>,
+    /// The formatted return value, for functions with a return format which returned
+    pub ret: Option<String>,
+    /// The instruction count at entry
+    pub icount: u64,
+}
+
+#[derive(Debug)]
+/// A call awaiting its return
+struct Pending {
+    return_addr: u64,
+    function: usize,
+    /// The raw arguments
+    values: Vec<u64>,
+    event: ApiCallEvent,
+}
+
+#[derive(Debug)]
+/// Logs calls to the functions of the loaded profiles
+pub struct ApiTracer {
+    arch: Arch,
+    functions: Vec<ApiFunction>,
+    /// Indices into `functions` by symbol name and alias
+    symbols: HashMap<String, usize>,
+    /// Indices into `functions` for functions given by address
+    addresses: HashMap<u64, usize>,
+    /// The calls each vCPU is in, innermost last
+    pending: HashMap<VCPUIndex, Vec<Pending>>,
+}
+
+impl ApiTracer {
+    /// Create a tracer from profiles separated by `;`, each a built-in profile name or a
+    /// path, and functions given by address as `name:address` pairs separated by `;`, e.g.
+    /// `libc;/opt/profiles/zlib.toml` and `SSL_read:0x7f0012a0`
+    pub fn new(arch: Arch, profiles: &str, functions: Option<&str>) -> Result<Self> {
+        let profiled = profiles
+            .split(';')
+            .filter(|p| !p.trim().is_empty())
+            .map(|p| ApiProfile::load(p.trim()))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flat_map(|p| p.functions)
+            .collect::<Vec<_>>();
+
+        let mut symbols = HashMap::new();
+
+        for (i, function) in profiled.iter().enumerate() {
+            for symbol in [&function.name].into_iter().chain(&function.aliases) {
+                symbols.entry(symbol.clone()).or_insert(i);
+            }
+        }
+
+        let addresses = functions
+            .unwrap_or_default()
+            .split(';')
+            .filter(|f| !f.trim().is_empty())
+            .map(|f| {
+                let (name, addr) = f
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("Invalid API function {f}, expected name:address"))?;
+                let function = symbols
+                    .get(name.trim())
+                    .copied()
+                    .ok_or_else(|| anyhow!("No loaded API profile describes {name}"))?;
+
+                Ok((parse_addr(addr)?, function))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        Ok(Self {
+            arch,
+            functions: profiled,
+            symbols,
+            addresses,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Returns the index of the function a block starting at `vaddr` with `symbol` is the
+    /// entry of, if it is profiled
+    pub fn function(&self, vaddr: u64, symbol: Option<&str>) -> Option<usize> {
+        self.addresses
+            .get(&vaddr)
+            .or_else(|| symbol.and_then(|s| self.symbols.get(s)))
+            .copied()
+    }
+
+    /// Whether any profiled function is logged on return, so every block must be checked
+    /// for returns
+    pub fn has_returns(&self) -> bool {
+        self.functions.iter().any(|f| f.returns.is_some())
+    }
+
+    fn format(&self, format: Format, value: u64, values: &[u64], ret: Option<u64>) -> String {
+        let size = self.arch.pointer_size();
+        let length = |length: Length| match length {
+            Length::Arg(i) => values.get(i).copied().unwrap_or_default(),
+            Length::Return => ret.unwrap_or_default(),
+        };
+
+        match format {
+            Format::Int => (value as i32).to_string(),
+            Format::Uint => (value as u32).to_string(),
+            Format::Long if size == 4 => (value as i32).to_string(),
+            Format::Long => (value as i64).to_string(),
+            Format::Ulong if size == 4 => (value as u32).to_string(),
+            Format::Ulong => value.to_string(),
+            Format::Hex => format!("{value:#x}"),
+            Format::Ptr | Format::Str | Format::Buf(_) | Format::Out(_) if value == 0 => {
+                "NULL".to_string()
+            }
+            Format::Ptr => format!("{value:#x}"),
+            Format::Bool => (value != 0).to_string(),
+            Format::Str => match read_cstring(value, MAX_LEN) {
+                Ok(data) if data.len() == MAX_LEN => {
+                    format!("{:?}...", String::from_utf8_lossy(&data))
+                }
+                Ok(data) => format!("{:?}", String::from_utf8_lossy(&data)),
+                Err(_) => format!("{value:#x}"),
+            },
+            Format::Buf(i) => Self::buffer(value, length(Length::Arg(i))),
+            // The function did not return, so the buffer's length is unknown
+            Format::Out(Length::Return) if ret.is_none() => format!("{value:#x}"),
+            Format::Out(l) => Self::buffer(value, length(l)),
+        }
+    }
+
+    fn buffer(address: u64, len: u64) -> String {
+        // Lengths of failed calls, such as a read returning -1, are not lengths
+        let len = if (len as i64) < 0 { 0 } else { len as usize };
+
+        match qemu_plugin_read_memory_vaddr(address, len.min(MAX_LEN)) {
+            Ok(data) if len > MAX_LEN => format!("\"{}\"...", data.escape_ascii()),
+            Ok(data) => format!("\"{}\"", data.escape_ascii()),
+            Err(_) => format!("{address:#x}"),
+        }
+    }
+
+    fn arguments(
+        &self,
+        function: &ApiFunction,
+        values: &[u64],
+        ret: Option<u64>,
+        deferred: bool,
+    ) -> Vec<String> {
+        function
+            .args
+            .iter()
+            .zip(values)
+            .map(|(arg, value)| {
+                let formatted = match (arg.format, deferred) {
+                    // Output buffers are only filled once the function returns
+                    (Format::Out(_), false) => format!("{value:#x}"),
+                    (format, _) => self.format(format, *value, values, ret),
+                };
+
+                match arg.name.as_ref() {
+                    Some(name) => format!("{name}={formatted}"),
+                    None => formatted,
+                }
+            })
+            .collect()
+    }
+
+    fn return_address(&self, registers: &[RegisterDescriptor<'static>]) -> Result<u64> {
+        match self
+            .arch
+            .link_register_names()
+            .iter()
+            .find(|name| registers.iter().any(|r| r.name == **name))
+        {
+            // The low bit of an ARM return address selects Thumb state
+            Some(name) => Ok(read_register(self.arch, registers, name)? & !1),
+            None => {
+                let sp = read_register(self.arch, registers, self.arch.stack_pointer_names()[0])?;
+
+                read_pointer(self.arch, sp, self.arch.pointer_size())
+            }
+        }
+    }
+
+    /// Record that the block at `vaddr` executed, where `function` is the index of the
+    /// function it is the entry of, if any, reported at `address`. Returns the calls which completed: those logged
+    /// on entry, and those whose return address the block is at.
+    pub fn on_block(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        vaddr: u64,
+        address: u64,
+        function: Option<usize>,
+        registers: &[RegisterDescriptor<'static>],
+        icount: u64,
+    ) -> Result<Vec<ApiCallEvent>> {
+        let mut events = Vec::new();
+        let pending = self.pending.entry(vcpu_index).or_default();
+
+        if let Some(i) = pending.iter().rposition(|p| p.return_addr == vaddr) {
+            let completed = pending.split_off(i);
+            let ret = read_register(self.arch, registers, self.arch.return_register())?;
+
+            // Calls above the one returning were unwound past, as by `longjmp`
+            for (j, call) in completed.into_iter().enumerate().rev() {
+                let ret = (j == 0).then_some(ret);
+                let function = &self.functions[call.function];
+                let mut event = call.event;
+
+                event.args = self.arguments(function, &call.values, ret, true);
+                event.ret = ret
+                    .zip(function.returns)
+                    .map(|(ret, format)| self.format(format, ret, &call.values, Some(ret)));
+                events.push(event);
+            }
+        }
+
+        let Some(index) = function else {
+            return Ok(events);
+        };
+
+        let function = &self.functions[index];
+        let values = (0..function.args.len())
+            .map(|i| read_argument(self.arch, registers, i))
+            .collect::<Result<Vec<_>>>()?;
+        let event = ApiCallEvent::builder()
+            .vcpu_index(vcpu_index)
+            .function(function.name.clone())
+            .address(address)
+            .args(self.arguments(function, &values, None, false))
+            .ret(None)
+            .icount(icount)
+            .build();
+
+        if function.returns.is_none() {
+            events.push(event);
+            return Ok(events);
+        }
+
+        let return_addr = self.return_address(registers)?;
+        let pending = self.pending.entry(vcpu_index).or_default();
+
+        if pending.len() >= MAX_PENDING {
+            pending.remove(0);
+        }
+
+        pending.push(Pending {
+            return_addr,
+            function: index,
+            values,
+            event,
+        });
+
+        Ok(events)
+    }
+}
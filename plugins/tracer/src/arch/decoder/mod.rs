@@ -0,0 +1,440 @@
+//! Instruction decoders configured for the guest's CPU, so that instructions from extensions
+//! the configured model lacks are not decoded as if they were legal
+//!
+//! x86 models are mapped to the closest microarchitecture preset, and extensions enabled on
+//! the command line or reported by the vCPU's registers are added to it. Presets can only be
+//! extended, so flags disabled on the command line are not applied. Models without a preset,
+//! including QEMU's default, `max` and `host`, decode every extension.
+
+use super::{Arch, IsaMode};
+use qemu_plugin::target::Cpu;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Formatter};
+use yaxpeax_x86::{amd64, protected_mode};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Uarch {
+    Core,
+    Penryn,
+    Nehalem,
+    Westmere,
+    SandyBridge,
+    IvyBridge,
+    Haswell,
+    Broadwell,
+    Skylake,
+    SkylakeServer,
+    Icelake,
+    K8,
+    K10,
+    Bulldozer,
+    Piledriver,
+    Zen,
+    Zen2,
+    Zen3,
+}
+
+/// QEMU x86 CPU model name prefixes and their microarchitectures, most specific first
+const MODELS: &[(&str, Uarch)] = &[
+    ("Conroe", Uarch::Core),
+    ("core2duo", Uarch::Core),
+    ("Penryn", Uarch::Penryn),
+    ("Nehalem", Uarch::Nehalem),
+    ("Westmere", Uarch::Westmere),
+    ("SandyBridge", Uarch::SandyBridge),
+    ("IvyBridge", Uarch::IvyBridge),
+    ("Haswell", Uarch::Haswell),
+    ("Broadwell", Uarch::Broadwell),
+    ("Skylake-Server", Uarch::SkylakeServer),
+    ("Cascadelake-Server", Uarch::SkylakeServer),
+    ("Cooperlake", Uarch::SkylakeServer),
+    ("Skylake-Client", Uarch::Skylake),
+    ("Icelake", Uarch::Icelake),
+    ("SapphireRapids", Uarch::Icelake),
+    ("Opteron_G1", Uarch::K8),
+    ("Opteron_G2", Uarch::K8),
+    ("Opteron_G3", Uarch::K10),
+    ("Opteron_G4", Uarch::Bulldozer),
+    ("Opteron_G5", Uarch::Piledriver),
+    ("EPYC-Rome", Uarch::Zen2),
+    ("EPYC-Milan", Uarch::Zen3),
+    ("EPYC-Genoa", Uarch::Zen3),
+    ("EPYC", Uarch::Zen),
+];
+
+impl Uarch {
+    fn from_cpu(cpu: &Cpu) -> Option<Self> {
+        let model = cpu.model.as_deref()?;
+
+        MODELS
+            .iter()
+            .find_map(|(prefix, uarch)| model.starts_with(prefix).then_some(*uarch))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Extension {
+    Avx,
+    Avx2,
+    Avx512F,
+    Avx512Cd,
+    Avx512Bw,
+    Avx512Dq,
+    Avx512Vl,
+    Avx512Vbmi,
+    Avx512Vbmi2,
+    Avx512Bitalg,
+    Avx512Vpopcntdq,
+    Bmi1,
+    Bmi2,
+    Adx,
+    Aesni,
+    Sha,
+    Fma3,
+    Fma4,
+    F16c,
+    Movbe,
+    Popcnt,
+    Rdrand,
+    Rdseed,
+    Xsave,
+    Gfni,
+    Vaes,
+    Pclmulqdq,
+    Sse3,
+    Ssse3,
+    Sse4_1,
+    Sse4_2,
+    Sse4a,
+    Abm,
+    Tbm,
+    Xop,
+    Rdtscp,
+    Clwb,
+    Clflushopt,
+    Cmpxchg16b,
+    Lahfsahf,
+    Tsx,
+}
+
+/// QEMU x86 feature flag names and the extensions they enable. QEMU accepts `_` in place of
+/// `-`, so names are compared after replacing it.
+const FLAGS: &[(&str, Extension)] = &[
+    ("avx", Extension::Avx),
+    ("avx2", Extension::Avx2),
+    ("avx512f", Extension::Avx512F),
+    ("avx512cd", Extension::Avx512Cd),
+    ("avx512bw", Extension::Avx512Bw),
+    ("avx512dq", Extension::Avx512Dq),
+    ("avx512vl", Extension::Avx512Vl),
+    ("avx512vbmi", Extension::Avx512Vbmi),
+    ("avx512vbmi2", Extension::Avx512Vbmi2),
+    ("avx512bitalg", Extension::Avx512Bitalg),
+    ("avx512-vpopcntdq", Extension::Avx512Vpopcntdq),
+    ("bmi1", Extension::Bmi1),
+    ("bmi2", Extension::Bmi2),
+    ("adx", Extension::Adx),
+    ("aes", Extension::Aesni),
+    ("sha-ni", Extension::Sha),
+    ("fma", Extension::Fma3),
+    ("fma4", Extension::Fma4),
+    ("f16c", Extension::F16c),
+    ("movbe", Extension::Movbe),
+    ("popcnt", Extension::Popcnt),
+    ("rdrand", Extension::Rdrand),
+    ("rdseed", Extension::Rdseed),
+    ("xsave", Extension::Xsave),
+    ("gfni", Extension::Gfni),
+    ("vaes", Extension::Vaes),
+    ("pclmulqdq", Extension::Pclmulqdq),
+    ("pni", Extension::Sse3),
+    ("sse3", Extension::Sse3),
+    ("ssse3", Extension::Ssse3),
+    ("sse4.1", Extension::Sse4_1),
+    ("sse4.2", Extension::Sse4_2),
+    ("sse4a", Extension::Sse4a),
+    ("abm", Extension::Abm),
+    ("tbm", Extension::Tbm),
+    ("xop", Extension::Xop),
+    ("rdtscp", Extension::Rdtscp),
+    ("clwb", Extension::Clwb),
+    ("clflushopt", Extension::Clflushopt),
+    ("cx16", Extension::Cmpxchg16b),
+    ("lahf-lm", Extension::Lahfsahf),
+    ("rtm", Extension::Tsx),
+    ("hle", Extension::Tsx),
+];
+
+impl Extension {
+    fn from_flag(flag: &str) -> Option<Self> {
+        let flag = flag.replace('_', "-");
+
+        FLAGS
+            .iter()
+            .find_map(|(name, extension)| (*name == flag).then_some(*extension))
+    }
+
+    /// The extensions implied by the vCPU's register features
+    fn from_features(cpu: &Cpu) -> Vec<Self> {
+        let mut extensions = Vec::new();
+
+        if cpu.has_feature("org.gnu.gdb.i386.avx") {
+            extensions.push(Self::Avx);
+        }
+
+        if cpu.has_feature("org.gnu.gdb.i386.avx512") {
+            extensions.push(Self::Avx512F);
+        }
+
+        extensions
+    }
+
+    fn enabled(cpu: &Cpu) -> Vec<Self> {
+        cpu.flags
+            .iter()
+            .filter(|(_, enabled)| **enabled)
+            .filter_map(|(flag, _)| Self::from_flag(flag))
+            .chain(Self::from_features(cpu))
+            .collect()
+    }
+}
+
+/// Build a decoder for one of the x86 modes, which share preset and extension names
+macro_rules! x86_decoder {
+    ($name:ident, $mode:ident) => {
+        fn $name(cpu: &Cpu) -> $mode::InstDecoder {
+            use $mode::uarch::{amd, intel};
+
+            let Some(uarch) = Uarch::from_cpu(cpu) else {
+                return $mode::InstDecoder::default();
+            };
+
+            let decoder = match uarch {
+                Uarch::Core => intel::core(),
+                Uarch::Penryn => intel::penryn(),
+                Uarch::Nehalem => intel::nehalem(),
+                Uarch::Westmere => intel::westmere(),
+                Uarch::SandyBridge => intel::sandybridge(),
+                Uarch::IvyBridge => intel::ivybridge(),
+                Uarch::Haswell => intel::haswell(),
+                Uarch::Broadwell => intel::broadwell(),
+                Uarch::Skylake => intel::skylake(),
+                Uarch::SkylakeServer => intel::skylake()
+                    .with_avx512_f()
+                    .with_avx512_cd()
+                    .with_avx512_bw()
+                    .with_avx512_dq()
+                    .with_avx512_vl(),
+                Uarch::Icelake => intel::skylake()
+                    .with_avx512_f()
+                    .with_avx512_cd()
+                    .with_avx512_bw()
+                    .with_avx512_dq()
+                    .with_avx512_vl()
+                    .with_avx512_vbmi()
+                    .with_avx512_vbmi2()
+                    .with_avx512_bitalg()
+                    .with_avx512_vpopcntdq()
+                    .with_gfni()
+                    .with_vaes(),
+                Uarch::K8 => amd::k8(),
+                Uarch::K10 => amd::k10(),
+                Uarch::Bulldozer => amd::bulldozer(),
+                Uarch::Piledriver => amd::piledriver(),
+                Uarch::Zen => amd::zen(),
+                Uarch::Zen2 => amd::zen2(),
+                Uarch::Zen3 => amd::zen3(),
+            };
+
+            Extension::enabled(cpu).into_iter().fold(
+                decoder,
+                |decoder, extension| match extension {
+                    Extension::Avx => decoder.with_avx(),
+                    Extension::Avx2 => decoder.with_avx2(),
+                    Extension::Avx512F => decoder.with_avx512_f(),
+                    Extension::Avx512Cd => decoder.with_avx512_cd(),
+                    Extension::Avx512Bw => decoder.with_avx512_bw(),
+                    Extension::Avx512Dq => decoder.with_avx512_dq(),
+                    Extension::Avx512Vl => decoder.with_avx512_vl(),
+                    Extension::Avx512Vbmi => decoder.with_avx512_vbmi(),
+                    Extension::Avx512Vbmi2 => decoder.with_avx512_vbmi2(),
+                    Extension::Avx512Bitalg => decoder.with_avx512_bitalg(),
+                    Extension::Avx512Vpopcntdq => decoder.with_avx512_vpopcntdq(),
+                    Extension::Bmi1 => decoder.with_bmi1(),
+                    Extension::Bmi2 => decoder.with_bmi2(),
+                    Extension::Adx => decoder.with_adx(),
+                    Extension::Aesni => decoder.with_aesni(),
+                    Extension::Sha => decoder.with_sha(),
+                    Extension::Fma3 => decoder.with_fma3(),
+                    Extension::Fma4 => decoder.with_fma4(),
+                    Extension::F16c => decoder.with_f16c(),
+                    Extension::Movbe => decoder.with_movbe(),
+                    Extension::Popcnt => decoder.with_popcnt(),
+                    Extension::Rdrand => decoder.with_rdrand(),
+                    Extension::Rdseed => decoder.with_rdseed(),
+                    Extension::Xsave => decoder.with_xsave(),
+                    Extension::Gfni => decoder.with_gfni(),
+                    Extension::Vaes => decoder.with_vaes(),
+                    Extension::Pclmulqdq => decoder.with_pclmulqdq(),
+                    Extension::Sse3 => decoder.with_sse3(),
+                    Extension::Ssse3 => decoder.with_ssse3(),
+                    Extension::Sse4_1 => decoder.with_sse4_1(),
+                    Extension::Sse4_2 => decoder.with_sse4_2(),
+                    Extension::Sse4a => decoder.with_sse4a(),
+                    Extension::Abm => decoder.with_abm(),
+                    Extension::Tbm => decoder.with_tbm(),
+                    Extension::Xop => decoder.with_xop(),
+                    Extension::Rdtscp => decoder.with_rdtscp(),
+                    Extension::Clwb => decoder.with_clwb(),
+                    Extension::Clflushopt => decoder.with_clflushopt(),
+                    Extension::Cmpxchg16b => decoder.with_cmpxchg16b(),
+                    Extension::Lahfsahf => decoder.with_lahfsahf(),
+                    Extension::Tsx => decoder.with_tsx(),
+                },
+            )
+        }
+    };
+}
+
+x86_decoder!(x86_64_decoder, amd64);
+x86_decoder!(i386_decoder, protected_mode);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// How an instruction's memory operand computes its effective address, as
+/// `segment:[base + index * scale + displacement]`
+pub struct AddressBreakdown {
+    /// The segment override, if the instruction has one
+    pub segment: Option<String>,
+    pub base: Option<String>,
+    pub index: Option<String>,
+    /// The scale applied to the index, or `None` without an index
+    pub scale: Option<u8>,
+    /// The displacement, or the whole address for an absolute operand
+    pub displacement: i64,
+}
+
+/// Break down the first memory operand of a decoded instruction. The mode's absolute
+/// operand variants are given in addition to the 32-bit one they share.
+macro_rules! x86_breakdown {
+    ($name:ident, $mode:ident, $absolute:ident) => {
+        fn $name(insn: &$mode::Instruction) -> Option<AddressBreakdown> {
+            use $mode::Operand;
+
+            (0..insn.operand_count()).find_map(|i| {
+                let (base, index, scale, displacement) = match insn.operand(i) {
+                    Operand::AbsoluteU32 { addr } => (None, None, None, addr as i64),
+                    Operand::$absolute { addr } => (None, None, None, addr as i64),
+                    Operand::MemDeref { base } | Operand::MemDerefMasked { base, .. } => {
+                        (Some(base), None, None, 0)
+                    }
+                    Operand::Disp { base, disp } | Operand::DispMasked { base, disp, .. } => {
+                        (Some(base), None, None, disp as i64)
+                    }
+                    Operand::MemIndexScale { index, scale }
+                    | Operand::MemIndexScaleMasked { index, scale, .. } => {
+                        (None, Some(index), Some(scale), 0)
+                    }
+                    Operand::MemIndexScaleDisp { index, scale, disp }
+                    | Operand::MemIndexScaleDispMasked {
+                        index, scale, disp, ..
+                    } => (None, Some(index), Some(scale), disp as i64),
+                    Operand::MemBaseIndexScale { base, index, scale }
+                    | Operand::MemBaseIndexScaleMasked {
+                        base, index, scale, ..
+                    } => (Some(base), Some(index), Some(scale), 0),
+                    Operand::MemBaseIndexScaleDisp {
+                        base,
+                        index,
+                        scale,
+                        disp,
+                    }
+                    | Operand::MemBaseIndexScaleDispMasked {
+                        base,
+                        index,
+                        scale,
+                        disp,
+                        ..
+                    } => (Some(base), Some(index), Some(scale), disp as i64),
+                    _ => return None,
+                };
+
+                Some(AddressBreakdown {
+                    segment: insn.segment_override_for_op(i).map(|s| s.to_string()),
+                    base: base.map(|r| r.to_string()),
+                    index: index.map(|r| r.to_string()),
+                    scale,
+                    displacement,
+                })
+            })
+        }
+    };
+}
+
+x86_breakdown!(x86_64_breakdown, amd64, AbsoluteU64);
+x86_breakdown!(i386_breakdown, protected_mode, AbsoluteU16);
+
+#[derive(Clone, Copy, Default)]
+/// Decodes guest instructions for the target architecture and CPU
+pub enum Decoder {
+    /// 64-bit x86
+    X86_64(amd64::InstDecoder),
+    /// 32-bit x86
+    I386(protected_mode::InstDecoder),
+    #[default]
+    /// No decoder is available for the target, so QEMU's disassembly is used
+    Qemu,
+}
+
+impl Debug for Decoder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::X86_64(decoder) => write!(f, "X86_64({decoder})"),
+            Self::I386(decoder) => write!(f, "I386({decoder})"),
+            Self::Qemu => write!(f, "Qemu"),
+        }
+    }
+}
+
+impl Decoder {
+    /// Create a decoder for `arch`, restricted to the extensions of `cpu`
+    pub fn new(arch: Option<Arch>, cpu: &Cpu) -> Self {
+        match arch {
+            Some(Arch::X86_64) => Self::X86_64(x86_64_decoder(cpu)),
+            Some(Arch::I386) => Self::I386(i386_decoder(cpu)),
+            _ => Self::Qemu,
+        }
+    }
+
+    /// Create a decoder for code executing in `mode`, which may not be the target's default,
+    /// such as 32-bit code on an x86_64 target. Real mode and ARM code are left to QEMU's
+    /// disassembly, which follows the vCPU's mode itself.
+    pub fn for_mode(arch: Option<Arch>, cpu: &Cpu, mode: IsaMode) -> Self {
+        match (arch, mode) {
+            (Some(Arch::X86_64 | Arch::I386), IsaMode::Long64) => Self::X86_64(x86_64_decoder(cpu)),
+            (Some(Arch::X86_64 | Arch::I386), IsaMode::Protected32) => {
+                Self::I386(i386_decoder(cpu))
+            }
+            _ => Self::Qemu,
+        }
+    }
+
+    /// Disassemble one instruction, or return `None` if it is not legal for the CPU or no
+    /// decoder is available
+    pub fn decode(&self, data: &[u8]) -> Option<String> {
+        match self {
+            Self::X86_64(decoder) => decoder.decode_slice(data).ok().map(|i| i.to_string()),
+            Self::I386(decoder) => decoder.decode_slice(data).ok().map(|i| i.to_string()),
+            Self::Qemu => None,
+        }
+    }
+
+    /// Decode one instruction and break down the effective address of its first memory
+    /// operand. Returns `None` if it has none, or if no decoder is available.
+    pub fn address_breakdown(&self, data: &[u8]) -> Option<AddressBreakdown> {
+        match self {
+            Self::X86_64(decoder) => x86_64_breakdown(&decoder.decode_slice(data).ok()?),
+            Self::I386(decoder) => i386_breakdown(&decoder.decode_slice(data).ok()?),
+            Self::Qemu => None,
+        }
+    }
+}
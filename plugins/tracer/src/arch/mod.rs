@@ -0,0 +1,1677 @@
+//! Per-architecture knowledge about guest targets, such as syscall numbers
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub mod decoder;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+/// A guest architecture, as named by QEMU's `target_name`
+pub enum Arch {
+    I386,
+    X86_64,
+    Arm,
+    Aarch64,
+    /// Big-endian MIPS with the o32 ABI
+    Mips,
+    /// Little-endian MIPS with the o32 ABI
+    Mipsel,
+    /// Big-endian MIPS64 with the n64 ABI
+    Mips64,
+    /// Little-endian MIPS64 with the n64 ABI
+    Mips64el,
+    /// 32-bit big-endian PowerPC
+    Ppc,
+    /// Big-endian 64-bit PowerPC
+    Ppc64,
+    /// Little-endian 64-bit PowerPC
+    Ppc64le,
+    LoongArch64,
+    S390x,
+    Hexagon,
+    Riscv32,
+    Riscv64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+/// Linux syscalls the tracer knows how to interpret
+pub enum Syscall {
+    Read,
+    Write,
+    Open,
+    Openat,
+    Close,
+    Lseek,
+    Pread64,
+    Pwrite64,
+    Readv,
+    Writev,
+    Mmap,
+    Mprotect,
+    Munmap,
+    Socket,
+    Bind,
+    Listen,
+    Accept,
+    Accept4,
+    Connect,
+    Sendto,
+    Recvfrom,
+    Sendmsg,
+    Recvmsg,
+    Execve,
+    Exit,
+    ExitGroup,
+    Futex,
+    Getrandom,
+    Clone,
+    Gettid,
+    Kill,
+    Tkill,
+    Tgkill,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+/// Instructions which enter or leave a function
+pub enum Branch {
+    Call,
+    Return,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+/// Instructions which use the exclusive monitor
+pub enum Exclusive {
+    /// A load-exclusive, which opens the monitor
+    Load,
+    /// A store-exclusive, which writes 0 to the status register on success and 1 on failure
+    Store { status: u8 },
+    /// A `clrex`, which abandons the monitor
+    Clear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+/// Instructions from cryptographic extensions
+pub enum Crypto {
+    /// AES rounds and key expansion, such as AES-NI or the ARMv8 AES instructions
+    Aes,
+    /// SHA-1, SHA-2 and SHA-3 rounds and message schedules
+    Sha,
+    /// Carry-less multiplication, used by GCM and CRCs, such as `pclmulqdq` or `pmull`
+    Carryless,
+    /// SM3 and SM4 rounds
+    Sm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+/// Instructions from an extension of the base instruction set, such as a vector extension
+pub enum Extension {
+    /// x87 floating point
+    X87,
+    /// MMX, on the `mm` registers
+    Mmx,
+    /// SSE up to SSE4.2, on the `xmm` registers without a VEX encoding
+    Sse,
+    /// AVX, including the VEX-encoded forms of SSE instructions
+    Avx,
+    /// AVX2's integer and permute instructions on `ymm` registers, gathers and broadcasts
+    Avx2,
+    /// AVX-512, on the `zmm` or mask registers or on vector registers above 15
+    Avx512,
+    /// Fused multiply-adds
+    Fma,
+    /// BMI1 and BMI2 bit manipulation
+    Bmi,
+    /// VFP floating point on 32-bit ARM
+    Vfp,
+    /// Advanced SIMD, on the `v` or `q` registers
+    Neon,
+    /// SVE and SVE2, on the `z` or predicate registers
+    Sve,
+    /// SME, on the `za` array
+    Sme,
+    /// AltiVec, also called VMX
+    Altivec,
+    /// VSX, on the 64 vector-scalar registers
+    Vsx,
+    /// MIPS SIMD Architecture
+    Msa,
+    /// LoongArch's 128-bit SIMD
+    Lsx,
+    /// LoongArch's 256-bit SIMD
+    Lasx,
+    /// The z/Architecture vector facility
+    ZVector,
+    /// Hexagon Vector eXtensions
+    Hvx,
+    /// The RISC-V vector extension
+    Rvv,
+    /// RISC-V's Zba, Zbb and Zbs bit manipulation
+    Zb,
+    /// A cryptographic extension
+    Crypto(Crypto),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+/// The instruction set a vCPU executes, which decides how its code must be disassembled
+pub enum IsaMode {
+    /// AArch64's A64 instruction set
+    A64,
+    /// The 32-bit ARM instruction set, including AArch64's AArch32 state
+    Arm,
+    /// The Thumb and Thumb-2 instruction sets, including ThumbEE
+    Thumb,
+    /// Java bytecode, executed by ARM cores with Jazelle
+    Jazelle,
+    /// x86 16-bit real mode
+    Real16,
+    /// x86 32-bit protected mode
+    Protected32,
+    /// x86 64-bit long mode
+    Long64,
+    /// The MIPS32 and MIPS64 instruction sets. MIPS16e and microMIPS code is not told apart.
+    Mips,
+    /// The PowerPC instruction set, which has no other modes
+    Power,
+    /// The LoongArch instruction set, which has no other modes
+    LoongArch,
+    /// z/Architecture, taken to be in 64-bit addressing mode
+    ZArch,
+    /// The Hexagon instruction set, which has no other modes
+    Hexagon,
+    /// The RISC-V instruction set, including compressed instructions
+    Riscv,
+}
+
+/// The Thumb and Jazelle state bits of the ARM CPSR
+const CPSR_T: u64 = 1 << 5;
+const CPSR_J: u64 = 1 << 24;
+/// Set in AArch64's PSTATE when executing in AArch32 state
+const PSTATE_NRW: u64 = 1 << 4;
+/// Set in x86's CR0 once protected mode is enabled
+const CR0_PE: u64 = 1 << 0;
+/// Set in x86's EFER while long mode is active
+const EFER_LMA: u64 = 1 << 10;
+
+const I386_SYSCALLS: &[(Syscall, i64)] = &[
+    (Syscall::Exit, 1),
+    (Syscall::Read, 3),
+    (Syscall::Write, 4),
+    (Syscall::Open, 5),
+    (Syscall::Close, 6),
+    (Syscall::Execve, 11),
+    (Syscall::Lseek, 19),
+    (Syscall::Kill, 37),
+    (Syscall::Munmap, 91),
+    (Syscall::Clone, 120),
+    (Syscall::Mprotect, 125),
+    (Syscall::Readv, 145),
+    (Syscall::Writev, 146),
+    (Syscall::Pread64, 180),
+    (Syscall::Pwrite64, 181),
+    (Syscall::Mmap, 192),
+    (Syscall::Gettid, 224),
+    (Syscall::Tkill, 238),
+    (Syscall::Futex, 240),
+    (Syscall::ExitGroup, 252),
+    (Syscall::Tgkill, 270),
+    (Syscall::Openat, 295),
+    (Syscall::Getrandom, 355),
+    (Syscall::Socket, 359),
+    (Syscall::Bind, 361),
+    (Syscall::Connect, 362),
+    (Syscall::Listen, 363),
+    (Syscall::Accept4, 364),
+    (Syscall::Sendto, 369),
+    (Syscall::Sendmsg, 370),
+    (Syscall::Recvfrom, 371),
+    (Syscall::Recvmsg, 372),
+];
+
+const X86_64_SYSCALLS: &[(Syscall, i64)] = &[
+    (Syscall::Read, 0),
+    (Syscall::Write, 1),
+    (Syscall::Open, 2),
+    (Syscall::Close, 3),
+    (Syscall::Lseek, 8),
+    (Syscall::Mmap, 9),
+    (Syscall::Mprotect, 10),
+    (Syscall::Munmap, 11),
+    (Syscall::Pread64, 17),
+    (Syscall::Pwrite64, 18),
+    (Syscall::Readv, 19),
+    (Syscall::Writev, 20),
+    (Syscall::Socket, 41),
+    (Syscall::Connect, 42),
+    (Syscall::Accept, 43),
+    (Syscall::Sendto, 44),
+    (Syscall::Recvfrom, 45),
+    (Syscall::Sendmsg, 46),
+    (Syscall::Recvmsg, 47),
+    (Syscall::Bind, 49),
+    (Syscall::Listen, 50),
+    (Syscall::Clone, 56),
+    (Syscall::Execve, 59),
+    (Syscall::Exit, 60),
+    (Syscall::Kill, 62),
+    (Syscall::Gettid, 186),
+    (Syscall::Tkill, 200),
+    (Syscall::Futex, 202),
+    (Syscall::ExitGroup, 231),
+    (Syscall::Tgkill, 234),
+    (Syscall::Openat, 257),
+    (Syscall::Accept4, 288),
+    (Syscall::Getrandom, 318),
+];
+
+const ARM_SYSCALLS: &[(Syscall, i64)] = &[
+    (Syscall::Exit, 1),
+    (Syscall::Read, 3),
+    (Syscall::Write, 4),
+    (Syscall::Open, 5),
+    (Syscall::Close, 6),
+    (Syscall::Execve, 11),
+    (Syscall::Lseek, 19),
+    (Syscall::Kill, 37),
+    (Syscall::Munmap, 91),
+    (Syscall::Clone, 120),
+    (Syscall::Mprotect, 125),
+    (Syscall::Readv, 145),
+    (Syscall::Writev, 146),
+    (Syscall::Pread64, 180),
+    (Syscall::Pwrite64, 181),
+    (Syscall::Mmap, 192),
+    (Syscall::Gettid, 224),
+    (Syscall::Tkill, 238),
+    (Syscall::Futex, 240),
+    (Syscall::ExitGroup, 248),
+    (Syscall::Tgkill, 268),
+    (Syscall::Socket, 281),
+    (Syscall::Bind, 282),
+    (Syscall::Connect, 283),
+    (Syscall::Listen, 284),
+    (Syscall::Accept, 285),
+    (Syscall::Sendto, 290),
+    (Syscall::Recvfrom, 292),
+    (Syscall::Sendmsg, 296),
+    (Syscall::Recvmsg, 297),
+    (Syscall::Openat, 322),
+    (Syscall::Accept4, 366),
+    (Syscall::Getrandom, 384),
+];
+
+const AARCH64_SYSCALLS: &[(Syscall, i64)] = &[
+    (Syscall::Openat, 56),
+    (Syscall::Close, 57),
+    (Syscall::Lseek, 62),
+    (Syscall::Read, 63),
+    (Syscall::Write, 64),
+    (Syscall::Readv, 65),
+    (Syscall::Writev, 66),
+    (Syscall::Pread64, 67),
+    (Syscall::Pwrite64, 68),
+    (Syscall::Exit, 93),
+    (Syscall::ExitGroup, 94),
+    (Syscall::Futex, 98),
+    (Syscall::Kill, 129),
+    (Syscall::Tkill, 130),
+    (Syscall::Tgkill, 131),
+    (Syscall::Gettid, 178),
+    (Syscall::Socket, 198),
+    (Syscall::Bind, 200),
+    (Syscall::Listen, 201),
+    (Syscall::Accept, 202),
+    (Syscall::Connect, 203),
+    (Syscall::Sendto, 206),
+    (Syscall::Recvfrom, 207),
+    (Syscall::Sendmsg, 211),
+    (Syscall::Recvmsg, 212),
+    (Syscall::Munmap, 215),
+    (Syscall::Clone, 220),
+    (Syscall::Execve, 221),
+    (Syscall::Mmap, 222),
+    (Syscall::Mprotect, 226),
+    (Syscall::Accept4, 242),
+    (Syscall::Getrandom, 278),
+];
+
+const MIPS_O32_SYSCALLS: &[(Syscall, i64)] = &[
+    (Syscall::Exit, 4001),
+    (Syscall::Read, 4003),
+    (Syscall::Write, 4004),
+    (Syscall::Open, 4005),
+    (Syscall::Close, 4006),
+    (Syscall::Execve, 4011),
+    (Syscall::Lseek, 4019),
+    (Syscall::Kill, 4037),
+    (Syscall::Mmap, 4090),
+    (Syscall::Munmap, 4091),
+    (Syscall::Clone, 4120),
+    (Syscall::Mprotect, 4125),
+    (Syscall::Readv, 4145),
+    (Syscall::Writev, 4146),
+    (Syscall::Accept, 4168),
+    (Syscall::Bind, 4169),
+    (Syscall::Connect, 4170),
+    (Syscall::Listen, 4174),
+    (Syscall::Recvfrom, 4176),
+    (Syscall::Recvmsg, 4177),
+    (Syscall::Sendmsg, 4179),
+    (Syscall::Sendto, 4180),
+    (Syscall::Socket, 4183),
+    (Syscall::Pread64, 4200),
+    (Syscall::Pwrite64, 4201),
+    (Syscall::Gettid, 4222),
+    (Syscall::Tkill, 4236),
+    (Syscall::Futex, 4238),
+    (Syscall::ExitGroup, 4246),
+    (Syscall::Tgkill, 4266),
+    (Syscall::Openat, 4288),
+    (Syscall::Accept4, 4334),
+    (Syscall::Getrandom, 4353),
+];
+
+const MIPS_N64_SYSCALLS: &[(Syscall, i64)] = &[
+    (Syscall::Read, 5000),
+    (Syscall::Write, 5001),
+    (Syscall::Open, 5002),
+    (Syscall::Close, 5003),
+    (Syscall::Lseek, 5008),
+    (Syscall::Mmap, 5009),
+    (Syscall::Mprotect, 5010),
+    (Syscall::Munmap, 5011),
+    (Syscall::Pread64, 5016),
+    (Syscall::Pwrite64, 5017),
+    (Syscall::Readv, 5018),
+    (Syscall::Writev, 5019),
+    (Syscall::Socket, 5040),
+    (Syscall::Connect, 5041),
+    (Syscall::Accept, 5042),
+    (Syscall::Sendto, 5043),
+    (Syscall::Recvfrom, 5044),
+    (Syscall::Sendmsg, 5045),
+    (Syscall::Recvmsg, 5046),
+    (Syscall::Bind, 5048),
+    (Syscall::Listen, 5049),
+    (Syscall::Clone, 5055),
+    (Syscall::Execve, 5057),
+    (Syscall::Exit, 5058),
+    (Syscall::Kill, 5060),
+    (Syscall::Gettid, 5178),
+    (Syscall::Tkill, 5192),
+    (Syscall::Futex, 5194),
+    (Syscall::ExitGroup, 5205),
+    (Syscall::Tgkill, 5225),
+    (Syscall::Openat, 5247),
+    (Syscall::Accept4, 5293),
+    (Syscall::Getrandom, 5313),
+];
+
+/// Shared by 32- and 64-bit PowerPC
+const PPC_SYSCALLS: &[(Syscall, i64)] = &[
+    (Syscall::Exit, 1),
+    (Syscall::Read, 3),
+    (Syscall::Write, 4),
+    (Syscall::Open, 5),
+    (Syscall::Close, 6),
+    (Syscall::Execve, 11),
+    (Syscall::Lseek, 19),
+    (Syscall::Kill, 37),
+    (Syscall::Mmap, 90),
+    (Syscall::Munmap, 91),
+    (Syscall::Clone, 120),
+    (Syscall::Mprotect, 125),
+    (Syscall::Readv, 145),
+    (Syscall::Writev, 146),
+    (Syscall::Pread64, 179),
+    (Syscall::Pwrite64, 180),
+    (Syscall::Gettid, 207),
+    (Syscall::Tkill, 208),
+    (Syscall::Futex, 221),
+    (Syscall::ExitGroup, 234),
+    (Syscall::Tgkill, 250),
+    (Syscall::Openat, 286),
+    (Syscall::Socket, 326),
+    (Syscall::Bind, 327),
+    (Syscall::Connect, 328),
+    (Syscall::Listen, 329),
+    (Syscall::Accept, 330),
+    (Syscall::Sendto, 335),
+    (Syscall::Recvfrom, 337),
+    (Syscall::Sendmsg, 341),
+    (Syscall::Recvmsg, 342),
+    (Syscall::Accept4, 344),
+    (Syscall::Getrandom, 359),
+];
+
+/// The asm-generic table
+const LOONGARCH64_SYSCALLS: &[(Syscall, i64)] = &[
+    (Syscall::Openat, 56),
+    (Syscall::Close, 57),
+    (Syscall::Lseek, 62),
+    (Syscall::Read, 63),
+    (Syscall::Write, 64),
+    (Syscall::Readv, 65),
+    (Syscall::Writev, 66),
+    (Syscall::Pread64, 67),
+    (Syscall::Pwrite64, 68),
+    (Syscall::Exit, 93),
+    (Syscall::ExitGroup, 94),
+    (Syscall::Futex, 98),
+    (Syscall::Kill, 129),
+    (Syscall::Tkill, 130),
+    (Syscall::Tgkill, 131),
+    (Syscall::Gettid, 178),
+    (Syscall::Socket, 198),
+    (Syscall::Bind, 200),
+    (Syscall::Listen, 201),
+    (Syscall::Accept, 202),
+    (Syscall::Connect, 203),
+    (Syscall::Sendto, 206),
+    (Syscall::Recvfrom, 207),
+    (Syscall::Sendmsg, 211),
+    (Syscall::Recvmsg, 212),
+    (Syscall::Munmap, 215),
+    (Syscall::Clone, 220),
+    (Syscall::Execve, 221),
+    (Syscall::Mmap, 222),
+    (Syscall::Mprotect, 226),
+    (Syscall::Accept4, 242),
+    (Syscall::Getrandom, 278),
+];
+
+/// `mmap` is left out, since on s390x it takes its arguments in memory, and there is no
+/// `accept` but `accept4`
+const S390X_SYSCALLS: &[(Syscall, i64)] = &[
+    (Syscall::Exit, 1),
+    (Syscall::Read, 3),
+    (Syscall::Write, 4),
+    (Syscall::Open, 5),
+    (Syscall::Close, 6),
+    (Syscall::Execve, 11),
+    (Syscall::Lseek, 19),
+    (Syscall::Kill, 37),
+    (Syscall::Munmap, 91),
+    (Syscall::Clone, 120),
+    (Syscall::Mprotect, 125),
+    (Syscall::Readv, 145),
+    (Syscall::Writev, 146),
+    (Syscall::Pread64, 180),
+    (Syscall::Pwrite64, 181),
+    (Syscall::Gettid, 236),
+    (Syscall::Tkill, 237),
+    (Syscall::Futex, 238),
+    (Syscall::Tgkill, 241),
+    (Syscall::ExitGroup, 248),
+    (Syscall::Openat, 288),
+    (Syscall::Getrandom, 349),
+    (Syscall::Socket, 359),
+    (Syscall::Bind, 361),
+    (Syscall::Connect, 362),
+    (Syscall::Listen, 363),
+    (Syscall::Accept4, 364),
+    (Syscall::Sendto, 369),
+    (Syscall::Sendmsg, 370),
+    (Syscall::Recvfrom, 371),
+    (Syscall::Recvmsg, 372),
+];
+
+/// The asm-generic table of 32-bit targets with only 64-bit time, where `futex` is
+/// `futex_time64` and `mmap` is `mmap2`, and without `llseek`
+const RISCV32_SYSCALLS: &[(Syscall, i64)] = &[
+    (Syscall::Openat, 56),
+    (Syscall::Close, 57),
+    (Syscall::Read, 63),
+    (Syscall::Write, 64),
+    (Syscall::Readv, 65),
+    (Syscall::Writev, 66),
+    (Syscall::Pread64, 67),
+    (Syscall::Pwrite64, 68),
+    (Syscall::Exit, 93),
+    (Syscall::ExitGroup, 94),
+    (Syscall::Kill, 129),
+    (Syscall::Tkill, 130),
+    (Syscall::Tgkill, 131),
+    (Syscall::Gettid, 178),
+    (Syscall::Socket, 198),
+    (Syscall::Bind, 200),
+    (Syscall::Listen, 201),
+    (Syscall::Accept, 202),
+    (Syscall::Connect, 203),
+    (Syscall::Sendto, 206),
+    (Syscall::Recvfrom, 207),
+    (Syscall::Sendmsg, 211),
+    (Syscall::Recvmsg, 212),
+    (Syscall::Munmap, 215),
+    (Syscall::Clone, 220),
+    (Syscall::Execve, 221),
+    (Syscall::Mmap, 222),
+    (Syscall::Mprotect, 226),
+    (Syscall::Accept4, 242),
+    (Syscall::Getrandom, 278),
+    (Syscall::Futex, 422),
+];
+
+/// The asm-generic table of 32-bit targets, without `llseek` and `mmap2`, which replace
+/// `lseek` and `mmap` and take their arguments differently
+const HEXAGON_SYSCALLS: &[(Syscall, i64)] = &[
+    (Syscall::Openat, 56),
+    (Syscall::Close, 57),
+    (Syscall::Read, 63),
+    (Syscall::Write, 64),
+    (Syscall::Readv, 65),
+    (Syscall::Writev, 66),
+    (Syscall::Pread64, 67),
+    (Syscall::Pwrite64, 68),
+    (Syscall::Exit, 93),
+    (Syscall::ExitGroup, 94),
+    (Syscall::Futex, 98),
+    (Syscall::Kill, 129),
+    (Syscall::Tkill, 130),
+    (Syscall::Tgkill, 131),
+    (Syscall::Gettid, 178),
+    (Syscall::Socket, 198),
+    (Syscall::Bind, 200),
+    (Syscall::Listen, 201),
+    (Syscall::Accept, 202),
+    (Syscall::Connect, 203),
+    (Syscall::Sendto, 206),
+    (Syscall::Recvfrom, 207),
+    (Syscall::Sendmsg, 211),
+    (Syscall::Recvmsg, 212),
+    (Syscall::Munmap, 215),
+    (Syscall::Clone, 220),
+    (Syscall::Execve, 221),
+    (Syscall::Mprotect, 226),
+    (Syscall::Accept4, 242),
+    (Syscall::Getrandom, 278),
+];
+
+/// Decode an integer of at most 8 bytes in the given byte order, zero-extending narrower
+/// values. Guest values are decoded with [`Arch::decode`], and files whose header gives their
+/// byte order, such as ELF files, with this.
+pub fn decode(data: &[u8], big_endian: bool) -> u64 {
+    let data = &data[..data.len().min(8)];
+    let mut bytes = [0u8; 8];
+
+    if big_endian {
+        bytes[8 - data.len()..].copy_from_slice(data);
+        u64::from_be_bytes(bytes)
+    } else {
+        bytes[..data.len()].copy_from_slice(data);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+impl Arch {
+    /// Returns the architecture for a QEMU target name, if it is supported
+    pub fn from_target_name(target_name: &str) -> Option<Self> {
+        match target_name {
+            "i386" => Some(Self::I386),
+            "x86_64" => Some(Self::X86_64),
+            "arm" => Some(Self::Arm),
+            "aarch64" => Some(Self::Aarch64),
+            "mips" => Some(Self::Mips),
+            "mipsel" => Some(Self::Mipsel),
+            "mips64" => Some(Self::Mips64),
+            "mips64el" => Some(Self::Mips64el),
+            "ppc" => Some(Self::Ppc),
+            "ppc64" => Some(Self::Ppc64),
+            "ppc64le" => Some(Self::Ppc64le),
+            "loongarch64" => Some(Self::LoongArch64),
+            "s390x" => Some(Self::S390x),
+            "hexagon" => Some(Self::Hexagon),
+            "riscv32" => Some(Self::Riscv32),
+            "riscv64" => Some(Self::Riscv64),
+            _ => None,
+        }
+    }
+
+    fn syscalls(&self) -> &'static [(Syscall, i64)] {
+        match self {
+            Self::I386 => I386_SYSCALLS,
+            Self::X86_64 => X86_64_SYSCALLS,
+            Self::Arm => ARM_SYSCALLS,
+            Self::Aarch64 => AARCH64_SYSCALLS,
+            Self::Mips | Self::Mipsel => MIPS_O32_SYSCALLS,
+            Self::Mips64 | Self::Mips64el => MIPS_N64_SYSCALLS,
+            Self::Ppc | Self::Ppc64 | Self::Ppc64le => PPC_SYSCALLS,
+            Self::LoongArch64 => LOONGARCH64_SYSCALLS,
+            Self::S390x => S390X_SYSCALLS,
+            Self::Hexagon => HEXAGON_SYSCALLS,
+            Self::Riscv32 => RISCV32_SYSCALLS,
+            // RISC-V uses the asm-generic table, like AArch64
+            Self::Riscv64 => AARCH64_SYSCALLS,
+        }
+    }
+
+    /// The instruction set the target starts executing in, and stays in if its mode cannot
+    /// be read
+    pub fn default_mode(&self) -> IsaMode {
+        match self {
+            Self::I386 => IsaMode::Protected32,
+            Self::X86_64 => IsaMode::Long64,
+            Self::Arm => IsaMode::Arm,
+            Self::Aarch64 => IsaMode::A64,
+            Self::Mips | Self::Mipsel | Self::Mips64 | Self::Mips64el => IsaMode::Mips,
+            Self::Ppc | Self::Ppc64 | Self::Ppc64le => IsaMode::Power,
+            Self::LoongArch64 => IsaMode::LoongArch,
+            Self::S390x => IsaMode::ZArch,
+            Self::Hexagon => IsaMode::Hexagon,
+            Self::Riscv32 | Self::Riscv64 => IsaMode::Riscv,
+        }
+    }
+
+    /// Returns the registers the instruction set is read from, in the order [`Arch::mode`]
+    /// takes their values. x86 exposes them in system mode only, and targets with a single
+    /// instruction set have none.
+    pub fn mode_register_names(&self) -> &'static [&'static str] {
+        match self {
+            Self::I386 | Self::X86_64 => &["cr0", "efer"],
+            Self::Arm | Self::Aarch64 => &["cpsr"],
+            Self::Mips
+            | Self::Mipsel
+            | Self::Mips64
+            | Self::Mips64el
+            | Self::Ppc
+            | Self::Ppc64
+            | Self::Ppc64le
+            | Self::LoongArch64
+            | Self::S390x
+            | Self::Hexagon
+            | Self::Riscv32
+            | Self::Riscv64 => &[],
+        }
+    }
+
+    /// The instruction set a vCPU executes, from the values of its mode registers. Code
+    /// segments whose default size differs from the mode, such as 32-bit compatibility mode
+    /// in long mode, are not told apart, since QEMU does not expose segment descriptors.
+    pub fn mode(&self, values: &[u64]) -> IsaMode {
+        let value = |i: usize| values.get(i).copied().unwrap_or_default();
+        let aarch32 = |cpsr: u64| match (cpsr & CPSR_T != 0, cpsr & CPSR_J != 0) {
+            (false, false) => IsaMode::Arm,
+            (false, true) => IsaMode::Jazelle,
+            (true, _) => IsaMode::Thumb,
+        };
+
+        match self {
+            Self::I386 | Self::X86_64 => {
+                if value(0) & CR0_PE == 0 {
+                    IsaMode::Real16
+                } else if value(1) & EFER_LMA != 0 {
+                    IsaMode::Long64
+                } else {
+                    IsaMode::Protected32
+                }
+            }
+            Self::Arm => aarch32(value(0)),
+            Self::Aarch64 if value(0) & PSTATE_NRW != 0 => aarch32(value(0)),
+            Self::Aarch64 => IsaMode::A64,
+            Self::Mips
+            | Self::Mipsel
+            | Self::Mips64
+            | Self::Mips64el
+            | Self::Ppc
+            | Self::Ppc64
+            | Self::Ppc64le
+            | Self::LoongArch64
+            | Self::S390x
+            | Self::Hexagon
+            | Self::Riscv32
+            | Self::Riscv64 => self.default_mode(),
+        }
+    }
+
+    /// Whether QEMU translates the target's code a packet of instructions at a time, so that
+    /// each plugin instruction and its disassembly is a whole packet
+    pub fn has_packets(&self) -> bool {
+        matches!(self, Self::Hexagon)
+    }
+
+    /// Returns the instructions of a plugin instruction's disassembly: on Hexagon those of
+    /// its packet, which QEMU prints as `{ insn; insn }` or with one instruction per line,
+    /// each after the word encoding it, and otherwise the disassembly itself
+    pub fn split_packet<'a>(&self, disas: &'a str) -> Vec<&'a str> {
+        if !self.has_packets() {
+            return vec![disas.trim()];
+        }
+
+        disas
+            .split(['\n', ';'])
+            .map(|insn| {
+                let insn = insn.trim();
+                // Drop the encoding QEMU prints first on each line
+                let insn = match insn.split_once(char::is_whitespace) {
+                    Some((word, rest)) if word.starts_with("0x") => rest,
+                    _ if insn.starts_with("0x") => "",
+                    _ => insn,
+                };
+                let insn = insn.split(":endloop").next().unwrap_or_default();
+
+                insn.trim()
+                    .trim_start_matches('{')
+                    .trim_end_matches('}')
+                    .trim()
+            })
+            .filter(|insn| !insn.is_empty())
+            .collect()
+    }
+
+    /// Returns the number of instructions in a plugin instruction's encoding: on Hexagon
+    /// those of its packet, where a duplex word holds two and constant extenders are not
+    /// counted, and otherwise one
+    pub fn instruction_count(&self, data: &[u8]) -> usize {
+        if !self.has_packets() {
+            return 1;
+        }
+
+        let mut count = 0;
+
+        for word in data.chunks_exact(4) {
+            let word = u32::from_le_bytes(word.try_into().expect("Chunks are 4 bytes"));
+
+            match (word >> 14) & 0b11 {
+                // A duplex, which always ends its packet
+                0b00 => return count + 2,
+                // A constant extender, in instruction class 0
+                _ if word >> 28 == 0 => {}
+                _ => count += 1,
+            }
+
+            if (word >> 14) & 0b11 == 0b11 {
+                break;
+            }
+        }
+
+        count
+    }
+
+    /// Returns the size of a guest pointer in bytes
+    pub fn pointer_size(&self) -> usize {
+        match self {
+            Self::I386
+            | Self::Arm
+            | Self::Mips
+            | Self::Mipsel
+            | Self::Ppc
+            | Self::Hexagon
+            | Self::Riscv32 => 4,
+            Self::X86_64
+            | Self::Aarch64
+            | Self::Mips64
+            | Self::Mips64el
+            | Self::Ppc64
+            | Self::Ppc64le
+            | Self::LoongArch64
+            | Self::S390x
+            | Self::Riscv64 => 8,
+        }
+    }
+
+    /// Whether the guest stores integers most significant byte first, in memory and in the
+    /// register values QEMU reports
+    pub fn big_endian(&self) -> bool {
+        match self {
+            Self::Mips | Self::Mips64 | Self::Ppc | Self::Ppc64 | Self::S390x => true,
+            Self::I386
+            | Self::X86_64
+            | Self::Arm
+            | Self::Aarch64
+            | Self::Mipsel
+            | Self::Mips64el
+            | Self::Ppc64le
+            | Self::LoongArch64
+            | Self::Hexagon
+            | Self::Riscv32
+            | Self::Riscv64 => false,
+        }
+    }
+
+    /// Decode an integer of at most 8 bytes, such as a register value or a pointer read from
+    /// guest memory, in the guest's byte order. Narrower values are zero-extended.
+    pub fn decode(&self, data: &[u8]) -> u64 {
+        decode(data, self.big_endian())
+    }
+
+    /// Returns the names QEMU may use for the stack pointer register on this architecture
+    pub fn stack_pointer_names(&self) -> &'static [&'static str] {
+        match self {
+            Self::I386 => &["esp"],
+            Self::X86_64 => &["rsp"],
+            Self::Arm => &["sp", "r13"],
+            Self::Aarch64 => &["sp"],
+            Self::Mips | Self::Mipsel | Self::Mips64 | Self::Mips64el => &["r29", "sp"],
+            Self::Ppc | Self::Ppc64 | Self::Ppc64le => &["r1"],
+            Self::LoongArch64 => &["r3", "sp"],
+            Self::S390x => &["r15"],
+            Self::Hexagon => &["r29", "sp"],
+            Self::Riscv32 | Self::Riscv64 => &["sp", "x2"],
+        }
+    }
+
+    /// Returns the names QEMU may use for the register holding the thread pointer, the base
+    /// of the running thread's thread-local storage. Empty on MIPS, which keeps it in the
+    /// `UserLocal` coprocessor register QEMU does not expose, and on s390x, which splits it
+    /// across access registers 0 and 1.
+    pub fn thread_pointer_names(&self) -> &'static [&'static str] {
+        match self {
+            Self::I386 => &["gs_base"],
+            Self::X86_64 => &["fs_base"],
+            Self::Arm => &["TPIDRURO", "tpidruro"],
+            Self::Aarch64 => &["TPIDR_EL0", "tpidr_el0"],
+            Self::Mips | Self::Mipsel | Self::Mips64 | Self::Mips64el => &[],
+            Self::Ppc => &["r2"],
+            Self::Ppc64 | Self::Ppc64le => &["r13"],
+            Self::LoongArch64 => &["r2", "tp"],
+            Self::S390x => &[],
+            Self::Hexagon => &["ugp"],
+            Self::Riscv32 | Self::Riscv64 => &["tp", "x4"],
+        }
+    }
+
+    /// Returns the 64-bit file offset passed to `pread64` or `pwrite64`. On 32-bit targets
+    /// the offset is split across two registers, which on ARM, MIPS, PowerPC and Hexagon
+    /// start at an even register, in the target's byte order.
+    pub fn rw_offset(&self, args: &[u64; 8]) -> u64 {
+        match self {
+            Self::I386 | Self::Riscv32 => (args[3] & 0xffff_ffff) | (args[4] << 32),
+            Self::Arm | Self::Mipsel | Self::Hexagon => (args[4] & 0xffff_ffff) | (args[5] << 32),
+            Self::Mips | Self::Ppc => (args[5] & 0xffff_ffff) | (args[4] << 32),
+            Self::X86_64
+            | Self::Aarch64
+            | Self::Mips64
+            | Self::Mips64el
+            | Self::Ppc64
+            | Self::Ppc64le
+            | Self::LoongArch64
+            | Self::S390x
+            | Self::Riscv64 => args[3],
+        }
+    }
+
+    /// Returns the file offset passed to `mmap`. The i386, ARM, Hexagon and 32-bit RISC-V
+    /// syscall is `mmap2`, which takes the offset in 4096-byte pages.
+    pub fn mmap_offset(&self, args: &[u64; 8]) -> u64 {
+        match self {
+            Self::I386 | Self::Arm | Self::Hexagon | Self::Riscv32 => args[5] << 12,
+            _ => args[5],
+        }
+    }
+
+    /// Returns the syscall number of `syscall` on this architecture, if it has one
+    pub fn sysno(&self, syscall: Syscall) -> Option<i64> {
+        self.syscalls()
+            .iter()
+            .find_map(|(s, num)| (*s == syscall).then_some(*num))
+    }
+
+    /// Returns the syscall with number `num` on this architecture, if it is known
+    pub fn syscall(&self, num: i64) -> Option<Syscall> {
+        self.syscalls()
+            .iter()
+            .find_map(|(s, n)| (*n == num).then_some(*s))
+    }
+
+    /// Returns the registers the first arguments of a function call are passed in. Empty on
+    /// i386, where arguments are passed on the stack.
+    pub fn argument_registers(&self) -> &'static [&'static str] {
+        match self {
+            Self::I386 => &[],
+            Self::X86_64 => &["rdi", "rsi", "rdx", "rcx", "r8", "r9"],
+            Self::Arm => &["r0", "r1", "r2", "r3"],
+            Self::Aarch64 => &["x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7"],
+            Self::Mips | Self::Mipsel => &["r4", "r5", "r6", "r7"],
+            Self::Mips64 | Self::Mips64el => &["r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11"],
+            Self::Ppc | Self::Ppc64 | Self::Ppc64le => {
+                &["r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10"]
+            }
+            Self::LoongArch64 => &["r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11"],
+            Self::S390x => &["r2", "r3", "r4", "r5", "r6"],
+            Self::Hexagon => &["r00", "r01", "r02", "r03", "r04", "r05"],
+            Self::Riscv32 | Self::Riscv64 => &["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"],
+        }
+    }
+
+    /// Returns the register a function's return value is passed in
+    pub fn return_register(&self) -> &'static str {
+        match self {
+            Self::I386 => "eax",
+            Self::X86_64 => "rax",
+            Self::Arm => "r0",
+            Self::Aarch64 => "x0",
+            Self::Mips | Self::Mipsel | Self::Mips64 | Self::Mips64el => "r2",
+            Self::Ppc | Self::Ppc64 | Self::Ppc64le => "r3",
+            Self::LoongArch64 => "r4",
+            Self::S390x => "r2",
+            Self::Hexagon => "r00",
+            Self::Riscv32 | Self::Riscv64 => "a0",
+        }
+    }
+
+    /// Returns the names QEMU may use for the register a call stores its return address in.
+    /// Empty on x86, where the return address is pushed to the stack.
+    pub fn link_register_names(&self) -> &'static [&'static str] {
+        match self {
+            Self::I386 | Self::X86_64 => &[],
+            Self::Arm => &["lr", "r14"],
+            Self::Aarch64 => &["lr", "x30"],
+            Self::Mips | Self::Mipsel | Self::Mips64 | Self::Mips64el => &["r31", "ra"],
+            Self::Ppc | Self::Ppc64 | Self::Ppc64le => &["lr"],
+            Self::LoongArch64 => &["r1", "ra"],
+            Self::S390x => &["r14"],
+            Self::Hexagon => &["r31", "lr"],
+            Self::Riscv32 | Self::Riscv64 => &["ra", "x1"],
+        }
+    }
+
+    /// Whether an instruction is an atomic or ordered memory access, such as a `lock`-prefixed
+    /// instruction or a load- or store-exclusive, from its disassembly
+    pub fn is_atomic(&self, disas: &str) -> bool {
+        let disas = disas.trim();
+        let (mnemonic, operands) = disas.split_once(char::is_whitespace).unwrap_or((disas, ""));
+
+        match self {
+            Self::I386 | Self::X86_64 => {
+                mnemonic == "lock"
+                    || mnemonic.starts_with("cmpxchg")
+                    // An exchange with memory is locked without a prefix
+                    || (mnemonic.starts_with("xchg")
+                        && (operands.contains('[') || operands.contains('(')))
+            }
+            Self::Arm => ["ldrex", "strex", "ldaex", "stlex", "lda", "stl", "swp"]
+                .iter()
+                .any(|prefix| mnemonic.starts_with(prefix)),
+            Self::Aarch64 => [
+                "ldxr", "ldxp", "ldaxr", "ldaxp", "stxr", "stxp", "stlxr", "stlxp", "ldar",
+                "ldapr", "stlr", "cas", "swp", "ldadd", "ldclr", "ldeor", "ldset", "ldsmax",
+                "ldsmin", "ldumax", "ldumin", "stadd", "stclr", "steor", "stset", "stsmax",
+                "stsmin", "stumax", "stumin",
+            ]
+            .iter()
+            .any(|prefix| mnemonic.starts_with(prefix)),
+            Self::Mips | Self::Mipsel | Self::Mips64 | Self::Mips64el => {
+                ["ll", "lld", "llwp", "lldp", "sc", "scd", "scwp", "scdp"].contains(&mnemonic)
+            }
+            Self::Ppc | Self::Ppc64 | Self::Ppc64le => [
+                "lbarx", "lharx", "lwarx", "ldarx", "lqarx", "stbcx.", "sthcx.", "stwcx.",
+                "stdcx.", "stqcx.",
+            ]
+            .contains(&mnemonic),
+            Self::LoongArch64 => {
+                ["ll.", "sc.", "llacq.", "screl.", "am"]
+                    .iter()
+                    .any(|prefix| mnemonic.starts_with(prefix))
+                    // `amadd.w` and the other atomic memory operations, but not `andi`
+                    && (!mnemonic.starts_with("am") || mnemonic.contains('.'))
+            }
+            Self::S390x => [
+                "cs", "csy", "csg", "cds", "cdsy", "cdsg", "csst", "laa", "laag", "laal", "laalg",
+                "lan", "lang", "lao", "laog", "lax", "laxg", "lpd", "lpdg",
+            ]
+            .contains(&mnemonic),
+            // Load-locked and store-conditional, such as `R0 = memw_locked(R1)`
+            Self::Hexagon => self
+                .split_packet(disas)
+                .iter()
+                .any(|insn| insn.contains("_locked(")),
+            // Load-reserved, store-conditional and the atomic memory operations
+            Self::Riscv32 | Self::Riscv64 => ["lr.", "sc.", "amo"]
+                .iter()
+                .any(|prefix| mnemonic.starts_with(prefix)),
+        }
+    }
+
+    /// Classify an instruction from its encoding as a load- or store-exclusive or `clrex`.
+    /// AArch64, A32 and 32-bit Thumb encodings are decoded, as are RISC-V's `lr.w`, `lr.d`,
+    /// `sc.w` and `sc.d`; other targets have none. A32 and Thumb encodings overlap, so on ARM
+    /// the disassembly must also name an exclusive.
+    pub fn exclusive(&self, data: &[u8], disas: &str) -> Option<Exclusive> {
+        let insn = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+
+        match self {
+            Self::Aarch64 => {
+                if insn & 0xffff_f0ff == 0xd503_305f {
+                    Some(Exclusive::Clear)
+                } else if insn & 0x3f80_0000 != 0x0800_0000 {
+                    // Not in the load/store exclusive class, or an ordered or CAS access
+                    None
+                } else if insn & (1 << 21) != 0 && insn & (1 << 31) == 0 {
+                    // A CASP, which shares the class with the 64-bit exclusive pairs
+                    None
+                } else if insn & (1 << 22) != 0 {
+                    Some(Exclusive::Load)
+                } else {
+                    Some(Exclusive::Store {
+                        status: ((insn >> 16) & 0x1f) as u8,
+                    })
+                }
+            }
+            Self::Arm => {
+                let (hw1, hw2) = (insn & 0xffff, insn >> 16);
+                let mnemonic = disas.split_whitespace().next().unwrap_or_default();
+
+                if !["ldrex", "ldaex", "strex", "stlex", "clrex"]
+                    .iter()
+                    .any(|prefix| mnemonic.starts_with(prefix))
+                {
+                    None
+                } else if insn == 0xf57f_f01f || insn == 0x8f2f_f3bf {
+                    Some(Exclusive::Clear)
+                } else if insn & 0x0f90_0eff == 0x0190_0e9f {
+                    Some(Exclusive::Load)
+                } else if insn & 0x0f90_0ef0 == 0x0180_0e90 {
+                    Some(Exclusive::Store {
+                        status: ((insn >> 12) & 0xf) as u8,
+                    })
+                } else if hw1 & 0xfff0 == 0xe850 || (hw1 & 0xfff0 == 0xe8d0 && hw2 & 0x0040 != 0) {
+                    Some(Exclusive::Load)
+                } else if hw1 & 0xfff0 == 0xe840 {
+                    Some(Exclusive::Store {
+                        status: ((hw2 >> 8) & 0xf) as u8,
+                    })
+                } else if hw1 & 0xfff0 == 0xe8c0 && hw2 & 0x0040 != 0 {
+                    Some(Exclusive::Store {
+                        status: (hw2 & 0xf) as u8,
+                    })
+                } else {
+                    None
+                }
+            }
+            // LR and SC are in the AMO major opcode, with a width of W or D and funct5 2 or 3.
+            // SC writes 0 to `rd` on success and a nonzero code on failure.
+            Self::Riscv32 | Self::Riscv64 => {
+                if insn & 0x7f != 0x2f || !matches!((insn >> 12) & 0x7, 0b010 | 0b011) {
+                    None
+                } else {
+                    match insn >> 27 {
+                        0b00010 => Some(Exclusive::Load),
+                        0b00011 => Some(Exclusive::Store {
+                            status: ((insn >> 7) & 0x1f) as u8,
+                        }),
+                        _ => None,
+                    }
+                }
+            }
+            Self::I386
+            | Self::X86_64
+            | Self::Mips
+            | Self::Mipsel
+            | Self::Mips64
+            | Self::Mips64el
+            | Self::Ppc
+            | Self::Ppc64
+            | Self::Ppc64le
+            | Self::LoongArch64
+            | Self::S390x
+            | Self::Hexagon => None,
+        }
+    }
+
+    /// Returns the values an instruction uses, from its disassembly: its numeric operands,
+    /// the targets of PC-relative operands, and any constant it completes in a register.
+    /// `built` holds the constants built up in registers by the preceding instructions of
+    /// the block, with `movw`/`movt`, `movk`, `adrp`/`add`, `pcaddu12i`/`pcalau12i` or
+    /// `lui`/`lis`/`lu12i.w` and `ori` or `addiu`/`addi`/`addi.d`, and is updated.
+    pub fn operand_values(
+        &self,
+        pc: u64,
+        len: usize,
+        disas: &str,
+        built: &mut HashMap<String, u64>,
+    ) -> Vec<u64> {
+        let disas = disas.trim();
+        let (mnemonic, operands) = disas.split_once(char::is_whitespace).unwrap_or((disas, ""));
+        let numbers = numbers(operands);
+        let mut values = numbers.clone();
+
+        match self {
+            Self::I386 | Self::X86_64 if operands.contains("rip") => {
+                if let Some(disp) = relative(operands, "rip") {
+                    values.push((pc + len as u64).wrapping_add_signed(disp));
+                }
+            }
+            Self::Arm if operands.contains("[pc") => {
+                // Thumb reads the PC as the word-aligned address 4 bytes ahead, ARM 8 bytes
+                let base = if len == 2 { (pc + 4) & !3 } else { pc + 8 };
+
+                if let Some(disp) = relative(operands, "pc") {
+                    values.push(base.wrapping_add_signed(disp));
+                }
+            }
+            _ => {}
+        }
+
+        let mut registers = operands.split(',').map(str::trim);
+        let destination = registers.next().unwrap_or_default().to_string();
+        let source = registers.next().unwrap_or_default();
+        let immediate = numbers.first().copied();
+
+        let value = match (mnemonic, immediate) {
+            ("movw" | "mov" | "adrp" | "adr", Some(imm)) => Some(imm),
+            ("movt", Some(imm)) => built
+                .get(&destination)
+                .map(|low| (low & 0xffff) | (imm << 16)),
+            ("movk", Some(imm)) => {
+                let shift = numbers.get(1).copied().unwrap_or_default();
+
+                built
+                    .get(&destination)
+                    .map(|v| (v & !(0xffff << shift)) | (imm << shift))
+            }
+            ("lui" | "lis", Some(imm)) => Some(imm << 16),
+            ("lu12i.w", Some(imm)) => Some(imm << 12),
+            ("pcaddu12i", Some(imm)) => Some(pc.wrapping_add(imm << 12)),
+            ("pcalau12i", Some(imm)) => Some((pc & !0xfff).wrapping_add(imm << 12)),
+            ("ori", Some(imm)) => built.get(source).map(|v| v | imm),
+            ("add" | "addiu" | "daddiu" | "addi" | "addi.d" | "addi.w", Some(imm)) => {
+                built.get(source).map(|v| v.wrapping_add(imm))
+            }
+            _ => None,
+        };
+
+        match value {
+            Some(value) => {
+                built.insert(destination, value);
+                values.push(value);
+            }
+            None => {
+                built.remove(&destination);
+            }
+        }
+
+        values
+    }
+
+    /// Classify an instruction from its disassembly as one from a cryptographic extension
+    pub fn crypto(&self, disas: &str) -> Option<Crypto> {
+        let disas = disas.trim();
+        let mnemonic = disas.split_whitespace().next().unwrap_or_default();
+
+        match self {
+            Self::I386 | Self::X86_64 => {
+                // VEX and EVEX encodings prefix the mnemonic with `v`
+                let mnemonic = mnemonic.strip_prefix('v').unwrap_or(mnemonic);
+
+                if mnemonic.starts_with("aes") {
+                    Some(Crypto::Aes)
+                } else if ["sha1", "sha256", "sha512"]
+                    .iter()
+                    .any(|prefix| mnemonic.starts_with(prefix))
+                {
+                    Some(Crypto::Sha)
+                } else if mnemonic.starts_with("pclmul") {
+                    Some(Crypto::Carryless)
+                } else if mnemonic.starts_with("sm3") || mnemonic.starts_with("sm4") {
+                    Some(Crypto::Sm)
+                } else {
+                    None
+                }
+            }
+            Self::Arm | Self::Aarch64 => {
+                let (base, suffix) = mnemonic.split_once('.').unwrap_or((mnemonic, ""));
+
+                match base {
+                    "aese" | "aesd" | "aesmc" | "aesimc" => Some(Crypto::Aes),
+                    "eor3" | "bcax" | "rax1" | "xar" => Some(Crypto::Sha),
+                    "pmull" | "pmull2" => Some(Crypto::Carryless),
+                    "vmull" if suffix.starts_with('p') => Some(Crypto::Carryless),
+                    _ if ["sha1", "sha256", "sha512", "sha3"]
+                        .iter()
+                        .any(|prefix| base.starts_with(prefix)) =>
+                    {
+                        Some(Crypto::Sha)
+                    }
+                    _ if base.starts_with("sm3") || base.starts_with("sm4") => Some(Crypto::Sm),
+                    _ => None,
+                }
+            }
+            Self::Ppc | Self::Ppc64 | Self::Ppc64le => match mnemonic {
+                "vcipher" | "vcipherlast" | "vncipher" | "vncipherlast" | "vsbox" => {
+                    Some(Crypto::Aes)
+                }
+                "vshasigmaw" | "vshasigmad" => Some(Crypto::Sha),
+                "vpmsumb" | "vpmsumh" | "vpmsumw" | "vpmsumd" => Some(Crypto::Carryless),
+                _ => None,
+            },
+            Self::S390x => match mnemonic {
+                // The CPACF cipher functions, which also cover DES, chosen by function code
+                "km" | "kmc" | "kma" | "kmctr" | "kmf" | "kmo" => Some(Crypto::Aes),
+                "kimd" | "klmd" => Some(Crypto::Sha),
+                _ => None,
+            },
+            Self::Riscv32 | Self::Riscv64 => {
+                // Vector crypto instructions prefix the scalar names with `v`
+                let base = mnemonic.strip_prefix('v').unwrap_or(mnemonic);
+
+                if base.starts_with("aes") {
+                    Some(Crypto::Aes)
+                } else if base.starts_with("sha") {
+                    Some(Crypto::Sha)
+                } else if base.starts_with("clmul") || ["vghsh.vv", "vgmul.vv"].contains(&mnemonic)
+                {
+                    Some(Crypto::Carryless)
+                } else if base.starts_with("sm3") || base.starts_with("sm4") {
+                    Some(Crypto::Sm)
+                } else {
+                    None
+                }
+            }
+            Self::Mips
+            | Self::Mipsel
+            | Self::Mips64
+            | Self::Mips64el
+            | Self::LoongArch64
+            | Self::Hexagon => None,
+        }
+    }
+
+    /// Classify an instruction from its disassembly as one from an extension of the base
+    /// instruction set, by its mnemonic and the registers it names
+    pub fn extension(&self, disas: &str) -> Option<Extension> {
+        if let Some(crypto) = self.crypto(disas) {
+            return Some(Extension::Crypto(crypto));
+        }
+
+        let disas = disas.trim().to_lowercase();
+        let (mnemonic, operands) = disas
+            .split_once(char::is_whitespace)
+            .unwrap_or((disas.as_str(), ""));
+        let words = || operands.split(|c: char| !c.is_ascii_alphanumeric());
+        // The numbers of the registers named with `prefix`, such as 3 for `%xmm3` or `v3.4s`
+        let registers = |prefix: &str| {
+            words()
+                .filter_map(|word| word.strip_prefix(prefix)?.parse::<u32>().ok())
+                .collect::<Vec<_>>()
+        };
+        let named = |prefix: &str| !registers(prefix).is_empty();
+
+        match self {
+            Self::I386 | Self::X86_64 => {
+                let vector = [registers("xmm"), registers("ymm")].concat();
+
+                if mnemonic.starts_with('k')
+                    || named("zmm")
+                    || named("k")
+                    || operands.contains('{')
+                    || vector.iter().any(|r| *r > 15)
+                {
+                    Some(Extension::Avx512)
+                } else if ["vfmadd", "vfmsub", "vfnmadd", "vfnmsub"]
+                    .iter()
+                    .any(|prefix| mnemonic.starts_with(prefix))
+                {
+                    Some(Extension::Fma)
+                } else if [
+                    "vpbroadcast",
+                    "vbroadcasti128",
+                    "vinserti128",
+                    "vextracti128",
+                    "vperm2i128",
+                    "vpblendd",
+                    "vpmaskmov",
+                    "vpsllv",
+                    "vpsrlv",
+                    "vpsrav",
+                    "vgather",
+                    "vpgather",
+                ]
+                .iter()
+                .any(|prefix| mnemonic.starts_with(prefix))
+                    || ["vpermd", "vpermq", "vpermps", "vpermpd"].contains(&mnemonic)
+                    || (mnemonic.starts_with("vp")
+                        && !mnemonic.starts_with("vpermil")
+                        && named("ymm"))
+                {
+                    Some(Extension::Avx2)
+                } else if (mnemonic.starts_with('v') && !vector.is_empty())
+                    || mnemonic.starts_with("vzero")
+                {
+                    Some(Extension::Avx)
+                } else if !vector.is_empty() {
+                    Some(Extension::Sse)
+                } else if named("mm") || mnemonic == "emms" {
+                    Some(Extension::Mmx)
+                } else if [
+                    "andn", "bextr", "blsi", "blsmsk", "blsr", "bzhi", "mulx", "pdep", "pext",
+                    "rorx", "sarx", "shlx", "shrx",
+                ]
+                // AT&T syntax suffixes the operand size
+                .contains(&mnemonic.strip_suffix(['l', 'q']).unwrap_or(mnemonic))
+                    || ["andn", "blsi", "blsr", "bzhi", "pdep", "pext"].contains(&mnemonic)
+                {
+                    Some(Extension::Bmi)
+                } else if mnemonic.starts_with('f') {
+                    Some(Extension::X87)
+                } else {
+                    None
+                }
+            }
+            Self::Aarch64 => {
+                // The array is named whole as `za` and by tile as `za0h.s`
+                if mnemonic.starts_with("smstart")
+                    || mnemonic.starts_with("smstop")
+                    || named("zt")
+                    || words().any(|word| word.starts_with("za"))
+                {
+                    Some(Extension::Sme)
+                } else if named("z") || named("p") {
+                    Some(Extension::Sve)
+                } else if named("v") || named("q") {
+                    Some(Extension::Neon)
+                } else {
+                    None
+                }
+            }
+            Self::Arm => {
+                let (base, suffix) = mnemonic.split_once('.').unwrap_or((mnemonic, ""));
+                // Integer element types, which VFP only converts to and from single registers
+                let integer = suffix.starts_with(['i', 's', 'u', 'p']);
+
+                if !base.starts_with('v') {
+                    None
+                } else if named("q")
+                    || [
+                        "vld1", "vld2", "vld3", "vld4", "vst1", "vst2", "vst3", "vst4",
+                    ]
+                    .contains(&base)
+                    || (integer && !named("s"))
+                {
+                    Some(Extension::Neon)
+                } else {
+                    Some(Extension::Vfp)
+                }
+            }
+            Self::Ppc | Self::Ppc64 | Self::Ppc64le => {
+                if [
+                    "xs", "xv", "xx", "lxs", "lxv", "stxs", "stxv", "mfvsr", "mtvsr",
+                ]
+                .iter()
+                .any(|prefix| mnemonic.starts_with(prefix))
+                {
+                    Some(Extension::Vsx)
+                } else if mnemonic.starts_with('v')
+                    || [
+                        "lvx", "lvxl", "stvx", "stvxl", "lvsl", "lvsr", "mfvscr", "mtvscr",
+                    ]
+                    .contains(&mnemonic)
+                    || ["lve", "stve", "dss", "dst"]
+                        .iter()
+                        .any(|prefix| mnemonic.starts_with(prefix))
+                {
+                    Some(Extension::Altivec)
+                } else {
+                    None
+                }
+            }
+            Self::Mips | Self::Mipsel | Self::Mips64 | Self::Mips64el => {
+                named("w").then_some(Extension::Msa)
+            }
+            Self::LoongArch64 => {
+                if mnemonic.starts_with("xv") {
+                    Some(Extension::Lasx)
+                } else if mnemonic.starts_with('v') {
+                    Some(Extension::Lsx)
+                } else {
+                    None
+                }
+            }
+            Self::S390x => named("v").then_some(Extension::ZVector),
+            // Scalar instructions are named like vector ones, as in `r0 = vaddh(r1,r2)`, so
+            // only HVX's vector and predicate registers tell its instructions apart, and they
+            // may be the first word, as in `v0 = vmem(r0+#0)`
+            Self::Hexagon => disas
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .filter_map(|word| word.strip_prefix(['v', 'q']))
+                .any(|number| number.parse::<u32>().is_ok())
+                .then_some(Extension::Hvx),
+            // Only vector instructions are named with a `v`, and the vector crypto ones are
+            // classified above
+            Self::Riscv32 | Self::Riscv64 => {
+                if mnemonic.starts_with('v') {
+                    Some(Extension::Rvv)
+                } else if RISCV_BITMANIP.contains(&mnemonic) {
+                    Some(Extension::Zb)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Classify an instruction from its disassembly as a call, a return, or neither
+    pub fn branch(&self, disas: &str) -> Option<Branch> {
+        let (mnemonic, operands) = disas
+            .trim()
+            .split_once(char::is_whitespace)
+            .unwrap_or((disas.trim(), ""));
+
+        match self {
+            Self::I386 | Self::X86_64 => match disas
+                .split_whitespace()
+                // Prefixes are disassembled as separate words, such as `repz ret`
+                .find(|word| !X86_BRANCH_PREFIXES.contains(word))
+                .unwrap_or_default()
+            {
+                "call" | "calll" | "callq" | "lcall" | "lcalll" | "lcallq" => Some(Branch::Call),
+                "ret" | "retl" | "retq" | "retn" | "retf" | "retfq" | "lret" | "lretl"
+                | "lretq" => Some(Branch::Return),
+                _ => None,
+            },
+            Self::Arm => {
+                // Conditional forms append a condition, such as `blne`, and Thumb-2 forms may
+                // append a width, such as `pop.w`
+                let mnemonic = mnemonic.split('.').next().unwrap_or_default();
+                let base = ["bl", "blx", "bx", "pop", "ldm", "ldmia", "ldmfd"]
+                    .into_iter()
+                    .find(|base| {
+                        mnemonic
+                            .strip_prefix(base)
+                            .is_some_and(|cond| cond.is_empty() || ARM_CONDITIONS.contains(&cond))
+                    });
+
+                match base? {
+                    "bl" | "blx" => Some(Branch::Call),
+                    "bx" if operands.trim() == "lr" => Some(Branch::Return),
+                    "pop" | "ldm" | "ldmia" | "ldmfd" if operands.contains("pc") => {
+                        Some(Branch::Return)
+                    }
+                    _ => None,
+                }
+            }
+            Self::Aarch64 => match mnemonic {
+                // Including the pointer authenticating forms
+                "bl" | "blr" | "blraa" | "blrab" | "blraaz" | "blrabz" => Some(Branch::Call),
+                "ret" | "retaa" | "retab" => Some(Branch::Return),
+                _ => None,
+            },
+            Self::Mips | Self::Mipsel | Self::Mips64 | Self::Mips64el => match mnemonic {
+                "jal" | "jalr" | "jalx" | "jalrc" | "jalr.hb" | "bal" | "balc" | "bgezal"
+                | "bltzal" => Some(Branch::Call),
+                "jr" | "jrc" | "jr.hb" if MIPS_RA.contains(&operands.trim()) => {
+                    Some(Branch::Return)
+                }
+                _ => None,
+            },
+            Self::LoongArch64 => {
+                let mut registers = operands.split(',').map(loongarch_register);
+
+                match (
+                    mnemonic,
+                    registers.next().flatten(),
+                    registers.next().flatten(),
+                ) {
+                    ("bl", _, _) | ("jirl", Some(1), _) => Some(Branch::Call),
+                    ("ret", _, _) | ("jirl", Some(0), Some(1)) => Some(Branch::Return),
+                    _ => None,
+                }
+            }
+            Self::Hexagon => self.split_packet(disas).into_iter().find_map(|insn| {
+                // Predicated forms start with their predicate, such as `if (P0) jumpr R31`
+                let insn = match insn.strip_prefix("if") {
+                    Some(rest) => rest.split_once(')').map(|(_, insn)| insn.trim())?,
+                    None => insn,
+                };
+                let (mnemonic, operands) =
+                    insn.split_once(char::is_whitespace).unwrap_or((insn, ""));
+                // Branch hints may be appended, such as `jumpr:nt`
+                let mnemonic = mnemonic.split(':').next().unwrap_or_default();
+
+                match mnemonic {
+                    "call" | "callr" => Some(Branch::Call),
+                    "jumpr" if ["r31", "lr"].contains(&operands.trim().to_lowercase().as_str()) => {
+                        Some(Branch::Return)
+                    }
+                    _ if insn.contains("dealloc_return") => Some(Branch::Return),
+                    _ => None,
+                }
+            }),
+            Self::Riscv32 | Self::Riscv64 => {
+                // Compressed forms are disassembled with a `c.` prefix by some disassemblers
+                let mnemonic = mnemonic.strip_prefix("c.").unwrap_or(mnemonic);
+                let mut registers = operands.split(',').map(str::trim);
+
+                match (mnemonic, registers.next(), registers.next()) {
+                    ("ret", _, _) => Some(Branch::Return),
+                    ("jr", Some(rs1), _) if RISCV_RA.contains(&rs1) => Some(Branch::Return),
+                    ("jalr", Some(rd), Some(rs1))
+                        if RISCV_ZERO.contains(&rd) && RISCV_RA.contains(&rs1) =>
+                    {
+                        Some(Branch::Return)
+                    }
+                    // `jal` and `jalr` with one operand link in `ra`; with `zero` they are jumps
+                    ("jal" | "jalr" | "call", rd, _)
+                        if !rd.is_some_and(|rd| RISCV_ZERO.contains(&rd)) =>
+                    {
+                        Some(Branch::Call)
+                    }
+                    _ => None,
+                }
+            }
+            Self::S390x => match mnemonic {
+                "brasl" | "bras" | "basr" | "bas" | "bal" | "balr" => Some(Branch::Call),
+                "br" if operands.trim() == "%r14" => Some(Branch::Return),
+                _ => None,
+            },
+            Self::Ppc | Self::Ppc64 | Self::Ppc64le => {
+                // Branch prediction hints may be appended, such as `beqlr+`
+                let condition = mnemonic.trim_end_matches(['+', '-']).strip_prefix('b')?;
+
+                if condition.ends_with("lrl")
+                    || condition.ends_with("ctrl")
+                    || ["l", "la", "cl", "cla"].contains(&condition)
+                {
+                    Some(Branch::Call)
+                } else if condition.ends_with("lr") {
+                    Some(Branch::Return)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Prefixes which may come before a call or return without changing it
+const X86_BRANCH_PREFIXES: &[&str] = &[
+    "bnd", "notrack", "rep", "repe", "repz", "repne", "repnz", "data16", "addr32",
+];
+
+/// The instructions of RISC-V's Zba, Zbb and Zbs extensions
+const RISCV_BITMANIP: &[&str] = &[
+    "sh1add",
+    "sh2add",
+    "sh3add",
+    "add.uw",
+    "sh1add.uw",
+    "sh2add.uw",
+    "sh3add.uw",
+    "slli.uw",
+    "zext.w",
+    "andn",
+    "orn",
+    "xnor",
+    "clz",
+    "clzw",
+    "ctz",
+    "ctzw",
+    "cpop",
+    "cpopw",
+    "max",
+    "maxu",
+    "min",
+    "minu",
+    "sext.b",
+    "sext.h",
+    "zext.h",
+    "rol",
+    "rolw",
+    "ror",
+    "rori",
+    "roriw",
+    "rorw",
+    "orc.b",
+    "rev8",
+    "bclr",
+    "bclri",
+    "bext",
+    "bexti",
+    "binv",
+    "binvi",
+    "bset",
+    "bseti",
+];
+
+/// The names MIPS disassembly may give the return address register
+const MIPS_RA: &[&str] = &["ra", "$ra", "$31"];
+
+/// The names RISC-V disassembly may give the return address and zero registers
+const RISCV_RA: &[&str] = &["ra", "x1"];
+const RISCV_ZERO: &[&str] = &["zero", "x0"];
+
+/// The RISC-V integer registers by number, under the ABI names QEMU gives them
+pub const RISCV_REGISTERS: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// The number of a LoongArch register operand, which QEMU names `r1` or `$r1` and other
+/// disassemblers may name by its ABI name
+fn loongarch_register(operand: &str) -> Option<u8> {
+    let operand = operand.trim().trim_start_matches('$');
+
+    match operand {
+        "zero" => Some(0),
+        "ra" => Some(1),
+        _ => operand.strip_prefix('r')?.parse().ok(),
+    }
+}
+
+/// The condition codes ARM instructions may be suffixed with
+const ARM_CONDITIONS: &[&str] = &[
+    "eq", "ne", "cs", "hs", "cc", "lo", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le",
+    "al",
+];
+
+/// Returns the numbers among an instruction's operands, in order
+fn numbers(operands: &str) -> Vec<u64> {
+    operands
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter_map(|token| match token.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => token.parse().ok(),
+        })
+        .collect()
+}
+
+/// Returns the displacement added to `base` in a memory operand such as `[rip + 0x10]` or
+/// `[pc, #-0x10]`
+fn relative(operands: &str, base: &str) -> Option<i64> {
+    let rest = &operands[operands.find(base)? + base.len()..];
+    let end = rest.find(']').unwrap_or(rest.len());
+    let rest = &rest[..end];
+    let negative = rest.contains('-');
+    let disp = *numbers(rest).first()? as i64;
+
+    Some(if negative { -disp } else { disp })
+}
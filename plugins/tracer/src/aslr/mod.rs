@@ -0,0 +1,181 @@
+//! Compensation for address-space layout randomization, so that traces and profiles of runs
+//! which loaded the program at different addresses can be compared and merged
+//!
+//! A module is loaded at a bias, the difference between the addresses it runs at and those it
+//! was linked at: zero for a program linked at a fixed address, and a new random one on each
+//! run for a position-independent program under ASLR. By default, the code addresses every
+//! sink records are normalized to link-time addresses by subtracting the bias of the module
+//! they fall in, so an instruction has the same address in every run. In user mode the
+//! program's bias is found from where QEMU loaded it, and shared libraries are tracked when
+//! `symbolize_libraries` follows their mappings. Addresses outside any module, such as
+//! JIT-compiled code, and data addresses are recorded as they are, as is everything with
+//! `absolute_pcs=true`.
+//!
+//! Libraries are linked at low addresses, so normalized addresses in different libraries can
+//! coincide. The run manifest lists each module with its range and bias, from which the
+//! addresses a run executed at can be recovered.
+
+use crate::{Event, InstructionEvent};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    RwLock,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A module's code as loaded in a run
+pub struct LoadedModule {
+    pub name: String,
+    pub start: u64,
+    /// The address after the last address of the module's code
+    pub end: u64,
+    /// The difference between the addresses the module runs at and those it was linked at
+    pub bias: u64,
+}
+
+impl LoadedModule {
+    pub fn contains(&self, vaddr: u64) -> bool {
+        (self.start..self.end).contains(&vaddr)
+    }
+}
+
+#[derive(Debug, Default)]
+/// Normalizes the code addresses of events by the bias of the module they fall in
+pub struct Relocator {
+    modules: RwLock<Vec<LoadedModule>>,
+    /// Whether any module has a bias, without which events are left as they are
+    biased: AtomicBool,
+}
+
+impl Relocator {
+    /// Add a module, replacing any it overlaps
+    pub fn add(&self, module: LoadedModule) -> Result<()> {
+        let mut modules = self
+            .modules
+            .write()
+            .map_err(|e| anyhow!("Failed to lock modules: {e}"))?;
+
+        if module.bias != 0 {
+            self.biased.store(true, Ordering::Relaxed);
+        }
+
+        modules.retain(|m| m.end <= module.start || m.start >= module.end);
+        modules.push(module);
+
+        Ok(())
+    }
+
+    /// The modules loaded so far
+    pub fn modules(&self) -> Result<Vec<LoadedModule>> {
+        Ok(self
+            .modules
+            .read()
+            .map_err(|e| anyhow!("Failed to lock modules: {e}"))?
+            .clone())
+    }
+
+    /// Returns `event` with its code addresses normalized, or `None` if it is unchanged
+    pub fn relocate(&self, event: &Event) -> Result<Option<Event>> {
+        if !self.biased.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+
+        let modules = self
+            .modules
+            .read()
+            .map_err(|e| anyhow!("Failed to lock modules: {e}"))?;
+        let normalize = |vaddr: u64| match modules.iter().find(|m| m.contains(vaddr)) {
+            Some(module) => vaddr.wrapping_sub(module.bias),
+            None => vaddr,
+        };
+        let mut event = event.clone();
+
+        relocate(&mut event, &normalize)?;
+
+        Ok(Some(event))
+    }
+}
+
+#[cfg(feature = "plugin-api-v4")]
+fn relocate_all(vaddrs: &mut [u64], normalize: &dyn Fn(u64) -> u64) {
+    vaddrs
+        .iter_mut()
+        .for_each(|vaddr| *vaddr = normalize(*vaddr));
+}
+
+/// Normalize each field of `event` holding a code address
+fn relocate(event: &mut Event, normalize: &dyn Fn(u64) -> u64) -> Result<()> {
+    match event {
+        Event::Instruction {
+            event: InstructionEvent { vaddr, .. },
+            ..
+        } => *vaddr = normalize(*vaddr),
+        Event::MemoryRange(range) => range.pc = normalize(range.pc),
+        Event::Pcs(batch) => *batch = batch.map(normalize)?,
+        Event::Bookmark(bookmark) => bookmark.pc = bookmark.pc.map(normalize),
+        Event::Coverage(coverage) => coverage.vaddr = normalize(coverage.vaddr),
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Fault(fault) => {
+            if let crate::faults::FaultSite::MemoryRead { pc, .. } = &mut fault.site {
+                *pc = normalize(*pc);
+            }
+        }
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Dump(dump) => {
+            if let crate::dump::DumpTrigger::Pc(pc) = &mut dump.trigger {
+                *pc = normalize(*pc);
+            }
+        }
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Race(race) => {
+            for access in [&mut race.first, &mut race.second] {
+                access.pc = normalize(access.pc);
+                relocate_all(&mut access.call_stack, normalize);
+            }
+        }
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Periph(periph) => periph.pc = normalize(periph.pc),
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Hang(hang) => hang.pc = normalize(hang.pc),
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Api(api) => api.address = normalize(api.address),
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Rop(rop) => {
+            rop.pc = normalize(rop.pc);
+            relocate_all(&mut rop.call_stack, normalize);
+            rop.history
+                .iter_mut()
+                .for_each(|transfer| transfer.vaddr = normalize(transfer.vaddr));
+        }
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Integrity(integrity) => {
+            integrity.pc = normalize(integrity.pc);
+            integrity.expected = normalize(integrity.expected);
+            integrity.actual = normalize(integrity.actual);
+            relocate_all(&mut integrity.call_stack, normalize);
+        }
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Signature(signature) => signature.pc = normalize(signature.pc),
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Probe(probe) => probe.pc = normalize(probe.pc),
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Crypto(crypto) => {
+            crypto.pc = normalize(crypto.pc);
+            crypto.function = crypto.function.map(normalize);
+        }
+        #[cfg(feature = "plugin-api-v4")]
+        Event::String(string) => string.pc = normalize(string.pc),
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Mode(mode) => mode.pc = normalize(mode.pc),
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Divergence(divergence) => divergence.pc = normalize(divergence.pc),
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Wx(wx) => wx.pc = wx.pc.map(normalize),
+        #[cfg(feature = "plugin-api-v4")]
+        Event::Crash(crash) => crash.pc = normalize(crash.pc),
+        _ => {}
+    }
+
+    Ok(())
+}
@@ -0,0 +1,220 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use serde_cbor::{Deserializer, Value};
+use std::{
+    fs::create_dir_all,
+    io::BufReader,
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    main,
+    net::TcpListener,
+    spawn,
+    task::spawn_blocking,
+};
+use tracer::{
+    aggregate::{Aggregator, Record, Report},
+    tracefile::TraceFile,
+};
+
+#[derive(Parser, Debug, Clone)]
+/// Collect the traces of many `tracer` plugin instances run with `aggregator=host:port`,
+/// merging their coverage and deduplicating the crashes they report
+struct Args {
+    #[clap(short, long, default_value = "0.0.0.0:7878")]
+    /// The address to accept plugin instances on
+    pub listen: SocketAddr,
+    #[clap(short, long)]
+    /// An address to serve the status API on over HTTP: `GET /status` returns the
+    /// instances, coverage counts and crashes as JSON, and `GET /coverage` the merged
+    /// coverage as CBOR
+    pub status: Option<SocketAddr>,
+    #[clap(short, long)]
+    /// A file to write the merged coverage to each time an instance reports its coverage
+    pub coverage: Option<PathBuf>,
+    #[clap(long)]
+    /// A directory to write each new crash to, as JSON with the event which reported it
+    pub crashes: Option<PathBuf>,
+    #[clap(long)]
+    /// A directory to write each instance's events to, as a trace file per instance
+    pub traces: Option<PathBuf>,
+}
+
+/// Read the records instance `id` sends until it disconnects
+fn read_records(
+    args: &Args,
+    aggregator: &Mutex<Aggregator>,
+    id: u64,
+    stream: TcpStream,
+) -> Result<()> {
+    let mut trace = args
+        .traces
+        .as_ref()
+        .map(|traces| TraceFile::create(traces.join(format!("instance-{id}.trace"))))
+        .transpose()?;
+
+    for value in Deserializer::from_reader(BufReader::new(stream)).into_iter::<Value>() {
+        let record = Record::decode(value?)?;
+
+        if let (Some(trace), Record::Event(event)) = (trace.as_mut(), &record) {
+            trace.write_record(event)?;
+        }
+
+        let mut aggregator = aggregator
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock aggregator: {e}"))?;
+
+        if let Some(summary) = aggregator.on_record(id, &record)? {
+            eprintln!(
+                "Instance {id} found new {} crash {} at {:#x}",
+                summary.crash.kind, summary.signature, summary.crash.pc
+            );
+
+            if let (Some(crashes), Record::Event(event)) = (args.crashes.as_ref(), &record) {
+                std::fs::write(
+                    crashes.join(format!("{}.json", summary.signature)),
+                    serde_json::to_vec_pretty(&serde_json::json!({
+                        "summary": summary,
+                        "event": event,
+                    }))?,
+                )?;
+            }
+        }
+
+        if let (Some(coverage), Record::Report(Report::Coverage(_))) =
+            (args.coverage.as_ref(), &record)
+        {
+            aggregator.coverage().write(coverage)?;
+        }
+    }
+
+    if let Some(mut trace) = trace {
+        trace.finish()?;
+    }
+
+    Ok(())
+}
+
+/// Track an instance for as long as it stays connected
+fn handle(
+    args: &Args,
+    aggregator: &Mutex<Aggregator>,
+    stream: TcpStream,
+    peer: SocketAddr,
+) -> Result<()> {
+    let id = aggregator
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock aggregator: {e}"))?
+        .connect(peer.to_string());
+
+    eprintln!("Instance {id} connected from {peer}");
+
+    let result = read_records(args, aggregator, id, stream);
+
+    aggregator
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock aggregator: {e}"))?
+        .disconnect(id);
+
+    eprintln!("Instance {id} disconnected");
+
+    result
+}
+
+/// Serve the status API on `address`
+async fn serve_status(address: SocketAddr, aggregator: Arc<Mutex<Aggregator>>) -> Result<()> {
+    let listener = TcpListener::bind(address).await?;
+
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let aggregator = aggregator.clone();
+
+        spawn(async move {
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+                match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buf[..n]),
+                }
+            }
+
+            let request = String::from_utf8_lossy(&request);
+            let mut parts = request.split_whitespace();
+            let body = match (parts.next(), parts.next()) {
+                (Some("GET"), Some("/status")) => aggregator
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock aggregator: {e}"))
+                    .and_then(|a| Ok(serde_json::to_vec_pretty(&a.status())?))
+                    .map(|body| ("200 OK", "application/json", body)),
+                (Some("GET"), Some("/coverage")) => aggregator
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock aggregator: {e}"))
+                    .and_then(|a| Ok(serde_cbor::to_vec(a.coverage())?))
+                    .map(|body| ("200 OK", "application/cbor", body)),
+                _ => Ok(("404 Not Found", "text/plain", b"Not found\n".to_vec())),
+            };
+            let (status, content_type, body) = body.unwrap_or_else(|e| {
+                (
+                    "500 Internal Server Error",
+                    "text/plain",
+                    format!("{e}\n").into_bytes(),
+                )
+            });
+            let header = format!(
+                "HTTP/1.1 {status}\r\ncontent-type: {content_type}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                body.len()
+            );
+
+            if stream.write_all(header.as_bytes()).await.is_ok() {
+                stream.write_all(&body).await.ok();
+            }
+        });
+    }
+}
+
+#[main]
+async fn main() -> Result<()> {
+    let args = Arc::new(Args::parse());
+    let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+
+    for dir in [args.crashes.as_ref(), args.traces.as_ref()]
+        .into_iter()
+        .flatten()
+    {
+        create_dir_all(dir)?;
+    }
+
+    if let Some(status) = args.status {
+        let aggregator = aggregator.clone();
+
+        spawn(async move {
+            if let Err(e) = serve_status(status, aggregator).await {
+                eprintln!("Status API failed: {e}");
+            }
+        });
+    }
+
+    let listener = TcpListener::bind(args.listen).await?;
+
+    eprintln!("Listening for instances on {}", args.listen);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let stream = stream.into_std()?;
+        let args = args.clone();
+        let aggregator = aggregator.clone();
+
+        stream.set_nonblocking(false)?;
+
+        spawn_blocking(move || {
+            if let Err(e) = handle(&args, &aggregator, stream, peer) {
+                eprintln!("Instance from {peer} failed: {e}");
+            }
+        });
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::Result;
+use clap::Parser;
+use std::path::PathBuf;
+use tracer::{
+    decisions::{DecisionLog, DecisionQuery},
+    memmap::parse_addr,
+};
+
+#[derive(Parser, Debug, Clone)]
+/// Search the decision log a `tracer` plugin wrote with `decision_log`, to find which
+/// callbacks were registered on a block and why
+struct Args {
+    /// The decision log to read
+    pub log: PathBuf,
+    #[clap(long, value_parser = parse_addr)]
+    /// Only show blocks containing this address, with their decisions about the block or
+    /// about the instruction at it
+    pub pc: Option<u64>,
+    #[clap(long)]
+    /// Only show the decisions of this feature, named after the argument enabling it
+    pub feature: Option<String>,
+    #[clap(long)]
+    /// Only show decisions which registered nothing
+    pub skipped: bool,
+    #[clap(long)]
+    /// Show how many callbacks each feature registered and how often it passed over a block
+    /// or instruction, instead of the blocks
+    pub summary: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let log = DecisionLog::read(&args.log)?;
+
+    if args.summary {
+        for (feature, summary) in log.summary() {
+            let registered = summary
+                .registered
+                .iter()
+                .map(|(callback, count)| format!("{count} {callback}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            println!(
+                "{feature}: registered {}, skipped {}",
+                if registered.is_empty() {
+                    "nothing"
+                } else {
+                    &registered
+                },
+                summary.skipped
+            );
+        }
+
+        return Ok(());
+    }
+
+    let query = DecisionQuery::builder()
+        .pc(args.pc)
+        .feature(args.feature)
+        .skipped(args.skipped)
+        .build();
+    let blocks = log.query(&query);
+
+    if blocks.is_empty() {
+        eprintln!("No translated block matches");
+    }
+
+    for block in blocks {
+        println!("{block}");
+    }
+
+    Ok(())
+}
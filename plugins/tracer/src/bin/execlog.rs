@@ -0,0 +1,93 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+use tracer::{
+    execlog::Execlog,
+    tracefile::{ShardedTraceFile, TraceReader},
+    Event,
+};
+
+#[derive(Parser, Debug, Clone)]
+/// Convert between `tracer` traces and the text logs of QEMU's contrib execlog plugin
+struct Args {
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Convert an execlog log to a trace file
+    Import {
+        /// The execlog log to read
+        execlog: PathBuf,
+        /// The trace file to write, or the directory of a sharded trace with `--shards`
+        trace: PathBuf,
+        #[clap(long)]
+        /// Write a sharded trace, keeping each instruction's vCPU
+        shards: bool,
+    },
+    /// Convert a trace file to an execlog log
+    Export {
+        /// The trace file to read, or the directory of a sharded trace with `--shards`
+        trace: PathBuf,
+        /// The execlog log to write
+        execlog: PathBuf,
+        #[clap(long)]
+        /// Read a sharded trace, keeping each event's vCPU. Events of a trace file are
+        /// logged as vCPU 0
+        shards: bool,
+    },
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    match args.command {
+        Command::Import {
+            execlog,
+            trace,
+            shards,
+        } => {
+            let log = Execlog::read(execlog)?;
+
+            if shards {
+                log.write_shards(trace)
+            } else {
+                log.write_trace(trace)
+            }
+        }
+        Command::Export {
+            trace,
+            execlog,
+            shards,
+        } => {
+            let log = if shards {
+                let records = ShardedTraceFile::read_merged(&trace)?;
+
+                Execlog::from_events(records.iter().map(|r| (r.vcpu_index, &r.event)))?
+            } else {
+                let records = TraceReader::open(&trace)?.records::<Event>()?;
+
+                if records.skipped > 0 {
+                    eprintln!(
+                        "Skipped {} events in {} not understood by this build",
+                        records.skipped,
+                        trace.display()
+                    );
+                }
+
+                Execlog::from_events(records.records.iter().map(|event| (0, event)))?
+            };
+            let mut writer = BufWriter::new(File::create(execlog)?);
+
+            log.write(&mut writer)?;
+            writer.flush()?;
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, List, ListItem, Paragraph, Row, Sparkline, Table},
+    DefaultTerminal, Frame,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tracer::{coverage::CoverageEvent, stats::StatsSnapshot};
+
+/// The number of refreshes the event rate history covers
+const HISTORY: usize = 120;
+
+#[derive(Parser, Debug, Clone)]
+/// Show a live dashboard of a `tracer` plugin instance's statistics, read from the control
+/// socket it was given with `control_path` (`--control-socket` to `tracer`): instruction rates
+/// and utilization per vCPU, event rates, the sink backlog and new coverage beyond a baseline
+struct Args {
+    /// The plugin's control socket
+    pub control: PathBuf,
+    #[clap(short, long, default_value_t = 1000)]
+    /// Milliseconds between refreshes
+    pub interval: u64,
+}
+
+/// A connection to a plugin's control socket
+struct Control {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Control {
+    fn connect(path: &Path) -> Result<Self> {
+        let writer = UnixStream::connect(path)
+            .map_err(|e| anyhow!("Failed to connect to {}: {e}", path.display()))?;
+
+        Ok(Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        })
+    }
+
+    /// Send a command and read its reply
+    fn request<T>(&mut self, command: &str) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        writeln!(self.writer, "{command}")?;
+
+        let mut line = String::new();
+
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(anyhow!("The plugin closed the control socket"));
+        }
+
+        let reply = serde_json::from_str::<Value>(&line)?;
+
+        if let Some(error) = reply.get("error") {
+            return Err(anyhow!("{command} failed: {error}"));
+        }
+
+        Ok(serde_json::from_value(reply)?)
+    }
+}
+
+/// Per-second rate of a counter between two snapshots `ms` milliseconds apart
+fn rate(previous: u64, current: u64, ms: u128) -> f64 {
+    current.saturating_sub(previous) as f64 * 1000.0 / ms.max(1) as f64
+}
+
+#[derive(Debug, Default)]
+struct Monitor {
+    previous: Option<StatsSnapshot>,
+    current: Option<StatsSnapshot>,
+    /// Events sent per second at each refresh, oldest first
+    event_rates: VecDeque<u64>,
+    coverage: Vec<CoverageEvent>,
+    /// Why the monitor stopped refreshing
+    error: Option<String>,
+}
+
+impl Monitor {
+    fn refresh(&mut self, control: &mut Control) -> Result<()> {
+        let snapshot = control.request::<StatsSnapshot>("stats")?;
+
+        // Plugins without coverage events reject the command, which only leaves the ticker
+        // empty
+        self.coverage = control.request("coverage").unwrap_or_default();
+
+        if let Some(current) = self.current.as_ref() {
+            let ms = snapshot.timestamp_ms.saturating_sub(current.timestamp_ms);
+
+            self.event_rates
+                .push_back(rate(current.events_sent, snapshot.events_sent, ms) as u64);
+
+            if self.event_rates.len() > HISTORY {
+                self.event_rates.pop_front();
+            }
+        }
+
+        self.previous = self.current.replace(snapshot);
+
+        Ok(())
+    }
+
+    /// The milliseconds between the last two snapshots
+    fn elapsed_ms(&self) -> Option<u128> {
+        Some(self.current.as_ref()?.timestamp_ms - self.previous.as_ref()?.timestamp_ms)
+    }
+
+    fn summary(&self) -> Vec<Line<'static>> {
+        let Some(current) = self.current.as_ref() else {
+            return vec![Line::from("Waiting for statistics")];
+        };
+
+        let (mips, events, dropped) = match (self.previous.as_ref(), self.elapsed_ms()) {
+            (Some(previous), Some(ms)) => (
+                rate(previous.instructions, current.instructions, ms) / 1e6,
+                rate(previous.events_sent, current.events_sent, ms),
+                rate(previous.events_dropped, current.events_dropped, ms),
+            ),
+            _ => (0.0, 0.0, 0.0),
+        };
+
+        vec![
+            Line::from(format!(
+                "Instructions {} ({mips:.2} MIPS)  Blocks {} executed, {} translated",
+                current.instructions, current.executed_blocks, current.translated_blocks
+            )),
+            Line::from(format!(
+                "Events {} ({events:.0}/s)  Dropped {} ({dropped:.0}/s)  Sink backlog {}  \
+                 Pending syscalls {}  New coverage {}",
+                current.events_sent,
+                current.events_dropped,
+                current.sink_waiting,
+                current.pending_syscalls,
+                current.new_coverage
+            )),
+        ]
+    }
+
+    fn vcpu_rows(&self) -> Vec<Row<'static>> {
+        let Some(current) = self.current.as_ref() else {
+            return Vec::new();
+        };
+
+        current
+            .vcpu_instructions
+            .iter()
+            .map(|(vcpu_index, instructions)| {
+                let previous = self.previous.as_ref();
+                let mips = match (previous, self.elapsed_ms()) {
+                    (Some(previous), Some(ms)) => {
+                        let before = previous.vcpu_instructions.get(vcpu_index).copied();
+
+                        rate(before.unwrap_or_default(), *instructions, ms) / 1e6
+                    }
+                    _ => 0.0,
+                };
+                let time = current
+                    .vcpu_time
+                    .get(vcpu_index)
+                    .copied()
+                    .unwrap_or_default();
+                let before = previous
+                    .and_then(|p| p.vcpu_time.get(vcpu_index).copied())
+                    .unwrap_or_default();
+                let busy = time.busy_ms.saturating_sub(before.busy_ms);
+                let idle = time.idle_ms.saturating_sub(before.idle_ms);
+                let utilization = busy as f64 * 100.0 / (busy + idle).max(1) as f64;
+
+                Row::new(vec![
+                    vcpu_index.to_string(),
+                    instructions.to_string(),
+                    format!("{mips:.2}"),
+                    format!("{utilization:.1}%"),
+                    time.idles.to_string(),
+                ])
+            })
+            .collect()
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let vcpus = self
+            .current
+            .as_ref()
+            .map_or(0, |c| c.vcpu_instructions.len()) as u16;
+        let [summary, table, sparkline, coverage, footer] = Layout::vertical([
+            Constraint::Length(4),
+            Constraint::Length(vcpus + 3),
+            Constraint::Length(6),
+            Constraint::Min(4),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Paragraph::new(self.summary()).block(Block::bordered().title("Tracer")),
+            summary,
+        );
+
+        frame.render_widget(
+            Table::new(
+                self.vcpu_rows(),
+                [
+                    Constraint::Length(6),
+                    Constraint::Length(16),
+                    Constraint::Length(10),
+                    Constraint::Length(8),
+                    Constraint::Length(8),
+                ],
+            )
+            .header(
+                Row::new(vec!["vCPU", "Instructions", "MIPS", "Busy", "Idles"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::bordered().title("vCPUs")),
+            table,
+        );
+
+        let rates = self.event_rates.iter().copied().collect::<Vec<_>>();
+
+        frame.render_widget(
+            Sparkline::default()
+                .data(&rates)
+                .style(Style::default().fg(Color::Cyan))
+                .block(Block::bordered().title("Events per second")),
+            sparkline,
+        );
+
+        let items = self
+            .coverage
+            .iter()
+            .rev()
+            .map(|event| {
+                let kind = match (event.block, event.edge_from) {
+                    (true, _) => "block".to_string(),
+                    (false, Some(from)) => format!("edge from {from:#x}"),
+                    (false, None) => String::new(),
+                };
+
+                ListItem::new(format!(
+                    "{:>14}  vCPU {}  {}+{:#x} ({:#x})  {kind}",
+                    event.icount, event.vcpu_index, event.module, event.offset, event.vaddr
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title("New coverage")),
+            coverage,
+        );
+
+        let status = match self.error.as_deref() {
+            Some(error) => format!("Stopped: {error}. Press q to quit"),
+            None => "Press q to quit".to_string(),
+        };
+
+        frame.render_widget(Paragraph::new(status), footer);
+    }
+}
+
+fn run(terminal: &mut DefaultTerminal, control: &mut Control, interval: Duration) -> Result<()> {
+    let mut monitor = Monitor::default();
+
+    loop {
+        if monitor.error.is_none() {
+            if let Err(e) = monitor.refresh(control) {
+                monitor.error = Some(e.to_string());
+            }
+        }
+
+        terminal.draw(|frame| monitor.draw(frame))?;
+
+        let deadline = Instant::now() + interval;
+
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            if !event::poll(timeout)? {
+                break;
+            }
+
+            if let Event::Key(key) = event::read()? {
+                let quit = matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                    || (key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL));
+
+                if key.kind == KeyEventKind::Press && quit {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut control = Control::connect(&args.control)?;
+    let mut terminal = ratatui::init();
+    let result = run(
+        &mut terminal,
+        &mut control,
+        Duration::from_millis(args.interval),
+    );
+
+    ratatui::restore();
+    result
+}
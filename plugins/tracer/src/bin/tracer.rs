@@ -2,10 +2,10 @@ use anyhow::{anyhow, Error, Result};
 use clap::Parser;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use serde_cbor::Deserializer;
-use serde_json::to_string;
+use serde_json::{json, to_string};
 use std::process::{Command, Stdio};
 use std::{
-    fs::OpenOptions,
+    fs::{File, OpenOptions},
     io::{stdout, BufRead, BufReader, Write},
     os::unix::net::UnixListener,
     path::{Path, PathBuf},
@@ -15,7 +15,19 @@ use tokio::{
     join, main, spawn,
     task::spawn_blocking,
 };
-use tracer::Event;
+use tracer::{
+    bookmarks::write_annotations,
+    qemulog::QemuLog,
+    redact::RedactionPolicy,
+    tracefile::{ShardedTraceFile, TraceReader},
+    Event,
+};
+#[cfg(feature = "plugin-api-v4")]
+use tracer::{
+    layout::{CStructs, LayoutLearner},
+    sched::write_timeline,
+    testing::ExpectationReport,
+};
 
 #[cfg(debug_assertions)]
 const PLUGIN: &[u8] = include_bytes!(concat!(
@@ -53,9 +65,23 @@ struct Args {
     #[clap(short = 'm', long)]
     /// Whether memory accesses should be logged
     pub log_mem: bool,
+    #[clap(long)]
+    /// Whether memory events should carry the value loaded or stored. Requires plugin API
+    /// version 4, and slows every memory access down
+    pub log_mem_values: bool,
     #[clap(short = 's', long)]
     /// Whether syscalls should be logged
     pub log_syscalls: bool,
+    #[clap(short = 'c', long)]
+    /// Whether the program's writes to stdout and stderr should be logged as events
+    pub log_console: bool,
+    #[clap(short = 'e', long)]
+    /// Whether the program's arguments and environment should be logged at startup
+    pub log_start: bool,
+    #[clap(short = 'p', long)]
+    /// Whether the address of each executed instruction should be logged, using a compact
+    /// encoding that is much cheaper than full instruction events
+    pub log_pcs: bool,
     #[clap(short = 'a', long)]
     /// Whether all events should be logged
     pub log_all: bool,
@@ -65,6 +91,424 @@ struct Args {
     #[clap(short = 'O', long)]
     /// An output file to write the trace to, otherwise stdout is used
     pub output_file: Option<PathBuf>,
+    #[clap(long)]
+    /// A file to write a JSON snapshot of the plugin's statistics to when QEMU receives
+    /// SIGUSR1 or a `stats` command is sent to the control socket
+    pub stats_file: Option<PathBuf>,
+    #[clap(long)]
+    /// A Unix socket path the plugin listens on for control commands (e.g. `stats`)
+    pub control_socket: Option<PathBuf>,
+    #[clap(long)]
+    /// A file to write a JSON report of the files the program accessed to at exit
+    pub file_report: Option<PathBuf>,
+    #[clap(long, requires = "file_report")]
+    /// Whether the file report should include a hash of the data read from each file
+    pub hash_files: bool,
+    #[clap(long)]
+    /// A pcap file to write the program's network traffic to, reconstructed from its socket
+    /// syscalls
+    pub pcap: Option<PathBuf>,
+    #[clap(long)]
+    /// Whether the random data the program reads from `getrandom` and the random devices
+    /// should be logged
+    pub log_random: bool,
+    #[clap(long)]
+    /// A seed for QEMU's guest random number generator, making `getrandom` results
+    /// reproducible across runs
+    pub seed: Option<u64>,
+    #[clap(long)]
+    /// A JSON file of fault injection rules. Each site matching a rule is logged as a fault
+    /// event
+    pub fault_rules: Option<PathBuf>,
+    #[clap(long, requires = "file_report")]
+    /// The maximum number of paths kept in the file report
+    pub max_files: Option<usize>,
+    #[clap(long, requires = "file_report")]
+    /// The maximum number of ranges kept for each path in the file report
+    pub max_ranges: Option<usize>,
+    #[clap(long, value_parser = ["evict", "stop"])]
+    /// What to do when a limit is reached: evict the oldest entry, or stop the run
+    pub limit_policy: Option<String>,
+    #[clap(long, value_parser = ["lossless", "lossy"])]
+    /// Merge runs of sequential stores by one instruction, such as `memset` loops, into one
+    /// ranged memory event. Lossless runs can be expanded back into the original events,
+    /// lossy runs keep only the first store's event
+    pub coalesce_writes: Option<String>,
+    #[clap(long)]
+    /// A file to write a flat profile of the instructions executed in each symbol to at exit
+    pub symprof: Option<PathBuf>,
+    #[clap(long, requires = "symprof")]
+    /// Weights of classes of instructions (atomic, crypto, branch, memory and other) for a
+    /// cycle estimate of each symbol, as `class:weight` pairs separated by `;`, such as
+    /// `memory:4;atomic:20`
+    pub symprof_weights: Option<String>,
+    #[clap(long)]
+    /// A file to write the instruction set extensions executed, such as AVX2, SVE or NEON, to
+    /// at exit, with how many of their instructions were executed in each module
+    pub extension_report: Option<PathBuf>,
+    #[clap(long)]
+    /// A file to write the executed instructions to at exit, grouped by the source line
+    /// they were compiled from, with execution counts
+    pub source_report: Option<PathBuf>,
+    #[clap(long, requires = "source_report")]
+    /// The ELF file whose DWARF line tables the source report uses, instead of the program,
+    /// such as firmware run in system mode
+    pub source_binary: Option<PathBuf>,
+    #[clap(long)]
+    /// Demangle recorded symbol names: `all`, or the languages to demangle separated by `;`,
+    /// from `rust`, `cpp` and `swift`. Swift needs the Swift runtime on the host
+    pub demangle: Option<String>,
+    #[clap(long)]
+    /// Name code QEMU has no symbol for, such as shared libraries, from the modules' symbol
+    /// tables and debug files found by build-id
+    pub symbolize_libraries: bool,
+    #[clap(long)]
+    /// Record code addresses as executed, instead of normalizing them to the addresses the
+    /// program and libraries were linked at, which are the same across runs under ASLR
+    pub absolute_pcs: bool,
+    #[clap(long, requires = "symbolize_libraries")]
+    /// Space-separated http:// debuginfod servers to fetch missing debug files from, instead
+    /// of DEBUGINFOD_URLS. Needs the debuginfod feature
+    pub debuginfod_urls: Option<String>,
+    #[clap(long, requires = "symbolize_libraries")]
+    /// The directory debug files are cached in, instead of the debuginfod client cache
+    pub debuginfod_cache: Option<PathBuf>,
+    #[clap(long)]
+    /// A file to write the program's block and edge coverage to at exit
+    pub coverage: Option<PathBuf>,
+    #[clap(long)]
+    /// A file to write the program's block coverage to at exit in drcov format, for coverage
+    /// tools such as Lighthouse
+    pub drcov: Option<PathBuf>,
+    #[clap(long, requires = "coverage")]
+    /// Merge the coverage into the coverage file, if it exists, instead of replacing it. Runs
+    /// merging into the same file concurrently take turns under a lock.
+    pub coverage_merge: bool,
+    #[clap(long)]
+    /// A coverage file to compare against as the program runs, logging a coverage event each
+    /// time it reaches a block or edge not covered in the file
+    pub coverage_baseline: Option<PathBuf>,
+    #[clap(long)]
+    /// How blocks are keyed in coverage and the retranslation report: `offset`, their offset
+    /// in the program, or `content`, their offset and a hash of their code, which stays the
+    /// same across rebuilds that leave the block's code in place
+    pub block_identity: Option<String>,
+    #[clap(long)]
+    /// A file for the plugin to write events to directly, instead of sending them to this
+    /// program. The file remains readable up to its last commit if QEMU is killed.
+    pub trace_file: Option<PathBuf>,
+    #[clap(long, conflicts_with = "trace_file")]
+    /// A directory for the plugin to write one trace file per vCPU to, with a manifest
+    pub trace_shards: Option<PathBuf>,
+    #[clap(long, requires = "trace_shards")]
+    /// Once the program exits, merge the shards by instruction count and write them to the
+    /// output as a single trace
+    pub merge_shards: bool,
+    #[clap(long)]
+    /// A shared library implementing the analysis module ABI, to pass every event to. May
+    /// be repeated
+    pub analysis_module: Vec<PathBuf>,
+    #[clap(long, requires = "analysis_module", conflicts_with_all = ["trace_file", "trace_shards"])]
+    /// Pass events only to the analysis modules, without writing a trace
+    pub analysis_only: bool,
+    #[clap(long)]
+    /// Check the plugin's environment and arguments, print a report on QEMU's log and run the
+    /// program untraced
+    pub check: bool,
+    #[clap(long)]
+    /// Write a run manifest describing the capture: QEMU's version, the plugin API version,
+    /// the target, the plugin's arguments and their hash, hashes of the guest binaries, and
+    /// the counters at start and exit. It is written next to the trace file, into the
+    /// sharded trace, object store or aggregator, or to `run.json`
+    pub run_manifest: bool,
+    #[clap(long, requires = "run_manifest")]
+    /// Write the run manifest to this file instead
+    pub run_manifest_path: Option<PathBuf>,
+    #[clap(long, requires = "run_manifest")]
+    /// A file to hash in the run manifest besides the guest program, such as a firmware or
+    /// kernel image. May be repeated
+    pub run_binary: Vec<PathBuf>,
+    #[clap(long, conflicts_with_all = ["trace_file", "trace_shards"])]
+    /// The address of an `aggregator` server, as `host:port`, for the plugin to send events
+    /// and its coverage to, instead of to this program
+    pub aggregator: Option<String>,
+    #[clap(long, requires = "aggregator")]
+    /// The name the instance is known by on the aggregator, by default QEMU's process ID
+    pub aggregator_name: Option<String>,
+    #[clap(long)]
+    /// A bookmark to log each time the instruction at an address executes, as `name@pc`.
+    /// May be repeated
+    pub bookmark: Vec<String>,
+    #[clap(long)]
+    /// Log a bookmark when the program makes this syscall, which it can use as a hypercall,
+    /// with a pointer to the bookmark's name as its first argument and, optionally, a
+    /// pointer to a note as its second
+    pub bookmark_syscall: Option<i64>,
+    #[clap(long)]
+    /// Once the program exits, write the bookmarks in the trace to this file as JSON
+    /// annotations. Requires an output file or a trace file to read the trace back from
+    pub annotations: Option<PathBuf>,
+    #[clap(long)]
+    /// Have QEMU also write its own `-d exec,in_asm,nochain` log of the blocks it translates
+    /// and executes to this file. Code addresses are recorded as executed, as QEMU logs them
+    pub qemu_log: Option<PathBuf>,
+    #[clap(long, requires = "qemu_log")]
+    /// Once the program exits, write how closely QEMU's log and the trace agree on the
+    /// addresses executed to this file as JSON. Requires an output file or a trace file to
+    /// read the trace back from
+    pub qemu_log_alignment: Option<PathBuf>,
+    #[clap(long)]
+    /// Whether to decode hypercalls made by the program with the `qemu-plugin-guest` crate,
+    /// logging its bookmarks, coverage hints and messages
+    pub guest_hypercalls: bool,
+    #[clap(long)]
+    /// Send the busy, idle and estimated stolen time of each vCPU as a utilization event
+    /// every this many milliseconds
+    pub utilization_interval: Option<u64>,
+    #[clap(long)]
+    /// Send the instruction count with the host's monotonic and wall clock times as a clock
+    /// event every this many milliseconds, to correlate the trace with host-side logs
+    pub clock_interval: Option<u64>,
+    #[clap(long)]
+    /// Once the program exits, write a summary of how busy each vCPU was to this file as
+    /// JSON
+    pub utilization_report: Option<PathBuf>,
+    #[clap(long)]
+    /// Once the program exits, write statistics on translation cache flushes and the blocks
+    /// and pages retranslated most often to this file as JSON
+    pub retranslation_report: Option<PathBuf>,
+    #[clap(long, requires = "retranslation_report")]
+    /// How many blocks and pages the retranslation report includes
+    pub retranslation_top: Option<usize>,
+    #[cfg(feature = "object-store")]
+    #[clap(long, conflicts_with_all = ["trace_file", "trace_shards", "aggregator"])]
+    /// A path-style URL, `http[s]://host[:port]/bucket[/prefix]`, or `s3://bucket[/prefix]`,
+    /// for the plugin to upload the trace to in compressed chunks, with a manifest. Requests
+    /// are signed with the credentials in `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`, if
+    /// set
+    pub object_store: Option<String>,
+    #[cfg(feature = "object-store")]
+    #[clap(long, requires = "object_store")]
+    /// The region requests to the object store are signed for, by default `us-east-1`
+    pub object_store_region: Option<String>,
+    #[cfg(feature = "object-store")]
+    #[clap(long, requires = "object_store")]
+    /// The size of the chunks uploaded, before compression, by default 8 MiB
+    pub object_store_chunk_size: Option<usize>,
+    #[clap(long)]
+    /// Once the program exits, write a copy of the trace file or sharded trace here with
+    /// guest memory contents, paths and the environment redacted, so it can be shared
+    pub redact: Option<PathBuf>,
+    #[clap(long, requires = "redact")]
+    /// A TOML or JSON redaction policy, whose fields are applied over the default policy's
+    pub redaction_policy: Option<PathBuf>,
+    #[clap(long)]
+    /// A directory to write ELF core dumps of the program's memory to when a dump trigger
+    /// fires
+    pub dump_dir: Option<PathBuf>,
+    #[clap(long, requires = "dump_dir")]
+    /// A range of guest addresses to include in dumps, as `start-end`. May be repeated
+    pub dump_range: Vec<String>,
+    #[clap(long, requires = "dump_dir")]
+    /// Dump when the instruction at this address is executed
+    pub dump_pc: Option<String>,
+    #[clap(long, requires = "dump_dir")]
+    /// Dump when the program makes this syscall, which it can use as a hypercall
+    pub dump_syscall: Option<i64>,
+    #[clap(long, requires = "dump_dir")]
+    /// Dump when the program sends itself a fatal signal, e.g. by calling `abort`
+    pub dump_on_abort: bool,
+    #[clap(long, requires = "dump_dir")]
+    /// The maximum number of dumps to write
+    pub dump_limit: Option<usize>,
+    #[clap(long)]
+    /// A rate limit for a class of events, as `class:per_second[:burst]`, e.g.
+    /// `memory:10000`. Dropped events are counted in `Dropped` events. May be repeated
+    pub throttle: Vec<String>,
+    #[clap(long)]
+    /// Only record the first N instruction, pc and memory events of each instruction
+    pub dedup_limit: Option<u64>,
+    #[clap(long, requires = "dedup_limit")]
+    /// Count occurrences separately for each kind of event, instead of counting executions of
+    /// each instruction
+    pub dedup_per_class: bool,
+    #[clap(long)]
+    /// Whether heap allocations should be tracked, attributing each memory event to the
+    /// allocation it falls in and the call stack which allocated it
+    pub track_heap: bool,
+    #[clap(long, requires = "track_heap")]
+    /// An allocator function the program has no symbol for, as `name:address`, e.g.
+    /// `malloc:0x4011a0`. May be repeated
+    pub heap_function: Vec<String>,
+    #[clap(long, requires = "track_heap")]
+    /// How many return addresses to record for each allocation site
+    pub heap_depth: Option<usize>,
+    #[clap(long)]
+    /// An API profile whose functions' calls should be logged with readable arguments:
+    /// `libc`, `openssl`, or the path of a TOML or JSON profile. May be repeated
+    pub api_profile: Vec<String>,
+    #[clap(long, requires = "api_profile")]
+    /// A profiled function the program has no symbol for, as `name:address`, e.g.
+    /// `SSL_read:0x7f0012a0`. May be repeated
+    pub api_function: Vec<String>,
+    #[clap(long)]
+    /// Whether control flow resembling a ROP chain should be logged: returns to addresses
+    /// not preceded by a call, and runs of short blocks ending in returns. With
+    /// `--track-heap`, events include the call stack
+    pub detect_rop: bool,
+    #[clap(long, requires = "detect_rop")]
+    /// The score at which a ROP event is logged. Each unexpected return adds 10, each
+    /// gadget 4, and each other block subtracts 1
+    pub rop_threshold: Option<u64>,
+    #[clap(long, requires = "detect_rop")]
+    /// The most instructions a block may have to count as a gadget
+    pub rop_gadget_len: Option<usize>,
+    #[clap(long)]
+    /// Whether returns which do not land on the return address of the call they return from
+    /// should be logged as integrity violations
+    pub shadow_stack: bool,
+    #[clap(long, requires = "shadow_stack")]
+    /// Also log returns which unwind to a caller further up the stack, as C++ exception
+    /// unwinding does
+    pub shadow_stack_strict: bool,
+    #[clap(long, requires = "shadow_stack")]
+    /// A return target never logged as a violation, e.g. the signal restorer a signal
+    /// handler returns to. May be repeated
+    pub shadow_stack_allow: Vec<String>,
+    #[clap(long)]
+    /// A JSON file of byte signatures, e.g. `[{"name": "memcpy", "pattern": "55 48 ?? e5"}]`,
+    /// to match against the program's code as it is translated
+    pub signatures: Option<PathBuf>,
+    #[clap(long)]
+    /// A buffer whose entropy and compression ratio should be logged when the instruction at
+    /// an address executes, as `pc:address:length`, where the address and length are
+    /// registers or constants, e.g. `0x401a20:rdi:rdx`. May be repeated
+    pub entropy_probe: Vec<String>,
+    #[clap(long)]
+    /// Whether functions which may implement cryptographic algorithms should be logged,
+    /// found from blocks using cryptographic extensions or constants characteristic of an
+    /// algorithm, such as the AES S-box
+    pub detect_crypto: bool,
+    #[clap(long, requires = "detect_crypto")]
+    /// The fewest instructions from cryptographic extensions, such as AES-NI, a block
+    /// needs to be logged without a characteristic constant. Defaults to 2
+    pub crypto_threshold: Option<usize>,
+    #[clap(long)]
+    /// Whether the strings in the program's read-only data should be logged the first time
+    /// an instruction referencing each executes
+    pub log_strings: bool,
+    #[clap(long, requires = "log_strings")]
+    /// A range of guest addresses to take strings from, as `start-end`, such as a firmware's
+    /// flash when the program has no ELF file. May be repeated
+    pub string_range: Vec<String>,
+    #[clap(long)]
+    /// Whether the guest threads each vCPU runs should be logged, as spans of instructions
+    /// between changes to the thread pointer register
+    pub log_schedule: bool,
+    #[clap(long)]
+    /// Whether changes of instruction set, between ARM and Thumb or between x86's real,
+    /// protected and long modes, should be logged, and instructions annotated with their mode
+    pub log_isa_modes: bool,
+    #[clap(long)]
+    /// A file to write a JSON report of the most contended pthread mutexes to at exit, with
+    /// their wait and hold times and the call stacks which acquired them
+    pub lock_report: Option<PathBuf>,
+    #[clap(long, requires = "lock_report")]
+    /// A mutex function the program has no symbol for, as `name:address`, e.g.
+    /// `pthread_mutex_lock:0x4011a0`. May be repeated
+    pub lock_function: Vec<String>,
+    #[clap(long, requires = "lock_report")]
+    /// How many locks the lock report includes
+    pub lock_top: Option<usize>,
+    #[clap(long)]
+    /// Whether accesses to the same memory by different threads without an intervening
+    /// atomic instruction or futex should be logged as potential races
+    pub detect_races: bool,
+    #[clap(long)]
+    /// A file to write a JSON report of the program's load-/store-exclusive sequences to at
+    /// exit, with their failure and retry counts. ARM targets only
+    pub exclusive_report: Option<PathBuf>,
+    #[clap(long)]
+    /// A TOML or JSON file of expectations about the program's behavior, such as functions
+    /// it calls and memory it never writes, checked as it runs. The run fails if any is not
+    /// met
+    pub expectations: Option<PathBuf>,
+    #[clap(long, requires = "expectations")]
+    /// A file to write the result of each expectation to as JSON at exit
+    pub expectation_report: Option<PathBuf>,
+    #[clap(long)]
+    /// A file to write the virtual-to-physical page mappings observed, and their changes,
+    /// to as JSON at exit. New and changed mappings are also logged as they happen. System
+    /// mode only
+    pub translation_log: Option<PathBuf>,
+    #[clap(long)]
+    /// Whether memory both written and executed, or mapped writable and executable at once,
+    /// should be logged as a W^X violation
+    pub detect_wx: bool,
+    #[clap(long)]
+    /// A file to write each page which broke W^X to as JSON at exit, with a timeline of its
+    /// protections, writes and executions. Implies `--detect-wx`
+    pub wx_report: Option<PathBuf>,
+    #[clap(long)]
+    /// A directory to write a crash bundle to when the program reaches `abort`, `panic` or
+    /// another `--crash-trigger`, holding its registers, its stack and its last events
+    pub crash_dir: Option<PathBuf>,
+    #[clap(long, requires = "crash_dir")]
+    /// A function, by symbol or address, which triggers a crash bundle, replacing the
+    /// default functions. May be repeated
+    pub crash_trigger: Vec<String>,
+    #[clap(long, requires = "crash_dir")]
+    /// How many bytes of stack a crash bundle holds
+    pub crash_stack: Option<usize>,
+    #[clap(long, requires = "crash_dir")]
+    /// How many of the last events of the crashing vCPU a crash bundle holds
+    pub crash_window: Option<usize>,
+    #[clap(long, requires = "crash_dir")]
+    /// The maximum number of crash bundles to write
+    pub crash_limit: Option<usize>,
+    #[clap(long, requires = "crash_dir")]
+    /// Write a minidump of the crashing vCPU into each crash bundle
+    pub crash_minidump: bool,
+    #[clap(long)]
+    /// A file to log each callback the tracer registers on each translated block to, and
+    /// why, as JSON lines. Read it with the `decisions` binary
+    pub decision_log: Option<PathBuf>,
+    #[clap(long)]
+    /// A rule restricting instrumentation to ELF sections or segments, like `.text`,
+    /// `!.plt*`, `segment:1` or `.text@libfoo.so`. May be repeated. User mode only
+    pub section_filter: Vec<String>,
+    #[clap(long)]
+    /// Name PLT stubs and the functions bound to them after the functions they import, and
+    /// trace calls through them as calls to those functions. User mode only
+    pub resolve_plt: bool,
+    #[clap(long, requires = "inject_data")]
+    /// A path whose contents the program should read as the data of `--inject-data`, as the
+    /// program opens it, or `-` for stdin
+    pub inject_path: Option<String>,
+    #[clap(long, requires = "inject_path")]
+    /// A file of data to serve to the program's reads of `--inject-path`
+    pub inject_data: Option<PathBuf>,
+    #[clap(long)]
+    /// Log a hang when the program executes this many instructions in one iteration, i.e.
+    /// since it last reached a `--watchdog-reset` address, or since it started
+    pub watchdog_budget: Option<u64>,
+    #[clap(long, requires = "watchdog_budget")]
+    /// An address whose execution starts a new iteration, e.g. the top of a test loop. May
+    /// be repeated
+    pub watchdog_reset: Vec<String>,
+    #[clap(long, requires = "watchdog_budget")]
+    /// Exit with status 124 when the watchdog budget runs out, instead of only logging it
+    pub watchdog_exit: bool,
+    #[clap(long)]
+    /// A sequence point, e.g. the top of a message loop, at which to log a hash of the
+    /// program's state. May be repeated
+    pub state_point: Vec<String>,
+    #[clap(long)]
+    /// A region of memory to include in state hashes, as `address:length`. May be repeated
+    pub state_region: Vec<String>,
+    #[clap(long)]
+    /// A register to include in state hashes. May be repeated
+    pub state_register: Vec<String>,
     /// The program to run
     #[clap()]
     pub program: PathBuf,
@@ -84,12 +528,26 @@ struct Args {
     #[clap(short = 'm', long)]
     /// Whether memory accesses should be logged
     pub log_mem: bool,
+    #[clap(long)]
+    /// Whether memory events should carry the value loaded or stored. Requires plugin API
+    /// version 4, and slows every memory access down
+    pub log_mem_values: bool,
     #[clap(short = 's', long)]
     /// Whether syscalls should be logged
     pub log_syscalls: bool,
     #[clap(short = 'r', long)]
     /// Whether registers should be logged
     pub log_registers: bool,
+    #[clap(short = 'c', long)]
+    /// Whether the program's writes to stdout and stderr should be logged as events
+    pub log_console: bool,
+    #[clap(short = 'e', long)]
+    /// Whether the program's arguments and environment should be logged at startup
+    pub log_start: bool,
+    #[clap(short = 'p', long)]
+    /// Whether the address of each executed instruction should be logged, using a compact
+    /// encoding that is much cheaper than full instruction events
+    pub log_pcs: bool,
     #[clap(short = 'a', long)]
     /// Whether all events should be logged
     pub log_all: bool,
@@ -99,6 +557,453 @@ struct Args {
     #[clap(short = 'O', long)]
     /// An output file to write the trace to, otherwise stdout is used
     pub output_file: Option<PathBuf>,
+    #[clap(long)]
+    /// A file to write a JSON snapshot of the plugin's statistics to when QEMU receives
+    /// SIGUSR1 or a `stats` command is sent to the control socket
+    pub stats_file: Option<PathBuf>,
+    #[clap(long)]
+    /// A Unix socket path the plugin listens on for control commands (e.g. `stats`)
+    pub control_socket: Option<PathBuf>,
+    #[clap(long)]
+    /// A file to write a JSON report of the files the program accessed to at exit
+    pub file_report: Option<PathBuf>,
+    #[clap(long, requires = "file_report")]
+    /// Whether the file report should include a hash of the data read from each file
+    pub hash_files: bool,
+    #[clap(long)]
+    /// A pcap file to write the program's network traffic to, reconstructed from its socket
+    /// syscalls
+    pub pcap: Option<PathBuf>,
+    #[clap(long)]
+    /// Whether the random data the program reads from `getrandom` and the random devices
+    /// should be logged
+    pub log_random: bool,
+    #[clap(long)]
+    /// A seed for QEMU's guest random number generator, making `getrandom` results
+    /// reproducible across runs
+    pub seed: Option<u64>,
+    #[clap(long)]
+    /// A JSON file of fault injection rules. Each site matching a rule is logged as a fault
+    /// event
+    pub fault_rules: Option<PathBuf>,
+    #[clap(long, requires = "file_report")]
+    /// The maximum number of paths kept in the file report
+    pub max_files: Option<usize>,
+    #[clap(long, requires = "file_report")]
+    /// The maximum number of ranges kept for each path in the file report
+    pub max_ranges: Option<usize>,
+    #[clap(long, value_parser = ["evict", "stop"])]
+    /// What to do when a limit is reached: evict the oldest entry, or stop the run
+    pub limit_policy: Option<String>,
+    #[clap(long, value_parser = ["lossless", "lossy"])]
+    /// Merge runs of sequential stores by one instruction, such as `memset` loops, into one
+    /// ranged memory event. Lossless runs can be expanded back into the original events,
+    /// lossy runs keep only the first store's event
+    pub coalesce_writes: Option<String>,
+    #[clap(long)]
+    /// A file to write a flat profile of the instructions executed in each symbol to at exit
+    pub symprof: Option<PathBuf>,
+    #[clap(long, requires = "symprof")]
+    /// Weights of classes of instructions (atomic, crypto, branch, memory and other) for a
+    /// cycle estimate of each symbol, as `class:weight` pairs separated by `;`, such as
+    /// `memory:4;atomic:20`
+    pub symprof_weights: Option<String>,
+    #[clap(long)]
+    /// A file to write the instruction set extensions executed, such as AVX2, SVE or NEON, to
+    /// at exit, with how many of their instructions were executed in each module
+    pub extension_report: Option<PathBuf>,
+    #[clap(long)]
+    /// A file to write the executed instructions to at exit, grouped by the source line
+    /// they were compiled from, with execution counts
+    pub source_report: Option<PathBuf>,
+    #[clap(long, requires = "source_report")]
+    /// The ELF file whose DWARF line tables the source report uses, instead of the program,
+    /// such as firmware run in system mode
+    pub source_binary: Option<PathBuf>,
+    #[clap(long)]
+    /// Demangle recorded symbol names: `all`, or the languages to demangle separated by `;`,
+    /// from `rust`, `cpp` and `swift`. Swift needs the Swift runtime on the host
+    pub demangle: Option<String>,
+    #[clap(long)]
+    /// Name code QEMU has no symbol for, such as shared libraries, from the modules' symbol
+    /// tables and debug files found by build-id
+    pub symbolize_libraries: bool,
+    #[clap(long)]
+    /// Record code addresses as executed, instead of normalizing them to the addresses the
+    /// program and libraries were linked at, which are the same across runs under ASLR
+    pub absolute_pcs: bool,
+    #[clap(long, requires = "symbolize_libraries")]
+    /// Space-separated http:// debuginfod servers to fetch missing debug files from, instead
+    /// of DEBUGINFOD_URLS. Needs the debuginfod feature
+    pub debuginfod_urls: Option<String>,
+    #[clap(long, requires = "symbolize_libraries")]
+    /// The directory debug files are cached in, instead of the debuginfod client cache
+    pub debuginfod_cache: Option<PathBuf>,
+    #[clap(long)]
+    /// A file to write the program's block and edge coverage to at exit
+    pub coverage: Option<PathBuf>,
+    #[clap(long)]
+    /// A file to write the program's block coverage to at exit in drcov format, for coverage
+    /// tools such as Lighthouse
+    pub drcov: Option<PathBuf>,
+    #[clap(long, requires = "coverage")]
+    /// Merge the coverage into the coverage file, if it exists, instead of replacing it. Runs
+    /// merging into the same file concurrently take turns under a lock.
+    pub coverage_merge: bool,
+    #[clap(long)]
+    /// A coverage file to compare against as the program runs, logging a coverage event each
+    /// time it reaches a block or edge not covered in the file
+    pub coverage_baseline: Option<PathBuf>,
+    #[clap(long)]
+    /// How blocks are keyed in coverage and the retranslation report: `offset`, their offset
+    /// in the program, or `content`, their offset and a hash of their code, which stays the
+    /// same across rebuilds that leave the block's code in place
+    pub block_identity: Option<String>,
+    #[clap(long)]
+    /// A file for the plugin to write events to directly, instead of sending them to this
+    /// program. The file remains readable up to its last commit if QEMU is killed.
+    pub trace_file: Option<PathBuf>,
+    #[clap(long, conflicts_with = "trace_file")]
+    /// A directory for the plugin to write one trace file per vCPU to, with a manifest
+    pub trace_shards: Option<PathBuf>,
+    #[clap(long, requires = "trace_shards")]
+    /// Once the program exits, merge the shards by instruction count and write them to the
+    /// output as a single trace
+    pub merge_shards: bool,
+    #[clap(long)]
+    /// A shared library implementing the analysis module ABI, to pass every event to. May
+    /// be repeated
+    pub analysis_module: Vec<PathBuf>,
+    #[clap(long, requires = "analysis_module", conflicts_with_all = ["trace_file", "trace_shards"])]
+    /// Pass events only to the analysis modules, without writing a trace
+    pub analysis_only: bool,
+    #[clap(long)]
+    /// Check the plugin's environment and arguments, print a report on QEMU's log and run the
+    /// program untraced
+    pub check: bool,
+    #[clap(long)]
+    /// Write a run manifest describing the capture: QEMU's version, the plugin API version,
+    /// the target, the plugin's arguments and their hash, hashes of the guest binaries, and
+    /// the counters at start and exit. It is written next to the trace file, into the
+    /// sharded trace, object store or aggregator, or to `run.json`
+    pub run_manifest: bool,
+    #[clap(long, requires = "run_manifest")]
+    /// Write the run manifest to this file instead
+    pub run_manifest_path: Option<PathBuf>,
+    #[clap(long, requires = "run_manifest")]
+    /// A file to hash in the run manifest besides the guest program, such as a firmware or
+    /// kernel image. May be repeated
+    pub run_binary: Vec<PathBuf>,
+    #[clap(long, conflicts_with_all = ["trace_file", "trace_shards"])]
+    /// The address of an `aggregator` server, as `host:port`, for the plugin to send events
+    /// and its coverage to, instead of to this program
+    pub aggregator: Option<String>,
+    #[clap(long, requires = "aggregator")]
+    /// The name the instance is known by on the aggregator, by default QEMU's process ID
+    pub aggregator_name: Option<String>,
+    #[clap(long)]
+    /// A bookmark to log each time the instruction at an address executes, as `name@pc`.
+    /// May be repeated
+    pub bookmark: Vec<String>,
+    #[clap(long)]
+    /// Log a bookmark when the program makes this syscall, which it can use as a hypercall,
+    /// with a pointer to the bookmark's name as its first argument and, optionally, a
+    /// pointer to a note as its second
+    pub bookmark_syscall: Option<i64>,
+    #[clap(long)]
+    /// Once the program exits, write the bookmarks in the trace to this file as JSON
+    /// annotations. Requires an output file or a trace file to read the trace back from
+    pub annotations: Option<PathBuf>,
+    #[clap(long)]
+    /// Have QEMU also write its own `-d exec,in_asm,nochain` log of the blocks it translates
+    /// and executes to this file. Code addresses are recorded as executed, as QEMU logs them
+    pub qemu_log: Option<PathBuf>,
+    #[clap(long, requires = "qemu_log")]
+    /// Once the program exits, write how closely QEMU's log and the trace agree on the
+    /// addresses executed to this file as JSON. Requires an output file or a trace file to
+    /// read the trace back from
+    pub qemu_log_alignment: Option<PathBuf>,
+    #[clap(long)]
+    /// Whether to decode hypercalls made by the program with the `qemu-plugin-guest` crate,
+    /// logging its bookmarks, coverage hints and messages
+    pub guest_hypercalls: bool,
+    #[clap(long)]
+    /// Send the busy, idle and estimated stolen time of each vCPU as a utilization event
+    /// every this many milliseconds
+    pub utilization_interval: Option<u64>,
+    #[clap(long)]
+    /// Send the instruction count with the host's monotonic and wall clock times as a clock
+    /// event every this many milliseconds, to correlate the trace with host-side logs
+    pub clock_interval: Option<u64>,
+    #[clap(long)]
+    /// Once the program exits, write a summary of how busy each vCPU was to this file as
+    /// JSON
+    pub utilization_report: Option<PathBuf>,
+    #[clap(long)]
+    /// Once the program exits, write statistics on translation cache flushes and the blocks
+    /// and pages retranslated most often to this file as JSON
+    pub retranslation_report: Option<PathBuf>,
+    #[clap(long, requires = "retranslation_report")]
+    /// How many blocks and pages the retranslation report includes
+    pub retranslation_top: Option<usize>,
+    #[cfg(feature = "object-store")]
+    #[clap(long, conflicts_with_all = ["trace_file", "trace_shards", "aggregator"])]
+    /// A path-style URL, `http[s]://host[:port]/bucket[/prefix]`, or `s3://bucket[/prefix]`,
+    /// for the plugin to upload the trace to in compressed chunks, with a manifest. Requests
+    /// are signed with the credentials in `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`, if
+    /// set
+    pub object_store: Option<String>,
+    #[cfg(feature = "object-store")]
+    #[clap(long, requires = "object_store")]
+    /// The region requests to the object store are signed for, by default `us-east-1`
+    pub object_store_region: Option<String>,
+    #[cfg(feature = "object-store")]
+    #[clap(long, requires = "object_store")]
+    /// The size of the chunks uploaded, before compression, by default 8 MiB
+    pub object_store_chunk_size: Option<usize>,
+    #[clap(long)]
+    /// Once the program exits, write a copy of the trace file or sharded trace here with
+    /// guest memory contents, paths and the environment redacted, so it can be shared
+    pub redact: Option<PathBuf>,
+    #[clap(long, requires = "redact")]
+    /// A TOML or JSON redaction policy, whose fields are applied over the default policy's
+    pub redaction_policy: Option<PathBuf>,
+    #[clap(long)]
+    /// A directory to write ELF core dumps of the program's memory to when a dump trigger
+    /// fires
+    pub dump_dir: Option<PathBuf>,
+    #[clap(long, requires = "dump_dir")]
+    /// A range of guest addresses to include in dumps, as `start-end`. May be repeated
+    pub dump_range: Vec<String>,
+    #[clap(long, requires = "dump_dir")]
+    /// Dump when the instruction at this address is executed
+    pub dump_pc: Option<String>,
+    #[clap(long, requires = "dump_dir")]
+    /// Dump when the program makes this syscall, which it can use as a hypercall
+    pub dump_syscall: Option<i64>,
+    #[clap(long, requires = "dump_dir")]
+    /// Dump when the program sends itself a fatal signal, e.g. by calling `abort`
+    pub dump_on_abort: bool,
+    #[clap(long, requires = "dump_dir")]
+    /// The maximum number of dumps to write
+    pub dump_limit: Option<usize>,
+    #[clap(long)]
+    /// A rate limit for a class of events, as `class:per_second[:burst]`, e.g.
+    /// `memory:10000`. Dropped events are counted in `Dropped` events. May be repeated
+    pub throttle: Vec<String>,
+    #[clap(long)]
+    /// Only record the first N instruction, pc and memory events of each instruction
+    pub dedup_limit: Option<u64>,
+    #[clap(long, requires = "dedup_limit")]
+    /// Count occurrences separately for each kind of event, instead of counting executions of
+    /// each instruction
+    pub dedup_per_class: bool,
+    #[clap(long)]
+    /// Whether heap allocations should be tracked, attributing each memory event to the
+    /// allocation it falls in and the call stack which allocated it
+    pub track_heap: bool,
+    #[clap(long, requires = "track_heap")]
+    /// An allocator function the program has no symbol for, as `name:address`, e.g.
+    /// `malloc:0x4011a0`. May be repeated
+    pub heap_function: Vec<String>,
+    #[clap(long, requires = "track_heap")]
+    /// How many return addresses to record for each allocation site
+    pub heap_depth: Option<usize>,
+    #[clap(long)]
+    /// An API profile whose functions' calls should be logged with readable arguments:
+    /// `libc`, `openssl`, or the path of a TOML or JSON profile. May be repeated
+    pub api_profile: Vec<String>,
+    #[clap(long, requires = "api_profile")]
+    /// A profiled function the program has no symbol for, as `name:address`, e.g.
+    /// `SSL_read:0x7f0012a0`. May be repeated
+    pub api_function: Vec<String>,
+    #[clap(long)]
+    /// Whether control flow resembling a ROP chain should be logged: returns to addresses
+    /// not preceded by a call, and runs of short blocks ending in returns. With
+    /// `--track-heap`, events include the call stack
+    pub detect_rop: bool,
+    #[clap(long, requires = "detect_rop")]
+    /// The score at which a ROP event is logged. Each unexpected return adds 10, each
+    /// gadget 4, and each other block subtracts 1
+    pub rop_threshold: Option<u64>,
+    #[clap(long, requires = "detect_rop")]
+    /// The most instructions a block may have to count as a gadget
+    pub rop_gadget_len: Option<usize>,
+    #[clap(long)]
+    /// Whether returns which do not land on the return address of the call they return from
+    /// should be logged as integrity violations
+    pub shadow_stack: bool,
+    #[clap(long, requires = "shadow_stack")]
+    /// Also log returns which unwind to a caller further up the stack, as C++ exception
+    /// unwinding does
+    pub shadow_stack_strict: bool,
+    #[clap(long, requires = "shadow_stack")]
+    /// A return target never logged as a violation, e.g. the signal restorer a signal
+    /// handler returns to. May be repeated
+    pub shadow_stack_allow: Vec<String>,
+    #[clap(long)]
+    /// A JSON file of byte signatures, e.g. `[{"name": "memcpy", "pattern": "55 48 ?? e5"}]`,
+    /// to match against the program's code as it is translated
+    pub signatures: Option<PathBuf>,
+    #[clap(long)]
+    /// A buffer whose entropy and compression ratio should be logged when the instruction at
+    /// an address executes, as `pc:address:length`, where the address and length are
+    /// registers or constants, e.g. `0x401a20:rdi:rdx`. May be repeated
+    pub entropy_probe: Vec<String>,
+    #[clap(long)]
+    /// Whether functions which may implement cryptographic algorithms should be logged,
+    /// found from blocks using cryptographic extensions or constants characteristic of an
+    /// algorithm, such as the AES S-box
+    pub detect_crypto: bool,
+    #[clap(long, requires = "detect_crypto")]
+    /// The fewest instructions from cryptographic extensions, such as AES-NI, a block
+    /// needs to be logged without a characteristic constant. Defaults to 2
+    pub crypto_threshold: Option<usize>,
+    #[clap(long)]
+    /// Whether the strings in the program's read-only data should be logged the first time
+    /// an instruction referencing each executes
+    pub log_strings: bool,
+    #[clap(long, requires = "log_strings")]
+    /// A range of guest addresses to take strings from, as `start-end`, such as a firmware's
+    /// flash when the program has no ELF file. May be repeated
+    pub string_range: Vec<String>,
+    #[clap(long)]
+    /// Whether the guest threads each vCPU runs should be logged, as spans of instructions
+    /// between changes to the thread pointer register
+    pub log_schedule: bool,
+    #[clap(long)]
+    /// Whether changes of instruction set, between ARM and Thumb or between x86's real,
+    /// protected and long modes, should be logged, and instructions annotated with their mode
+    pub log_isa_modes: bool,
+    #[clap(long)]
+    /// A file to write a JSON report of the most contended pthread mutexes to at exit, with
+    /// their wait and hold times and the call stacks which acquired them
+    pub lock_report: Option<PathBuf>,
+    #[clap(long, requires = "lock_report")]
+    /// A mutex function the program has no symbol for, as `name:address`, e.g.
+    /// `pthread_mutex_lock:0x4011a0`. May be repeated
+    pub lock_function: Vec<String>,
+    #[clap(long, requires = "lock_report")]
+    /// How many locks the lock report includes
+    pub lock_top: Option<usize>,
+    #[clap(long)]
+    /// Whether accesses to the same memory by different threads without an intervening
+    /// atomic instruction or futex should be logged as potential races
+    pub detect_races: bool,
+    #[clap(long)]
+    /// A file to write a JSON report of the program's load-/store-exclusive sequences to at
+    /// exit, with their failure and retry counts. ARM targets only
+    pub exclusive_report: Option<PathBuf>,
+    #[clap(long)]
+    /// A TOML or JSON file of expectations about the program's behavior, such as functions
+    /// it calls and memory it never writes, checked as it runs. The run fails if any is not
+    /// met
+    pub expectations: Option<PathBuf>,
+    #[clap(long, requires = "expectations")]
+    /// A file to write the result of each expectation to as JSON at exit
+    pub expectation_report: Option<PathBuf>,
+    #[clap(long)]
+    /// A file to write the virtual-to-physical page mappings observed, and their changes,
+    /// to as JSON at exit. New and changed mappings are also logged as they happen. System
+    /// mode only
+    pub translation_log: Option<PathBuf>,
+    #[clap(long)]
+    /// Whether memory both written and executed, or mapped writable and executable at once,
+    /// should be logged as a W^X violation
+    pub detect_wx: bool,
+    #[clap(long)]
+    /// A file to write each page which broke W^X to as JSON at exit, with a timeline of its
+    /// protections, writes and executions. Implies `--detect-wx`
+    pub wx_report: Option<PathBuf>,
+    #[clap(long)]
+    /// A directory to write a crash bundle to when the program reaches `abort`, `panic` or
+    /// another `--crash-trigger`, holding its registers, its stack and its last events
+    pub crash_dir: Option<PathBuf>,
+    #[clap(long, requires = "crash_dir")]
+    /// A function, by symbol or address, which triggers a crash bundle, replacing the
+    /// default functions. May be repeated
+    pub crash_trigger: Vec<String>,
+    #[clap(long, requires = "crash_dir")]
+    /// How many bytes of stack a crash bundle holds
+    pub crash_stack: Option<usize>,
+    #[clap(long, requires = "crash_dir")]
+    /// How many of the last events of the crashing vCPU a crash bundle holds
+    pub crash_window: Option<usize>,
+    #[clap(long, requires = "crash_dir")]
+    /// The maximum number of crash bundles to write
+    pub crash_limit: Option<usize>,
+    #[clap(long, requires = "crash_dir")]
+    /// Write a minidump of the crashing vCPU into each crash bundle
+    pub crash_minidump: bool,
+    #[clap(long)]
+    /// A file to log each callback the tracer registers on each translated block to, and
+    /// why, as JSON lines. Read it with the `decisions` binary
+    pub decision_log: Option<PathBuf>,
+    #[clap(long)]
+    /// A rule restricting instrumentation to ELF sections or segments, like `.text`,
+    /// `!.plt*`, `segment:1` or `.text@libfoo.so`. May be repeated. User mode only
+    pub section_filter: Vec<String>,
+    #[clap(long)]
+    /// Name PLT stubs and the functions bound to them after the functions they import, and
+    /// trace calls through them as calls to those functions. User mode only
+    pub resolve_plt: bool,
+    #[clap(long, requires = "inject_data")]
+    /// A path whose contents the program should read as the data of `--inject-data`, as the
+    /// program opens it, or `-` for stdin
+    pub inject_path: Option<String>,
+    #[clap(long, requires = "inject_path")]
+    /// A file of data to serve to the program's reads of `--inject-path`
+    pub inject_data: Option<PathBuf>,
+    #[clap(long)]
+    /// Log a hang when the program executes this many instructions in one iteration, i.e.
+    /// since it last reached a `--watchdog-reset` address, or since it started
+    pub watchdog_budget: Option<u64>,
+    #[clap(long, requires = "watchdog_budget")]
+    /// An address whose execution starts a new iteration, e.g. the top of a test loop. May
+    /// be repeated
+    pub watchdog_reset: Vec<String>,
+    #[clap(long, requires = "watchdog_budget")]
+    /// Exit with status 124 when the watchdog budget runs out, instead of only logging it
+    pub watchdog_exit: bool,
+    #[clap(long)]
+    /// A sequence point, e.g. the top of a message loop, at which to log a hash of the
+    /// program's state. May be repeated
+    pub state_point: Vec<String>,
+    #[clap(long)]
+    /// A region of memory to include in state hashes, as `address:length`. May be repeated
+    pub state_region: Vec<String>,
+    #[clap(long)]
+    /// A register to include in state hashes. May be repeated
+    pub state_register: Vec<String>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[clap(long, requires = "track_heap")]
+    /// Once the program exits, learn structure layouts from the memory accesses to each
+    /// allocation site in the trace, and write them to this file as C structures. Requires
+    /// an output file or a trace file to read the trace back from
+    pub struct_layouts: Option<PathBuf>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[clap(long, requires = "log_schedule")]
+    /// Once the program exits, write the scheduling timeline in the trace to this file as a
+    /// Chrome trace, which Perfetto can open. Requires an output file or a trace file to read
+    /// the trace back from
+    pub schedule_timeline: Option<PathBuf>,
+    #[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+    #[clap(long)]
+    /// A file of YARA rules to scan the program's memory with when a `--yara-pc` or
+    /// `--yara-syscall` trigger fires. Only a subset of the YARA language is supported
+    pub yara_rules: Option<PathBuf>,
+    #[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+    #[clap(long, requires = "yara_rules")]
+    /// A range of guest addresses to scan, as `start-end`. May be repeated
+    pub yara_range: Vec<String>,
+    #[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+    #[clap(long, requires = "yara_rules")]
+    /// Scan when the instruction at this address is executed. May be repeated
+    pub yara_pc: Vec<String>,
+    #[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+    #[clap(long, requires = "yara_rules")]
+    /// Scan when the program makes this syscall, which it can use as a hypercall
+    pub yara_syscall: Option<i64>,
     /// The program to run
     #[clap()]
     pub program: PathBuf,
@@ -112,39 +1017,549 @@ impl Args {
         #[cfg(feature = "plugin-api-v1")]
         {
             format!(
-                "log_insns={},log_mem={},log_syscalls={}",
+                "log_insns={},log_mem={},log_syscalls={},log_console={}",
                 self.log_insns | self.log_all,
                 self.log_mem | self.log_all,
                 self.log_syscalls | self.log_all,
+                self.log_console | self.log_all,
             )
         }
         #[cfg(not(feature = "plugin-api-v1"))]
         {
             format!(
-                "log_insns={},log_mem={},log_syscalls={},log_registers={}",
+                "log_insns={},log_mem={},log_syscalls={},log_registers={},log_console={}",
                 self.log_insns | self.log_all,
                 self.log_mem | self.log_all,
                 self.log_syscalls | self.log_all,
                 self.log_registers | self.log_all,
+                self.log_console | self.log_all,
             )
         }
     }
 
+    fn to_optional_args(&self) -> String {
+        let mut optional_args = String::new();
+
+        if self.check {
+            optional_args.push_str(",check=true");
+        }
+
+        if self.log_start || self.log_all {
+            optional_args.push_str(",log_start=true");
+        }
+
+        if self.log_pcs {
+            optional_args.push_str(",log_pcs=true");
+        }
+
+        if self.log_mem_values {
+            optional_args.push_str(",log_mem_values=true");
+        }
+
+        if let Some(coalesce_writes) = self.coalesce_writes.as_ref() {
+            optional_args.push_str(&format!(",coalesce_writes={coalesce_writes}"));
+        }
+
+        if let Some(symprof) = self.symprof.as_ref() {
+            optional_args.push_str(&format!(",symprof_report={}", symprof.display()));
+        }
+
+        if let Some(symprof_weights) = self.symprof_weights.as_ref() {
+            optional_args.push_str(&format!(",symprof_weights={symprof_weights}"));
+        }
+
+        if let Some(extension_report) = self.extension_report.as_ref() {
+            optional_args.push_str(&format!(",extension_report={}", extension_report.display()));
+        }
+
+        if let Some(source_report) = self.source_report.as_ref() {
+            optional_args.push_str(&format!(",source_report={}", source_report.display()));
+        }
+
+        if let Some(source_binary) = self.source_binary.as_ref() {
+            optional_args.push_str(&format!(",source_binary={}", source_binary.display()));
+        }
+
+        if let Some(demangle) = self.demangle.as_ref() {
+            optional_args.push_str(&format!(",demangle={demangle}"));
+        }
+
+        if self.symbolize_libraries {
+            optional_args.push_str(",symbolize_libraries=true");
+        }
+
+        if self.absolute_pcs || self.qemu_log.is_some() {
+            optional_args.push_str(",absolute_pcs=true");
+        }
+
+        if let Some(debuginfod_urls) = self.debuginfod_urls.as_ref() {
+            optional_args.push_str(&format!(",debuginfod_urls={debuginfod_urls}"));
+        }
+
+        if let Some(debuginfod_cache) = self.debuginfod_cache.as_ref() {
+            optional_args.push_str(&format!(",debuginfod_cache={}", debuginfod_cache.display()));
+        }
+
+        if let Some(stats_file) = self.stats_file.as_ref() {
+            optional_args.push_str(&format!(",stats_path={}", stats_file.display()));
+        }
+
+        if let Some(control_socket) = self.control_socket.as_ref() {
+            optional_args.push_str(&format!(",control_path={}", control_socket.display()));
+        }
+
+        if let Some(file_report) = self.file_report.as_ref() {
+            optional_args.push_str(&format!(
+                ",file_report={},hash_files={}",
+                file_report.display(),
+                self.hash_files
+            ));
+        }
+
+        if let Some(max_files) = self.max_files {
+            optional_args.push_str(&format!(",max_files={max_files}"));
+        }
+
+        if let Some(max_ranges) = self.max_ranges {
+            optional_args.push_str(&format!(",max_ranges={max_ranges}"));
+        }
+
+        if let Some(limit_policy) = self.limit_policy.as_ref() {
+            optional_args.push_str(&format!(",limit_policy={limit_policy}"));
+        }
+
+        if let Some(coverage) = self.coverage.as_ref() {
+            optional_args.push_str(&format!(",coverage_path={}", coverage.display()));
+        }
+
+        if let Some(drcov) = self.drcov.as_ref() {
+            optional_args.push_str(&format!(",drcov_path={}", drcov.display()));
+        }
+
+        if self.coverage_merge {
+            optional_args.push_str(",coverage_merge=true");
+        }
+
+        if let Some(coverage_baseline) = self.coverage_baseline.as_ref() {
+            optional_args.push_str(&format!(
+                ",coverage_baseline={}",
+                coverage_baseline.display()
+            ));
+        }
+
+        if let Some(block_identity) = self.block_identity.as_ref() {
+            optional_args.push_str(&format!(",block_identity={block_identity}"));
+        }
+
+        if let Some(trace_file) = self.trace_file.as_ref() {
+            optional_args.push_str(&format!(",trace_path={}", trace_file.display()));
+        }
+
+        if let Some(trace_shards) = self.trace_shards.as_ref() {
+            optional_args.push_str(&format!(",trace_shards={}", trace_shards.display()));
+        }
+
+        if !self.analysis_module.is_empty() {
+            optional_args.push_str(&format!(
+                ",analysis_modules={}",
+                self.analysis_module
+                    .iter()
+                    .map(|m| m.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(";")
+            ));
+
+            if self.analysis_only {
+                optional_args.push_str(",analysis_only=true");
+            }
+        }
+
+        if self.run_manifest {
+            optional_args.push_str(",run_manifest=true");
+
+            if let Some(run_manifest_path) = self.run_manifest_path.as_ref() {
+                optional_args.push_str(&format!(
+                    ",run_manifest_path={}",
+                    run_manifest_path.display()
+                ));
+            }
+
+            if !self.run_binary.is_empty() {
+                optional_args.push_str(&format!(
+                    ",run_binaries={}",
+                    self.run_binary
+                        .iter()
+                        .map(|b| b.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(";")
+                ));
+            }
+        }
+
+        if let Some(aggregator) = self.aggregator.as_ref() {
+            optional_args.push_str(&format!(",aggregator={aggregator}"));
+
+            if let Some(aggregator_name) = self.aggregator_name.as_ref() {
+                optional_args.push_str(&format!(",aggregator_name={aggregator_name}"));
+            }
+        }
+
+        if !self.bookmark.is_empty() {
+            optional_args.push_str(&format!(",bookmarks={}", self.bookmark.join(";")));
+        }
+
+        if let Some(bookmark_syscall) = self.bookmark_syscall {
+            optional_args.push_str(&format!(",bookmark_syscall={bookmark_syscall}"));
+        }
+
+        if self.guest_hypercalls {
+            optional_args.push_str(",guest_hypercalls=true");
+        }
+
+        if let Some(utilization_interval) = self.utilization_interval {
+            optional_args.push_str(&format!(",utilization_interval={utilization_interval}"));
+        }
+
+        if let Some(clock_interval) = self.clock_interval {
+            optional_args.push_str(&format!(",clock_interval={clock_interval}"));
+        }
+
+        if let Some(utilization_report) = self.utilization_report.as_ref() {
+            optional_args.push_str(&format!(
+                ",utilization_report={}",
+                utilization_report.display()
+            ));
+        }
+
+        if let Some(retranslation_report) = self.retranslation_report.as_ref() {
+            optional_args.push_str(&format!(
+                ",retranslation_report={}",
+                retranslation_report.display()
+            ));
+
+            if let Some(retranslation_top) = self.retranslation_top {
+                optional_args.push_str(&format!(",retranslation_top={retranslation_top}"));
+            }
+        }
+
+        #[cfg(feature = "object-store")]
+        if let Some(object_store) = self.object_store.as_ref() {
+            optional_args.push_str(&format!(",object_store_url={object_store}"));
+
+            if let Some(region) = self.object_store_region.as_ref() {
+                optional_args.push_str(&format!(",object_store_region={region}"));
+            }
+
+            if let Some(chunk_size) = self.object_store_chunk_size {
+                optional_args.push_str(&format!(",object_store_chunk_size={chunk_size}"));
+            }
+        }
+
+        if let Some(pcap) = self.pcap.as_ref() {
+            optional_args.push_str(&format!(",pcap_path={}", pcap.display()));
+        }
+
+        if let Some(fault_rules) = self.fault_rules.as_ref() {
+            optional_args.push_str(&format!(
+                ",fault_rules={},fault_seed={}",
+                fault_rules.display(),
+                self.seed.unwrap_or_default()
+            ));
+        }
+
+        if let Some(dump_dir) = self.dump_dir.as_ref() {
+            optional_args.push_str(&format!(
+                ",dump_dir={},dump_on_abort={}",
+                dump_dir.display(),
+                self.dump_on_abort
+            ));
+
+            if !self.dump_range.is_empty() {
+                optional_args.push_str(&format!(",dump_ranges={}", self.dump_range.join(";")));
+            }
+
+            if let Some(dump_pc) = self.dump_pc.as_ref() {
+                optional_args.push_str(&format!(",dump_pc={dump_pc}"));
+            }
+
+            if let Some(dump_syscall) = self.dump_syscall {
+                optional_args.push_str(&format!(",dump_syscall={dump_syscall}"));
+            }
+
+            if let Some(dump_limit) = self.dump_limit {
+                optional_args.push_str(&format!(",dump_limit={dump_limit}"));
+            }
+        }
+
+        if !self.throttle.is_empty() {
+            optional_args.push_str(&format!(",throttle={}", self.throttle.join(";")));
+        }
+
+        if let Some(dedup_limit) = self.dedup_limit {
+            optional_args.push_str(&format!(
+                ",dedup_limit={dedup_limit},dedup_per_class={}",
+                self.dedup_per_class
+            ));
+        }
+
+        if self.track_heap {
+            optional_args.push_str(",track_heap=true");
+
+            if !self.heap_function.is_empty() {
+                optional_args
+                    .push_str(&format!(",heap_functions={}", self.heap_function.join(";")));
+            }
+
+            if let Some(heap_depth) = self.heap_depth {
+                optional_args.push_str(&format!(",heap_depth={heap_depth}"));
+            }
+        }
+
+        if self.detect_rop {
+            optional_args.push_str(",detect_rop=true");
+
+            if let Some(rop_threshold) = self.rop_threshold {
+                optional_args.push_str(&format!(",rop_threshold={rop_threshold}"));
+            }
+
+            if let Some(rop_gadget_len) = self.rop_gadget_len {
+                optional_args.push_str(&format!(",rop_gadget_len={rop_gadget_len}"));
+            }
+        }
+
+        #[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+        if let Some(yara_rules) = self.yara_rules.as_ref() {
+            optional_args.push_str(&format!(
+                ",yara_rules={},yara_ranges={}",
+                yara_rules.display(),
+                self.yara_range.join(";")
+            ));
+
+            if !self.yara_pc.is_empty() {
+                optional_args.push_str(&format!(",yara_pcs={}", self.yara_pc.join(";")));
+            }
+
+            if let Some(yara_syscall) = self.yara_syscall {
+                optional_args.push_str(&format!(",yara_syscall={yara_syscall}"));
+            }
+        }
+
+        if let Some(signatures) = self.signatures.as_ref() {
+            optional_args.push_str(&format!(",signatures={}", signatures.display()));
+        }
+
+        if self.detect_crypto {
+            optional_args.push_str(",detect_crypto=true");
+
+            if let Some(crypto_threshold) = self.crypto_threshold {
+                optional_args.push_str(&format!(",crypto_threshold={crypto_threshold}"));
+            }
+        }
+
+        if self.log_strings {
+            optional_args.push_str(&format!(
+                ",log_strings=true,string_ranges={}",
+                self.string_range.join(";")
+            ));
+        }
+
+        if !self.entropy_probe.is_empty() {
+            optional_args.push_str(&format!(",entropy_probes={}", self.entropy_probe.join(";")));
+        }
+
+        if self.shadow_stack {
+            optional_args.push_str(&format!(
+                ",shadow_stack=true,shadow_stack_strict={}",
+                self.shadow_stack_strict
+            ));
+
+            if !self.shadow_stack_allow.is_empty() {
+                optional_args.push_str(&format!(
+                    ",shadow_stack_allow={}",
+                    self.shadow_stack_allow.join(";")
+                ));
+            }
+        }
+
+        if !self.api_profile.is_empty() {
+            optional_args.push_str(&format!(",api_profiles={}", self.api_profile.join(";")));
+
+            if !self.api_function.is_empty() {
+                optional_args.push_str(&format!(",api_functions={}", self.api_function.join(";")));
+            }
+        }
+
+        if let Some(lock_report) = self.lock_report.as_ref() {
+            optional_args.push_str(&format!(",lock_report={}", lock_report.display()));
+
+            if !self.lock_function.is_empty() {
+                optional_args
+                    .push_str(&format!(",lock_functions={}", self.lock_function.join(";")));
+            }
+
+            if let Some(lock_top) = self.lock_top {
+                optional_args.push_str(&format!(",lock_top={lock_top}"));
+            }
+        }
+
+        if let Some(exclusive_report) = self.exclusive_report.as_ref() {
+            optional_args.push_str(&format!(",exclusive_report={}", exclusive_report.display()));
+        }
+
+        if let Some(expectations) = self.expectations.as_ref() {
+            optional_args.push_str(&format!(",expectations={}", expectations.display()));
+        }
+
+        if let Some(expectation_report) = self.expectation_report.as_ref() {
+            optional_args.push_str(&format!(
+                ",expectation_report={}",
+                expectation_report.display()
+            ));
+        }
+
+        if let Some(translation_log) = self.translation_log.as_ref() {
+            optional_args.push_str(&format!(",translation_log={}", translation_log.display()));
+        }
+
+        if self.detect_wx {
+            optional_args.push_str(",detect_wx=true");
+        }
+
+        if let Some(wx_report) = self.wx_report.as_ref() {
+            optional_args.push_str(&format!(",wx_report={}", wx_report.display()));
+        }
+
+        if let Some(crash_dir) = self.crash_dir.as_ref() {
+            optional_args.push_str(&format!(",crash_dir={}", crash_dir.display()));
+
+            if !self.crash_trigger.is_empty() {
+                optional_args
+                    .push_str(&format!(",crash_triggers={}", self.crash_trigger.join(";")));
+            }
+
+            if let Some(crash_stack) = self.crash_stack {
+                optional_args.push_str(&format!(",crash_stack={crash_stack}"));
+            }
+
+            if let Some(crash_window) = self.crash_window {
+                optional_args.push_str(&format!(",crash_window={crash_window}"));
+            }
+
+            if let Some(crash_limit) = self.crash_limit {
+                optional_args.push_str(&format!(",crash_limit={crash_limit}"));
+            }
+
+            if self.crash_minidump {
+                optional_args.push_str(",crash_minidump=true");
+            }
+        }
+
+        if let Some(decision_log) = self.decision_log.as_ref() {
+            optional_args.push_str(&format!(",decision_log={}", decision_log.display()));
+        }
+
+        if !self.section_filter.is_empty() {
+            optional_args.push_str(&format!(
+                ",section_filter={}",
+                self.section_filter.join(";")
+            ));
+        }
+
+        if self.resolve_plt {
+            optional_args.push_str(",resolve_plt=true");
+        }
+
+        if self.detect_races {
+            optional_args.push_str(",detect_races=true");
+        }
+
+        if let Some(watchdog_budget) = self.watchdog_budget {
+            optional_args.push_str(&format!(
+                ",watchdog_budget={watchdog_budget},watchdog_exit={}",
+                self.watchdog_exit
+            ));
+
+            if !self.watchdog_reset.is_empty() {
+                optional_args.push_str(&format!(
+                    ",watchdog_resets={}",
+                    self.watchdog_reset.join(";")
+                ));
+            }
+        }
+
+        if !self.state_point.is_empty() {
+            optional_args.push_str(&format!(",state_points={}", self.state_point.join(";")));
+
+            if !self.state_region.is_empty() {
+                optional_args.push_str(&format!(",state_regions={}", self.state_region.join(";")));
+            }
+
+            if !self.state_register.is_empty() {
+                optional_args.push_str(&format!(
+                    ",state_registers={}",
+                    self.state_register.join(";")
+                ));
+            }
+        }
+
+        if let (Some(inject_path), Some(inject_data)) =
+            (self.inject_path.as_ref(), self.inject_data.as_ref())
+        {
+            optional_args.push_str(&format!(
+                ",inject_path={inject_path},inject_data={}",
+                inject_data.display()
+            ));
+        }
+
+        if self.log_schedule {
+            optional_args.push_str(",log_schedule=true");
+        }
+
+        if self.log_isa_modes {
+            optional_args.push_str(",log_isa_modes=true");
+        }
+
+        if self.log_random || self.log_all {
+            optional_args.push_str(&format!(",log_random=true,seeded={}", self.seed.is_some()));
+        }
+
+        optional_args
+    }
+
     fn to_qemu_args(&self, socket_path: &Path, plugin_path: &Path) -> Result<Vec<String>> {
-        let mut qemu_args = vec![
+        let mut qemu_args = Vec::new();
+
+        if let Some(seed) = self.seed {
+            qemu_args.extend(["-seed".to_string(), seed.to_string()]);
+        }
+
+        if let Some(qemu_log) = self.qemu_log.as_ref() {
+            qemu_args.extend([
+                "-d".to_string(),
+                "exec,in_asm,nochain".to_string(),
+                "-D".to_string(),
+                qemu_log
+                    .to_str()
+                    .ok_or_else(|| anyhow!("Failed to convert QEMU log path to string"))?
+                    .to_string(),
+            ]);
+        }
+
+        qemu_args.extend([
             "-plugin".to_string(),
             format!(
-                "{},{},socket_path={}",
+                "{},{},socket_path={}{}",
                 plugin_path.display(),
                 self.to_plugin_args(),
-                socket_path.display()
+                socket_path.display(),
+                self.to_optional_args()
             ),
             "--".to_string(),
             self.program
                 .to_str()
                 .ok_or_else(|| anyhow!("Failed to convert program path to string"))?
                 .to_string(),
-        ];
+        ]);
 
         qemu_args.extend(self.args.clone());
 
@@ -240,16 +1655,149 @@ where
     let it = Deserializer::from_reader(&mut stream).into_iter::<Event>();
 
     for event in it {
-        outfile_stream.write_all(to_string(&event?)?.as_bytes())?;
+        let line = match event? {
+            Event::Pcs(batch) => to_string(&json!({
+                "Pcs": {
+                    "vcpu_index": batch.vcpu_index,
+                    "pcs": batch.decode()?,
+                }
+            }))?,
+            event => to_string(&event)?,
+        };
+
+        outfile_stream.write_all(line.as_bytes())?;
+        outfile_stream.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+fn merge_shards<P>(trace_shards: &Path, outfile: Option<P>) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let mut outfile_stream = if let Some(outfile) = outfile.as_ref() {
+        Box::new(OpenOptions::new().create(true).append(true).open(outfile)?) as Box<dyn Write>
+    } else {
+        Box::new(stdout()) as Box<dyn Write>
+    };
+
+    for record in ShardedTraceFile::read_merged(trace_shards)? {
+        outfile_stream.write_all(to_string(&record)?.as_bytes())?;
         outfile_stream.write_all(b"\n")?;
     }
 
     Ok(())
 }
 
+/// Write a redacted copy of the trace file or sharded trace of a finished run to `path`
+fn redact_trace(args: &Args, path: &Path) -> Result<()> {
+    let policy = match args.redaction_policy.as_ref() {
+        Some(redaction_policy) => RedactionPolicy::load(redaction_policy)?,
+        None => RedactionPolicy::default(),
+    };
+
+    if let Some(trace_file) = args.trace_file.as_ref() {
+        policy.redact_trace_file(trace_file, path)?;
+    } else if let Some(trace_shards) = args.trace_shards.as_ref() {
+        policy.redact_shards(trace_shards, path)?;
+    }
+
+    Ok(())
+}
+
+/// Read back the trace of a finished run, from wherever the plugin or `listen` wrote it
+fn read_trace(args: &Args) -> Result<Vec<Event>> {
+    if let Some(trace_file) = args.trace_file.as_ref() {
+        let records = TraceReader::open(trace_file)?.records::<Event>()?;
+
+        if records.skipped > 0 {
+            eprintln!(
+                "Skipped {} events in {} not understood by this build",
+                records.skipped,
+                trace_file.display()
+            );
+        }
+
+        Ok(records.records)
+    } else if let Some(trace_shards) = args.trace_shards.as_ref() {
+        Ok(ShardedTraceFile::read_merged(trace_shards)?
+            .into_iter()
+            .map(|record| record.event)
+            .collect())
+    } else if let Some(output_file) = args.output_file.as_ref() {
+        BufReader::new(File::open(output_file)?)
+            .lines()
+            .filter_map(|line| match line {
+                // Pc batches are written decoded, and carry no memory accesses
+                Ok(line) if line.starts_with("{\"Pcs\"") => None,
+                Ok(line) => Some(serde_json::from_str(&line).map_err(Error::from)),
+                Err(e) => Some(Err(e.into())),
+            })
+            .collect()
+    } else {
+        Err(anyhow!("No trace to read back"))
+    }
+}
+
+#[cfg(feature = "plugin-api-v4")]
+fn write_struct_layouts(args: &Args, path: &Path) -> Result<()> {
+    let events = read_trace(args)?;
+    let layouts = LayoutLearner::from_events(&events).layouts();
+
+    std::fs::write(path, CStructs(layouts).to_string())?;
+
+    Ok(())
+}
+
+#[cfg(feature = "plugin-api-v4")]
+fn write_schedule_timeline(args: &Args, path: &Path) -> Result<()> {
+    let events = read_trace(args)?;
+    let slices = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Schedule(slice) => Some(slice.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    write_timeline(path, &slices, &events)
+}
+
 #[main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    #[allow(unused_mut)]
+    let mut args = Args::parse();
+    // The plugin's report of the expectations is needed to fail the run, so one is written
+    // to a temporary file if none was asked for
+    #[cfg(feature = "plugin-api-v4")]
+    let temporary_report = args.expectations.is_some() && args.expectation_report.is_none();
+
+    #[cfg(feature = "plugin-api-v4")]
+    if temporary_report {
+        args.expectation_report = Some(tmp("/tmp/qemu-", ".json"));
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    let analyzes = args.struct_layouts.is_some() || args.schedule_timeline.is_some();
+    #[cfg(not(feature = "plugin-api-v4"))]
+    let analyzes = false;
+
+    if (analyzes || args.annotations.is_some() || args.qemu_log_alignment.is_some())
+        && args.trace_file.is_none()
+        && args.trace_shards.is_none()
+        && args.output_file.is_none()
+    {
+        return Err(anyhow!(
+            "Analyzing the trace requires an output file or trace file"
+        ));
+    }
+
+    if args.redact.is_some() && args.trace_file.is_none() && args.trace_shards.is_none() {
+        return Err(anyhow!(
+            "Redacting the trace requires a trace file or trace shards"
+        ));
+    }
 
     let socket_path = tmp("/tmp/qemu-", ".sock");
     let plugin_path = tmp("/tmp/qemu-", ".so");
@@ -269,7 +1817,22 @@ async fn main() -> Result<()> {
     let listen_sock = UnixListener::bind(&socket_path)?;
 
     let qemu_args = args.to_qemu_args(&socket_path, &plugin_path)?;
-    let socket_task = spawn_blocking(move || listen(listen_sock, args.output_file.as_ref()));
+    let output_file = args.output_file.clone();
+    // Events go straight to trace files, or the plugin only checks itself, so it never connects
+    let direct = args.trace_file.is_some()
+        || args.trace_shards.is_some()
+        || args.aggregator.is_some()
+        || args.analysis_only
+        || args.check;
+    #[cfg(feature = "object-store")]
+    let direct = direct || args.object_store.is_some();
+    let socket_task = spawn_blocking(move || {
+        if direct {
+            return Ok(());
+        }
+
+        listen(listen_sock, output_file.as_ref())
+    });
     let qemu_task = spawn(async move { run(input, qemu_args).await });
     let (qemu_res, socket_res) = join!(socket_task, qemu_task);
 
@@ -279,5 +1842,52 @@ async fn main() -> Result<()> {
     qemu_res??;
     socket_res??;
 
+    #[cfg(feature = "plugin-api-v4")]
+    if let Some(expectation_report) = args.expectation_report.as_ref() {
+        let report = ExpectationReport::read(expectation_report)?;
+
+        if temporary_report {
+            remove_file(expectation_report).await?;
+        }
+
+        if !report.passed {
+            return Err(anyhow!(
+                "{} of {} expectations failed",
+                report.failures().count(),
+                report.results.len()
+            ));
+        }
+    }
+
+    if let (Some(trace_shards), true) = (args.trace_shards.as_ref(), args.merge_shards) {
+        merge_shards(trace_shards, args.output_file.as_ref())?;
+    }
+
+    if let Some(redact) = args.redact.as_ref() {
+        redact_trace(&args, redact)?;
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    if let Some(struct_layouts) = args.struct_layouts.as_ref() {
+        write_struct_layouts(&args, struct_layouts)?;
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    if let Some(schedule_timeline) = args.schedule_timeline.as_ref() {
+        write_schedule_timeline(&args, schedule_timeline)?;
+    }
+
+    if let Some(annotations) = args.annotations.as_ref() {
+        write_annotations(annotations, &read_trace(&args)?)?;
+    }
+
+    if let (Some(qemu_log), Some(alignment)) =
+        (args.qemu_log.as_ref(), args.qemu_log_alignment.as_ref())
+    {
+        let report = QemuLog::read(qemu_log)?.align(&read_trace(&args)?)?;
+
+        serde_json::to_writer_pretty(File::create(alignment)?, &report)?;
+    }
+
     Ok(())
 }
@@ -0,0 +1,202 @@
+//! Named bookmarks marking interesting moments in a trace, so that long traces can be jumped
+//! around by name
+//!
+//! A bookmark is logged when the guest reaches a pc given as `name@pc`, e.g.
+//! `parsed@0x4011a0`, when it makes the configured hypercall syscall with a pointer to a
+//! NUL-terminated name in its first argument and, optionally, a pointer to a note in its
+//! second, or when `bookmark <name> [note]` is written to the control socket.
+//!
+//! A trace's bookmarks are looked up by name with [`index`], written as JSON annotations by
+//! [`write_annotations`], and shown by Perfetto as instant markers on each vCPU's track of a
+//! Chrome trace built with [`chrome_markers`]. Instruction counts stand in for timestamps.
+
+use crate::{memmap::parse_addr, Event};
+use anyhow::{anyhow, Result};
+use qemu_plugin::{path::create_sink, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, path::Path, str::FromStr};
+use typed_builder::TypedBuilder;
+
+#[cfg(feature = "plugin-api-v4")]
+/// The most bytes of a name or note read from guest memory
+const MAX_LEN: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// What logged a bookmark
+pub enum BookmarkSource {
+    /// The guest reached a bookmarked pc
+    Pc,
+    /// The guest made the bookmark hypercall
+    Hypercall,
+    /// A command on the control socket
+    Control,
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// A named moment in the trace
+pub struct BookmarkEvent {
+    /// The vCPU which logged the bookmark, or 0 for bookmarks from the control socket
+    pub vcpu_index: VCPUIndex,
+    pub name: String,
+    #[builder(default)]
+    pub note: Option<String>,
+    #[builder(default)]
+    pub pc: Option<u64>,
+    pub source: BookmarkSource,
+    pub icount: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A bookmark logged whenever the guest executes an instruction
+pub struct PcBookmark {
+    pub name: String,
+    pub pc: u64,
+}
+
+impl FromStr for PcBookmark {
+    type Err = anyhow::Error;
+
+    /// Parse a bookmark written as `name@pc`
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, pc) = s
+            .rsplit_once('@')
+            .filter(|(name, _)| !name.trim().is_empty())
+            .ok_or_else(|| anyhow!("Invalid bookmark {s}, expected name@pc"))?;
+
+        Ok(Self {
+            name: name.trim().to_string(),
+            pc: parse_addr(pc)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// The bookmarks configured for a run
+pub struct Bookmarks {
+    pub pcs: Vec<PcBookmark>,
+    /// The syscall number the guest makes to log a bookmark
+    pub syscall: Option<i64>,
+}
+
+impl Bookmarks {
+    /// Parse a list of pc bookmarks separated by `;`
+    pub fn new(pcs: Option<&str>, syscall: Option<i64>) -> Result<Self> {
+        Ok(Self {
+            pcs: pcs
+                .unwrap_or_default()
+                .split(';')
+                .filter(|b| !b.trim().is_empty())
+                .map(str::parse)
+                .collect::<Result<_>>()?,
+            syscall,
+        })
+    }
+
+    /// The bookmarks logged when the instruction at `pc` executes
+    pub fn at(&self, pc: u64) -> impl Iterator<Item = &PcBookmark> {
+        self.pcs.iter().filter(move |b| b.pc == pc)
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Returns the bookmark a syscall logs, if it is the bookmark hypercall
+    pub fn on_syscall(
+        &self,
+        vcpu_index: VCPUIndex,
+        num: i64,
+        args: [u64; 8],
+        icount: u64,
+    ) -> Result<Option<BookmarkEvent>> {
+        use crate::guest::read_cstring;
+
+        if self.syscall != Some(num) {
+            return Ok(None);
+        }
+
+        let read = |address: u64| -> Result<String> {
+            Ok(String::from_utf8_lossy(&read_cstring(address, MAX_LEN)?).into_owned())
+        };
+
+        Ok(Some(
+            BookmarkEvent::builder()
+                .vcpu_index(vcpu_index)
+                .name(read(args[0])?)
+                .note((args[1] != 0).then(|| read(args[1])).transpose()?)
+                .source(BookmarkSource::Hypercall)
+                .icount(icount)
+                .build(),
+        ))
+    }
+}
+
+/// The bookmarks in a trace
+pub fn bookmarks(events: &[Event]) -> impl Iterator<Item = (usize, &BookmarkEvent)> {
+    events
+        .iter()
+        .enumerate()
+        .filter_map(|(i, event)| match event {
+            Event::Bookmark(bookmark) => Some((i, bookmark)),
+            _ => None,
+        })
+}
+
+/// The indices of the events of each bookmark in a trace, by name, in trace order
+pub fn index(events: &[Event]) -> BTreeMap<String, Vec<usize>> {
+    let mut index = BTreeMap::<String, Vec<usize>>::new();
+
+    for (i, bookmark) in bookmarks(events) {
+        index.entry(bookmark.name.clone()).or_default().push(i);
+    }
+
+    index
+}
+
+/// Write the bookmarks in a trace to `path` as a JSON array of annotations, each with the
+/// index of its event in the trace
+pub fn write_annotations<P>(path: P, events: &[Event]) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let annotations = bookmarks(events)
+        .map(|(i, bookmark)| {
+            json!({
+                "event": i,
+                "name": bookmark.name,
+                "note": bookmark.note,
+                "vcpu_index": bookmark.vcpu_index,
+                "pc": bookmark.pc,
+                "source": bookmark.source,
+                "icount": bookmark.icount,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::to_writer_pretty(create_sink(path)?, &annotations)?;
+
+    Ok(())
+}
+
+/// Chrome trace instant events marking each bookmark on its vCPU's track
+pub fn chrome_markers<'a, I>(bookmarks: I) -> Vec<Value>
+where
+    I: IntoIterator<Item = &'a BookmarkEvent>,
+{
+    bookmarks
+        .into_iter()
+        .map(|bookmark| {
+            json!({
+                "name": bookmark.name,
+                "cat": "bookmark",
+                "ph": "i",
+                "s": "t",
+                "pid": 0,
+                "tid": bookmark.vcpu_index,
+                "ts": bookmark.icount,
+                "args": {
+                    "note": bookmark.note,
+                    "pc": bookmark.pc.map(|pc| format!("{pc:#x}")),
+                },
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,307 @@
+//! A self-check of the plugin's environment, run at install instead of tracing when the
+//! plugin is loaded with `check=on`
+//!
+//! The check covers the plugin API version QEMU offers, the QEMU symbols the plugin calls,
+//! whether the plugin's arguments parse, whether each output path can be written and whether
+//! each address and filter list parses. Its report is printed as JSON through QEMU's log, and
+//! the plugin uninstalls itself once the first vCPU starts, so the guest runs untraced:
+//!
+//! ```sh
+//! qemu-x86_64 -plugin libtracer.so,check=on,trace_path=trace.jsonl,throttle=... /bin/true
+//! ```
+
+use crate::{
+    bookmarks::Bookmarks,
+    coverage::{BlockIdentity, CoverageSet},
+    demangle::DemanglePolicy,
+    filter::SectionFilter,
+    fuzz, memmap,
+    throttle::Throttle,
+    PluginArgs,
+};
+#[cfg(feature = "plugin-api-v4")]
+use crate::{crash::CrashTrigger, dump::DumpRange, probes::EntropyProbe, testing::ExpectationFile};
+use anyhow::{anyhow, Result};
+use qemu_plugin::{
+    compat::distro::{Compatibility, COMPILED_API_LEVEL},
+    install::{qemu_plugin_version, Args, Info},
+};
+use serde::Serialize;
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// QEMU's plugin API functions the tracer calls, which QEMU must export
+const SYMBOLS: &[&str] = &[
+    "qemu_plugin_register_vcpu_tb_trans_cb",
+    "qemu_plugin_register_vcpu_insn_exec_cb",
+    "qemu_plugin_register_vcpu_mem_cb",
+    "qemu_plugin_register_vcpu_syscall_cb",
+    "qemu_plugin_register_vcpu_syscall_ret_cb",
+    "qemu_plugin_register_atexit_cb",
+    "qemu_plugin_insn_disas",
+    "qemu_plugin_outs",
+    "qemu_plugin_uninstall",
+    "qemu_plugin_start_code",
+    "qemu_plugin_end_code",
+    "qemu_plugin_entry_code",
+    "qemu_plugin_path_to_binary",
+    #[cfg(not(feature = "plugin-api-v1"))]
+    "qemu_plugin_get_registers",
+    #[cfg(not(feature = "plugin-api-v1"))]
+    "qemu_plugin_read_register",
+    #[cfg(feature = "plugin-api-v4")]
+    "qemu_plugin_read_memory_vaddr",
+    #[cfg(feature = "plugin-api-v4")]
+    "qemu_plugin_mem_get_value",
+];
+
+#[derive(Clone, Debug, Serialize)]
+/// The outcome of one check
+pub struct Check {
+    /// What was checked: `api_version`, `symbols`, `arguments`, `output` or `filter`
+    pub kind: &'static str,
+    /// The plugin argument checked, if any
+    pub name: Option<String>,
+    pub ok: bool,
+    /// What was found, or why the check failed
+    pub detail: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Every check run, and whether all of them passed
+pub struct CheckReport {
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl CheckReport {
+    /// Check the environment the plugin was installed in, with the arguments it was given
+    pub fn run(args: &Args, info: &Info) -> Self {
+        let mut report = Self {
+            ok: true,
+            checks: Vec::new(),
+        };
+
+        report.push("api_version", None, api_version(info));
+        report.push("symbols", None, symbols());
+
+        match PluginArgs::try_from(args) {
+            Ok(plugin_args) => {
+                report.push("arguments", None, Ok(format!("{} given", args.raw.len())));
+
+                for (name, path) in outputs(&plugin_args) {
+                    report.push("output", Some(name), writable(path));
+                }
+
+                for (name, result) in filters(&plugin_args) {
+                    report.push("filter", Some(name), result.map(|()| "Parsed".to_string()));
+                }
+            }
+            Err(e) => report.push("arguments", None, Err(e)),
+        }
+
+        report
+    }
+
+    fn push(&mut self, kind: &'static str, name: Option<&str>, result: Result<String>) {
+        let (ok, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, e.to_string()),
+        };
+
+        self.ok &= ok;
+        self.checks.push(Check {
+            kind,
+            name: name.map(str::to_string),
+            ok,
+            detail,
+        });
+    }
+}
+
+/// Whether QEMU accepts the plugin API version the plugin was built for
+fn api_version(info: &Info) -> Result<String> {
+    let version = i64::from(qemu_plugin_version);
+    let (minimum, current) = (info.version.mininum, info.version.current);
+
+    if !(minimum..=current).contains(&version) {
+        return Err(anyhow!(
+            "Built for plugin API version {version}, but QEMU supports {minimum} to {current}"
+        ));
+    }
+
+    let quirks = Compatibility::from_info(info).quirks;
+
+    Ok(format!(
+        "Built for plugin API version {version} (level {COMPILED_API_LEVEL}), QEMU supports \
+         {minimum} to {current}, lacking {quirks:?}"
+    ))
+}
+
+/// Whether QEMU exports every API function the plugin calls
+fn symbols() -> Result<String> {
+    let missing = SYMBOLS
+        .iter()
+        .filter(|symbol| {
+            let name = CString::new(**symbol).expect("Symbol names have no NUL bytes");
+            // SAFETY: The name is a valid C string, and looking a symbol up has no side effects
+            unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) }.is_null()
+        })
+        .copied()
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        Ok(format!("All {} found", SYMBOLS.len()))
+    } else {
+        Err(anyhow!("Not exported by QEMU: {}", missing.join(", ")))
+    }
+}
+
+/// The files and directories the plugin writes, by argument
+fn outputs(args: &PluginArgs) -> Vec<(&'static str, &PathBuf)> {
+    [
+        ("stats_path", args.stats_path.as_ref()),
+        ("file_report", args.file_report.as_ref()),
+        ("pcap_path", args.pcap_path.as_ref()),
+        ("coverage_path", args.coverage_path.as_ref()),
+        ("drcov_path", args.drcov_path.as_ref()),
+        ("trace_path", args.trace_path.as_ref()),
+        ("trace_shards", args.trace_shards.as_ref()),
+        ("run_manifest_path", args.run_manifest_path.as_ref()),
+        ("utilization_report", args.utilization_report.as_ref()),
+        ("retranslation_report", args.retranslation_report.as_ref()),
+        ("fuzz_output", args.fuzz_output.as_ref()),
+        ("fuzz_corpus", args.fuzz_corpus.as_ref()),
+        ("dump_dir", args.dump_dir.as_ref()),
+        ("sample_dir", args.sample_dir.as_ref()),
+        ("lock_report", args.lock_report.as_ref()),
+        ("exclusive_report", args.exclusive_report.as_ref()),
+        ("expectation_report", args.expectation_report.as_ref()),
+        ("translation_log", args.translation_log.as_ref()),
+        ("wx_report", args.wx_report.as_ref()),
+        ("crash_dir", args.crash_dir.as_ref()),
+        ("decision_log", args.decision_log.as_ref()),
+        ("debuginfod_cache", args.debuginfod_cache.as_ref()),
+        ("source_report", args.source_report.as_ref()),
+        ("extension_report", args.extension_report.as_ref()),
+    ]
+    .into_iter()
+    .filter_map(|(name, path)| path.map(|path| (name, path)))
+    .collect()
+}
+
+/// Whether `path` can be written, or created if it does not exist
+fn writable(path: &Path) -> Result<String> {
+    let (target, detail) = if path.exists() {
+        (path, "Writable")
+    } else {
+        let parent = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        (parent, "Can be created")
+    };
+
+    let c_target = CString::new(target.as_os_str().as_bytes())?;
+
+    // SAFETY: The path is a valid C string
+    if unsafe { libc::access(c_target.as_ptr(), libc::W_OK) } == 0 {
+        Ok(format!("{detail}: {}", path.display()))
+    } else {
+        Err(anyhow!(
+            "{} is not writable: {}",
+            target.display(),
+            std::io::Error::last_os_error()
+        ))
+    }
+}
+
+/// The result of parsing each address and filter argument given, by argument
+fn filters(args: &PluginArgs) -> Vec<(&'static str, Result<()>)> {
+    let mut results = Vec::new();
+
+    if let Some(throttle) = args.throttle.as_deref() {
+        results.push(("throttle", Throttle::parse(throttle).map(drop)));
+    }
+
+    if let Some(bookmarks) = args.bookmarks.as_deref() {
+        results.push(("bookmarks", Bookmarks::new(Some(bookmarks), None).map(drop)));
+    }
+
+    if let Some(policy) = args.demangle.as_deref() {
+        results.push(("demangle", policy.parse::<DemanglePolicy>().map(drop)));
+    }
+
+    if let Some(baseline) = args.coverage_baseline.as_ref() {
+        results.push(("coverage_baseline", CoverageSet::read(baseline).map(drop)));
+    }
+
+    if let Some(filter) = args.section_filter.as_deref() {
+        results.push(("section_filter", SectionFilter::parse(filter).map(drop)));
+    }
+
+    if let Some(identity) = args.block_identity.as_deref() {
+        results.push((
+            "block_identity",
+            identity.parse::<BlockIdentity>().map(drop),
+        ));
+    }
+
+    for (name, addr) in [
+        ("kernel_base", args.kernel_base.as_deref()),
+        ("dump_pc", args.dump_pc.as_deref()),
+        ("fuzz_start", args.fuzz_start.as_deref()),
+        ("fuzz_end", args.fuzz_end.as_deref()),
+    ] {
+        if let Some(addr) = addr {
+            results.push((name, memmap::parse_addr(addr).map(drop)));
+        }
+    }
+
+    for (name, markers) in [
+        ("fuzz_crashes", args.fuzz_crashes.as_deref()),
+        ("watchdog_resets", args.watchdog_resets.as_deref()),
+        ("shadow_stack_allow", args.shadow_stack_allow.as_deref()),
+        ("yara_pcs", args.yara_pcs.as_deref()),
+    ] {
+        if let Some(markers) = markers {
+            results.push((name, fuzz::parse_markers(markers).map(drop)));
+        }
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    {
+        for (name, ranges) in [
+            ("dump_ranges", args.dump_ranges.as_deref()),
+            ("string_ranges", args.string_ranges.as_deref()),
+            ("yara_ranges", args.yara_ranges.as_deref()),
+        ] {
+            if let Some(ranges) = ranges {
+                results.push((name, DumpRange::parse_list(ranges).map(drop)));
+            }
+        }
+
+        if let Some(probes) = args.entropy_probes.as_deref() {
+            results.push(("entropy_probes", EntropyProbe::parse_list(probes).map(drop)));
+        }
+
+        if let Some(triggers) = args.crash_triggers.as_deref() {
+            results.push((
+                "crash_triggers",
+                CrashTrigger::parse_list(triggers).map(drop),
+            ));
+        }
+
+        if let Some(expectations) = args.expectations.as_ref() {
+            results.push((
+                "expectations",
+                ExpectationFile::load(expectations).map(drop),
+            ));
+        }
+    }
+
+    results
+}
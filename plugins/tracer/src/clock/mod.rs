@@ -0,0 +1,180 @@
+//! Pairs of guest instruction count and host time, for correlating a trace with host-side
+//! logs such as QEMU's own `-d` output
+//!
+//! With `clock_interval`, a [`ClockEvent`] is sent every interval pairing the instruction
+//! count events are timestamped with to the host's monotonic clock, which perf and ftrace
+//! timestamp with, and its wall clock, which QEMU's `-msg timestamp=on` logs with. A
+//! [`ClockMap`] built from a trace's clock events converts between the two timelines by
+//! interpolating between the nearest pairs, and extrapolating from the first or last two
+//! outside them. Instruction counts advance a block at a time, and not at all while every
+//! vCPU is idle, so an instruction count maps to the time its block started executing after
+//! the last pause.
+
+use crate::{log, Event};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    thread::{sleep, spawn},
+    time::Duration,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// The guest instruction count and the host clocks at one instant
+pub struct ClockEvent {
+    pub icount: u64,
+    /// Nanoseconds on the host's `CLOCK_MONOTONIC`
+    pub monotonic_ns: u64,
+    /// Nanoseconds since the Unix epoch on the host's `CLOCK_REALTIME`
+    pub realtime_ns: u64,
+}
+
+/// Read a host clock in nanoseconds
+fn clock_ns(clock: libc::clockid_t) -> Result<u64> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    // SAFETY: `time` is a valid timespec for the duration of the call
+    if unsafe { libc::clock_gettime(clock, &mut time) } != 0 {
+        return Err(anyhow!(
+            "Failed to read clock {clock}: {}",
+            io::Error::last_os_error()
+        ));
+    }
+
+    Ok(time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64)
+}
+
+impl ClockEvent {
+    /// Pair `icount` with the current host time
+    pub fn now(icount: u64) -> Result<Self> {
+        Ok(Self {
+            icount,
+            monotonic_ns: clock_ns(libc::CLOCK_MONOTONIC)?,
+            realtime_ns: clock_ns(libc::CLOCK_REALTIME)?,
+        })
+    }
+}
+
+/// Send a clock event every `interval`, from the instruction count `icount` returns.
+/// Sampling stops if `send` fails.
+pub fn sample_periodically<I, F>(interval: Duration, icount: I, send: F) -> Result<()>
+where
+    I: Fn() -> u64 + Send + 'static,
+    F: Fn(ClockEvent) -> Result<()> + Send + 'static,
+{
+    send(ClockEvent::now(icount())?)?;
+
+    spawn(move || loop {
+        sleep(interval);
+
+        if let Err(e) = ClockEvent::now(icount()).and_then(&send) {
+            log(format!("Failed to send clock event: {e}"));
+            return;
+        }
+    });
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A host clock recorded in clock events
+pub enum HostClock {
+    Monotonic,
+    Realtime,
+}
+
+impl HostClock {
+    fn ns(&self, event: &ClockEvent) -> u64 {
+        match self {
+            Self::Monotonic => event.monotonic_ns,
+            Self::Realtime => event.realtime_ns,
+        }
+    }
+}
+
+/// Interpolate the `y` of `x` along `points`, sorted by `x`, extrapolating from the first or
+/// last two points outside them
+fn interpolate(points: &[(u64, u64)], x: u64) -> Option<u64> {
+    let index = points.partition_point(|(px, _)| *px <= x);
+    let (a, b) = match (points.len(), index) {
+        (0, _) => return None,
+        (1, _) => return points.first().filter(|(px, _)| *px == x).map(|(_, y)| *y),
+        (_, 0) => (points[0], points[1]),
+        (len, index) if index == len => (points[len - 2], points[len - 1]),
+        (_, index) => (points[index - 1], points[index]),
+    };
+
+    if a.0 == b.0 {
+        return Some(b.1);
+    }
+
+    let y = a.1 as i128
+        + (x as i128 - a.0 as i128) * (b.1 as i128 - a.1 as i128) / (b.0 as i128 - a.0 as i128);
+
+    Some(y.clamp(0, u64::MAX as i128) as u64)
+}
+
+#[derive(Clone, Debug, Default)]
+/// Converts between guest instruction counts and host time, from a trace's clock events
+pub struct ClockMap {
+    /// The events in the order they were taken
+    events: Vec<ClockEvent>,
+}
+
+impl ClockMap {
+    pub fn new<I>(events: I) -> Self
+    where
+        I: IntoIterator<Item = ClockEvent>,
+    {
+        let mut events = events.into_iter().collect::<Vec<_>>();
+
+        events.sort_by_key(|event| event.monotonic_ns);
+
+        Self { events }
+    }
+
+    /// Build a map from the clock events among `events`
+    pub fn from_events<'a, I>(events: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Event>,
+    {
+        Self::new(events.into_iter().filter_map(|event| match event {
+            Event::Clock(clock) => Some(*clock),
+            _ => None,
+        }))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// The host time on `clock` at which the guest reached instruction count `icount`
+    pub fn host_ns(&self, icount: u64, clock: HostClock) -> Option<u64> {
+        let mut points = self
+            .events
+            .iter()
+            .map(|event| (event.icount, clock.ns(event)))
+            .collect::<Vec<_>>();
+
+        points.sort_unstable();
+
+        interpolate(&points, icount)
+    }
+
+    /// The guest instruction count at host time `ns` on `clock`
+    pub fn icount(&self, ns: u64, clock: HostClock) -> Option<u64> {
+        // The wall clock may have been stepped between events
+        let mut points = self
+            .events
+            .iter()
+            .map(|event| (clock.ns(event), event.icount))
+            .collect::<Vec<_>>();
+
+        points.sort_unstable();
+
+        interpolate(&points, ns)
+    }
+}
@@ -0,0 +1,222 @@
+//! Coalescing of runs of sequential stores by one instruction, such as those of `memset` and
+//! `memcpy` loops or `rep stos`, into one ranged event
+//!
+//! A store extends its vCPU's run when it is made by the same instruction with the same size,
+//! at the address just past the run's last store. Any other store ends the run, which is sent
+//! as a [`MemoryRangeEvent`], or as the [`MemoryEvent`] it holds if it has only one store.
+//! Loads pass through without ending a run, so a run is sent after the loads and other events
+//! made while it was open.
+//!
+//! In [`CoalesceMode::Lossless`] a store only extends a run if every field of its event
+//! follows on from the last store's, and the run keeps the value of each store, so that
+//! [`MemoryRangeEvent::expand`] rebuilds the events exactly. [`CoalesceMode::Lossy`] merges on
+//! guest addresses alone and keeps only the first store's event.
+
+use crate::{Event, MemoryEvent};
+use anyhow::{anyhow, Error, Result};
+use qemu_plugin::VCPUIndex;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
+use typed_builder::TypedBuilder;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// How much of each store a coalesced run keeps
+pub enum CoalesceMode {
+    #[default]
+    /// Merge only stores whose events can be rebuilt exactly from the run
+    Lossless,
+    /// Merge any sequential stores of the same size, keeping only the first store's event
+    Lossy,
+}
+
+impl FromStr for CoalesceMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lossless" => Ok(Self::Lossless),
+            "lossy" => Ok(Self::Lossy),
+            _ => Err(anyhow!("Unknown coalescing mode {s}")),
+        }
+    }
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// A run of `count` sequential stores by the instruction at `pc`, covering `count *
+/// first.size_bytes` bytes from `first.vaddr`
+pub struct MemoryRangeEvent {
+    pub pc: u64,
+    pub first: MemoryEvent,
+    pub count: u64,
+    pub mode: CoalesceMode,
+    /// The value of each store after the first, when values are logged and the run is
+    /// lossless
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    #[serde(default)]
+    pub values: Vec<u128>,
+}
+
+impl MemoryRangeEvent {
+    /// The guest addresses the run stored to
+    pub fn range(&self) -> std::ops::Range<u64> {
+        let start = self.first.vaddr;
+        start..start + self.count * self.first.size_bytes as u64
+    }
+
+    /// The store events the run merged. Exact for lossless runs, while the events of a lossy
+    /// run are the first store's, moved on as if the run were contiguous in host memory too
+    /// and without values.
+    pub fn expand(&self) -> impl Iterator<Item = MemoryEvent> + '_ {
+        (0..self.count).map(|i| {
+            #[allow(unused_mut)]
+            let mut event = advance(&self.first, i * self.first.size_bytes as u64);
+
+            #[cfg(feature = "plugin-api-v4")]
+            if i > 0 {
+                event.value = self.values.get(i as usize - 1).copied();
+            }
+
+            event
+        })
+    }
+}
+
+/// `event`, moved `bytes` further on in guest, host and allocation addresses
+fn advance(event: &MemoryEvent, bytes: u64) -> MemoryEvent {
+    let mut event = event.clone();
+    event.vaddr += bytes;
+    event.haddr = event.haddr.map(|haddr| haddr + bytes);
+
+    #[cfg(feature = "plugin-api-v4")]
+    if let Some(allocation) = event.allocation.as_mut() {
+        allocation.offset += bytes;
+    }
+
+    event
+}
+
+#[derive(Clone, Debug)]
+/// The stores a vCPU's run has merged so far
+struct Run {
+    pc: u64,
+    first: MemoryEvent,
+    last: MemoryEvent,
+    count: u64,
+    #[cfg(feature = "plugin-api-v4")]
+    values: Vec<u128>,
+}
+
+impl Run {
+    fn new(pc: u64, event: MemoryEvent) -> Self {
+        Self {
+            pc,
+            first: event.clone(),
+            last: event,
+            count: 1,
+            #[cfg(feature = "plugin-api-v4")]
+            values: Vec::new(),
+        }
+    }
+
+    /// Add `event` to the run if it follows on from the last store
+    fn extend(&mut self, mode: CoalesceMode, pc: u64, event: MemoryEvent) -> Option<MemoryEvent> {
+        let bytes = self.last.size_bytes as u64;
+
+        if pc != self.pc
+            || event.size_bytes != self.last.size_bytes
+            || event.vaddr != self.last.vaddr.wrapping_add(bytes)
+        {
+            return Some(event);
+        }
+
+        if mode == CoalesceMode::Lossless {
+            #[allow(unused_mut)]
+            let mut expected = advance(&self.last, bytes);
+
+            #[cfg(feature = "plugin-api-v4")]
+            {
+                expected.value = event.value;
+            }
+
+            if expected != event {
+                return Some(event);
+            }
+
+            #[cfg(feature = "plugin-api-v4")]
+            if let Some(value) = event.value {
+                self.values.push(value);
+            }
+        }
+
+        self.last = event;
+        self.count += 1;
+        None
+    }
+
+    fn into_event(self, mode: CoalesceMode) -> Event {
+        if self.count == 1 {
+            return Event::Memory(self.first);
+        }
+
+        Event::MemoryRange(MemoryRangeEvent {
+            pc: self.pc,
+            first: self.first,
+            count: self.count,
+            mode,
+            #[cfg(feature = "plugin-api-v4")]
+            values: self.values,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// The open run of stores of each vCPU
+pub struct WriteCoalescer {
+    mode: CoalesceMode,
+    runs: HashMap<VCPUIndex, Run>,
+}
+
+impl WriteCoalescer {
+    pub fn new(mode: CoalesceMode) -> Self {
+        Self {
+            mode,
+            runs: HashMap::new(),
+        }
+    }
+
+    /// Add a memory access by the instruction at `pc`, returning the event to send now, if
+    /// any: the access itself if it is a load, or the run it ended if it is a store
+    pub fn push(&mut self, vcpu_index: VCPUIndex, pc: u64, event: MemoryEvent) -> Option<Event> {
+        if !event.is_store {
+            return Some(Event::Memory(event));
+        }
+
+        let Some(run) = self.runs.get_mut(&vcpu_index) else {
+            self.runs.insert(vcpu_index, Run::new(pc, event));
+            return None;
+        };
+
+        let event = run.extend(self.mode, pc, event)?;
+        let ended = std::mem::replace(run, Run::new(pc, event));
+
+        Some(ended.into_event(self.mode))
+    }
+
+    /// End the open run of a vCPU, returning its event
+    pub fn flush(&mut self, vcpu_index: VCPUIndex) -> Option<Event> {
+        self.runs
+            .remove(&vcpu_index)
+            .map(|run| run.into_event(self.mode))
+    }
+
+    /// End every open run, returning their events by vCPU
+    pub fn drain(&mut self) -> Vec<(VCPUIndex, Event)> {
+        let mode = self.mode;
+
+        self.runs
+            .drain()
+            .map(|(vcpu_index, run)| (vcpu_index, run.into_event(mode)))
+            .collect()
+    }
+}
@@ -0,0 +1,476 @@
+//! Block and edge coverage, stored as compressed Roaring bitmaps keyed by module-relative
+//! offsets
+//!
+//! With `block_identity=content`, blocks are instead keyed by their offset together with a
+//! hash of their code, so that coverage of a rebuilt program only merges with earlier coverage
+//! for blocks whose code is unchanged at the same offset, as when only data or unrelated
+//! sections changed. Edges remain keyed by offsets.
+//!
+//! Coverage files are replaced atomically, by writing a temporary file beside them and
+//! renaming it over them, so a reader never sees a partly written set. With `coverage_merge`,
+//! the set is instead merged into the one already in the file, under an exclusive `flock` of
+//! `<file>.lock`, so that many runs, such as the jobs of a CI matrix, can accumulate their
+//! coverage in one shared file.
+//!
+//! Given a baseline coverage file with `coverage_baseline`, a coverage event is sent as soon
+//! as a block or edge is covered which neither the baseline nor the run so far covered, so
+//! that an interactive user sees when their input reaches unexplored code.
+
+use anyhow::{anyhow, Error, Result};
+use qemu_plugin::{path::create_sink, VCPUIndex};
+use roaring::RoaringTreemap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    fs::{rename, File, OpenOptions},
+    io::{self, BufReader, BufWriter, ErrorKind, Write},
+    os::fd::AsRawFd,
+    path::Path,
+    process,
+    str::FromStr,
+};
+
+/// The module name used for addresses outside any known module, whose offsets are absolute
+pub const UNKNOWN_MODULE: &str = "[unknown]";
+
+/// Pack an edge between two module-relative offsets into a single key. Offsets are truncated
+/// to 32 bits, which is lossless for modules smaller than 4GiB.
+pub fn edge_key(from: u64, to: u64) -> u64 {
+    ((from as u32 as u64) << 32) | to as u32 as u64
+}
+
+/// Unpack an edge key produced by [`edge_key`]
+pub fn edge_offsets(key: u64) -> (u64, u64) {
+    (key >> 32, key & 0xffff_ffff)
+}
+
+/// Pack a block's module-relative offset and a hash of its code into a single key. Both are
+/// truncated to 32 bits.
+pub fn block_key(offset: u64, hash: u64) -> u64 {
+    ((offset as u32 as u64) << 32) | hash as u32 as u64
+}
+
+/// Unpack a block key produced by [`block_key`] into its offset and truncated hash
+pub fn block_parts(key: u64) -> (u64, u64) {
+    (key >> 32, key & 0xffff_ffff)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// What identifies a block across runs
+pub enum BlockIdentity {
+    #[default]
+    /// The block's offset in its module
+    Offset,
+    /// The block's offset in its module and a hash of its code
+    Content,
+}
+
+impl FromStr for BlockIdentity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "offset" => Ok(Self::Offset),
+            "content" => Ok(Self::Content),
+            _ => Err(anyhow!(
+                "Unknown block identity {s}, expected offset or content"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A block identified by its module, its offset in the module and a hash of its code
+pub struct BlockId {
+    pub module: String,
+    pub offset: u64,
+    pub hash: u64,
+}
+
+impl BlockId {
+    /// Identify the block at `vaddr` whose code hashes to `hash`
+    pub fn new(modules: &[Module], vaddr: u64, hash: u64) -> Self {
+        match modules.iter().find(|m| (m.start..m.end).contains(&vaddr)) {
+            Some(module) => Self {
+                module: module.name.clone(),
+                offset: vaddr - module.start,
+                hash,
+            },
+            None => Self {
+                module: UNKNOWN_MODULE.to_string(),
+                offset: vaddr,
+                hash,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// Coverage reached for the first time, beyond the baseline
+pub struct CoverageEvent {
+    pub vcpu_index: VCPUIndex,
+    pub vaddr: u64,
+    pub module: String,
+    pub offset: u64,
+    /// Whether the block itself is new
+    pub block: bool,
+    /// The offset of the previous block, if the edge from it is new
+    pub edge_from: Option<u64>,
+    pub icount: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+/// Sets of covered blocks and edges, per module
+pub struct CoverageSet {
+    pub blocks: BTreeMap<String, RoaringTreemap>,
+    pub edges: BTreeMap<String, RoaringTreemap>,
+    /// Points reached which the guest identified by number with coverage hint hypercalls
+    #[serde(default)]
+    pub hints: RoaringTreemap,
+    /// How blocks are keyed
+    #[serde(default)]
+    pub identity: BlockIdentity,
+}
+
+impl CoverageSet {
+    /// Add a block at `offset` in `module`, returning whether it was newly covered
+    pub fn add_block(&mut self, module: &str, offset: u64) -> bool {
+        self.blocks
+            .entry(module.to_string())
+            .or_default()
+            .insert(offset)
+    }
+
+    /// Add an edge between two blocks in `module`, returning whether it was newly covered
+    pub fn add_edge(&mut self, module: &str, from: u64, to: u64) -> bool {
+        self.edges
+            .entry(module.to_string())
+            .or_default()
+            .insert(edge_key(from, to))
+    }
+
+    /// Whether the block keyed `key` in `module` is covered
+    pub fn contains_block(&self, module: &str, key: u64) -> bool {
+        self.blocks.get(module).is_some_and(|b| b.contains(key))
+    }
+
+    /// Whether the edge between two blocks in `module` is covered
+    pub fn contains_edge(&self, module: &str, from: u64, to: u64) -> bool {
+        self.edges
+            .get(module)
+            .is_some_and(|e| e.contains(edge_key(from, to)))
+    }
+
+    /// Add a coverage hint, returning whether it was newly covered
+    pub fn add_hint(&mut self, id: u64) -> bool {
+        self.hints.insert(id)
+    }
+
+    /// Add all blocks, edges and hints covered by `other`. Fails if its blocks are keyed
+    /// differently, unless this set is empty.
+    pub fn merge(&mut self, other: &Self) -> Result<()> {
+        if self.identity != other.identity {
+            if !self.blocks.is_empty() || !self.edges.is_empty() || !self.hints.is_empty() {
+                return Err(anyhow!(
+                    "Cannot merge coverage with {:?} block identity into {:?}",
+                    other.identity,
+                    self.identity
+                ));
+            }
+
+            self.identity = other.identity;
+        }
+
+        for (module, blocks) in &other.blocks {
+            *self.blocks.entry(module.clone()).or_default() |= blocks;
+        }
+
+        for (module, edges) in &other.edges {
+            *self.edges.entry(module.clone()).or_default() |= edges;
+        }
+
+        self.hints |= &other.hints;
+
+        Ok(())
+    }
+
+    /// The number of blocks covered across all modules
+    pub fn block_count(&self) -> u64 {
+        self.blocks.values().map(|b| b.len()).sum()
+    }
+
+    /// The number of edges covered across all modules
+    pub fn edge_count(&self) -> u64 {
+        self.edges.values().map(|e| e.len()).sum()
+    }
+
+    /// Read a coverage set written by [`CoverageSet::write`]
+    pub fn read<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(serde_cbor::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// Write the coverage set to `path` as CBOR, replacing the file atomically if it is a
+    /// regular file or does not exist yet
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        if path.metadata().is_ok_and(|m| !m.is_file()) {
+            return Ok(serde_cbor::to_writer(
+                BufWriter::new(create_sink(path)?),
+                self,
+            )?);
+        }
+
+        let mut name = OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(format!(".{}.tmp", process::id()));
+
+        let temporary = path.with_file_name(name);
+        let mut writer = BufWriter::new(create_sink(&temporary)?);
+
+        serde_cbor::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        rename(&temporary, path)?;
+
+        Ok(())
+    }
+
+    /// Merge the coverage set into the one in the file at `path`, if there is one, holding an
+    /// exclusive lock on `<path>.lock` so that concurrent merges into the file are serialized
+    pub fn merge_into<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let mut lock_path = path.as_os_str().to_os_string();
+        lock_path.push(".lock");
+
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)?;
+
+        // SAFETY: The descriptor is open for as long as `lock`, and the lock is released when
+        // it is closed
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(anyhow!(
+                "Failed to lock {}: {}",
+                Path::new(&lock_path).display(),
+                io::Error::last_os_error()
+            ));
+        }
+
+        let mut merged = match Self::read(path) {
+            Ok(existing) => existing,
+            Err(e)
+                if e.downcast_ref::<io::Error>()
+                    .is_some_and(|e| e.kind() == ErrorKind::NotFound) =>
+            {
+                Self::default()
+            }
+            Err(e) => return Err(anyhow!("Failed to read {}: {e}", path.display())),
+        };
+
+        merged.merge(self)?;
+        merged.write(path)
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A named range of guest addresses which coverage offsets are relative to
+pub struct Module {
+    pub name: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+/// Accumulates a `CoverageSet` from executed blocks
+pub struct CoverageTracker {
+    modules: Vec<Module>,
+    previous: HashMap<VCPUIndex, (usize, u64)>,
+    coverage: CoverageSet,
+    /// The coverage which reaching does not send an event, if new coverage is reported
+    baseline: Option<CoverageSet>,
+    /// The size of each block by module index and offset, which the `CoverageSet` does not
+    /// keep, when recorded for drcov output
+    sizes: Option<HashMap<(usize, u64), u16>>,
+}
+
+impl CoverageTracker {
+    pub fn new(modules: Vec<Module>) -> Self {
+        Self {
+            modules,
+            ..Default::default()
+        }
+    }
+
+    /// Key blocks by `identity`
+    pub fn with_identity(mut self, identity: BlockIdentity) -> Self {
+        self.coverage.identity = identity;
+        self
+    }
+
+    /// Report coverage not in `baseline` as it is reached. Fails if the baseline's blocks are
+    /// keyed differently.
+    pub fn with_baseline(mut self, baseline: CoverageSet) -> Result<Self> {
+        let empty = baseline.blocks.is_empty() && baseline.edges.is_empty();
+
+        if baseline.identity != self.coverage.identity && !empty {
+            return Err(anyhow!(
+                "The baseline has {:?} block identity, but coverage has {:?}",
+                baseline.identity,
+                self.coverage.identity
+            ));
+        }
+
+        self.baseline = Some(baseline);
+        Ok(self)
+    }
+
+    pub fn identity(&self) -> BlockIdentity {
+        self.coverage.identity
+    }
+
+    /// Also record the size of each block, which [`CoverageTracker::write_drcov`] requires
+    pub fn with_sizes(mut self) -> Self {
+        self.sizes = Some(HashMap::new());
+        self
+    }
+
+    /// Returns the index of the module containing `vaddr` and the offset into it. Addresses
+    /// outside any module have index `modules.len()`.
+    fn locate(&self, vaddr: u64) -> (usize, u64) {
+        self.modules
+            .iter()
+            .position(|m| (m.start..m.end).contains(&vaddr))
+            .map(|i| (i, vaddr - self.modules[i].start))
+            .unwrap_or((self.modules.len(), vaddr))
+    }
+
+    fn module_name(&self, index: usize) -> &str {
+        self.modules
+            .get(index)
+            .map(|m| m.name.as_str())
+            .unwrap_or(UNKNOWN_MODULE)
+    }
+
+    /// Record the execution of the `size` byte block at `vaddr` whose code hashes to `hash`,
+    /// and the edge from the block `vcpu_index` previously executed if both are in the same
+    /// module. The hash is only used with content identity. Returns the new coverage if there
+    /// is a baseline and the block or edge is in neither it nor the coverage so far.
+    pub fn on_block(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        vaddr: u64,
+        size: usize,
+        hash: u64,
+        icount: u64,
+    ) -> Option<CoverageEvent> {
+        let (module, offset) = self.locate(vaddr);
+        let name = self.module_name(module).to_string();
+        let key = match self.coverage.identity {
+            BlockIdentity::Offset => offset,
+            BlockIdentity::Content => block_key(offset, hash),
+        };
+
+        let new_block = self.coverage.add_block(&name, key);
+
+        if new_block {
+            if let Some(sizes) = self.sizes.as_mut() {
+                sizes.insert((module, offset), size.min(u16::MAX as usize) as u16);
+            }
+        }
+
+        let mut new_edge = None;
+
+        if let Some((previous_module, previous_offset)) =
+            self.previous.insert(vcpu_index, (module, offset))
+        {
+            if previous_module == module && self.coverage.add_edge(&name, previous_offset, offset) {
+                new_edge = Some(previous_offset);
+            }
+        }
+
+        let baseline = self.baseline.as_ref()?;
+        let block = new_block && !baseline.contains_block(&name, key);
+        let edge_from = new_edge.filter(|from| !baseline.contains_edge(&name, *from, offset));
+
+        (block || edge_from.is_some()).then_some(CoverageEvent {
+            vcpu_index,
+            vaddr,
+            module: name,
+            offset,
+            block,
+            edge_from,
+            icount,
+        })
+    }
+
+    /// Record a coverage hint from the guest, returning whether it was newly covered
+    pub fn on_hint(&mut self, id: u64) -> bool {
+        self.coverage.add_hint(id)
+    }
+
+    pub fn coverage(&self) -> &CoverageSet {
+        &self.coverage
+    }
+    /// Write the blocks covered in known modules to `path` in DynamoRIO's drcov format, which
+    /// coverage tools such as Lighthouse and bncov load. Blocks outside any module, edges and
+    /// hints have no drcov representation and are left out.
+    pub fn write_drcov<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let sizes = self
+            .sizes
+            .as_ref()
+            .ok_or_else(|| anyhow!("Block sizes were not recorded for drcov output"))?;
+        let mut blocks = sizes
+            .iter()
+            .filter(|((module, offset), _)| {
+                *module < self.modules.len() && *offset <= u32::MAX as u64
+            })
+            .map(|(&(module, offset), &size)| (module as u16, offset as u32, size))
+            .collect::<Vec<_>>();
+        blocks.sort_unstable();
+
+        let mut writer = BufWriter::new(create_sink(path)?);
+
+        writeln!(writer, "DRCOV VERSION: 2")?;
+        writeln!(writer, "DRCOV FLAVOR: drcov")?;
+        writeln!(
+            writer,
+            "Module Table: version 2, count {}",
+            self.modules.len()
+        )?;
+        writeln!(writer, "Columns: id, base, end, entry, path")?;
+
+        for (id, module) in self.modules.iter().enumerate() {
+            writeln!(
+                writer,
+                "{id}, {:#018x}, {:#018x}, {:#018x}, {}",
+                module.start, module.end, 0, module.name
+            )?;
+        }
+
+        writeln!(writer, "BB Table: {} bbs", blocks.len())?;
+
+        for (module, offset, size) in blocks {
+            writer.write_all(&offset.to_le_bytes())?;
+            writer.write_all(&size.to_le_bytes())?;
+            writer.write_all(&module.to_le_bytes())?;
+        }
+
+        Ok(writer.flush()?)
+    }
+}
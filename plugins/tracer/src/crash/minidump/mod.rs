@@ -0,0 +1,365 @@
+//! Minidumps of crash bundles, for triage with Breakpad, Crashpad and `rust-minidump` tools
+//!
+//! A minidump holds the crashing vCPU as its only thread, with its integer, control and
+//! segment registers as the thread's context, its stack, and the code around the crashing
+//! block. The other vCPUs' registers cannot be read from the crashing vCPU, so they are left
+//! out. The modules the relocator knows of are listed by name and address, without the
+//! CodeView records tools look symbols up by, so symbols are found by module name. The
+//! exception is recorded as a `SIGABRT` at the crashing block, since the functions which
+//! trigger a bundle are those a failing program calls on its way to aborting.
+
+use crate::{arch::Arch, aslr::LoadedModule, crash::CrashReport};
+use anyhow::{anyhow, Result};
+use std::{
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SIGNATURE: u32 = 0x504d_444d;
+const VERSION: u32 = 0xa793;
+
+const THREAD_LIST_STREAM: u32 = 3;
+const MODULE_LIST_STREAM: u32 = 4;
+const MEMORY_LIST_STREAM: u32 = 5;
+const EXCEPTION_STREAM: u32 = 6;
+const SYSTEM_INFO_STREAM: u32 = 7;
+
+/// The platform ID Breakpad gives Linux
+const PLATFORM_LINUX: u32 = 0x8201;
+/// The exception code Breakpad gives a Linux `SIGABRT`
+const EXCEPTION_SIGABRT: u32 = 6;
+
+/// How a `CONTEXT` structure is laid out for an architecture
+struct ContextLayout {
+    processor_architecture: u16,
+    size: usize,
+    flags: u32,
+    flags_offset: usize,
+    /// Each register's QEMU name, offset and size
+    registers: &'static [(&'static str, usize, usize)],
+}
+
+impl ContextLayout {
+    /// The layout for `arch`, for the architectures minidump tools understand
+    fn for_arch(arch: Arch) -> Result<Self> {
+        Ok(match arch {
+            Arch::X86_64 => Self {
+                processor_architecture: 9,
+                size: 1232,
+                // CONTEXT_AMD64 with its control, integer and segment registers
+                flags: 0x0010_0007,
+                flags_offset: 48,
+                registers: &[
+                    ("cs", 56, 2),
+                    ("ds", 58, 2),
+                    ("es", 60, 2),
+                    ("fs", 62, 2),
+                    ("gs", 64, 2),
+                    ("ss", 66, 2),
+                    ("eflags", 68, 4),
+                    ("rax", 120, 8),
+                    ("rcx", 128, 8),
+                    ("rdx", 136, 8),
+                    ("rbx", 144, 8),
+                    ("rsp", 152, 8),
+                    ("rbp", 160, 8),
+                    ("rsi", 168, 8),
+                    ("rdi", 176, 8),
+                    ("r8", 184, 8),
+                    ("r9", 192, 8),
+                    ("r10", 200, 8),
+                    ("r11", 208, 8),
+                    ("r12", 216, 8),
+                    ("r13", 224, 8),
+                    ("r14", 232, 8),
+                    ("r15", 240, 8),
+                    ("rip", 248, 8),
+                ],
+            },
+            Arch::I386 => Self {
+                processor_architecture: 0,
+                size: 716,
+                // CONTEXT_X86 with its control, integer and segment registers
+                flags: 0x0001_0007,
+                flags_offset: 0,
+                registers: &[
+                    ("gs", 140, 4),
+                    ("fs", 144, 4),
+                    ("es", 148, 4),
+                    ("ds", 152, 4),
+                    ("edi", 156, 4),
+                    ("esi", 160, 4),
+                    ("ebx", 164, 4),
+                    ("edx", 168, 4),
+                    ("ecx", 172, 4),
+                    ("eax", 176, 4),
+                    ("ebp", 180, 4),
+                    ("eip", 184, 4),
+                    ("cs", 188, 4),
+                    ("eflags", 192, 4),
+                    ("esp", 196, 4),
+                    ("ss", 200, 4),
+                ],
+            },
+            Arch::Aarch64 => Self {
+                processor_architecture: 12,
+                size: 912,
+                // CONTEXT_ARM64 with its control and integer registers
+                flags: 0x0040_0003,
+                flags_offset: 0,
+                registers: &[
+                    ("cpsr", 4, 4),
+                    ("x0", 8, 8),
+                    ("x1", 16, 8),
+                    ("x2", 24, 8),
+                    ("x3", 32, 8),
+                    ("x4", 40, 8),
+                    ("x5", 48, 8),
+                    ("x6", 56, 8),
+                    ("x7", 64, 8),
+                    ("x8", 72, 8),
+                    ("x9", 80, 8),
+                    ("x10", 88, 8),
+                    ("x11", 96, 8),
+                    ("x12", 104, 8),
+                    ("x13", 112, 8),
+                    ("x14", 120, 8),
+                    ("x15", 128, 8),
+                    ("x16", 136, 8),
+                    ("x17", 144, 8),
+                    ("x18", 152, 8),
+                    ("x19", 160, 8),
+                    ("x20", 168, 8),
+                    ("x21", 176, 8),
+                    ("x22", 184, 8),
+                    ("x23", 192, 8),
+                    ("x24", 200, 8),
+                    ("x25", 208, 8),
+                    ("x26", 216, 8),
+                    ("x27", 224, 8),
+                    ("x28", 232, 8),
+                    ("x29", 240, 8),
+                    ("x30", 248, 8),
+                    ("sp", 256, 8),
+                    ("pc", 264, 8),
+                ],
+            },
+            Arch::Arm => Self {
+                processor_architecture: 5,
+                size: 368,
+                // MD_CONTEXT_ARM with its integer registers
+                flags: 0x4000_0002,
+                flags_offset: 0,
+                registers: &[
+                    ("r0", 4, 4),
+                    ("r1", 8, 4),
+                    ("r2", 12, 4),
+                    ("r3", 16, 4),
+                    ("r4", 20, 4),
+                    ("r5", 24, 4),
+                    ("r6", 28, 4),
+                    ("r7", 32, 4),
+                    ("r8", 36, 4),
+                    ("r9", 40, 4),
+                    ("r10", 44, 4),
+                    ("r11", 48, 4),
+                    ("r12", 52, 4),
+                    ("sp", 56, 4),
+                    ("lr", 60, 4),
+                    ("pc", 64, 4),
+                    ("cpsr", 68, 4),
+                ],
+            },
+            _ => return Err(anyhow!("Minidumps are not supported for {arch:?}")),
+        })
+    }
+
+    /// Build the context from register values
+    fn context(&self, report: &CrashReport) -> Vec<u8> {
+        let mut context = vec![0u8; self.size];
+
+        context[self.flags_offset..self.flags_offset + 4]
+            .copy_from_slice(&self.flags.to_le_bytes());
+
+        for (name, offset, size) in self.registers {
+            let Some(value) = report.registers.get(*name) else {
+                continue;
+            };
+
+            let len = value.len().min(*size);
+            context[*offset..*offset + len].copy_from_slice(&value[..len]);
+        }
+
+        context
+    }
+}
+
+/// Check minidumps can be written for `arch`
+pub fn supported(arch: Arch) -> Result<()> {
+    ContextLayout::for_arch(arch).map(drop)
+}
+
+/// A minidump being built, as the bytes of the file with a location for each appended item
+#[derive(Default)]
+struct Builder {
+    data: Vec<u8>,
+}
+
+impl Builder {
+    /// Append `bytes` at the next 8-byte boundary, returning their location as size and RVA
+    fn push(&mut self, bytes: &[u8]) -> [u8; 8] {
+        self.data.resize(self.data.len().next_multiple_of(8), 0);
+
+        let rva = self.data.len() as u32;
+
+        self.data.extend(bytes);
+
+        location(bytes.len() as u32, rva)
+    }
+
+    /// Append a `MINIDUMP_STRING`, returning its RVA
+    fn push_string(&mut self, s: &str) -> u32 {
+        let utf16 = s
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        let mut string = (utf16.len() as u32).to_le_bytes().to_vec();
+
+        string.extend(utf16);
+        string.extend([0, 0]);
+
+        rva(self.push(&string))
+    }
+}
+
+fn location(size: u32, rva: u32) -> [u8; 8] {
+    let mut location = [0; 8];
+
+    location[..4].copy_from_slice(&size.to_le_bytes());
+    location[4..].copy_from_slice(&rva.to_le_bytes());
+    location
+}
+
+fn rva(location: [u8; 8]) -> u32 {
+    u32::from_le_bytes([location[4], location[5], location[6], location[7]])
+}
+
+/// A `MINIDUMP_MEMORY_DESCRIPTOR` of memory at `start` stored at `location`
+fn memory_descriptor(start: u64, location: [u8; 8]) -> Vec<u8> {
+    [start.to_le_bytes().as_slice(), &location].concat()
+}
+
+/// Write a minidump of a crash, with its stack and the other memory ranges given
+pub fn write_minidump<W>(
+    mut w: W,
+    arch: Arch,
+    report: &CrashReport,
+    stack: &[u8],
+    memory: &[(u64, Vec<u8>)],
+    modules: &[LoadedModule],
+) -> Result<()>
+where
+    W: Write,
+{
+    let layout = ContextLayout::for_arch(arch)?;
+    let streams = 5;
+    let mut builder = Builder::default();
+
+    // The header and stream directory are filled in once the streams are placed
+    builder.data.resize(32 + streams * 12, 0);
+
+    let context = builder.push(&layout.context(report));
+    let stack = (
+        report.stack_pointer.unwrap_or_default(),
+        builder.push(stack),
+    );
+    let mut ranges = vec![stack];
+
+    for (start, data) in memory {
+        ranges.push((*start, builder.push(data)));
+    }
+
+    let thread_id = report.vcpu_index;
+
+    let mut thread_list = 1u32.to_le_bytes().to_vec();
+    thread_list.extend(thread_id.to_le_bytes());
+    // Suspend count, priority class and priority
+    thread_list.extend([0; 12]);
+    // The thread environment block
+    thread_list.extend(0u64.to_le_bytes());
+    thread_list.extend(memory_descriptor(stack.0, stack.1));
+    thread_list.extend(context);
+
+    let mut memory_list = (ranges.len() as u32).to_le_bytes().to_vec();
+    for (start, location) in &ranges {
+        memory_list.extend(memory_descriptor(*start, *location));
+    }
+
+    let mut exception = thread_id.to_le_bytes().to_vec();
+    exception.extend([0; 4]);
+    exception.extend(EXCEPTION_SIGABRT.to_le_bytes());
+    // Flags and the nested exception record
+    exception.extend([0; 12]);
+    exception.extend(report.pc.to_le_bytes());
+    // The parameter count, alignment and parameters
+    exception.extend([0; 8 + 15 * 8]);
+    exception.extend(context);
+
+    let names = modules
+        .iter()
+        .map(|module| builder.push_string(&module.name))
+        .collect::<Vec<_>>();
+    let mut module_list = (modules.len() as u32).to_le_bytes().to_vec();
+    for (module, name) in modules.iter().zip(names) {
+        module_list.extend(module.start.to_le_bytes());
+        module_list.extend((module.end.saturating_sub(module.start) as u32).to_le_bytes());
+        // Checksum and timestamp
+        module_list.extend([0; 8]);
+        module_list.extend(name.to_le_bytes());
+        // Version info, CodeView and misc records, and reserved fields
+        module_list.extend([0; 52 + 8 + 8 + 16]);
+    }
+
+    let csd_version = builder.push_string("");
+    let mut system_info = layout.processor_architecture.to_le_bytes().to_vec();
+    // Processor level and revision, processor count, product type, and OS version
+    system_info.extend([0; 4]);
+    system_info.extend([1, 0]);
+    system_info.extend([0; 12]);
+    system_info.extend(PLATFORM_LINUX.to_le_bytes());
+    system_info.extend(csd_version.to_le_bytes());
+    // Suite mask, reserved field, and CPU information
+    system_info.extend([0; 4 + 24]);
+
+    let directory = [
+        (THREAD_LIST_STREAM, builder.push(&thread_list)),
+        (MODULE_LIST_STREAM, builder.push(&module_list)),
+        (MEMORY_LIST_STREAM, builder.push(&memory_list)),
+        (EXCEPTION_STREAM, builder.push(&exception)),
+        (SYSTEM_INFO_STREAM, builder.push(&system_info)),
+    ];
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32);
+
+    let mut header = SIGNATURE.to_le_bytes().to_vec();
+    header.extend(VERSION.to_le_bytes());
+    header.extend((streams as u32).to_le_bytes());
+    header.extend(32u32.to_le_bytes());
+    // Checksum
+    header.extend(0u32.to_le_bytes());
+    header.extend(timestamp.to_le_bytes());
+    // Flags
+    header.extend(0u64.to_le_bytes());
+
+    for (kind, location) in directory {
+        header.extend(kind.to_le_bytes());
+        header.extend(location);
+    }
+
+    builder.data[..header.len()].copy_from_slice(&header);
+    w.write_all(&builder.data)?;
+    w.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,322 @@
+//! Crash bundles: the registers, stack and last events of a vCPU, written when it reaches a
+//! function which only runs when the guest fails, such as `abort` or `panic`
+//!
+//! With `crash_dir`, each vCPU reaching one of the configured functions writes a bundle to
+//! `crash-<n>-<trigger>` in that directory, holding:
+//!
+//! - `crash.json`: the trigger, the vCPU, its registers and where its stack was read from
+//! - `stack.bin`: the vCPU's stack, read upwards from its stack pointer
+//! - `window.trace`: the last events the vCPU sent before the trigger, as a trace file
+//! - `crash.dmp`: with `crash_minidump`, a minidump of the vCPU, its stack and the code
+//!   around the crashing block, as described in [`minidump`]
+//!
+//! Functions are given by symbol or address. A block is taken to be in a function when QEMU
+//! or the symbolizer names it after that function, so every block of the function matches,
+//! and a vCPU only writes a bundle the first time it reaches any of them. The window holds
+//! whatever events the run records, so a run recording neither instructions nor pcs has
+//! bundles without the instructions leading up to the failure.
+
+use crate::{arch::Arch, aslr::LoadedModule, memmap::parse_addr, tracefile::TraceFile, Event};
+use anyhow::{anyhow, Result};
+use qemu_plugin::{
+    path::create_sink, qemu_plugin_read_memory_vaddr, RegisterDescriptor, VCPUIndex,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt::{self, Display, Formatter},
+    fs::{create_dir_all, write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use typed_builder::TypedBuilder;
+
+pub mod minidump;
+
+/// The functions which trigger a bundle when none are configured
+pub const DEFAULT_TRIGGERS: &[&str] = &[
+    "__assert_fail",
+    "abort",
+    "panic",
+    "rust_panic",
+    "__stack_chk_fail",
+];
+/// The granularity memory is read at, so an unmapped page ends it rather than failing
+const PAGE_SIZE: u64 = 4096;
+/// The bytes of code before the crashing block a minidump holds, and the bytes in all
+const CODE_BEFORE: u64 = 128;
+const CODE_SIZE: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A function whose execution triggers a bundle
+pub enum CrashTrigger {
+    Symbol(String),
+    Address(u64),
+}
+
+impl Display for CrashTrigger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Symbol(symbol) => write!(f, "{symbol}"),
+            Self::Address(address) => write!(f, "pc-{address:x}"),
+        }
+    }
+}
+
+impl CrashTrigger {
+    /// Parse a list of triggers separated by `;`, each an address if it starts with a digit
+    /// and a symbol otherwise
+    pub fn parse_list(list: &str) -> Result<Vec<Self>> {
+        list.split(';')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(|t| {
+                if t.starts_with(|c: char| c.is_ascii_digit()) {
+                    Ok(Self::Address(parse_addr(t)?))
+                } else {
+                    Ok(Self::Symbol(t.to_string()))
+                }
+            })
+            .collect()
+    }
+}
+
+fn default_triggers() -> Vec<CrashTrigger> {
+    DEFAULT_TRIGGERS
+        .iter()
+        .map(|symbol| CrashTrigger::Symbol(symbol.to_string()))
+        .collect()
+}
+
+#[derive(TypedBuilder, Clone, Debug)]
+/// When to write bundles, what to put in them, and where to write them
+pub struct CrashConfig {
+    pub dir: PathBuf,
+    #[builder(default = default_triggers())]
+    pub triggers: Vec<CrashTrigger>,
+    /// The bytes of stack to read
+    #[builder(default = 4096)]
+    pub stack_size: usize,
+    /// The maximum number of bundles to write
+    #[builder(default = 1)]
+    pub limit: usize,
+    /// Whether bundles hold a minidump
+    #[builder(default)]
+    pub minidump: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Sent once a bundle is written
+pub struct CrashEvent {
+    pub vcpu_index: VCPUIndex,
+    /// The address of the block which triggered the bundle
+    pub pc: u64,
+    pub trigger: CrashTrigger,
+    /// The bundle's directory
+    pub path: PathBuf,
+    pub icount: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The `crash.json` of a bundle
+pub struct CrashReport {
+    pub trigger: CrashTrigger,
+    pub vcpu_index: VCPUIndex,
+    pub pc: u64,
+    pub icount: u64,
+    /// Every register QEMU exposes, as bytes in the guest's byte order
+    pub registers: BTreeMap<String, Vec<u8>>,
+    /// The address `stack.bin` was read from
+    pub stack_pointer: Option<u64>,
+    /// The bytes of `stack.bin`, fewer than configured if the stack ended first
+    pub stack_size: usize,
+    /// The events in `window.trace`
+    pub window: usize,
+}
+
+#[derive(Debug)]
+/// The last events sent on each vCPU, recorded as they are written
+pub struct TraceWindow {
+    size: usize,
+    events: Mutex<HashMap<VCPUIndex, VecDeque<Event>>>,
+}
+
+impl TraceWindow {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            events: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn push(&self, vcpu_index: VCPUIndex, event: &Event) -> Result<()> {
+        let mut events = self
+            .events
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock trace window: {e}"))?;
+        let events = events.entry(vcpu_index).or_default();
+
+        if events.len() >= self.size {
+            events.pop_front();
+        }
+
+        events.push_back(event.clone());
+
+        Ok(())
+    }
+
+    /// The events a vCPU sent, oldest first
+    pub fn events(&self, vcpu_index: VCPUIndex) -> Result<Vec<Event>> {
+        Ok(self
+            .events
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock trace window: {e}"))?
+            .get(&vcpu_index)
+            .map(|events| events.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+}
+
+/// Read up to `size` bytes upwards from `addr`, stopping at the first page which cannot be
+/// read
+fn read_memory(addr: u64, size: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut next = addr;
+
+    while data.len() < size {
+        let page_end = (next | (PAGE_SIZE - 1)).saturating_add(1);
+        let len = ((page_end - next) as usize).min(size - data.len());
+
+        match qemu_plugin_read_memory_vaddr(next, len) {
+            Ok(chunk) if !chunk.is_empty() => data.extend(chunk),
+            _ => break,
+        }
+
+        next = page_end;
+    }
+
+    data
+}
+
+#[derive(Debug)]
+/// Writes bundles when vCPUs reach the configured functions, up to the configured limit
+pub struct CrashRecorder {
+    config: CrashConfig,
+    arch: Arch,
+    window: Arc<TraceWindow>,
+    taken: usize,
+    /// The vCPUs which wrote a bundle
+    crashed: HashSet<VCPUIndex>,
+}
+
+impl CrashRecorder {
+    /// Create a recorder for a guest of `arch`, writing the events `window` holds into its
+    /// bundles
+    pub fn new(config: CrashConfig, arch: Arch, window: Arc<TraceWindow>) -> Self {
+        Self {
+            config,
+            arch,
+            window,
+            taken: 0,
+            crashed: HashSet::new(),
+        }
+    }
+
+    /// Returns the trigger a block at `vaddr`, named `symbol`, fires, if any
+    pub fn trigger(&self, vaddr: u64, symbol: Option<&str>) -> Option<CrashTrigger> {
+        self.config
+            .triggers
+            .iter()
+            .find(|trigger| match trigger {
+                CrashTrigger::Symbol(name) => symbol == Some(name.as_str()),
+                CrashTrigger::Address(address) => *address == vaddr,
+            })
+            .cloned()
+    }
+
+    /// Write a bundle for a vCPU which reached `trigger` at `pc`, with `modules` loaded.
+    /// Returns the event recording it, or `None` if the vCPU already wrote one or the limit
+    /// has been reached.
+    pub fn capture(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        pc: u64,
+        trigger: CrashTrigger,
+        registers: &[RegisterDescriptor<'static>],
+        modules: &[LoadedModule],
+        icount: u64,
+    ) -> Result<Option<CrashEvent>> {
+        if self.taken >= self.config.limit || !self.crashed.insert(vcpu_index) {
+            return Ok(None);
+        }
+
+        self.taken += 1;
+
+        let path = self
+            .config
+            .dir
+            .join(format!("crash-{}-{trigger}", self.taken));
+
+        create_dir_all(&path)?;
+
+        let registers = registers
+            .iter()
+            .map(|r| Ok((r.name.clone(), r.read()?)))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        let stack_pointer = self
+            .arch
+            .stack_pointer_names()
+            .iter()
+            .find_map(|name| registers.get(*name))
+            .map(|value| self.arch.decode(value));
+        let stack = stack_pointer
+            .map(|sp| read_memory(sp, self.config.stack_size))
+            .unwrap_or_default();
+        let window = self.window.events(vcpu_index)?;
+
+        write(path.join("stack.bin"), &stack)?;
+
+        let mut trace = TraceFile::create(path.join("window.trace"))?;
+
+        for event in &window {
+            trace.write_record(event)?;
+        }
+
+        trace.finish()?;
+
+        let report = CrashReport {
+            trigger: trigger.clone(),
+            vcpu_index,
+            pc,
+            icount,
+            registers,
+            stack_pointer,
+            stack_size: stack.len(),
+            window: window.len(),
+        };
+
+        serde_json::to_writer_pretty(create_sink(path.join("crash.json"))?, &report)?;
+
+        if self.config.minidump {
+            let code_start = pc.saturating_sub(CODE_BEFORE).max(pc & !(PAGE_SIZE - 1));
+            let code = read_memory(code_start, CODE_SIZE);
+
+            minidump::write_minidump(
+                create_sink(path.join("crash.dmp"))?,
+                self.arch,
+                &report,
+                &stack,
+                &[(code_start, code)],
+                modules,
+            )?;
+        }
+
+        Ok(Some(CrashEvent {
+            vcpu_index,
+            pc,
+            trigger,
+            path,
+            icount,
+        }))
+    }
+}
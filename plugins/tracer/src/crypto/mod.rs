@@ -0,0 +1,306 @@
+//! Detection of cryptographic routines from the instructions and constants of their blocks
+//!
+//! Blocks are scanned as they are translated. A block is a candidate when it contains at
+//! least `threshold` instructions from cryptographic extensions, as classified by
+//! [`Arch::crypto`], or when it uses a constant characteristic of an algorithm: an
+//! immediate such as SHA-1's round constants, or a table such as the AES S-box at an
+//! address it references. References are taken from immediates, PC-relative operands, and
+//! constants built across instructions with `movw`/`movt`, `movk` or `adrp`/`add`.
+//!
+//! Candidates are reported once per function, the first time one of their blocks executes,
+//! with the function's entry found from a per-vCPU stack of the calls executed. Blocks of
+//! functions entered before detection began are reported by their own address.
+
+use crate::arch::{Arch, Crypto};
+use qemu_plugin::{qemu_plugin_read_memory_vaddr, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use typed_builder::TypedBuilder;
+
+/// The lowest value treated as an address which may hold a table
+const MIN_ADDRESS: u64 = 0x1000;
+
+/// The deepest a function stack may grow before its oldest frames are dropped
+const MAX_FRAMES: usize = 4096;
+
+/// Immediates characteristic of algorithms
+const CONSTANTS: &[(&str, u64)] = &[
+    ("MD5/SHA-1", 0x6745_2301),
+    ("MD5/SHA-1", 0xefcd_ab89),
+    ("MD5/SHA-1", 0x98ba_dcfe),
+    ("MD5/SHA-1", 0x1032_5476),
+    ("SHA-1", 0xc3d2_e1f0),
+    ("SHA-1", 0x5a82_7999),
+    ("SHA-1", 0x6ed9_eba1),
+    ("SHA-1", 0x8f1b_bcdc),
+    ("SHA-1", 0xca62_c1d6),
+    ("MD5", 0xd76a_a478),
+    ("MD5", 0xe8c7_b756),
+    ("SHA-2", 0x6a09_e667),
+    ("SHA-2", 0xbb67_ae85),
+    ("SHA-256", 0x428a_2f98),
+    ("SHA-256", 0x7137_4491),
+    ("SHA-512", 0x6a09_e667_f3bc_c908),
+    ("SHA-512", 0x428a_2f98_d728_ae22),
+    ("TEA", 0x9e37_79b9),
+    ("TEA", 0x61c8_8647),
+    ("RC5", 0xb7e1_5163),
+    ("ChaCha", 0x6170_7865),
+    ("ChaCha", 0x3320_646e),
+    ("ChaCha", 0x7962_2d32),
+    ("ChaCha", 0x6b20_6574),
+    ("CRC-32", 0xedb8_8320),
+    ("CRC-32", 0x04c1_1db7),
+    ("CRC-32C", 0x82f6_3b78),
+    ("Blowfish", 0x243f_6a88),
+    ("AES", 0xc663_63a5),
+    ("AES", 0xa563_63c6),
+];
+
+/// The first entries of tables characteristic of algorithms
+enum Table {
+    Bytes(&'static [u8]),
+    Words(&'static [u32]),
+    Quads(&'static [u64]),
+}
+
+impl Table {
+    /// The table's first bytes as they are laid out in the guest memory of `arch`
+    fn bytes(&self, arch: Arch) -> Vec<u8> {
+        let big_endian = arch.big_endian();
+
+        match self {
+            Self::Bytes(bytes) => bytes.to_vec(),
+            Self::Words(words) if big_endian => {
+                words.iter().flat_map(|w| w.to_be_bytes()).collect()
+            }
+            Self::Words(words) => words.iter().flat_map(|w| w.to_le_bytes()).collect(),
+            Self::Quads(quads) if big_endian => {
+                quads.iter().flat_map(|q| q.to_be_bytes()).collect()
+            }
+            Self::Quads(quads) => quads.iter().flat_map(|q| q.to_le_bytes()).collect(),
+        }
+    }
+}
+
+const TABLES: &[(&str, Table)] = &[
+    (
+        "AES S-box",
+        Table::Bytes(&[
+            0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7,
+            0xab, 0x76,
+        ]),
+    ),
+    (
+        "AES inverse S-box",
+        Table::Bytes(&[
+            0x52, 0x09, 0x6a, 0xd5, 0x30, 0x36, 0xa5, 0x38, 0xbf, 0x40, 0xa3, 0x9e, 0x81, 0xf3,
+            0xd7, 0xfb,
+        ]),
+    ),
+    (
+        "AES T-table",
+        Table::Words(&[0xc663_63a5, 0xf87c_7c84, 0xee77_7799, 0xf67b_7b8d]),
+    ),
+    (
+        "AES T-table",
+        Table::Words(&[0xa563_63c6, 0x847c_7cf8, 0x9977_77ee, 0x8d7b_7bf6]),
+    ),
+    (
+        "AES inverse T-table",
+        Table::Words(&[0x51f4_a750, 0x7e41_6553, 0x1a17_a4c3, 0x3a27_5e96]),
+    ),
+    (
+        "DES S-box",
+        Table::Bytes(&[14, 4, 13, 1, 2, 15, 11, 8, 3, 10, 6, 12, 5, 9, 0, 7]),
+    ),
+    (
+        "SHA-256 round constants",
+        Table::Words(&[0x428a_2f98, 0x7137_4491, 0xb5c0_fbcf, 0xe9b5_dba5]),
+    ),
+    (
+        "SHA-512 round constants",
+        Table::Quads(&[0x428a_2f98_d728_ae22, 0x7137_4491_23ef_65cd]),
+    ),
+    (
+        "MD5 sine table",
+        Table::Words(&[0xd76a_a478, 0xe8c7_b756, 0x2420_70db, 0xc1bd_ceee]),
+    ),
+    (
+        "CRC-32 table",
+        Table::Words(&[0x0000_0000, 0x7707_3096, 0xee0e_612c, 0x9909_51ba]),
+    ),
+    (
+        "Blowfish P-array",
+        Table::Words(&[0x243f_6a88, 0x85a3_08d3, 0x1319_8a2e, 0x0370_7344]),
+    ),
+    ("ChaCha sigma", Table::Bytes(b"expand 32-byte k")),
+];
+
+/// The most bytes of any table compared
+const TABLE_LEN: usize = 16;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// What made a translated block a candidate
+pub struct Finding {
+    /// The block's instructions from cryptographic extensions
+    pub crypto_instructions: usize,
+    pub kinds: BTreeSet<Crypto>,
+    /// The names of the characteristic constants and tables the block uses
+    pub constants: BTreeSet<String>,
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// A function which may implement a cryptographic algorithm
+pub struct CryptoEvent {
+    pub vcpu_index: VCPUIndex,
+    /// The entry of the function containing the block, if its call was seen
+    pub function: Option<u64>,
+    /// The block which made the function a candidate
+    pub pc: u64,
+    pub instructions: usize,
+    pub crypto_instructions: usize,
+    pub kinds: Vec<Crypto>,
+    pub constants: Vec<String>,
+    pub icount: u64,
+}
+
+#[derive(Debug, Default)]
+struct VcpuState {
+    /// The entries of the functions called and not yet returned from
+    functions: Vec<u64>,
+    /// Whether a call has just executed, so that the next block is a function entry
+    entering: bool,
+}
+
+#[derive(Debug)]
+/// Scans translated blocks for cryptographic routines
+pub struct CryptoDetector {
+    arch: Arch,
+    /// The fewest instructions from cryptographic extensions a block needs to be a candidate
+    threshold: usize,
+    /// The table found at each address read, if any
+    tables: HashMap<u64, Option<&'static str>>,
+    /// The functions, or blocks outside known functions, already reported
+    reported: HashSet<u64>,
+    vcpus: HashMap<VCPUIndex, VcpuState>,
+}
+
+impl CryptoDetector {
+    pub fn new(arch: Arch, threshold: usize) -> Self {
+        Self {
+            arch,
+            threshold,
+            tables: HashMap::new(),
+            reported: HashSet::new(),
+            vcpus: HashMap::new(),
+        }
+    }
+
+    /// Scan a translated block, given as the address, length and disassembly of each of its
+    /// instructions, returning a finding if it is a candidate
+    pub fn scan(&mut self, instructions: &[(u64, usize, String)]) -> Option<Finding> {
+        let mut finding = Finding::default();
+        // Constants built up in registers over several instructions
+        let mut built = HashMap::<String, u64>::new();
+
+        for (pc, len, disas) in instructions {
+            if let Some(kind) = self.arch.crypto(disas) {
+                finding.crypto_instructions += 1;
+                finding.kinds.insert(kind);
+            }
+
+            for value in self.arch.operand_values(*pc, *len, disas, &mut built) {
+                if let Some(name) = constant(value) {
+                    finding.constants.insert(name.to_string());
+                }
+
+                if let Some(name) = self.table(value) {
+                    finding.constants.insert(name.to_string());
+                }
+            }
+        }
+
+        (finding.crypto_instructions >= self.threshold || !finding.constants.is_empty())
+            .then_some(finding)
+    }
+
+    /// Returns the table starting at `addr`, if it holds one, reading guest memory the
+    /// first time each address is checked
+    fn table(&mut self, addr: u64) -> Option<&'static str> {
+        if addr < MIN_ADDRESS {
+            return None;
+        }
+
+        let arch = self.arch;
+
+        *self.tables.entry(addr).or_insert_with(|| {
+            let data = qemu_plugin_read_memory_vaddr(addr, TABLE_LEN).ok()?;
+
+            TABLES
+                .iter()
+                .find_map(|(name, table)| data.starts_with(&table.bytes(arch)).then_some(*name))
+                .or_else(|| {
+                    // A literal pool entry, or a word table starting with a constant
+                    let word = arch.decode(data.get(..4)?);
+                    let quad = arch.decode(data.get(..8)?);
+
+                    constant(word).or_else(|| constant(quad))
+                })
+        })
+    }
+
+    /// Record that a call instruction executed
+    pub fn on_call(&mut self, vcpu_index: VCPUIndex) {
+        self.vcpus.entry(vcpu_index).or_default().entering = true;
+    }
+
+    /// Record that a return instruction executed
+    pub fn on_return(&mut self, vcpu_index: VCPUIndex) {
+        self.vcpus.entry(vcpu_index).or_default().functions.pop();
+    }
+
+    /// Record that the block at `vaddr`, of `instructions` instructions, executed. Returns
+    /// an event if it is a candidate and its function has not been reported.
+    pub fn on_block(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        vaddr: u64,
+        instructions: usize,
+        finding: Option<&Finding>,
+        icount: u64,
+    ) -> Option<CryptoEvent> {
+        let state = self.vcpus.entry(vcpu_index).or_default();
+
+        if std::mem::take(&mut state.entering) {
+            if state.functions.len() >= MAX_FRAMES {
+                state.functions.remove(0);
+            }
+
+            state.functions.push(vaddr);
+        }
+
+        let finding = finding?;
+        let function = state.functions.last().copied();
+
+        self.reported.insert(function.unwrap_or(vaddr)).then(|| {
+            CryptoEvent::builder()
+                .vcpu_index(vcpu_index)
+                .function(function)
+                .pc(vaddr)
+                .instructions(instructions)
+                .crypto_instructions(finding.crypto_instructions)
+                .kinds(finding.kinds.iter().copied().collect())
+                .constants(finding.constants.iter().cloned().collect())
+                .icount(icount)
+                .build()
+        })
+    }
+}
+
+/// Returns the algorithm `value` is a characteristic constant of, if any
+fn constant(value: u64) -> Option<&'static str> {
+    CONSTANTS
+        .iter()
+        .find_map(|(name, c)| (*c == value).then_some(*name))
+}
@@ -0,0 +1,76 @@
+//! A minimal debuginfod client, fetching debug files by build-id over plain HTTP
+
+use anyhow::{anyhow, Result};
+use std::{
+    fs::{create_dir_all, rename, write},
+    io::{Read, Write},
+    net::TcpStream,
+    path::Path,
+    process,
+    time::Duration,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Fetch the debug file of `build_id` from the server at `url`, `http://host[:port][/prefix]`.
+/// Returns `None` if the server does not have it.
+pub fn fetch(url: &str, build_id: &str) -> Result<Option<Vec<u8>>> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow!("Only http:// debuginfod servers are supported, not {url}"))?;
+    let (host, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    let prefix = prefix.trim_end_matches('/');
+    let path = if prefix.is_empty() {
+        format!("/buildid/{build_id}/debuginfo")
+    } else {
+        format!("/{prefix}/buildid/{build_id}/debuginfo")
+    };
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{host}:80")
+    };
+
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    // HTTP/1.0, so that the body is never chunked
+    stream.write_all(
+        format!("GET {path} HTTP/1.0\r\nhost: {host}\r\nconnection: close\r\n\r\n").as_bytes(),
+    )?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("Invalid response from {url}"))?;
+    let status = String::from_utf8_lossy(&response[..end])
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| anyhow!("Invalid response from {url}"))?;
+
+    match status {
+        200 => Ok(Some(response.split_off(end + 4))),
+        404 => Ok(None),
+        _ => Err(anyhow!("{url} returned {status} for {build_id}")),
+    }
+}
+
+/// Write a fetched debug file to `path` in the cache, through a temporary file so that a
+/// reader never sees part of one
+pub fn store(path: &Path, data: &[u8]) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("{} has no directory", path.display()))?;
+    let temporary = dir.join(format!(".debuginfo.{}", process::id()));
+
+    create_dir_all(dir)?;
+    write(&temporary, data)?;
+    rename(&temporary, path)?;
+
+    Ok(())
+}
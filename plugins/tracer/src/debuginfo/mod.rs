@@ -0,0 +1,318 @@
+//! Symbolization of guest code QEMU has no symbol for, such as the shared libraries a user-mode
+//! guest loads and stripped programs, from the modules' own symbol tables or from separate
+//! debug files found by build-id
+//!
+//! Libraries are found by following the guest as it opens files and maps them executable.
+//! Guest paths are read on the host, under `$QEMU_LD_PREFIX` if the file exists there, so a
+//! guest run with `-L` needs the same prefix in the environment.
+//!
+//! The debug file of a module with a build-id is looked for in the system's
+//! `/usr/lib/debug/.build-id` directory and then in a cache laid out as debuginfod clients lay
+//! theirs out, `<cache>/<build-id>/debuginfo`. The cache is `debuginfod_cache`, or
+//! `$DEBUGINFOD_CACHE_PATH`, or `debuginfod_client` in the user's cache directory, so debug
+//! files fetched by `debuginfod-find` or gdb are reused. With the `debuginfod` feature,
+//! missing debug files are fetched into the cache from the servers in `debuginfod_urls`, or
+//! `$DEBUGINFOD_URLS`, separated by spaces. Only plain HTTP is spoken, so HTTPS servers must be
+//! reached through a proxy or mirror. Modules without a debug file fall back to their own
+//! symbol tables.
+
+#[cfg(feature = "debuginfod")]
+pub mod debuginfod;
+
+use crate::{
+    arch::{Arch, Syscall},
+    aslr::LoadedModule,
+    guest::read_cstring,
+    modules::{build_id, mapping_bias, SymbolTable},
+};
+use anyhow::{anyhow, Result};
+use qemu_plugin::VCPUIndex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env::var_os,
+    fs::read,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// The system directory of debug files
+const DEBUG_DIR: &str = "/usr/lib/debug";
+/// The longest path read from guest memory
+const MAX_PATH: usize = 4096;
+const PROT_EXEC: u64 = 4;
+
+#[derive(Clone, Debug)]
+/// Where debug files are looked for, and fetched to
+pub struct DebugLocator {
+    cache: PathBuf,
+    #[cfg(feature = "debuginfod")]
+    urls: Vec<String>,
+}
+
+impl DebugLocator {
+    /// Look for debug files in `cache`, or the default cache, and fetch missing ones from
+    /// `urls`, or `$DEBUGINFOD_URLS`. Fails if servers are given but the tracer was built
+    /// without the `debuginfod` feature.
+    pub fn new(cache: Option<PathBuf>, urls: Option<&str>) -> Result<Self> {
+        let cache = cache
+            .or_else(|| var_os("DEBUGINFOD_CACHE_PATH").map(PathBuf::from))
+            .or_else(|| var_os("XDG_CACHE_HOME").map(|d| Path::new(&d).join("debuginfod_client")))
+            .or_else(|| var_os("HOME").map(|d| Path::new(&d).join(".cache/debuginfod_client")))
+            .ok_or_else(|| anyhow!("No debuginfod cache given, and HOME is not set"))?;
+
+        #[cfg(feature = "debuginfod")]
+        {
+            let urls = urls
+                .map(str::to_string)
+                .or_else(|| var_os("DEBUGINFOD_URLS").map(|u| u.to_string_lossy().into_owned()))
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect();
+
+            Ok(Self { cache, urls })
+        }
+
+        #[cfg(not(feature = "debuginfod"))]
+        match urls {
+            Some(_) => Err(anyhow!(
+                "debuginfod_urls needs the tracer built with the debuginfod feature"
+            )),
+            None => Ok(Self { cache }),
+        }
+    }
+
+    /// Returns the path of the debug file of `build_id`, fetching it into the cache if it is
+    /// not found locally and a server has it
+    pub fn find(&self, build_id: &str) -> Option<PathBuf> {
+        if build_id.len() < 3 {
+            return None;
+        }
+
+        let (dir, file) = build_id.split_at(2);
+        let system = Path::new(DEBUG_DIR)
+            .join(".build-id")
+            .join(dir)
+            .join(format!("{file}.debug"));
+
+        if system.is_file() {
+            return Some(system);
+        }
+
+        let cached = self.cache.join(build_id).join("debuginfo");
+
+        if cached.is_file() {
+            return Some(cached);
+        }
+
+        #[cfg(feature = "debuginfod")]
+        for url in &self.urls {
+            if let Ok(Some(data)) = debuginfod::fetch(url, build_id) {
+                if debuginfod::store(&cached, &data).is_ok() {
+                    return Some(cached);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Clone, Debug)]
+/// An executable mapping of a module
+struct Mapping {
+    end: u64,
+    bias: u64,
+    symbols: Arc<SymbolTable>,
+}
+
+#[derive(Clone, Debug)]
+/// A syscall which has been entered but has not yet returned
+struct PendingSyscall {
+    syscall: Syscall,
+    args: [u64; 8],
+    path: Option<String>,
+}
+
+#[derive(Debug)]
+/// Names the functions of the modules mapped into the guest
+pub struct Symbolizer {
+    locator: DebugLocator,
+    /// The guest path each open file descriptor was opened from
+    fds: HashMap<u64, String>,
+    pending: HashMap<VCPUIndex, PendingSyscall>,
+    /// The symbols of each module read so far, by host path
+    tables: HashMap<PathBuf, Arc<SymbolTable>>,
+    /// Executable mappings by start address
+    mappings: BTreeMap<u64, Mapping>,
+}
+
+impl Symbolizer {
+    pub fn new(locator: DebugLocator) -> Self {
+        Self {
+            locator,
+            fds: HashMap::new(),
+            pending: HashMap::new(),
+            tables: HashMap::new(),
+            mappings: BTreeMap::new(),
+        }
+    }
+
+    /// The symbols of the module at `path` on the host, from its debug file if one is found
+    fn symbols(&mut self, path: &Path, data: &[u8]) -> Arc<SymbolTable> {
+        if let Some(symbols) = self.tables.get(path) {
+            return symbols.clone();
+        }
+
+        let debug = build_id(data)
+            .ok()
+            .flatten()
+            .and_then(|id| self.locator.find(&id))
+            .and_then(|debug| read(debug).ok())
+            .and_then(|debug| SymbolTable::parse(&debug).ok())
+            .filter(|symbols| !symbols.is_empty());
+        let symbols = Arc::new(
+            debug
+                .or_else(|| SymbolTable::parse(data).ok())
+                .unwrap_or_default(),
+        );
+
+        self.tables.insert(path.to_path_buf(), symbols.clone());
+        symbols
+    }
+
+    /// Add a module loaded at `bias` whose code spans `start` to `end`, such as the program
+    pub fn add_module<P>(&mut self, path: P, start: u64, end: u64, bias: u64) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let symbols = self.symbols(path, &read(path)?);
+
+        self.mappings.insert(start, Mapping { end, bias, symbols });
+
+        Ok(())
+    }
+
+    /// Record a syscall a vCPU entered, if it opens, maps or unmaps a file
+    pub fn on_syscall(
+        &mut self,
+        arch: Arch,
+        vcpu_index: VCPUIndex,
+        num: i64,
+        args: [u64; 8],
+    ) -> Result<()> {
+        let Some(syscall) = arch.syscall(num) else {
+            return Ok(());
+        };
+
+        // A path the guest passes an unreadable pointer for fails the syscall, so it names
+        // no file to map
+        let path = match syscall {
+            Syscall::Open => read_cstring(args[0], MAX_PATH).ok(),
+            Syscall::Openat => read_cstring(args[1], MAX_PATH).ok(),
+            Syscall::Mmap if args[2] & PROT_EXEC != 0 => None,
+            Syscall::Close | Syscall::Munmap => None,
+            _ => return Ok(()),
+        }
+        .map(|path| String::from_utf8_lossy(&path).into_owned());
+
+        self.pending.insert(
+            vcpu_index,
+            PendingSyscall {
+                syscall,
+                args,
+                path,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Update the open files and mappings once a syscall returns. Returns the module mapped,
+    /// if the syscall mapped one.
+    pub fn on_syscall_return(
+        &mut self,
+        arch: Arch,
+        vcpu_index: VCPUIndex,
+        ret: i64,
+    ) -> Option<LoadedModule> {
+        let pending = self.pending.remove(&vcpu_index)?;
+
+        // Addresses returned by `mmap` may be negative, while errors are -1 to -4095
+        if (-4095..0).contains(&ret) {
+            return None;
+        }
+
+        let args = pending.args;
+
+        match pending.syscall {
+            Syscall::Open | Syscall::Openat => {
+                if let Some(path) = pending.path {
+                    self.fds.insert(ret as u64, path);
+                }
+            }
+            Syscall::Close => {
+                self.fds.remove(&args[0]);
+            }
+            Syscall::Munmap => {
+                let unmapped = args[0]..args[0].wrapping_add(args[1]);
+
+                self.mappings.retain(|start, mapping| {
+                    !unmapped.contains(start) || mapping.end > unmapped.end
+                });
+            }
+            // A failure to read a module only leaves its code unnamed
+            Syscall::Mmap => return self.map(arch, ret as u64, args).ok().flatten(),
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Add an executable mapping of a file at `start`
+    fn map(&mut self, arch: Arch, start: u64, args: [u64; 8]) -> Result<Option<LoadedModule>> {
+        let Some(guest_path) = self.fds.get(&args[4]) else {
+            return Ok(None);
+        };
+
+        let name = guest_path.clone();
+        let path = host_path(guest_path);
+        let data = read(&path)?;
+        let bias = mapping_bias(&data, start, arch.mmap_offset(&args))?;
+        let symbols = self.symbols(&path, &data);
+        let end = start.wrapping_add(args[1]);
+
+        self.mappings.insert(start, Mapping { end, bias, symbols });
+
+        Ok(Some(LoadedModule {
+            name,
+            start,
+            end,
+            bias,
+        }))
+    }
+
+    /// Returns the name of the function containing `vaddr`, if it is in a mapped module with
+    /// a symbol for it
+    pub fn symbol(&self, vaddr: u64) -> Option<String> {
+        let (_, mapping) = self.mappings.range(..=vaddr).next_back()?;
+
+        if vaddr >= mapping.end {
+            return None;
+        }
+
+        mapping
+            .symbols
+            .lookup(vaddr.wrapping_sub(mapping.bias))
+            .map(str::to_string)
+    }
+}
+
+/// The host path of a file the guest opened, under `$QEMU_LD_PREFIX` if it exists there
+pub(crate) fn host_path(guest_path: &str) -> PathBuf {
+    var_os("QEMU_LD_PREFIX")
+        .map(|prefix| Path::new(&prefix).join(guest_path.trim_start_matches('/')))
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(guest_path))
+}
@@ -0,0 +1,330 @@
+//! A log of the callbacks each feature registers on each translated block, and why, for
+//! debugging the tracer's own instrumentation
+//!
+//! With `decision_log`, each block is written to the log as a line of JSON once it is
+//! translated, listing each callback a feature registered on the block or on one of its
+//! instructions, and each block or instruction a feature considered and passed over, with
+//! the reason. Features are named after the argument which enables them, and callbacks not
+//! tied to one, like the instruction count, after what they maintain. A callback which never
+//! fires either has no decision, when its feature never considered the block, or a decision
+//! saying why it was passed over, or was registered on a block which never ran. QEMU
+//! translates a block again after flushing its cache, so a block may be listed more than
+//! once.
+//!
+//! The log is read back with [`DecisionLog::read`] and searched with a [`DecisionQuery`], as
+//! the `decisions` binary does.
+
+use anyhow::{anyhow, Result};
+use qemu_plugin::path::create_sink;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    thread::{self, ThreadId},
+};
+use typed_builder::TypedBuilder;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// The accesses a memory callback runs on
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A kind of callback
+pub enum Callback {
+    /// Runs each time the block or instruction executes
+    Execute,
+    /// Runs each time the block or instruction executes, and reads registers
+    ExecuteRegisters,
+    /// Runs when a per-vCPU counter the block adds to reaches a threshold
+    Conditional,
+    /// Updates a per-vCPU counter inline, without calling into the plugin
+    Inline,
+    /// Runs on each memory access of the instruction
+    Memory(Access),
+}
+
+impl Display for Callback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Execute => write!(f, "execute"),
+            Self::ExecuteRegisters => write!(f, "execute+regs"),
+            Self::Conditional => write!(f, "conditional"),
+            Self::Inline => write!(f, "inline"),
+            Self::Memory(Access::Read) => write!(f, "memory-r"),
+            Self::Memory(Access::Write) => write!(f, "memory-w"),
+            Self::Memory(Access::ReadWrite) => write!(f, "memory-rw"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A callback a feature registered, or a block or instruction it passed over
+pub struct Decision {
+    pub feature: String,
+    /// The instruction the decision is about, or `None` for the whole block
+    pub pc: Option<u64>,
+    /// The callback registered, or `None` if the feature registered nothing
+    pub callback: Option<Callback>,
+    pub reason: String,
+}
+
+impl Display for Decision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let target = match self.pc {
+            Some(pc) => format!("{pc:#x}"),
+            None => "block".to_string(),
+        };
+        let callback = match self.callback {
+            Some(callback) => callback.to_string(),
+            None => "skipped".to_string(),
+        };
+
+        write!(
+            f,
+            "{:<20} {target:<18} {callback:<13} {}",
+            self.feature, self.reason
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The decisions made while translating a block
+pub struct BlockDecisions {
+    /// The number of blocks translated before this one
+    pub index: u64,
+    pub vaddr: u64,
+    /// The size of the block's code, in bytes
+    pub size: usize,
+    pub instructions: usize,
+    /// The symbol of the block's first instruction
+    pub symbol: Option<String>,
+    pub decisions: Vec<Decision>,
+}
+
+impl BlockDecisions {
+    /// Whether the block's code contains `pc`
+    pub fn contains(&self, pc: u64) -> bool {
+        self.vaddr <= pc && pc < self.vaddr + self.size as u64
+    }
+}
+
+impl Display for BlockDecisions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "block {:#x} #{}: {} bytes, {} instructions",
+            self.vaddr, self.index, self.size, self.instructions
+        )?;
+
+        if let Some(symbol) = self.symbol.as_ref() {
+            write!(f, " in {symbol}")?;
+        }
+
+        for decision in &self.decisions {
+            write!(f, "\n  {decision}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+/// Writes each block's decisions to the log once it is translated
+pub struct DecisionLogger {
+    writer: Mutex<BufWriter<File>>,
+    /// The blocks being translated, by the thread translating them, since vCPUs may
+    /// translate blocks at the same time
+    blocks: Mutex<HashMap<ThreadId, BlockDecisions>>,
+    translated: AtomicU64,
+}
+
+impl DecisionLogger {
+    pub fn create<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(create_sink(path)?)),
+            blocks: Mutex::new(HashMap::new()),
+            translated: AtomicU64::new(0),
+        })
+    }
+
+    /// Start recording the decisions for a block this thread is translating
+    pub fn begin(
+        &self,
+        vaddr: u64,
+        size: usize,
+        instructions: usize,
+        symbol: Option<String>,
+    ) -> Result<()> {
+        let block = BlockDecisions {
+            index: self.translated.fetch_add(1, Ordering::Relaxed),
+            vaddr,
+            size,
+            instructions,
+            symbol,
+            decisions: Vec::new(),
+        };
+
+        self.blocks
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock decision log: {e}"))?
+            .insert(thread::current().id(), block);
+
+        Ok(())
+    }
+
+    /// Record a decision for the block this thread is translating
+    pub fn record(&self, decision: Decision) -> Result<()> {
+        if let Some(block) = self
+            .blocks
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock decision log: {e}"))?
+            .get_mut(&thread::current().id())
+        {
+            block.decisions.push(decision);
+        }
+
+        Ok(())
+    }
+
+    /// Write the decisions for the block this thread finished translating
+    pub fn finish(&self) -> Result<()> {
+        let Some(block) = self
+            .blocks
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock decision log: {e}"))?
+            .remove(&thread::current().id())
+        else {
+            return Ok(());
+        };
+
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock decision log: {e}"))?;
+
+        serde_json::to_writer(&mut *writer, &block)?;
+        writeln!(writer)?;
+
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.writer
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock decision log: {e}"))?
+            .flush()?;
+
+        Ok(())
+    }
+}
+
+#[derive(TypedBuilder, Clone, Debug, Default)]
+/// The blocks and decisions to find in a log
+pub struct DecisionQuery {
+    /// Only blocks containing this address, and their decisions about the block or about
+    /// the instruction at it
+    #[builder(default)]
+    pub pc: Option<u64>,
+    /// Only decisions of this feature
+    #[builder(default)]
+    pub feature: Option<String>,
+    /// Only decisions which registered nothing
+    #[builder(default)]
+    pub skipped: bool,
+}
+
+impl DecisionQuery {
+    /// Returns the block with only the decisions the query matches, or `None` if the query
+    /// matches no block or decision
+    pub fn matches(&self, block: &BlockDecisions) -> Option<BlockDecisions> {
+        if self.pc.is_some_and(|pc| !block.contains(pc)) {
+            return None;
+        }
+
+        let decisions = block
+            .decisions
+            .iter()
+            .filter(|d| self.pc.is_none_or(|pc| d.pc.is_none_or(|p| p == pc)))
+            .filter(|d| self.feature.as_ref().is_none_or(|f| d.feature == *f))
+            .filter(|d| !self.skipped || d.callback.is_none())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // A block matching an address is shown even without decisions, since that is the
+        // answer when no feature considered it
+        if decisions.is_empty() && (self.feature.is_some() || self.skipped) {
+            return None;
+        }
+
+        Some(BlockDecisions {
+            decisions,
+            ..block.clone()
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The callbacks a feature registered and the times it passed over a block or instruction
+pub struct FeatureSummary {
+    pub registered: BTreeMap<String, u64>,
+    pub skipped: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// The blocks of a decision log, in the order they were translated
+pub struct DecisionLog {
+    pub blocks: Vec<BlockDecisions>,
+}
+
+impl DecisionLog {
+    pub fn read<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let blocks = BufReader::new(File::open(path)?)
+            .lines()
+            .filter(|line| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { blocks })
+    }
+
+    /// The blocks the query matches, with the decisions it matches
+    pub fn query(&self, query: &DecisionQuery) -> Vec<BlockDecisions> {
+        self.blocks
+            .iter()
+            .filter_map(|block| query.matches(block))
+            .collect()
+    }
+
+    /// What each feature decided across the log
+    pub fn summary(&self) -> BTreeMap<String, FeatureSummary> {
+        let mut summary = BTreeMap::<String, FeatureSummary>::new();
+
+        for decision in self.blocks.iter().flat_map(|block| &block.decisions) {
+            let feature = summary.entry(decision.feature.clone()).or_default();
+
+            match decision.callback {
+                Some(callback) => *feature.registered.entry(callback.to_string()).or_default() += 1,
+                None => feature.skipped += 1,
+            }
+        }
+
+        summary
+    }
+}
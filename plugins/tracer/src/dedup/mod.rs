@@ -0,0 +1,92 @@
+//! Recording only the first occurrences of each instruction, for traces whose purpose is to
+//! find out what code and data a program touches rather than how often
+//!
+//! Occurrences are counted per instruction address, or per address and event class. The
+//! counter for a key is created when its instruction is translated and moved into the
+//! callbacks, so counting at runtime is a single atomic operation. Instructions which are
+//! retranslated after reaching the limit get no logging callbacks at all.
+
+use crate::{sync::Quota, throttle::EventClass};
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// An instruction address, and the event class if occurrences are counted per class
+type Key = (u64, Option<EventClass>);
+
+#[derive(Clone, Debug, Default)]
+/// Decides whether each occurrence of an event at an instruction is recorded. The default
+/// counter records every occurrence.
+pub struct Counter {
+    count: Option<Arc<Quota>>,
+    /// Whether the counter is shared by every event class, in which case it counts
+    /// executions of the instruction with `tick` instead of events
+    shared: bool,
+}
+
+impl Counter {
+    /// Whether the counter counts executions, and must be ticked before each execution's
+    /// events are admitted
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// Count an execution of the instruction
+    pub fn tick(&self) {
+        if let Some(count) = self.count.as_ref() {
+            count.tick();
+        }
+    }
+
+    /// Whether an event should be recorded
+    pub fn admit(&self) -> bool {
+        match self.count.as_ref() {
+            None => true,
+            Some(count) if self.shared => count.is_within(),
+            Some(count) => count.take(),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// The occurrence counters of every instruction translated so far
+pub struct Dedup {
+    limit: u64,
+    per_class: bool,
+    counters: Mutex<HashMap<Key, Arc<Quota>>>,
+}
+
+impl Dedup {
+    /// Record the first `limit` occurrences of each instruction, or of each event class at
+    /// each instruction if `per_class` is set
+    pub fn new(limit: u64, per_class: bool) -> Self {
+        Self {
+            limit,
+            per_class,
+            counters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the counter for events of `class` at `pc`, or `None` if the limit has already
+    /// been reached and no callback is needed
+    pub fn counter(&self, pc: u64, class: EventClass) -> Result<Option<Counter>> {
+        let count = self
+            .counters
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock dedup counters: {e}"))?
+            .entry((pc, self.per_class.then_some(class)))
+            .or_insert_with(|| Arc::new(Quota::new(self.limit)))
+            .clone();
+
+        if count.is_exhausted() {
+            return Ok(None);
+        }
+
+        Ok(Some(Counter {
+            count: Some(count),
+            shared: !self.per_class,
+        }))
+    }
+}
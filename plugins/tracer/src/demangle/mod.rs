@@ -0,0 +1,200 @@
+//! Demangling of the symbol names QEMU reports for guest code, so that traces and profiles
+//! of guests written in Rust, C++ or Swift show readable names
+//!
+//! The policy is given as `demangle=all`, or as the languages to demangle separated by `;`,
+//! such as `demangle=rust;cpp`. Rust's legacy and v0 manglings are demangled without their
+//! hashes, and C++ names with the Itanium ABI. Swift names are demangled by the Swift
+//! runtime's `swift_demangle`, so only when `libswiftCore.so` can be loaded on the host;
+//! with `all` they are otherwise left mangled.
+//!
+//! Only recorded names are demangled. Allocator, mutex and API functions are still matched
+//! by the names QEMU reports.
+
+use anyhow::{anyhow, Error, Result};
+use cpp_demangle::{DemangleOptions, Symbol};
+use libloading::Library;
+use std::{
+    ffi::{c_char, c_void, CStr},
+    ptr::null_mut,
+    str::FromStr,
+};
+
+/// The library holding the Swift runtime's demangler
+const SWIFT_RUNTIME: &str = "libswiftCore.so";
+
+/// The prefixes of Swift manglings, from Swift 4 on
+const SWIFT_PREFIXES: &[&str] = &["$s", "_$s", "$S", "_$S", "$e", "_$e", "_T0"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A language whose symbol names can be demangled
+pub enum Language {
+    Rust,
+    Cpp,
+    Swift,
+}
+
+impl FromStr for Language {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rust" => Ok(Self::Rust),
+            "cpp" | "c++" => Ok(Self::Cpp),
+            "swift" => Ok(Self::Swift),
+            _ => Err(anyhow!("Unknown demangling language {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// The languages whose names are demangled
+pub struct DemanglePolicy {
+    pub languages: Vec<Language>,
+    /// Whether the policy was `all`, so that a missing Swift runtime is not an error
+    pub all: bool,
+}
+
+impl DemanglePolicy {
+    pub fn contains(&self, language: Language) -> bool {
+        self.languages.contains(&language)
+    }
+}
+
+impl FromStr for DemanglePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "all" => Ok(Self {
+                languages: vec![Language::Rust, Language::Cpp, Language::Swift],
+                all: true,
+            }),
+            "none" | "" => Ok(Self::default()),
+            s => Ok(Self {
+                languages: s
+                    .split(';')
+                    .map(|language| language.trim().parse())
+                    .collect::<Result<_>>()?,
+                all: false,
+            }),
+        }
+    }
+}
+
+/// `swift_demangle`, which returns a demangled name allocated with `malloc`, or null
+type SwiftDemangleFn = unsafe extern "C" fn(
+    mangled: *const c_char,
+    len: usize,
+    output: *mut c_char,
+    output_len: *mut usize,
+    flags: u32,
+) -> *mut c_char;
+
+#[derive(Debug)]
+/// The Swift runtime's demangler
+struct SwiftDemangler {
+    demangle: SwiftDemangleFn,
+    /// Kept loaded for as long as the demangler may be called
+    _library: Library,
+}
+
+impl SwiftDemangler {
+    fn load() -> Result<Self> {
+        // SAFETY: Loading the Swift runtime runs its initializers, which have no effect on the
+        // guest or QEMU
+        let library = unsafe { Library::new(SWIFT_RUNTIME) }
+            .map_err(|e| anyhow!("Failed to load {SWIFT_RUNTIME} to demangle Swift: {e}"))?;
+        // SAFETY: `swift_demangle` has had this signature since Swift 4
+        let demangle = *unsafe { library.get::<SwiftDemangleFn>(b"swift_demangle\0") }
+            .map_err(|e| anyhow!("{SWIFT_RUNTIME} has no swift_demangle: {e}"))?;
+
+        Ok(Self {
+            demangle,
+            _library: library,
+        })
+    }
+
+    fn demangle(&self, symbol: &str) -> Option<String> {
+        // SAFETY: The name is passed with its length, so needs no terminator, and a null
+        // output buffer asks for the result to be allocated
+        let demangled = unsafe {
+            (self.demangle)(
+                symbol.as_ptr() as *const c_char,
+                symbol.len(),
+                null_mut(),
+                null_mut(),
+                0,
+            )
+        };
+
+        if demangled.is_null() {
+            return None;
+        }
+
+        // SAFETY: A non-null result is a NUL-terminated string allocated with `malloc`, which
+        // the caller frees once it is copied
+        let name = unsafe {
+            let name = CStr::from_ptr(demangled).to_string_lossy().into_owned();
+            libc::free(demangled as *mut c_void);
+            name
+        };
+
+        Some(name)
+    }
+}
+
+#[derive(Debug)]
+/// Demangles symbol names under a policy
+pub struct Demangler {
+    policy: DemanglePolicy,
+    swift: Option<SwiftDemangler>,
+}
+
+impl Demangler {
+    /// Create a demangler for `policy`. Fails if the policy names Swift and the Swift runtime
+    /// cannot be loaded.
+    pub fn new(policy: DemanglePolicy) -> Result<Self> {
+        let swift = if policy.contains(Language::Swift) {
+            match SwiftDemangler::load() {
+                Ok(swift) => Some(swift),
+                Err(_) if policy.all => None,
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+
+        Ok(Self { policy, swift })
+    }
+
+    /// Returns the demangled form of `symbol`, or `symbol` itself if it is not mangled in a
+    /// language the policy covers
+    pub fn demangle(&self, symbol: String) -> String {
+        self.try_demangle(&symbol).unwrap_or(symbol)
+    }
+
+    fn try_demangle(&self, symbol: &str) -> Option<String> {
+        // Legacy Rust manglings are also valid C++ ones, so Rust is tried first
+        if self.policy.contains(Language::Rust) {
+            if let Ok(demangled) = rustc_demangle::try_demangle(symbol) {
+                return Some(format!("{demangled:#}"));
+            }
+        }
+
+        if self.policy.contains(Language::Cpp) && symbol.starts_with("_Z") {
+            if let Some(demangled) = Symbol::new(symbol)
+                .ok()
+                .and_then(|s| s.demangle(&DemangleOptions::default()).ok())
+            {
+                return Some(demangled);
+            }
+        }
+
+        match self.swift.as_ref() {
+            Some(swift) if SWIFT_PREFIXES.iter().any(|p| symbol.starts_with(p)) => {
+                swift.demangle(symbol)
+            }
+            _ => None,
+        }
+    }
+}
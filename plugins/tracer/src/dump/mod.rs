@@ -0,0 +1,445 @@
+//! Guest memory dumps written when a trigger fires
+//!
+//! A dump is taken when the guest reaches a program counter, makes a chosen syscall (which
+//! guest code can use as a hypercall, since unknown syscalls just fail with `ENOSYS`), or
+//! raises a fatal signal against itself, as `abort` does. Faults delivered by QEMU itself are
+//! not visible to plugins before the guest is terminated, so they cannot trigger a dump.
+//!
+//! With a QMP socket, QEMU's own `dump-guest-memory` writes all of guest RAM as an ELF core,
+//! asynchronously. Otherwise the plugin writes an ELF core of the configured virtual address
+//! ranges, read with `qemu_plugin_read_memory_vaddr`, with an `NT_PRSTATUS` note holding the
+//! registers of the triggering vCPU so that `gdb` can load it. Every register QEMU exposes is
+//! also stored as JSON in a `QEMU-RS` note.
+
+use crate::{
+    arch::{Arch, Syscall},
+    log,
+    memmap::parse_addr,
+    qmp::Qmp,
+};
+use anyhow::{anyhow, Result};
+use qemu_plugin::{path::create_sink, qemu_plugin_read_memory_vaddr, RegisterDescriptor};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    io::Write,
+    path::PathBuf,
+    process,
+    str::FromStr,
+    thread::spawn,
+};
+use typed_builder::TypedBuilder;
+
+const SIGABRT: i64 = 6;
+const SIGBUS: i64 = 7;
+const SIGFPE: i64 = 8;
+const SIGSEGV: i64 = 11;
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+/// The note type of the JSON register note, which tools other than the tracer ignore
+const NT_QEMU_RS_REGISTERS: u32 = 0x5152_0001;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// What caused a dump to be taken
+pub enum DumpTrigger {
+    /// Execution reached the instruction at this address
+    Pc(u64),
+    /// The guest made the configured hypercall syscall
+    Syscall(i64),
+    /// The guest sent itself this fatal signal
+    Signal(i64),
+}
+
+impl Display for DumpTrigger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pc(pc) => write!(f, "pc-{pc:x}"),
+            Self::Syscall(num) => write!(f, "syscall-{num}"),
+            Self::Signal(signal) => write!(f, "signal-{signal}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A range of guest virtual addresses to dump
+pub struct DumpRange {
+    pub start: u64,
+    /// The address after the last address in the range
+    pub end: u64,
+}
+
+impl FromStr for DumpRange {
+    type Err = anyhow::Error;
+
+    /// Parse a range written as `start-end`, e.g. `0x400000-0x401000`
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow!("Invalid dump range {s}, expected start-end"))?;
+        let (start, end) = (parse_addr(start)?, parse_addr(end)?);
+
+        if end <= start {
+            return Err(anyhow!("Dump range {s} is empty"));
+        }
+
+        Ok(Self { start, end })
+    }
+}
+
+impl DumpRange {
+    /// Parse a list of ranges separated by `;`. QEMU splits plugin arguments on `,`, so that
+    /// cannot be used as the separator.
+    pub fn parse_list(list: &str) -> Result<Vec<Self>> {
+        list.split(';')
+            .filter(|r| !r.trim().is_empty())
+            .map(str::parse)
+            .collect()
+    }
+}
+
+#[derive(TypedBuilder, Clone, Debug)]
+/// When to dump, what to dump, and where to write it
+pub struct DumpConfig {
+    /// The directory dumps are written to, as `dump-<n>-<trigger>.core`
+    pub dir: PathBuf,
+    #[builder(default)]
+    pub ranges: Vec<DumpRange>,
+    #[builder(default)]
+    pub pc: Option<u64>,
+    #[builder(default)]
+    pub syscall: Option<i64>,
+    /// Whether a fatal signal the guest sends itself triggers a dump
+    #[builder(default)]
+    pub on_abort: bool,
+    /// The maximum number of dumps to take, as a trigger on a hot path could fill the disk
+    #[builder(default = 1)]
+    pub limit: usize,
+    /// A QMP socket to request the dump over, capturing all of guest RAM
+    #[builder(default)]
+    pub qmp_socket: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Sent when a dump is taken. QMP dumps are still being written when this is sent.
+pub struct DumpEvent {
+    pub trigger: DumpTrigger,
+    pub path: PathBuf,
+    pub icount: u64,
+}
+
+/// How an architecture's `elf_prstatus` is laid out, for the `NT_PRSTATUS` note
+struct CoreLayout {
+    machine: u16,
+    is_64: bool,
+    /// The size of `elf_prstatus`
+    size: usize,
+    /// The offset of `pr_reg` in `elf_prstatus`
+    regs_offset: usize,
+    /// The registers in `pr_reg`, by their QEMU names. Empty names are always zero.
+    regs: &'static [&'static str],
+}
+
+impl CoreLayout {
+    /// The layout for `arch`. Only little-endian targets are supported, since the dump is
+    /// written in little-endian byte order.
+    fn for_arch(arch: Arch) -> Result<Self> {
+        Ok(match arch {
+            Arch::X86_64 => Self {
+                machine: 62,
+                is_64: true,
+                size: 336,
+                regs_offset: 112,
+                regs: &[
+                    "r15", "r14", "r13", "r12", "rbp", "rbx", "r11", "r10", "r9", "r8", "rax",
+                    "rcx", "rdx", "rsi", "rdi", "", "rip", "cs", "eflags", "rsp", "ss", "fs_base",
+                    "gs_base", "ds", "es", "fs", "gs",
+                ],
+            },
+            Arch::Aarch64 => Self {
+                machine: 183,
+                is_64: true,
+                size: 392,
+                regs_offset: 112,
+                regs: &[
+                    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11",
+                    "x12", "x13", "x14", "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22",
+                    "x23", "x24", "x25", "x26", "x27", "x28", "x29", "x30", "sp", "pc", "cpsr",
+                ],
+            },
+            Arch::I386 => Self {
+                machine: 3,
+                is_64: false,
+                size: 144,
+                regs_offset: 72,
+                regs: &[
+                    "ebx", "ecx", "edx", "esi", "edi", "ebp", "eax", "ds", "es", "fs", "gs", "",
+                    "eip", "cs", "eflags", "esp", "ss",
+                ],
+            },
+            Arch::Arm => Self {
+                machine: 40,
+                is_64: false,
+                size: 148,
+                regs_offset: 72,
+                regs: &[
+                    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11",
+                    "r12", "sp", "lr", "pc", "cpsr", "",
+                ],
+            },
+            _ => return Err(anyhow!("Core dumps are not supported for {arch:?}")),
+        })
+    }
+
+    fn word(&self) -> usize {
+        if self.is_64 {
+            8
+        } else {
+            4
+        }
+    }
+
+    /// Build `elf_prstatus` for the current process from register values
+    fn prstatus(&self, signal: i64, registers: &BTreeMap<String, Vec<u8>>) -> Vec<u8> {
+        let mut prstatus = vec![0u8; self.size];
+        let word = self.word();
+
+        // pr_cursig follows the three ints of pr_info, and pr_pid follows the two
+        // signal masks after it
+        prstatus[12..14].copy_from_slice(&(signal as u16).to_le_bytes());
+        let pid_offset = 16 + 2 * word;
+        prstatus[pid_offset..pid_offset + 4].copy_from_slice(&process::id().to_le_bytes());
+
+        for (i, name) in self.regs.iter().enumerate() {
+            let Some(value) = registers.get(*name) else {
+                continue;
+            };
+
+            let offset = self.regs_offset + i * word;
+            let len = value.len().min(word);
+            prstatus[offset..offset + len].copy_from_slice(&value[..len]);
+        }
+
+        prstatus
+    }
+}
+
+/// Append an ELF note, padding the name and descriptor to 4 bytes
+fn push_note(notes: &mut Vec<u8>, name: &str, kind: u32, desc: &[u8]) {
+    let name = [name.as_bytes(), b"\0"].concat();
+
+    notes.extend((name.len() as u32).to_le_bytes());
+    notes.extend((desc.len() as u32).to_le_bytes());
+    notes.extend(kind.to_le_bytes());
+
+    for data in [name.as_slice(), desc] {
+        notes.extend(data);
+        notes.resize(notes.len().next_multiple_of(4), 0);
+    }
+}
+
+/// A loadable segment of an ELF core: the memory at `vaddr`
+struct Segment {
+    vaddr: u64,
+    data: Vec<u8>,
+}
+
+/// Write an ELF core with a note segment followed by one loadable segment per range
+fn write_core<W>(mut w: W, layout: &CoreLayout, notes: &[u8], segments: &[Segment]) -> Result<()>
+where
+    W: Write,
+{
+    let (ehsize, phentsize) = if layout.is_64 { (64, 56) } else { (52, 32) };
+    let phnum = segments.len() + 1;
+    let mut offset = (ehsize + phentsize * phnum) as u64;
+
+    // Write a field of the header as 4 or 8 bytes, depending on the class
+    let addr = |h: &mut Vec<u8>, value: u64| {
+        if layout.is_64 {
+            h.extend(value.to_le_bytes());
+        } else {
+            h.extend((value as u32).to_le_bytes());
+        }
+    };
+
+    let mut header = Vec::with_capacity(ehsize + phentsize * phnum);
+    header.extend(b"\x7fELF");
+    header.extend([if layout.is_64 { 2 } else { 1 }, 1, 1, 0]);
+    header.extend([0; 8]);
+    header.extend(4u16.to_le_bytes());
+    header.extend(layout.machine.to_le_bytes());
+    header.extend(1u32.to_le_bytes());
+    addr(&mut header, 0);
+    addr(&mut header, ehsize as u64);
+    addr(&mut header, 0);
+    header.extend(0u32.to_le_bytes());
+    header.extend((ehsize as u16).to_le_bytes());
+    header.extend((phentsize as u16).to_le_bytes());
+    header.extend((phnum as u16).to_le_bytes());
+    header.extend([0; 6]);
+
+    let mut program_header = |h: &mut Vec<u8>, kind: u32, flags: u32, vaddr: u64, len: u64| {
+        h.extend(kind.to_le_bytes());
+
+        if layout.is_64 {
+            h.extend(flags.to_le_bytes());
+        }
+
+        addr(h, offset);
+        addr(h, vaddr);
+        addr(h, 0);
+        addr(h, len);
+        addr(h, len);
+
+        if !layout.is_64 {
+            h.extend(flags.to_le_bytes());
+        }
+
+        addr(h, 1);
+        offset += len;
+    };
+
+    program_header(&mut header, PT_NOTE, 0, 0, notes.len() as u64);
+
+    for segment in segments {
+        program_header(
+            &mut header,
+            PT_LOAD,
+            0b111,
+            segment.vaddr,
+            segment.data.len() as u64,
+        );
+    }
+
+    w.write_all(&header)?;
+    w.write_all(notes)?;
+
+    for segment in segments {
+        w.write_all(&segment.data)?;
+    }
+
+    w.flush()?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+/// Takes dumps when the configured triggers fire, up to the configured limit
+pub struct Dumper {
+    config: DumpConfig,
+    taken: usize,
+}
+
+impl Dumper {
+    pub fn new(config: DumpConfig) -> Self {
+        Self { config, taken: 0 }
+    }
+
+    /// Whether executing the instruction at `pc` triggers a dump
+    pub fn wants_pc(&self, pc: u64) -> bool {
+        self.config.pc == Some(pc)
+    }
+
+    /// Returns the trigger fired by a syscall, if any
+    pub fn on_syscall(&self, arch: Arch, num: i64, args: [u64; 8]) -> Option<DumpTrigger> {
+        if self.config.syscall == Some(num) {
+            return Some(DumpTrigger::Syscall(num));
+        }
+
+        if !self.config.on_abort {
+            return None;
+        }
+
+        // kill and tgkill could target another process or thread, but tkill and tgkill
+        // with the process's own IDs are what abort and raise use
+        let signal = match arch.syscall(num)? {
+            Syscall::Kill | Syscall::Tkill => args[1],
+            Syscall::Tgkill => args[2],
+            _ => return None,
+        } as i64;
+
+        matches!(signal, SIGABRT | SIGBUS | SIGFPE | SIGSEGV).then_some(DumpTrigger::Signal(signal))
+    }
+
+    /// Take a dump for `trigger` on the current vCPU, whose registers are `registers`.
+    /// Returns the path of the dump, or `None` once the limit has been reached.
+    pub fn dump(
+        &mut self,
+        arch: Arch,
+        trigger: DumpTrigger,
+        registers: &[RegisterDescriptor<'static>],
+    ) -> Result<Option<PathBuf>> {
+        if self.taken >= self.config.limit {
+            return Ok(None);
+        }
+
+        self.taken += 1;
+
+        let path = self
+            .config
+            .dir
+            .join(format!("dump-{}-{trigger}.core", self.taken));
+
+        // QEMU stops the VM to dump it, which waits for this vCPU to leave the callback, so
+        // the dump must be detached and requested from another thread
+        if let Some(qmp_socket) = self.config.qmp_socket.clone() {
+            let dump_path = path.clone();
+
+            spawn(move || {
+                if let Err(e) = Qmp::connect(&qmp_socket)
+                    .and_then(|mut qmp| qmp.dump_guest_memory(dump_path, true))
+                {
+                    log(format!("Failed to dump guest memory over QMP: {e}"));
+                }
+            });
+
+            return Ok(Some(path));
+        }
+
+        let registers = registers
+            .iter()
+            .map(|r| Ok((r.name.clone(), r.read()?)))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        let segments = self
+            .config
+            .ranges
+            .iter()
+            .map(|range| {
+                Ok(Segment {
+                    vaddr: range.start,
+                    data: qemu_plugin_read_memory_vaddr(
+                        range.start,
+                        (range.end - range.start) as usize,
+                    )?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let signal = match trigger {
+            DumpTrigger::Signal(signal) => signal,
+            DumpTrigger::Pc(_) | DumpTrigger::Syscall(_) => 0,
+        };
+
+        let layout = CoreLayout::for_arch(arch)?;
+        let mut notes = Vec::new();
+        push_note(
+            &mut notes,
+            "CORE",
+            NT_PRSTATUS,
+            &layout.prstatus(signal, &registers),
+        );
+        push_note(
+            &mut notes,
+            "QEMU-RS",
+            NT_QEMU_RS_REGISTERS,
+            &serde_json::to_vec(&registers)?,
+        );
+
+        write_core(create_sink(&path)?, &layout, &notes, &segments)?;
+
+        Ok(Some(path))
+    }
+}
@@ -0,0 +1,701 @@
+//! A micro-interpreter re-deriving the values instructions compute, for offline analysis
+//!
+//! Registers are only captured when `log_registers` is set, and memory values are not
+//! captured at all. [`MicroInterp`] steps through the instructions of a trace from a known
+//! [`MachineState`], such as the registers of one instruction event, and computes what each
+//! instruction reads and writes, so tools reading a trace can recover values between
+//! snapshots.
+//!
+//! Only x86_64 is supported, and only a subset of its integer instructions: moves and
+//! extensions, `lea`, arithmetic and logic, shifts, two and three operand `imul`, `push`,
+//! `pop`, `xchg`, `call` and `ret`. Flags are not modelled, so comparisons and branches have
+//! no effects other than the stack accesses of calls. An instruction which is not supported,
+//! or which reads a register or memory the state does not know, is an error and leaves the
+//! state unchanged.
+
+use crate::arch::Arch;
+#[cfg(not(feature = "plugin-api-v1"))]
+use crate::Registers;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use yaxpeax_arch::LengthedInstruction;
+use yaxpeax_x86::amd64::{
+    register_class, InstDecoder, Instruction, Opcode, Operand, RegSpec, Segment,
+};
+
+/// The 64-bit general purpose registers, in encoding order
+const GPRS: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+/// A place an instruction reads or writes
+pub enum Location {
+    /// A register, by the name of its full 64-bit register, so that `eax` and `al` are both
+    /// `rax`
+    Register(String),
+    Memory {
+        address: u64,
+        size: usize,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A value read from or written to a location. Register values are the whole register's.
+pub struct Access {
+    pub location: Location,
+    pub value: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// What an instruction read and wrote, in order
+pub struct Effects {
+    pub reads: Vec<Access>,
+    pub writes: Vec<Access>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The registers an instruction uses and defines, and whether it accesses memory, known
+/// without any state
+pub struct DefUse {
+    /// Registers whose values flow into the values the instruction writes
+    pub uses: BTreeSet<String>,
+    /// Registers the instruction writes
+    pub defs: BTreeSet<String>,
+    /// Registers only used to compute the addresses of memory operands
+    pub addresses: BTreeSet<String>,
+    /// Registers defined only from their own value, such as the stack pointer by `push`
+    pub updates: BTreeSet<String>,
+    pub loads: bool,
+    pub stores: bool,
+}
+
+impl DefUse {
+    fn operand(&mut self, operand: Operand, used: bool, defined: bool) -> Result<()> {
+        match operand {
+            Operand::Register { reg } => {
+                let (name, _, size) = register(reg)?;
+
+                // Writes to 8 and 16-bit registers keep the rest of the full register
+                if used || (defined && size < 4) {
+                    self.uses.insert(name.to_string());
+                }
+
+                if defined {
+                    self.defs.insert(name.to_string());
+                }
+            }
+            _ if is_memory(&operand) => {
+                for reg in address_registers(&operand) {
+                    self.addresses.insert(register(reg)?.0.to_string());
+                }
+
+                self.loads |= used;
+                self.stores |= defined;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The known registers and memory of a vCPU
+pub struct MachineState {
+    pub registers: BTreeMap<String, u64>,
+    /// Known bytes of memory, by address
+    pub memory: BTreeMap<u64, u8>,
+}
+
+impl MachineState {
+    #[cfg(not(feature = "plugin-api-v1"))]
+    /// Create a state from registers captured with an instruction event. Registers wider
+    /// than 64 bits are left out.
+    pub fn from_registers(registers: &Registers) -> Self {
+        Self {
+            registers: registers
+                .0
+                .iter()
+                .filter(|(_, value)| value.len() <= 8)
+                .map(|(name, value)| {
+                    let mut bytes = [0u8; 8];
+
+                    bytes[..value.len()].copy_from_slice(value);
+
+                    (name.clone(), u64::from_le_bytes(bytes))
+                })
+                .collect(),
+            memory: BTreeMap::new(),
+        }
+    }
+
+    #[cfg(not(feature = "plugin-api-v1"))]
+    /// Replace the known registers with those captured with a later instruction event,
+    /// keeping the known memory
+    pub fn update_registers(&mut self, registers: &Registers) {
+        self.registers
+            .extend(Self::from_registers(registers).registers);
+    }
+
+    /// Record the contents of memory at `address`, such as from a memory dump
+    pub fn write_memory(&mut self, address: u64, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.memory.insert(address.wrapping_add(i as u64), *byte);
+        }
+    }
+
+    /// Read `size` bytes of memory at `address` as a little-endian value, if all are known
+    pub fn read_memory(&self, address: u64, size: usize) -> Option<u64> {
+        (0..size).rev().try_fold(0u64, |value, i| {
+            let byte = self.memory.get(&address.wrapping_add(i as u64))?;
+
+            Some(value << 8 | *byte as u64)
+        })
+    }
+
+    fn apply(&mut self, access: &Access) {
+        match &access.location {
+            Location::Register(name) => {
+                self.registers.insert(name.clone(), access.value);
+            }
+            Location::Memory { address, size } => {
+                self.write_memory(*address, &access.value.to_le_bytes()[..*size]);
+            }
+        }
+    }
+}
+
+/// The full register a register operand is part of, the offset of the operand in it in
+/// bits, and the operand's width in bytes
+fn register(reg: RegSpec) -> Result<(&'static str, u32, usize)> {
+    let num = reg.num() as usize;
+    let class = reg.class();
+
+    if class == register_class::RIP {
+        Ok(("rip", 0, 8))
+    } else if class == register_class::Q {
+        Ok((GPRS[num], 0, 8))
+    } else if class == register_class::D {
+        Ok((GPRS[num], 0, 4))
+    } else if class == register_class::W {
+        Ok((GPRS[num], 0, 2))
+    } else if class == register_class::RB {
+        Ok((GPRS[num], 0, 1))
+    } else if class == register_class::B && num >= 4 {
+        // Without a REX prefix, byte registers 4 to 7 are `ah`, `ch`, `dh` and `bh`
+        Ok((GPRS[num - 4], 8, 1))
+    } else if class == register_class::B {
+        Ok((GPRS[num], 0, 1))
+    } else {
+        Err(anyhow!("Register {reg} is not supported"))
+    }
+}
+
+fn mask(size: usize) -> u64 {
+    if size >= 8 {
+        u64::MAX
+    } else {
+        (1 << (size * 8)) - 1
+    }
+}
+
+fn sign_extend(value: u64, size: usize) -> u64 {
+    let shift = 64 - size as u32 * 8;
+
+    (((value << shift) as i64) >> shift) as u64
+}
+
+/// The registers a memory operand's address is computed from
+fn address_registers(operand: &Operand) -> Vec<RegSpec> {
+    match *operand {
+        Operand::MemDeref { base } | Operand::Disp { base, .. } => vec![base],
+        Operand::MemIndexScale { index, .. } | Operand::MemIndexScaleDisp { index, .. } => {
+            vec![index]
+        }
+        Operand::MemBaseIndexScale { base, index, .. }
+        | Operand::MemBaseIndexScaleDisp { base, index, .. } => vec![base, index],
+        _ => Vec::new(),
+    }
+}
+
+fn is_memory(operand: &Operand) -> bool {
+    matches!(
+        operand,
+        Operand::AbsoluteU32 { .. }
+            | Operand::AbsoluteU64 { .. }
+            | Operand::MemDeref { .. }
+            | Operand::Disp { .. }
+            | Operand::MemIndexScale { .. }
+            | Operand::MemIndexScaleDisp { .. }
+            | Operand::MemBaseIndexScale { .. }
+            | Operand::MemBaseIndexScaleDisp { .. }
+    )
+}
+
+/// Executes one instruction against a state, collecting its effects without applying them
+struct Execution<'a> {
+    state: &'a MachineState,
+    insn: &'a Instruction,
+    /// The address of the next instruction, which is `rip` while this one executes
+    next: u64,
+    effects: Effects,
+}
+
+impl Execution<'_> {
+    fn read_full(&mut self, name: &str) -> Result<u64> {
+        let value = if name == "rip" {
+            self.next
+        } else if let Some(access) = self
+            .effects
+            .writes
+            .iter()
+            .rev()
+            .find(|a| a.location == Location::Register(name.to_string()))
+        {
+            access.value
+        } else {
+            *self
+                .state
+                .registers
+                .get(name)
+                .ok_or_else(|| anyhow!("Register {name} is unknown"))?
+        };
+
+        self.effects.reads.push(Access {
+            location: Location::Register(name.to_string()),
+            value,
+        });
+
+        Ok(value)
+    }
+
+    fn read_register(&mut self, reg: RegSpec) -> Result<u64> {
+        let (name, shift, size) = register(reg)?;
+
+        Ok((self.read_full(name)? >> shift) & mask(size))
+    }
+
+    fn write_full(&mut self, name: &str, value: u64) {
+        self.effects.writes.push(Access {
+            location: Location::Register(name.to_string()),
+            value,
+        });
+    }
+
+    fn write_register(&mut self, reg: RegSpec, value: u64) -> Result<()> {
+        let (name, shift, size) = register(reg)?;
+        let value = match size {
+            // Writes to 32-bit registers zero the upper half of the full register
+            8 | 4 => value & mask(size),
+            _ => {
+                let full = self.read_full(name)?;
+
+                full & !(mask(size) << shift) | (value & mask(size)) << shift
+            }
+        };
+
+        self.write_full(name, value);
+
+        Ok(())
+    }
+
+    fn read_memory(&mut self, address: u64, size: usize) -> Result<u64> {
+        let mut value = 0;
+
+        for i in (0..size as u64).rev() {
+            let address = address.wrapping_add(i);
+            let byte = match self
+                .effects
+                .writes
+                .iter()
+                .rev()
+                .find_map(|a| match a.location {
+                    Location::Memory {
+                        address: start,
+                        size,
+                    } if address.wrapping_sub(start) < size as u64 => {
+                        Some((a.value >> (address.wrapping_sub(start) * 8)) as u8)
+                    }
+                    _ => None,
+                }) {
+                Some(byte) => byte,
+                None => *self
+                    .state
+                    .memory
+                    .get(&address)
+                    .ok_or_else(|| anyhow!("Memory at {address:#x} is unknown"))?,
+            };
+
+            value = value << 8 | byte as u64;
+        }
+
+        self.effects.reads.push(Access {
+            location: Location::Memory { address, size },
+            value,
+        });
+
+        Ok(value)
+    }
+
+    fn write_memory(&mut self, address: u64, size: usize, value: u64) {
+        self.effects.writes.push(Access {
+            location: Location::Memory { address, size },
+            value: value & mask(size),
+        });
+    }
+
+    /// The effective address of memory operand `i`
+    fn address(&mut self, i: u8) -> Result<u64> {
+        let operand = self.insn.operand(i);
+        let reg = |e: &mut Self, reg: RegSpec| e.read_register(reg);
+        let mut address = match operand {
+            Operand::AbsoluteU32 { addr } => addr as u64,
+            Operand::AbsoluteU64 { addr } => addr,
+            Operand::MemDeref { base } => reg(self, base)?,
+            Operand::Disp { base, disp } => reg(self, base)?.wrapping_add(disp as i64 as u64),
+            Operand::MemIndexScale { index, scale } => reg(self, index)?.wrapping_mul(scale as u64),
+            Operand::MemIndexScaleDisp { index, scale, disp } => reg(self, index)?
+                .wrapping_mul(scale as u64)
+                .wrapping_add(disp as i64 as u64),
+            Operand::MemBaseIndexScale { base, index, scale } => {
+                reg(self, base)?.wrapping_add(reg(self, index)?.wrapping_mul(scale as u64))
+            }
+            Operand::MemBaseIndexScaleDisp {
+                base,
+                index,
+                scale,
+                disp,
+            } => reg(self, base)?
+                .wrapping_add(reg(self, index)?.wrapping_mul(scale as u64))
+                .wrapping_add(disp as i64 as u64),
+            _ => return Err(anyhow!("Operand {operand} is not a memory operand")),
+        };
+
+        // Other segments have a zero base in long mode
+        match self.insn.segment_override_for_op(i) {
+            Some(Segment::FS) => address = address.wrapping_add(self.read_full("fs_base")?),
+            Some(Segment::GS) => address = address.wrapping_add(self.read_full("gs_base")?),
+            _ => {}
+        }
+
+        Ok(address)
+    }
+
+    fn memory_size(&self) -> Result<usize> {
+        self.insn
+            .mem_size()
+            .and_then(|size| size.bytes_size())
+            .map(|size| size as usize)
+            .ok_or_else(|| anyhow!("Memory access size of {} is unknown", self.insn))
+    }
+
+    /// The width of operand `i` in bytes
+    fn size(&self, i: u8) -> Result<usize> {
+        let operand = self.insn.operand(i);
+
+        match operand {
+            Operand::Register { reg } => Ok(register(reg)?.2),
+            _ if is_memory(&operand) => self.memory_size(),
+            _ => Err(anyhow!("Operand {operand} has no width")),
+        }
+    }
+
+    /// Read operand `i`, sign-extending immediates to 64 bits
+    fn read(&mut self, i: u8) -> Result<u64> {
+        let operand = self.insn.operand(i);
+
+        match operand {
+            Operand::ImmediateI8 { imm } => Ok(imm as i64 as u64),
+            Operand::ImmediateU8 { imm } => Ok(imm as u64),
+            Operand::ImmediateI16 { imm } => Ok(imm as i64 as u64),
+            Operand::ImmediateU16 { imm } => Ok(imm as u64),
+            Operand::ImmediateI32 { imm } => Ok(imm as i64 as u64),
+            Operand::ImmediateU32 { imm } => Ok(imm as u64),
+            Operand::ImmediateI64 { imm } => Ok(imm as u64),
+            Operand::ImmediateU64 { imm } => Ok(imm),
+            Operand::Register { reg } => self.read_register(reg),
+            _ if is_memory(&operand) => {
+                let address = self.address(i)?;
+                let size = self.memory_size()?;
+
+                self.read_memory(address, size)
+            }
+            _ => Err(anyhow!("Operand {operand} is not supported")),
+        }
+    }
+
+    fn write(&mut self, i: u8, value: u64) -> Result<()> {
+        let operand = self.insn.operand(i);
+
+        match operand {
+            Operand::Register { reg } => self.write_register(reg, value),
+            _ if is_memory(&operand) => {
+                let address = self.address(i)?;
+                let size = self.memory_size()?;
+
+                self.write_memory(address, size, value);
+
+                Ok(())
+            }
+            _ => Err(anyhow!("Operand {operand} cannot be written")),
+        }
+    }
+
+    fn push(&mut self, value: u64) -> Result<()> {
+        let rsp = self.read_full("rsp")?.wrapping_sub(8);
+
+        self.write_full("rsp", rsp);
+        self.write_memory(rsp, 8, value);
+
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<u64> {
+        let rsp = self.read_full("rsp")?;
+        let value = self.read_memory(rsp, 8)?;
+
+        self.write_full("rsp", rsp.wrapping_add(8));
+
+        Ok(value)
+    }
+
+    fn execute(&mut self) -> Result<()> {
+        let insn = self.insn;
+        let opcode = insn.opcode();
+
+        match opcode {
+            Opcode::MOV => {
+                let value = self.read(1)?;
+
+                self.write(0, value)
+            }
+            Opcode::MOVZX => {
+                let value = self.read(1)?;
+
+                self.write(0, value)
+            }
+            Opcode::MOVSX | Opcode::MOVSXD => {
+                let size = self.size(1)?;
+                let value = self.read(1)?;
+
+                self.write(0, sign_extend(value, size))
+            }
+            Opcode::LEA => {
+                let address = self.address(1)?;
+
+                self.write(0, address)
+            }
+            Opcode::ADD
+            | Opcode::SUB
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::XOR
+            | Opcode::SHL
+            | Opcode::SAL
+            | Opcode::SHR
+            | Opcode::SAR
+            | Opcode::IMUL
+                if insn.operand_count() == 2 =>
+            {
+                let size = self.size(0)?;
+
+                // Zeroing a register with itself does not depend on its value
+                if opcode == Opcode::XOR && insn.operand(0) == insn.operand(1) {
+                    return self.write(0, 0);
+                }
+
+                let a = self.read(0)?;
+                let b = self.read(1)?;
+                let count = (b & if size == 8 { 63 } else { 31 }) as u32;
+                let value = match opcode {
+                    Opcode::ADD => a.wrapping_add(b),
+                    Opcode::SUB => a.wrapping_sub(b),
+                    Opcode::AND => a & b,
+                    Opcode::OR => a | b,
+                    Opcode::XOR => a ^ b,
+                    Opcode::SHL | Opcode::SAL => a.checked_shl(count).unwrap_or(0),
+                    Opcode::SHR => (a & mask(size)).checked_shr(count).unwrap_or(0),
+                    Opcode::SAR => (sign_extend(a, size) as i64 >> count) as u64,
+                    _ => a.wrapping_mul(b),
+                };
+
+                self.write(0, value)
+            }
+            Opcode::IMUL if insn.operand_count() == 3 => {
+                let value = self.read(1)?.wrapping_mul(self.read(2)?);
+
+                self.write(0, value)
+            }
+            Opcode::INC | Opcode::DEC | Opcode::NEG | Opcode::NOT => {
+                let a = self.read(0)?;
+                let value = match opcode {
+                    Opcode::INC => a.wrapping_add(1),
+                    Opcode::DEC => a.wrapping_sub(1),
+                    Opcode::NEG => a.wrapping_neg(),
+                    _ => !a,
+                };
+
+                self.write(0, value)
+            }
+            Opcode::XCHG => {
+                let a = self.read(0)?;
+                let b = self.read(1)?;
+
+                self.write(0, b)?;
+                self.write(1, a)
+            }
+            Opcode::PUSH => {
+                let value = self.read(0)?;
+
+                self.push(value)
+            }
+            Opcode::POP => {
+                let value = self.pop()?;
+
+                self.write(0, value)
+            }
+            Opcode::CALL => {
+                let next = self.next;
+
+                self.push(next)
+            }
+            Opcode::RETURN => self.pop().map(|_| ()),
+            Opcode::CMP | Opcode::TEST | Opcode::NOP | Opcode::JMP => Ok(()),
+            _ if opcode.is_jcc() => Ok(()),
+            _ => Err(anyhow!("Instruction {insn} is not supported")),
+        }
+    }
+}
+
+/// Steps through the instructions of a trace, keeping track of the values they compute
+#[derive(Clone)]
+pub struct MicroInterp {
+    decoder: InstDecoder,
+    state: MachineState,
+}
+
+impl MicroInterp {
+    /// Create an interpreter for `arch` starting from `state`
+    pub fn new(arch: Arch, state: MachineState) -> Result<Self> {
+        match arch {
+            Arch::X86_64 => Ok(Self {
+                decoder: InstDecoder::default(),
+                state,
+            }),
+            _ => Err(anyhow!("Emulation is not supported for {arch:?}")),
+        }
+    }
+
+    pub fn state(&self) -> &MachineState {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut MachineState {
+        &mut self.state
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Instruction> {
+        self.decoder
+            .decode_slice(data)
+            .map_err(|e| anyhow!("Failed to decode instruction: {e}"))
+    }
+
+    /// Execute the instruction `data` at `pc`, returning what it read and wrote. The state
+    /// is only updated if the instruction could be executed.
+    pub fn step(&mut self, pc: u64, data: &[u8]) -> Result<Effects> {
+        let insn = self.decode(data)?;
+        let mut execution = Execution {
+            state: &self.state,
+            insn: &insn,
+            next: pc.wrapping_add(insn.len().to_const()),
+            effects: Effects::default(),
+        };
+
+        execution.execute()?;
+
+        let effects = execution.effects;
+
+        for access in &effects.writes {
+            self.state.apply(access);
+        }
+
+        Ok(effects)
+    }
+
+    /// Returns the registers the instruction `data` uses and defines, without executing it
+    pub fn def_use(&self, data: &[u8]) -> Result<DefUse> {
+        let insn = self.decode(data)?;
+        let mut def_use = DefUse::default();
+        let opcode = insn.opcode();
+
+        match opcode {
+            Opcode::MOV | Opcode::MOVZX | Opcode::MOVSX | Opcode::MOVSXD => {
+                def_use.operand(insn.operand(0), false, true)?;
+                def_use.operand(insn.operand(1), true, false)?;
+            }
+            Opcode::LEA => {
+                def_use.operand(insn.operand(0), false, true)?;
+
+                for reg in address_registers(&insn.operand(1)) {
+                    def_use.uses.insert(register(reg)?.0.to_string());
+                }
+            }
+            Opcode::XOR if insn.operand(0) == insn.operand(1) => {
+                def_use.operand(insn.operand(0), false, true)?
+            }
+            Opcode::ADD
+            | Opcode::SUB
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::XOR
+            | Opcode::SHL
+            | Opcode::SAL
+            | Opcode::SHR
+            | Opcode::SAR
+            | Opcode::IMUL
+                if insn.operand_count() == 2 =>
+            {
+                def_use.operand(insn.operand(0), true, true)?;
+                def_use.operand(insn.operand(1), true, false)?;
+            }
+            Opcode::IMUL if insn.operand_count() == 3 => {
+                def_use.operand(insn.operand(0), false, true)?;
+                def_use.operand(insn.operand(1), true, false)?;
+            }
+            Opcode::INC | Opcode::DEC | Opcode::NEG | Opcode::NOT => {
+                def_use.operand(insn.operand(0), true, true)?
+            }
+            Opcode::XCHG => {
+                def_use.operand(insn.operand(0), true, true)?;
+                def_use.operand(insn.operand(1), true, true)?;
+            }
+            Opcode::PUSH => {
+                def_use.operand(insn.operand(0), true, false)?;
+                def_use.stores = true;
+            }
+            Opcode::POP => {
+                def_use.operand(insn.operand(0), false, true)?;
+                def_use.loads = true;
+            }
+            Opcode::CALL => def_use.stores = true,
+            Opcode::RETURN => def_use.loads = true,
+            Opcode::CMP | Opcode::TEST | Opcode::NOP | Opcode::JMP => {}
+            _ if opcode.is_jcc() => {}
+            _ => return Err(anyhow!("Instruction {insn} is not supported")),
+        }
+
+        if matches!(
+            opcode,
+            Opcode::PUSH | Opcode::POP | Opcode::CALL | Opcode::RETURN
+        ) {
+            def_use.addresses.insert("rsp".to_string());
+            def_use.defs.insert("rsp".to_string());
+            def_use.updates.insert("rsp".to_string());
+        }
+
+        Ok(def_use)
+    }
+}
@@ -0,0 +1,251 @@
+//! Compact encoding of executed instruction addresses
+//!
+//! Per-instruction traces are dominated by the cost of building and serializing one event per
+//! executed instruction. Instead, each vCPU accumulates the addresses it executes into a
+//! [`PcEncoder`], which stores the difference from the previous address as a zigzag varint.
+//! Consecutive instructions are usually only a few bytes apart, so most addresses take a
+//! single byte, and a full batch is sent as one [`PcBatch`] event.
+//!
+//! The encoders are held in [`PcBuffers`], one slot per vCPU, so logging an address only
+//! locks the executing vCPU's encoder and vCPUs never contend with each other.
+
+use anyhow::{anyhow, Result};
+use qemu_plugin::VCPUIndex;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Mutex};
+use typed_builder::TypedBuilder;
+
+/// The number of addresses buffered before a batch is sent
+pub const BATCH_SIZE: usize = 4096;
+/// The number of vCPUs given their own encoder slot. QEMU supports at most a few hundred
+/// vCPUs in system mode, but user mode assigns a new index to every guest thread.
+pub const VCPU_SLOTS: usize = 256;
+
+/// Map a signed delta onto an unsigned integer, keeping small magnitudes small
+#[inline]
+pub fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Invert [`zigzag`]
+#[inline]
+pub fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// The number of bytes needed to encode `value` as a varint
+#[inline]
+pub fn varint_len(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
+}
+
+/// Append `value` to `buf` as a little-endian base-128 varint. The length is computed up
+/// front, so the only branch is the loop over the output bytes.
+#[inline]
+pub fn write_varint(buf: &mut Vec<u8>, value: u64) {
+    let len = varint_len(value);
+
+    for i in 0..len {
+        let continuation = ((i + 1 < len) as u8) << 7;
+        buf.push(((value >> (7 * i)) as u8 & 0x7f) | continuation);
+    }
+}
+
+/// Read a varint from the start of `data`, returning the value and the number of bytes read
+#[inline]
+pub fn read_varint(data: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+
+    for (i, byte) in data.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+
+    None
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// A batch of consecutively executed instruction addresses from one vCPU
+pub struct PcBatch {
+    pub vcpu_index: VCPUIndex,
+    /// The address the first delta is relative to
+    pub base: u64,
+    pub count: u64,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+impl PcBatch {
+    /// Decode the addresses in the batch
+    pub fn decode(&self) -> Result<Vec<u64>> {
+        // Every address takes at least one byte, so a count larger than the data is corrupt
+        // and must not size the allocation
+        let mut pcs = Vec::with_capacity((self.count as usize).min(self.data.len()));
+        let mut last = self.base;
+        let mut data = self.data.as_slice();
+
+        while !data.is_empty() {
+            let (delta, len) =
+                read_varint(data).ok_or_else(|| anyhow!("Truncated varint in pc batch"))?;
+            last = last.wrapping_add(unzigzag(delta) as u64);
+            pcs.push(last);
+            data = &data[len..];
+        }
+
+        if pcs.len() as u64 != self.count {
+            return Err(anyhow!(
+                "Expected {} pcs in batch, decoded {}",
+                self.count,
+                pcs.len()
+            ));
+        }
+
+        Ok(pcs)
+    }
+
+    /// Returns the batch with `f` applied to its base and each of its addresses
+    pub fn map<F>(&self, f: F) -> Result<Self>
+    where
+        F: Fn(u64) -> u64,
+    {
+        let mut encoder = PcEncoder {
+            last: f(self.base),
+            ..Default::default()
+        };
+
+        for pc in self.decode()? {
+            encoder.push(f(pc));
+        }
+
+        let mut batch = encoder.take(self.vcpu_index);
+        batch.base = f(self.base);
+
+        Ok(batch)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Accumulates executed instruction addresses for one vCPU
+pub struct PcEncoder {
+    base: u64,
+    last: u64,
+    count: u64,
+    data: Vec<u8>,
+}
+
+impl PcEncoder {
+    /// Append an executed address
+    #[inline]
+    pub fn push(&mut self, pc: u64) {
+        if self.count == 0 {
+            self.base = self.last;
+        }
+
+        write_varint(&mut self.data, zigzag(pc.wrapping_sub(self.last) as i64));
+        self.last = pc;
+        self.count += 1;
+    }
+
+    /// Whether the batch is full and should be sent
+    pub fn is_full(&self) -> bool {
+        self.count as usize >= BATCH_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Take the addresses accumulated so far as a batch. Deltas continue from the last
+    /// address, so the next batch remains small.
+    pub fn take(&mut self, vcpu_index: VCPUIndex) -> PcBatch {
+        let batch = PcBatch::builder()
+            .vcpu_index(vcpu_index)
+            .base(self.base)
+            .count(self.count)
+            .data(std::mem::replace(
+                &mut self.data,
+                Vec::with_capacity(BATCH_SIZE * 2),
+            ))
+            .build();
+
+        self.count = 0;
+        batch
+    }
+}
+
+#[derive(Debug, Default)]
+#[repr(align(64))]
+/// An encoder on its own cache line, so vCPUs updating neighboring slots do not share one
+struct Slot(Mutex<PcEncoder>);
+
+#[derive(Debug)]
+/// The pc encoders of every vCPU
+pub struct PcBuffers {
+    slots: Box<[Slot]>,
+    /// Encoders of vCPUs whose index is past the last slot
+    overflow: Mutex<HashMap<VCPUIndex, PcEncoder>>,
+}
+
+impl Default for PcBuffers {
+    fn default() -> Self {
+        Self {
+            slots: (0..VCPU_SLOTS).map(|_| Slot::default()).collect(),
+            overflow: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl PcBuffers {
+    /// Append an executed address to the encoder of `vcpu_index`, returning a batch if the
+    /// encoder filled up and should be sent
+    #[inline]
+    pub fn push(&self, vcpu_index: VCPUIndex, pc: u64) -> Result<Option<PcBatch>> {
+        let push = |encoder: &mut PcEncoder| {
+            encoder.push(pc);
+            encoder.is_full().then(|| encoder.take(vcpu_index))
+        };
+
+        match self.slots.get(vcpu_index as usize) {
+            Some(Slot(encoder)) => encoder
+                .lock()
+                .map(|mut encoder| push(&mut encoder))
+                .map_err(|e| anyhow!("Failed to lock pcs: {e}")),
+            None => self
+                .overflow
+                .lock()
+                .map(|mut overflow| push(overflow.entry(vcpu_index).or_default()))
+                .map_err(|e| anyhow!("Failed to lock pcs: {e}")),
+        }
+    }
+
+    /// Take the addresses accumulated so far by every vCPU as batches
+    pub fn drain(&self) -> Result<Vec<PcBatch>> {
+        let mut batches = Vec::new();
+
+        for (vcpu_index, Slot(encoder)) in self.slots.iter().enumerate() {
+            let mut encoder = encoder
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock pcs: {e}"))?;
+
+            if !encoder.is_empty() {
+                batches.push(encoder.take(vcpu_index as VCPUIndex));
+            }
+        }
+
+        for (vcpu_index, encoder) in self
+            .overflow
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock pcs: {e}"))?
+            .iter_mut()
+        {
+            if !encoder.is_empty() {
+                batches.push(encoder.take(*vcpu_index));
+            }
+        }
+
+        Ok(batches)
+    }
+}
@@ -0,0 +1,146 @@
+//! Usage of the exclusive monitor by load-/store-exclusive loops, to find livelocks and
+//! loops which retry excessively
+//!
+//! Each store-exclusive is paired with the load-exclusive which opened the monitor on its
+//! vCPU, and its outcome is read from its status register when the next instruction in its
+//! block executes. A compiler-generated loop branches on the status right after the store,
+//! so stores which end their block are rare, and are counted without an outcome.
+//!
+//! ARM's load-/store-exclusives and RISC-V's `lr.w`/`lr.d` and `sc.w`/`sc.d` are decoded. On
+//! both, a store writes 0 to its status register on success.
+
+use crate::{
+    arch::{Arch, RISCV_REGISTERS},
+    heap::read_register,
+};
+use anyhow::Result;
+use qemu_plugin::{path::create_sink, RegisterDescriptor, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The outcomes of the exclusive sequences opened by one load-exclusive
+pub struct ExclusiveSite {
+    pub load_pc: u64,
+    /// The store-exclusives which closed sequences opened here
+    pub store_pcs: BTreeSet<u64>,
+    pub attempts: u64,
+    pub successes: u64,
+    pub failures: u64,
+    /// Stores whose outcome was not observed
+    pub unobserved: u64,
+    /// The most store failures in a row on one vCPU, i.e. the longest retry loop
+    pub max_retries: u64,
+    /// Sequences abandoned by `clrex` or another load-exclusive before any store
+    pub abandoned: u64,
+}
+
+#[derive(Debug, Default)]
+struct VcpuState {
+    /// The load-exclusive which opened the monitor, if it is open
+    open: Option<u64>,
+    /// Store failures in a row
+    retries: u64,
+}
+
+#[derive(Debug)]
+/// Pairs load- and store-exclusives per vCPU and counts their outcomes
+pub struct ExclusiveTracker {
+    arch: Arch,
+    vcpus: HashMap<VCPUIndex, VcpuState>,
+    sites: HashMap<u64, ExclusiveSite>,
+}
+
+impl ExclusiveTracker {
+    /// Create a tracker which has seen no exclusives
+    pub fn new(arch: Arch) -> Self {
+        Self {
+            arch,
+            vcpus: HashMap::new(),
+            sites: HashMap::new(),
+        }
+    }
+
+    fn site(&mut self, load_pc: u64) -> &mut ExclusiveSite {
+        self.sites.entry(load_pc).or_insert_with(|| ExclusiveSite {
+            load_pc,
+            ..Default::default()
+        })
+    }
+
+    /// Record a load-exclusive, abandoning any sequence already open on the vCPU
+    pub fn on_load(&mut self, vcpu_index: VCPUIndex, pc: u64) {
+        let state = self.vcpus.entry(vcpu_index).or_default();
+
+        if let Some(open) = state.open.replace(pc) {
+            self.site(open).abandoned += 1;
+        }
+    }
+
+    /// Record a `clrex`
+    pub fn on_clear(&mut self, vcpu_index: VCPUIndex) {
+        if let Some(open) = self.vcpus.entry(vcpu_index).or_default().open.take() {
+            self.site(open).abandoned += 1;
+        }
+    }
+
+    /// Record a store-exclusive, with its outcome if it was observed. Stores without an open
+    /// sequence are counted against their own address.
+    pub fn on_store(&mut self, vcpu_index: VCPUIndex, pc: u64, success: Option<bool>) {
+        let state = self.vcpus.entry(vcpu_index).or_default();
+        let load_pc = state.open.take().unwrap_or(pc);
+
+        state.retries = match success {
+            Some(false) => state.retries + 1,
+            _ => 0,
+        };
+
+        let retries = state.retries;
+        let site = self.site(load_pc);
+
+        site.store_pcs.insert(pc);
+        site.attempts += 1;
+        site.max_retries = site.max_retries.max(retries);
+
+        match success {
+            Some(true) => site.successes += 1,
+            Some(false) => site.failures += 1,
+            None => site.unobserved += 1,
+        }
+    }
+
+    /// Returns whether a store-exclusive succeeded, from the status register it wrote
+    pub fn read_status(
+        &self,
+        status: u8,
+        registers: &[RegisterDescriptor<'static>],
+    ) -> Result<bool> {
+        let name = match self.arch {
+            Arch::Aarch64 => format!("x{status}"),
+            Arch::Riscv32 | Arch::Riscv64 => RISCV_REGISTERS[status as usize & 0x1f].to_string(),
+            _ => format!("r{status}"),
+        };
+
+        Ok(read_register(self.arch, registers, &name)? & 0xffff_ffff == 0)
+    }
+
+    /// Returns every site, those with the most failures first
+    pub fn report(&self) -> Vec<ExclusiveSite> {
+        let mut sites = self.sites.values().cloned().collect::<Vec<_>>();
+
+        sites.sort_by_key(|s| (std::cmp::Reverse(s.failures), s.load_pc));
+        sites
+    }
+
+    /// Write the report to `path` as JSON
+    pub fn write_report<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        serde_json::to_writer_pretty(create_sink(path)?, &self.report())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,397 @@
+//! Conversion between traces and the text logs of QEMU's contrib `execlog` plugin, so tooling
+//! built around either can read the other's output and the two can be compared
+//!
+//! An execlog line holds the vCPU, address, opcode and disassembly of an instruction, then
+//! each memory access it made: its direction and its virtual address in user mode, or its
+//! physical address and device in system mode. With `reg=` or `rego=`, the registers an
+//! instruction changed follow on their own lines. Instructions become instruction events and
+//! accesses memory events following them, and back.
+//!
+//! The formats do not hold the same details, so conversions lose some:
+//!
+//! - Execlog logs the first four bytes of an instruction as its opcode, which may run past a
+//!   short instruction or stop short of a long one, and does not log access sizes, which are
+//!   imported as zero.
+//! - System-mode accesses only have a physical address, which is imported as the virtual
+//!   address as well.
+//! - Instruction events hold the registers before the instruction runs, and execlog those an
+//!   instruction changed, so imported events only hold the registers changed so far, and the
+//!   last instruction of each vCPU is exported without its changes.
+//! - Plain trace files do not record vCPUs, so their events are exported as vCPU 0 and
+//!   imported logs lose theirs unless written as a sharded trace.
+//! - Pc batches are exported as instructions without an opcode or disassembly, and coalesced
+//!   memory ranges and other events are not exported.
+
+#[cfg(not(feature = "plugin-api-v1"))]
+use crate::Registers;
+use crate::{
+    tracefile::{ShardedTraceFile, TraceFile},
+    Event, InstructionEvent, MemoryEvent,
+};
+use anyhow::{anyhow, Result};
+use qemu_plugin::VCPUIndex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+/// The device execlog names for accesses to RAM
+const RAM: &str = "RAM";
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A memory access logged after an instruction
+pub struct ExeclogAccess {
+    pub is_store: bool,
+    /// The virtual address, or the physical address if there is a device
+    pub address: u64,
+    /// The device accessed, in system mode
+    pub device: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// An instruction logged by execlog
+pub struct ExeclogEntry {
+    pub vcpu_index: VCPUIndex,
+    pub vaddr: u64,
+    /// The first four bytes of the instruction, read as a little-endian word
+    pub opcode: u32,
+    pub disas: String,
+    pub accesses: Vec<ExeclogAccess>,
+    /// The registers the instruction changed, with their little-endian values
+    pub registers: Vec<(String, Vec<u8>)>,
+}
+
+fn parse_hex(field: &str) -> Option<u64> {
+    u64::from_str_radix(field.trim().strip_prefix("0x")?, 16).ok()
+}
+
+impl ExeclogEntry {
+    /// Parse an instruction line: `0, 0x401126, 0xe5894855, "pushq %rbp", store, 0x7ffd0`
+    fn parse(line: &str) -> Option<Self> {
+        let (fields, rest) = line.split_once('"')?;
+        let (disas, accesses) = rest.rsplit_once('"')?;
+        let mut fields = fields.split(',');
+        let vcpu_index = fields.next()?.trim().parse().ok()?;
+        let vaddr = parse_hex(fields.next()?)?;
+        let opcode = u32::try_from(parse_hex(fields.next()?)?).ok()?;
+        let mut accesses = accesses.split(',').map(str::trim).skip(1).peekable();
+        let mut parsed = Vec::new();
+
+        while let Some(direction) = accesses.next() {
+            let is_store = match direction {
+                "store" => true,
+                "load" => false,
+                _ => return None,
+            };
+            let address = parse_hex(accesses.next()?)?;
+            // A device follows the address in system mode, where it is not another direction
+            let device = accesses
+                .next_if(|field| !matches!(*field, "store" | "load"))
+                .map(str::to_string);
+
+            parsed.push(ExeclogAccess {
+                is_store,
+                address,
+                device,
+            });
+        }
+
+        Some(Self {
+            vcpu_index,
+            vaddr,
+            opcode,
+            disas: disas.to_string(),
+            accesses: parsed,
+            registers: Vec::new(),
+        })
+    }
+}
+
+/// Parse a register line: `      rax -> 0x0000000000401126`, whose value is most significant
+/// byte first
+fn parse_register(line: &str) -> Option<(String, Vec<u8>)> {
+    let (name, value) = line.trim().split_once(" -> ")?;
+    let value = value.strip_prefix("0x")?;
+
+    if value.len() % 2 != 0 {
+        return None;
+    }
+
+    let bytes = (0..value.len())
+        .step_by(2)
+        .rev()
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    Some((name.to_string(), bytes))
+}
+
+impl Display for ExeclogEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, {:#x}, {:#x}, \"{}\"",
+            self.vcpu_index, self.vaddr, self.opcode, self.disas
+        )?;
+
+        for access in &self.accesses {
+            let direction = if access.is_store { "store" } else { "load" };
+
+            write!(f, ", {direction}, 0x{:08x}", access.address)?;
+
+            if let Some(device) = access.device.as_ref() {
+                write!(f, ", {device}")?;
+            }
+        }
+
+        for (name, value) in &self.registers {
+            write!(f, "\n      {name} -> 0x")?;
+
+            for byte in value.iter().rev() {
+                write!(f, "{byte:02x}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// The instructions of an execlog log, in order
+pub struct Execlog {
+    pub entries: Vec<ExeclogEntry>,
+}
+
+impl Execlog {
+    /// Parse a log, failing on lines which are neither instructions nor registers
+    pub fn parse<R>(reader: R) -> Result<Self>
+    where
+        R: BufRead,
+    {
+        let mut entries = Vec::<ExeclogEntry>::new();
+
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(entry) = ExeclogEntry::parse(&line) {
+                entries.push(entry);
+            } else if let (Some(register), Some(entry)) =
+                (parse_register(&line), entries.last_mut())
+            {
+                entry.registers.push(register);
+            } else {
+                return Err(anyhow!(
+                    "Failed to parse execlog line {}: {line}",
+                    number + 1
+                ));
+            }
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn read<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        Self::parse(BufReader::new(File::open(path).map_err(|e| {
+            anyhow!("Failed to open execlog {}: {e}", path.display())
+        })?))
+    }
+
+    /// Write the log in execlog's format
+    pub fn write<W>(&self, mut writer: W) -> Result<()>
+    where
+        W: Write,
+    {
+        for entry in &self.entries {
+            writeln!(writer, "{entry}")?;
+        }
+
+        Ok(())
+    }
+
+    /// The events of the log, each with the vCPU which ran it
+    pub fn events(&self) -> Vec<(VCPUIndex, Event)> {
+        #[cfg(not(feature = "plugin-api-v1"))]
+        let mut registers = HashMap::<VCPUIndex, HashMap<String, Vec<u8>>>::new();
+        let mut events = Vec::new();
+
+        for entry in &self.entries {
+            let event = InstructionEvent::builder()
+                .vaddr(entry.vaddr)
+                .haddr(0)
+                .disas(entry.disas.clone())
+                .symbol(None)
+                .data(entry.opcode.to_le_bytes().to_vec())
+                .build();
+
+            #[cfg(not(feature = "plugin-api-v1"))]
+            let event = {
+                let registers = registers.entry(entry.vcpu_index).or_default();
+                let event = Event::Instruction {
+                    event,
+                    registers: Registers(registers.clone()),
+                };
+
+                registers.extend(entry.registers.iter().cloned());
+                event
+            };
+            #[cfg(feature = "plugin-api-v1")]
+            let event = Event::Instruction { event };
+
+            events.push((entry.vcpu_index, event));
+
+            for access in &entry.accesses {
+                let event = MemoryEvent::builder()
+                    .vaddr(access.address)
+                    .haddr(access.device.as_ref().map(|_| access.address))
+                    .haddr_is_io(access.device.as_deref().map(|device| device != RAM))
+                    .haddr_device_name(access.device.clone())
+                    .size_shift(0)
+                    .size_bytes(0)
+                    .sign_extended(false)
+                    .is_store(access.is_store)
+                    .big_endian(false)
+                    .build();
+
+                events.push((entry.vcpu_index, Event::Memory(event)));
+            }
+        }
+
+        events
+    }
+
+    /// Build a log from events, each with the vCPU which ran it
+    pub fn from_events<'a, I>(events: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (VCPUIndex, &'a Event)>,
+    {
+        let mut entries = Vec::<ExeclogEntry>::new();
+        // The index of each vCPU's last instruction, and its registers before it ran
+        #[allow(unused_mut)]
+        let mut last = HashMap::<VCPUIndex, (usize, HashMap<String, Vec<u8>>)>::new();
+
+        for (vcpu_index, event) in events {
+            match event {
+                #[cfg(not(feature = "plugin-api-v1"))]
+                Event::Instruction { event, registers } => {
+                    // The instruction's registers are the previous instruction's results
+                    if let Some((index, before)) = last.get(&vcpu_index) {
+                        let mut changed = registers
+                            .0
+                            .iter()
+                            .filter(|(name, value)| before.get(*name) != Some(*value))
+                            .map(|(name, value)| (name.clone(), value.clone()))
+                            .collect::<Vec<_>>();
+
+                        changed.sort();
+                        entries[*index].registers = changed;
+                    }
+
+                    last.insert(vcpu_index, (entries.len(), registers.0.clone()));
+                    entries.push(entry(vcpu_index, event));
+                }
+                #[cfg(feature = "plugin-api-v1")]
+                Event::Instruction { event } => {
+                    last.insert(vcpu_index, (entries.len(), HashMap::new()));
+                    entries.push(entry(vcpu_index, event));
+                }
+                Event::Memory(memory) => {
+                    let Some((index, _)) = last.get(&vcpu_index) else {
+                        continue;
+                    };
+
+                    let access = match (memory.haddr, memory.haddr_device_name.as_ref()) {
+                        (Some(haddr), Some(device)) => ExeclogAccess {
+                            is_store: memory.is_store,
+                            address: haddr,
+                            device: Some(device.clone()),
+                        },
+                        _ => ExeclogAccess {
+                            is_store: memory.is_store,
+                            address: memory.vaddr,
+                            device: None,
+                        },
+                    };
+
+                    entries[*index].accesses.push(access);
+                }
+                Event::Pcs(batch) => {
+                    for vaddr in batch.decode()? {
+                        entries.push(ExeclogEntry {
+                            vcpu_index: batch.vcpu_index,
+                            vaddr,
+                            opcode: 0,
+                            disas: String::new(),
+                            accesses: Vec::new(),
+                            registers: Vec::new(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Write the log's events to a trace file, without their vCPUs
+    pub fn write_trace<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut trace = TraceFile::create(path)?;
+
+        for (_, event) in self.events() {
+            trace.write_record(&event)?;
+        }
+
+        trace.finish()
+    }
+
+    /// Write the log's events to a sharded trace, keeping their vCPUs and order
+    pub fn write_shards<P>(&self, dir: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let trace = ShardedTraceFile::create(dir)?;
+        let mut icount = 0;
+
+        for (vcpu_index, event) in self.events() {
+            if matches!(event, Event::Instruction { .. }) {
+                icount += 1;
+            }
+
+            trace.write(vcpu_index, icount, &event)?;
+        }
+
+        trace.finish()
+    }
+}
+
+fn entry(vcpu_index: VCPUIndex, event: &InstructionEvent) -> ExeclogEntry {
+    let mut opcode = [0; 4];
+    let len = event.data.len().min(opcode.len());
+
+    opcode[..len].copy_from_slice(&event.data[..len]);
+
+    ExeclogEntry {
+        vcpu_index,
+        vaddr: event.vaddr,
+        opcode: u32::from_le_bytes(opcode),
+        disas: event.disas.clone(),
+        accesses: Vec::new(),
+        registers: Vec::new(),
+    }
+}
@@ -0,0 +1,198 @@
+//! Rule-driven fault injection for syscalls and memory reads
+//!
+//! Rules are loaded from a JSON file containing a list of [`FaultRule`]s. Each rule matches
+//! either syscalls (by name, number, path argument, and probability) or memory reads (by
+//! program counter and address range), and describes the fault to inject.
+//!
+//! The plugin API does not yet expose register or memory writes, so matched faults cannot be
+//! applied to the guest. Each match is instead reported as a [`FaultEvent`] recording the
+//! site, the fault, and the value it would replace, which gives the complete list of error
+//! paths a rule set exercises.
+
+use crate::{
+    arch::{Arch, Syscall},
+    guest::read_cstring,
+};
+use anyhow::{anyhow, Result};
+use qemu_plugin::{qemu_plugin_read_memory_vaddr, VCPUIndex};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::{fs::read_to_string, path::Path};
+use typed_builder::TypedBuilder;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// The fault to inject when a rule matches
+pub enum FaultAction {
+    /// Fail the syscall, returning `-errno`
+    Errno(i64),
+    /// XOR the loaded value with a mask
+    Corrupt(u64),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+/// A rule describing which syscalls or memory reads to fault. All present conditions must
+/// match. Rules with a `pc` or `address` range match memory reads, all others match syscalls.
+pub struct FaultRule {
+    pub syscall: Option<Syscall>,
+    pub number: Option<i64>,
+    /// The path argument of `open`, `openat`, or `execve`
+    pub path: Option<String>,
+    /// The probability in `[0, 1]` that a matching site is faulted
+    pub probability: Option<f64>,
+    /// An inclusive range of instruction addresses
+    pub pc: Option<(u64, u64)>,
+    /// An inclusive range of data addresses
+    pub address: Option<(u64, u64)>,
+    pub action: Option<FaultAction>,
+}
+
+impl FaultRule {
+    fn is_memory(&self) -> bool {
+        self.pc.is_some() || self.address.is_some()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FaultSite {
+    Syscall { num: i64, args: [u64; 8] },
+    MemoryRead { pc: u64, vaddr: u64, value: Vec<u8> },
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+pub struct FaultEvent {
+    pub rule: usize,
+    pub action: FaultAction,
+    pub site: FaultSite,
+    pub vcpu_index: VCPUIndex,
+    pub icount: u64,
+}
+
+fn in_range(range: Option<(u64, u64)>, value: u64) -> bool {
+    range.is_none_or(|(start, end)| (start..=end).contains(&value))
+}
+
+#[derive(Debug)]
+/// Matches guest syscalls and memory reads against a set of fault rules
+pub struct FaultInjector {
+    rules: Vec<FaultRule>,
+    rng: StdRng,
+}
+
+impl FaultInjector {
+    /// Load rules from a JSON file. `seed` makes probabilistic rules reproducible.
+    pub fn load<P>(path: P, seed: u64) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let rules: Vec<FaultRule> = serde_json::from_str(&read_to_string(path)?)?;
+
+        if let Some(i) = rules.iter().position(|r| r.action.is_none()) {
+            return Err(anyhow!("Fault rule {i} has no action"));
+        }
+
+        Ok(Self {
+            rules,
+            rng: StdRng::seed_from_u64(seed),
+        })
+    }
+
+    fn roll(&mut self, rule: usize) -> bool {
+        self.rules[rule]
+            .probability
+            .is_none_or(|p| self.rng.gen_bool(p.clamp(0.0, 1.0)))
+    }
+
+    /// Whether memory reads by the instruction at `pc` can match a rule
+    pub fn wants_pc(&self, pc: u64) -> bool {
+        self.rules
+            .iter()
+            .any(|r| r.is_memory() && in_range(r.pc, pc))
+    }
+
+    /// Returns the fault to inject into a syscall on entry, if any rule matches
+    pub fn on_syscall(
+        &mut self,
+        arch: Arch,
+        vcpu_index: VCPUIndex,
+        num: i64,
+        args: [u64; 8],
+        icount: u64,
+    ) -> Result<Option<FaultEvent>> {
+        let syscall = arch.syscall(num);
+        let path = match syscall {
+            Some(Syscall::Open | Syscall::Execve) => Some(read_cstring(args[0], 4096)),
+            Some(Syscall::Openat) => Some(read_cstring(args[1], 4096)),
+            _ => None,
+        };
+        // A NULL or unmapped path fails in the guest with EFAULT, so no rule applies to it
+        let path = match path {
+            Some(Ok(path)) => Some(String::from_utf8_lossy(&path).into_owned()),
+            Some(Err(_)) => return Ok(None),
+            None => None,
+        };
+
+        for i in 0..self.rules.len() {
+            let rule = &self.rules[i];
+
+            if rule.is_memory()
+                || rule.syscall.is_some_and(|s| Some(s) != syscall)
+                || rule.number.is_some_and(|n| n != num)
+                || rule.path.as_ref().is_some_and(|p| Some(p) != path.as_ref())
+                || !self.roll(i)
+            {
+                continue;
+            }
+
+            return Ok(Some(
+                FaultEvent::builder()
+                    .rule(i)
+                    .action(self.rules[i].action.ok_or_else(|| anyhow!("No action"))?)
+                    .site(FaultSite::Syscall { num, args })
+                    .vcpu_index(vcpu_index)
+                    .icount(icount)
+                    .build(),
+            ));
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the fault to inject into a memory read, if any rule matches
+    pub fn on_memory_read(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        pc: u64,
+        vaddr: u64,
+        size: usize,
+        icount: u64,
+    ) -> Result<Option<FaultEvent>> {
+        for i in 0..self.rules.len() {
+            let rule = &self.rules[i];
+
+            if !rule.is_memory()
+                || !in_range(rule.pc, pc)
+                || !in_range(rule.address, vaddr)
+                || !self.roll(i)
+            {
+                continue;
+            }
+
+            return Ok(Some(
+                FaultEvent::builder()
+                    .rule(i)
+                    .action(self.rules[i].action.ok_or_else(|| anyhow!("No action"))?)
+                    .site(FaultSite::MemoryRead {
+                        pc,
+                        vaddr,
+                        value: qemu_plugin_read_memory_vaddr(vaddr, size)?,
+                    })
+                    .vcpu_index(vcpu_index)
+                    .icount(icount)
+                    .build(),
+            ));
+        }
+
+        Ok(None)
+    }
+}
@@ -0,0 +1,336 @@
+//! Tracking of guest file I/O syscalls, producing a per-run report of which files were
+//! accessed, how many bytes were moved, and at which offsets
+
+use crate::{
+    arch::{Arch, Syscall},
+    guest::read_cstring,
+    limits::{Admission, Budget},
+};
+use anyhow::Result;
+use qemu_plugin::{path::create_sink, qemu_plugin_read_memory_vaddr, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::{Debug, Formatter},
+    path::Path,
+};
+
+/// The longest path read from guest memory
+const MAX_PATH: usize = 4096;
+/// The path recorded when the guest passes a pointer which cannot be read
+const UNKNOWN_PATH: &str = "<unknown>";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A contiguous range of a file accessed by one or more consecutive syscalls
+pub struct FileRange {
+    pub kind: AccessKind,
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// All accesses made to a single path during a run
+pub struct FileAccess {
+    pub opens: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub ranges: Vec<FileRange>,
+    /// SHA-256 of the bytes read from the file, in the order they were read
+    pub sha256: Option<String>,
+}
+
+/// The report written at exit, keyed by guest path
+pub type FileReport = BTreeMap<String, FileAccess>;
+
+#[derive(Clone, Debug)]
+struct OpenFile {
+    path: String,
+    offset: u64,
+}
+
+#[derive(Clone, Debug)]
+struct PendingSyscall {
+    syscall: Syscall,
+    args: [u64; 8],
+    path: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+/// Budgets bounding the size of a `FileReport`
+pub struct FileLimits {
+    /// The number of paths in the report
+    pub files: Budget,
+    /// The number of ranges recorded for each path
+    pub ranges: Budget,
+}
+
+#[derive(Clone, Default)]
+/// Tracks open file descriptors across syscalls and accumulates a `FileReport`
+pub struct FileTracker {
+    hash_contents: bool,
+    limits: FileLimits,
+    fds: HashMap<u64, OpenFile>,
+    pending: HashMap<VCPUIndex, PendingSyscall>,
+    files: BTreeMap<String, FileAccess>,
+    /// Paths in the order they were first added to `files`, for eviction
+    order: VecDeque<String>,
+    hashers: HashMap<String, Sha256>,
+}
+
+impl Debug for FileTracker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileTracker")
+            .field("hash_contents", &self.hash_contents)
+            .field("limits", &self.limits)
+            .field("fds", &self.fds)
+            .field("files", &self.files)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FileTracker {
+    /// Create a tracker. If `hash_contents` is set, the data returned by reads is hashed
+    /// per file.
+    pub fn new(hash_contents: bool, limits: FileLimits) -> Self {
+        let fds = [(0, "<stdin>"), (1, "<stdout>"), (2, "<stderr>")]
+            .into_iter()
+            .map(|(fd, path)| {
+                (
+                    fd,
+                    OpenFile {
+                        path: path.to_string(),
+                        offset: 0,
+                    },
+                )
+            })
+            .collect();
+
+        Self {
+            hash_contents,
+            limits,
+            fds,
+            ..Default::default()
+        }
+    }
+
+    /// Record the arguments of a syscall on entry
+    pub fn on_syscall(
+        &mut self,
+        arch: Arch,
+        vcpu_index: VCPUIndex,
+        num: i64,
+        args: [u64; 8],
+    ) -> Result<()> {
+        let Some(syscall) = arch.syscall(num) else {
+            return Ok(());
+        };
+
+        let path = match syscall {
+            Syscall::Open => Some(Self::read_path(args[0])),
+            Syscall::Openat => Some(Self::read_path(args[1])),
+            Syscall::Read
+            | Syscall::Write
+            | Syscall::Pread64
+            | Syscall::Pwrite64
+            | Syscall::Readv
+            | Syscall::Writev
+            | Syscall::Lseek
+            | Syscall::Close => None,
+            _ => return Ok(()),
+        };
+
+        self.pending.insert(
+            vcpu_index,
+            PendingSyscall {
+                syscall,
+                args,
+                path,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Read a path argument. Opening NULL or an unmapped pointer is valid guest behavior
+    /// which fails with EFAULT, so the path is recorded as unknown instead of failing.
+    fn read_path(addr: u64) -> String {
+        read_cstring(addr, MAX_PATH)
+            .map(|path| String::from_utf8_lossy(&path).into_owned())
+            .unwrap_or_else(|_| UNKNOWN_PATH.to_string())
+    }
+
+    /// Update descriptor state and the report once a syscall returns
+    pub fn on_syscall_return(&mut self, arch: Arch, vcpu_index: VCPUIndex, ret: i64) -> Result<()> {
+        let Some(pending) = self.pending.remove(&vcpu_index) else {
+            return Ok(());
+        };
+
+        if ret < 0 {
+            return Ok(());
+        }
+
+        let fd = pending.args[0];
+        let len = ret as u64;
+
+        match pending.syscall {
+            Syscall::Open | Syscall::Openat => {
+                let path = pending.path.unwrap_or_default();
+                self.admit(&path)?;
+                self.files.entry(path.clone()).or_default().opens += 1;
+                self.fds.insert(ret as u64, OpenFile { path, offset: 0 });
+            }
+            Syscall::Close => {
+                self.fds.remove(&fd);
+            }
+            Syscall::Lseek => {
+                if let Some(file) = self.fds.get_mut(&fd) {
+                    file.offset = len;
+                }
+            }
+            Syscall::Read | Syscall::Readv => self.access(
+                fd,
+                None,
+                len,
+                AccessKind::Read,
+                pending.args[1],
+                pending.syscall,
+            )?,
+            Syscall::Write | Syscall::Writev => {
+                self.access(fd, None, len, AccessKind::Write, 0, pending.syscall)?
+            }
+            Syscall::Pread64 => self.access(
+                fd,
+                Some(arch.rw_offset(&pending.args)),
+                len,
+                AccessKind::Read,
+                pending.args[1],
+                pending.syscall,
+            )?,
+            Syscall::Pwrite64 => self.access(
+                fd,
+                Some(arch.rw_offset(&pending.args)),
+                len,
+                AccessKind::Write,
+                0,
+                pending.syscall,
+            )?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn access(
+        &mut self,
+        fd: u64,
+        offset: Option<u64>,
+        len: u64,
+        kind: AccessKind,
+        buf: u64,
+        syscall: Syscall,
+    ) -> Result<()> {
+        let Some(file) = self.fds.get_mut(&fd) else {
+            return Ok(());
+        };
+
+        let start = offset.unwrap_or(file.offset);
+
+        // Positional I/O does not move the file offset
+        if offset.is_none() {
+            file.offset += len;
+        }
+
+        let path = file.path.clone();
+        self.admit(&path)?;
+        let access = self.files.entry(path.clone()).or_default();
+
+        match kind {
+            AccessKind::Read => access.bytes_read += len,
+            AccessKind::Write => access.bytes_written += len,
+        }
+
+        match access.ranges.last_mut() {
+            Some(last) if last.kind == kind && last.offset + last.len == start => last.len += len,
+            _ => {
+                if self.limits.ranges.admit(access.ranges.len())? == Admission::Evict {
+                    access.ranges.remove(0);
+                }
+
+                access.ranges.push(FileRange {
+                    kind,
+                    offset: start,
+                    len,
+                });
+            }
+        }
+
+        // Vectored reads scatter into several buffers, so only plain reads are hashed
+        if self.hash_contents
+            && kind == AccessKind::Read
+            && matches!(syscall, Syscall::Read | Syscall::Pread64)
+            && len > 0
+        {
+            let data = qemu_plugin_read_memory_vaddr(buf, len as usize)?;
+            self.hashers.entry(path).or_default().update(&data);
+        }
+
+        Ok(())
+    }
+
+    /// Make room for `path` in the report, evicting the oldest path if the report is full
+    fn admit(&mut self, path: &str) -> Result<()> {
+        if !self.files.contains_key(path) {
+            if self.limits.files.admit(self.files.len())? == Admission::Evict {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.files.remove(&oldest);
+                    self.hashers.remove(&oldest);
+                }
+            }
+
+            self.order.push_back(path.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the budgets of the report, including how many entries were evicted
+    pub fn limits(&self) -> &FileLimits {
+        &self.limits
+    }
+
+    /// Returns the report accumulated so far
+    pub fn report(&self) -> FileReport {
+        let mut files = self.files.clone();
+
+        for (path, hasher) in &self.hashers {
+            if let Some(access) = files.get_mut(path) {
+                access.sha256 = Some(
+                    hasher
+                        .clone()
+                        .finalize()
+                        .iter()
+                        .map(|b| format!("{b:02x}"))
+                        .collect(),
+                );
+            }
+        }
+
+        files
+    }
+
+    /// Write the report accumulated so far to `path` as JSON
+    pub fn write_report<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        serde_json::to_writer_pretty(create_sink(path)?, &self.report())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+//! Selective instrumentation: restricting the blocks the tracer instruments to the ELF
+//! sections or segments of the modules a user names, such as only `.text`, everything but
+//! `.plt`, or a section holding the code under test
+//!
+//! A filter is a list of rules separated by `;`. Each rule names a section, optionally ending
+//! in `*` to match every section starting with the rest, or a loadable segment as
+//! `segment:<n>`, counting from 0. A rule applies to every module unless it ends in
+//! `@<module>`, the file name of a module. A rule starting with `!` excludes the code it
+//! matches, and any other includes it:
+//!
+//! ```text
+//! .text;!.plt*;.text.uut@libfoo.so;segment:1@program
+//! ```
+//!
+//! With no including rule, all code is included. With one, only the code it matches is, and
+//! code outside any module the filter knows is left out. Excluding rules win over including
+//! ones.
+//!
+//! The program's sections and segments are resolved when the plugin is installed, and those
+//! of each library as the guest maps it, when libraries are followed with
+//! `symbolize_libraries`. A block is filtered by the address it starts at, and a filtered
+//! block is only counted, so instruction counts and the limits built on them stay right.
+//! Trackers following calls and returns, like the shadow stack, do not see those in filtered
+//! code.
+
+use crate::modules::ModuleMap;
+use anyhow::{anyhow, Result};
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The part of a module a rule matches
+pub enum Target {
+    /// A section by name, or every section starting with a prefix
+    Section { name: String, prefix: bool },
+    /// A loadable segment by its position among the module's loadable segments
+    Segment(usize),
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Section {
+                name,
+                prefix: false,
+            } => write!(f, "{name}"),
+            Self::Section { name, prefix: true } => write!(f, "{name}*"),
+            Self::Segment(index) => write!(f, "segment:{index}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A rule of a filter
+pub struct Rule {
+    pub exclude: bool,
+    pub target: Target,
+    /// The module the rule applies to, or `None` for every module
+    pub module: Option<String>,
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.exclude {
+            write!(f, "!")?;
+        }
+
+        write!(f, "{}", self.target)?;
+
+        if let Some(module) = self.module.as_ref() {
+            write!(f, "@{module}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Rule {
+    fn parse(rule: &str) -> Result<Self> {
+        let (exclude, rule) = match rule.strip_prefix('!') {
+            Some(rule) => (true, rule),
+            None => (false, rule),
+        };
+        let (target, module) = match rule.rsplit_once('@') {
+            Some((target, module)) if !module.is_empty() => (target, Some(module.to_string())),
+            Some(_) => return Err(anyhow!("Missing module after '@' in {rule}")),
+            None => (rule, None),
+        };
+        let target = match target.strip_prefix("segment:") {
+            Some(index) => Target::Segment(
+                index
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid segment index {index}: {e}"))?,
+            ),
+            None if target.is_empty() || target == "*" => {
+                return Err(anyhow!("Missing section name in {rule}"))
+            }
+            None => match target.strip_suffix('*') {
+                Some(name) => Target::Section {
+                    name: name.to_string(),
+                    prefix: true,
+                },
+                None => Target::Section {
+                    name: target.to_string(),
+                    prefix: false,
+                },
+            },
+        };
+
+        Ok(Self {
+            exclude,
+            target,
+            module,
+        })
+    }
+
+    /// Whether the rule matches code at `vaddr` in `module`
+    fn matches(&self, module: &ModuleMap, vaddr: u64) -> bool {
+        if self.module.as_ref().is_some_and(|m| *m != module.name) {
+            return false;
+        }
+
+        match &self.target {
+            Target::Section { name, prefix } => module.section(vaddr).is_some_and(|section| {
+                if *prefix {
+                    section.name.starts_with(name.as_str())
+                } else {
+                    section.name == *name
+                }
+            }),
+            Target::Segment(index) => module
+                .segment(vaddr)
+                .is_some_and(|segment| segment.index == *index),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Decides which blocks are instrumented from the sections and segments they are in
+pub struct SectionFilter {
+    rules: Vec<Rule>,
+    modules: Vec<ModuleMap>,
+}
+
+impl SectionFilter {
+    pub fn parse(rules: &str) -> Result<Self> {
+        let rules = rules
+            .split(';')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .map(Rule::parse)
+            .collect::<Result<Vec<_>>>()?;
+
+        if rules.is_empty() {
+            return Err(anyhow!("No rules in section filter"));
+        }
+
+        Ok(Self {
+            rules,
+            modules: Vec::new(),
+        })
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Add the sections and segments of a loaded module. A module mapped more than once is
+    /// only added the first time.
+    pub fn add_module(&mut self, module: ModuleMap) {
+        if self
+            .modules
+            .iter()
+            .any(|m| m.name == module.name && m.bias == module.bias)
+        {
+            return;
+        }
+
+        self.modules.push(module);
+    }
+
+    /// The module whose sections or segments contain `vaddr`, if any
+    fn module(&self, vaddr: u64) -> Option<&ModuleMap> {
+        self.modules
+            .iter()
+            .find(|m| m.section(vaddr).is_some() || m.segment(vaddr).is_some())
+    }
+
+    /// Returns why a block starting at `vaddr` is left out, or `None` if it is instrumented
+    pub fn rejection(&self, vaddr: u64) -> Option<String> {
+        let includes = self.rules.iter().any(|rule| !rule.exclude);
+        let Some(module) = self.module(vaddr) else {
+            return includes.then(|| format!("{vaddr:#x} is in no known module"));
+        };
+        let place = match module.section(vaddr) {
+            Some(section) => format!("{} {}", module.name, section.name),
+            None => format!("{} outside any section", module.name),
+        };
+
+        if let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| rule.exclude && rule.matches(module, vaddr))
+        {
+            return Some(format!("{place} is excluded by {rule}"));
+        }
+
+        if includes
+            && !self
+                .rules
+                .iter()
+                .any(|rule| !rule.exclude && rule.matches(module, vaddr))
+        {
+            return Some(format!("{place} is not included"));
+        }
+
+        None
+    }
+}
@@ -0,0 +1,115 @@
+//! Edge coverage reported to honggfuzz through its shared feedback map
+//!
+//! honggfuzz passes every target it runs a shared `feedback_t` on file descriptor 1022, which
+//! its own instrumentation (`libhfuzz`) writes coverage into, and the index of the fuzzing
+//! thread running it in `HFUZZ_THREAD_NO`. Running QEMU as the target with this plugin loaded
+//! reports the guest's edges the same way: each edge is hashed to a bit of the shared
+//! `bbMapPc` bitmap, and edges whose bit was clear bump the thread's `pidNewEdge` counter,
+//! which honggfuzz checks after each run to decide whether the input found new coverage. For
+//! example:
+//!
+//! ```text
+//! honggfuzz -i corpus -- qemu-x86_64 -plugin libtracer.so,honggfuzz=on ./target ___FILE___
+//! ```
+//!
+//! The offsets below follow the `feedback_t` layout of honggfuzz 2.x.
+
+use anyhow::{anyhow, Result};
+use memmap2::MmapMut;
+use qemu_plugin::VCPUIndex;
+use std::{
+    collections::HashMap,
+    env::var,
+    fs::File,
+    os::fd::FromRawFd,
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+};
+
+/// The file descriptor honggfuzz passes its feedback map on
+pub const COV_BITMAP_FD: i32 = 1022;
+/// The environment variable holding the index of the fuzzing thread running the target
+pub const THREAD_NO_ENV: &str = "HFUZZ_THREAD_NO";
+
+/// `_HF_PC_GUARD_MAX`, the size of `pcGuardMap`
+const PC_GUARD_MAX: usize = 64 << 20;
+/// `_HF_PERF_BITMAP_SIZE_16M`, the size of `bbMapPc` and the length of `bbMapCmp`
+const BITMAP_SIZE: usize = 16 << 20;
+/// `_HF_PERF_BITMAP_BITSZ_MASK`, which masks hashes to a bit of `bbMapPc`
+const BITMAP_MASK: u64 = 0x7ff_ffff;
+/// `_HF_THREAD_MAX`, the length of each per-thread counter array
+const THREAD_MAX: usize = 1024;
+
+const BB_MAP_PC: usize = PC_GUARD_MAX;
+const PID_NEW_PC: usize = BB_MAP_PC + BITMAP_SIZE + BITMAP_SIZE * 4;
+const PID_NEW_EDGE: usize = PID_NEW_PC + THREAD_MAX * 8;
+const GUARD_NB: usize = PID_NEW_EDGE + THREAD_MAX * 16;
+const PID_TOTAL_PC: usize = GUARD_NB + 8;
+const PID_TOTAL_EDGE: usize = PID_TOTAL_PC + THREAD_MAX * 8;
+const FEEDBACK_SIZE: usize = PID_TOTAL_EDGE + THREAD_MAX * 16;
+
+#[derive(Debug)]
+/// honggfuzz's feedback map, mapped from the descriptor it passed QEMU
+pub struct HonggfuzzFeedback {
+    map: MmapMut,
+    thread: usize,
+    /// The last block each vCPU executed
+    prev: HashMap<VCPUIndex, u64>,
+}
+
+impl HonggfuzzFeedback {
+    /// Map the feedback map honggfuzz passed, failing if QEMU was not started by honggfuzz
+    pub fn open() -> Result<Self> {
+        let thread = var(THREAD_NO_ENV)
+            .map_err(|_| anyhow!("{THREAD_NO_ENV} is not set, QEMU was not run by honggfuzz"))?
+            .parse::<usize>()?;
+
+        if thread >= THREAD_MAX {
+            return Err(anyhow!("Invalid honggfuzz thread {thread}"));
+        }
+
+        // SAFETY: honggfuzz keeps the descriptor open for the target, and nothing else in
+        // the process uses it
+        let file = unsafe { File::from_raw_fd(COV_BITMAP_FD) };
+        let size = file.metadata()?.len() as usize;
+
+        if size < FEEDBACK_SIZE {
+            return Err(anyhow!(
+                "honggfuzz feedback map is {size} bytes, expected at least {FEEDBACK_SIZE}"
+            ));
+        }
+
+        Ok(Self {
+            map: unsafe { MmapMut::map_mut(&file)? },
+            thread,
+            prev: HashMap::new(),
+        })
+    }
+
+    fn byte(&self, offset: usize) -> &AtomicU8 {
+        // SAFETY: offsets are within the map, which is shared with other processes and so
+        // only accessed atomically
+        unsafe { AtomicU8::from_ptr(self.map.as_ptr().add(offset) as *mut u8) }
+    }
+
+    fn counter(&self, array: usize) -> &AtomicU64 {
+        // SAFETY: as for `byte`, and the counter arrays are 8-byte aligned in the map
+        unsafe { AtomicU64::from_ptr(self.map.as_ptr().add(array + self.thread * 8) as *mut u64) }
+    }
+
+    /// Record that a vCPU executed the block at `pc`
+    pub fn on_block(&mut self, vcpu_index: VCPUIndex, pc: u64) {
+        let prev = self.prev.insert(vcpu_index, pc).unwrap_or_default();
+        let bit = ((prev << 12) ^ pc) & BITMAP_MASK;
+        let mask = 1 << (bit % 8);
+
+        if self
+            .byte(BB_MAP_PC + (bit / 8) as usize)
+            .fetch_or(mask, Ordering::Relaxed)
+            & mask
+            == 0
+        {
+            self.counter(PID_NEW_EDGE).fetch_add(1, Ordering::Relaxed);
+            self.counter(PID_TOTAL_EDGE).fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
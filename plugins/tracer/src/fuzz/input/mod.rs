@@ -0,0 +1,152 @@
+//! Injection of a fuzz input into a user mode guest's reads of a file or of stdin
+//!
+//! The plugin API cannot write guest memory, but in user mode QEMU passes the guest's file
+//! descriptors through to the host, so what a read returns can be replaced by replacing the
+//! descriptor. When the guest opens the configured path, the descriptor the open returned is
+//! replaced with `dup2` by an in-memory file holding the current input, and for stdin,
+//! descriptor 0 is replaced whenever the input is set. Reads then return the input, with
+//! their lengths, the file offset and end of file handled by the host kernel as for any
+//! file, and `fstat` reports the input's size.
+//!
+//! [`InputInjector::set_input`] changes the input without restarting the guest, so a
+//! persistent harness which reopens its input file each iteration reads each new input.
+//! Paths are compared exactly as the guest passes them to `open` or `openat`.
+
+use crate::{
+    arch::{Arch, Syscall},
+    guest::read_cstring,
+};
+use anyhow::Result;
+use qemu_plugin::VCPUIndex;
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    fs::File,
+    io::{self, Seek, Write},
+    os::fd::{AsRawFd, FromRawFd, RawFd},
+    str::FromStr,
+};
+
+/// The longest path read from guest memory
+const MAX_PATH: usize = 4096;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Where the guest reads the input from
+pub enum InputTarget {
+    Stdin,
+    /// A path, as the guest opens it
+    Path(Vec<u8>),
+}
+
+impl FromStr for InputTarget {
+    type Err = Infallible;
+
+    /// Parse `-` or `stdin` as stdin, and anything else as a path
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "-" | "stdin" => Self::Stdin,
+            path => Self::Path(path.as_bytes().to_vec()),
+        })
+    }
+}
+
+#[derive(Debug)]
+/// Replaces the descriptors the guest reads the input from
+pub struct InputInjector {
+    target: InputTarget,
+    input: Vec<u8>,
+    /// The vCPUs in an `open` of the target path
+    opening: HashSet<VCPUIndex>,
+}
+
+impl InputInjector {
+    /// Create an injector serving `input`. For stdin, descriptor 0 is replaced immediately.
+    pub fn new(target: InputTarget, input: Vec<u8>) -> Result<Self> {
+        let injector = Self {
+            target,
+            input,
+            opening: HashSet::new(),
+        };
+
+        if injector.target == InputTarget::Stdin {
+            injector.replace(0)?;
+        }
+
+        Ok(injector)
+    }
+
+    /// Replace descriptor `fd` with a new in-memory file holding the input, keeping its
+    /// close-on-exec flag
+    fn replace(&self, fd: RawFd) -> Result<()> {
+        // SAFETY: the name is NUL-terminated, and the descriptor is owned by `file` alone
+        let memfd = unsafe { libc::memfd_create(c"qemu-rs-input".as_ptr(), libc::MFD_CLOEXEC) };
+
+        if memfd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let mut file = unsafe { File::from_raw_fd(memfd) };
+
+        file.write_all(&self.input)?;
+        file.rewind()?;
+
+        // SAFETY: `fd` is a descriptor the guest owns, and is only replaced, never closed
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+
+        if flags < 0 || unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+
+    /// Set the input for subsequent opens of the path, or replace stdin with it
+    pub fn set_input(&mut self, input: Vec<u8>) -> Result<()> {
+        self.input = input;
+
+        if self.target == InputTarget::Stdin {
+            self.replace(0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Record the arguments of a syscall on entry
+    pub fn on_syscall(
+        &mut self,
+        arch: Arch,
+        vcpu_index: VCPUIndex,
+        num: i64,
+        args: [u64; 8],
+    ) -> Result<()> {
+        let InputTarget::Path(path) = &self.target else {
+            return Ok(());
+        };
+
+        let opened = match arch.syscall(num) {
+            Some(Syscall::Open) => read_cstring(args[0], MAX_PATH),
+            Some(Syscall::Openat) => read_cstring(args[1], MAX_PATH),
+            _ => return Ok(()),
+        };
+
+        // An unreadable path, such as a NULL pointer, cannot be the input path
+        if opened.is_ok_and(|opened| opened == *path) {
+            self.opening.insert(vcpu_index);
+        }
+
+        Ok(())
+    }
+
+    /// Record the return of a syscall, replacing the descriptor an open of the path returned
+    pub fn on_syscall_return(&mut self, vcpu_index: VCPUIndex, ret: i64) -> Result<()> {
+        if self.opening.remove(&vcpu_index) && ret >= 0 {
+            self.replace(ret as RawFd)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+//! A LibAFL observer and executor built on the fuzz [`Harness`], so that LibAFL-based fuzzers
+//! can drive a QEMU guest through this plugin rather than the `libafl_qemu` fork
+//!
+//! [`HarnessExecutor`] runs each input from the snapshot with [`Harness::run`], and copies the
+//! edge map of the run into an [`EdgeObserver`], which applies AFL's hit count buckets as the
+//! plugin's own fuzz loop does. The fuzzer must run on a thread of the plugin's own, as
+//! [`loop_`](super::loop_) does:
+//!
+//! ```ignore
+//! let (target, signals) = FuzzTarget::new(start, end, crashes);
+//! let harness = Harness::connect(&config, Arc::new(Mutex::new(target)), signals)?;
+//! let edges = edge_observer("edges");
+//! let mut feedback = MaxMapFeedback::new(&edges);
+//! let mut objective = CrashFeedback::new();
+//! let mut executor = HarnessExecutor::new(harness, edges, ());
+//! // Build the state, scheduler, fuzzer and stages as for any other executor
+//! ```
+//!
+//! New program states reported by [`FuzzTarget::on_new_state`](super::FuzzTarget) are not
+//! observed; read them from [`HarnessExecutor::harness`] after a run if they are needed.
+
+use super::{Harness, Outcome, MAP_SIZE};
+use libafl::{
+    executors::{Executor, ExitKind, HasObservers},
+    inputs::HasTargetBytes,
+    observers::{HitcountsMapObserver, OwnedMapObserver},
+    state::HasExecutions,
+    Error,
+};
+use libafl_bolts::{tuples::RefIndexable, AsSlice};
+
+/// The observer of the edge map, with hit counts bucketed as AFL's are
+pub type EdgeObserver = HitcountsMapObserver<OwnedMapObserver<u8>>;
+
+/// Create an observer of the edge map
+pub fn edge_observer(name: &'static str) -> EdgeObserver {
+    HitcountsMapObserver::new(OwnedMapObserver::new(name, vec![0; MAP_SIZE]))
+}
+
+#[derive(Debug)]
+/// An executor running each input through a [`Harness`], observed by an [`EdgeObserver`]
+/// and any other observers `OT`
+pub struct HarnessExecutor<OT> {
+    harness: Harness,
+    observers: (EdgeObserver, OT),
+}
+
+impl<OT> HarnessExecutor<OT> {
+    /// Create an executor copying each run's edges into `edges`
+    pub fn new(harness: Harness, edges: EdgeObserver, observers: OT) -> Self {
+        Self {
+            harness,
+            observers: (edges, observers),
+        }
+    }
+
+    /// Returns the harness, as to read the new program states of the last run
+    pub fn harness(&self) -> &Harness {
+        &self.harness
+    }
+
+    /// Returns the harness, as to stop QEMU with [`Harness::quit`] once fuzzing is done
+    pub fn into_harness(self) -> Harness {
+        self.harness
+    }
+}
+
+impl<EM, I, OT, S, Z> Executor<EM, I, S, Z> for HarnessExecutor<OT>
+where
+    I: HasTargetBytes,
+    S: HasExecutions,
+{
+    fn run_target(
+        &mut self,
+        _fuzzer: &mut Z,
+        state: &mut S,
+        _mgr: &mut EM,
+        input: &I,
+    ) -> Result<ExitKind, Error> {
+        *state.executions_mut() += 1;
+
+        let outcome = self
+            .harness
+            .run(input.target_bytes().as_slice())
+            .map_err(|e| Error::unknown(format!("Failed to run input: {e}")))?;
+        let edges = &mut self.observers.0;
+
+        self.harness
+            .with_map(|map| edges.copy_from_slice(map.as_bytes()))
+            .map_err(|e| Error::unknown(e.to_string()))?;
+
+        Ok(match outcome {
+            Outcome::Ok => ExitKind::Ok,
+            Outcome::Crash => ExitKind::Crash,
+            Outcome::Timeout => ExitKind::Timeout,
+        })
+    }
+}
+
+impl<OT> HasObservers for HarnessExecutor<OT> {
+    type Observers = (EdgeObserver, OT);
+
+    fn observers(&self) -> RefIndexable<&Self::Observers, Self::Observers> {
+        RefIndexable::from(&self.observers)
+    }
+
+    fn observers_mut(&mut self) -> RefIndexable<&mut Self::Observers, Self::Observers> {
+        RefIndexable::from(&mut self.observers)
+    }
+}
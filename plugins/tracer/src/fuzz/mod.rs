@@ -0,0 +1,669 @@
+//! A coverage-guided snapshot fuzzer for full-system guests
+//!
+//! The guest marks its harness with three kinds of program counter, which act as hypercalls
+//! without needing a channel into the guest: a start marker it reaches before reading each
+//! input, an end marker it reaches once an input is processed, and any number of crash
+//! markers such as a panic handler or exception vector. [`loop_`] drives the guest over QMP:
+//!
+//! 1. When the vCPU first reaches the start marker, the VM is stopped and saved with `savevm`.
+//! 2. An input is written to the input file, the edge map is cleared, and the VM continues.
+//! 3. Once the guest reaches the end marker or a crash marker, or the timeout elapses, the VM
+//!    is stopped and the edge map, an AFL-style bitmap of hashed block transitions, is
+//!    compared against every edge seen before.
+//! 4. Inputs reaching new edges join the queue, crashes and hangs are saved, the snapshot is
+//!    restored with `loadvm`, and the next input is a mutation of the queue.
+//!
+//! The plugin API cannot write guest memory, so inputs are passed through a file the guest
+//! reads as a read-only disk, which `loadvm` leaves alone. The file holds a little-endian
+//! 32-bit length followed by the input, and is sized for the largest input up front, as QEMU
+//! fixes a disk's size when it opens it. The length is zero until the first input is
+//! written, so the harness should poll it after the start marker:
+//!
+//! ```c
+//! for (;;) {
+//!     fuzz_start();               /* the start marker */
+//!     read_sector(0, buf);        /* from the input disk, bypassing any cache */
+//!     uint32_t len = *(uint32_t *)buf;
+//!     if (len) { target(buf + 4, len); fuzz_end(); }
+//! }
+//! ```
+//!
+//! `savevm` stores the VM state in a writable qcow2 disk, so a full invocation looks like:
+//!
+//! ```text
+//! qemu-system-aarch64 -M virt -cpu cortex-a57 -kernel harness.elf -nographic \
+//!     -drive if=none,format=qcow2,file=state.qcow2 \
+//!     -drive if=virtio,format=raw,readonly=on,file=input.bin \
+//!     -qmp unix:/tmp/qmp.sock,server=on,wait=off \
+//!     -plugin libtracer.so,qmp_socket=/tmp/qmp.sock,fuzz_start=0x40080000,\
+//!         fuzz_end=0x40080040,fuzz_crashes=0x40081000,fuzz_input=input.bin,fuzz_output=out
+//! ```
+//!
+//! The output directory holds `queue`, `crashes` and `hangs` subdirectories, as AFL's does.
+
+use crate::{log, memmap::parse_addr, qmp::Qmp};
+use anyhow::{anyhow, Result};
+use qemu_plugin::VCPUIndex;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, read, read_dir, write, File, OpenOptions},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+use typed_builder::TypedBuilder;
+
+pub mod honggfuzz;
+#[cfg(feature = "plugin-api-v4")]
+pub mod input;
+#[cfg(feature = "libafl")]
+pub mod libafl;
+#[cfg(feature = "plugin-api-v4")]
+pub mod state;
+
+/// The size of the edge map, as in AFL
+pub const MAP_SIZE: usize = 1 << 16;
+
+/// The name of the snapshot each input starts from
+const SNAPSHOT: &str = "fuzz";
+
+/// How many inputs are run between progress reports
+const REPORT_INTERVAL: u64 = 1000;
+
+/// Values likely to hit boundary conditions, as AFL's `interesting_*` tables
+const INTERESTING: &[i64] = &[
+    -128,
+    -1,
+    0,
+    1,
+    16,
+    32,
+    64,
+    100,
+    127,
+    -32768,
+    -129,
+    128,
+    255,
+    256,
+    512,
+    1000,
+    1024,
+    4096,
+    32767,
+    -2147483648,
+    -100663046,
+    -32769,
+    32768,
+    65535,
+    65536,
+    100663045,
+    2147483647,
+];
+
+#[derive(Debug)]
+/// An AFL-style edge map, counting transitions between blocks by hashed location
+pub struct EdgeMap {
+    map: Vec<u8>,
+    /// The location of the last block each vCPU executed, shifted right by one
+    prev: HashMap<VCPUIndex, usize>,
+}
+
+impl Default for EdgeMap {
+    fn default() -> Self {
+        Self {
+            map: vec![0; MAP_SIZE],
+            prev: HashMap::new(),
+        }
+    }
+}
+
+impl EdgeMap {
+    /// Record that a vCPU executed the block at `pc`, hashing it as AFL's QEMU mode does
+    pub fn on_block(&mut self, vcpu_index: VCPUIndex, pc: u64) {
+        let location = (((pc >> 4) ^ (pc << 8)) as usize) & (MAP_SIZE - 1);
+        let prev = self.prev.entry(vcpu_index).or_default();
+        let edge = &mut self.map[location ^ *prev];
+
+        *edge = edge.wrapping_add(1);
+        *prev = location >> 1;
+    }
+
+    /// Clear the map for the next input
+    pub fn reset(&mut self) {
+        self.map.fill(0);
+        self.prev.clear();
+    }
+
+    /// Returns the raw hit counts
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+/// Returns AFL's bucket for a hit count, so that loops are only interesting when their trip
+/// count changes by an order of magnitude
+fn bucket(count: u8) -> u8 {
+    match count {
+        0 => 0,
+        1 => 1,
+        2 => 2,
+        3 => 4,
+        4..=7 => 8,
+        8..=15 => 16,
+        16..=31 => 32,
+        32..=127 => 64,
+        128..=255 => 128,
+    }
+}
+
+#[derive(Debug)]
+/// The hit count buckets of each edge not yet seen by any input
+pub struct Virgin(Vec<u8>);
+
+impl Default for Virgin {
+    fn default() -> Self {
+        Self(vec![0xff; MAP_SIZE])
+    }
+}
+
+impl Virgin {
+    /// Remove the buckets an input reached. Returns whether any had not been seen before.
+    pub fn merge(&mut self, map: &EdgeMap) -> bool {
+        let mut new = false;
+
+        for (virgin, count) in self.0.iter_mut().zip(map.as_bytes()) {
+            let bucket = bucket(*count);
+
+            if *virgin & bucket != 0 {
+                *virgin &= !bucket;
+                new = true;
+            }
+        }
+
+        new
+    }
+
+    /// Returns the number of edges any input has reached
+    pub fn edges(&self) -> usize {
+        self.0.iter().filter(|v| **v != 0xff).count()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// How running an input ended
+pub enum Outcome {
+    /// The guest reached the end marker
+    Ok,
+    /// The guest reached a crash marker
+    Crash,
+    /// The guest reached neither marker before the timeout
+    Timeout,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A marker the guest reached, sent from the vCPU to the fuzz loop
+pub enum Signal {
+    Start,
+    Done(Outcome),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for the guest to reach the start marker for the first time
+    Waiting,
+    /// Running an input
+    Running,
+    /// Stopped, or about to be, while the fuzz loop handles a marker
+    Stopped,
+}
+
+#[derive(Debug)]
+/// The guest side of the fuzzer, which watches executed blocks for markers and records edges
+pub struct FuzzTarget {
+    start: u64,
+    end: u64,
+    crashes: Vec<u64>,
+    map: EdgeMap,
+    /// The new program states the running input reached
+    new_states: u64,
+    phase: Phase,
+    signals: Sender<Signal>,
+}
+
+impl FuzzTarget {
+    /// Create a target with the given markers, and the receiver the fuzz loop waits on
+    pub fn new(start: u64, end: u64, crashes: Vec<u64>) -> (Self, Receiver<Signal>) {
+        let (signals, receiver) = channel();
+
+        (
+            Self {
+                start,
+                end,
+                crashes,
+                map: EdgeMap::default(),
+                new_states: 0,
+                phase: Phase::Waiting,
+                signals,
+            },
+            receiver,
+        )
+    }
+
+    /// Record that a vCPU executed the block at `pc`
+    pub fn on_block(&mut self, vcpu_index: VCPUIndex, pc: u64) -> Result<()> {
+        let signal = match self.phase {
+            Phase::Waiting if pc == self.start => Signal::Start,
+            Phase::Running => {
+                self.map.on_block(vcpu_index, pc);
+
+                if pc == self.end {
+                    Signal::Done(Outcome::Ok)
+                } else if self.crashes.contains(&pc) {
+                    Signal::Done(Outcome::Crash)
+                } else {
+                    return Ok(());
+                }
+            }
+            _ => return Ok(()),
+        };
+
+        self.phase = Phase::Stopped;
+        self.signals
+            .send(signal)
+            .map_err(|e| anyhow!("Failed to signal fuzz loop: {e}"))
+    }
+}
+
+impl FuzzTarget {
+    /// Returns the start marker
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// End the running input as a timeout, as when a watchdog finds the guest has run past
+    /// its budget
+    pub fn on_hang(&mut self) -> Result<()> {
+        if self.phase != Phase::Running {
+            return Ok(());
+        }
+
+        self.phase = Phase::Stopped;
+        self.signals
+            .send(Signal::Done(Outcome::Timeout))
+            .map_err(|e| anyhow!("Failed to signal fuzz loop: {e}"))
+    }
+
+    /// Record that the running input reached a program state not reached before, so that
+    /// it is interesting even without new edges
+    pub fn on_new_state(&mut self) {
+        if self.phase == Phase::Running {
+            self.new_states += 1;
+        }
+    }
+}
+
+/// Parse crash markers separated by `;`
+pub fn parse_markers(markers: &str) -> Result<Vec<u64>> {
+    markers
+        .split(';')
+        .filter(|m| !m.trim().is_empty())
+        .map(parse_addr)
+        .collect()
+}
+
+#[derive(Debug)]
+/// AFL-style havoc mutations
+pub struct Mutator {
+    rng: StdRng,
+    max_len: usize,
+}
+
+impl Mutator {
+    /// Create a mutator producing inputs of at most `max_len` bytes
+    pub fn new(seed: u64, max_len: usize) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            max_len,
+        }
+    }
+
+    /// Returns a random range of `data`
+    fn range(&mut self, data: &[u8]) -> (usize, usize) {
+        let start = self.rng.gen_range(0..data.len());
+        let len = self.rng.gen_range(1..=(data.len() - start).min(32));
+
+        (start, start + len)
+    }
+
+    /// Returns a mutation of a random entry in `queue`, which must not be empty
+    pub fn mutate(&mut self, queue: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = queue[self.rng.gen_range(0..queue.len())].clone();
+
+        for _ in 0..1 << self.rng.gen_range(1..=5) {
+            if data.is_empty() {
+                data.push(self.rng.gen());
+            }
+
+            match self.rng.gen_range(0..7) {
+                0 => {
+                    let bit = self.rng.gen_range(0..data.len() * 8);
+                    data[bit / 8] ^= 1 << (bit % 8);
+                }
+                1 => {
+                    let i = self.rng.gen_range(0..data.len());
+                    data[i] = self.rng.gen();
+                }
+                2 => {
+                    let value = INTERESTING[self.rng.gen_range(0..INTERESTING.len())];
+                    let width = [1, 2, 4][self.rng.gen_range(0..3)].min(data.len());
+                    let i = self.rng.gen_range(0..=data.len() - width);
+                    data[i..i + width].copy_from_slice(&value.to_le_bytes()[..width]);
+                }
+                3 => {
+                    let i = self.rng.gen_range(0..data.len());
+                    data[i] = data[i].wrapping_add(self.rng.gen_range(1..=35) as u8);
+                }
+                4 => {
+                    let (start, end) = self.range(&data);
+                    data.drain(start..end);
+                }
+                5 => {
+                    let (start, end) = self.range(&data);
+                    let i = self.rng.gen_range(0..=data.len());
+                    let block = data[start..end].to_vec();
+                    data.splice(i..i, block);
+                }
+                _ => {
+                    let other = &queue[self.rng.gen_range(0..queue.len())];
+                    let i = self.rng.gen_range(0..=data.len().min(other.len()));
+                    data.truncate(i);
+                    data.extend_from_slice(&other[i..]);
+                }
+            }
+        }
+
+        data.truncate(self.max_len);
+
+        if data.is_empty() {
+            data.push(0);
+        }
+
+        data
+    }
+}
+
+#[derive(TypedBuilder, Clone, Debug)]
+/// Where the fuzzer reads and writes inputs, and how long it runs
+pub struct FuzzConfig {
+    pub qmp_socket: PathBuf,
+    /// The file the guest reads inputs from as a disk
+    pub input: PathBuf,
+    /// The directory the queue, crashes and hangs are written to
+    pub output: PathBuf,
+    /// A directory of seed inputs, or `None` to start from a single zero byte
+    #[builder(default)]
+    pub corpus: Option<PathBuf>,
+    #[builder(default = 4096)]
+    pub max_len: usize,
+    #[builder(default = Duration::from_secs(1))]
+    pub timeout: Duration,
+    /// The number of inputs to run before QEMU is stopped, or `None` to run until killed
+    #[builder(default)]
+    pub iterations: Option<u64>,
+    #[builder(default)]
+    pub seed: u64,
+}
+
+/// Returns the seed inputs in `corpus`, in name order
+fn load_seeds(corpus: Option<&Path>, max_len: usize) -> Result<Vec<Vec<u8>>> {
+    let mut paths = match corpus {
+        Some(corpus) => read_dir(corpus)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+
+    paths.sort();
+
+    let mut seeds = paths
+        .into_iter()
+        .filter(|path| path.is_file())
+        .map(|path| {
+            let mut seed = read(path)?;
+            seed.truncate(max_len);
+            Ok(seed)
+        })
+        .filter(|seed| !matches!(seed, Ok(seed) if seed.is_empty()))
+        .collect::<Result<Vec<_>>>()?;
+
+    if seeds.is_empty() {
+        seeds.push(vec![0]);
+    }
+
+    Ok(seeds)
+}
+
+/// Run an HMP command which prints nothing on success, such as `savevm`
+fn hmp(qmp: &mut Qmp, command: &str) -> Result<()> {
+    let output = qmp.human_monitor_command(command)?;
+
+    if output.trim().is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("HMP command {command} failed: {}", output.trim()))
+    }
+}
+
+#[derive(Debug)]
+/// Runs one input at a time in the guest, each from the snapshot taken at the start marker
+///
+/// [`loop_`] is built on the harness, and a fuzzer with its own queue and mutations can be
+/// too: its executor calls [`Harness::run`] for each input, and its map observer reads the
+/// hit counts of [`EdgeMap::as_bytes`] through [`Harness::with_map`] once the run returns.
+/// The `libafl` feature provides both for LibAFL-based fuzzers.
+pub struct Harness {
+    qmp: Qmp,
+    target: Arc<Mutex<FuzzTarget>>,
+    signals: Receiver<Signal>,
+    input: File,
+    timeout: Duration,
+    /// Whether an input has run since the snapshot was taken or last restored
+    dirty: bool,
+}
+
+impl Harness {
+    /// Prepare the input file, then wait for the guest to reach the start marker and take
+    /// the snapshot. Blocks until the guest reaches the marker.
+    pub fn connect(
+        config: &FuzzConfig,
+        target: Arc<Mutex<FuzzTarget>>,
+        signals: Receiver<Signal>,
+    ) -> Result<Self> {
+        let input = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&config.input)?;
+
+        // QEMU sizes the disk when it opens the file, so it must already fit every input
+        input.set_len(4 + config.max_len as u64)?;
+
+        let mut qmp = Qmp::connect(&config.qmp_socket)?;
+
+        if signals.recv()? != Signal::Start {
+            return Err(anyhow!(
+                "The guest finished an input before reaching the start marker"
+            ));
+        }
+
+        qmp.execute("stop", None)?;
+        hmp(&mut qmp, &format!("savevm {SNAPSHOT}"))?;
+
+        Ok(Self {
+            qmp,
+            target,
+            signals,
+            input,
+            timeout: config.timeout,
+            dirty: false,
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, FuzzTarget>> {
+        self.target
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock fuzz target: {e}"))
+    }
+
+    /// Run an input from the snapshot and return how it ended. The VM is stopped when this
+    /// returns, and the edge map holds the input's coverage until the next run.
+    pub fn run(&mut self, data: &[u8]) -> Result<Outcome> {
+        if self.dirty {
+            hmp(&mut self.qmp, &format!("loadvm {SNAPSHOT}"))?;
+        }
+
+        self.input
+            .write_all_at(&(data.len() as u32).to_le_bytes(), 0)?;
+        self.input.write_all_at(data, 4)?;
+
+        {
+            let mut target = self.lock()?;
+
+            target.map.reset();
+            target.new_states = 0;
+            target.phase = Phase::Running;
+        }
+
+        self.dirty = true;
+        self.qmp.execute("cont", None)?;
+
+        let outcome = match self.signals.recv_timeout(self.timeout) {
+            Ok(Signal::Done(outcome)) => Some(outcome),
+            Ok(Signal::Start) => return Err(anyhow!("Unexpected start signal")),
+            Err(RecvTimeoutError::Timeout) => {
+                let mut target = self.lock()?;
+
+                // Otherwise a marker was reached just as the timeout elapsed
+                (target.phase == Phase::Running).then(|| {
+                    target.phase = Phase::Stopped;
+                    Outcome::Timeout
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let outcome = match outcome {
+            Some(outcome) => outcome,
+            None => match self.signals.recv()? {
+                Signal::Done(outcome) => outcome,
+                Signal::Start => return Err(anyhow!("Unexpected start signal")),
+            },
+        };
+
+        self.qmp.execute("stop", None)?;
+
+        Ok(outcome)
+    }
+
+    /// Call `f` with the edge map of the last run
+    pub fn with_map<T>(&self, f: impl FnOnce(&EdgeMap) -> T) -> Result<T> {
+        Ok(f(&self.lock()?.map))
+    }
+
+    /// Returns the number of new program states the last run reached
+    pub fn new_states(&self) -> Result<u64> {
+        Ok(self.lock()?.new_states)
+    }
+
+    /// Stop QEMU
+    pub fn quit(mut self) {
+        // QEMU closes the connection as it exits, so the response may never arrive
+        self.qmp.execute("quit", None).ok();
+    }
+}
+
+fn summary(queue: &[Vec<u8>], crashes: u64, hangs: u64, virgin: &Virgin) -> String {
+    format!(
+        "{} queued, {crashes} crashes, {hangs} hangs, {} edges",
+        queue.len(),
+        virgin.edges()
+    )
+}
+
+/// Run the fuzz loop, blocking until the configured number of inputs has run. QMP is served
+/// from QEMU's main loop, so this must run on a thread of the plugin's own.
+pub fn loop_(
+    config: FuzzConfig,
+    target: Arc<Mutex<FuzzTarget>>,
+    signals: Receiver<Signal>,
+) -> Result<()> {
+    let [queue_dir, crashes_dir, hangs_dir] =
+        ["queue", "crashes", "hangs"].map(|dir| config.output.join(dir));
+
+    for dir in [&queue_dir, &crashes_dir, &hangs_dir] {
+        create_dir_all(dir)?;
+    }
+
+    let mut queue = load_seeds(config.corpus.as_deref(), config.max_len)?;
+    let seeds = queue.len() as u64;
+    let mut mutator = Mutator::new(config.seed, config.max_len);
+    let mut virgin = Virgin::default();
+    let (mut crashes, mut hangs) = (0, 0);
+    let mut harness = Harness::connect(&config, target, signals)?;
+    let mut iteration = 0;
+
+    while config.iterations.is_none_or(|limit| iteration < limit) {
+        // Seeds run unmodified first, to measure their coverage
+        let data = match queue.get(iteration as usize).filter(|_| iteration < seeds) {
+            Some(seed) => seed.clone(),
+            None => mutator.mutate(&queue),
+        };
+
+        let outcome = harness.run(&data)?;
+        let new = harness.with_map(|map| virgin.merge(map))? | (harness.new_states()? > 0);
+
+        match outcome {
+            Outcome::Crash if new => {
+                write(crashes_dir.join(format!("id-{crashes:06}")), &data)?;
+                crashes += 1;
+            }
+            Outcome::Timeout if new => {
+                write(hangs_dir.join(format!("id-{hangs:06}")), &data)?;
+                hangs += 1;
+            }
+            Outcome::Ok if iteration < seeds => {
+                write(queue_dir.join(format!("id-{iteration:06}")), &data)?;
+            }
+            Outcome::Ok if new => {
+                write(queue_dir.join(format!("id-{:06}", queue.len())), &data)?;
+                queue.push(data);
+            }
+            _ => {}
+        }
+
+        iteration += 1;
+
+        if iteration % REPORT_INTERVAL == 0 {
+            log(format!(
+                "Fuzzer ran {iteration} inputs: {}",
+                summary(&queue, crashes, hangs, &virgin)
+            ));
+        }
+    }
+
+    log(format!(
+        "Fuzzer finished after {iteration} inputs: {}",
+        summary(&queue, crashes, hangs, &virgin)
+    ));
+
+    harness.quit();
+
+    Ok(())
+}
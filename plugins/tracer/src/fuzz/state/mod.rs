@@ -0,0 +1,144 @@
+//! Program state identifiers, hashed from chosen guest memory and registers at sequence
+//! points
+//!
+//! A stateful target, such as a protocol server, can take the same edges in many different
+//! states, so edge coverage alone cannot tell a fuzzer that an input moved it somewhere new.
+//! At each sequence point, a block chosen by the user such as the top of a message loop, the
+//! configured memory regions and registers are hashed into a state identifier. The first time
+//! a state is reached at a sequence point it is new, which the fuzz loop treats like new
+//! coverage.
+//!
+//! Only the state variables should be chosen: hashing buffers or counters which change on
+//! every message makes every state new.
+
+use crate::memmap::parse_addr;
+use anyhow::{anyhow, Result};
+use qemu_plugin::{qemu_plugin_read_memory_vaddr, RegisterDescriptor, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use typed_builder::TypedBuilder;
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// The state the guest was in at a sequence point
+pub struct StateEvent {
+    pub vcpu_index: VCPUIndex,
+    pub pc: u64,
+    pub state: u64,
+    /// Whether the state had not been reached at this sequence point before
+    pub new: bool,
+    pub icount: u64,
+}
+
+#[derive(Debug)]
+/// Hashes guest state at sequence points, remembering the states reached
+pub struct StateTracker {
+    points: Vec<u64>,
+    /// Regions of guest virtual memory, as `(address, length)`
+    regions: Vec<(u64, usize)>,
+    registers: Vec<String>,
+    seen: HashSet<(u64, u64)>,
+}
+
+impl StateTracker {
+    /// Create a tracker from sequence points separated by `;`, memory regions as
+    /// `address:length` separated by `;`, and register names separated by `;`, e.g.
+    /// `0x401a20`, `0x4c6f00:16;0x4c7010:4` and `rbx;r12`
+    pub fn new(points: &str, regions: Option<&str>, registers: Option<&str>) -> Result<Self> {
+        let points = points
+            .split(';')
+            .filter(|p| !p.trim().is_empty())
+            .map(parse_addr)
+            .collect::<Result<Vec<_>>>()?;
+        let regions = regions
+            .unwrap_or_default()
+            .split(';')
+            .filter(|r| !r.trim().is_empty())
+            .map(|r| {
+                let (address, length) = r
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("Invalid state region {r}, expected address:length"))?;
+
+                Ok((parse_addr(address)?, length.trim().parse()?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let registers = registers
+            .unwrap_or_default()
+            .split(';')
+            .map(|r| r.trim().to_string())
+            .filter(|r| !r.is_empty())
+            .collect::<Vec<_>>();
+
+        if points.is_empty() {
+            return Err(anyhow!("No sequence points given"));
+        }
+
+        if regions.is_empty() && registers.is_empty() {
+            return Err(anyhow!(
+                "State hashing requires memory regions or registers"
+            ));
+        }
+
+        Ok(Self {
+            points,
+            regions,
+            registers,
+            seen: HashSet::new(),
+        })
+    }
+
+    /// Returns whether a block starting at `pc` is a sequence point
+    pub fn is_point(&self, pc: u64) -> bool {
+        self.points.contains(&pc)
+    }
+
+    /// Check that QEMU exposes every register to hash
+    pub fn check_registers(&self, registers: &[RegisterDescriptor<'static>]) -> Result<()> {
+        match self
+            .registers
+            .iter()
+            .find(|name| !registers.iter().any(|r| r.name == **name))
+        {
+            Some(name) => Err(anyhow!("QEMU does not expose register {name}")),
+            None => Ok(()),
+        }
+    }
+
+    /// Hash the guest's state at the sequence point `pc`
+    pub fn on_point(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        pc: u64,
+        registers: &[RegisterDescriptor<'static>],
+        icount: u64,
+    ) -> Result<StateEvent> {
+        let mut hasher = Sha256::new();
+
+        for name in &self.registers {
+            let register = registers
+                .iter()
+                .find(|r| r.name == *name)
+                .ok_or_else(|| anyhow!("No register named {name}"))?;
+
+            hasher.update(register.read()?);
+        }
+
+        for (address, length) in &self.regions {
+            hasher.update(qemu_plugin_read_memory_vaddr(*address, *length)?);
+        }
+
+        let digest = hasher.finalize();
+        let mut state = [0u8; 8];
+        state.copy_from_slice(&digest[..8]);
+
+        let state = u64::from_le_bytes(state);
+
+        Ok(StateEvent::builder()
+            .vcpu_index(vcpu_index)
+            .pc(pc)
+            .state(state)
+            .new(self.seen.insert((pc, state)))
+            .icount(icount)
+            .build())
+    }
+}
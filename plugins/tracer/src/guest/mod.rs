@@ -0,0 +1,71 @@
+//! Helpers for reading structured data out of guest virtual memory
+
+use crate::arch::Arch;
+use anyhow::Result;
+use qemu_plugin::qemu_plugin_read_memory_vaddr;
+
+/// Reads are split at this boundary so that a string ending just before an unmapped page can
+/// still be read
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Read a NUL-terminated string of at most `max_len` bytes (excluding the terminator) from
+/// guest memory at `addr`. The terminator is not included in the result.
+pub fn read_cstring(addr: u64, max_len: usize) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut cursor = addr;
+
+    while data.len() < max_len {
+        let to_boundary = (PAGE_SIZE - (cursor % PAGE_SIZE)) as usize;
+        let chunk = qemu_plugin_read_memory_vaddr(cursor, to_boundary.min(max_len - data.len()))?;
+
+        if let Some(end) = chunk.iter().position(|b| *b == 0) {
+            data.extend_from_slice(&chunk[..end]);
+            return Ok(data);
+        }
+
+        cursor += chunk.len() as u64;
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(data)
+}
+
+/// Read an integer of `size` bytes (at most 8), such as a pointer, from guest memory in the
+/// guest's byte order
+pub fn read_pointer(arch: Arch, addr: u64, size: usize) -> Result<u64> {
+    Ok(arch.decode(&qemu_plugin_read_memory_vaddr(addr, size)?))
+}
+
+/// The most entries read from a guest `argv` or `envp` array
+const MAX_STRINGS: u64 = 4096;
+/// The longest single string read from a guest `argv` or `envp` array
+const MAX_STRING: usize = 0x10000;
+
+/// Read a NULL-terminated array of pointers to strings, as used for `argv` and `envp`.
+/// Returns the strings and the address just past the terminating NULL.
+fn read_string_array(arch: Arch, addr: u64) -> Result<(Vec<String>, u64)> {
+    let pointer_size = arch.pointer_size();
+    let mut strings = Vec::new();
+    let mut cursor = addr;
+
+    for _ in 0..MAX_STRINGS {
+        let pointer = read_pointer(arch, cursor, pointer_size)?;
+        cursor += pointer_size as u64;
+
+        if pointer == 0 {
+            break;
+        }
+
+        strings.push(String::from_utf8_lossy(&read_cstring(pointer, MAX_STRING)?).into_owned());
+    }
+
+    Ok((strings, cursor))
+}
+
+/// Read `argv` and `envp` from the initial process stack laid out by the kernel (or QEMU in
+/// user mode), where `sp` points at `argc`
+pub fn read_process_args(arch: Arch, sp: u64) -> Result<(Vec<String>, Vec<String>)> {
+    let (argv, envp_addr) = read_string_array(arch, sp + arch.pointer_size() as u64)?;
+    let (envp, _) = read_string_array(arch, envp_addr)?;
+    Ok((argv, envp))
+}
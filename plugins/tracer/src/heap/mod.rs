@@ -0,0 +1,374 @@
+//! Tracking of live heap allocations, so that memory accesses can be attributed to the
+//! allocation they touch and the call stack which allocated it
+//!
+//! Allocator functions are found by symbol name, or by address for stripped binaries. A
+//! shadow call stack is kept for each vCPU from the calls and returns it executes: a block
+//! reached through a call (or through the PLT stub a call lands on) is a function entry,
+//! where an allocator's arguments are read, and the return which unwinds its frame carries
+//! its return value. Frames are unwound to the block a return lands on, so tail calls and
+//! `longjmp` do not desynchronize the stack.
+//!
+//! QEMU only has symbols for the guest binary itself, so allocators in a dynamically linked
+//! C library must be given by address.
+
+use crate::{arch::Arch, guest::read_pointer, memmap::parse_addr};
+use anyhow::{anyhow, Result};
+use qemu_plugin::{RegisterDescriptor, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+/// The deepest a shadow stack may grow before its oldest frames are dropped, bounding the
+/// cost of calls which never return, such as those into `exit`
+const MAX_FRAMES: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+/// The allocator functions which are tracked
+pub enum HeapFunction {
+    Malloc,
+    Calloc,
+    Realloc,
+    Free,
+}
+
+/// Allocator symbol names, including glibc's internal aliases which static binaries resolve
+/// the public names to
+const SYMBOLS: &[(&str, HeapFunction)] = &[
+    ("malloc", HeapFunction::Malloc),
+    ("__libc_malloc", HeapFunction::Malloc),
+    ("calloc", HeapFunction::Calloc),
+    ("__libc_calloc", HeapFunction::Calloc),
+    ("realloc", HeapFunction::Realloc),
+    ("__libc_realloc", HeapFunction::Realloc),
+    ("free", HeapFunction::Free),
+    ("cfree", HeapFunction::Free),
+    ("__libc_free", HeapFunction::Free),
+];
+
+impl FromStr for HeapFunction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        SYMBOLS
+            .iter()
+            .find_map(|(name, function)| (*name == s).then_some(*function))
+            .ok_or_else(|| anyhow!("Unknown allocator function {s}"))
+    }
+}
+
+impl HeapFunction {
+    /// Returns the allocator function a symbol names, if any
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        symbol.parse().ok()
+    }
+
+    fn arguments(&self) -> usize {
+        match self {
+            Self::Malloc | Self::Free => 1,
+            Self::Calloc | Self::Realloc => 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A live heap allocation
+pub struct Allocation {
+    pub start: u64,
+    pub size: u64,
+    /// The return addresses on the call stack when it was allocated, innermost first
+    pub site: Vec<u64>,
+    pub icount: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// The allocation a memory access falls in
+pub struct Attribution {
+    pub start: u64,
+    pub size: u64,
+    /// The offset of the access within the allocation
+    pub offset: u64,
+    pub site: Vec<u64>,
+}
+
+#[derive(Clone, Debug)]
+struct Frame {
+    return_addr: u64,
+    /// The allocator call this frame is for, and its arguments
+    call: Option<(HeapFunction, [u64; 2])>,
+}
+
+#[derive(Debug, Default)]
+struct VcpuState {
+    frames: Vec<Frame>,
+    /// The return address of a call which has just executed, until its target block runs
+    entering: Option<u64>,
+    /// The return value of a return which has just executed, until its target block runs
+    returning: Option<u64>,
+    /// Whether the last block ran was the first of the top frame, so that the next block
+    /// may be the function a PLT stub jumps to
+    entered: bool,
+}
+
+#[derive(Debug)]
+/// The live allocations of the guest, and the shadow call stacks used to find them
+pub struct HeapTracker {
+    arch: Arch,
+    /// Allocator functions given by address, for binaries without symbols
+    functions: HashMap<u64, HeapFunction>,
+    /// How many return addresses are recorded for each allocation site
+    depth: usize,
+    vcpus: HashMap<VCPUIndex, VcpuState>,
+    allocations: BTreeMap<u64, Allocation>,
+}
+
+/// Read a register as an integer in the guest's byte order, zero-extending values narrower
+/// than 64 bits
+pub(crate) fn read_register(
+    arch: Arch,
+    registers: &[RegisterDescriptor<'static>],
+    name: &str,
+) -> Result<u64> {
+    let value = registers
+        .iter()
+        .find(|r| r.name == name)
+        .ok_or_else(|| anyhow!("No register named {name}"))?
+        .read()?;
+
+    Ok(arch.decode(&value))
+}
+
+/// Read the `i`th integer argument of a function, at its first block
+pub(crate) fn read_argument(
+    arch: Arch,
+    registers: &[RegisterDescriptor<'static>],
+    i: usize,
+) -> Result<u64> {
+    match arch.argument_registers().get(i) {
+        Some(name) => read_register(arch, registers, name),
+        None => {
+            // Arguments are on the stack, above the return address
+            let sp = read_register(arch, registers, arch.stack_pointer_names()[0])?;
+            let size = arch.pointer_size();
+
+            read_pointer(arch, sp + ((i + 1) * size) as u64, size)
+        }
+    }
+}
+
+impl HeapTracker {
+    /// Create a tracker, with allocator functions given by address as `name:address`
+    /// pairs separated by `;`, e.g. `malloc:0x401a20;free:0x401c80`
+    pub fn new(arch: Arch, functions: Option<&str>, depth: usize) -> Result<Self> {
+        let functions = functions
+            .unwrap_or_default()
+            .split(';')
+            .filter(|f| !f.trim().is_empty())
+            .map(|f| {
+                let (name, addr) = f.split_once(':').ok_or_else(|| {
+                    anyhow!("Invalid allocator function {f}, expected name:address")
+                })?;
+
+                Ok((parse_addr(addr)?, name.trim().parse()?))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        Ok(Self {
+            arch,
+            functions,
+            depth,
+            vcpus: HashMap::new(),
+            allocations: BTreeMap::new(),
+        })
+    }
+
+    /// Returns the allocator function a block starting at `vaddr` with `symbol` belongs to
+    pub fn function(&self, vaddr: u64, symbol: Option<&str>) -> Option<HeapFunction> {
+        self.functions
+            .get(&vaddr)
+            .copied()
+            .or_else(|| symbol.and_then(HeapFunction::from_symbol))
+    }
+
+    /// Record that a call instruction whose return address is `return_addr` executed
+    pub fn on_call(&mut self, vcpu_index: VCPUIndex, return_addr: u64) {
+        self.vcpus.entry(vcpu_index).or_default().entering = Some(return_addr);
+    }
+
+    /// Record that a return instruction executed. Its return value is read now, before
+    /// the caller can overwrite it.
+    pub fn on_return(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        registers: &[RegisterDescriptor<'static>],
+    ) -> Result<()> {
+        let state = self.vcpus.entry(vcpu_index).or_default();
+
+        state.returning = if state.frames.iter().any(|f| f.call.is_some()) {
+            Some(read_register(
+                self.arch,
+                registers,
+                self.arch.return_register(),
+            )?)
+        } else {
+            Some(0)
+        };
+
+        Ok(())
+    }
+
+    /// Record that the block at `vaddr` executed. `function` is the allocator function the
+    /// block belongs to, if any.
+    pub fn on_block(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        vaddr: u64,
+        function: Option<HeapFunction>,
+        registers: &[RegisterDescriptor<'static>],
+        icount: u64,
+    ) -> Result<()> {
+        let arch = self.arch;
+        let state = self.vcpus.entry(vcpu_index).or_default();
+
+        if let Some(value) = state.returning.take() {
+            if let Some(i) = state.frames.iter().rposition(|f| f.return_addr == vaddr) {
+                let completed = state.frames.split_off(i);
+                let callers = state
+                    .frames
+                    .iter()
+                    .rev()
+                    .map(|f| f.return_addr)
+                    .collect::<Vec<_>>();
+
+                for frame in completed.into_iter().rev() {
+                    if let Some((function, args)) = frame.call {
+                        // The allocator's own return address is its call site
+                        let site = [frame.return_addr]
+                            .into_iter()
+                            .chain(callers.iter().copied())
+                            .take(self.depth)
+                            .collect();
+
+                        self.complete(function, args, value, site, icount);
+                    }
+                }
+            }
+        }
+
+        let state = self.vcpus.entry(vcpu_index).or_default();
+        let entered = std::mem::take(&mut state.entered);
+        let entering = state.entering.take();
+
+        // Only the first block of a call, or a block jumped to from it as by a PLT stub,
+        // is a function entry whose arguments are intact
+        if entering.is_none() && (!entered || function.is_none()) {
+            return Ok(());
+        }
+
+        let call = match function {
+            Some(function) => Some((function, Self::arguments(arch, function, registers)?)),
+            None => None,
+        };
+
+        if let Some(return_addr) = entering {
+            if state.frames.len() >= MAX_FRAMES {
+                state.frames.remove(0);
+            }
+
+            state.frames.push(Frame { return_addr, call });
+            state.entered = true;
+        } else if let Some(frame) = state.frames.last_mut().filter(|f| f.call.is_none()) {
+            frame.call = call;
+        }
+
+        // free has no result to wait for, so it is applied on entry
+        if let Some((HeapFunction::Free, [ptr, _])) = call {
+            self.allocations.remove(&ptr);
+        }
+
+        Ok(())
+    }
+
+    fn arguments(
+        arch: Arch,
+        function: HeapFunction,
+        registers: &[RegisterDescriptor<'static>],
+    ) -> Result<[u64; 2]> {
+        let mut args = [0; 2];
+
+        for (i, arg) in args.iter_mut().enumerate().take(function.arguments()) {
+            *arg = read_argument(arch, registers, i)?;
+        }
+
+        Ok(args)
+    }
+
+    fn complete(
+        &mut self,
+        function: HeapFunction,
+        args: [u64; 2],
+        ptr: u64,
+        site: Vec<u64>,
+        icount: u64,
+    ) {
+        let size = match function {
+            HeapFunction::Malloc => args[0],
+            HeapFunction::Calloc => args[0].saturating_mul(args[1]),
+            HeapFunction::Realloc => {
+                self.allocations.remove(&args[0]);
+                args[1]
+            }
+            HeapFunction::Free => return,
+        };
+
+        if ptr == 0 {
+            return;
+        }
+
+        self.allocations.insert(
+            ptr,
+            Allocation {
+                start: ptr,
+                size,
+                site,
+                icount,
+            },
+        );
+    }
+
+    /// Returns the live allocation containing `vaddr`, if any
+    pub fn attribute(&self, vaddr: u64) -> Option<Attribution> {
+        let (_, allocation) = self.allocations.range(..=vaddr).next_back()?;
+        let offset = vaddr - allocation.start;
+
+        (offset < allocation.size).then(|| Attribution {
+            start: allocation.start,
+            size: allocation.size,
+            offset,
+            site: allocation.site.clone(),
+        })
+    }
+
+    /// Returns the return addresses on a vCPU's shadow call stack, innermost first, up to
+    /// the allocation site depth
+    pub fn call_stack(&self, vcpu_index: VCPUIndex) -> Vec<u64> {
+        self.vcpus
+            .get(&vcpu_index)
+            .map(|state| {
+                state
+                    .frames
+                    .iter()
+                    .rev()
+                    .map(|f| f.return_addr)
+                    .take(self.depth)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the live allocations, by start address
+    pub fn allocations(&self) -> &BTreeMap<u64, Allocation> {
+        &self.allocations
+    }
+}
@@ -0,0 +1,79 @@
+//! Decoding of hypercalls made by guest programs linked with `qemu-plugin-guest`
+//!
+//! A hypercall is a syscall with number [`SYSCALL`] whose first argument is the [`Op`]. With
+//! `guest_hypercalls=true`, bookmarks are logged as bookmark events, coverage hints are added
+//! to the coverage set as well as logged, and messages are logged. Strings and messages are
+//! read from guest memory when the syscall is made, truncated to [`MAX_LEN`] bytes.
+
+use anyhow::{anyhow, Result};
+use qemu_plugin::{qemu_plugin_read_memory_vaddr, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use typed_builder::TypedBuilder;
+
+pub use qemu_plugin_guest::{Op, MAX_LEN, SYSCALL};
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A decoded hypercall
+pub enum Hypercall {
+    Bookmark {
+        name: String,
+        note: Option<String>,
+    },
+    /// A point the guest identifies by number was reached
+    Coverage(u64),
+    Message {
+        kind: u64,
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+}
+
+impl Hypercall {
+    /// Decode a syscall, returning `None` if it is not a hypercall. A hypercall with an
+    /// unknown op or unreadable arguments also returns `None`, so that a guest built against
+    /// a newer `qemu-plugin-guest` or passing a bad pointer only makes an ordinary syscall.
+    pub fn decode(num: i64, args: [u64; 8]) -> Option<Self> {
+        if num != SYSCALL as i64 {
+            return None;
+        }
+
+        Some(match Op::from_u64(args[0])? {
+            Op::Bookmark => Self::Bookmark {
+                name: read_string(args[1], args[2]).ok()?,
+                note: (args[3] != 0)
+                    .then(|| read_string(args[3], args[4]))
+                    .transpose()
+                    .ok()?,
+            },
+            Op::Coverage => Self::Coverage(args[1]),
+            Op::Message => Self::Message {
+                kind: args[1],
+                data: read(args[2], args[3]).ok()?,
+            },
+        })
+    }
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// A coverage hint or message from the guest
+pub struct HypercallEvent {
+    pub vcpu_index: VCPUIndex,
+    pub hypercall: Hypercall,
+    pub icount: u64,
+}
+
+/// Read `len` bytes of guest memory at `address`, truncated to [`MAX_LEN`]
+fn read(address: u64, len: u64) -> Result<Vec<u8>> {
+    let len = (len as usize).min(MAX_LEN);
+
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+
+    qemu_plugin_read_memory_vaddr(address, len)
+        .map_err(|e| anyhow!("Failed to read hypercall argument at {address:#x}: {e}"))
+}
+
+fn read_string(address: u64, len: u64) -> Result<String> {
+    Ok(String::from_utf8_lossy(&read(address, len)?).into_owned())
+}
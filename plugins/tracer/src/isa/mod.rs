@@ -0,0 +1,117 @@
+//! Tracking of the instruction set each vCPU executes, for guests which switch between ARM
+//! and Thumb code or between x86's real, protected and long modes
+//!
+//! The mode is read from the target's mode registers at the start of every block: the T and
+//! J bits of the CPSR on ARM, and on AArch64 also the nRW bit of PSTATE, which QEMU exposes
+//! as `cpsr`, and `cr0` and `efer` on x86. Every instruction which switches modes also ends
+//! its block, so the mode read at a block's start holds for all of its instructions. A change
+//! is sent as a [`ModeEvent`], and instruction events are annotated with the mode they ran in.
+//!
+//! x86 exposes its control registers in system mode only. In user mode, or when QEMU does not
+//! expose the registers, every vCPU is taken to stay in the target's default mode.
+
+use crate::{
+    arch::{Arch, IsaMode},
+    heap::read_register,
+};
+use anyhow::Result;
+use qemu_plugin::{RegisterDescriptor, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A vCPU switching instruction sets, seen at the start of the block at `pc`
+pub struct ModeEvent {
+    pub vcpu_index: VCPUIndex,
+    pub pc: u64,
+    /// The mode the vCPU was last seen in, or `None` for its first block
+    pub from: Option<IsaMode>,
+    pub to: IsaMode,
+    pub icount: u64,
+}
+
+#[derive(Debug)]
+/// The instruction set each vCPU was in at the start of its last block
+pub struct IsaTracker {
+    arch: Arch,
+    /// Whether QEMU exposes every mode register of the target
+    readable: bool,
+    modes: HashMap<VCPUIndex, IsaMode>,
+    /// The mode of the most recently executed block on any vCPU
+    last: IsaMode,
+}
+
+impl IsaTracker {
+    pub fn new(arch: Arch) -> Self {
+        Self {
+            arch,
+            readable: false,
+            modes: HashMap::new(),
+            last: arch.default_mode(),
+        }
+    }
+
+    /// Check that the mode registers are among a vCPU's registers. Targets with a single
+    /// instruction set have none, and are never tracked.
+    pub fn find_registers(&mut self, registers: &[RegisterDescriptor<'static>]) {
+        let names = self.arch.mode_register_names();
+
+        self.readable = !names.is_empty()
+            && names
+                .iter()
+                .all(|name| registers.iter().any(|r| r.name == *name));
+    }
+
+    /// Whether modes can be read, so that blocks need to be tracked
+    pub fn is_readable(&self) -> bool {
+        self.readable
+    }
+
+    /// Record that the block at `pc` executed on a vCPU. Returns a mode event if the vCPU has
+    /// switched instruction sets since its last block, or if this is its first block and it
+    /// is not in the target's default mode.
+    pub fn on_block(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        registers: &[RegisterDescriptor<'static>],
+        pc: u64,
+        icount: u64,
+    ) -> Result<Option<ModeEvent>> {
+        let values = self
+            .arch
+            .mode_register_names()
+            .iter()
+            .map(|name| read_register(self.arch, registers, name))
+            .collect::<Result<Vec<_>>>()?;
+        let to = self.arch.mode(&values);
+        let from = self.modes.insert(vcpu_index, to);
+
+        self.last = to;
+
+        if from.unwrap_or(self.arch.default_mode()) == to {
+            return Ok(None);
+        }
+
+        Ok(Some(ModeEvent {
+            vcpu_index,
+            pc,
+            from,
+            to,
+            icount,
+        }))
+    }
+
+    /// The mode a vCPU was in at the start of its last block
+    pub fn mode(&self, vcpu_index: VCPUIndex) -> IsaMode {
+        self.modes
+            .get(&vcpu_index)
+            .copied()
+            .unwrap_or(self.arch.default_mode())
+    }
+
+    /// The mode of the most recently executed block, which code translated next is most
+    /// likely to run in
+    pub fn last(&self) -> IsaMode {
+        self.last
+    }
+}
@@ -0,0 +1,293 @@
+//! Recovery of structure layouts from the memory accesses attributed to heap allocations
+//!
+//! Accesses are grouped by the call stack which allocated the memory they touch, on the
+//! assumption that each allocation site allocates one type. Within a site, each offset
+//! accessed becomes a field sized by the access width used most often at it, and accesses
+//! overlapping a field (such as a `memcpy` of the whole structure) are folded into it. The
+//! result is written out as packed C structures, with padding for the bytes never accessed.
+
+use crate::{Event, MemoryEvent};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The number of each kind of access made to a field
+pub struct AccessCounts {
+    pub reads: u64,
+    pub writes: u64,
+    /// Reads which sign-extended the value, suggesting a signed integer field
+    pub signed_reads: u64,
+}
+
+impl AccessCounts {
+    fn add(&mut self, other: &Self) {
+        self.reads += other.reads;
+        self.writes += other.writes;
+        self.signed_reads += other.signed_reads;
+    }
+
+    fn total(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    /// The kinds of access made, as `read`, `write` or `read/write`
+    pub fn kind(&self) -> &'static str {
+        match (self.reads > 0, self.writes > 0) {
+            (true, true) => "read/write",
+            (false, true) => "write",
+            _ => "read",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A field proposed for a structure
+pub struct Field {
+    pub offset: u64,
+    pub size: u64,
+    pub accesses: AccessCounts,
+}
+
+impl Field {
+    fn c_type(&self) -> Option<&'static str> {
+        let signed = self.accesses.signed_reads > 0;
+
+        Some(match (self.size, signed) {
+            (1, false) => "uint8_t",
+            (1, true) => "int8_t",
+            (2, false) => "uint16_t",
+            (2, true) => "int16_t",
+            (4, false) => "uint32_t",
+            (4, true) => "int32_t",
+            (8, false) => "uint64_t",
+            (8, true) => "int64_t",
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// The structure layout proposed for the allocations made at one site
+pub struct StructLayout {
+    /// The return addresses on the call stack of the allocations, innermost first
+    pub site: Vec<u64>,
+    /// The size allocated most often at the site
+    pub size: u64,
+    /// The number of distinct allocations accessed
+    pub allocations: usize,
+    pub fields: Vec<Field>,
+}
+
+impl StructLayout {
+    /// A C identifier for the structure, named after its innermost allocation site
+    pub fn name(&self) -> String {
+        match self.site.first() {
+            Some(addr) => format!("alloc_{addr:x}"),
+            None => "alloc_unknown".to_string(),
+        }
+    }
+
+    /// Write the layout as a packed C structure named `name`, padded out to its size
+    pub fn write_c(&self, f: &mut Formatter<'_>, name: &str) -> fmt::Result {
+        let site = self
+            .site
+            .iter()
+            .map(|addr| format!("{addr:#x}"))
+            .collect::<Vec<_>>()
+            .join(" <- ");
+
+        writeln!(
+            f,
+            "/* Allocated at {site}: {} allocations, usually of {} bytes */",
+            self.allocations, self.size
+        )?;
+        writeln!(f, "struct __attribute__((packed)) {name} {{")?;
+
+        let mut end = 0;
+
+        for field in &self.fields {
+            if field.offset > end {
+                writeln!(f, "    uint8_t pad_{end:x}[{}];", field.offset - end)?;
+            }
+
+            let comment = format!(
+                "/* {:#x}: {}, {} reads, {} writes */",
+                field.offset,
+                field.accesses.kind(),
+                field.accesses.reads,
+                field.accesses.writes
+            );
+
+            match field.c_type() {
+                Some(ty) => writeln!(f, "    {ty} field_{:x}; {comment}", field.offset)?,
+                None => writeln!(
+                    f,
+                    "    uint8_t field_{:x}[{}]; {comment}",
+                    field.offset, field.size
+                )?,
+            }
+
+            end = field.offset + field.size;
+        }
+
+        if self.size > end {
+            writeln!(f, "    uint8_t pad_{end:x}[{}];", self.size - end)?;
+        }
+
+        writeln!(f, "}};")
+    }
+}
+
+#[derive(Debug, Default)]
+struct SiteAccesses {
+    /// The number of allocations of each size
+    sizes: HashMap<u64, usize>,
+    starts: HashSet<u64>,
+    /// Accesses by offset and width
+    accesses: BTreeMap<(u64, u64), AccessCounts>,
+}
+
+impl SiteAccesses {
+    fn layout(&self, site: Vec<u64>) -> StructLayout {
+        // The width used most often at each offset, and every access made at it
+        let mut offsets = BTreeMap::<u64, (u64, u64, AccessCounts)>::new();
+
+        for ((offset, size), counts) in &self.accesses {
+            let (width, count, total) = offsets.entry(*offset).or_default();
+
+            if counts.total() > *count || (counts.total() == *count && size > width) {
+                *width = *size;
+                *count = counts.total();
+            }
+
+            total.add(counts);
+        }
+
+        let mut fields = Vec::<Field>::new();
+
+        for (offset, (size, _, accesses)) in offsets {
+            match fields.last_mut() {
+                Some(field) if offset < field.offset + field.size => {
+                    field.size = field.size.max(offset + size - field.offset);
+                    field.accesses.add(&accesses);
+                }
+                _ => fields.push(Field {
+                    offset,
+                    size,
+                    accesses,
+                }),
+            }
+        }
+
+        let size = self
+            .sizes
+            .iter()
+            .max_by_key(|(size, count)| (**count, **size))
+            .map(|(size, _)| *size)
+            .unwrap_or_default();
+
+        StructLayout {
+            site,
+            size,
+            allocations: self.starts.len(),
+            fields,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// Collects the memory accesses of a trace which fall in heap allocations
+pub struct LayoutLearner {
+    sites: HashMap<Vec<u64>, SiteAccesses>,
+}
+
+impl LayoutLearner {
+    /// Create an empty learner
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Learn from every memory event in `events`
+    pub fn from_events<'a, I>(events: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Event>,
+    {
+        let mut learner = Self::new();
+
+        for event in events {
+            if let Event::Memory(event) = event {
+                learner.add(event);
+            }
+        }
+
+        learner
+    }
+
+    /// Record a memory access. Accesses outside any tracked allocation are ignored.
+    pub fn add(&mut self, event: &MemoryEvent) {
+        let Some(allocation) = event.allocation.as_ref() else {
+            return;
+        };
+
+        let site = self.sites.entry(allocation.site.clone()).or_default();
+
+        if site.starts.insert(allocation.start) {
+            *site.sizes.entry(allocation.size).or_default() += 1;
+        }
+
+        let counts = site
+            .accesses
+            .entry((allocation.offset, event.size_bytes as u64))
+            .or_default();
+
+        if event.is_store {
+            counts.writes += 1;
+        } else {
+            counts.reads += 1;
+            counts.signed_reads += event.sign_extended as u64;
+        }
+    }
+
+    /// Returns the layout proposed for each allocation site, ordered by site
+    pub fn layouts(&self) -> Vec<StructLayout> {
+        let mut layouts = self
+            .sites
+            .iter()
+            .map(|(site, accesses)| accesses.layout(site.clone()))
+            .collect::<Vec<_>>();
+
+        layouts.sort_by(|a, b| a.site.cmp(&b.site));
+        layouts
+    }
+}
+
+/// The layouts of a trace, displayed as a C header of structure skeletons
+pub struct CStructs(pub Vec<StructLayout>);
+
+impl Display for CStructs {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "#include <stdint.h>")?;
+
+        // Sites sharing an innermost return address are told apart by a suffix
+        let mut names = HashMap::<String, usize>::new();
+
+        for layout in &self.0 {
+            let name = layout.name();
+            let seen = names.entry(name.clone()).or_default();
+            let name = match *seen {
+                0 => name,
+                n => format!("{name}_{n}"),
+            };
+
+            *seen += 1;
+
+            writeln!(f)?;
+            layout.write_c(f, &name)?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,24 +1,239 @@
+#[cfg(feature = "object-store")]
+use crate::object_store::ObjectSink;
+use aggregate::Report;
+use analysis::{
+    extensions::ExtensionProfiler,
+    srcline::SourceProfiler,
+    symprof::{InsnClass, SymbolProfiler, Weights},
+    Analyses,
+};
 use anyhow::{anyhow, Error, Result};
+#[cfg(feature = "plugin-api-v4")]
+use api::{ApiCallEvent, ApiTracer};
+use arch::{
+    decoder::{AddressBreakdown, Decoder},
+    Arch, IsaMode,
+};
+#[cfg(feature = "plugin-api-v4")]
+use arch::{Branch, Exclusive, Syscall};
+use aslr::{LoadedModule, Relocator};
+use bookmarks::{BookmarkEvent, BookmarkSource, Bookmarks};
+use check::CheckReport;
+use clock::ClockEvent;
+use coalesce::{CoalesceMode, MemoryRangeEvent, WriteCoalescer};
+use coverage::{BlockIdentity, CoverageEvent, CoverageSet, CoverageTracker, Module};
+#[cfg(feature = "plugin-api-v4")]
+use crash::{CrashConfig, CrashEvent, CrashRecorder, CrashTrigger, TraceWindow};
+#[cfg(feature = "plugin-api-v4")]
+use crypto::{CryptoDetector, CryptoEvent};
 use ctor::ctor;
 #[cfg(feature = "plugin-api-v4")]
-use qemu_plugin::qemu_plugin_read_memory_vaddr;
+use debuginfo::{DebugLocator, Symbolizer};
+use decisions::{Access, Callback, Decision, DecisionLogger};
+use dedup::{Counter, Dedup};
+use demangle::{DemanglePolicy, Demangler};
+#[cfg(feature = "plugin-api-v4")]
+use dump::{DumpConfig, DumpEvent, DumpRange, DumpTrigger, Dumper};
+use encoding::{PcBatch, PcBuffers};
+#[cfg(feature = "plugin-api-v4")]
+use exclusive::ExclusiveTracker;
+#[cfg(feature = "plugin-api-v4")]
+use faults::{FaultEvent, FaultInjector};
+#[cfg(feature = "plugin-api-v4")]
+use files::{FileLimits, FileTracker};
+use filter::SectionFilter;
+#[cfg(feature = "plugin-api-v4")]
+use fuzz::input::InputInjector;
+#[cfg(feature = "plugin-api-v4")]
+use fuzz::state::{StateEvent, StateTracker};
+use fuzz::{honggfuzz::HonggfuzzFeedback, FuzzConfig, FuzzTarget};
+#[cfg(feature = "plugin-api-v4")]
+use guest::read_process_args;
+#[cfg(feature = "plugin-api-v4")]
+use heap::{Attribution, HeapTracker};
+#[cfg(feature = "plugin-api-v4")]
+use hypercall::{Hypercall, HypercallEvent};
+#[cfg(feature = "plugin-api-v4")]
+use isa::{IsaTracker, ModeEvent};
+#[cfg(feature = "plugin-api-v4")]
+use limits::Budget;
+use limits::LimitPolicy;
+#[cfg(feature = "plugin-api-v4")]
+use locks::LockTracker;
+use memmap::{MapSource, MemoryMap};
+use mmu::{TranslationEvent, TranslationSource, TranslationTracker};
+use modules::ModuleMap;
+#[cfg(feature = "plugin-api-v4")]
+use modules::Section;
+#[cfg(feature = "plugin-api-v4")]
+use net::NetTracker;
+#[cfg(feature = "plugin-api-v4")]
+use periph::{mem_value, PeriphEvent, Peripherals};
+#[cfg(feature = "plugin-api-v4")]
+use plt::PltResolver;
+use presets::PresetArgs;
+#[cfg(feature = "plugin-api-v4")]
+use probes::{EntropyProbe, ProbeEvent};
 use qemu_plugin::{
     install::{Args, Info, Value},
     plugin::{HasCallbacks, Plugin, Register, PLUGIN},
+    qemu_plugin_end_code, qemu_plugin_outs, qemu_plugin_path_to_binary,
+    qemu_plugin_register_atexit_cb, qemu_plugin_start_code, qemu_plugin_uninstall,
+    target::{Cpu, TargetInfo},
     Instruction, MemRW, MemoryInfo, PluginId, TranslationBlock, VCPUIndex,
 };
+#[cfg(feature = "plugin-api-v4")]
+use qemu_plugin::{qemu_plugin_entry_code, qemu_plugin_read_memory_vaddr, CallbackFlags, MemValue};
 #[cfg(not(feature = "plugin-api-v1"))]
 use qemu_plugin::{qemu_plugin_get_registers, RegisterDescriptor};
+#[cfg(feature = "plugin-api-v4")]
+use races::{RaceAccess, RaceDetector, RaceEvent};
+#[cfg(feature = "plugin-api-v4")]
+use random::{RandomEvent, RandomTracker};
+#[cfg(feature = "plugin-api-v4")]
+use reference::{DivergenceEvent, ReferenceChecker};
+use retranslation::{code_hash, RetranslationTracker};
+#[cfg(feature = "plugin-api-v4")]
+use rop::{RopConfig, RopDetector, RopEvent};
+use sampler::SampleConfig;
+#[cfg(feature = "plugin-api-v4")]
+use sched::{ScheduleEvent, Scheduler};
 use serde::{Deserialize, Serialize};
 use serde_cbor::to_writer;
+use session::{RunManifest, RUN_MANIFEST};
+#[cfg(feature = "plugin-api-v4")]
+use shadow::{IntegrityEvent, ShadowStack};
+#[cfg(feature = "plugin-api-v4")]
+use signatures::{SignatureEvent, SignatureScanner};
+use stats::{ControlAddress, Stats};
 use std::{
     collections::HashMap,
+    fmt,
+    io::{self, Write},
+    net::TcpStream,
     os::unix::net::UnixStream,
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{atomic::Ordering, Arc, Mutex},
+    thread::spawn,
+    time::Duration,
 };
+#[cfg(feature = "plugin-api-v4")]
+use strings::{StringEvent, StringTracker};
+#[cfg(feature = "plugin-api-v4")]
+use sync::Latch;
+#[cfg(feature = "plugin-api-v4")]
+use testing::{ExpectationFile, Expectations};
+use throttle::{DroppedEvent, EventClass, Throttle};
+use tracefile::{ShardedTraceFile, TraceFile};
 use typed_builder::TypedBuilder;
-use yaxpeax_x86::amd64::InstDecoder;
+#[cfg(feature = "plugin-api-v4")]
+use uart::{UartDecoder, UartEvent};
+use utilization::{UtilizationEvent, UtilizationReport};
+#[cfg(feature = "plugin-api-v4")]
+use watchdog::{HangEvent, Watchdog};
+#[cfg(feature = "plugin-api-v4")]
+use wx::{WxEvent, WxTracker};
+#[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+use yara::{YaraConfig, YaraEvent, YaraScanner};
+
+pub mod aggregate;
+pub mod analysis;
+#[cfg(feature = "plugin-api-v4")]
+pub mod api;
+pub mod arch;
+pub mod aslr;
+pub mod bookmarks;
+pub mod check;
+pub mod clock;
+pub mod coalesce;
+pub mod coverage;
+#[cfg(feature = "plugin-api-v4")]
+pub mod crash;
+#[cfg(feature = "plugin-api-v4")]
+pub mod crypto;
+#[cfg(feature = "plugin-api-v4")]
+pub mod debuginfo;
+pub mod decisions;
+pub mod dedup;
+pub mod demangle;
+#[cfg(feature = "plugin-api-v4")]
+pub mod dump;
+pub mod emu;
+pub mod encoding;
+#[cfg(feature = "plugin-api-v4")]
+pub mod exclusive;
+pub mod execlog;
+#[cfg(feature = "plugin-api-v4")]
+pub mod faults;
+#[cfg(feature = "plugin-api-v4")]
+pub mod files;
+pub mod filter;
+pub mod fuzz;
+#[cfg(feature = "plugin-api-v4")]
+pub mod guest;
+#[cfg(feature = "plugin-api-v4")]
+pub mod heap;
+#[cfg(feature = "plugin-api-v4")]
+pub mod hypercall;
+#[cfg(feature = "plugin-api-v4")]
+pub mod isa;
+#[cfg(feature = "plugin-api-v4")]
+pub mod layout;
+pub mod limits;
+#[cfg(feature = "plugin-api-v4")]
+pub mod locks;
+pub mod memmap;
+pub mod mmu;
+pub mod modules;
+#[cfg(feature = "plugin-api-v4")]
+pub mod net;
+#[cfg(feature = "object-store")]
+pub mod object_store;
+#[cfg(feature = "plugin-api-v4")]
+pub mod periph;
+#[cfg(feature = "plugin-api-v4")]
+pub mod plt;
+pub mod presets;
+#[cfg(feature = "plugin-api-v4")]
+pub mod probes;
+pub mod qemulog;
+pub mod qmp;
+#[cfg(feature = "plugin-api-v4")]
+pub mod races;
+#[cfg(feature = "plugin-api-v4")]
+pub mod random;
+pub mod redact;
+#[cfg(feature = "plugin-api-v4")]
+pub mod reference;
+pub mod retranslation;
+#[cfg(feature = "plugin-api-v4")]
+pub mod rop;
+pub mod sampler;
+#[cfg(feature = "plugin-api-v4")]
+pub mod sched;
+pub mod session;
+#[cfg(feature = "plugin-api-v4")]
+pub mod shadow;
+#[cfg(feature = "plugin-api-v4")]
+pub mod signatures;
+pub mod stats;
+#[cfg(feature = "plugin-api-v4")]
+pub mod strings;
+pub mod sync;
+pub mod taint;
+#[cfg(feature = "plugin-api-v4")]
+pub mod testing;
+pub mod throttle;
+pub mod tracefile;
+#[cfg(feature = "plugin-api-v4")]
+pub mod uart;
+pub mod utilization;
+#[cfg(feature = "plugin-api-v4")]
+pub mod watchdog;
+#[cfg(feature = "plugin-api-v4")]
+pub mod wx;
+#[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+pub mod yara;
 
 #[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
 pub struct InstructionEvent {
@@ -27,18 +242,19 @@ pub struct InstructionEvent {
     pub disas: String,
     pub symbol: Option<String>,
     pub data: Vec<u8>,
+    /// The instruction set the instruction ran in, when modes are tracked
+    #[builder(default)]
+    #[serde(default)]
+    pub mode: Option<IsaMode>,
 }
 
-impl TryFrom<&Instruction<'_>> for InstructionEvent {
-    type Error = Error;
-
-    fn try_from(value: &Instruction) -> Result<Self> {
+impl InstructionEvent {
+    fn try_from(value: &Instruction, decoder: &Decoder) -> Result<Self> {
         let data = value.data();
-        let decoder = InstDecoder::default();
-        let disas = decoder
-            .decode_slice(&data)
-            .map(|d| d.to_string())
-            .or_else(|_| value.disas())?;
+        let disas = match decoder.decode(&data) {
+            Some(disas) => disas,
+            None => value.disas()?,
+        };
 
         Ok(Self::builder()
             .vaddr(value.vaddr())
@@ -48,9 +264,23 @@ impl TryFrom<&Instruction<'_>> for InstructionEvent {
             .data(data)
             .build())
     }
+
+    /// Annotate the event with the mode it ran in. An instruction translated for another
+    /// mode is disassembled again, if the mode has a decoder.
+    #[cfg(feature = "plugin-api-v4")]
+    fn in_mode(mut self, arch: Option<Arch>, cpu: &Cpu, mode: IsaMode) -> Self {
+        if self.mode.is_some_and(|translated| translated != mode) {
+            if let Some(disas) = Decoder::for_mode(arch, cpu, mode).decode(&self.data) {
+                self.disas = disas;
+            }
+        }
+
+        self.mode = Some(mode);
+        self
+    }
 }
 
-#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+#[derive(TypedBuilder, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct MemoryEvent {
     pub vaddr: u64,
     pub haddr: Option<u64>,
@@ -61,10 +291,22 @@ pub struct MemoryEvent {
     pub sign_extended: bool,
     pub is_store: bool,
     pub big_endian: bool,
+    #[builder(default)]
+    #[serde(default)]
+    pub operand: Option<AddressBreakdown>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    #[serde(default)]
+    pub allocation: Option<Attribution>,
+    /// The value loaded or stored, of `size_bytes` bytes, if values are logged
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    #[serde(default)]
+    pub value: Option<u128>,
 }
 
 impl MemoryEvent {
-    fn try_from(value: &MemoryInfo, vaddr: u64) -> Result<Self> {
+    fn try_from(value: &MemoryInfo, vaddr: u64, operand: Option<AddressBreakdown>) -> Result<Self> {
         let haddr = value.hwaddr(vaddr);
         Ok(Self::builder()
             .vaddr(vaddr)
@@ -82,8 +324,43 @@ impl MemoryEvent {
             .sign_extended(value.sign_extended())
             .is_store(value.is_store())
             .big_endian(value.big_endian())
+            .operand(operand)
             .build())
     }
+
+    /// Returns the base register, index register, scale, and displacement which produced
+    /// the access, as decoded from the instruction when it was translated. Only available
+    /// for targets with a decoder.
+    pub fn effective_address_breakdown(&self) -> Option<&AddressBreakdown> {
+        self.operand.as_ref()
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Attribute the access to the live heap allocation it falls in, if heap tracking is
+    /// enabled
+    fn with_allocation(mut self, heap: Option<&Mutex<HeapTracker>>) -> Result<Self> {
+        if let Some(heap) = heap {
+            self.allocation = heap
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock heap: {e}"))?
+                .attribute(self.vaddr);
+        }
+
+        Ok(self)
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Record the value loaded or stored by the access, which QEMU gives at the access's size
+    fn with_value(mut self, info: &MemoryInfo) -> Self {
+        self.value = Some(match info.value() {
+            MemValue::U8(v) => v as u128,
+            MemValue::U16(v) => v as u128,
+            MemValue::U32(v) => v as u128,
+            MemValue::U64(v) => v as u128,
+            MemValue::U128(v) => v,
+        });
+        self
+    }
 }
 
 #[derive(TypedBuilder, Clone, Debug, PartialEq, Eq, Hash)]
@@ -102,6 +379,27 @@ pub struct SyscallEvent {
     pub buffers: HashMap<usize, Vec<u8>>,
 }
 
+#[cfg(feature = "plugin-api-v4")]
+/// The most bytes of a single console write captured, as the length is guest-controlled
+const MAX_CONSOLE_WRITE: usize = 64 * 1024;
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+pub struct ConsoleEvent {
+    pub fd: u64,
+    pub icount: u64,
+    pub vcpu_index: VCPUIndex,
+    pub data: Vec<u8>,
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+pub struct StartEvent {
+    pub target_name: Option<String>,
+    pub binary: Option<PathBuf>,
+    pub argv: Vec<String>,
+    pub envp: Vec<String>,
+    pub icount: u64,
+}
+
 #[cfg(not(feature = "plugin-api-v1"))]
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Registers(pub HashMap<String, Vec<u8>>);
@@ -114,202 +412,3760 @@ pub enum Event {
         registers: Registers,
     },
     Memory(MemoryEvent),
+    MemoryRange(MemoryRangeEvent),
     Syscall(SyscallEvent),
+    Console(ConsoleEvent),
+    Start(StartEvent),
+    Pcs(PcBatch),
+    #[cfg(feature = "plugin-api-v4")]
+    Random(RandomEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Fault(FaultEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Dump(DumpEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Schedule(ScheduleEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Race(RaceEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Periph(PeriphEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Uart(UartEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Hang(HangEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    State(StateEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Api(ApiCallEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Rop(RopEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Integrity(IntegrityEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Signature(SignatureEvent),
+    #[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+    Yara(YaraEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Probe(ProbeEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Crypto(CryptoEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    String(StringEvent),
+    Bookmark(BookmarkEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Hypercall(HypercallEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Mode(ModeEvent),
+    Utilization(UtilizationEvent),
+    Coverage(CoverageEvent),
+    Clock(ClockEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Divergence(DivergenceEvent),
+    Translation(TranslationEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Wx(WxEvent),
+    #[cfg(feature = "plugin-api-v4")]
+    Crash(CrashEvent),
+    Dropped(DroppedEvent),
 }
 
-#[derive(TypedBuilder, Clone, Debug)]
-struct Tracer {
-    #[builder(default)]
-    pub target_name: Option<String>,
-    pub syscalls: Arc<Mutex<HashMap<SyscallSource, SyscallEvent>>>,
-    #[cfg(not(feature = "plugin-api-v1"))]
-    pub registers: Arc<Mutex<Vec<RegisterDescriptor<'static>>>>,
-    #[builder(default)]
-    pub tx: Arc<Mutex<Option<UnixStream>>>,
-    #[builder(default)]
-    pub log_insns: bool,
-    #[builder(default)]
-    pub log_mem: bool,
-    #[builder(default)]
-    pub log_syscalls: bool,
-    #[cfg(not(feature = "plugin-api-v1"))]
-    #[builder(default)]
-    pub log_registers: bool,
+impl Event {
+    /// Returns the class the event is rate limited as, or `None` for events recording drops,
+    /// which are never dropped themselves
+    pub fn class(&self) -> Option<EventClass> {
+        match self {
+            Event::Instruction { .. } => Some(EventClass::Instruction),
+            Event::Memory(_) | Event::MemoryRange(_) => Some(EventClass::Memory),
+            Event::Syscall(_) => Some(EventClass::Syscall),
+            Event::Console(_) => Some(EventClass::Console),
+            Event::Start(_) => Some(EventClass::Start),
+            Event::Pcs(_) => Some(EventClass::Pcs),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Random(_) => Some(EventClass::Random),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Fault(_) => Some(EventClass::Fault),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Dump(_) => Some(EventClass::Dump),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Schedule(_) => Some(EventClass::Schedule),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Race(_) => Some(EventClass::Race),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Periph(_) => Some(EventClass::Periph),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Uart(_) => Some(EventClass::Console),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Hang(_) => Some(EventClass::Hang),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::State(_) => Some(EventClass::State),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Api(_) => Some(EventClass::Api),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Rop(_) => Some(EventClass::Rop),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Integrity(_) => Some(EventClass::Integrity),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Signature(_) => Some(EventClass::Signature),
+            #[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+            Event::Yara(_) => Some(EventClass::Yara),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Probe(_) => Some(EventClass::Probe),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Crypto(_) => Some(EventClass::Crypto),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::String(_) => Some(EventClass::String),
+            Event::Bookmark(_) => Some(EventClass::Bookmark),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Hypercall(_) => Some(EventClass::Hypercall),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Mode(_) => Some(EventClass::Mode),
+            Event::Utilization(_) => Some(EventClass::Utilization),
+            Event::Coverage(_) => Some(EventClass::Coverage),
+            Event::Clock(_) => Some(EventClass::Clock),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Divergence(_) => Some(EventClass::Divergence),
+            Event::Translation(_) => Some(EventClass::Translation),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Wx(_) => Some(EventClass::Wx),
+            #[cfg(feature = "plugin-api-v4")]
+            Event::Crash(_) => Some(EventClass::Crash),
+            Event::Dropped(_) => None,
+        }
+    }
 }
 
-impl Tracer {
-    pub fn new() -> Self {
-        #[cfg(feature = "plugin-api-v1")]
-        {
-            Self::builder()
-                .syscalls(Arc::new(Mutex::new(HashMap::new())))
-                .build()
-        }
-        #[cfg(not(feature = "plugin-api-v1"))]
-        {
-            Self::builder()
-                .syscalls(Arc::new(Mutex::new(HashMap::new())))
-                .registers(Arc::new(Mutex::new(Vec::new())))
-                .build()
+#[derive(Debug)]
+/// The destination events are written to
+pub enum Sink {
+    /// The socket the `tracer` binary listens on
+    Socket(UnixStream),
+    /// A trace file written directly by the plugin
+    File(TraceFile),
+    /// The connection to an `aggregator` server
+    Aggregator(TcpStream),
+    #[cfg(feature = "object-store")]
+    /// Chunks uploaded to object storage
+    Object(ObjectSink),
+}
+
+impl Sink {
+    /// Write a single record, which trace files frame so readers can skip it
+    fn write_record<T>(&mut self, record: &T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        match self {
+            Sink::File(file) => file.write_record(record),
+            sink => Ok(to_writer(sink, record)?),
         }
     }
 }
 
-impl HasCallbacks for Tracer {
-    #[cfg(not(feature = "plugin-api-v1"))]
-    fn on_vcpu_init(
-        &mut self,
-        _id: PluginId,
-        _vcpu_id: VCPUIndex,
-    ) -> std::prelude::v1::Result<(), anyhow::Error> {
-        *self
-            .registers
-            .lock()
-            .map_err(|e| anyhow!("Failed to lock registers: {}", e))? =
-            qemu_plugin_get_registers()?;
-        Ok(())
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Socket(stream) => stream.write(buf),
+            Sink::File(_) => Err(io::Error::other(
+                "Trace files are written a record at a time",
+            )),
+            Sink::Aggregator(stream) => stream.write(buf),
+            #[cfg(feature = "object-store")]
+            Sink::Object(object) => object.write(buf),
+        }
     }
 
-    fn on_translation_block_translate(
-        &mut self,
-        _id: PluginId,
-        tb: TranslationBlock,
-    ) -> Result<()> {
-        tb.instructions().try_for_each(|insn| {
-            let event = InstructionEvent::try_from(&insn)?;
-
-            #[cfg(feature = "plugin-api-v1")]
-            if self.log_insns {
-                let tx = self.tx.clone();
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Socket(stream) => stream.flush(),
+            Sink::File(file) => file.commit().map_err(io::Error::other),
+            Sink::Aggregator(stream) => stream.flush(),
+            #[cfg(feature = "object-store")]
+            Sink::Object(object) => object.flush(),
+        }
+    }
+}
 
-                insn.register_execute_callback(move |_| {
-                    tx.lock()
-                        .map_err(|e| anyhow!("Failed to lock tx: {}", e))
-                        .and_then(|tx| {
-                            to_writer(
-                                tx.as_ref().ok_or_else(|| anyhow!("No tx"))?,
-                                &Event::Instruction {
-                                    event: event.clone(),
-                                },
-                            )
-                            .map_err(|e| anyhow!(e))
-                        })
-                        .expect("Failed to send instruction event");
-                });
-            }
+#[derive(Debug, Default)]
+/// Where events are sent: either a single sink, or one trace file shard per vCPU
+pub struct Output {
+    pub sink: Mutex<Option<Sink>>,
+    pub shards: Option<ShardedTraceFile>,
+    pub throttle: Option<Throttle>,
+    /// Analysis modules passed every event written
+    pub analyses: Option<Analyses>,
+    /// Normalizes code addresses to link-time addresses, unless absolute pcs were asked for
+    pub relocator: Option<Arc<Relocator>>,
+    #[cfg(feature = "plugin-api-v4")]
+    /// The last events written on each vCPU, for crash bundles
+    pub window: Option<Arc<TraceWindow>>,
+}
 
-            #[cfg(not(feature = "plugin-api-v1"))]
-            if self.log_insns {
-                let tx = self.tx.clone();
-                let registers = self
-                    .registers
-                    .lock()
-                    .map_err(|e| anyhow!("Failed to lock registers: {}", e))?
-                    .clone();
+/// Write a diagnostic to QEMU's log, which goes to stderr or the file given with `-D`. A
+/// failure to write it is ignored, as there is nowhere left to report it.
+pub fn log<S>(message: S)
+where
+    S: AsRef<str>,
+{
+    qemu_plugin_outs(format!("{}\n", message.as_ref())).ok();
+}
 
-                insn.register_execute_callback(move |_| {
-                    tx.lock()
-                        .map_err(|e| anyhow!("Failed to lock tx: {}", e))
-                        .and_then(|tx| {
-                            to_writer(
-                                tx.as_ref().ok_or_else(|| anyhow!("No tx"))?,
-                                &Event::Instruction {
-                                    event: event.clone(),
-                                    registers: Registers(
-                                        registers
-                                            .iter()
-                                            .map(|r| {
-                                                let value = r.read().unwrap_or_else(|_| vec![]);
-                                                (r.name.clone(), value)
-                                            })
-                                            .collect(),
-                                    ),
-                                },
-                            )
-                            .map_err(|e| anyhow!(e))
-                        })
-                        .expect("Failed to send instruction event");
-                });
+fn send_event(tx: &Output, stats: &Stats, vcpu_index: VCPUIndex, event: &Event) -> Result<()> {
+    if let (Some(throttle), Some(class)) = (tx.throttle.as_ref(), event.class()) {
+        match throttle.admit(class, vcpu_index)? {
+            None => {
+                Stats::bump(&stats.events_dropped);
+                return Ok(());
             }
+            Some(0) => {}
+            Some(count) => write_event(
+                tx,
+                stats,
+                vcpu_index,
+                &Event::Dropped(DroppedEvent {
+                    class,
+                    vcpu_index,
+                    count,
+                    icount: stats.icount(),
+                }),
+            )?,
+        }
+    }
 
-            if self.log_mem {
-                let tx = self.tx.clone();
+    write_event(tx, stats, vcpu_index, event)
+}
 
-                insn.register_memory_access_callback(
-                    move |_, info, vaddr| {
-                        tx.lock()
-                            .map_err(|e| anyhow!("Failed to lock tx: {}", e))
-                            .and_then(|tx| {
-                                to_writer(
-                                    tx.as_ref().ok_or_else(|| anyhow!("No tx"))?,
-                                    &Event::Memory(MemoryEvent::try_from(&info, vaddr)?),
-                                )
-                                .map_err(|e| anyhow!(e))
-                            })
-                            .expect("Failed to send memory event");
-                    },
-                    MemRW::QEMU_PLUGIN_MEM_RW,
-                );
-            }
+fn write_event(tx: &Output, stats: &Stats, vcpu_index: VCPUIndex, event: &Event) -> Result<()> {
+    #[cfg(feature = "self-profile")]
+    static SINK: qemu_plugin::profile::Section = qemu_plugin::profile::Section::new("sink");
 
-            Ok::<(), Error>(())
-        })?;
+    let relocated = match tx.relocator.as_ref() {
+        Some(relocator) => relocator.relocate(event)?,
+        None => None,
+    };
+    let event = relocated.as_ref().unwrap_or(event);
 
-        Ok(())
+    #[cfg(feature = "plugin-api-v4")]
+    if let Some(window) = tx.window.as_ref() {
+        window.push(vcpu_index, event)?;
     }
 
-    fn on_syscall(
-        &mut self,
-        id: PluginId,
-        vcpu_index: VCPUIndex,
-        num: i64,
-        a1: u64,
-        a2: u64,
-        a3: u64,
-        a4: u64,
-        a5: u64,
-        a6: u64,
-        a7: u64,
-        a8: u64,
-    ) -> Result<()> {
-        if !self.log_syscalls {
-            return Ok(());
+    let send = || {
+        if let Some(analyses) = tx.analyses.as_ref() {
+            analyses.dispatch(vcpu_index, event)?;
         }
 
-        #[cfg(any(
-            feature = "plugin-api-v1",
-            feature = "plugin-api-v2",
-            feature = "plugin-api-v3"
-        ))]
-        let event = SyscallEvent::builder()
-            .num(num)
-            .return_value(-1)
-            .args([a1, a2, a3, a4, a5, a6, a7, a8])
-            .build();
+        if let Some(shards) = tx.shards.as_ref() {
+            return shards.write(vcpu_index, stats.icount(), event);
+        }
 
-        #[cfg(feature = "plugin-api-v4")]
-        let event = {
-            let buffers = if let Some(write_sysno) = match self.target_name.as_deref() {
-                Some("i386") => Some(4),
-                Some("x86_64") => Some(1),
-                Some("arm") => Some(4),
-                Some("aarch64") => Some(64),
-                _ => None,
-            } {
-                if num == write_sysno {
-                    let addr = a2;
-                    let len = a3 as usize;
-                    let buffer = qemu_plugin_read_memory_vaddr(addr, len)?;
-                    [(1, buffer)].into_iter().collect::<HashMap<_, _>>()
-                } else {
-                    Default::default()
-                }
-            } else {
-                Default::default()
-            };
+        let mut sink = tx
+            .sink
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock sink: {e}"))?;
 
-            SyscallEvent::builder()
-                .num(num)
+        match sink.as_mut() {
+            Some(sink) => sink.write_record(event),
+            // Only analysis modules receive events
+            None if tx.analyses.is_some() => Ok(()),
+            None => Err(anyhow!("No sink")),
+        }
+    };
+
+    stats.sink_waiting.fetch_add(1, Ordering::Relaxed);
+
+    #[cfg(feature = "self-profile")]
+    let sent = SINK.measure(send);
+    #[cfg(not(feature = "self-profile"))]
+    let sent = send();
+
+    stats.sink_waiting.fetch_sub(1, Ordering::Relaxed);
+    sent?;
+
+    Stats::bump(&stats.events_sent);
+
+    Ok(())
+}
+
+#[cfg(feature = "plugin-api-v4")]
+/// Take a dump on the current vCPU and send an event recording it, unless the dump limit has
+/// been reached
+fn send_dump(
+    tx: &Output,
+    stats: &Stats,
+    dumper: &Mutex<Dumper>,
+    arch: Arch,
+    registers: &[RegisterDescriptor<'static>],
+    vcpu_index: VCPUIndex,
+    trigger: DumpTrigger,
+) -> Result<()> {
+    let Some(path) = dumper
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock dumper: {e}"))?
+        .dump(arch, trigger, registers)?
+    else {
+        return Ok(());
+    };
+
+    send_event(
+        tx,
+        stats,
+        vcpu_index,
+        &Event::Dump(DumpEvent {
+            trigger,
+            path,
+            icount: stats.icount(),
+        }),
+    )
+}
+
+/// The modules coverage offsets are relative to: the program's code, in user mode
+fn program_modules() -> Result<Vec<Module>> {
+    let mut modules = Vec::new();
+
+    if let (Some(start), Some(end)) = (qemu_plugin_start_code(), qemu_plugin_end_code()) {
+        modules.push(Module {
+            name: qemu_plugin_path_to_binary()?
+                .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+                .unwrap_or_else(|| "main".to_string()),
+            start,
+            end,
+        });
+    }
+
+    Ok(modules)
+}
+
+#[derive(TypedBuilder, Clone, Debug)]
+struct Tracer {
+    /// Plugin arguments set by a preset, which those given to QEMU override
+    #[builder(default)]
+    pub preset: PresetArgs,
+    /// Whether the plugin was installed to check its environment, and uninstalls itself
+    /// instead of tracing
+    #[builder(default)]
+    pub checking: bool,
+    #[builder(default)]
+    pub uninstalling: bool,
+    #[builder(default)]
+    pub target_name: Option<String>,
+    #[builder(default)]
+    pub arch: Option<Arch>,
+    #[builder(default)]
+    pub cpu: Cpu,
+    #[builder(default)]
+    pub decoder: Decoder,
+    pub syscalls: Arc<Mutex<HashMap<SyscallSource, SyscallEvent>>>,
+    #[cfg(not(feature = "plugin-api-v1"))]
+    pub registers: Arc<Mutex<Vec<RegisterDescriptor<'static>>>>,
+    #[builder(default)]
+    pub tx: Arc<Output>,
+    #[builder(default)]
+    pub log_insns: bool,
+    #[builder(default)]
+    pub log_mem: bool,
+    /// Whether memory events carry the value loaded or stored, which costs a call into QEMU
+    /// and a larger event for every access
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub log_mem_values: bool,
+    #[builder(default)]
+    pub log_syscalls: bool,
+    #[cfg(not(feature = "plugin-api-v1"))]
+    #[builder(default)]
+    pub log_registers: bool,
+    #[builder(default)]
+    pub log_console: bool,
+    #[builder(default)]
+    pub log_start: bool,
+    #[builder(default)]
+    pub log_pcs: bool,
+    #[builder(default)]
+    pub pcs: Arc<PcBuffers>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub started: Arc<Latch>,
+    #[builder(default)]
+    pub stats: Arc<Stats>,
+    #[builder(default)]
+    pub count_instructions: bool,
+    #[builder(default)]
+    pub coverage: Option<Arc<Mutex<CoverageTracker>>>,
+    #[builder(default)]
+    pub coverage_path: Option<PathBuf>,
+    #[builder(default)]
+    pub drcov_path: Option<PathBuf>,
+    /// Whether coverage is merged into the coverage file rather than replacing it
+    #[builder(default)]
+    pub coverage_merge: bool,
+    #[builder(default)]
+    pub block_identity: BlockIdentity,
+    #[builder(default)]
+    pub run_manifest: Option<Arc<Mutex<RunManifest>>>,
+    /// Where the run manifest is written, or `None` when it is sent through the sink
+    #[builder(default)]
+    pub run_manifest_path: Option<PathBuf>,
+    #[builder(default)]
+    pub fuzz: Option<Arc<Mutex<FuzzTarget>>>,
+    #[builder(default)]
+    pub honggfuzz: Option<Arc<Mutex<HonggfuzzFeedback>>>,
+    #[builder(default)]
+    pub memory_map: Option<Arc<Mutex<MemoryMap>>>,
+    #[builder(default)]
+    pub memory_map_path: Option<PathBuf>,
+    #[builder(default)]
+    pub translations: Option<Arc<Mutex<TranslationTracker>>>,
+    #[builder(default)]
+    pub translation_log: Option<PathBuf>,
+    #[builder(default)]
+    pub decisions: Option<Arc<DecisionLogger>>,
+    #[builder(default)]
+    pub section_filter: Option<Arc<Mutex<SectionFilter>>>,
+    #[builder(default)]
+    pub dedup: Option<Arc<Dedup>>,
+    #[builder(default)]
+    pub coalescer: Option<Arc<Mutex<WriteCoalescer>>>,
+    #[builder(default)]
+    pub bookmarks: Option<Arc<Bookmarks>>,
+    #[builder(default)]
+    pub utilization_report: Option<PathBuf>,
+    #[builder(default)]
+    pub retranslation: Option<Arc<Mutex<RetranslationTracker>>>,
+    #[builder(default)]
+    pub retranslation_report: Option<PathBuf>,
+    #[builder(default)]
+    pub symprof: Option<Arc<Mutex<SymbolProfiler>>>,
+    #[builder(default)]
+    pub symprof_report: Option<PathBuf>,
+    #[builder(default)]
+    pub srcline: Option<Arc<Mutex<SourceProfiler>>>,
+    #[builder(default)]
+    pub source_report: Option<PathBuf>,
+    #[builder(default)]
+    pub extensions: Option<Arc<Mutex<ExtensionProfiler>>>,
+    #[builder(default)]
+    pub extension_report: Option<PathBuf>,
+    #[builder(default)]
+    pub demangler: Option<Arc<Demangler>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub symbolizer: Option<Arc<Mutex<Symbolizer>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub files: Option<Arc<Mutex<FileTracker>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub file_report: Option<PathBuf>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub net: Option<Arc<Mutex<NetTracker>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub random: Option<Arc<Mutex<RandomTracker>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub faults: Option<Arc<Mutex<FaultInjector>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub periph: Option<Arc<Mutex<Peripherals>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub input: Option<Arc<Mutex<InputInjector>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub uart: Option<Arc<Mutex<UartDecoder>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub watchdog: Option<Arc<Watchdog>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub watchdog_exit: bool,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub state: Option<Arc<Mutex<StateTracker>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub api: Option<Arc<Mutex<ApiTracer>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub rop: Option<Arc<Mutex<RopDetector>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub shadow: Option<Arc<Mutex<ShadowStack>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub signatures: Option<Arc<Mutex<SignatureScanner>>>,
+    #[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+    #[builder(default)]
+    pub yara: Option<Arc<YaraScanner>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub probes: Vec<EntropyProbe>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub crypto: Option<Arc<Mutex<CryptoDetector>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub strings: Option<Arc<Mutex<StringTracker>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub guest_hypercalls: bool,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub dumper: Option<Arc<Mutex<Dumper>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub heap: Option<Arc<Mutex<HeapTracker>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub scheduler: Option<Arc<Mutex<Scheduler>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub isa: Option<Arc<Mutex<IsaTracker>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub locks: Option<Arc<Mutex<LockTracker>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub lock_report: Option<PathBuf>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub races: Option<Arc<Mutex<RaceDetector>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub exclusives: Option<Arc<Mutex<ExclusiveTracker>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub exclusive_report: Option<PathBuf>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub expectations: Option<Arc<Mutex<Expectations>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub expectation_report: Option<PathBuf>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub reference: Option<Arc<Mutex<ReferenceChecker>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub wx: Option<Arc<Mutex<WxTracker>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub wx_report: Option<PathBuf>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub crash: Option<Arc<Mutex<CrashRecorder>>>,
+    #[cfg(feature = "plugin-api-v4")]
+    #[builder(default)]
+    pub plt: Option<Arc<Mutex<PltResolver>>>,
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        #[cfg(feature = "plugin-api-v1")]
+        {
+            Self::builder()
+                .syscalls(Arc::new(Mutex::new(HashMap::new())))
+                .build()
+        }
+        #[cfg(not(feature = "plugin-api-v1"))]
+        {
+            Self::builder()
+                .syscalls(Arc::new(Mutex::new(HashMap::new())))
+                .registers(Arc::new(Mutex::new(Vec::new())))
+                .build()
+        }
+    }
+
+    /// Uninstall the plugin once its check has been reported, leaving the guest to run
+    /// untraced. Only the first vCPU to start uninstalls it, and the others do nothing.
+    fn uninstall_after_check(&mut self, id: PluginId) -> Result<()> {
+        if !self.uninstalling {
+            self.uninstalling = true;
+            qemu_plugin_uninstall(id, |_| {})?;
+        }
+
+        Ok(())
+    }
+
+    /// The name to record for the instruction at `vaddr` given the symbol QEMU reports: found
+    /// in the guest's libraries and debug files, or after the PLT stub it is in or the
+    /// function bound to one, if QEMU has none, and demangled under the policy given
+    fn symbol(&self, vaddr: u64, symbol: Option<String>) -> Result<Option<String>> {
+        let symbol = symbol.filter(|s| !s.is_empty());
+
+        #[cfg(feature = "plugin-api-v4")]
+        let symbol = match (symbol, self.symbolizer.as_ref()) {
+            (None, Some(symbolizer)) => symbolizer
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock symbolizer: {e}"))?
+                .symbol(vaddr),
+            (symbol, _) => symbol,
+        };
+        #[cfg(feature = "plugin-api-v4")]
+        let symbol = match (symbol, self.plt.as_ref()) {
+            (None, Some(plt)) => plt
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock PLT resolver: {e}"))?
+                .symbol(vaddr),
+            (symbol, _) => symbol,
+        };
+        #[cfg(not(feature = "plugin-api-v4"))]
+        let _ = vaddr;
+
+        Ok(match (self.demangler.as_ref(), symbol) {
+            (Some(demangler), Some(symbol)) => Some(demangler.demangle(symbol)),
+            (_, symbol) => symbol,
+        })
+    }
+
+    /// Register the callback which adds each execution of the block to the symbol profile
+    fn profile_symbols(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(symprof) = self.symprof.as_ref() else {
+            return Ok(());
+        };
+
+        let mut profiler = symprof
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock symbol profiler: {e}"))?;
+        let classify = self.arch.filter(|_| profiler.is_weighted());
+        let packets = self.arch.filter(Arch::has_packets);
+        let instructions = tb.instructions().collect::<Vec<_>>();
+        let runs = profiler.on_translate(
+            instructions
+                .iter()
+                .enumerate()
+                .map(|(i, insn)| {
+                    let class = match classify {
+                        Some(arch) => {
+                            let data = insn.data();
+                            let disas = match self.decoder.decode(&data) {
+                                Some(disas) => disas,
+                                None => insn.disas()?,
+                            };
+
+                            Some(InsnClass::classify(
+                                arch,
+                                &self.decoder,
+                                &data,
+                                &disas,
+                                i + 1 == instructions.len(),
+                            ))
+                        }
+                        None => None,
+                    };
+
+                    let count = packets.map_or(1, |arch| arch.instruction_count(&insn.data()));
+
+                    Ok((
+                        insn.vaddr(),
+                        self.symbol(insn.vaddr(), insn.symbol()?)?,
+                        class,
+                        count as u64,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        );
+        drop(profiler);
+
+        let symprof = symprof.clone();
+
+        self.registered(
+            "symprof_report",
+            None,
+            Callback::Execute,
+            format_args!("symbol profiling is enabled"),
+        )?;
+        tb.register_execute_callback(move |_| {
+            symprof
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock symbol profiler: {e}"))
+                .map(|mut symprof| symprof.on_block(&runs))
+                .expect("Failed to profile symbols");
+        });
+
+        Ok(())
+    }
+
+    /// Register the callback which adds each execution of the block to the source line
+    /// profile
+    fn profile_source_lines(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(srcline) = self.srcline.as_ref() else {
+            return Ok(());
+        };
+
+        let instructions = tb
+            .instructions()
+            .map(|insn| {
+                let disas = match self.decoder.decode(&insn.data()) {
+                    Some(disas) => disas,
+                    None => insn.disas()?,
+                };
+
+                Ok((insn.vaddr(), disas))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let block = srcline
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock source line profiler: {e}"))?
+            .on_translate(instructions);
+        let srcline = srcline.clone();
+
+        self.registered(
+            "source_report",
+            None,
+            Callback::Execute,
+            format_args!("source line profiling is enabled"),
+        )?;
+        tb.register_execute_callback(move |_| {
+            srcline
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock source line profiler: {e}"))
+                .map(|mut srcline| srcline.on_block(block))
+                .expect("Failed to profile source lines");
+        });
+
+        Ok(())
+    }
+
+    /// Register the callback which adds each execution of the block to the extension report
+    fn profile_extensions(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(extensions) = self.extensions.as_ref() else {
+            return Ok(());
+        };
+
+        let packets = self.arch.filter(Arch::has_packets);
+        let instructions = tb
+            .instructions()
+            .map(|insn| {
+                let data = insn.data();
+                let disas = match self.decoder.decode(&data) {
+                    Some(disas) => disas,
+                    None => insn.disas()?,
+                };
+                let count = packets.map_or(1, |arch| arch.instruction_count(&data));
+
+                Ok((disas, count as u64))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let usage = extensions
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock extension profiler: {e}"))?
+            .on_translate(tb.vaddr(), instructions);
+        let extensions = extensions.clone();
+
+        self.registered(
+            "extension_report",
+            None,
+            Callback::Execute,
+            format_args!("extension profiling is enabled"),
+        )?;
+        tb.register_execute_callback(move |_| {
+            extensions
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock extension profiler: {e}"))
+                .map(|mut extensions| extensions.on_block(&usage))
+                .expect("Failed to profile extensions");
+        });
+
+        Ok(())
+    }
+
+    /// Register the callback which records the translations of the pages the block's code
+    /// was translated from
+    fn track_translations(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(translations) = self.translations.clone() else {
+            return Ok(());
+        };
+
+        let mut pages = Vec::<(u64, u64)>::new();
+
+        // A block may cross into a second page, which may not follow the first physically
+        for insn in tb.instructions() {
+            let page = insn.vaddr() & !(mmu::PAGE_SIZE - 1);
+
+            if pages.last().is_none_or(|(vaddr, _)| *vaddr != page) {
+                pages.push((page, insn.haddr()));
+            }
+        }
+
+        let tx = self.tx.clone();
+        let stats = self.stats.clone();
+
+        self.registered(
+            "translation_log",
+            None,
+            Callback::Execute,
+            format_args!("the block's code spans {} pages", pages.len()),
+        )?;
+        tb.register_execute_callback(move |vcpu_index| {
+            for (vaddr, paddr) in &pages {
+                translations
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock translations: {e}"))
+                    .map(|mut translations| {
+                        translations.observe(
+                            vcpu_index,
+                            *vaddr,
+                            *paddr,
+                            TranslationSource::Code,
+                            false,
+                            stats.icount(),
+                        )
+                    })
+                    .and_then(|event| match event {
+                        Some(event) => {
+                            send_event(&tx, &stats, vcpu_index, &Event::Translation(event))
+                        }
+                        None => Ok(()),
+                    })
+                    .expect("Failed to send translation event");
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Register the callback adding the block's instructions to the instruction count, if an
+    /// output needs it
+    fn count_instructions(&self, tb: &TranslationBlock) -> Result<()> {
+        if !self.count_instructions {
+            return Ok(());
+        }
+
+        let stats = self.stats.clone();
+        let size = match self.arch.filter(Arch::has_packets) {
+            Some(arch) => tb
+                .instructions()
+                .map(|insn| arch.instruction_count(&insn.data()) as u64)
+                .sum(),
+            None => tb.size() as u64,
+        };
+
+        self.registered(
+            "icount",
+            None,
+            Callback::Execute,
+            format_args!("an output needs the instruction count"),
+        )?;
+        tb.register_execute_callback(move |vcpu_index| {
+            Stats::bump(&stats.executed_blocks);
+            stats
+                .add_instructions(vcpu_index, size)
+                .expect("Failed to count instructions");
+        });
+
+        Ok(())
+    }
+
+    /// Write out reports and flush outputs once execution is finished
+    fn on_exit(&self) -> Result<()> {
+        if let Some(coalescer) = self.coalescer.as_ref() {
+            for (vcpu_index, event) in coalescer
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock coalescer: {e}"))?
+                .drain()
+            {
+                self.send(vcpu_index, &event)?;
+            }
+        }
+
+        for batch in self.pcs.drain()? {
+            self.send(batch.vcpu_index, &Event::Pcs(batch))?;
+        }
+
+        if let Some(coverage) = self.coverage.as_ref() {
+            let coverage = coverage
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock coverage: {e}"))?;
+
+            if let Some(coverage_path) = self.coverage_path.as_ref() {
+                if self.coverage_merge {
+                    coverage.coverage().merge_into(coverage_path)?;
+                } else {
+                    coverage.coverage().write(coverage_path)?;
+                }
+            }
+
+            if let Some(drcov_path) = self.drcov_path.as_ref() {
+                coverage.write_drcov(drcov_path)?;
+            }
+
+            if let Some(Sink::Aggregator(stream)) = self
+                .tx
+                .sink
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock sink: {e}"))?
+                .as_mut()
+            {
+                to_writer(stream, &Report::Coverage(coverage.coverage().clone()))?;
+            }
+        }
+
+        if let Some(utilization_report) = self.utilization_report.as_ref() {
+            UtilizationReport::new(&self.stats)?.write(utilization_report)?;
+        }
+
+        if let (Some(retranslation), Some(retranslation_report)) = (
+            self.retranslation.as_ref(),
+            self.retranslation_report.as_ref(),
+        ) {
+            retranslation
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock retranslation tracker: {e}"))?
+                .write_report(retranslation_report)?;
+        }
+
+        if let (Some(symprof), Some(symprof_report)) =
+            (self.symprof.as_ref(), self.symprof_report.as_ref())
+        {
+            symprof
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock symbol profiler: {e}"))?
+                .write_report(symprof_report)?;
+        }
+
+        if let (Some(srcline), Some(source_report)) =
+            (self.srcline.as_ref(), self.source_report.as_ref())
+        {
+            srcline
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock source line profiler: {e}"))?
+                .write_report(source_report)?;
+        }
+
+        if let (Some(extensions), Some(extension_report)) =
+            (self.extensions.as_ref(), self.extension_report.as_ref())
+        {
+            extensions
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock extension profiler: {e}"))?
+                .write_report(extension_report)?;
+        }
+
+        if let (Some(memory_map), Some(memory_map_path)) =
+            (self.memory_map.as_ref(), self.memory_map_path.as_ref())
+        {
+            memory_map
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock memory map: {e}"))?
+                .write(memory_map_path)?;
+        }
+
+        if let (Some(translations), Some(translation_log)) =
+            (self.translations.as_ref(), self.translation_log.as_ref())
+        {
+            translations
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock translations: {e}"))?
+                .log()
+                .write(translation_log)?;
+        }
+
+        if let Some(decisions) = self.decisions.as_ref() {
+            decisions.flush()?;
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        {
+            if let (Some(files), Some(file_report)) =
+                (self.files.as_ref(), self.file_report.as_ref())
+            {
+                let files = files
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock files: {e}"))?;
+
+                files.write_report(file_report)?;
+
+                let limits = files.limits();
+
+                for budget in [&limits.files, &limits.ranges] {
+                    if budget.exceeded() {
+                        log(format!("Tracer budget exceeded: {budget}"));
+                    }
+                }
+            }
+
+            if let Some(net) = self.net.as_ref() {
+                net.lock()
+                    .map_err(|e| anyhow!("Failed to lock net: {e}"))?
+                    .flush()?;
+            }
+
+            if let (Some(locks), Some(lock_report)) =
+                (self.locks.as_ref(), self.lock_report.as_ref())
+            {
+                locks
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock locks: {e}"))?
+                    .write_report(lock_report)?;
+            }
+
+            if let (Some(exclusives), Some(exclusive_report)) =
+                (self.exclusives.as_ref(), self.exclusive_report.as_ref())
+            {
+                exclusives
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock exclusives: {e}"))?
+                    .write_report(exclusive_report)?;
+            }
+
+            if let Some(expectations) = self.expectations.as_ref() {
+                let report = expectations
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock expectations: {e}"))?
+                    .report();
+
+                report.print_failures();
+
+                if let Some(expectation_report) = self.expectation_report.as_ref() {
+                    report.write(expectation_report)?;
+                }
+            }
+
+            if let Some(reference) = self.reference.as_ref() {
+                let divergences = reference
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock reference checker: {e}"))?
+                    .finish();
+
+                for divergence in divergences {
+                    self.send(divergence.vcpu_index, &Event::Divergence(divergence))?;
+                }
+            }
+
+            if let (Some(wx), Some(wx_report)) = (self.wx.as_ref(), self.wx_report.as_ref()) {
+                wx.lock()
+                    .map_err(|e| anyhow!("Failed to lock wx: {e}"))?
+                    .report()
+                    .write(wx_report)?;
+            }
+
+            if let Some(scheduler) = self.scheduler.as_ref() {
+                let slices = scheduler
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock scheduler: {e}"))?
+                    .finish(self.stats.icount());
+
+                for slice in slices {
+                    self.send(slice.vcpu_index, &Event::Schedule(slice))?;
+                }
+            }
+
+            if let Some(uart) = self.uart.as_ref() {
+                let lines = uart
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock uart: {e}"))?
+                    .finish(self.stats.icount());
+
+                for line in lines {
+                    self.send(line.vcpu_index, &Event::Uart(line))?;
+                }
+            }
+        }
+
+        if let Some(throttle) = self.tx.throttle.as_ref() {
+            for (class, vcpu_index, count) in throttle.take_pending()? {
+                write_event(
+                    &self.tx,
+                    &self.stats,
+                    vcpu_index,
+                    &Event::Dropped(DroppedEvent {
+                        class,
+                        vcpu_index,
+                        count,
+                        icount: self.stats.icount(),
+                    }),
+                )?;
+            }
+
+            for (class, count) in throttle.totals()? {
+                log(format!("Tracer throttle dropped {count} {class} events"));
+            }
+        }
+
+        if let Some(analyses) = self.tx.analyses.as_ref() {
+            analyses.finish()?;
+        }
+
+        if let Some(run_manifest) = self.run_manifest.as_ref() {
+            let mut run_manifest = run_manifest
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock run manifest: {e}"))?;
+
+            if let Some(relocator) = self.tx.relocator.as_ref() {
+                run_manifest.modules = relocator.modules()?;
+            }
+
+            run_manifest.finish(self.stats.snapshot()?);
+            self.write_run_manifest(&run_manifest)?;
+        }
+
+        match self
+            .tx
+            .sink
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock sink: {e}"))?
+            .as_mut()
+        {
+            Some(Sink::File(file)) => file.finish()?,
+            #[cfg(feature = "object-store")]
+            Some(Sink::Object(object)) => object.finish()?,
+            _ => {}
+        }
+
+        if let Some(shards) = self.tx.shards.as_ref() {
+            shards.finish()?;
+        }
+
+        Ok(())
+    }
+
+    fn send(&self, vcpu_index: VCPUIndex, event: &Event) -> Result<()> {
+        send_event(&self.tx, &self.stats, vcpu_index, event)
+    }
+
+    /// Write the run manifest to its path, or send it through the sink
+    fn write_run_manifest(&self, run_manifest: &RunManifest) -> Result<()> {
+        if let Some(run_manifest_path) = self.run_manifest_path.as_ref() {
+            return run_manifest.write(run_manifest_path);
+        }
+
+        match self
+            .tx
+            .sink
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock sink: {e}"))?
+            .as_mut()
+        {
+            Some(Sink::Aggregator(stream)) => {
+                to_writer(stream, &Report::Run(Box::new(run_manifest.clone())))?
+            }
+            #[cfg(feature = "object-store")]
+            Some(Sink::Object(object)) => object.put(RUN_MANIFEST, run_manifest.to_json()?)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Handle a hypercall from the guest. Bookmarks are sent as bookmark events, and coverage
+    /// hints are added to the coverage set, if coverage is collected, before being sent.
+    fn on_hypercall(&self, vcpu_index: VCPUIndex, hypercall: Hypercall) -> Result<()> {
+        let icount = self.stats.icount();
+
+        let event = match hypercall {
+            Hypercall::Bookmark { name, note } => Event::Bookmark(
+                BookmarkEvent::builder()
+                    .vcpu_index(vcpu_index)
+                    .name(name)
+                    .note(note)
+                    .source(BookmarkSource::Hypercall)
+                    .icount(icount)
+                    .build(),
+            ),
+            hypercall => {
+                if let (Hypercall::Coverage(id), Some(coverage)) =
+                    (&hypercall, self.coverage.as_ref())
+                {
+                    coverage
+                        .lock()
+                        .map_err(|e| anyhow!("Failed to lock coverage: {e}"))?
+                        .on_hint(*id);
+                }
+
+                Event::Hypercall(
+                    HypercallEvent::builder()
+                        .vcpu_index(vcpu_index)
+                        .hypercall(hypercall)
+                        .icount(icount)
+                        .build(),
+                )
+            }
+        };
+
+        self.send(vcpu_index, &event)
+    }
+
+    /// Returns the counter admitting events of `class` at `pc`, or `None` if they have
+    /// already been recorded as many times as allowed
+    fn occurrences(&self, pc: u64, class: EventClass) -> Result<Option<Counter>> {
+        match self.dedup.as_ref() {
+            Some(dedup) => dedup.counter(pc, class),
+            None => Ok(Some(Counter::default())),
+        }
+    }
+
+    /// Record in the decision log that `feature` registered `callback` on the block being
+    /// translated, or on its instruction at `pc`
+    fn registered(
+        &self,
+        feature: &str,
+        pc: Option<u64>,
+        callback: Callback,
+        reason: fmt::Arguments,
+    ) -> Result<()> {
+        self.decide(feature, pc, Some(callback), reason)
+    }
+
+    /// Record in the decision log that `feature` passed over the block being translated, or
+    /// its instruction at `pc`
+    fn skipped(&self, feature: &str, pc: Option<u64>, reason: fmt::Arguments) -> Result<()> {
+        self.decide(feature, pc, None, reason)
+    }
+
+    fn decide(
+        &self,
+        feature: &str,
+        pc: Option<u64>,
+        callback: Option<Callback>,
+        reason: fmt::Arguments,
+    ) -> Result<()> {
+        let Some(decisions) = self.decisions.as_ref() else {
+            return Ok(());
+        };
+
+        decisions.record(Decision {
+            feature: feature.to_string(),
+            pc,
+            callback,
+            reason: reason.to_string(),
+        })
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register a callback on the first block of each PLT stub which reads its GOT slot until
+    /// the function it imports is bound, so that the function's blocks are named after it
+    fn resolve_plt(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(plt) = self.plt.clone() else {
+            return Ok(());
+        };
+
+        let vaddr = tb.vaddr();
+        let Some(import) = plt
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock PLT resolver: {e}"))?
+            .import(vaddr)
+            .map(str::to_string)
+        else {
+            return Ok(());
+        };
+
+        self.registered(
+            "resolve_plt",
+            None,
+            Callback::Execute,
+            format_args!("the block is the PLT stub of {import}"),
+        )?;
+        tb.register_execute_callback(move |_| {
+            plt.lock()
+                .map_err(|e| anyhow!("Failed to lock PLT resolver: {e}"))
+                .map(|mut plt| {
+                    plt.on_call(vaddr);
+                })
+                .expect("Failed to resolve PLT stub");
+        });
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register a callback on the program's entry block which reads `argv` and `envp` off the
+    /// initial stack and sends them as the start event
+    fn capture_start(&self, tb: &TranslationBlock) -> Result<()> {
+        if !self.log_start || qemu_plugin_entry_code() != Some(tb.vaddr()) {
+            return Ok(());
+        }
+
+        if self.started.is_tripped() {
+            return self.skipped(
+                "log_start",
+                None,
+                format_args!("the start event was already sent"),
+            );
+        }
+
+        let Some(arch) = self.arch else {
+            return Ok(());
+        };
+
+        let Some(sp) = self
+            .registers
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock registers: {e}"))?
+            .iter()
+            .find(|r| arch.stack_pointer_names().contains(&r.name.as_str()))
+            .cloned()
+        else {
+            return Err(anyhow!("No stack pointer register found"));
+        };
+
+        let tx = self.tx.clone();
+        let stats = self.stats.clone();
+        let started = self.started.clone();
+        let target_name = self.target_name.clone();
+
+        self.registered(
+            "log_start",
+            None,
+            Callback::ExecuteRegisters,
+            format_args!("the block is the program's entry point"),
+        )?;
+        tb.register_execute_callback_flags(
+            move |vcpu_index| {
+                if !started.trip() {
+                    return;
+                }
+
+                sp.read()
+                    .map_err(Error::from)
+                    .and_then(|value| {
+                        let (argv, envp) = read_process_args(arch, arch.decode(&value))?;
+
+                        send_event(
+                            &tx,
+                            &stats,
+                            vcpu_index,
+                            &Event::Start(
+                                StartEvent::builder()
+                                    .target_name(target_name.clone())
+                                    .binary(qemu_plugin_path_to_binary()?)
+                                    .argv(argv)
+                                    .envp(envp)
+                                    .icount(stats.icount())
+                                    .build(),
+                            ),
+                        )
+                    })
+                    .expect("Failed to send start event");
+            },
+            CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Send the data written by a guest `write` to stdout or stderr as a console event
+    fn capture_console(
+        &self,
+        vcpu_index: VCPUIndex,
+        num: i64,
+        a1: u64,
+        a2: u64,
+        a3: u64,
+    ) -> Result<()> {
+        if !self.log_console
+            || !matches!(a1, 1 | 2)
+            || self.arch.and_then(|arch| arch.syscall(num)) != Some(Syscall::Write)
+        {
+            return Ok(());
+        }
+
+        // The write fails in the guest if its buffer is unreadable, so there is nothing to log
+        let Ok(data) = qemu_plugin_read_memory_vaddr(a2, (a3 as usize).min(MAX_CONSOLE_WRITE))
+        else {
+            return Ok(());
+        };
+
+        self.send(
+            vcpu_index,
+            &Event::Console(
+                ConsoleEvent::builder()
+                    .fd(a1)
+                    .icount(self.stats.icount())
+                    .vcpu_index(vcpu_index)
+                    .data(data)
+                    .build(),
+            ),
+        )
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register the callbacks which maintain the heap tracker's shadow call stacks: one on
+    /// the block, which may be a function entry, and one on each call and return
+    fn track_heap(&self, tb: &TranslationBlock) -> Result<()> {
+        let (Some(heap), Some(arch)) = (self.heap.as_ref(), self.arch) else {
+            return Ok(());
+        };
+
+        let registers = self
+            .registers
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock registers: {e}"))?
+            .clone();
+        let vaddr = tb.vaddr();
+        let symbol = match tb.instructions().next() {
+            Some(insn) => insn.symbol()?,
+            None => None,
+        };
+        let function = heap
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock heap: {e}"))?
+            .function(vaddr, symbol.as_deref());
+
+        {
+            let heap = heap.clone();
+            let registers = registers.clone();
+            let stats = self.stats.clone();
+
+            match function {
+                Some(function) => self.registered(
+                    "track_heap",
+                    None,
+                    Callback::ExecuteRegisters,
+                    format_args!("the block enters {function:?}"),
+                )?,
+                None => self.registered(
+                    "track_heap",
+                    None,
+                    Callback::ExecuteRegisters,
+                    format_args!("each block is checked for calls returning to it"),
+                )?,
+            }
+            tb.register_execute_callback_flags(
+                move |vcpu_index| {
+                    heap.lock()
+                        .map_err(|e| anyhow!("Failed to lock heap: {e}"))
+                        .and_then(|mut heap| {
+                            heap.on_block(vcpu_index, vaddr, function, &registers, stats.icount())
+                        })
+                        .expect("Failed to track heap");
+                },
+                CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+            );
+        }
+
+        for insn in tb.instructions() {
+            let data = insn.data();
+            let disas = match self.decoder.decode(&data) {
+                Some(disas) => disas,
+                None => insn.disas()?,
+            };
+
+            match arch.branch(&disas) {
+                Some(Branch::Call) => {
+                    let heap = heap.clone();
+                    let return_addr = insn.vaddr() + data.len() as u64;
+
+                    self.registered(
+                        "track_heap",
+                        Some(insn.vaddr()),
+                        Callback::Execute,
+                        format_args!("{disas} is a call"),
+                    )?;
+                    insn.register_execute_callback(move |vcpu_index| {
+                        heap.lock()
+                            .map_err(|e| anyhow!("Failed to lock heap: {e}"))
+                            .map(|mut heap| heap.on_call(vcpu_index, return_addr))
+                            .expect("Failed to track heap");
+                    });
+                }
+                Some(Branch::Return) => {
+                    let heap = heap.clone();
+                    let registers = registers.clone();
+
+                    self.registered(
+                        "track_heap",
+                        Some(insn.vaddr()),
+                        Callback::ExecuteRegisters,
+                        format_args!("{disas} is a return"),
+                    )?;
+                    insn.register_execute_callback_flags(
+                        move |vcpu_index| {
+                            heap.lock()
+                                .map_err(|e| anyhow!("Failed to lock heap: {e}"))
+                                .and_then(|mut heap| heap.on_return(vcpu_index, &registers))
+                                .expect("Failed to track heap");
+                        },
+                        CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+                    );
+                }
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register a callback on the first block of each mutex function, after the heap
+    /// tracker's so that the call is already on its shadow stack
+    fn track_locks(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(locks) = self.locks.as_ref() else {
+            return Ok(());
+        };
+
+        let symbol = match tb.instructions().next() {
+            Some(insn) => insn.symbol()?,
+            None => None,
+        };
+        let Some(function) = locks
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock locks: {e}"))?
+            .function(tb.vaddr(), symbol.as_deref())
+        else {
+            return Ok(());
+        };
+
+        let locks = locks.clone();
+        let heap = self.heap.clone();
+        let registers = self
+            .registers
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock registers: {e}"))?
+            .clone();
+        let stats = self.stats.clone();
+
+        self.registered(
+            "lock_report",
+            None,
+            Callback::ExecuteRegisters,
+            format_args!("the block enters {function:?}"),
+        )?;
+        tb.register_execute_callback_flags(
+            move |vcpu_index| {
+                heap.as_ref()
+                    .map(|heap| {
+                        heap.lock()
+                            .map_err(|e| anyhow!("Failed to lock heap: {e}"))
+                            .map(|heap| heap.call_stack(vcpu_index))
+                    })
+                    .transpose()
+                    .and_then(|callers| {
+                        locks
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock locks: {e}"))?
+                            .on_function(
+                                vcpu_index,
+                                function,
+                                &registers,
+                                callers.unwrap_or_default(),
+                                stats.icount(),
+                            )
+                    })
+                    .expect("Failed to track locks");
+            },
+            CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register a callback on each memory access for the race detector. Atomic instructions
+    /// synchronize the thread executing them instead of being checked.
+    fn detect_races(&self, tb: &TranslationBlock) -> Result<()> {
+        let (Some(races), Some(arch)) = (self.races.as_ref(), self.arch) else {
+            return Ok(());
+        };
+
+        for insn in tb.instructions() {
+            let data = insn.data();
+            let disas = match self.decoder.decode(&data) {
+                Some(disas) => disas,
+                None => insn.disas()?,
+            };
+            let races = races.clone();
+
+            if arch.is_atomic(&disas) {
+                self.registered(
+                    "detect_races",
+                    Some(insn.vaddr()),
+                    Callback::Memory(Access::ReadWrite),
+                    format_args!("{disas} is atomic, so it synchronizes"),
+                )?;
+                insn.register_memory_access_callback(
+                    move |vcpu_index, _, _| {
+                        races
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock races: {e}"))
+                            .map(|mut races| races.on_sync(vcpu_index))
+                            .expect("Failed to detect races");
+                    },
+                    MemRW::QEMU_PLUGIN_MEM_RW,
+                );
+                continue;
+            }
+
+            let tx = self.tx.clone();
+            let stats = self.stats.clone();
+            let heap = self.heap.clone();
+            let pc = insn.vaddr();
+
+            self.registered(
+                "detect_races",
+                Some(pc),
+                Callback::Memory(Access::ReadWrite),
+                format_args!("race detection is enabled"),
+            )?;
+            insn.register_memory_access_callback(
+                move |vcpu_index, info, vaddr| {
+                    heap.as_ref()
+                        .map(|heap| {
+                            heap.lock()
+                                .map_err(|e| anyhow!("Failed to lock heap: {e}"))
+                                .map(|heap| heap.call_stack(vcpu_index))
+                        })
+                        .transpose()
+                        .and_then(|call_stack| {
+                            races
+                                .lock()
+                                .map_err(|e| anyhow!("Failed to lock races: {e}"))
+                                .map(|mut races| {
+                                    races.on_access(RaceAccess {
+                                        vcpu_index,
+                                        pc,
+                                        vaddr,
+                                        is_store: info.is_store(),
+                                        icount: stats.icount(),
+                                        call_stack: call_stack.unwrap_or_default(),
+                                    })
+                                })
+                        })
+                        .and_then(|found| {
+                            found.into_iter().try_for_each(|race| {
+                                send_event(&tx, &stats, vcpu_index, &Event::Race(race))
+                            })
+                        })
+                        .expect("Failed to detect races");
+                },
+                MemRW::QEMU_PLUGIN_MEM_RW,
+            );
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register callbacks on each exclusive instruction. A store-exclusive's outcome is read
+    /// by a callback on the instruction after it, once it has written its status.
+    fn track_exclusives(&self, tb: &TranslationBlock) -> Result<()> {
+        let (Some(exclusives), Some(arch)) = (self.exclusives.as_ref(), self.arch) else {
+            return Ok(());
+        };
+
+        let insns = tb.instructions().collect::<Vec<_>>();
+
+        for (i, insn) in insns.iter().enumerate() {
+            let exclusives = exclusives.clone();
+            let pc = insn.vaddr();
+
+            match arch.exclusive(&insn.data(), &insn.disas()?) {
+                Some(Exclusive::Load) => {
+                    self.registered(
+                        "exclusive_report",
+                        Some(pc),
+                        Callback::Execute,
+                        format_args!("the instruction is a load-exclusive"),
+                    )?;
+                    insn.register_execute_callback(move |vcpu_index| {
+                        exclusives
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock exclusives: {e}"))
+                            .map(|mut exclusives| exclusives.on_load(vcpu_index, pc))
+                            .expect("Failed to track exclusives");
+                    })
+                }
+                Some(Exclusive::Clear) => {
+                    self.registered(
+                        "exclusive_report",
+                        Some(pc),
+                        Callback::Execute,
+                        format_args!("the instruction clears the exclusive monitor"),
+                    )?;
+                    insn.register_execute_callback(move |vcpu_index| {
+                        exclusives
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock exclusives: {e}"))
+                            .map(|mut exclusives| exclusives.on_clear(vcpu_index))
+                            .expect("Failed to track exclusives");
+                    })
+                }
+                Some(Exclusive::Store { status }) => match insns.get(i + 1) {
+                    Some(next) => {
+                        self.registered(
+                            "exclusive_report",
+                            Some(next.vaddr()),
+                            Callback::ExecuteRegisters,
+                            format_args!(
+                                "the store-exclusive at {pc:#x} before it writes its status"
+                            ),
+                        )?;
+
+                        let registers = self
+                            .registers
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock registers: {e}"))?
+                            .clone();
+
+                        next.register_execute_callback_flags(
+                            move |vcpu_index| {
+                                exclusives
+                                    .lock()
+                                    .map_err(|e| anyhow!("Failed to lock exclusives: {e}"))
+                                    .and_then(|mut exclusives| {
+                                        let success = exclusives.read_status(status, &registers)?;
+                                        exclusives.on_store(vcpu_index, pc, Some(success));
+                                        Ok(())
+                                    })
+                                    .expect("Failed to track exclusives");
+                            },
+                            CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+                        );
+                    }
+                    None => {
+                        self.registered(
+                            "exclusive_report",
+                            Some(pc),
+                            Callback::Execute,
+                            format_args!(
+                                "the store-exclusive ends the block, so its status is not read"
+                            ),
+                        )?;
+                        insn.register_execute_callback(move |vcpu_index| {
+                            exclusives
+                                .lock()
+                                .map_err(|e| anyhow!("Failed to lock exclusives: {e}"))
+                                .map(|mut exclusives| exclusives.on_store(vcpu_index, pc, None))
+                                .expect("Failed to track exclusives");
+                        })
+                    }
+                },
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Instrument a block for the watchdog. A hang is logged, ends the running fuzz input,
+    /// and exits QEMU if configured to.
+    fn watch(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(watchdog) = self.watchdog.clone() else {
+            return Ok(());
+        };
+
+        let fuzz = self.fuzz.clone();
+        let exit = self.watchdog_exit;
+        let tx = self.tx.clone();
+        let stats = self.stats.clone();
+        let vaddr = tb.vaddr();
+
+        if watchdog.resets(vaddr) {
+            self.registered(
+                "watchdog_budget",
+                None,
+                Callback::Inline,
+                format_args!("the block starts an iteration, so it resets the counter"),
+            )?;
+        }
+
+        self.registered(
+            "watchdog_budget",
+            None,
+            Callback::Inline,
+            format_args!("each block adds its instructions to the counter"),
+        )?;
+        self.registered(
+            "watchdog_budget",
+            None,
+            Callback::Conditional,
+            format_args!("the budget counter reaches {}", watchdog.budget()),
+        )?;
+        watchdog.clone().instrument(tb, move |vcpu_index| {
+            let event = watchdog.on_hang(vcpu_index, vaddr);
+
+            send_event(&tx, &stats, vcpu_index, &Event::Hang(event))
+                .expect("Failed to send hang event");
+
+            if let Some(fuzz) = fuzz.as_ref() {
+                fuzz.lock()
+                    .map_err(|e| anyhow!("Failed to lock fuzz target: {e}"))
+                    .and_then(|mut fuzz| fuzz.on_hang())
+                    .expect("Failed to end hung fuzz input");
+            }
+
+            if exit {
+                log(format!(
+                    "Watchdog budget exhausted on vCPU {vcpu_index} at {vaddr:#x}"
+                ));
+                std::process::exit(124);
+            }
+        });
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register a callback on a sequence point block which hashes the guest's state, sending
+    /// a state event and telling the fuzz target when the state is new
+    fn track_state(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(state) = self.state.clone() else {
+            return Ok(());
+        };
+
+        let vaddr = tb.vaddr();
+
+        if !state
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock state tracker: {e}"))?
+            .is_point(vaddr)
+        {
+            return Ok(());
+        }
+
+        let registers = self
+            .registers
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock registers: {e}"))?
+            .clone();
+        let fuzz = self.fuzz.clone();
+        let tx = self.tx.clone();
+        let stats = self.stats.clone();
+
+        self.registered(
+            "state_points",
+            None,
+            Callback::ExecuteRegisters,
+            format_args!("the block is a sequence point"),
+        )?;
+        tb.register_execute_callback_flags(
+            move |vcpu_index| {
+                state
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock state tracker: {e}"))
+                    .and_then(|mut state| {
+                        state.on_point(vcpu_index, vaddr, &registers, stats.icount())
+                    })
+                    .and_then(|event| {
+                        if let Some(fuzz) = fuzz.as_ref().filter(|_| event.new) {
+                            fuzz.lock()
+                                .map_err(|e| anyhow!("Failed to lock fuzz target: {e}"))?
+                                .on_new_state();
+                        }
+
+                        send_event(&tx, &stats, vcpu_index, &Event::State(event))
+                    })
+                    .expect("Failed to hash state");
+            },
+            CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register a callback on each profiled function's first block, and on every block
+    /// when calls are logged on return, sending API call events
+    fn trace_api(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(api) = self.api.clone() else {
+            return Ok(());
+        };
+
+        let vaddr = tb.vaddr();
+        let symbol = match tb.instructions().next() {
+            Some(insn) => insn.symbol()?,
+            None => None,
+        };
+        // A PLT stub is the entry of the function it imports, as far as its caller knows
+        let plt = match (symbol.is_none(), self.plt.as_ref()) {
+            (true, Some(plt)) => plt
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock PLT resolver: {e}"))?
+                .import(vaddr)
+                .map(|import| (plt.clone(), import.to_string())),
+            _ => None,
+        };
+        let symbol = symbol.or_else(|| plt.as_ref().map(|(_, import)| import.clone()));
+        let plt = plt.map(|(plt, _)| plt);
+        let (function, has_returns) = {
+            let api = api
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock API tracer: {e}"))?;
+
+            (api.function(vaddr, symbol.as_deref()), api.has_returns())
+        };
+
+        if function.is_none() && !has_returns {
+            return Ok(());
+        }
+
+        let registers = self
+            .registers
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock registers: {e}"))?
+            .clone();
+        let tx = self.tx.clone();
+        let stats = self.stats.clone();
+
+        match function.as_ref() {
+            Some(function) => self.registered(
+                "api_profiles",
+                None,
+                Callback::ExecuteRegisters,
+                format_args!("the block enters {function:?}"),
+            )?,
+            None => self.registered(
+                "api_profiles",
+                None,
+                Callback::ExecuteRegisters,
+                format_args!("traced calls may return to the block"),
+            )?,
+        }
+        tb.register_execute_callback_flags(
+            move |vcpu_index| {
+                // A call through a stub is reported at the function once it is bound
+                let address = match (function, plt.as_ref()) {
+                    (Some(_), Some(plt)) => plt
+                        .lock()
+                        .map_err(|e| anyhow!("Failed to lock PLT resolver: {e}"))
+                        .map(|mut plt| plt.on_call(vaddr).unwrap_or(vaddr))
+                        .expect("Failed to resolve PLT stub"),
+                    _ => vaddr,
+                };
+
+                api.lock()
+                    .map_err(|e| anyhow!("Failed to lock API tracer: {e}"))
+                    .and_then(|mut api| {
+                        api.on_block(
+                            vcpu_index,
+                            vaddr,
+                            address,
+                            function,
+                            &registers,
+                            stats.icount(),
+                        )
+                    })
+                    .and_then(|events| {
+                        events.into_iter().try_for_each(|event| {
+                            send_event(&tx, &stats, vcpu_index, &Event::Api(event))
+                        })
+                    })
+                    .expect("Failed to trace API calls");
+            },
+            CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register a callback on the first block of each function an expectation counts calls
+    /// to
+    fn check_expectations(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(expectations) = self.expectations.clone() else {
+            return Ok(());
+        };
+
+        let vaddr = tb.vaddr();
+        let symbol = match tb.instructions().next() {
+            Some(insn) => insn.symbol()?,
+            None => None,
+        };
+        let calls = expectations
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock expectations: {e}"))?
+            .calls(vaddr, symbol.as_deref());
+
+        if calls.is_empty() {
+            return Ok(());
+        }
+
+        let registers = self
+            .registers
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock registers: {e}"))?
+            .clone();
+        let stats = self.stats.clone();
+
+        self.registered(
+            "expectations",
+            None,
+            Callback::ExecuteRegisters,
+            format_args!("{} expectations name the block's function", calls.len()),
+        )?;
+        tb.register_execute_callback_flags(
+            move |vcpu_index| {
+                expectations
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock expectations: {e}"))
+                    .and_then(|mut expectations| {
+                        expectations.on_call(vcpu_index, vaddr, &calls, &registers, stats.icount())
+                    })
+                    .expect("Failed to check expectations");
+            },
+            CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Record the return addresses of a block's calls, and register a callback scoring
+    /// its execution for signs of a ROP chain
+    fn detect_rop(&self, tb: &TranslationBlock) -> Result<()> {
+        let (Some(rop), Some(arch)) = (self.rop.clone(), self.arch) else {
+            return Ok(());
+        };
+
+        let mut returns = false;
+
+        for insn in tb.instructions() {
+            let data = insn.data();
+            let disas = match self.decoder.decode(&data) {
+                Some(disas) => disas,
+                None => insn.disas()?,
+            };
+
+            match arch.branch(&disas) {
+                Some(Branch::Call) => rop
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock ROP detector: {e}"))?
+                    .on_call_translated(insn.vaddr() + data.len() as u64),
+                Some(Branch::Return) => returns = true,
+                None => {}
+            }
+        }
+
+        let vaddr = tb.vaddr();
+        let instructions = tb.size();
+        let heap = self.heap.clone();
+        let tx = self.tx.clone();
+        let stats = self.stats.clone();
+
+        self.registered(
+            "detect_rop",
+            None,
+            Callback::Execute,
+            format_args!(
+                "every block is scored{}",
+                if returns {
+                    ", and this one returns"
+                } else {
+                    ""
+                }
+            ),
+        )?;
+        tb.register_execute_callback(move |vcpu_index| {
+            let call_stack = || {
+                heap.as_ref()
+                    .and_then(|heap| heap.lock().ok())
+                    .map(|heap| heap.call_stack(vcpu_index))
+                    .unwrap_or_default()
+            };
+
+            rop.lock()
+                .map_err(|e| anyhow!("Failed to lock ROP detector: {e}"))
+                .map(|mut rop| {
+                    rop.on_block(
+                        vcpu_index,
+                        vaddr,
+                        instructions,
+                        returns,
+                        stats.icount(),
+                        call_stack,
+                    )
+                })
+                .and_then(|event| match event {
+                    Some(event) => send_event(&tx, &stats, vcpu_index, &Event::Rop(event)),
+                    None => Ok(()),
+                })
+                .expect("Failed to detect ROP");
+        });
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register callbacks pushing each call to the shadow stack, and checking the block
+    /// each return lands on against it
+    fn check_shadow_stack(&self, tb: &TranslationBlock) -> Result<()> {
+        let (Some(shadow), Some(arch)) = (self.shadow.as_ref(), self.arch) else {
+            return Ok(());
+        };
+
+        {
+            let shadow = shadow.clone();
+            let vaddr = tb.vaddr();
+            let tx = self.tx.clone();
+            let stats = self.stats.clone();
+
+            self.registered(
+                "shadow_stack",
+                None,
+                Callback::Execute,
+                format_args!("every block is checked against the return it may follow"),
+            )?;
+            tb.register_execute_callback(move |vcpu_index| {
+                shadow
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock shadow stack: {e}"))
+                    .map(|mut shadow| shadow.on_block(vcpu_index, vaddr, stats.icount()))
+                    .and_then(|event| match event {
+                        Some(event) => {
+                            send_event(&tx, &stats, vcpu_index, &Event::Integrity(event))
+                        }
+                        None => Ok(()),
+                    })
+                    .expect("Failed to check shadow stack");
+            });
+        }
+
+        for insn in tb.instructions() {
+            let data = insn.data();
+            let disas = match self.decoder.decode(&data) {
+                Some(disas) => disas,
+                None => insn.disas()?,
+            };
+            let shadow = shadow.clone();
+            let pc = insn.vaddr();
+
+            match arch.branch(&disas) {
+                Some(Branch::Call) => {
+                    let return_addr = pc + data.len() as u64;
+
+                    self.registered(
+                        "shadow_stack",
+                        Some(pc),
+                        Callback::Execute,
+                        format_args!("{disas} is a call"),
+                    )?;
+                    insn.register_execute_callback(move |vcpu_index| {
+                        shadow
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock shadow stack: {e}"))
+                            .map(|mut shadow| shadow.on_call(vcpu_index, return_addr))
+                            .expect("Failed to check shadow stack");
+                    });
+                }
+                Some(Branch::Return) => {
+                    self.registered(
+                        "shadow_stack",
+                        Some(pc),
+                        Callback::Execute,
+                        format_args!("{disas} is a return"),
+                    )?;
+                    insn.register_execute_callback(move |vcpu_index| {
+                        shadow
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock shadow stack: {e}"))
+                            .map(|mut shadow| shadow.on_return(vcpu_index, pc))
+                            .expect("Failed to check shadow stack");
+                    });
+                }
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register a callback recording that the block's pages executed, by virtual address in
+    /// user mode and by physical address in system mode
+    fn detect_wx(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(wx) = self.wx.clone() else {
+            return Ok(());
+        };
+
+        let system = wx
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock wx: {e}"))?
+            .system();
+        let mut pages = Vec::<(u64, u64)>::new();
+
+        for insn in tb.instructions() {
+            let page = insn.vaddr() & !(wx::PAGE_SIZE - 1);
+
+            if pages.last().is_none_or(|(vaddr, _)| *vaddr != page) {
+                pages.push((page, if system { insn.haddr() } else { page }));
+            }
+        }
+
+        let pc = tb.vaddr();
+        let tx = self.tx.clone();
+        let stats = self.stats.clone();
+
+        self.registered(
+            "detect_wx",
+            None,
+            Callback::Execute,
+            format_args!("the block's code spans {} pages", pages.len()),
+        )?;
+        tb.register_execute_callback(move |vcpu_index| {
+            for (_, addr) in &pages {
+                wx.lock()
+                    .map_err(|e| anyhow!("Failed to lock wx: {e}"))
+                    .map(|mut wx| wx.on_execute(vcpu_index, pc, *addr, stats.icount()))
+                    .and_then(|event| match event {
+                        Some(event) => send_event(&tx, &stats, vcpu_index, &Event::Wx(event)),
+                        None => Ok(()),
+                    })
+                    .expect("Failed to send wx event");
+            }
+        });
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register a callback writing a crash bundle on a block in a function which triggers one
+    fn capture_crashes(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(crash) = self.crash.clone() else {
+            return Ok(());
+        };
+
+        let vaddr = tb.vaddr();
+        let symbol = match tb.instructions().next() {
+            Some(insn) => self.symbol(vaddr, insn.symbol()?)?,
+            None => None,
+        };
+        let Some(trigger) = crash
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock crash recorder: {e}"))?
+            .trigger(vaddr, symbol.as_deref())
+        else {
+            return Ok(());
+        };
+
+        let registers = self
+            .registers
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock registers: {e}"))?
+            .clone();
+        let tx = self.tx.clone();
+        let stats = self.stats.clone();
+
+        self.registered(
+            "crash_dir",
+            None,
+            Callback::ExecuteRegisters,
+            format_args!("the block matches the trigger {trigger}"),
+        )?;
+        tb.register_execute_callback_flags(
+            move |vcpu_index| {
+                crash
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock crash recorder: {e}"))
+                    .and_then(|mut crash| {
+                        let modules = tx
+                            .relocator
+                            .as_ref()
+                            .map(|relocator| relocator.modules())
+                            .transpose()?
+                            .unwrap_or_default();
+
+                        crash.capture(
+                            vcpu_index,
+                            vaddr,
+                            trigger.clone(),
+                            &registers,
+                            &modules,
+                            stats.icount(),
+                        )
+                    })
+                    .and_then(|event| match event {
+                        Some(event) => send_event(&tx, &stats, vcpu_index, &Event::Crash(event)),
+                        None => Ok(()),
+                    })
+                    .expect("Failed to write crash bundle");
+            },
+            CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Match signatures at each instruction of a block, and register a callback sending
+    /// an event for each match the first time the block executes
+    fn scan_signatures(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(signatures) = self.signatures.clone() else {
+            return Ok(());
+        };
+
+        let mut code = Vec::new();
+        let mut starts = Vec::new();
+
+        for insn in tb.instructions() {
+            starts.push(insn.vaddr());
+            code.extend(insn.data());
+        }
+
+        let matches = signatures
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock signatures: {e}"))?
+            .scan(tb.vaddr(), &code, &starts);
+
+        if matches.is_empty() {
+            return Ok(());
+        }
+
+        let tx = self.tx.clone();
+        let stats = self.stats.clone();
+
+        self.registered(
+            "signatures",
+            None,
+            Callback::Execute,
+            format_args!("{} signatures match the block's code", matches.len()),
+        )?;
+        tb.register_execute_callback(move |vcpu_index| {
+            signatures
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock signatures: {e}"))
+                .map(|mut signatures| {
+                    matches
+                        .iter()
+                        .filter_map(|(pc, index)| {
+                            signatures.on_match(vcpu_index, *pc, *index, stats.icount())
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .and_then(|events| {
+                    events.into_iter().try_for_each(|event| {
+                        send_event(&tx, &stats, vcpu_index, &Event::Signature(event))
+                    })
+                })
+                .expect("Failed to report signature matches");
+        });
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Scan a block for cryptographic instructions and constants, and register callbacks
+    /// tracking function entries and sending an event when a candidate block executes
+    fn detect_crypto(&self, tb: &TranslationBlock) -> Result<()> {
+        let (Some(crypto), Some(arch)) = (self.crypto.as_ref(), self.arch) else {
+            return Ok(());
+        };
+
+        let mut instructions = Vec::new();
+
+        for insn in tb.instructions() {
+            let data = insn.data();
+            let disas = match self.decoder.decode(&data) {
+                Some(disas) => disas,
+                None => insn.disas()?,
+            };
+            let crypto = crypto.clone();
+
+            match arch.branch(&disas) {
+                Some(Branch::Call) => {
+                    self.registered(
+                        "detect_crypto",
+                        Some(insn.vaddr()),
+                        Callback::Execute,
+                        format_args!("{disas} is a call"),
+                    )?;
+                    insn.register_execute_callback(move |vcpu_index| {
+                        crypto
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock crypto detector: {e}"))
+                            .map(|mut crypto| crypto.on_call(vcpu_index))
+                            .expect("Failed to detect crypto");
+                    });
+                }
+                Some(Branch::Return) => {
+                    self.registered(
+                        "detect_crypto",
+                        Some(insn.vaddr()),
+                        Callback::Execute,
+                        format_args!("{disas} is a return"),
+                    )?;
+                    insn.register_execute_callback(move |vcpu_index| {
+                        crypto
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock crypto detector: {e}"))
+                            .map(|mut crypto| crypto.on_return(vcpu_index))
+                            .expect("Failed to detect crypto");
+                    });
+                }
+                None => {}
+            }
+
+            instructions.push((insn.vaddr(), data.len(), disas));
+        }
+
+        let finding = crypto
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock crypto detector: {e}"))?
+            .scan(&instructions);
+        let crypto = crypto.clone();
+        let vaddr = tb.vaddr();
+        let size = tb.size();
+        let tx = self.tx.clone();
+        let stats = self.stats.clone();
+
+        self.registered(
+            "detect_crypto",
+            None,
+            Callback::Execute,
+            format_args!(
+                "every block is counted, and this one {}",
+                if finding.is_some() {
+                    "is a candidate"
+                } else {
+                    "is not a candidate"
+                }
+            ),
+        )?;
+        tb.register_execute_callback(move |vcpu_index| {
+            crypto
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock crypto detector: {e}"))
+                .map(|mut crypto| {
+                    crypto.on_block(vcpu_index, vaddr, size, finding.as_ref(), stats.icount())
+                })
+                .and_then(|event| match event {
+                    Some(event) => send_event(&tx, &stats, vcpu_index, &Event::Crypto(event)),
+                    None => Ok(()),
+                })
+                .expect("Failed to detect crypto");
+        });
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register a callback on each instruction of a block referencing strings not yet
+    /// logged, sending an event for each the first time it executes
+    fn log_strings(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(strings) = self.strings.as_ref() else {
+            return Ok(());
+        };
+
+        let mut built = HashMap::new();
+
+        for insn in tb.instructions() {
+            let data = insn.data();
+            let disas = match self.decoder.decode(&data) {
+                Some(disas) => disas,
+                None => insn.disas()?,
+            };
+            let pc = insn.vaddr();
+            let references = strings
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock string tracker: {e}"))?
+                .references(pc, data.len(), &disas, &mut built);
+
+            if references.is_empty() {
+                continue;
+            }
+
+            let strings = strings.clone();
+            let tx = self.tx.clone();
+            let stats = self.stats.clone();
+
+            self.registered(
+                "log_strings",
+                Some(pc),
+                Callback::Execute,
+                format_args!("{disas} references {} strings", references.len()),
+            )?;
+            insn.register_execute_callback(move |vcpu_index| {
+                strings
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock string tracker: {e}"))
+                    .map(|mut strings| {
+                        references
+                            .iter()
+                            .filter_map(|address| {
+                                strings.on_reference(vcpu_index, pc, *address, stats.icount())
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .and_then(|events| {
+                        events.into_iter().try_for_each(|event| {
+                            send_event(&tx, &stats, vcpu_index, &Event::String(event))
+                        })
+                    })
+                    .expect("Failed to log strings");
+            });
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register a callback on the block which reads the thread pointer, sending a schedule
+    /// event when the vCPU has switched threads
+    fn track_schedule(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(scheduler) = self.scheduler.clone() else {
+            return Ok(());
+        };
+
+        let registers = self
+            .registers
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock registers: {e}"))?
+            .clone();
+        let tx = self.tx.clone();
+        let stats = self.stats.clone();
+
+        self.registered(
+            "log_schedule",
+            None,
+            Callback::ExecuteRegisters,
+            format_args!("every block reads the thread pointer"),
+        )?;
+        tb.register_execute_callback_flags(
+            move |vcpu_index| {
+                scheduler
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock scheduler: {e}"))
+                    .and_then(|mut scheduler| {
+                        scheduler.on_block(vcpu_index, &registers, stats.icount())
+                    })
+                    .and_then(|slice| match slice {
+                        Some(slice) => send_event(&tx, &stats, vcpu_index, &Event::Schedule(slice)),
+                        None => Ok(()),
+                    })
+                    .expect("Failed to track schedule");
+            },
+            CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Register a callback on the block which reads the vCPU's mode registers, sending a mode
+    /// event when it has switched instruction sets
+    fn track_isa_mode(&self, tb: &TranslationBlock) -> Result<()> {
+        let Some(isa) = self.isa.clone() else {
+            return Ok(());
+        };
+
+        if !isa
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock ISA tracker: {e}"))?
+            .is_readable()
+        {
+            return self.skipped(
+                "log_isa_modes",
+                None,
+                format_args!("the vCPU has no registers the mode can be read from"),
+            );
+        }
+
+        let registers = self
+            .registers
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock registers: {e}"))?
+            .clone();
+        let tx = self.tx.clone();
+        let stats = self.stats.clone();
+        let pc = tb.vaddr();
+
+        self.registered(
+            "log_isa_modes",
+            None,
+            Callback::ExecuteRegisters,
+            format_args!("every block reads the mode registers"),
+        )?;
+        tb.register_execute_callback_flags(
+            move |vcpu_index| {
+                isa.lock()
+                    .map_err(|e| anyhow!("Failed to lock ISA tracker: {e}"))
+                    .and_then(|mut isa| isa.on_block(vcpu_index, &registers, pc, stats.icount()))
+                    .and_then(|event| match event {
+                        Some(event) => send_event(&tx, &stats, vcpu_index, &Event::Mode(event)),
+                        None => Ok(()),
+                    })
+                    .expect("Failed to track ISA mode");
+            },
+            CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+        );
+
+        Ok(())
+    }
+}
+
+impl HasCallbacks for Tracer {
+    #[cfg(not(feature = "plugin-api-v1"))]
+    fn on_vcpu_init(
+        &mut self,
+        id: PluginId,
+        vcpu_id: VCPUIndex,
+    ) -> std::prelude::v1::Result<(), anyhow::Error> {
+        if self.checking {
+            return self.uninstall_after_check(id);
+        }
+
+        self.stats.utilization.on_init(vcpu_id)?;
+
+        let registers = qemu_plugin_get_registers()?;
+
+        self.cpu.add_registers(&registers);
+        self.decoder = Decoder::new(self.arch, &self.cpu);
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let Some(scheduler) = self.scheduler.as_ref() {
+            scheduler
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock scheduler: {e}"))?
+                .find_register(&registers)?;
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let Some(isa) = self.isa.as_ref() {
+            isa.lock()
+                .map_err(|e| anyhow!("Failed to lock ISA tracker: {e}"))?
+                .find_registers(&registers);
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let Some(state) = self.state.as_ref() {
+            state
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock state tracker: {e}"))?
+                .check_registers(&registers)?;
+        }
+
+        *self
+            .registers
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock registers: {}", e))? = registers;
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v1")]
+    fn on_vcpu_init(&mut self, id: PluginId, vcpu_id: VCPUIndex) -> Result<()> {
+        if self.checking {
+            return self.uninstall_after_check(id);
+        }
+
+        self.stats.utilization.on_init(vcpu_id)
+    }
+
+    fn on_vcpu_idle(&mut self, _id: PluginId, vcpu_id: VCPUIndex) -> Result<()> {
+        self.stats.utilization.on_idle(vcpu_id)
+    }
+
+    fn on_vcpu_resume(&mut self, _id: PluginId, vcpu_id: VCPUIndex) -> Result<()> {
+        self.stats.utilization.on_resume(vcpu_id)
+    }
+
+    fn on_vcpu_exit(&mut self, _id: PluginId, vcpu_id: VCPUIndex) -> Result<()> {
+        if let Some(coalescer) = self.coalescer.as_ref() {
+            let event = coalescer
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock coalescer: {e}"))?
+                .flush(vcpu_id);
+
+            if let Some(event) = event {
+                self.send(vcpu_id, &event)?;
+            }
+        }
+
+        self.stats.utilization.on_exit(vcpu_id)
+    }
+
+    fn on_flush(&mut self, _id: PluginId) -> Result<()> {
+        if let Some(retranslation) = self.retranslation.as_ref() {
+            retranslation
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock retranslation tracker: {e}"))?
+                .on_flush(self.stats.icount());
+        }
+
+        Ok(())
+    }
+
+    fn on_translation_block_translate(
+        &mut self,
+        _id: PluginId,
+        tb: TranslationBlock,
+    ) -> Result<()> {
+        Stats::bump(&self.stats.translated_blocks);
+
+        if let Some(decisions) = self.decisions.as_ref() {
+            let symbol = match tb.instructions().next() {
+                Some(insn) => self.symbol(tb.vaddr(), insn.symbol()?)?,
+                None => None,
+            };
+
+            decisions.begin(
+                tb.vaddr(),
+                tb.instructions().map(|insn| insn.size()).sum(),
+                tb.size(),
+                symbol,
+            )?;
+        }
+
+        if let Some(retranslation) = self.retranslation.as_ref() {
+            retranslation
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock retranslation tracker: {e}"))?
+                .on_translate(
+                    tb.vaddr(),
+                    tb.size(),
+                    code_hash(tb.instructions().map(|insn| insn.data())),
+                );
+        }
+
+        if let Some(filter) = self.section_filter.as_ref() {
+            let rejection = filter
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock section filter: {e}"))?
+                .rejection(tb.vaddr());
+
+            // A filtered block is still counted, so counts and the limits on them stay right
+            if let Some(rejection) = rejection {
+                self.skipped("section_filter", None, format_args!("{rejection}"))?;
+                self.count_instructions(&tb)?;
+
+                if let Some(decisions) = self.decisions.as_ref() {
+                    decisions.finish()?;
+                }
+
+                return Ok(());
+            }
+        }
+
+        self.profile_symbols(&tb)?;
+        self.profile_source_lines(&tb)?;
+        self.profile_extensions(&tb)?;
+        self.track_translations(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.capture_start(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.resolve_plt(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.track_heap(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.track_schedule(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.track_isa_mode(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.track_locks(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.detect_races(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.track_exclusives(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.watch(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.track_state(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.trace_api(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.check_expectations(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.detect_rop(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.check_shadow_stack(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.detect_wx(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.capture_crashes(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.scan_signatures(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.detect_crypto(&tb)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        self.log_strings(&tb)?;
+
+        self.count_instructions(&tb)?;
+
+        if let Some(fuzz) = self.fuzz.as_ref() {
+            let fuzz = fuzz.clone();
+            let vaddr = tb.vaddr();
+
+            self.registered(
+                "fuzz",
+                None,
+                Callback::Execute,
+                format_args!("every block is fuzz coverage"),
+            )?;
+            tb.register_execute_callback(move |vcpu_index| {
+                fuzz.lock()
+                    .map_err(|e| anyhow!("Failed to lock fuzz target: {e}"))
+                    .and_then(|mut fuzz| fuzz.on_block(vcpu_index, vaddr))
+                    .expect("Failed to record fuzz coverage");
+            });
+        }
+
+        if let Some(honggfuzz) = self.honggfuzz.as_ref() {
+            let honggfuzz = honggfuzz.clone();
+            let vaddr = tb.vaddr();
+
+            self.registered(
+                "honggfuzz",
+                None,
+                Callback::Execute,
+                format_args!("every block is fuzz coverage"),
+            )?;
+            tb.register_execute_callback(move |vcpu_index| {
+                honggfuzz
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock honggfuzz feedback: {e}"))
+                    .map(|mut honggfuzz| honggfuzz.on_block(vcpu_index, vaddr))
+                    .expect("Failed to record honggfuzz coverage");
+            });
+        }
+
+        if let Some(coverage) = self.coverage.as_ref() {
+            let coverage = coverage.clone();
+            let vaddr = tb.vaddr();
+            let size = tb.instructions().map(|insn| insn.size()).sum::<usize>();
+            let hash = match self.block_identity {
+                BlockIdentity::Offset => 0,
+                BlockIdentity::Content => code_hash(tb.instructions().map(|insn| insn.data())),
+            };
+
+            let tx = self.tx.clone();
+            let stats = self.stats.clone();
+
+            self.registered(
+                "coverage",
+                None,
+                Callback::Execute,
+                format_args!(
+                    "every block is coverage, identified by {:?}",
+                    self.block_identity
+                ),
+            )?;
+            tb.register_execute_callback(move |vcpu_index| {
+                coverage
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock coverage: {e}"))
+                    .map(|mut coverage| {
+                        coverage.on_block(vcpu_index, vaddr, size, hash, stats.icount())
+                    })
+                    .and_then(|event| match event {
+                        Some(event) => {
+                            stats.on_new_coverage(&event)?;
+                            send_event(&tx, &stats, vcpu_index, &Event::Coverage(event))
+                        }
+                        None => Ok(()),
+                    })
+                    .expect("Failed to record coverage");
+            });
+        }
+
+        // Code is decoded for the mode the last block ran in, and decoded again when it runs
+        // if that was wrong
+        #[cfg(feature = "plugin-api-v4")]
+        let mode = self
+            .isa
+            .as_ref()
+            .map(|isa| {
+                isa.lock()
+                    .map_err(|e| anyhow!("Failed to lock ISA tracker: {e}"))
+                    .map(|isa| isa.last())
+            })
+            .transpose()?;
+        #[cfg(not(feature = "plugin-api-v4"))]
+        let mode = None;
+        let decoder = match (mode, self.arch) {
+            (Some(mode), Some(arch)) if mode != arch.default_mode() => {
+                Decoder::for_mode(self.arch, &self.cpu, mode)
+            }
+            _ => self.decoder,
+        };
+
+        tb.instructions().try_for_each(|insn| {
+            let mut event = InstructionEvent::try_from(&insn, &decoder)?;
+            event.mode = mode;
+            event.symbol = self.symbol(insn.vaddr(), event.symbol)?;
+            let insn_counter = self.occurrences(insn.vaddr(), EventClass::Instruction)?;
+            let pcs_counter = self.occurrences(insn.vaddr(), EventClass::Pcs)?;
+            let mem_counter = self.occurrences(insn.vaddr(), EventClass::Memory)?;
+
+            for (enabled, counter, feature) in [
+                (self.log_insns, &insn_counter, "log_insns"),
+                (self.log_pcs, &pcs_counter, "log_pcs"),
+                (self.log_mem, &mem_counter, "log_mem"),
+            ] {
+                if enabled && counter.is_none() {
+                    self.skipped(
+                        feature,
+                        Some(insn.vaddr()),
+                        format_args!("already recorded as many times as dedup_limit allows"),
+                    )?;
+                }
+            }
+
+            // A counter shared by every class counts executions, so it must be ticked before
+            // the callbacks which admit each execution's events
+            if let Some(counter) = insn_counter.as_ref().filter(|c| c.is_shared()) {
+                if self.log_insns || self.log_pcs || self.log_mem {
+                    let counter = counter.clone();
+
+                    self.registered(
+                        "dedup_limit",
+                        Some(insn.vaddr()),
+                        Callback::Execute,
+                        format_args!("every class shares a counter, ticked once per execution"),
+                    )?;
+                    insn.register_execute_callback(move |_| counter.tick());
+                }
+            }
+
+            #[cfg(feature = "plugin-api-v1")]
+            if let (true, Some(counter)) = (self.log_insns, insn_counter) {
+                let tx = self.tx.clone();
+                let stats = self.stats.clone();
+
+                self.registered(
+                    "log_insns",
+                    Some(insn.vaddr()),
+                    Callback::Execute,
+                    format_args!("instructions are logged"),
+                )?;
+                insn.register_execute_callback(move |vcpu_index| {
+                    if !counter.admit() {
+                        return;
+                    }
+
+                    send_event(
+                        &tx,
+                        &stats,
+                        vcpu_index,
+                        &Event::Instruction {
+                            event: event.clone(),
+                        },
+                    )
+                    .expect("Failed to send instruction event");
+                });
+            }
+
+            #[cfg(not(feature = "plugin-api-v1"))]
+            if let (true, Some(counter)) = (self.log_insns, insn_counter) {
+                let tx = self.tx.clone();
+                let stats = self.stats.clone();
+                let registers = self
+                    .registers
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock registers: {}", e))?
+                    .clone();
+                #[cfg(feature = "plugin-api-v4")]
+                let (isa, arch, cpu) = (self.isa.clone(), self.arch, self.cpu.clone());
+
+                self.registered(
+                    "log_insns",
+                    Some(insn.vaddr()),
+                    Callback::Execute,
+                    format_args!("instructions are logged with {} registers", registers.len()),
+                )?;
+                insn.register_execute_callback(move |vcpu_index| {
+                    if !counter.admit() {
+                        return;
+                    }
+
+                    #[allow(unused_mut)]
+                    let mut event = event.clone();
+
+                    #[cfg(feature = "plugin-api-v4")]
+                    if let Some(isa) = isa.as_ref() {
+                        let mode = isa
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock ISA tracker: {e}"))
+                            .map(|isa| isa.mode(vcpu_index))
+                            .expect("Failed to read ISA mode");
+
+                        event = event.in_mode(arch, &cpu, mode);
+                    }
+
+                    send_event(
+                        &tx,
+                        &stats,
+                        vcpu_index,
+                        &Event::Instruction {
+                            event,
+                            registers: Registers(
+                                registers
+                                    .iter()
+                                    .map(|r| {
+                                        let value = r.read().unwrap_or_else(|_| vec![]);
+                                        (r.name.clone(), value)
+                                    })
+                                    .collect(),
+                            ),
+                        },
+                    )
+                    .expect("Failed to send instruction event");
+                });
+            }
+
+            if let (true, Some(counter)) = (self.log_pcs, pcs_counter) {
+                let tx = self.tx.clone();
+                let stats = self.stats.clone();
+                let pcs = self.pcs.clone();
+                let pc = insn.vaddr();
+
+                self.registered(
+                    "log_pcs",
+                    Some(pc),
+                    Callback::Execute,
+                    format_args!("pcs are logged"),
+                )?;
+                insn.register_execute_callback(move |vcpu_index| {
+                    if !counter.admit() {
+                        return;
+                    }
+
+                    pcs.push(vcpu_index, pc)
+                        .and_then(|batch| match batch {
+                            Some(batch) => send_event(&tx, &stats, vcpu_index, &Event::Pcs(batch)),
+                            None => Ok(()),
+                        })
+                        .expect("Failed to send pc batch");
+                });
+            }
+
+            if let (true, Some(counter)) = (self.log_mem, mem_counter) {
+                let tx = self.tx.clone();
+                let stats = self.stats.clone();
+                let operand = decoder.address_breakdown(&insn.data());
+                #[cfg(feature = "plugin-api-v4")]
+                let heap = self.heap.clone();
+                #[cfg(feature = "plugin-api-v4")]
+                let log_values = self.log_mem_values;
+                let coalescer = self.coalescer.clone();
+                let pc = insn.vaddr();
+
+                self.registered(
+                    "log_mem",
+                    Some(pc),
+                    Callback::Memory(Access::ReadWrite),
+                    format_args!("memory accesses are logged"),
+                )?;
+                insn.register_memory_access_callback(
+                    move |vcpu_index, info, vaddr| {
+                        Stats::bump(&stats.memory_accesses);
+
+                        if !counter.admit() {
+                            return;
+                        }
+
+                        MemoryEvent::try_from(&info, vaddr, operand.clone())
+                            .and_then(|event| {
+                                #[cfg(feature = "plugin-api-v4")]
+                                let event = event.with_allocation(heap.as_deref())?;
+                                #[cfg(feature = "plugin-api-v4")]
+                                let event = if log_values {
+                                    event.with_value(&info)
+                                } else {
+                                    event
+                                };
+
+                                let Some(coalescer) = coalescer.as_ref() else {
+                                    return send_event(
+                                        &tx,
+                                        &stats,
+                                        vcpu_index,
+                                        &Event::Memory(event),
+                                    );
+                                };
+
+                                match coalescer
+                                    .lock()
+                                    .map_err(|e| anyhow!("Failed to lock coalescer: {e}"))?
+                                    .push(vcpu_index, pc, event)
+                                {
+                                    Some(event) => send_event(&tx, &stats, vcpu_index, &event),
+                                    None => Ok(()),
+                                }
+                            })
+                            .expect("Failed to send memory event");
+                    },
+                    MemRW::QEMU_PLUGIN_MEM_RW,
+                );
+            }
+
+            if let Some(bookmarks) = self.bookmarks.as_ref() {
+                for bookmark in bookmarks.at(insn.vaddr()) {
+                    let tx = self.tx.clone();
+                    let stats = self.stats.clone();
+                    let name = bookmark.name.clone();
+                    let pc = bookmark.pc;
+
+                    self.registered(
+                        "bookmarks",
+                        Some(pc),
+                        Callback::Execute,
+                        format_args!("the bookmark {name} is at the instruction"),
+                    )?;
+                    insn.register_execute_callback(move |vcpu_index| {
+                        let event = BookmarkEvent::builder()
+                            .vcpu_index(vcpu_index)
+                            .name(name.clone())
+                            .pc(Some(pc))
+                            .source(BookmarkSource::Pc)
+                            .icount(stats.icount())
+                            .build();
+
+                        send_event(&tx, &stats, vcpu_index, &Event::Bookmark(event))
+                            .expect("Failed to send bookmark event");
+                    });
+                }
+            }
+
+            if let Some(memory_map) = self.memory_map.as_ref() {
+                let memory_map = memory_map.clone();
+
+                self.registered(
+                    "memory_map",
+                    Some(insn.vaddr()),
+                    Callback::Memory(Access::ReadWrite),
+                    format_args!("accesses add to the map unless it was queried over QMP"),
+                )?;
+                insn.register_memory_access_callback(
+                    move |_, info, vaddr| {
+                        let Some(hwaddr) = info.hwaddr(vaddr) else {
+                            return;
+                        };
+
+                        let device = hwaddr.device_name().ok().flatten();
+
+                        memory_map
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock memory map: {e}"))
+                            .map(|mut memory_map| {
+                                // A map queried over QMP is complete, so accesses add nothing
+                                if memory_map.source == MapSource::Observed {
+                                    memory_map.observe(
+                                        hwaddr.hwaddr(),
+                                        hwaddr.is_io(),
+                                        device.as_deref(),
+                                    );
+                                }
+                            })
+                            .expect("Failed to record memory map");
+                    },
+                    MemRW::QEMU_PLUGIN_MEM_RW,
+                );
+            }
+
+            if let Some(translations) = self.translations.as_ref() {
+                let translations = translations.clone();
+                let tx = self.tx.clone();
+                let stats = self.stats.clone();
+
+                self.registered(
+                    "translation_log",
+                    Some(insn.vaddr()),
+                    Callback::Memory(Access::ReadWrite),
+                    format_args!("each access's translation is observed"),
+                )?;
+                insn.register_memory_access_callback(
+                    move |vcpu_index, info, vaddr| {
+                        let Some(hwaddr) = info.hwaddr(vaddr) else {
+                            return;
+                        };
+
+                        translations
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock translations: {e}"))
+                            .map(|mut translations| {
+                                translations.observe(
+                                    vcpu_index,
+                                    vaddr,
+                                    hwaddr.hwaddr(),
+                                    TranslationSource::Data,
+                                    hwaddr.is_io(),
+                                    stats.icount(),
+                                )
+                            })
+                            .and_then(|event| match event {
+                                Some(event) => {
+                                    send_event(&tx, &stats, vcpu_index, &Event::Translation(event))
+                                }
+                                None => Ok(()),
+                            })
+                            .expect("Failed to send translation event");
+                    },
+                    MemRW::QEMU_PLUGIN_MEM_RW,
+                );
+            }
+
+            #[cfg(feature = "plugin-api-v4")]
+            if let (Some(dumper), Some(arch)) = (self.dumper.as_ref(), self.arch) {
+                let pc = insn.vaddr();
+
+                if dumper
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock dumper: {e}"))?
+                    .wants_pc(pc)
+                {
+                    let dumper = dumper.clone();
+                    let tx = self.tx.clone();
+                    let stats = self.stats.clone();
+                    let registers = self
+                        .registers
+                        .lock()
+                        .map_err(|e| anyhow!("Failed to lock registers: {e}"))?
+                        .clone();
+
+                    self.registered(
+                        "dump_pc",
+                        Some(pc),
+                        Callback::ExecuteRegisters,
+                        format_args!("a dump is triggered at the instruction"),
+                    )?;
+                    insn.register_execute_callback_flags(
+                        move |vcpu_index| {
+                            send_dump(
+                                &tx,
+                                &stats,
+                                &dumper,
+                                arch,
+                                &registers,
+                                vcpu_index,
+                                DumpTrigger::Pc(pc),
+                            )
+                            .expect("Failed to dump guest memory");
+                        },
+                        CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+                    );
+                }
+            }
+
+            #[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+            if let Some(yara) = self.yara.as_ref().filter(|y| y.wants_pc(insn.vaddr())) {
+                let yara = yara.clone();
+                let pc = insn.vaddr();
+                let tx = self.tx.clone();
+                let stats = self.stats.clone();
+
+                self.registered(
+                    "yara_pcs",
+                    Some(pc),
+                    Callback::Execute,
+                    format_args!("a scan is triggered at the instruction"),
+                )?;
+                insn.register_execute_callback(move |vcpu_index| {
+                    yara.scan(vcpu_index, DumpTrigger::Pc(pc), stats.icount())
+                        .into_iter()
+                        .try_for_each(|event| {
+                            send_event(&tx, &stats, vcpu_index, &Event::Yara(event))
+                        })
+                        .expect("Failed to scan guest memory");
+                });
+            }
+
+            #[cfg(feature = "plugin-api-v4")]
+            for probe in self.probes.iter().filter(|p| p.pc == insn.vaddr()) {
+                let probe = probe.clone();
+                let arch = self
+                    .arch
+                    .ok_or_else(|| anyhow!("entropy_probes does not know the target"))?;
+                let tx = self.tx.clone();
+                let stats = self.stats.clone();
+                let registers = self
+                    .registers
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock registers: {e}"))?
+                    .clone();
+
+                self.registered(
+                    "entropy_probes",
+                    Some(probe.pc),
+                    Callback::ExecuteRegisters,
+                    format_args!("a probe is at the instruction"),
+                )?;
+                insn.register_execute_callback_flags(
+                    move |vcpu_index| {
+                        probe
+                            .measure(arch, vcpu_index, &registers, stats.icount())
+                            .and_then(|event| {
+                                send_event(&tx, &stats, vcpu_index, &Event::Probe(event))
+                            })
+                            .expect("Failed to measure guest buffer");
+                    },
+                    CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+                );
+            }
+
+            #[cfg(feature = "plugin-api-v4")]
+            if let Some(faults) = self.faults.as_ref() {
+                let pc = insn.vaddr();
+
+                if faults
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock faults: {e}"))?
+                    .wants_pc(pc)
+                {
+                    let faults = faults.clone();
+                    let tx = self.tx.clone();
+                    let stats = self.stats.clone();
+
+                    self.registered(
+                        "fault_rules",
+                        Some(pc),
+                        Callback::Memory(Access::Read),
+                        format_args!("a fault rule matches the instruction"),
+                    )?;
+                    insn.register_memory_access_callback(
+                        move |vcpu_index, info, vaddr| {
+                            faults
+                                .lock()
+                                .map_err(|e| anyhow!("Failed to lock faults: {e}"))
+                                .and_then(|mut faults| {
+                                    faults.on_memory_read(
+                                        vcpu_index,
+                                        pc,
+                                        vaddr,
+                                        1 << info.size_shift(),
+                                        stats.icount(),
+                                    )
+                                })
+                                .and_then(|event| match event {
+                                    Some(event) => {
+                                        send_event(&tx, &stats, vcpu_index, &Event::Fault(event))
+                                    }
+                                    None => Ok(()),
+                                })
+                                .expect("Failed to send fault event");
+                        },
+                        MemRW::QEMU_PLUGIN_MEM_R,
+                    );
+                }
+            }
+
+            #[cfg(feature = "plugin-api-v4")]
+            if let Some(periph) = self.periph.as_ref() {
+                let periph = periph.clone();
+                let tx = self.tx.clone();
+                let stats = self.stats.clone();
+                let pc = insn.vaddr();
+
+                self.registered(
+                    "periph_path",
+                    Some(pc),
+                    Callback::Memory(Access::ReadWrite),
+                    format_args!("any access may reach a peripheral"),
+                )?;
+                insn.register_memory_access_callback(
+                    move |vcpu_index, info, vaddr| {
+                        let Some(hwaddr) = info.hwaddr(vaddr) else {
+                            return;
+                        };
+
+                        periph
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock periph: {e}"))
+                            .map(|mut periph| {
+                                periph.on_access(
+                                    vcpu_index,
+                                    pc,
+                                    hwaddr.hwaddr(),
+                                    info.is_store(),
+                                    mem_value(&info.value()),
+                                    stats.icount(),
+                                )
+                            })
+                            .and_then(|event| match event {
+                                Some(event) => {
+                                    send_event(&tx, &stats, vcpu_index, &Event::Periph(event))
+                                }
+                                None => Ok(()),
+                            })
+                            .expect("Failed to send periph event");
+                    },
+                    MemRW::QEMU_PLUGIN_MEM_RW,
+                );
+            }
+
+            #[cfg(feature = "plugin-api-v4")]
+            if let Some(uart) = self.uart.as_ref() {
+                let uart = uart.clone();
+                let tx = self.tx.clone();
+                let stats = self.stats.clone();
+
+                self.registered(
+                    "uarts",
+                    Some(insn.vaddr()),
+                    Callback::Memory(Access::Write),
+                    format_args!("any store may reach a UART"),
+                )?;
+                insn.register_memory_access_callback(
+                    move |vcpu_index, info, vaddr| {
+                        let Some(hwaddr) = info.hwaddr(vaddr) else {
+                            return;
+                        };
+
+                        uart.lock()
+                            .map_err(|e| anyhow!("Failed to lock uart: {e}"))
+                            .map(|mut uart| {
+                                uart.on_access(
+                                    vcpu_index,
+                                    hwaddr.hwaddr(),
+                                    info.is_store(),
+                                    mem_value(&info.value()),
+                                    stats.icount(),
+                                )
+                            })
+                            .and_then(|event| match event {
+                                Some(event) => {
+                                    send_event(&tx, &stats, vcpu_index, &Event::Uart(event))
+                                }
+                                None => Ok(()),
+                            })
+                            .expect("Failed to send uart event");
+                    },
+                    MemRW::QEMU_PLUGIN_MEM_W,
+                );
+            }
+
+            #[cfg(feature = "plugin-api-v4")]
+            if let Some(reference) = self.reference.as_ref() {
+                let registers = reference
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock reference checker: {e}"))?
+                    .registers(
+                        &self
+                            .registers
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock registers: {e}"))?,
+                    );
+                let checker = reference.clone();
+                let tx = self.tx.clone();
+                let stats = self.stats.clone();
+                let pc = insn.vaddr();
+                let opcode = Arc::<[u8]>::from(insn.data());
+                let disas = Arc::<str>::from(match decoder.decode(&opcode) {
+                    Some(disas) => disas,
+                    None => insn.disas()?,
+                });
+
+                self.registered(
+                    "reference",
+                    Some(pc),
+                    Callback::ExecuteRegisters,
+                    format_args!(
+                        "models are shown every instruction, with {} registers",
+                        registers.len()
+                    ),
+                )?;
+                insn.register_execute_callback_flags(
+                    move |vcpu_index| {
+                        let divergences = checker
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock reference checker: {e}"))
+                            .and_then(|mut checker| {
+                                checker.on_instruction(
+                                    vcpu_index,
+                                    pc,
+                                    opcode.clone(),
+                                    disas.clone(),
+                                    &registers,
+                                    stats.icount(),
+                                )
+                            })
+                            .expect("Failed to check instruction against reference models");
+
+                        for divergence in divergences {
+                            let vetoed = divergence.vetoed.then(|| divergence.clone());
+
+                            send_event(&tx, &stats, vcpu_index, &Event::Divergence(divergence))
+                                .expect("Failed to send divergence event");
+
+                            if let Some(divergence) = vetoed {
+                                reference::veto(&divergence);
+                            }
+                        }
+                    },
+                    CallbackFlags::QEMU_PLUGIN_CB_R_REGS,
+                );
+            }
+
+            #[cfg(feature = "plugin-api-v4")]
+            if let Some(expectations) = self.expectations.as_ref() {
+                if expectations
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock expectations: {e}"))?
+                    .watches_writes()
+                {
+                    let expectations = expectations.clone();
+                    let stats = self.stats.clone();
+                    let pc = insn.vaddr();
+
+                    self.registered(
+                        "expectations",
+                        Some(pc),
+                        Callback::Memory(Access::Write),
+                        format_args!("an expectation watches writes"),
+                    )?;
+                    insn.register_memory_access_callback(
+                        move |vcpu_index, info, vaddr| {
+                            expectations
+                                .lock()
+                                .map_err(|e| anyhow!("Failed to lock expectations: {e}"))
+                                .map(|mut expectations| {
+                                    expectations.on_write(
+                                        vcpu_index,
+                                        pc,
+                                        vaddr,
+                                        1 << info.size_shift(),
+                                        stats.icount(),
+                                    )
+                                })
+                                .expect("Failed to check expectations");
+                        },
+                        MemRW::QEMU_PLUGIN_MEM_W,
+                    );
+                }
+            }
+
+            #[cfg(feature = "plugin-api-v4")]
+            if let Some(wx) = self.wx.as_ref() {
+                let wx = wx.clone();
+                let tx = self.tx.clone();
+                let stats = self.stats.clone();
+                let pc = insn.vaddr();
+
+                self.registered(
+                    "detect_wx",
+                    Some(pc),
+                    Callback::Memory(Access::Write),
+                    format_args!("any store may write code"),
+                )?;
+                insn.register_memory_access_callback(
+                    move |vcpu_index, info, vaddr| {
+                        // Pages are tracked by physical address in system mode
+                        let addr = match info.hwaddr(vaddr) {
+                            Some(hwaddr) => hwaddr.hwaddr(),
+                            None => vaddr,
+                        };
+
+                        wx.lock()
+                            .map_err(|e| anyhow!("Failed to lock wx: {e}"))
+                            .map(|mut wx| wx.on_write(vcpu_index, pc, addr, stats.icount()))
+                            .and_then(|event| match event {
+                                Some(event) => {
+                                    send_event(&tx, &stats, vcpu_index, &Event::Wx(event))
+                                }
+                                None => Ok(()),
+                            })
+                            .expect("Failed to send wx event");
+                    },
+                    MemRW::QEMU_PLUGIN_MEM_W,
+                );
+            }
+
+            Ok::<(), Error>(())
+        })?;
+
+        if let Some(decisions) = self.decisions.as_ref() {
+            decisions.finish()?;
+        }
+
+        Ok(())
+    }
+
+    fn on_syscall(
+        &mut self,
+        id: PluginId,
+        vcpu_index: VCPUIndex,
+        num: i64,
+        a1: u64,
+        a2: u64,
+        a3: u64,
+        a4: u64,
+        a5: u64,
+        a6: u64,
+        a7: u64,
+        a8: u64,
+    ) -> Result<()> {
+        #[cfg(feature = "plugin-api-v4")]
+        self.capture_console(vcpu_index, num, a1, a2, a3)?;
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let (Some(files), Some(arch)) = (self.files.as_ref(), self.arch) {
+            files
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock files: {e}"))?
+                .on_syscall(arch, vcpu_index, num, [a1, a2, a3, a4, a5, a6, a7, a8])?;
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let (Some(symbolizer), Some(arch)) = (self.symbolizer.as_ref(), self.arch) {
+            symbolizer
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock symbolizer: {e}"))?
+                .on_syscall(arch, vcpu_index, num, [a1, a2, a3, a4, a5, a6, a7, a8])?;
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let (Some(net), Some(arch)) = (self.net.as_ref(), self.arch) {
+            net.lock()
+                .map_err(|e| anyhow!("Failed to lock net: {e}"))?
+                .on_syscall(arch, vcpu_index, num, [a1, a2, a3, a4, a5, a6, a7, a8]);
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let (Some(input), Some(arch)) = (self.input.as_ref(), self.arch) {
+            input
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock input: {e}"))?
+                .on_syscall(arch, vcpu_index, num, [a1, a2, a3, a4, a5, a6, a7, a8])?;
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let (Some(random), Some(arch)) = (self.random.as_ref(), self.arch) {
+            random
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock random: {e}"))?
+                .on_syscall(arch, vcpu_index, num, [a1, a2, a3, a4, a5, a6, a7, a8])?;
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let Some(locks) = self.locks.as_ref() {
+            locks
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock locks: {e}"))?
+                .on_syscall(vcpu_index, num, [a1, a2, a3, a4, a5, a6, a7, a8]);
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let Some(wx) = self.wx.as_ref() {
+            wx.lock()
+                .map_err(|e| anyhow!("Failed to lock wx: {e}"))?
+                .on_syscall(vcpu_index, num, [a1, a2, a3, a4, a5, a6, a7, a8]);
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let Some(races) = self.races.as_ref() {
+            races
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock races: {e}"))?
+                .on_syscall(vcpu_index, num);
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let (Some(faults), Some(arch)) = (self.faults.as_ref(), self.arch) {
+            let event = faults
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock faults: {e}"))?
+                .on_syscall(
+                    arch,
+                    vcpu_index,
+                    num,
+                    [a1, a2, a3, a4, a5, a6, a7, a8],
+                    self.stats.icount(),
+                )?;
+
+            if let Some(event) = event {
+                self.send(vcpu_index, &Event::Fault(event))?;
+            }
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let (Some(dumper), Some(arch)) = (self.dumper.as_ref(), self.arch) {
+            let trigger = dumper
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock dumper: {e}"))?
+                .on_syscall(arch, num, [a1, a2, a3, a4, a5, a6, a7, a8]);
+
+            if let Some(trigger) = trigger {
+                send_dump(
+                    &self.tx,
+                    &self.stats,
+                    dumper,
+                    arch,
+                    &self
+                        .registers
+                        .lock()
+                        .map_err(|e| anyhow!("Failed to lock registers: {e}"))?,
+                    vcpu_index,
+                    trigger,
+                )?;
+            }
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let Some(bookmarks) = self.bookmarks.as_ref() {
+            let event = bookmarks.on_syscall(
+                vcpu_index,
+                num,
+                [a1, a2, a3, a4, a5, a6, a7, a8],
+                self.stats.icount(),
+            )?;
+
+            if let Some(event) = event {
+                self.send(vcpu_index, &Event::Bookmark(event))?;
+            }
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if self.guest_hypercalls {
+            if let Some(hypercall) = Hypercall::decode(num, [a1, a2, a3, a4, a5, a6, a7, a8]) {
+                self.on_hypercall(vcpu_index, hypercall)?;
+            }
+        }
+
+        #[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+        if let Some(yara) = self.yara.as_ref() {
+            if let Some(trigger) = yara.on_syscall(num) {
+                yara.scan(vcpu_index, trigger, self.stats.icount())
+                    .into_iter()
+                    .try_for_each(|event| {
+                        send_event(&self.tx, &self.stats, vcpu_index, &Event::Yara(event))
+                    })?;
+            }
+        }
+
+        if !self.log_syscalls {
+            return Ok(());
+        }
+
+        Stats::bump(&self.stats.syscalls);
+
+        #[cfg(any(
+            feature = "plugin-api-v1",
+            feature = "plugin-api-v2",
+            feature = "plugin-api-v3"
+        ))]
+        let event = SyscallEvent::builder()
+            .num(num)
+            .return_value(-1)
+            .args([a1, a2, a3, a4, a5, a6, a7, a8])
+            .build();
+
+        #[cfg(feature = "plugin-api-v4")]
+        let event = {
+            let buffers =
+                if let Some(write_sysno) = self.arch.and_then(|arch| arch.sysno(Syscall::Write)) {
+                    if num == write_sysno {
+                        let addr = a2;
+                        let len = a3 as usize;
+                        let buffer = qemu_plugin_read_memory_vaddr(addr, len)?;
+                        [(1, buffer)].into_iter().collect::<HashMap<_, _>>()
+                    } else {
+                        Default::default()
+                    }
+                } else {
+                    Default::default()
+                };
+
+            SyscallEvent::builder()
+                .num(num)
                 .return_value(-1)
                 .args([a1, a2, a3, a4, a5, a6, a7, a8])
                 .buffers(buffers)
@@ -328,6 +4184,7 @@ impl HasCallbacks for Tracer {
                 .build(),
             event,
         );
+        Stats::bump(&self.stats.pending_syscalls);
 
         Ok(())
     }
@@ -336,9 +4193,115 @@ impl HasCallbacks for Tracer {
         &mut self,
         id: PluginId,
         vcpu_index: VCPUIndex,
-        num: i64,
+        // Only the race detector, which needs plugin API v4, looks at the syscall number
+        #[cfg_attr(not(feature = "plugin-api-v4"), allow(unused_variables))] num: i64,
         ret: i64,
     ) -> Result<()> {
+        #[cfg(feature = "plugin-api-v4")]
+        if let (Some(files), Some(arch)) = (self.files.as_ref(), self.arch) {
+            files
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock files: {e}"))?
+                .on_syscall_return(arch, vcpu_index, ret)?;
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let (Some(symbolizer), Some(arch)) = (self.symbolizer.as_ref(), self.arch) {
+            let module = symbolizer
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock symbolizer: {e}"))?
+                .on_syscall_return(arch, vcpu_index, ret);
+
+            // A library the reader cannot parse is filtered as code in no known module, and
+            // has no PLT stubs
+            let map = match module.as_ref() {
+                Some(module) if self.section_filter.is_some() || self.plt.is_some() => {
+                    ModuleMap::load_at(debuginfo::host_path(&module.name), module.bias).ok()
+                }
+                _ => None,
+            };
+
+            if let (Some(map), Some(plt)) = (map.as_ref(), self.plt.as_ref()) {
+                plt.lock()
+                    .map_err(|e| anyhow!("Failed to lock PLT resolver: {e}"))?
+                    .add_module(map);
+            }
+
+            if let (Some(map), Some(filter)) = (map, self.section_filter.as_ref()) {
+                filter
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock section filter: {e}"))?
+                    .add_module(map);
+            }
+
+            if let (Some(module), Some(extensions)) = (module.as_ref(), self.extensions.as_ref()) {
+                extensions
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock extension profiler: {e}"))?
+                    .add_module(&module.name, module.start, module.end);
+            }
+
+            if let (Some(module), Some(relocator)) = (module, self.tx.relocator.as_ref()) {
+                relocator.add(module)?;
+            }
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let (Some(net), Some(arch)) = (self.net.as_ref(), self.arch) {
+            net.lock()
+                .map_err(|e| anyhow!("Failed to lock net: {e}"))?
+                .on_syscall_return(arch, vcpu_index, ret)?;
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let Some(input) = self.input.as_ref() {
+            input
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock input: {e}"))?
+                .on_syscall_return(vcpu_index, ret)?;
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let Some(locks) = self.locks.as_ref() {
+            locks
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock locks: {e}"))?
+                .on_syscall_return(vcpu_index, self.stats.icount());
+        }
+
+        // A thread woken from futex has acquired whatever its waker released
+        #[cfg(feature = "plugin-api-v4")]
+        if let Some(races) = self.races.as_ref() {
+            races
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock races: {e}"))?
+                .on_syscall(vcpu_index, num);
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let Some(random) = self.random.as_ref() {
+            let event = random
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock random: {e}"))?
+                .on_syscall_return(vcpu_index, ret, self.stats.icount())?;
+
+            if let Some(event) = event {
+                self.send(vcpu_index, &Event::Random(event))?;
+            }
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        if let Some(wx) = self.wx.as_ref() {
+            let event = wx
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock wx: {e}"))?
+                .on_syscall_return(vcpu_index, ret, self.stats.icount());
+
+            if let Some(event) = event {
+                self.send(vcpu_index, &Event::Wx(event))?;
+            }
+        }
+
         if !self.log_syscalls {
             return Ok(());
         }
@@ -357,49 +4320,563 @@ impl HasCallbacks for Tracer {
                     .build(),
             )
             .ok_or_else(|| anyhow!("No syscall event found"))?;
+        self.stats.pending_syscalls.fetch_sub(1, Ordering::Relaxed);
+
+        #[cfg(feature = "plugin-api-v4")]
+        {
+            if let Some(read_sysno) = self.arch.and_then(|arch| arch.sysno(Syscall::Read)) {
+                if num == read_sysno {
+                    let addr = event.args[1];
+                    let len = event.args[2] as usize;
+                    let buffer = qemu_plugin_read_memory_vaddr(addr, len)?;
+                    event.buffers.insert(1, buffer);
+                }
+            }
+        }
+
+        // Update the return value
+        event.return_value = ret;
+
+        // Send the event
+        self.send(vcpu_index, &Event::Syscall(event))
+    }
+}
+
+#[derive(TypedBuilder, Clone, Debug)]
+pub struct PluginArgs {
+    pub log_insns: bool,
+    pub log_mem: bool,
+    #[builder(default)]
+    pub log_mem_values: bool,
+    pub log_syscalls: bool,
+    #[cfg(not(feature = "plugin-api-v1"))]
+    pub log_registers: bool,
+    #[builder(default)]
+    pub log_console: bool,
+    #[builder(default)]
+    pub log_start: bool,
+    #[builder(default)]
+    pub log_pcs: bool,
+    #[builder(default)]
+    pub socket_path: Option<PathBuf>,
+    #[builder(default)]
+    pub stats_path: Option<PathBuf>,
+    #[builder(default)]
+    pub control_path: Option<PathBuf>,
+    /// A TCP address for the control channel, usable where Unix sockets are not
+    #[builder(default)]
+    pub control_address: Option<String>,
+    #[builder(default)]
+    pub file_report: Option<PathBuf>,
+    #[builder(default)]
+    pub hash_files: bool,
+    #[builder(default)]
+    pub pcap_path: Option<PathBuf>,
+    #[builder(default)]
+    pub log_random: bool,
+    #[builder(default)]
+    pub seeded: bool,
+    #[builder(default)]
+    pub fault_rules: Option<PathBuf>,
+    #[builder(default)]
+    pub periph_path: Option<PathBuf>,
+    #[builder(default)]
+    pub uarts: Option<String>,
+    #[builder(default)]
+    pub fault_seed: u64,
+    #[builder(default)]
+    pub max_files: Option<usize>,
+    #[builder(default)]
+    pub max_ranges: Option<usize>,
+    #[builder(default)]
+    pub limit_policy: LimitPolicy,
+    #[builder(default)]
+    pub coverage_path: Option<PathBuf>,
+    #[builder(default)]
+    pub drcov_path: Option<PathBuf>,
+    #[builder(default)]
+    pub coverage_merge: bool,
+    #[builder(default)]
+    pub coverage_baseline: Option<PathBuf>,
+    #[builder(default)]
+    pub block_identity: Option<String>,
+    #[builder(default)]
+    pub trace_path: Option<PathBuf>,
+    #[builder(default)]
+    pub trace_shards: Option<PathBuf>,
+    #[builder(default)]
+    pub analysis_modules: Option<String>,
+    #[builder(default)]
+    pub analysis_only: bool,
+    #[builder(default)]
+    pub run_manifest: bool,
+    #[builder(default)]
+    pub run_manifest_path: Option<PathBuf>,
+    #[builder(default)]
+    pub run_binaries: Option<String>,
+    #[builder(default)]
+    pub aggregator: Option<String>,
+    #[builder(default)]
+    pub aggregator_name: Option<String>,
+    #[builder(default)]
+    pub bookmarks: Option<String>,
+    #[builder(default)]
+    pub bookmark_syscall: Option<i64>,
+    #[builder(default)]
+    pub guest_hypercalls: bool,
+    #[builder(default)]
+    pub utilization_interval: Option<u64>,
+    #[builder(default)]
+    pub clock_interval: Option<u64>,
+    #[builder(default)]
+    pub utilization_report: Option<PathBuf>,
+    #[builder(default)]
+    pub retranslation_report: Option<PathBuf>,
+    #[builder(default)]
+    pub retranslation_top: Option<usize>,
+    #[builder(default)]
+    pub symprof_report: Option<PathBuf>,
+    #[builder(default)]
+    pub symprof_weights: Option<String>,
+    #[builder(default)]
+    pub source_report: Option<PathBuf>,
+    #[builder(default)]
+    pub source_binary: Option<PathBuf>,
+    #[builder(default)]
+    pub demangle: Option<String>,
+    #[builder(default)]
+    pub symbolize_libraries: bool,
+    #[builder(default)]
+    pub absolute_pcs: bool,
+    #[builder(default)]
+    pub debuginfod_urls: Option<String>,
+    #[builder(default)]
+    pub debuginfod_cache: Option<PathBuf>,
+    #[builder(default)]
+    pub object_store_url: Option<String>,
+    #[builder(default)]
+    pub object_store_region: Option<String>,
+    #[builder(default)]
+    pub object_store_chunk_size: Option<usize>,
+    #[builder(default)]
+    pub memory_map: Option<PathBuf>,
+    #[builder(default)]
+    pub qmp_socket: Option<PathBuf>,
+    #[builder(default)]
+    pub fuzz_start: Option<String>,
+    #[builder(default)]
+    pub fuzz_end: Option<String>,
+    #[builder(default)]
+    pub fuzz_crashes: Option<String>,
+    #[builder(default)]
+    pub fuzz_input: Option<PathBuf>,
+    #[builder(default)]
+    pub fuzz_output: Option<PathBuf>,
+    #[builder(default)]
+    pub fuzz_corpus: Option<PathBuf>,
+    #[builder(default)]
+    pub fuzz_timeout: Option<u64>,
+    #[builder(default)]
+    pub fuzz_iterations: Option<u64>,
+    #[builder(default)]
+    pub fuzz_seed: u64,
+    #[builder(default)]
+    pub honggfuzz: bool,
+    #[builder(default)]
+    pub inject_path: Option<String>,
+    #[builder(default)]
+    pub inject_data: Option<PathBuf>,
+    #[builder(default)]
+    pub watchdog_budget: Option<u64>,
+    #[builder(default)]
+    pub watchdog_resets: Option<String>,
+    #[builder(default)]
+    pub watchdog_exit: bool,
+    #[builder(default)]
+    pub state_points: Option<String>,
+    #[builder(default)]
+    pub state_regions: Option<String>,
+    #[builder(default)]
+    pub state_registers: Option<String>,
+    #[builder(default)]
+    pub dump_dir: Option<PathBuf>,
+    #[builder(default)]
+    pub dump_ranges: Option<String>,
+    #[builder(default)]
+    pub dump_pc: Option<String>,
+    #[builder(default)]
+    pub dump_syscall: Option<i64>,
+    #[builder(default)]
+    pub dump_on_abort: bool,
+    #[builder(default)]
+    pub dump_limit: Option<usize>,
+    #[builder(default)]
+    pub sample_dir: Option<PathBuf>,
+    #[builder(default)]
+    pub sample_interval: Option<u64>,
+    #[builder(default)]
+    pub sample_limit: Option<usize>,
+    #[builder(default)]
+    pub vol_symbols: Option<PathBuf>,
+    #[builder(default)]
+    pub kernel_base: Option<String>,
+    #[builder(default)]
+    pub throttle: Option<String>,
+    #[builder(default)]
+    pub dedup_limit: Option<u64>,
+    #[builder(default)]
+    pub dedup_per_class: bool,
+    #[builder(default)]
+    pub coalesce_writes: Option<CoalesceMode>,
+    #[builder(default)]
+    pub track_heap: bool,
+    #[builder(default)]
+    pub heap_functions: Option<String>,
+    #[builder(default)]
+    pub heap_depth: Option<usize>,
+    #[builder(default)]
+    pub api_profiles: Option<String>,
+    #[builder(default)]
+    pub api_functions: Option<String>,
+    #[builder(default)]
+    pub detect_rop: bool,
+    #[builder(default)]
+    pub rop_return_weight: Option<u64>,
+    #[builder(default)]
+    pub rop_gadget_weight: Option<u64>,
+    #[builder(default)]
+    pub rop_decay: Option<u64>,
+    #[builder(default)]
+    pub rop_threshold: Option<u64>,
+    #[builder(default)]
+    pub rop_gadget_len: Option<usize>,
+    #[builder(default)]
+    pub shadow_stack: bool,
+    #[builder(default)]
+    pub shadow_stack_strict: bool,
+    #[builder(default)]
+    pub shadow_stack_allow: Option<String>,
+    #[builder(default)]
+    pub signatures: Option<PathBuf>,
+    #[builder(default)]
+    pub yara_rules: Option<PathBuf>,
+    #[builder(default)]
+    pub yara_ranges: Option<String>,
+    #[builder(default)]
+    pub yara_pcs: Option<String>,
+    #[builder(default)]
+    pub yara_syscall: Option<i64>,
+    #[builder(default)]
+    pub entropy_probes: Option<String>,
+    #[builder(default)]
+    pub detect_crypto: bool,
+    #[builder(default)]
+    pub crypto_threshold: Option<usize>,
+    #[builder(default)]
+    pub log_strings: bool,
+    #[builder(default)]
+    pub string_ranges: Option<String>,
+    #[builder(default)]
+    pub log_schedule: bool,
+    #[builder(default)]
+    pub log_isa_modes: bool,
+    #[builder(default)]
+    pub lock_report: Option<PathBuf>,
+    #[builder(default)]
+    pub lock_functions: Option<String>,
+    #[builder(default)]
+    pub lock_top: Option<usize>,
+    #[builder(default)]
+    pub detect_races: bool,
+    #[builder(default)]
+    pub exclusive_report: Option<PathBuf>,
+    #[builder(default)]
+    pub expectations: Option<PathBuf>,
+    #[builder(default)]
+    pub expectation_report: Option<PathBuf>,
+    #[builder(default)]
+    pub translation_log: Option<PathBuf>,
+    #[builder(default)]
+    pub detect_wx: bool,
+    #[builder(default)]
+    pub wx_report: Option<PathBuf>,
+    #[builder(default)]
+    pub crash_dir: Option<PathBuf>,
+    #[builder(default)]
+    pub crash_triggers: Option<String>,
+    #[builder(default)]
+    pub crash_stack: Option<usize>,
+    #[builder(default)]
+    pub crash_window: Option<usize>,
+    #[builder(default)]
+    pub crash_limit: Option<usize>,
+    #[builder(default)]
+    pub crash_minidump: bool,
+    #[builder(default)]
+    pub decision_log: Option<PathBuf>,
+    #[builder(default)]
+    pub section_filter: Option<String>,
+    #[builder(default)]
+    pub resolve_plt: bool,
+    #[builder(default)]
+    pub extension_report: Option<PathBuf>,
+}
 
-        #[cfg(feature = "plugin-api-v4")]
-        {
-            if let Some(read_sysno) = match self.target_name.as_deref() {
-                Some("i386") => Some(3),
-                Some("x86_64") => Some(0),
-                Some("arm") => Some(3),
-                Some("aarch64") => Some(63),
-                _ => None,
-            } {
-                if num == read_sysno {
-                    let addr = event.args[1];
-                    let len = event.args[2] as usize;
-                    let buffer = qemu_plugin_read_memory_vaddr(addr, len)?;
-                    event.buffers.insert(1, buffer);
-                }
-            }
+fn arg_bool(args: &Args, name: &str) -> bool {
+    args.parsed
+        .get(name)
+        .map(|v| if let Value::Bool(v) = v { *v } else { false })
+        .unwrap_or_default()
+}
+
+fn arg_int(args: &Args, name: &str) -> Option<i64> {
+    args.parsed.get(name).and_then(|v| {
+        if let Value::Integer(v) = v {
+            Some(*v)
+        } else {
+            None
         }
+    })
+}
 
-        // Update the return value
-        event.return_value = ret;
+fn arg_path(args: &Args, name: &str) -> Option<PathBuf> {
+    args.get_path(name)
+}
 
-        // Send the event
-        let tx = self
-            .tx
-            .lock()
-            .map_err(|e| anyhow!("Failed to lock tx: {e}"))?;
-        let tx_stream = tx.as_ref().ok_or_else(|| anyhow!("No tx"))?;
+/// Returns the raw value of an argument, for values such as hex addresses which are parsed
+/// later
+fn arg_string(args: &Args, name: &str) -> Option<String> {
+    args.get_os(name)
+        .and_then(|v| v.to_str())
+        .map(|v| v.to_string())
+}
 
-        to_writer(tx_stream, &Event::Syscall(event)).map_err(|e| anyhow!(e))?;
+fn arg_limit_policy(args: &Args) -> Result<LimitPolicy> {
+    match args.parsed.get("limit_policy") {
+        Some(Value::String(v)) => v.parse(),
+        _ => Ok(LimitPolicy::default()),
+    }
+}
 
-        Ok(())
+/// Stores are coalesced losslessly when `coalesce_writes` is only switched on
+fn arg_coalesce_mode(args: &Args) -> Result<Option<CoalesceMode>> {
+    match args.parsed.get("coalesce_writes") {
+        Some(Value::String(v)) => v.parse().map(Some),
+        Some(Value::Bool(true)) => Ok(Some(CoalesceMode::default())),
+        _ => Ok(None),
     }
 }
 
-#[derive(TypedBuilder, Clone, Debug)]
-pub struct PluginArgs {
-    pub log_insns: bool,
-    pub log_mem: bool,
-    pub log_syscalls: bool,
-    #[cfg(not(feature = "plugin-api-v1"))]
-    pub log_registers: bool,
-    pub socket_path: PathBuf,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// An optional analysis or output enabled by the plugin's arguments
+enum Analysis {
+    Instructions,
+    Memory,
+    Syscalls,
+    Console,
+    Start,
+    Pcs,
+    Stats,
+    Control,
+    Files,
+    Pcap,
+    Random,
+    Faults,
+    Peripherals,
+    Uarts,
+    Coverage,
+    CoverageBaseline,
+    TraceShards,
+    Modules,
+    RunManifest,
+    Aggregator,
+    Bookmarks,
+    Hypercalls,
+    Utilization,
+    Clock,
+    Retranslation,
+    SymbolProfile,
+    SourceProfile,
+    MemoryMap,
+    Fuzz,
+    Honggfuzz,
+    Inject,
+    Watchdog,
+    StatePoints,
+    Dumps,
+    Samples,
+    Throttle,
+    Dedup,
+    Coalesce,
+    Heap,
+    Api,
+    Rop,
+    ShadowStack,
+    Signatures,
+    Yara,
+    Probes,
+    Crypto,
+    Strings,
+    Schedule,
+    IsaModes,
+    Locks,
+    Races,
+    Exclusive,
+    Expectations,
+    Translations,
+    Wx,
+    Crashes,
+    Decisions,
+    SectionFilter,
+    Plt,
+    Extensions,
+}
+
+impl Analysis {
+    /// Whether the analysis stamps its events or reports with the instruction count, which
+    /// is only maintained when an enabled analysis needs it
+    fn needs_icount(self) -> bool {
+        match self {
+            Self::Console
+            | Self::Start
+            | Self::Stats
+            | Self::Control
+            | Self::Random
+            | Self::Faults
+            | Self::Peripherals
+            | Self::Uarts
+            | Self::CoverageBaseline
+            | Self::TraceShards
+            | Self::RunManifest
+            | Self::Bookmarks
+            | Self::Hypercalls
+            | Self::Utilization
+            | Self::Clock
+            | Self::Retranslation
+            | Self::StatePoints
+            | Self::Dumps
+            | Self::Samples
+            | Self::Throttle
+            | Self::Heap
+            | Self::Api
+            | Self::Rop
+            | Self::ShadowStack
+            | Self::Signatures
+            | Self::Yara
+            | Self::Probes
+            | Self::Crypto
+            | Self::Strings
+            | Self::Schedule
+            | Self::IsaModes
+            | Self::Locks
+            | Self::Races
+            | Self::Expectations
+            | Self::Translations
+            | Self::Wx
+            | Self::Crashes => true,
+            Self::Instructions
+            | Self::Memory
+            | Self::Syscalls
+            | Self::Pcs
+            | Self::Files
+            | Self::Pcap
+            | Self::Coverage
+            | Self::Modules
+            | Self::Aggregator
+            | Self::SymbolProfile
+            | Self::SourceProfile
+            | Self::MemoryMap
+            | Self::Fuzz
+            | Self::Honggfuzz
+            | Self::Inject
+            | Self::Watchdog
+            | Self::Dedup
+            | Self::Coalesce
+            | Self::Exclusive
+            | Self::Decisions
+            | Self::SectionFilter
+            | Self::Plt
+            | Self::Extensions => false,
+        }
+    }
+}
+
+impl PluginArgs {
+    /// The analyses enabled by the arguments
+    fn analyses(&self) -> Vec<Analysis> {
+        [
+            (self.log_insns, Analysis::Instructions),
+            (self.log_mem, Analysis::Memory),
+            (self.log_syscalls, Analysis::Syscalls),
+            (self.log_console, Analysis::Console),
+            (self.log_start, Analysis::Start),
+            (self.log_pcs, Analysis::Pcs),
+            (self.stats_path.is_some(), Analysis::Stats),
+            (
+                self.control_path.is_some() || self.control_address.is_some(),
+                Analysis::Control,
+            ),
+            (self.file_report.is_some(), Analysis::Files),
+            (self.pcap_path.is_some(), Analysis::Pcap),
+            (self.log_random, Analysis::Random),
+            (self.fault_rules.is_some(), Analysis::Faults),
+            (self.periph_path.is_some(), Analysis::Peripherals),
+            (self.uarts.is_some(), Analysis::Uarts),
+            (self.coverage_path.is_some(), Analysis::Coverage),
+            (self.drcov_path.is_some(), Analysis::Coverage),
+            (self.coverage_baseline.is_some(), Analysis::CoverageBaseline),
+            (self.trace_shards.is_some(), Analysis::TraceShards),
+            (self.analysis_modules.is_some(), Analysis::Modules),
+            (self.run_manifest, Analysis::RunManifest),
+            (self.aggregator.is_some(), Analysis::Aggregator),
+            (self.bookmarks.is_some(), Analysis::Bookmarks),
+            (self.bookmark_syscall.is_some(), Analysis::Bookmarks),
+            (self.guest_hypercalls, Analysis::Hypercalls),
+            (self.utilization_interval.is_some(), Analysis::Utilization),
+            (self.utilization_report.is_some(), Analysis::Utilization),
+            (self.clock_interval.is_some(), Analysis::Clock),
+            (self.retranslation_report.is_some(), Analysis::Retranslation),
+            (self.symprof_report.is_some(), Analysis::SymbolProfile),
+            (self.source_report.is_some(), Analysis::SourceProfile),
+            (self.memory_map.is_some(), Analysis::MemoryMap),
+            (self.fuzz_start.is_some(), Analysis::Fuzz),
+            (self.honggfuzz, Analysis::Honggfuzz),
+            (self.inject_path.is_some(), Analysis::Inject),
+            (self.watchdog_budget.is_some(), Analysis::Watchdog),
+            (self.state_points.is_some(), Analysis::StatePoints),
+            (self.dump_dir.is_some(), Analysis::Dumps),
+            (self.sample_dir.is_some(), Analysis::Samples),
+            (self.throttle.is_some(), Analysis::Throttle),
+            (self.dedup_limit.is_some(), Analysis::Dedup),
+            (self.coalesce_writes.is_some(), Analysis::Coalesce),
+            (self.track_heap, Analysis::Heap),
+            (self.api_profiles.is_some(), Analysis::Api),
+            (self.detect_rop, Analysis::Rop),
+            (self.shadow_stack, Analysis::ShadowStack),
+            (self.signatures.is_some(), Analysis::Signatures),
+            (self.yara_rules.is_some(), Analysis::Yara),
+            (self.entropy_probes.is_some(), Analysis::Probes),
+            (self.detect_crypto, Analysis::Crypto),
+            (self.log_strings, Analysis::Strings),
+            (self.log_schedule, Analysis::Schedule),
+            (self.log_isa_modes, Analysis::IsaModes),
+            (self.lock_report.is_some(), Analysis::Locks),
+            (self.detect_races, Analysis::Races),
+            (self.exclusive_report.is_some(), Analysis::Exclusive),
+            (self.expectations.is_some(), Analysis::Expectations),
+            (self.translation_log.is_some(), Analysis::Translations),
+            (self.detect_wx, Analysis::Wx),
+            (self.wx_report.is_some(), Analysis::Wx),
+            (self.crash_dir.is_some(), Analysis::Crashes),
+            (self.decision_log.is_some(), Analysis::Decisions),
+            (self.section_filter.is_some(), Analysis::SectionFilter),
+            (self.resolve_plt, Analysis::Plt),
+            (self.extension_report.is_some(), Analysis::Extensions),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, analysis)| enabled.then_some(analysis))
+        .collect()
+    }
 }
 
 impl TryFrom<&Args> for PluginArgs {
@@ -430,19 +4907,143 @@ impl TryFrom<&Args> for PluginArgs {
                         .map(|ls| if let Value::Bool(v) = ls { *v } else { false })
                         .unwrap_or_default(),
                 )
-                .socket_path(
-                    value
-                        .parsed
-                        .get("socket_path")
-                        .and_then(|sp| {
-                            if let Value::String(v) = sp {
-                                Some(PathBuf::from(v))
-                            } else {
-                                None
-                            }
-                        })
-                        .ok_or_else(|| anyhow!("No socket path provided"))?,
+                .socket_path(arg_path(value, "socket_path"))
+                .log_console(arg_bool(value, "log_console"))
+                .log_start(arg_bool(value, "log_start"))
+                .log_pcs(arg_bool(value, "log_pcs"))
+                .log_mem_values(arg_bool(value, "log_mem_values"))
+                .stats_path(arg_path(value, "stats_path"))
+                .control_path(arg_path(value, "control_path"))
+                .control_address(arg_string(value, "control_address"))
+                .file_report(arg_path(value, "file_report"))
+                .hash_files(arg_bool(value, "hash_files"))
+                .pcap_path(arg_path(value, "pcap_path"))
+                .log_random(arg_bool(value, "log_random"))
+                .seeded(arg_bool(value, "seeded"))
+                .fault_rules(arg_path(value, "fault_rules"))
+                .periph_path(arg_path(value, "periph_path"))
+                .uarts(arg_string(value, "uarts"))
+                .fault_seed(arg_int(value, "fault_seed").unwrap_or_default() as u64)
+                .max_files(arg_int(value, "max_files").map(|v| v as usize))
+                .max_ranges(arg_int(value, "max_ranges").map(|v| v as usize))
+                .limit_policy(arg_limit_policy(value)?)
+                .coverage_path(arg_path(value, "coverage_path"))
+                .drcov_path(arg_path(value, "drcov_path"))
+                .coverage_merge(arg_bool(value, "coverage_merge"))
+                .coverage_baseline(arg_path(value, "coverage_baseline"))
+                .block_identity(arg_string(value, "block_identity"))
+                .trace_path(arg_path(value, "trace_path"))
+                .trace_shards(arg_path(value, "trace_shards"))
+                .analysis_modules(arg_string(value, "analysis_modules"))
+                .analysis_only(arg_bool(value, "analysis_only"))
+                .run_manifest(arg_bool(value, "run_manifest"))
+                .run_manifest_path(arg_path(value, "run_manifest_path"))
+                .run_binaries(arg_string(value, "run_binaries"))
+                .aggregator(arg_string(value, "aggregator"))
+                .aggregator_name(arg_string(value, "aggregator_name"))
+                .bookmarks(arg_string(value, "bookmarks"))
+                .bookmark_syscall(arg_int(value, "bookmark_syscall"))
+                .guest_hypercalls(arg_bool(value, "guest_hypercalls"))
+                .utilization_interval(arg_int(value, "utilization_interval").map(|v| v as u64))
+                .clock_interval(arg_int(value, "clock_interval").map(|v| v as u64))
+                .utilization_report(arg_path(value, "utilization_report"))
+                .retranslation_report(arg_path(value, "retranslation_report"))
+                .retranslation_top(arg_int(value, "retranslation_top").map(|v| v as usize))
+                .symprof_report(arg_path(value, "symprof_report"))
+                .symprof_weights(arg_string(value, "symprof_weights"))
+                .source_report(arg_path(value, "source_report"))
+                .source_binary(arg_path(value, "source_binary"))
+                .demangle(arg_string(value, "demangle"))
+                .symbolize_libraries(arg_bool(value, "symbolize_libraries"))
+                .absolute_pcs(arg_bool(value, "absolute_pcs"))
+                .debuginfod_urls(arg_string(value, "debuginfod_urls"))
+                .debuginfod_cache(arg_path(value, "debuginfod_cache"))
+                .object_store_url(arg_string(value, "object_store_url"))
+                .object_store_region(arg_string(value, "object_store_region"))
+                .object_store_chunk_size(
+                    arg_int(value, "object_store_chunk_size").map(|v| v as usize),
                 )
+                .memory_map(arg_path(value, "memory_map"))
+                .qmp_socket(arg_path(value, "qmp_socket"))
+                .fuzz_start(arg_string(value, "fuzz_start"))
+                .fuzz_end(arg_string(value, "fuzz_end"))
+                .fuzz_crashes(arg_string(value, "fuzz_crashes"))
+                .fuzz_input(arg_path(value, "fuzz_input"))
+                .fuzz_output(arg_path(value, "fuzz_output"))
+                .fuzz_corpus(arg_path(value, "fuzz_corpus"))
+                .fuzz_timeout(arg_int(value, "fuzz_timeout").map(|v| v as u64))
+                .fuzz_iterations(arg_int(value, "fuzz_iterations").map(|v| v as u64))
+                .fuzz_seed(arg_int(value, "fuzz_seed").unwrap_or_default() as u64)
+                .honggfuzz(arg_bool(value, "honggfuzz"))
+                .inject_path(arg_string(value, "inject_path"))
+                .inject_data(arg_path(value, "inject_data"))
+                .watchdog_budget(arg_int(value, "watchdog_budget").map(|v| v as u64))
+                .watchdog_resets(arg_string(value, "watchdog_resets"))
+                .watchdog_exit(arg_bool(value, "watchdog_exit"))
+                .state_points(arg_string(value, "state_points"))
+                .state_regions(arg_string(value, "state_regions"))
+                .state_registers(arg_string(value, "state_registers"))
+                .dump_dir(arg_path(value, "dump_dir"))
+                .dump_ranges(arg_string(value, "dump_ranges"))
+                .dump_pc(arg_string(value, "dump_pc"))
+                .dump_syscall(arg_int(value, "dump_syscall"))
+                .dump_on_abort(arg_bool(value, "dump_on_abort"))
+                .dump_limit(arg_int(value, "dump_limit").map(|v| v as usize))
+                .sample_dir(arg_path(value, "sample_dir"))
+                .sample_interval(arg_int(value, "sample_interval").map(|v| v as u64))
+                .sample_limit(arg_int(value, "sample_limit").map(|v| v as usize))
+                .vol_symbols(arg_path(value, "vol_symbols"))
+                .kernel_base(arg_string(value, "kernel_base"))
+                .throttle(arg_string(value, "throttle"))
+                .dedup_limit(arg_int(value, "dedup_limit").map(|v| v as u64))
+                .dedup_per_class(arg_bool(value, "dedup_per_class"))
+                .coalesce_writes(arg_coalesce_mode(value)?)
+                .track_heap(arg_bool(value, "track_heap"))
+                .heap_functions(arg_string(value, "heap_functions"))
+                .heap_depth(arg_int(value, "heap_depth").map(|v| v as usize))
+                .api_profiles(arg_string(value, "api_profiles"))
+                .api_functions(arg_string(value, "api_functions"))
+                .detect_rop(arg_bool(value, "detect_rop"))
+                .rop_return_weight(arg_int(value, "rop_return_weight").map(|v| v as u64))
+                .rop_gadget_weight(arg_int(value, "rop_gadget_weight").map(|v| v as u64))
+                .rop_decay(arg_int(value, "rop_decay").map(|v| v as u64))
+                .rop_threshold(arg_int(value, "rop_threshold").map(|v| v as u64))
+                .rop_gadget_len(arg_int(value, "rop_gadget_len").map(|v| v as usize))
+                .shadow_stack(arg_bool(value, "shadow_stack"))
+                .shadow_stack_strict(arg_bool(value, "shadow_stack_strict"))
+                .shadow_stack_allow(arg_string(value, "shadow_stack_allow"))
+                .signatures(arg_path(value, "signatures"))
+                .yara_rules(arg_path(value, "yara_rules"))
+                .yara_ranges(arg_string(value, "yara_ranges"))
+                .yara_pcs(arg_string(value, "yara_pcs"))
+                .yara_syscall(arg_int(value, "yara_syscall"))
+                .entropy_probes(arg_string(value, "entropy_probes"))
+                .detect_crypto(arg_bool(value, "detect_crypto"))
+                .crypto_threshold(arg_int(value, "crypto_threshold").map(|v| v as usize))
+                .log_strings(arg_bool(value, "log_strings"))
+                .string_ranges(arg_string(value, "string_ranges"))
+                .log_schedule(arg_bool(value, "log_schedule"))
+                .log_isa_modes(arg_bool(value, "log_isa_modes"))
+                .lock_report(arg_path(value, "lock_report"))
+                .lock_functions(arg_string(value, "lock_functions"))
+                .lock_top(arg_int(value, "lock_top").map(|v| v as usize))
+                .detect_races(arg_bool(value, "detect_races"))
+                .exclusive_report(arg_path(value, "exclusive_report"))
+                .expectations(arg_path(value, "expectations"))
+                .expectation_report(arg_path(value, "expectation_report"))
+                .translation_log(arg_path(value, "translation_log"))
+                .detect_wx(arg_bool(value, "detect_wx"))
+                .wx_report(arg_path(value, "wx_report"))
+                .crash_dir(arg_path(value, "crash_dir"))
+                .crash_triggers(arg_string(value, "crash_triggers"))
+                .crash_stack(arg_int(value, "crash_stack").map(|v| v as usize))
+                .crash_window(arg_int(value, "crash_window").map(|v| v as usize))
+                .crash_limit(arg_int(value, "crash_limit").map(|v| v as usize))
+                .crash_minidump(arg_bool(value, "crash_minidump"))
+                .decision_log(arg_path(value, "decision_log"))
+                .section_filter(arg_string(value, "section_filter"))
+                .resolve_plt(arg_bool(value, "resolve_plt"))
+                .extension_report(arg_path(value, "extension_report"))
                 .build())
         }
         #[cfg(not(feature = "plugin-api-v1"))]
@@ -476,33 +5077,293 @@ impl TryFrom<&Args> for PluginArgs {
                         .map(|lr| if let Value::Bool(v) = lr { *v } else { false })
                         .unwrap_or_default(),
                 )
-                .socket_path(
-                    value
-                        .parsed
-                        .get("socket_path")
-                        .and_then(|sp| {
-                            if let Value::String(v) = sp {
-                                Some(PathBuf::from(v))
-                            } else {
-                                None
-                            }
-                        })
-                        .ok_or_else(|| anyhow!("No socket path provided"))?,
+                .socket_path(arg_path(value, "socket_path"))
+                .log_console(arg_bool(value, "log_console"))
+                .log_start(arg_bool(value, "log_start"))
+                .log_pcs(arg_bool(value, "log_pcs"))
+                .log_mem_values(arg_bool(value, "log_mem_values"))
+                .stats_path(arg_path(value, "stats_path"))
+                .control_path(arg_path(value, "control_path"))
+                .control_address(arg_string(value, "control_address"))
+                .file_report(arg_path(value, "file_report"))
+                .hash_files(arg_bool(value, "hash_files"))
+                .pcap_path(arg_path(value, "pcap_path"))
+                .log_random(arg_bool(value, "log_random"))
+                .seeded(arg_bool(value, "seeded"))
+                .fault_rules(arg_path(value, "fault_rules"))
+                .periph_path(arg_path(value, "periph_path"))
+                .uarts(arg_string(value, "uarts"))
+                .fault_seed(arg_int(value, "fault_seed").unwrap_or_default() as u64)
+                .max_files(arg_int(value, "max_files").map(|v| v as usize))
+                .max_ranges(arg_int(value, "max_ranges").map(|v| v as usize))
+                .limit_policy(arg_limit_policy(value)?)
+                .coverage_path(arg_path(value, "coverage_path"))
+                .drcov_path(arg_path(value, "drcov_path"))
+                .coverage_merge(arg_bool(value, "coverage_merge"))
+                .coverage_baseline(arg_path(value, "coverage_baseline"))
+                .block_identity(arg_string(value, "block_identity"))
+                .trace_path(arg_path(value, "trace_path"))
+                .trace_shards(arg_path(value, "trace_shards"))
+                .analysis_modules(arg_string(value, "analysis_modules"))
+                .analysis_only(arg_bool(value, "analysis_only"))
+                .run_manifest(arg_bool(value, "run_manifest"))
+                .run_manifest_path(arg_path(value, "run_manifest_path"))
+                .run_binaries(arg_string(value, "run_binaries"))
+                .aggregator(arg_string(value, "aggregator"))
+                .aggregator_name(arg_string(value, "aggregator_name"))
+                .bookmarks(arg_string(value, "bookmarks"))
+                .bookmark_syscall(arg_int(value, "bookmark_syscall"))
+                .guest_hypercalls(arg_bool(value, "guest_hypercalls"))
+                .utilization_interval(arg_int(value, "utilization_interval").map(|v| v as u64))
+                .clock_interval(arg_int(value, "clock_interval").map(|v| v as u64))
+                .utilization_report(arg_path(value, "utilization_report"))
+                .retranslation_report(arg_path(value, "retranslation_report"))
+                .retranslation_top(arg_int(value, "retranslation_top").map(|v| v as usize))
+                .symprof_report(arg_path(value, "symprof_report"))
+                .symprof_weights(arg_string(value, "symprof_weights"))
+                .source_report(arg_path(value, "source_report"))
+                .source_binary(arg_path(value, "source_binary"))
+                .demangle(arg_string(value, "demangle"))
+                .symbolize_libraries(arg_bool(value, "symbolize_libraries"))
+                .absolute_pcs(arg_bool(value, "absolute_pcs"))
+                .debuginfod_urls(arg_string(value, "debuginfod_urls"))
+                .debuginfod_cache(arg_path(value, "debuginfod_cache"))
+                .object_store_url(arg_string(value, "object_store_url"))
+                .object_store_region(arg_string(value, "object_store_region"))
+                .object_store_chunk_size(
+                    arg_int(value, "object_store_chunk_size").map(|v| v as usize),
                 )
+                .memory_map(arg_path(value, "memory_map"))
+                .qmp_socket(arg_path(value, "qmp_socket"))
+                .fuzz_start(arg_string(value, "fuzz_start"))
+                .fuzz_end(arg_string(value, "fuzz_end"))
+                .fuzz_crashes(arg_string(value, "fuzz_crashes"))
+                .fuzz_input(arg_path(value, "fuzz_input"))
+                .fuzz_output(arg_path(value, "fuzz_output"))
+                .fuzz_corpus(arg_path(value, "fuzz_corpus"))
+                .fuzz_timeout(arg_int(value, "fuzz_timeout").map(|v| v as u64))
+                .fuzz_iterations(arg_int(value, "fuzz_iterations").map(|v| v as u64))
+                .fuzz_seed(arg_int(value, "fuzz_seed").unwrap_or_default() as u64)
+                .honggfuzz(arg_bool(value, "honggfuzz"))
+                .inject_path(arg_string(value, "inject_path"))
+                .inject_data(arg_path(value, "inject_data"))
+                .watchdog_budget(arg_int(value, "watchdog_budget").map(|v| v as u64))
+                .watchdog_resets(arg_string(value, "watchdog_resets"))
+                .watchdog_exit(arg_bool(value, "watchdog_exit"))
+                .state_points(arg_string(value, "state_points"))
+                .state_regions(arg_string(value, "state_regions"))
+                .state_registers(arg_string(value, "state_registers"))
+                .dump_dir(arg_path(value, "dump_dir"))
+                .dump_ranges(arg_string(value, "dump_ranges"))
+                .dump_pc(arg_string(value, "dump_pc"))
+                .dump_syscall(arg_int(value, "dump_syscall"))
+                .dump_on_abort(arg_bool(value, "dump_on_abort"))
+                .dump_limit(arg_int(value, "dump_limit").map(|v| v as usize))
+                .sample_dir(arg_path(value, "sample_dir"))
+                .sample_interval(arg_int(value, "sample_interval").map(|v| v as u64))
+                .sample_limit(arg_int(value, "sample_limit").map(|v| v as usize))
+                .vol_symbols(arg_path(value, "vol_symbols"))
+                .kernel_base(arg_string(value, "kernel_base"))
+                .throttle(arg_string(value, "throttle"))
+                .dedup_limit(arg_int(value, "dedup_limit").map(|v| v as u64))
+                .dedup_per_class(arg_bool(value, "dedup_per_class"))
+                .coalesce_writes(arg_coalesce_mode(value)?)
+                .track_heap(arg_bool(value, "track_heap"))
+                .heap_functions(arg_string(value, "heap_functions"))
+                .heap_depth(arg_int(value, "heap_depth").map(|v| v as usize))
+                .api_profiles(arg_string(value, "api_profiles"))
+                .api_functions(arg_string(value, "api_functions"))
+                .detect_rop(arg_bool(value, "detect_rop"))
+                .rop_return_weight(arg_int(value, "rop_return_weight").map(|v| v as u64))
+                .rop_gadget_weight(arg_int(value, "rop_gadget_weight").map(|v| v as u64))
+                .rop_decay(arg_int(value, "rop_decay").map(|v| v as u64))
+                .rop_threshold(arg_int(value, "rop_threshold").map(|v| v as u64))
+                .rop_gadget_len(arg_int(value, "rop_gadget_len").map(|v| v as usize))
+                .shadow_stack(arg_bool(value, "shadow_stack"))
+                .shadow_stack_strict(arg_bool(value, "shadow_stack_strict"))
+                .shadow_stack_allow(arg_string(value, "shadow_stack_allow"))
+                .signatures(arg_path(value, "signatures"))
+                .yara_rules(arg_path(value, "yara_rules"))
+                .yara_ranges(arg_string(value, "yara_ranges"))
+                .yara_pcs(arg_string(value, "yara_pcs"))
+                .yara_syscall(arg_int(value, "yara_syscall"))
+                .entropy_probes(arg_string(value, "entropy_probes"))
+                .detect_crypto(arg_bool(value, "detect_crypto"))
+                .crypto_threshold(arg_int(value, "crypto_threshold").map(|v| v as usize))
+                .log_strings(arg_bool(value, "log_strings"))
+                .string_ranges(arg_string(value, "string_ranges"))
+                .log_schedule(arg_bool(value, "log_schedule"))
+                .log_isa_modes(arg_bool(value, "log_isa_modes"))
+                .lock_report(arg_path(value, "lock_report"))
+                .lock_functions(arg_string(value, "lock_functions"))
+                .lock_top(arg_int(value, "lock_top").map(|v| v as usize))
+                .detect_races(arg_bool(value, "detect_races"))
+                .exclusive_report(arg_path(value, "exclusive_report"))
+                .expectations(arg_path(value, "expectations"))
+                .expectation_report(arg_path(value, "expectation_report"))
+                .translation_log(arg_path(value, "translation_log"))
+                .detect_wx(arg_bool(value, "detect_wx"))
+                .wx_report(arg_path(value, "wx_report"))
+                .crash_dir(arg_path(value, "crash_dir"))
+                .crash_triggers(arg_string(value, "crash_triggers"))
+                .crash_stack(arg_int(value, "crash_stack").map(|v| v as usize))
+                .crash_window(arg_int(value, "crash_window").map(|v| v as usize))
+                .crash_limit(arg_int(value, "crash_limit").map(|v| v as usize))
+                .crash_minidump(arg_bool(value, "crash_minidump"))
+                .decision_log(arg_path(value, "decision_log"))
+                .section_filter(arg_string(value, "section_filter"))
+                .resolve_plt(arg_bool(value, "resolve_plt"))
+                .extension_report(arg_path(value, "extension_report"))
                 .build())
         }
     }
 }
 
 impl Register for Tracer {
-    fn register(&mut self, _: PluginId, args: &Args, info: &Info) -> Result<()> {
+    fn register(&mut self, id: PluginId, args: &Args, info: &Info) -> Result<()> {
+        let args = &self.preset.apply(args);
+
+        if arg_bool(args, "check") {
+            let report = CheckReport::run(args, info);
+            qemu_plugin_outs(serde_json::to_string_pretty(&report)? + "\n")?;
+            // QEMU does not allow a plugin to uninstall itself during install
+            self.checking = true;
+            return Ok(());
+        }
+
         let plugin_args = PluginArgs::try_from(args)?;
 
         self.target_name = Some(info.target_name.clone());
+        self.arch = Arch::from_target_name(&info.target_name);
+        self.cpu = TargetInfo::from(info).cpu();
+        self.decoder = Decoder::new(self.arch, &self.cpu);
+
+        let throttle = plugin_args
+            .throttle
+            .as_deref()
+            .map(Throttle::parse)
+            .transpose()?;
+
+        let analyses = plugin_args
+            .analysis_modules
+            .as_deref()
+            .map(|modules| Analyses::load(modules, &args.raw.join(",")))
+            .transpose()?;
+
+        let relocator = (!plugin_args.absolute_pcs).then(|| Arc::new(Relocator::default()));
+
+        #[cfg(feature = "plugin-api-v4")]
+        let window = plugin_args
+            .crash_dir
+            .is_some()
+            .then(|| Arc::new(TraceWindow::new(plugin_args.crash_window.unwrap_or(1000))));
+
+        if let (Some(relocator), Some(path), Some(start), Some(end)) = (
+            relocator.as_ref(),
+            qemu_plugin_path_to_binary()?,
+            qemu_plugin_start_code(),
+            qemu_plugin_end_code(),
+        ) {
+            // A program the reader cannot parse is recorded at the addresses it ran at
+            if let Ok(module) = ModuleMap::load(&path, Some(start)) {
+                relocator.add(LoadedModule {
+                    name: module.name,
+                    start,
+                    end,
+                    bias: module.bias,
+                })?;
+            }
+        }
 
-        self.tx = Arc::new(Mutex::new(Some(UnixStream::connect(
-            plugin_args.socket_path,
-        )?)));
+        self.tx = Arc::new(match plugin_args.trace_shards.as_ref() {
+            Some(trace_shards) => Output {
+                sink: Mutex::new(None),
+                shards: Some(ShardedTraceFile::create(trace_shards)?),
+                throttle,
+                analyses,
+                relocator,
+                #[cfg(feature = "plugin-api-v4")]
+                window: window.clone(),
+            },
+            None if plugin_args.analysis_only => Output {
+                sink: Mutex::new(None),
+                shards: None,
+                throttle,
+                analyses,
+                relocator,
+                #[cfg(feature = "plugin-api-v4")]
+                window: window.clone(),
+            },
+            None => Output {
+                sink: Mutex::new(Some(match plugin_args.trace_path.as_ref() {
+                    Some(trace_path) => Sink::File(TraceFile::create(trace_path)?),
+                    None => match (
+                        plugin_args.aggregator.as_deref(),
+                        plugin_args.object_store_url.as_deref(),
+                    ) {
+                        (Some(aggregator), _) => Sink::Aggregator(aggregate::connect(
+                            aggregator,
+                            plugin_args.aggregator_name.clone(),
+                        )?),
+                        #[cfg(feature = "object-store")]
+                        (None, Some(url)) => Sink::Object(ObjectSink::create(
+                            url,
+                            plugin_args
+                                .object_store_region
+                                .as_deref()
+                                .unwrap_or("us-east-1"),
+                            plugin_args
+                                .object_store_chunk_size
+                                .unwrap_or(object_store::CHUNK_SIZE),
+                        )?),
+                        _ => Sink::Socket(UnixStream::connect(
+                            plugin_args
+                                .socket_path
+                                .as_ref()
+                                .ok_or_else(|| anyhow!("No socket path provided"))?,
+                        )?),
+                    },
+                })),
+                shards: None,
+                throttle,
+                analyses,
+                relocator,
+                #[cfg(feature = "plugin-api-v4")]
+                window: window.clone(),
+            },
+        });
+
+        if plugin_args.run_manifest {
+            let sent = plugin_args.aggregator.is_some()
+                || (cfg!(feature = "object-store") && plugin_args.object_store_url.is_some());
+
+            self.run_manifest_path = match (
+                plugin_args.run_manifest_path.as_ref(),
+                plugin_args.trace_shards.as_ref(),
+                plugin_args.trace_path.as_ref(),
+            ) {
+                (Some(run_manifest_path), _, _) => Some(run_manifest_path.clone()),
+                (None, Some(trace_shards), _) => Some(trace_shards.join(RUN_MANIFEST)),
+                (None, None, Some(trace_path)) => Some(trace_path.with_extension("run.json")),
+                _ if sent => None,
+                _ => Some(PathBuf::from(RUN_MANIFEST)),
+            };
+
+            let binaries = plugin_args
+                .run_binaries
+                .as_deref()
+                .map(|b| {
+                    b.split(';')
+                        .filter(|b| !b.is_empty())
+                        .map(PathBuf::from)
+                        .collect()
+                })
+                .unwrap_or_else(Vec::new);
+            let run_manifest = RunManifest::new(args, info, &binaries, self.stats.snapshot()?)?;
+
+            self.write_run_manifest(&run_manifest)?;
+            self.run_manifest = Some(Arc::new(Mutex::new(run_manifest)));
+        }
 
         self.log_insns = plugin_args.log_insns;
         self.log_mem = plugin_args.log_mem;
@@ -513,6 +5374,681 @@ impl Register for Tracer {
             self.log_registers = plugin_args.log_registers;
         }
 
+        self.log_console = plugin_args.log_console;
+        self.log_start = plugin_args.log_start;
+        self.log_pcs = plugin_args.log_pcs;
+        self.dedup = plugin_args
+            .dedup_limit
+            .map(|limit| Arc::new(Dedup::new(limit, plugin_args.dedup_per_class)));
+        self.coalescer = plugin_args
+            .coalesce_writes
+            .map(|mode| Arc::new(Mutex::new(WriteCoalescer::new(mode))));
+
+        if plugin_args.bookmarks.is_some() || plugin_args.bookmark_syscall.is_some() {
+            self.bookmarks = Some(Arc::new(Bookmarks::new(
+                plugin_args.bookmarks.as_deref(),
+                plugin_args.bookmark_syscall,
+            )?));
+        }
+
+        let block_identity = plugin_args
+            .block_identity
+            .as_deref()
+            .map(str::parse::<BlockIdentity>)
+            .transpose()?
+            .unwrap_or_default();
+
+        self.block_identity = block_identity;
+
+        if plugin_args.coverage_path.is_some()
+            || plugin_args.drcov_path.is_some()
+            || plugin_args.coverage_baseline.is_some()
+            || plugin_args.aggregator.is_some()
+        {
+            let coverage = CoverageTracker::new(program_modules()?).with_identity(block_identity);
+            let coverage = match plugin_args.coverage_baseline.as_ref() {
+                Some(baseline) => {
+                    coverage.with_baseline(CoverageSet::read(baseline).map_err(|e| {
+                        anyhow!("Failed to read baseline {}: {e}", baseline.display())
+                    })?)?
+                }
+                None => coverage,
+            };
+            let coverage = if plugin_args.drcov_path.is_some() {
+                coverage.with_sizes()
+            } else {
+                coverage
+            };
+
+            self.coverage = Some(Arc::new(Mutex::new(coverage)));
+            self.coverage_path = plugin_args.coverage_path.clone();
+            self.drcov_path = plugin_args.drcov_path.clone();
+            self.coverage_merge = plugin_args.coverage_merge;
+        }
+
+        if let Some(memory_map_path) = plugin_args.memory_map.as_ref() {
+            let memory_map = Arc::new(Mutex::new(MemoryMap::default()));
+
+            // QMP is served from QEMU's main loop, which does not run until installation
+            // finishes, so the query happens on a thread of its own
+            if let Some(qmp_socket) = plugin_args.qmp_socket.clone() {
+                let memory_map = memory_map.clone();
+
+                spawn(move || match MemoryMap::query(&qmp_socket) {
+                    Ok(queried) => match memory_map.lock() {
+                        Ok(mut memory_map) => *memory_map = queried,
+                        Err(e) => log(format!("Failed to lock memory map: {e}")),
+                    },
+                    Err(e) => log(format!("Failed to query memory map over QMP: {e}")),
+                });
+            }
+
+            self.memory_map = Some(memory_map);
+            self.memory_map_path = Some(memory_map_path.clone());
+        }
+
+        if let Some(translation_log) = plugin_args.translation_log.as_ref() {
+            if info.system.is_none() {
+                return Err(anyhow!("translation_log needs system mode"));
+            }
+
+            self.translations = Some(Arc::new(Mutex::new(TranslationTracker::default())));
+            self.translation_log = Some(translation_log.clone());
+        }
+
+        if let Some(decision_log) = plugin_args.decision_log.as_ref() {
+            self.decisions = Some(Arc::new(DecisionLogger::create(decision_log)?));
+        }
+
+        if let Some(section_filter) = plugin_args.section_filter.as_deref() {
+            if info.system.is_some() {
+                return Err(anyhow!("section_filter needs user mode"));
+            }
+
+            let mut filter = SectionFilter::parse(section_filter)?;
+
+            if let Some(path) = qemu_plugin_path_to_binary()? {
+                filter.add_module(ModuleMap::load(path, qemu_plugin_start_code())?);
+            }
+
+            self.section_filter = Some(Arc::new(Mutex::new(filter)));
+        }
+
+        if let Some(sample_dir) = plugin_args.sample_dir.as_ref() {
+            let qmp_socket = plugin_args
+                .qmp_socket
+                .clone()
+                .ok_or_else(|| anyhow!("Memory sampling requires a QMP socket"))?;
+
+            sampler::sample_periodically(
+                SampleConfig::builder()
+                    .dir(sample_dir.clone())
+                    .qmp_socket(qmp_socket)
+                    .interval(Duration::from_secs(
+                        plugin_args.sample_interval.unwrap_or(60),
+                    ))
+                    .limit(plugin_args.sample_limit)
+                    .symbols(plugin_args.vol_symbols.clone())
+                    .kernel_base(
+                        plugin_args
+                            .kernel_base
+                            .as_deref()
+                            .map(memmap::parse_addr)
+                            .transpose()?,
+                    )
+                    .target_name(self.target_name.clone())
+                    .cpu_model(self.cpu.model.clone())
+                    .build(),
+                self.stats.clone(),
+            )?;
+        }
+
+        if let Some(utilization_interval) = plugin_args.utilization_interval {
+            let tx = self.tx.clone();
+            let stats = self.stats.clone();
+
+            utilization::sample_periodically(
+                self.stats.clone(),
+                Duration::from_millis(utilization_interval),
+                move |event| send_event(&tx, &stats, event.vcpu_index, &Event::Utilization(event)),
+            )?;
+        }
+
+        self.utilization_report = plugin_args.utilization_report.clone();
+
+        if let Some(clock_interval) = plugin_args.clock_interval {
+            let tx = self.tx.clone();
+            let stats = self.stats.clone();
+            let icount = self.stats.clone();
+
+            clock::sample_periodically(
+                Duration::from_millis(clock_interval),
+                move || icount.icount(),
+                move |event| send_event(&tx, &stats, 0, &Event::Clock(event)),
+            )?;
+        }
+
+        if let Some(retranslation_report) = plugin_args.retranslation_report.as_ref() {
+            let retranslation =
+                RetranslationTracker::new(plugin_args.retranslation_top.unwrap_or(20));
+            let retranslation = match block_identity {
+                BlockIdentity::Offset => retranslation,
+                BlockIdentity::Content => retranslation.with_block_ids(program_modules()?),
+            };
+
+            self.retranslation = Some(Arc::new(Mutex::new(retranslation)));
+            self.retranslation_report = Some(retranslation_report.clone());
+        }
+
+        if let Some(policy) = plugin_args.demangle.as_deref() {
+            let policy = policy.parse::<DemanglePolicy>()?;
+
+            if !policy.languages.is_empty() {
+                self.demangler = Some(Arc::new(Demangler::new(policy)?));
+            }
+        }
+
+        if let Some(symprof_report) = plugin_args.symprof_report.as_ref() {
+            let sections = match qemu_plugin_path_to_binary()? {
+                Some(path) => ModuleMap::load(path, qemu_plugin_start_code())?.sections,
+                None => Vec::new(),
+            };
+
+            self.symprof = Some(Arc::new(Mutex::new(SymbolProfiler::new(
+                plugin_args
+                    .symprof_weights
+                    .as_deref()
+                    .map(str::parse::<Weights>)
+                    .transpose()?,
+                sections,
+            ))));
+            self.symprof_report = Some(symprof_report.clone());
+        }
+
+        if let Some(source_report) = plugin_args.source_report.as_ref() {
+            let path = match plugin_args.source_binary.clone() {
+                Some(path) => path,
+                None => qemu_plugin_path_to_binary()?
+                    .ok_or_else(|| anyhow!("source_report needs source_binary in system mode"))?,
+            };
+            let bias = ModuleMap::load(&path, qemu_plugin_start_code())?.bias;
+
+            self.srcline = Some(Arc::new(Mutex::new(SourceProfiler::load(path, bias)?)));
+            self.source_report = Some(source_report.clone());
+        }
+
+        if let Some(extension_report) = plugin_args.extension_report.as_ref() {
+            let arch = self.arch.ok_or_else(|| {
+                anyhow!("extension_report does not know target {}", info.target_name)
+            })?;
+            let mut profiler = ExtensionProfiler::new(arch);
+
+            for module in program_modules()? {
+                profiler.add_module(&module.name, module.start, module.end);
+            }
+
+            self.extensions = Some(Arc::new(Mutex::new(profiler)));
+            self.extension_report = Some(extension_report.clone());
+        }
+
+        if let Some(fuzz_start) = plugin_args.fuzz_start.as_deref() {
+            let (target, signals) = FuzzTarget::new(
+                memmap::parse_addr(fuzz_start)?,
+                memmap::parse_addr(
+                    plugin_args
+                        .fuzz_end
+                        .as_deref()
+                        .ok_or_else(|| anyhow!("Fuzzing requires an end marker"))?,
+                )?,
+                fuzz::parse_markers(plugin_args.fuzz_crashes.as_deref().unwrap_or_default())?,
+            );
+            let target = Arc::new(Mutex::new(target));
+            let config = FuzzConfig::builder()
+                .qmp_socket(
+                    plugin_args
+                        .qmp_socket
+                        .clone()
+                        .ok_or_else(|| anyhow!("Fuzzing requires a QMP socket"))?,
+                )
+                .input(
+                    plugin_args
+                        .fuzz_input
+                        .clone()
+                        .ok_or_else(|| anyhow!("Fuzzing requires an input file"))?,
+                )
+                .output(
+                    plugin_args
+                        .fuzz_output
+                        .clone()
+                        .ok_or_else(|| anyhow!("Fuzzing requires an output directory"))?,
+                )
+                .corpus(plugin_args.fuzz_corpus.clone())
+                .timeout(Duration::from_millis(
+                    plugin_args.fuzz_timeout.unwrap_or(1000),
+                ))
+                .iterations(plugin_args.fuzz_iterations)
+                .seed(plugin_args.fuzz_seed)
+                .build();
+
+            // Like the memory map query, the loop needs QEMU's main loop to serve QMP
+            {
+                let target = target.clone();
+
+                spawn(move || {
+                    if let Err(e) = fuzz::loop_(config, target, signals) {
+                        log(format!("Fuzz loop failed: {e}"));
+                    }
+                });
+            }
+
+            self.fuzz = Some(target);
+        }
+
+        if plugin_args.honggfuzz {
+            self.honggfuzz = Some(Arc::new(Mutex::new(HonggfuzzFeedback::open()?)));
+        }
+
+        #[cfg(feature = "plugin-api-v4")]
+        {
+            if plugin_args.file_report.is_some() {
+                self.files = Some(Arc::new(Mutex::new(FileTracker::new(
+                    plugin_args.hash_files,
+                    FileLimits {
+                        files: Budget::new(
+                            "file report paths",
+                            plugin_args.max_files,
+                            plugin_args.limit_policy,
+                        ),
+                        ranges: Budget::new(
+                            "file report ranges per path",
+                            plugin_args.max_ranges,
+                            plugin_args.limit_policy,
+                        ),
+                    },
+                ))));
+                self.file_report = plugin_args.file_report.clone();
+            }
+
+            if plugin_args.symbolize_libraries {
+                let mut symbolizer = Symbolizer::new(DebugLocator::new(
+                    plugin_args.debuginfod_cache.clone(),
+                    plugin_args.debuginfod_urls.as_deref(),
+                )?);
+
+                if let (Some(path), Some(start), Some(end)) = (
+                    qemu_plugin_path_to_binary()?,
+                    qemu_plugin_start_code(),
+                    qemu_plugin_end_code(),
+                ) {
+                    let bias = ModuleMap::load(&path, Some(start))?.bias;
+                    symbolizer.add_module(&path, start, end, bias)?;
+                }
+
+                self.symbolizer = Some(Arc::new(Mutex::new(symbolizer)));
+            }
+
+            if let Some(pcap_path) = plugin_args.pcap_path.as_ref() {
+                self.net = Some(Arc::new(Mutex::new(NetTracker::new(pcap_path)?)));
+            }
+
+            if let Some(periph_path) = plugin_args.periph_path.as_ref() {
+                self.periph = Some(Arc::new(Mutex::new(Peripherals::load(periph_path)?)));
+            }
+
+            if let Some(inject_path) = plugin_args.inject_path.as_deref() {
+                let inject_data = plugin_args
+                    .inject_data
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Input injection requires a file of input data"))?;
+
+                self.input = Some(Arc::new(Mutex::new(InputInjector::new(
+                    inject_path.parse()?,
+                    std::fs::read(inject_data)?,
+                )?)));
+            }
+
+            if let Some(budget) = plugin_args.watchdog_budget {
+                let mut resets = fuzz::parse_markers(
+                    plugin_args.watchdog_resets.as_deref().unwrap_or_default(),
+                )?;
+
+                // Each fuzz input is an iteration
+                if let Some(fuzz) = self.fuzz.as_ref() {
+                    resets.push(
+                        fuzz.lock()
+                            .map_err(|e| anyhow!("Failed to lock fuzz target: {e}"))?
+                            .start(),
+                    );
+                }
+
+                let watchdog = Arc::new(Watchdog::new(budget, resets));
+
+                self.stats.register_scoreboard("watchdog", {
+                    let watchdog = watchdog.clone();
+                    Box::new(move || watchdog.counts())
+                })?;
+                self.watchdog = Some(watchdog);
+                self.watchdog_exit = plugin_args.watchdog_exit;
+            }
+
+            if let Some(state_points) = plugin_args.state_points.as_deref() {
+                self.state = Some(Arc::new(Mutex::new(StateTracker::new(
+                    state_points,
+                    plugin_args.state_regions.as_deref(),
+                    plugin_args.state_registers.as_deref(),
+                )?)));
+            }
+
+            if let Some(uarts) = plugin_args.uarts.as_deref() {
+                self.uart = Some(Arc::new(Mutex::new(UartDecoder::new(uarts)?)));
+            }
+
+            if let Some(fault_rules) = plugin_args.fault_rules.as_ref() {
+                self.faults = Some(Arc::new(Mutex::new(FaultInjector::load(
+                    fault_rules,
+                    plugin_args.fault_seed,
+                )?)));
+            }
+
+            if plugin_args.log_random {
+                self.random = Some(Arc::new(Mutex::new(RandomTracker::new(plugin_args.seeded))));
+            }
+
+            if let (true, Some(arch)) = (plugin_args.track_heap, self.arch) {
+                self.heap = Some(Arc::new(Mutex::new(HeapTracker::new(
+                    arch,
+                    plugin_args.heap_functions.as_deref(),
+                    plugin_args.heap_depth.unwrap_or(8),
+                )?)));
+            }
+
+            if let (Some(api_profiles), Some(arch)) =
+                (plugin_args.api_profiles.as_deref(), self.arch)
+            {
+                self.api = Some(Arc::new(Mutex::new(ApiTracer::new(
+                    arch,
+                    api_profiles,
+                    plugin_args.api_functions.as_deref(),
+                )?)));
+            }
+
+            if plugin_args.detect_rop {
+                let defaults = RopConfig::builder().build();
+
+                self.rop = Some(Arc::new(Mutex::new(RopDetector::new(
+                    RopConfig::builder()
+                        .return_weight(
+                            plugin_args
+                                .rop_return_weight
+                                .unwrap_or(defaults.return_weight),
+                        )
+                        .gadget_weight(
+                            plugin_args
+                                .rop_gadget_weight
+                                .unwrap_or(defaults.gadget_weight),
+                        )
+                        .decay(plugin_args.rop_decay.unwrap_or(defaults.decay))
+                        .threshold(plugin_args.rop_threshold.unwrap_or(defaults.threshold))
+                        .gadget_len(plugin_args.rop_gadget_len.unwrap_or(defaults.gadget_len))
+                        .build(),
+                ))));
+            }
+
+            #[cfg(all(feature = "yara", feature = "plugin-api-v4"))]
+            if let Some(yara_rules) = plugin_args.yara_rules.as_ref() {
+                self.yara = Some(Arc::new(YaraScanner::new(
+                    YaraConfig::builder()
+                        .rules(YaraScanner::load_rules(yara_rules)?)
+                        .ranges(DumpRange::parse_list(
+                            plugin_args.yara_ranges.as_deref().unwrap_or_default(),
+                        )?)
+                        .pcs(fuzz::parse_markers(
+                            plugin_args.yara_pcs.as_deref().unwrap_or_default(),
+                        )?)
+                        .syscall(plugin_args.yara_syscall)
+                        .build(),
+                )));
+            }
+
+            if let (true, Some(arch)) = (plugin_args.detect_crypto, self.arch) {
+                self.crypto = Some(Arc::new(Mutex::new(CryptoDetector::new(
+                    arch,
+                    plugin_args.crypto_threshold.unwrap_or(2),
+                ))));
+            }
+
+            if let (true, Some(arch)) = (plugin_args.log_strings, self.arch) {
+                let mut regions = Vec::new();
+                let mut code = Vec::new();
+
+                if let Some(path) = qemu_plugin_path_to_binary()? {
+                    for section in ModuleMap::load(path, qemu_plugin_start_code())?.sections {
+                        if section.is_rodata() {
+                            regions.push(section);
+                        } else if section.executable {
+                            code.push(section);
+                        }
+                    }
+                }
+
+                for range in
+                    DumpRange::parse_list(plugin_args.string_ranges.as_deref().unwrap_or_default())?
+                {
+                    regions.push(Section {
+                        name: format!("{:#x}-{:#x}", range.start, range.end),
+                        start: range.start,
+                        end: range.end,
+                        writable: false,
+                        executable: false,
+                    });
+                }
+
+                if regions.is_empty() {
+                    return Err(anyhow!(
+                        "No read-only data to log strings from, give string ranges instead"
+                    ));
+                }
+
+                self.strings = Some(Arc::new(Mutex::new(StringTracker::new(
+                    arch, regions, code,
+                ))));
+            }
+
+            self.guest_hypercalls = plugin_args.guest_hypercalls;
+            self.log_mem_values = plugin_args.log_mem_values;
+
+            if let Some(entropy_probes) = plugin_args.entropy_probes.as_ref() {
+                // Registers are read in the guest's byte order
+                if self.arch.is_none() {
+                    return Err(anyhow!(
+                        "entropy_probes does not know target {}",
+                        info.target_name
+                    ));
+                }
+
+                self.probes = EntropyProbe::parse_list(entropy_probes)?;
+            }
+
+            if let Some(signatures) = plugin_args.signatures.as_ref() {
+                self.signatures = Some(Arc::new(Mutex::new(SignatureScanner::load(signatures)?)));
+            }
+
+            if plugin_args.shadow_stack {
+                self.shadow = Some(Arc::new(Mutex::new(ShadowStack::new(
+                    plugin_args.shadow_stack_strict,
+                    fuzz::parse_markers(
+                        plugin_args
+                            .shadow_stack_allow
+                            .as_deref()
+                            .unwrap_or_default(),
+                    )?,
+                ))));
+            }
+
+            if let (Some(lock_report), Some(arch)) = (plugin_args.lock_report.as_ref(), self.arch) {
+                self.locks = Some(Arc::new(Mutex::new(LockTracker::new(
+                    arch,
+                    plugin_args.lock_functions.as_deref(),
+                    plugin_args.lock_top.unwrap_or(10),
+                )?)));
+                self.lock_report = Some(lock_report.clone());
+            }
+
+            if let (Some(exclusive_report), Some(arch)) =
+                (plugin_args.exclusive_report.as_ref(), self.arch)
+            {
+                self.exclusives = Some(Arc::new(Mutex::new(ExclusiveTracker::new(arch))));
+                self.exclusive_report = Some(exclusive_report.clone());
+            }
+
+            if let (Some(expectations), Some(arch)) = (plugin_args.expectations.as_ref(), self.arch)
+            {
+                self.expectations = Some(Arc::new(Mutex::new(Expectations::new(
+                    arch,
+                    ExpectationFile::load(expectations)?,
+                )?)));
+                self.expectation_report = plugin_args.expectation_report.clone();
+            }
+
+            if let (true, Some(arch)) = (plugin_args.detect_races, self.arch) {
+                self.races = Some(Arc::new(Mutex::new(RaceDetector::new(arch))));
+            }
+
+            if let (true, Some(arch)) = (
+                plugin_args.detect_wx || plugin_args.wx_report.is_some(),
+                self.arch,
+            ) {
+                self.wx = Some(Arc::new(Mutex::new(WxTracker::new(
+                    arch,
+                    info.system.is_some(),
+                ))));
+                self.wx_report = plugin_args.wx_report.clone();
+            }
+
+            if let (Some(crash_dir), Some(window), Some(arch)) = (
+                plugin_args.crash_dir.as_ref(),
+                self.tx.window.clone(),
+                self.arch,
+            ) {
+                let mut config = CrashConfig::builder()
+                    .dir(crash_dir.clone())
+                    .stack_size(plugin_args.crash_stack.unwrap_or(4096))
+                    .limit(plugin_args.crash_limit.unwrap_or(1))
+                    .minidump(plugin_args.crash_minidump)
+                    .build();
+
+                if let Some(triggers) = plugin_args.crash_triggers.as_deref() {
+                    config.triggers = CrashTrigger::parse_list(triggers)?;
+                }
+
+                if config.minidump {
+                    crash::minidump::supported(arch)?;
+                }
+
+                self.crash = Some(Arc::new(Mutex::new(CrashRecorder::new(
+                    config, arch, window,
+                ))));
+            }
+
+            if let (true, Some(arch)) = (plugin_args.resolve_plt, self.arch) {
+                if info.system.is_some() {
+                    return Err(anyhow!("resolve_plt needs user mode"));
+                }
+
+                let mut plt = PltResolver::new(arch);
+
+                if let Some(path) = qemu_plugin_path_to_binary()? {
+                    plt.add_module(&ModuleMap::load(path, qemu_plugin_start_code())?);
+                }
+
+                self.plt = Some(Arc::new(Mutex::new(plt)));
+            }
+
+            if let (true, Some(arch)) = (plugin_args.log_schedule, self.arch) {
+                self.scheduler = Some(Arc::new(Mutex::new(Scheduler::new(arch))));
+            }
+
+            if let (true, Some(arch)) = (plugin_args.log_isa_modes, self.arch) {
+                self.isa = Some(Arc::new(Mutex::new(IsaTracker::new(arch))));
+            }
+
+            if let Some(dump_dir) = plugin_args.dump_dir.as_ref() {
+                self.dumper = Some(Arc::new(Mutex::new(Dumper::new(
+                    DumpConfig::builder()
+                        .dir(dump_dir.clone())
+                        .ranges(
+                            plugin_args
+                                .dump_ranges
+                                .as_deref()
+                                .map(DumpRange::parse_list)
+                                .transpose()?
+                                .unwrap_or_default(),
+                        )
+                        .pc(plugin_args
+                            .dump_pc
+                            .as_deref()
+                            .map(memmap::parse_addr)
+                            .transpose()?)
+                        .syscall(plugin_args.dump_syscall)
+                        .on_abort(plugin_args.dump_on_abort)
+                        .limit(plugin_args.dump_limit.unwrap_or(1))
+                        .qmp_socket(plugin_args.qmp_socket.clone())
+                        .build(),
+                ))));
+            }
+        }
+
+        // NOTE: QEMU keeps a single atexit callback per plugin, so all teardown happens here
+        let tracer = self.clone();
+        qemu_plugin_register_atexit_cb(id, move |_| {
+            if let Err(e) = tracer.on_exit() {
+                log(format!("Failed to finalize tracer: {e}"));
+            }
+        })?;
+
+        self.count_instructions = plugin_args
+            .analyses()
+            .into_iter()
+            .any(Analysis::needs_icount);
+
+        #[cfg(feature = "plugin-api-v4")]
+        if self.reference.is_some() {
+            self.count_instructions = true;
+        }
+
+        #[cfg(unix)]
+        if let Some(stats_path) = plugin_args.stats_path.as_ref() {
+            stats::dump_on_signal(self.stats.clone(), stats_path.clone())?;
+        }
+
+        let control = plugin_args
+            .control_address
+            .map(ControlAddress::Tcp)
+            .into_iter()
+            .chain(plugin_args.control_path.map(ControlAddress::Unix));
+
+        for address in control {
+            let tx = self.tx.clone();
+            let stats = self.stats.clone();
+
+            stats::serve_control(
+                self.stats.clone(),
+                address,
+                plugin_args.stats_path.clone(),
+                Box::new(move |name, note| {
+                    let event = BookmarkEvent::builder()
+                        .vcpu_index(0)
+                        .name(name)
+                        .note(note)
+                        .source(BookmarkSource::Control)
+                        .icount(stats.icount())
+                        .build();
+
+                    send_event(&tx, &stats, 0, &Event::Bookmark(event))
+                }),
+            )?;
+        }
+
         Ok(())
     }
 }
@@ -521,8 +6057,11 @@ impl Plugin for Tracer {}
 
 #[ctor]
 fn init() {
-    PLUGIN
-        .set(Mutex::new(Box::new(Tracer::new())))
-        .map_err(|_| anyhow::anyhow!("Failed to set plugin"))
-        .expect("Failed to set plugin");
+    // Loom atomics can only be created inside a model, so loom builds are not loadable
+    if cfg!(tracer_loom) {
+        return;
+    }
+
+    // A plugin built on this crate may have installed a preset already
+    PLUGIN.get_or_init(|| Mutex::new(Box::new(Tracer::new())));
 }
@@ -0,0 +1,98 @@
+//! Entry-count budgets for the maps the tracer accumulates over a run, so that long captures
+//! degrade predictably instead of exhausting host memory
+
+use anyhow::{anyhow, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// What happens when a collection reaches its budget
+pub enum LimitPolicy {
+    #[default]
+    /// Evict the oldest entry to make room for the new one
+    Evict,
+    /// Fail, stopping the run with an error naming the exhausted budget
+    Stop,
+}
+
+impl FromStr for LimitPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "evict" => Ok(Self::Evict),
+            "stop" => Ok(Self::Stop),
+            _ => Err(anyhow!("Unknown limit policy {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Whether a new entry can be added as-is, or the oldest entry must be evicted first
+pub enum Admission {
+    Insert,
+    Evict,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// A budget on the number of entries in a collection
+pub struct Budget {
+    pub name: String,
+    pub max_entries: Option<usize>,
+    pub policy: LimitPolicy,
+    /// The number of entries evicted to stay within the budget
+    pub evicted: u64,
+}
+
+impl Budget {
+    /// Create a budget. A `max_entries` of `None` is unlimited.
+    pub fn new<S>(name: S, max_entries: Option<usize>, policy: LimitPolicy) -> Self
+    where
+        S: AsRef<str>,
+    {
+        Self {
+            name: name.as_ref().to_string(),
+            max_entries,
+            policy,
+            evicted: 0,
+        }
+    }
+
+    /// Check whether an entry can be added to a collection currently holding `len` entries
+    pub fn admit(&mut self, len: usize) -> Result<Admission> {
+        match self.max_entries {
+            Some(max_entries) if len >= max_entries => match self.policy {
+                LimitPolicy::Evict => {
+                    self.evicted += 1;
+                    Ok(Admission::Evict)
+                }
+                LimitPolicy::Stop => Err(anyhow!(
+                    "{} reached its limit of {max_entries} entries",
+                    self.name
+                )),
+            },
+            _ => Ok(Admission::Insert),
+        }
+    }
+
+    /// Whether the budget has been reached at least once
+    pub fn exceeded(&self) -> bool {
+        self.evicted > 0
+    }
+}
+
+impl Display for Budget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.max_entries {
+            Some(max_entries) => write!(
+                f,
+                "{}: limit of {max_entries} entries, {} evicted",
+                self.name, self.evicted
+            ),
+            None => write!(f, "{}: unlimited", self.name),
+        }
+    }
+}
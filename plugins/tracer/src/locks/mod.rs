@@ -0,0 +1,346 @@
+//! Contention analysis for guest pthread mutexes
+//!
+//! `pthread_mutex_lock`, `pthread_mutex_trylock` and `pthread_mutex_unlock` are hooked at
+//! their first block, where the mutex they are passed is read. A lock request which waits
+//! in `futex` on the mutex before the thread's next lock function is contended: it is
+//! acquired when the last such wait returns, or immediately otherwise, and held until the
+//! thread unlocks it. Times are measured in instructions executed by the whole guest.
+//!
+//! `futex` waits are counted even when the lock functions cannot be hooked, so contended
+//! futex words are still found in stripped binaries. As with the heap tracker, functions in
+//! a dynamically linked C library must be given by address.
+
+use crate::{
+    arch::{Arch, Syscall},
+    guest::read_pointer,
+    heap::{read_argument, read_register},
+    memmap::parse_addr,
+};
+use anyhow::{anyhow, Result};
+use qemu_plugin::{path::create_sink, RegisterDescriptor, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path, str::FromStr};
+
+const FUTEX_CMD_MASK: u64 = 0x7f;
+const FUTEX_WAIT: u64 = 0;
+const FUTEX_LOCK_PI: u64 = 6;
+const FUTEX_WAIT_BITSET: u64 = 9;
+const FUTEX_LOCK_PI2: u64 = 13;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+/// The mutex functions which are hooked
+pub enum LockFunction {
+    Lock,
+    Trylock,
+    Unlock,
+}
+
+/// Mutex function symbol names, including glibc's internal aliases
+const SYMBOLS: &[(&str, LockFunction)] = &[
+    ("pthread_mutex_lock", LockFunction::Lock),
+    ("__pthread_mutex_lock", LockFunction::Lock),
+    ("pthread_mutex_trylock", LockFunction::Trylock),
+    ("__pthread_mutex_trylock", LockFunction::Trylock),
+    ("pthread_mutex_unlock", LockFunction::Unlock),
+    ("__pthread_mutex_unlock", LockFunction::Unlock),
+];
+
+impl FromStr for LockFunction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        SYMBOLS
+            .iter()
+            .find_map(|(name, function)| (*name == s).then_some(*function))
+            .ok_or_else(|| anyhow!("Unknown mutex function {s}"))
+    }
+}
+
+impl LockFunction {
+    /// Returns the mutex function a symbol names, if any
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        symbol.parse().ok()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The contention and hold times measured for one lock
+pub struct LockReport {
+    pub address: u64,
+    pub acquisitions: u64,
+    /// Acquisitions which waited in `futex` for the lock
+    pub contentions: u64,
+    /// `futex` waits on the lock, including those by unhooked code
+    pub futex_waits: u64,
+    pub total_wait: u64,
+    pub max_wait: u64,
+    pub total_hold: u64,
+    pub max_hold: u64,
+    /// The call stacks which acquired the lock, innermost first, and how many times each did
+    pub acquired_at: Vec<(Vec<u64>, u64)>,
+}
+
+#[derive(Debug, Default)]
+struct LockState {
+    report: LockReport,
+    sites: HashMap<Vec<u64>, u64>,
+}
+
+#[derive(Clone, Debug)]
+/// A lock request whose outcome is not yet known
+struct Request {
+    lock: u64,
+    requested: u64,
+    site: Vec<u64>,
+    contended: bool,
+    /// When the last `futex` wait on the lock returned
+    woken: Option<u64>,
+}
+
+#[derive(Debug)]
+/// Hooks guest mutex functions and `futex` to measure contention per lock
+pub struct LockTracker {
+    arch: Arch,
+    /// Mutex functions given by address, for binaries without symbols
+    functions: HashMap<u64, LockFunction>,
+    /// How many locks the report includes
+    top: usize,
+    requests: HashMap<VCPUIndex, Request>,
+    /// The `futex` word each vCPU is waiting on
+    waiting: HashMap<VCPUIndex, u64>,
+    /// When each vCPU acquired each lock it holds
+    held: HashMap<(VCPUIndex, u64), u64>,
+    locks: HashMap<u64, LockState>,
+}
+
+/// Read the return address of a function, at its first block
+fn return_address(arch: Arch, registers: &[RegisterDescriptor<'static>]) -> Result<u64> {
+    match arch {
+        Arch::I386 | Arch::X86_64 => {
+            let sp = read_register(arch, registers, arch.stack_pointer_names()[0])?;
+
+            read_pointer(arch, sp, arch.pointer_size())
+        }
+        Arch::Arm => {
+            read_register(arch, registers, "lr").or_else(|_| read_register(arch, registers, "r14"))
+        }
+        Arch::Aarch64 => {
+            read_register(arch, registers, "lr").or_else(|_| read_register(arch, registers, "x30"))
+        }
+        Arch::Mips | Arch::Mipsel | Arch::Mips64 | Arch::Mips64el => {
+            read_register(arch, registers, "r31").or_else(|_| read_register(arch, registers, "ra"))
+        }
+        Arch::Ppc | Arch::Ppc64 | Arch::Ppc64le => read_register(arch, registers, "lr"),
+        Arch::LoongArch64 => {
+            read_register(arch, registers, "r1").or_else(|_| read_register(arch, registers, "ra"))
+        }
+        Arch::S390x => read_register(arch, registers, "r14"),
+        Arch::Hexagon => read_register(arch, registers, "r31"),
+        Arch::Riscv32 | Arch::Riscv64 => {
+            read_register(arch, registers, "ra").or_else(|_| read_register(arch, registers, "x1"))
+        }
+    }
+}
+
+impl LockTracker {
+    /// Create a tracker, with mutex functions given by address as `name:address` pairs
+    /// separated by `;`, e.g. `pthread_mutex_lock:0x401a20;pthread_mutex_unlock:0x401c80`.
+    /// The report includes the `top` most contended locks.
+    pub fn new(arch: Arch, functions: Option<&str>, top: usize) -> Result<Self> {
+        let functions = functions
+            .unwrap_or_default()
+            .split(';')
+            .filter(|f| !f.trim().is_empty())
+            .map(|f| {
+                let (name, addr) = f
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("Invalid mutex function {f}, expected name:address"))?;
+
+                Ok((parse_addr(addr)?, name.trim().parse()?))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        Ok(Self {
+            arch,
+            functions,
+            top,
+            requests: HashMap::new(),
+            waiting: HashMap::new(),
+            held: HashMap::new(),
+            locks: HashMap::new(),
+        })
+    }
+
+    /// Returns the mutex function a block starting at `vaddr` with `symbol` belongs to
+    pub fn function(&self, vaddr: u64, symbol: Option<&str>) -> Option<LockFunction> {
+        self.functions
+            .get(&vaddr)
+            .copied()
+            .or_else(|| symbol.and_then(LockFunction::from_symbol))
+    }
+
+    /// Record that a mutex function was entered. `callers` is the vCPU's shadow call stack
+    /// if one is kept, otherwise the function's return address is used as its call site.
+    pub fn on_function(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        function: LockFunction,
+        registers: &[RegisterDescriptor<'static>],
+        callers: Vec<u64>,
+        icount: u64,
+    ) -> Result<()> {
+        let lock = read_argument(self.arch, registers, 0)?;
+
+        // Any earlier request has returned, as the thread has moved on
+        self.resolve(vcpu_index);
+
+        match function {
+            LockFunction::Lock | LockFunction::Trylock => {
+                let site = if callers.is_empty() {
+                    vec![return_address(self.arch, registers)?]
+                } else {
+                    callers
+                };
+
+                self.requests.insert(
+                    vcpu_index,
+                    Request {
+                        lock,
+                        requested: icount,
+                        site,
+                        contended: false,
+                        woken: None,
+                    },
+                );
+            }
+            LockFunction::Unlock => {
+                if let Some(acquired) = self.held.remove(&(vcpu_index, lock)) {
+                    let report = &mut self.locks.entry(lock).or_default().report;
+                    let hold = icount.saturating_sub(acquired);
+
+                    report.total_hold += hold;
+                    report.max_hold = report.max_hold.max(hold);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Complete the vCPU's outstanding lock request, if any, as acquired
+    fn resolve(&mut self, vcpu_index: VCPUIndex) {
+        let Some(request) = self.requests.remove(&vcpu_index) else {
+            return;
+        };
+
+        let acquired = request.woken.unwrap_or(request.requested);
+        let wait = acquired.saturating_sub(request.requested);
+        let state = self.locks.entry(request.lock).or_default();
+
+        state.report.address = request.lock;
+        state.report.acquisitions += 1;
+        state.report.contentions += request.contended as u64;
+        state.report.total_wait += wait;
+        state.report.max_wait = state.report.max_wait.max(wait);
+        *state.sites.entry(request.site).or_default() += 1;
+
+        self.held.insert((vcpu_index, request.lock), acquired);
+    }
+
+    /// Record the arguments of a syscall on entry
+    pub fn on_syscall(&mut self, vcpu_index: VCPUIndex, num: i64, args: [u64; 8]) {
+        if self.arch.syscall(num) != Some(Syscall::Futex) {
+            return;
+        }
+
+        let [uaddr, op, ..] = args;
+
+        if !matches!(
+            op & FUTEX_CMD_MASK,
+            FUTEX_WAIT | FUTEX_LOCK_PI | FUTEX_WAIT_BITSET | FUTEX_LOCK_PI2
+        ) {
+            return;
+        }
+
+        let state = self.locks.entry(uaddr).or_default();
+
+        state.report.address = uaddr;
+        state.report.futex_waits += 1;
+
+        if let Some(request) = self
+            .requests
+            .get_mut(&vcpu_index)
+            .filter(|r| r.lock == uaddr)
+        {
+            request.contended = true;
+        }
+
+        self.waiting.insert(vcpu_index, uaddr);
+    }
+
+    /// Record the return of a syscall
+    pub fn on_syscall_return(&mut self, vcpu_index: VCPUIndex, icount: u64) {
+        let Some(uaddr) = self.waiting.remove(&vcpu_index) else {
+            return;
+        };
+
+        if let Some(request) = self
+            .requests
+            .get_mut(&vcpu_index)
+            .filter(|r| r.lock == uaddr)
+        {
+            request.woken = Some(icount);
+        }
+    }
+
+    /// Returns the locks with the most contended acquisitions, breaking ties by the number
+    /// of `futex` waits and then the total wait
+    pub fn report(&self) -> Vec<LockReport> {
+        let mut reports = self
+            .locks
+            .values()
+            .filter(|state| state.report.futex_waits > 0 || state.report.contentions > 0)
+            .map(|state| {
+                let mut acquired_at = state
+                    .sites
+                    .iter()
+                    .map(|(site, count)| (site.clone(), *count))
+                    .collect::<Vec<_>>();
+
+                acquired_at.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+                LockReport {
+                    acquired_at,
+                    ..state.report.clone()
+                }
+            })
+            .collect::<Vec<_>>();
+
+        reports.sort_by_key(|r| {
+            (
+                std::cmp::Reverse(r.contentions),
+                std::cmp::Reverse(r.futex_waits),
+                std::cmp::Reverse(r.total_wait),
+                r.address,
+            )
+        });
+        reports.truncate(self.top);
+        reports
+    }
+
+    /// Complete every outstanding request and write the report to `path` as JSON
+    pub fn write_report<P>(&mut self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let vcpus = self.requests.keys().copied().collect::<Vec<_>>();
+
+        for vcpu_index in vcpus {
+            self.resolve(vcpu_index);
+        }
+
+        serde_json::to_writer_pretty(create_sink(path)?, &self.report())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+//! Snapshots of the guest physical memory layout: which ranges are RAM, ROM or MMIO
+//!
+//! The plugin API does not describe the memory layout, so it is gathered either from QMP,
+//! by parsing the flattened `memory` address space printed by `info mtree -f`, or, when no
+//! QMP socket is available, from the physical addresses the plugin observes guest accesses
+//! touching. An observed map only covers pages which were accessed.
+
+use crate::qmp::Qmp;
+use anyhow::{anyhow, Result};
+use qemu_plugin::path::create_sink;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The granularity at which observed accesses are recorded
+const PAGE_SIZE: u64 = 4096;
+
+/// Parse an address, in hex if prefixed with `0x` and in decimal otherwise
+pub fn parse_addr(addr: &str) -> Result<u64> {
+    let addr = addr.trim();
+
+    match addr.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => addr.parse(),
+    }
+    .map_err(|e| anyhow!("Invalid address {addr}: {e}"))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+/// The kind of memory backing a region
+pub enum RegionKind {
+    /// Guest RAM
+    Ram,
+    /// Read-only memory
+    Rom,
+    /// ROM which is read directly but whose writes are handled as I/O, e.g. flash
+    Romd,
+    /// Memory-mapped I/O
+    Io,
+}
+
+impl RegionKind {
+    fn from_mtree(kind: &str) -> Option<Self> {
+        match kind {
+            "ram" | "ramd" => Some(Self::Ram),
+            "rom" => Some(Self::Rom),
+            "romd" => Some(Self::Romd),
+            "i/o" => Some(Self::Io),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A contiguous range of guest physical memory
+pub struct Region {
+    /// The first address in the region
+    pub start: u64,
+    /// The address after the last address in the region
+    pub end: u64,
+    pub kind: RegionKind,
+    /// The name of the QEMU memory region or device backing this range
+    pub name: Option<String>,
+}
+
+impl Region {
+    /// Whether `paddr` falls within the region
+    pub fn contains(&self, paddr: u64) -> bool {
+        (self.start..self.end).contains(&paddr)
+    }
+
+    /// The size of the region in bytes
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Whether the region is empty
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// Where a memory map was gathered from
+pub enum MapSource {
+    /// The complete layout, queried over QMP
+    Qmp,
+    /// Pages observed being accessed by the guest
+    #[default]
+    Observed,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// The guest physical memory layout, sorted by address with no overlapping regions
+pub struct MemoryMap {
+    pub source: MapSource,
+    pub regions: Vec<Region>,
+}
+
+impl MemoryMap {
+    /// Query the layout over the QMP socket at `qmp_socket`. See `qmp` for when this may be
+    /// called.
+    pub fn query<P>(qmp_socket: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::parse_mtree(&Qmp::connect(qmp_socket)?.human_monitor_command("info mtree -f")?)
+    }
+
+    /// Parse the output of `info mtree -f`, taking the flat view of the `memory` address
+    /// space. Lines look like
+    /// `  0000000000000000-000000000009ffff (prio 0, ram): pc.ram @0000000000000000 KVM`.
+    pub fn parse_mtree(mtree: &str) -> Result<Self> {
+        let mut views: Vec<(bool, Vec<Region>)> = Vec::new();
+
+        for line in mtree.lines() {
+            let line = line.trim();
+
+            if line.starts_with("FlatView") {
+                views.push((false, Vec::new()));
+                continue;
+            }
+
+            let Some((memory, regions)) = views.last_mut() else {
+                continue;
+            };
+
+            if line.starts_with(r#"AS "memory""#) {
+                *memory = true;
+                continue;
+            }
+
+            if let Some(region) = Self::parse_mtree_line(line) {
+                regions.push(region);
+            }
+        }
+
+        let (_, mut regions) = views
+            .iter()
+            .position(|(memory, _)| *memory)
+            .or_else(|| (!views.is_empty()).then_some(0))
+            .map(|i| views.swap_remove(i))
+            .ok_or_else(|| anyhow!("No flat view found in mtree output"))?;
+
+        regions.sort_by_key(|r| r.start);
+
+        Ok(Self {
+            source: MapSource::Qmp,
+            regions,
+        })
+    }
+
+    fn parse_mtree_line(line: &str) -> Option<Region> {
+        let (range, rest) = line.split_once(' ')?;
+        let (start, end) = range.split_once('-')?;
+        let start = u64::from_str_radix(start, 16).ok()?;
+        let end = u64::from_str_radix(end, 16).ok()?.checked_add(1)?;
+
+        let (attrs, name) = rest.strip_prefix('(')?.split_once("): ")?;
+        let kind = RegionKind::from_mtree(attrs.rsplit(", ").next()?)?;
+        let name = name
+            .split_whitespace()
+            .next()
+            .filter(|n| !n.is_empty())
+            .map(|n| n.to_string());
+
+        Some(Region {
+            start,
+            end,
+            kind,
+            name,
+        })
+    }
+
+    /// Returns the region containing `paddr`, if it is mapped
+    pub fn region(&self, paddr: u64) -> Option<&Region> {
+        let i = self.regions.partition_point(|r| r.end <= paddr);
+        self.regions.get(i).filter(|r| r.contains(paddr))
+    }
+
+    /// Returns the RAM regions, which are what a memory dump needs to capture
+    pub fn ram(&self) -> impl Iterator<Item = &Region> {
+        self.regions.iter().filter(|r| r.kind == RegionKind::Ram)
+    }
+
+    /// Whether an access to `paddr` which QEMU reported as I/O (or not) agrees with the map.
+    /// Unmapped addresses are only consistent for observed maps, which are incomplete.
+    pub fn is_consistent(&self, paddr: u64, is_io: bool) -> bool {
+        match self.region(paddr) {
+            Some(region) => (region.kind == RegionKind::Io) == is_io,
+            None => self.source == MapSource::Observed,
+        }
+    }
+
+    /// Record a guest access to `paddr`, as reported by `qemu_plugin_get_hwaddr`. The page
+    /// containing it is added to the map, merged with neighbouring pages of the same kind
+    /// and device.
+    pub fn observe(&mut self, paddr: u64, is_io: bool, device: Option<&str>) {
+        if self.region(paddr).is_some() {
+            return;
+        }
+
+        let start = paddr & !(PAGE_SIZE - 1);
+        let region = Region {
+            start,
+            end: start.saturating_add(PAGE_SIZE),
+            kind: if is_io {
+                RegionKind::Io
+            } else {
+                RegionKind::Ram
+            },
+            name: device.map(|d| d.to_string()),
+        };
+
+        let i = self.regions.partition_point(|r| r.end <= start);
+        let same = |other: &Region| other.kind == region.kind && other.name == region.name;
+
+        let merges_prev =
+            i > 0 && self.regions[i - 1].end == region.start && same(&self.regions[i - 1]);
+        let merges_next =
+            i < self.regions.len() && self.regions[i].start == region.end && same(&self.regions[i]);
+
+        match (merges_prev, merges_next) {
+            (true, true) => {
+                self.regions[i - 1].end = self.regions[i].end;
+                self.regions.remove(i);
+            }
+            (true, false) => self.regions[i - 1].end = region.end,
+            (false, true) => self.regions[i].start = region.start,
+            (false, false) => self.regions.insert(i, region),
+        }
+    }
+
+    /// Write the map to `path` as JSON
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        serde_json::to_writer_pretty(create_sink(path)?, self)?;
+        Ok(())
+    }
+}
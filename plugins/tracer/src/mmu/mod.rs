@@ -0,0 +1,182 @@
+//! The virtual-to-physical translations a guest's MMU performed, as observed from the
+//! physical addresses of memory accesses and of executed code, for debugging guest page
+//! tables and for relating virtual addresses in a trace to physical ones
+//!
+//! With `translation_log`, each access's virtual page is recorded with the physical page
+//! QEMU resolved it to, and each executed block's pages with the physical pages its code was
+//! translated from. A [`TranslationEvent`] is sent when a vCPU first uses a virtual page, and
+//! whenever the page it maps to changes. At exit, the pages each vCPU last mapped and the
+//! changes observed are written to the translation log as JSON.
+//!
+//! Translations are only observed in system mode. They are not keyed by address space, so
+//! when a vCPU switches between processes mapping the same virtual page differently, the
+//! switch shows as a change. Only pages the guest touched are observed, and a change is only
+//! seen once the remapped page is touched again.
+
+use anyhow::Result;
+use qemu_plugin::{path::create_sink, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs::read_to_string, path::Path};
+
+/// The granularity translations are recorded at
+pub const PAGE_SIZE: u64 = 4096;
+/// The number of changes a translation log lists
+const MAX_CHANGES: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// How a translation was observed
+pub enum TranslationSource {
+    /// A block executed from the page
+    Code,
+    /// A load or store to the page
+    Data,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A virtual page a vCPU used for the first time, or which maps to a different physical page
+/// than when the vCPU last used it
+pub struct TranslationEvent {
+    pub vcpu_index: VCPUIndex,
+    /// The virtual page
+    pub vaddr: u64,
+    /// The physical page
+    pub paddr: u64,
+    /// The physical page the virtual page mapped to before, if it was used before
+    pub previous: Option<u64>,
+    pub source: TranslationSource,
+    /// Whether the physical page is MMIO
+    pub is_io: bool,
+    pub icount: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// The physical page a vCPU's virtual page mapped to when it was last used
+pub struct Mapping {
+    pub vcpu_index: VCPUIndex,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub is_io: bool,
+    /// How many times the mapping changed
+    pub changes: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The translations observed during a run
+pub struct TranslationLog {
+    /// The mappings of each virtual page, by vCPU and virtual address
+    pub mappings: Vec<Mapping>,
+    /// The first changes of a mapping, in order
+    pub changes: Vec<TranslationEvent>,
+    /// The changes observed past those listed
+    pub changes_omitted: u64,
+}
+
+impl TranslationLog {
+    pub fn read<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(serde_json::from_str(&read_to_string(path)?)?)
+    }
+
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        serde_json::to_writer_pretty(create_sink(path)?, self)?;
+        Ok(())
+    }
+
+    /// Returns the physical address `vaddr` last translated to on a vCPU, if its page was
+    /// observed
+    pub fn paddr(&self, vcpu_index: VCPUIndex, vaddr: u64) -> Option<u64> {
+        let page = vaddr & !(PAGE_SIZE - 1);
+
+        self.mappings
+            .binary_search_by_key(&(vcpu_index, page), |m| (m.vcpu_index, m.vaddr))
+            .ok()
+            .map(|i| self.mappings[i].paddr | (vaddr & (PAGE_SIZE - 1)))
+    }
+}
+
+#[derive(Debug, Default)]
+/// Tracks the physical page each vCPU's virtual pages map to
+pub struct TranslationTracker {
+    /// The physical page, MMIO flag and change count of each vCPU's virtual pages
+    pages: HashMap<(VCPUIndex, u64), (u64, bool, u64)>,
+    changes: Vec<TranslationEvent>,
+    changes_omitted: u64,
+}
+
+impl TranslationTracker {
+    /// Record that `vaddr` translated to `paddr` on a vCPU. Returns an event if the virtual
+    /// page is new or maps to a different physical page than before.
+    pub fn observe(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        vaddr: u64,
+        paddr: u64,
+        source: TranslationSource,
+        is_io: bool,
+        icount: u64,
+    ) -> Option<TranslationEvent> {
+        let vpage = vaddr & !(PAGE_SIZE - 1);
+        let ppage = paddr & !(PAGE_SIZE - 1);
+        let previous = match self.pages.get_mut(&(vcpu_index, vpage)) {
+            Some((mapped, _, _)) if *mapped == ppage => return None,
+            Some((mapped, mapped_io, changes)) => {
+                let previous = *mapped;
+
+                *mapped = ppage;
+                *mapped_io = is_io;
+                *changes += 1;
+                Some(previous)
+            }
+            None => {
+                self.pages.insert((vcpu_index, vpage), (ppage, is_io, 0));
+                None
+            }
+        };
+        let event = TranslationEvent {
+            vcpu_index,
+            vaddr: vpage,
+            paddr: ppage,
+            previous,
+            source,
+            is_io,
+            icount,
+        };
+
+        if previous.is_some() {
+            if self.changes.len() < MAX_CHANGES {
+                self.changes.push(event.clone());
+            } else {
+                self.changes_omitted += 1;
+            }
+        }
+
+        Some(event)
+    }
+
+    pub fn log(&self) -> TranslationLog {
+        let mut mappings = self
+            .pages
+            .iter()
+            .map(|((vcpu_index, vaddr), (paddr, is_io, changes))| Mapping {
+                vcpu_index: *vcpu_index,
+                vaddr: *vaddr,
+                paddr: *paddr,
+                is_io: *is_io,
+                changes: *changes,
+            })
+            .collect::<Vec<_>>();
+
+        mappings.sort_by_key(|m| (m.vcpu_index, m.vaddr));
+
+        TranslationLog {
+            mappings,
+            changes: self.changes.clone(),
+            changes_omitted: self.changes_omitted,
+        }
+    }
+}
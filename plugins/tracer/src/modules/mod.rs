@@ -0,0 +1,617 @@
+//! A map of the sections of the guest program, read from its ELF file
+//!
+//! In user mode the program's section headers give the addresses of its read-only data and
+//! code, and its program headers those of its loadable segments. A position-independent
+//! program is loaded at a bias, found from the address QEMU loaded its first executable
+//! segment at.
+//!
+//! Its relocations give the PLT stubs through which a module calls functions imported from
+//! others, for [`crate::plt`]. A stub is matched to its relocation by position, as in the
+//! PLT layout of each architecture's GNU linker, so stubs are only found for architectures
+//! with a fixed layout: x86, Arm, AArch64, RISC-V and LoongArch.
+//!
+//! The same reader gives the build-id and function symbols of libraries and their separate
+//! debug files, for [`crate::debuginfo`], and the DWARF sections of the program for
+//! [`crate::analysis::srcline`].
+
+use crate::arch::decode;
+use anyhow::{anyhow, Result};
+use miniz_oxide::inflate::decompress_to_vec_zlib;
+use std::{borrow::Cow, fs::read, path::Path};
+
+const ET_DYN: u16 = 3;
+const EM_386: u16 = 3;
+const EM_ARM: u16 = 40;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+const EM_RISCV: u16 = 243;
+const EM_LOONGARCH: u16 = 258;
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOTE: u32 = 7;
+const SHT_DYNSYM: u32 = 11;
+const STT_FUNC: u8 = 2;
+const NT_GNU_BUILD_ID: u32 = 3;
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_COMPRESSED: u64 = 0x800;
+const ELFCOMPRESS_ZLIB: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A section of a loaded module
+pub struct Section {
+    pub name: String,
+    pub start: u64,
+    /// The address after the last address in the section
+    pub end: u64,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+impl Section {
+    pub fn contains(&self, vaddr: u64) -> bool {
+        (self.start..self.end).contains(&vaddr)
+    }
+
+    /// Whether the section holds read-only data, such as string literals
+    pub fn is_rodata(&self) -> bool {
+        self.name.starts_with(".rodata")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A loadable segment of a loaded module
+pub struct LoadSegment {
+    /// The segment's position among the module's loadable segments
+    pub index: usize,
+    pub start: u64,
+    /// The address after the last address of the segment's contents in the file
+    pub end: u64,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+impl LoadSegment {
+    pub fn contains(&self, vaddr: u64) -> bool {
+        (self.start..self.end).contains(&vaddr)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A PLT stub of a loaded module, through which it calls a function imported from another
+pub struct PltStub {
+    /// The name of the imported function
+    pub name: String,
+    pub start: u64,
+    /// The address after the last address of the stub
+    pub end: u64,
+    /// The GOT slot the stub jumps through, which holds the function's address once the
+    /// dynamic linker has bound it
+    pub slot: u64,
+}
+
+impl PltStub {
+    pub fn contains(&self, vaddr: u64) -> bool {
+        (self.start..self.end).contains(&vaddr)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// The loaded sections, segments and PLT stubs of a module
+pub struct ModuleMap {
+    pub name: String,
+    pub sections: Vec<Section>,
+    pub segments: Vec<LoadSegment>,
+    pub plt: Vec<PltStub>,
+    /// The difference between the addresses the module was loaded at and those in its file
+    pub bias: u64,
+}
+
+/// The fields of a section header the tracer reads
+struct SectionHeader {
+    name: usize,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    offset: usize,
+    size: usize,
+    link: usize,
+}
+
+/// The fields of a program header the tracer reads
+struct Segment {
+    vaddr: u64,
+    offset: u64,
+    filesz: u64,
+    flags: u32,
+    align: u64,
+}
+
+/// Reads the fields of an ELF file of either class and byte order
+struct Reader<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    /// Check that `data` is an ELF file, and read its class and byte order
+    fn new(data: &'a [u8]) -> Result<Self> {
+        if !data.starts_with(b"\x7fELF") {
+            return Err(anyhow!("Not an ELF file"));
+        }
+
+        let big_endian = match data.get(5) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(anyhow!("Unknown byte order")),
+        };
+
+        Ok(Self {
+            data,
+            is_64: data.get(4) == Some(&2),
+            big_endian,
+        })
+    }
+
+    fn bytes<const N: usize>(&self, offset: usize) -> Result<[u8; N]> {
+        offset
+            .checked_add(N)
+            .and_then(|end| self.data.get(offset..end))
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| anyhow!("Truncated ELF file"))
+    }
+
+    /// Returns the contents of the `size` bytes at `offset`
+    fn slice(&self, offset: usize, size: usize) -> Result<&'a [u8]> {
+        offset
+            .checked_add(size)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or_else(|| anyhow!("Truncated ELF file"))
+    }
+
+    /// Returns the offset of entry `index` of the table of `size`-byte entries at `table`,
+    /// checking that the entry lies within the file. Offsets of fields within the entry are
+    /// then bounded by the file's length, so adding them cannot overflow.
+    fn entry(&self, table: usize, index: usize, size: usize) -> Result<usize> {
+        let offset = index
+            .checked_mul(size)
+            .and_then(|delta| table.checked_add(delta))
+            .ok_or_else(|| anyhow!("Truncated ELF file"))?;
+
+        self.slice(offset, size)?;
+        Ok(offset)
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        Ok(decode(&self.bytes::<2>(offset)?, self.big_endian) as u16)
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        Ok(decode(&self.bytes::<4>(offset)?, self.big_endian) as u32)
+    }
+
+    /// Read a field which is 8 bytes in 64-bit files and 4 in 32-bit files
+    fn word(&self, offset: usize) -> Result<u64> {
+        if self.is_64 {
+            Ok(decode(&self.bytes::<8>(offset)?, self.big_endian))
+        } else {
+            Ok(self.u32(offset)? as u64)
+        }
+    }
+
+    /// Read a field at `offset64` in 64-bit files and `offset32` in 32-bit files
+    fn field(&self, offset64: usize, offset32: usize) -> Result<u64> {
+        self.word(if self.is_64 { offset64 } else { offset32 })
+    }
+
+    fn half(&self, offset64: usize, offset32: usize) -> Result<usize> {
+        Ok(self.u16(if self.is_64 { offset64 } else { offset32 })? as usize)
+    }
+
+    /// Read the string at `offset` in the string table at `table`
+    fn string(&self, table: usize, offset: usize) -> String {
+        let data = table
+            .checked_add(offset)
+            .and_then(|offset| self.data.get(offset..))
+            .unwrap_or_default();
+        let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+
+        String::from_utf8_lossy(&data[..end]).into_owned()
+    }
+
+    fn segments(&self) -> Result<Vec<Segment>> {
+        let phoff = self.field(0x20, 0x1c)? as usize;
+        let phentsize = self.half(0x36, 0x2a)?;
+        let phnum = self.half(0x38, 0x2c)?;
+
+        (0..phnum)
+            .filter_map(|i| self.entry(phoff, i, phentsize).ok())
+            .filter(|header| self.u32(*header).ok() == Some(PT_LOAD))
+            .map(|header| {
+                Ok(Segment {
+                    vaddr: self.field(header + 0x10, header + 8)?,
+                    offset: self.field(header + 8, header + 4)?,
+                    filesz: self.field(header + 0x20, header + 0x10)?,
+                    flags: self.u32(if self.is_64 {
+                        header + 4
+                    } else {
+                        header + 0x18
+                    })?,
+                    align: self.field(header + 0x30, header + 0x1c)?,
+                })
+            })
+            .collect()
+    }
+
+    fn section_headers(&self) -> Result<Vec<SectionHeader>> {
+        let shoff = self.field(0x28, 0x20)? as usize;
+        let shentsize = self.half(0x3a, 0x2e)?;
+        let shnum = self.half(0x3c, 0x30)?;
+
+        (0..shnum)
+            .map(|i| self.entry(shoff, i, shentsize))
+            .map(|header| {
+                let header = header?;
+
+                Ok(SectionHeader {
+                    name: self.u32(header)? as usize,
+                    kind: self.u32(header + 4)?,
+                    flags: self.field(header + 8, header + 8)?,
+                    addr: self.field(header + 0x10, header + 0xc)?,
+                    offset: self.field(header + 0x18, header + 0x10)? as usize,
+                    size: self.field(header + 0x20, header + 0x14)? as usize,
+                    link: self.u32(if self.is_64 {
+                        header + 0x28
+                    } else {
+                        header + 0x18
+                    })? as usize,
+                })
+            })
+            .collect()
+    }
+
+    /// Read the PLT stubs of the file loaded at `bias`, given its section headers and the
+    /// offset of their names
+    fn plt_stubs(
+        &self,
+        headers: &[SectionHeader],
+        strtab: usize,
+        bias: u64,
+    ) -> Result<Vec<PltStub>> {
+        let named = |name: &str| headers.iter().find(|h| self.string(strtab, h.name) == name);
+        let Some((relocations, rela)) = named(".rela.plt")
+            .map(|h| (h, true))
+            .or_else(|| named(".rel.plt").map(|h| (h, false)))
+        else {
+            return Ok(Vec::new());
+        };
+        // Programs built for IBT call through a second PLT, without the header which pushes
+        // the relocation for lazy binding
+        let (plt, header) = match (self.u16(0x12)?, named(".plt.sec")) {
+            (EM_386 | EM_X86_64, Some(plt)) => (Some(plt), 0),
+            (EM_386 | EM_X86_64, None) => (named(".plt"), 16),
+            (EM_ARM, _) => (named(".plt"), 20),
+            (EM_AARCH64 | EM_RISCV | EM_LOONGARCH, _) => (named(".plt"), 32),
+            _ => (None, 0),
+        };
+        let Some(plt) = plt else {
+            return Ok(Vec::new());
+        };
+        let word = if self.is_64 { 8 } else { 4 };
+        let entsize = if rela { word * 3 } else { word * 2 };
+        let count = relocations.size / entsize;
+        // A PLT which does not divide evenly between the relocations has a layout the reader
+        // does not know, such as the lazy PLT of an IBT program
+        let Some(size) = plt
+            .size
+            .checked_sub(header)
+            .filter(|size| count > 0 && size % count == 0)
+            .map(|size| size / count)
+        else {
+            return Ok(Vec::new());
+        };
+        let symtab = headers
+            .get(relocations.link)
+            .ok_or_else(|| anyhow!("PLT relocations have no symbol table"))?;
+        let names = headers
+            .get(symtab.link)
+            .ok_or_else(|| anyhow!("Symbol table has no string table"))?
+            .offset;
+        let symsize = if self.is_64 { 24 } else { 16 };
+
+        let stubs = (0..count)
+            .map(|i| {
+                let relocation = self.entry(relocations.offset, i, entsize)?;
+                let info = self.word(relocation + word)?;
+                let symbol = if self.is_64 { info >> 32 } else { info >> 8 } as usize;
+
+                // Relocations without a symbol, like those of ifuncs, import nothing
+                if symbol == 0 {
+                    return Ok(None);
+                }
+
+                let start = plt
+                    .addr
+                    .wrapping_add(bias)
+                    .wrapping_add((header + i * size) as u64);
+                let name = self.u32(self.entry(symtab.offset, symbol, symsize)?)? as usize;
+
+                Ok(Some(PltStub {
+                    name: self.string(names, name),
+                    start,
+                    end: start.wrapping_add(size as u64),
+                    slot: self.word(relocation)?.wrapping_add(bias),
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(stubs.into_iter().flatten().collect())
+    }
+}
+
+/// Whether the ELF file `data` is big-endian
+pub fn big_endian(data: &[u8]) -> Result<bool> {
+    Ok(Reader::new(data)?.big_endian)
+}
+
+/// Returns the GNU build-id of the ELF file `data` in hex, if it has one
+pub fn build_id(data: &[u8]) -> Result<Option<String>> {
+    let elf = Reader::new(data)?;
+
+    for section in elf.section_headers()? {
+        if section.kind != SHT_NOTE {
+            continue;
+        }
+
+        // Offsets within the section's contents are bounded by its size
+        let notes = Reader {
+            data: elf.slice(section.offset, section.size)?,
+            ..elf
+        };
+        let mut note = 0;
+
+        while note + 12 <= notes.data.len() {
+            let namesz = notes.u32(note)? as usize;
+            let descsz = notes.u32(note + 4)? as usize;
+            let desc = namesz
+                .checked_next_multiple_of(4)
+                .and_then(|namesz| (note + 12).checked_add(namesz))
+                .ok_or_else(|| anyhow!("Truncated ELF file"))?;
+
+            if notes.u32(note + 8)? == NT_GNU_BUILD_ID && notes.string(note, 12) == "GNU" {
+                let id = notes.slice(desc, descsz)?;
+
+                return Ok(Some(id.iter().map(|b| format!("{b:02x}")).collect()));
+            }
+
+            note = descsz
+                .checked_next_multiple_of(4)
+                .and_then(|descsz| desc.checked_add(descsz))
+                .ok_or_else(|| anyhow!("Truncated ELF file"))?;
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the contents of the section `name` of the ELF file `data`, if it has one,
+/// decompressed if it is compressed with zlib
+pub fn section_data<'a>(data: &'a [u8], name: &str) -> Result<Option<Cow<'a, [u8]>>> {
+    let elf = Reader::new(data)?;
+    let headers = elf.section_headers()?;
+    let strtab = headers
+        .get(elf.half(0x3e, 0x32)?)
+        .ok_or_else(|| anyhow!("Truncated ELF file"))?
+        .offset;
+    let Some(section) = headers.iter().find(|h| elf.string(strtab, h.name) == name) else {
+        return Ok(None);
+    };
+    let contents = elf.slice(section.offset, section.size)?;
+
+    if section.flags & SHF_COMPRESSED == 0 {
+        return Ok(Some(Cow::Borrowed(contents)));
+    }
+
+    let header = if elf.is_64 { 24 } else { 12 };
+
+    if elf.u32(section.offset)? != ELFCOMPRESS_ZLIB {
+        return Err(anyhow!("{name} is compressed with an unsupported format"));
+    }
+
+    decompress_to_vec_zlib(contents.get(header..).unwrap_or_default())
+        .map(|contents| Some(Cow::Owned(contents)))
+        .map_err(|e| anyhow!("Failed to decompress {name}: {e:?}"))
+}
+
+/// Returns the bias of a module mapped at `start` from `offset` in its ELF file `data`, so
+/// that an address in the mapping less the bias is the address in the file
+pub fn mapping_bias(data: &[u8], start: u64, offset: u64) -> Result<u64> {
+    let segment = Reader::new(data)?
+        .segments()?
+        .into_iter()
+        .find(|s| {
+            s.offset - s.offset % s.align.max(1) <= offset
+                && s.offset
+                    .checked_add(s.filesz)
+                    .is_some_and(|end| offset < end)
+        })
+        .ok_or_else(|| anyhow!("No segment is mapped from offset {offset:#x}"))?;
+
+    Ok(start
+        .wrapping_sub(offset)
+        .wrapping_add(segment.offset)
+        .wrapping_sub(segment.vaddr))
+}
+
+#[derive(Clone, Debug, Default)]
+/// The function symbols of an ELF file, by their address in the file
+pub struct SymbolTable {
+    /// The address, size and name of each function, sorted by address
+    symbols: Vec<(u64, u64, String)>,
+}
+
+impl SymbolTable {
+    /// Read the functions in the static and dynamic symbol tables of the ELF file `data`
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let elf = Reader::new(data)?;
+        let sections = elf.section_headers()?;
+        let mut symbols = Vec::new();
+
+        let mut tables = sections
+            .iter()
+            .filter(|s| s.kind == SHT_SYMTAB || s.kind == SHT_DYNSYM)
+            .collect::<Vec<_>>();
+        // A function in both tables keeps its name from the static table
+        tables.sort_by_key(|s| s.kind != SHT_SYMTAB);
+
+        for table in tables {
+            let strtab = sections
+                .get(table.link)
+                .ok_or_else(|| anyhow!("Symbol table has no string table"))?
+                .offset;
+            let entsize = if elf.is_64 { 24 } else { 16 };
+
+            for i in 0..table.size / entsize {
+                let symbol = elf.entry(table.offset, i, entsize)?;
+                let (info, value, size) = if elf.is_64 {
+                    (
+                        elf.bytes::<1>(symbol + 4)?[0],
+                        elf.word(symbol + 8)?,
+                        elf.word(symbol + 0x10)?,
+                    )
+                } else {
+                    (
+                        elf.bytes::<1>(symbol + 0xc)?[0],
+                        elf.word(symbol + 4)?,
+                        elf.word(symbol + 8)?,
+                    )
+                };
+
+                if info & 0xf == STT_FUNC && value != 0 {
+                    let name = elf.string(strtab, elf.u32(symbol)? as usize);
+                    symbols.push((value, size, name));
+                }
+            }
+        }
+
+        symbols.sort_by_key(|(value, _, _)| *value);
+        symbols.dedup_by_key(|(value, _, _)| *value);
+
+        Ok(Self { symbols })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Returns the name of the function containing `vaddr`, an address in the file
+    pub fn lookup(&self, vaddr: u64) -> Option<&str> {
+        let index = self
+            .symbols
+            .partition_point(|(value, _, _)| *value <= vaddr);
+        let (value, size, name) = self.symbols.get(index.checked_sub(1)?)?;
+
+        (vaddr - value < (*size).max(1)).then_some(name.as_str())
+    }
+}
+
+impl ModuleMap {
+    /// Read the allocated sections of the ELF file at `path`, relocated to where the
+    /// program was loaded given `start_code`, the address of its first executable segment
+    pub fn load<P>(path: P, start_code: Option<u64>) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let data = read(path)?;
+        let elf = Reader::new(&data).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        let bias = match start_code {
+            Some(start_code) if elf.u16(0x10)? == ET_DYN => elf
+                .segments()?
+                .iter()
+                .filter(|s| s.flags & PF_X != 0)
+                .map(|s| s.vaddr)
+                .min()
+                .map_or(0, |lowest| start_code.wrapping_sub(lowest)),
+            _ => 0,
+        };
+
+        Self::read(path, &elf, bias)
+    }
+
+    /// Read the allocated sections and loadable segments of the ELF file at `path`, loaded
+    /// at `bias`, as a library mapped by the guest is
+    pub fn load_at<P>(path: P, bias: u64) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let data = read(path)?;
+        let elf = Reader::new(&data).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+
+        Self::read(path, &elf, bias)
+    }
+
+    fn read(path: &Path, elf: &Reader, bias: u64) -> Result<Self> {
+        let shstrndx = elf.half(0x3e, 0x32)?;
+        let headers = elf.section_headers()?;
+        let strtab = headers
+            .get(shstrndx)
+            .ok_or_else(|| anyhow!("Truncated ELF file"))?
+            .offset;
+        let sections = headers
+            .iter()
+            .filter(|h| h.flags & SHF_ALLOC != 0)
+            .map(|h| Section {
+                name: elf.string(strtab, h.name),
+                start: h.addr.wrapping_add(bias),
+                end: h.addr.wrapping_add(bias).wrapping_add(h.size as u64),
+                writable: h.flags & SHF_WRITE != 0,
+                executable: h.flags & SHF_EXECINSTR != 0,
+            })
+            .collect();
+        let segments = elf
+            .segments()?
+            .iter()
+            .enumerate()
+            .map(|(index, s)| LoadSegment {
+                index,
+                start: s.vaddr.wrapping_add(bias),
+                end: s.vaddr.wrapping_add(bias).wrapping_add(s.filesz),
+                writable: s.flags & PF_W != 0,
+                executable: s.flags & PF_X != 0,
+            })
+            .collect();
+        let plt = elf.plt_stubs(&headers, strtab, bias)?;
+
+        Ok(Self {
+            name: path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            sections,
+            segments,
+            plt,
+            bias,
+        })
+    }
+
+    /// Returns the section containing `vaddr`, if any
+    pub fn section(&self, vaddr: u64) -> Option<&Section> {
+        self.sections.iter().find(|s| s.contains(vaddr))
+    }
+
+    /// Returns the loadable segment containing `vaddr`, if any
+    pub fn segment(&self, vaddr: u64) -> Option<&LoadSegment> {
+        self.segments.iter().find(|s| s.contains(vaddr))
+    }
+
+    /// Returns the PLT stub containing `vaddr`, if any
+    pub fn plt_stub(&self, vaddr: u64) -> Option<&PltStub> {
+        self.plt.iter().find(|s| s.contains(vaddr))
+    }
+}
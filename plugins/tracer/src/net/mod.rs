@@ -0,0 +1,466 @@
+//! Monitoring of guest socket syscalls, reconstructing the payloads sent and received into a
+//! pcap file with synthetic IP and TCP/UDP headers so it can be opened in Wireshark
+//!
+//! Only `AF_INET` and `AF_INET6` sockets are tracked. On i386, guests which multiplex socket
+//! operations through `socketcall` are not supported.
+
+use crate::{
+    arch::{Arch, Syscall},
+    guest::read_pointer,
+};
+use anyhow::Result;
+use qemu_plugin::{path::create_sink, qemu_plugin_read_memory_vaddr, VCPUIndex};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const AF_INET: u64 = 2;
+const AF_INET6: u64 = 10;
+const SOCK_STREAM: u64 = 1;
+const SOCK_DGRAM: u64 = 2;
+const EINPROGRESS: i64 = 115;
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// pcap link type for packets which begin with an IPv4 or IPv6 header
+const LINKTYPE_RAW: u32 = 101;
+/// The largest payload placed in a single synthetic packet
+const MAX_SEGMENT: usize = 1400;
+
+/// The address used for the guest's end of a connection when it is not bound explicitly
+const GUEST_V4: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+const GUEST_V6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x15);
+
+#[derive(Debug)]
+/// Writer for the classic (non-ng) pcap file format
+pub struct PcapWriter {
+    file: BufWriter<File>,
+}
+
+impl PcapWriter {
+    /// Create a pcap file at `path` and write its global header
+    pub fn create<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = BufWriter::new(create_sink(path)?);
+        file.write_all(&0xa1b2c3d4u32.to_le_bytes())?;
+        file.write_all(&2u16.to_le_bytes())?;
+        file.write_all(&4u16.to_le_bytes())?;
+        file.write_all(&0i32.to_le_bytes())?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(&65535u32.to_le_bytes())?;
+        file.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        Ok(Self { file })
+    }
+
+    /// Write one packet, timestamped with the current host time
+    pub fn write_packet(&mut self, packet: &[u8]) -> Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        self.file.write_all(&(now.as_secs() as u32).to_le_bytes())?;
+        self.file.write_all(&now.subsec_micros().to_le_bytes())?;
+        self.file.write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.file.write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.file.write_all(packet)?;
+        Ok(())
+    }
+
+    /// Flush buffered packets to disk
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+fn ip_packet(src: IpAddr, dst: IpAddr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(40 + payload.len());
+
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            let sum = checksum(&packet);
+            packet[10..12].copy_from_slice(&sum.to_be_bytes());
+        }
+        (src, dst) => {
+            let to_v6 = |a: IpAddr| match a {
+                IpAddr::V4(a) => a.to_ipv6_mapped(),
+                IpAddr::V6(a) => a,
+            };
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&[protocol, 64]);
+            packet.extend_from_slice(&to_v6(src).octets());
+            packet.extend_from_slice(&to_v6(dst).octets());
+        }
+    }
+
+    packet.extend_from_slice(payload);
+    packet
+}
+
+fn tcp_segment(src: u16, dst: u16, seq: u32, ack: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(20 + payload.len());
+    segment.extend_from_slice(&src.to_be_bytes());
+    segment.extend_from_slice(&dst.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.extend_from_slice(&[5 << 4, flags]);
+    segment.extend_from_slice(&0xffffu16.to_be_bytes());
+    // NOTE: The TCP checksum is left zero, Wireshark does not validate it by default
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(payload);
+    segment
+}
+
+fn udp_datagram(src: u16, dst: u16, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(8 + payload.len());
+    datagram.extend_from_slice(&src.to_be_bytes());
+    datagram.extend_from_slice(&dst.to_be_bytes());
+    datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+/// Parse a guest `sockaddr_in` or `sockaddr_in6`
+fn read_sockaddr(arch: Arch, addr: u64, len: u64) -> Result<Option<SocketAddr>> {
+    if addr == 0 || len < 8 {
+        return Ok(None);
+    }
+
+    let data = qemu_plugin_read_memory_vaddr(addr, (len as usize).min(28))?;
+    // The family is in the guest's byte order, and the port and address in network order
+    let family = arch.decode(&data[..2]);
+    let port = u16::from_be_bytes([data[2], data[3]]);
+
+    Ok(match family {
+        AF_INET => Some(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(data[4], data[5], data[6], data[7])),
+            port,
+        )),
+        AF_INET6 if data.len() >= 24 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&data[8..24]);
+            Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+        }
+        _ => None,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SocketKind {
+    Stream,
+    Datagram,
+}
+
+#[derive(Clone, Debug)]
+struct Socket {
+    kind: SocketKind,
+    ipv6: bool,
+    local: Option<SocketAddr>,
+    remote: Option<SocketAddr>,
+    seq_out: u32,
+    seq_in: u32,
+}
+
+#[derive(Clone, Debug)]
+struct PendingSyscall {
+    syscall: Syscall,
+    args: [u64; 8],
+}
+
+#[derive(Debug)]
+/// Tracks guest sockets across syscalls and writes their traffic to a pcap file
+pub struct NetTracker {
+    pcap: PcapWriter,
+    sockets: HashMap<u64, Socket>,
+    pending: HashMap<VCPUIndex, PendingSyscall>,
+}
+
+impl NetTracker {
+    /// Create a tracker writing packets to a new pcap file at `path`
+    pub fn new<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self {
+            pcap: PcapWriter::create(path)?,
+            sockets: HashMap::new(),
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Record the arguments of a syscall on entry
+    pub fn on_syscall(&mut self, arch: Arch, vcpu_index: VCPUIndex, num: i64, args: [u64; 8]) {
+        if let Some(syscall) = arch.syscall(num) {
+            self.pending
+                .insert(vcpu_index, PendingSyscall { syscall, args });
+        }
+    }
+
+    /// Update socket state and write packets for any payload moved by a returning syscall
+    pub fn on_syscall_return(&mut self, arch: Arch, vcpu_index: VCPUIndex, ret: i64) -> Result<()> {
+        let Some(PendingSyscall { syscall, args }) = self.pending.remove(&vcpu_index) else {
+            return Ok(());
+        };
+
+        let fd = args[0];
+
+        match syscall {
+            Syscall::Socket if ret >= 0 => {
+                let kind = match args[1] & 0xf {
+                    SOCK_STREAM => SocketKind::Stream,
+                    SOCK_DGRAM => SocketKind::Datagram,
+                    _ => return Ok(()),
+                };
+
+                if matches!(args[0], AF_INET | AF_INET6) {
+                    self.sockets.insert(
+                        ret as u64,
+                        Socket {
+                            kind,
+                            ipv6: args[0] == AF_INET6,
+                            local: None,
+                            remote: None,
+                            seq_out: 1,
+                            seq_in: 1,
+                        },
+                    );
+                }
+            }
+            Syscall::Bind if ret == 0 => {
+                let local = read_sockaddr(arch, args[1], args[2])?;
+                if let Some(socket) = self.sockets.get_mut(&fd) {
+                    socket.local = local;
+                }
+            }
+            Syscall::Connect if ret == 0 || ret == -EINPROGRESS => {
+                let remote = read_sockaddr(arch, args[1], args[2])?;
+                if let Some(socket) = self.sockets.get_mut(&fd) {
+                    socket.remote = remote;
+                }
+                self.handshake(fd, true)?;
+            }
+            Syscall::Accept | Syscall::Accept4 if ret >= 0 => {
+                let Some(listener) = self.sockets.get(&fd).cloned() else {
+                    return Ok(());
+                };
+                let remote = if args[1] != 0 && args[2] != 0 {
+                    read_sockaddr(arch, args[1], read_pointer(arch, args[2], 4)?)?
+                } else {
+                    None
+                };
+                self.sockets.insert(
+                    ret as u64,
+                    Socket {
+                        remote,
+                        seq_out: 1,
+                        seq_in: 1,
+                        ..listener
+                    },
+                );
+                self.handshake(ret as u64, false)?;
+            }
+            Syscall::Write | Syscall::Sendto if ret > 0 => {
+                let remote = if syscall == Syscall::Sendto {
+                    read_sockaddr(arch, args[4], args[5])?
+                } else {
+                    None
+                };
+                self.payload(fd, true, args[1], ret as usize, remote)?;
+            }
+            Syscall::Read | Syscall::Recvfrom if ret > 0 => {
+                let remote = if syscall == Syscall::Recvfrom && args[4] != 0 && args[5] != 0 {
+                    read_sockaddr(arch, args[4], read_pointer(arch, args[5], 4)?)?
+                } else {
+                    None
+                };
+                self.payload(fd, false, args[1], ret as usize, remote)?;
+            }
+            Syscall::Sendmsg | Syscall::Recvmsg if ret > 0 => {
+                self.message(arch, fd, syscall == Syscall::Sendmsg, args[1], ret as usize)?;
+            }
+            Syscall::Close if ret == 0 => {
+                if self
+                    .sockets
+                    .get(&fd)
+                    .is_some_and(|s| s.kind == SocketKind::Stream && s.remote.is_some())
+                {
+                    self.segment(fd, true, TCP_FIN | TCP_ACK, &[])?;
+                }
+                self.sockets.remove(&fd);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn endpoints(socket: &Socket, fd: u64, remote: Option<SocketAddr>) -> (SocketAddr, SocketAddr) {
+        let local = socket.local.unwrap_or_else(|| {
+            let ip = if socket.ipv6 {
+                IpAddr::V6(GUEST_V6)
+            } else {
+                IpAddr::V4(GUEST_V4)
+            };
+            SocketAddr::new(ip, 40000 + (fd % 20000) as u16)
+        });
+        let remote = remote.or(socket.remote).unwrap_or_else(|| {
+            SocketAddr::new(
+                if socket.ipv6 {
+                    IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+                } else {
+                    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+                },
+                0,
+            )
+        });
+        (local, remote)
+    }
+
+    fn segment(&mut self, fd: u64, outgoing: bool, flags: u8, payload: &[u8]) -> Result<()> {
+        let Some(socket) = self.sockets.get_mut(&fd) else {
+            return Ok(());
+        };
+        let (local, remote) = Self::endpoints(socket, fd, None);
+        let (src, dst, seq, ack) = if outgoing {
+            (local, remote, socket.seq_out, socket.seq_in)
+        } else {
+            (remote, local, socket.seq_in, socket.seq_out)
+        };
+
+        let advance = payload.len() as u32 + u32::from(flags & (TCP_SYN | TCP_FIN) != 0);
+        if outgoing {
+            socket.seq_out = socket.seq_out.wrapping_add(advance);
+        } else {
+            socket.seq_in = socket.seq_in.wrapping_add(advance);
+        }
+
+        let segment = tcp_segment(src.port(), dst.port(), seq, ack, flags, payload);
+        self.pcap
+            .write_packet(&ip_packet(src.ip(), dst.ip(), IPPROTO_TCP, &segment))
+    }
+
+    /// Write a synthetic three-way handshake for a newly connected stream socket
+    fn handshake(&mut self, fd: u64, outgoing: bool) -> Result<()> {
+        if self.sockets.get(&fd).map(|s| s.kind) != Some(SocketKind::Stream) {
+            return Ok(());
+        }
+
+        self.segment(fd, outgoing, TCP_SYN, &[])?;
+        self.segment(fd, !outgoing, TCP_SYN | TCP_ACK, &[])?;
+        self.segment(fd, outgoing, TCP_ACK, &[])
+    }
+
+    fn payload(
+        &mut self,
+        fd: u64,
+        outgoing: bool,
+        buf: u64,
+        len: usize,
+        remote: Option<SocketAddr>,
+    ) -> Result<()> {
+        let Some(socket) = self.sockets.get(&fd) else {
+            return Ok(());
+        };
+        let data = qemu_plugin_read_memory_vaddr(buf, len)?;
+        self.send_data(fd, socket.kind, outgoing, &data, remote)
+    }
+
+    fn send_data(
+        &mut self,
+        fd: u64,
+        kind: SocketKind,
+        outgoing: bool,
+        data: &[u8],
+        remote: Option<SocketAddr>,
+    ) -> Result<()> {
+        match kind {
+            SocketKind::Stream => data
+                .chunks(MAX_SEGMENT)
+                .try_for_each(|chunk| self.segment(fd, outgoing, TCP_PSH | TCP_ACK, chunk))?,
+            SocketKind::Datagram => {
+                let Some(socket) = self.sockets.get(&fd) else {
+                    return Ok(());
+                };
+                let (local, remote) = Self::endpoints(socket, fd, remote);
+                let (src, dst) = if outgoing {
+                    (local, remote)
+                } else {
+                    (remote, local)
+                };
+                // NOTE: Datagrams are truncated rather than fragmented
+                let data = &data[..data.len().min(u16::MAX as usize - 48)];
+                let datagram = udp_datagram(src.port(), dst.port(), data);
+                self.pcap
+                    .write_packet(&ip_packet(src.ip(), dst.ip(), IPPROTO_UDP, &datagram))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gather the payload of a `sendmsg` or `recvmsg` from its `msghdr`
+    fn message(&mut self, arch: Arch, fd: u64, outgoing: bool, msg: u64, len: usize) -> Result<()> {
+        let Some(socket) = self.sockets.get(&fd) else {
+            return Ok(());
+        };
+        let kind = socket.kind;
+        let ptr = arch.pointer_size();
+        let name = read_pointer(arch, msg, ptr)?;
+        let namelen = read_pointer(arch, msg + ptr as u64, 4)?;
+        let iov = read_pointer(arch, msg + 2 * ptr as u64, ptr)?;
+        let iovlen = read_pointer(arch, msg + 3 * ptr as u64, ptr)?;
+
+        let remote = read_sockaddr(arch, name, namelen)?;
+        let mut data = Vec::with_capacity(len);
+
+        for i in 0..iovlen {
+            if data.len() >= len {
+                break;
+            }
+            let entry = iov + i * 2 * ptr as u64;
+            let base = read_pointer(arch, entry, ptr)?;
+            let size = read_pointer(arch, entry + ptr as u64, ptr)? as usize;
+            let size = size.min(len - data.len());
+            if size > 0 {
+                data.extend(qemu_plugin_read_memory_vaddr(base, size)?);
+            }
+        }
+
+        self.send_data(fd, kind, outgoing, &data, remote)
+    }
+
+    /// Flush buffered packets to disk
+    pub fn flush(&mut self) -> Result<()> {
+        self.pcap.flush()
+    }
+}
@@ -0,0 +1,367 @@
+//! A trace sink streaming compressed chunks to S3-compatible object storage
+//!
+//! Events are buffered into chunks of `chunk_size` bytes, which are compressed with zlib
+//! and uploaded with `PUT` under the URL's prefix as `chunk-<n>.cbor.zlib`, by a background
+//! thread so that vCPUs never wait on the network. Failed uploads are retried with
+//! exponential backoff. After each chunk, `manifest.json` is rewritten to list the chunks
+//! uploaded so far, and marked complete once the trace is finished, so a reader can tell a
+//! trace cut short from a whole one. Decompressed and concatenated in order, the chunks are
+//! the event stream.
+//!
+//! URLs are path-style, `http[s]://host[:port]/bucket[/prefix]`, as MinIO, Ceph and most S3
+//! gateways accept, or `s3://bucket[/prefix]` for AWS itself. Requests are signed with AWS
+//! Signature Version 4 when `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` are set, which
+//! also works with GCS's interoperability keys, and the other `AWS_` variables the
+//! `object_store` crate reads, such as `AWS_SESSION_TOKEN`, apply.
+
+use crate::{log, Event};
+use ::object_store::{
+    aws::{AmazonS3, AmazonS3Builder},
+    path::Path as ObjectPath,
+    BackoffConfig, ClientOptions, ObjectStoreExt, RetryConfig,
+};
+use anyhow::{anyhow, Result};
+use miniz_oxide::{deflate::compress_to_vec_zlib, inflate::decompress_to_vec_zlib};
+use serde::{Deserialize, Serialize};
+use serde_cbor::Deserializer;
+use sha2::{Digest, Sha256};
+use std::{
+    env::var_os,
+    fs::{read, File},
+    io::{self, Write},
+    path::Path,
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread::{spawn, JoinHandle},
+    time::Duration,
+};
+use tokio::runtime::{Builder, Runtime};
+
+const VERSION: u64 = 1;
+/// The name of the manifest under the URL's prefix
+const MANIFEST: &str = "manifest.json";
+/// The zlib level chunks are compressed at
+const LEVEL: u8 = 6;
+/// How many times an upload is attempted before its chunk is given up on
+const ATTEMPTS: usize = 5;
+/// The delay before the first retry, doubled for each one after
+const BACKOFF: Duration = Duration::from_millis(500);
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// How many chunks may wait for upload before writers block
+const QUEUE: usize = 4;
+/// The default size of a chunk before compression
+pub const CHUNK_SIZE: usize = 8 << 20;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Where objects are stored
+pub struct Endpoint {
+    /// The scheme, host and, if given, port of the server, or `None` for AWS itself
+    pub server: Option<String>,
+    pub bucket: String,
+    /// The prefix of object keys, empty or ending in `/`
+    pub prefix: String,
+}
+
+impl Endpoint {
+    /// Parse a path-style URL, `http[s]://host[:port]/bucket[/prefix]`, or an AWS URL,
+    /// `s3://bucket[/prefix]`
+    pub fn parse(url: &str) -> Result<Self> {
+        let (server, path) = match url.split_once("://") {
+            Some((scheme @ ("http" | "https"), rest)) => {
+                let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+
+                if host.is_empty() {
+                    return Err(anyhow!("Object storage URL {url} has no host"));
+                }
+
+                (Some(format!("{scheme}://{host}")), path)
+            }
+            Some(("s3", path)) => (None, path),
+            _ => return Err(anyhow!("Invalid object storage URL {url}")),
+        };
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+
+        if bucket.is_empty() {
+            return Err(anyhow!("Object storage URL {url} has no bucket"));
+        }
+
+        let prefix = prefix.trim_end_matches('/');
+
+        Ok(Self {
+            server,
+            bucket: bucket.to_string(),
+            prefix: if prefix.is_empty() {
+                String::new()
+            } else {
+                format!("{prefix}/")
+            },
+        })
+    }
+
+    fn path(&self, name: &str) -> ObjectPath {
+        ObjectPath::from(format!("{}{name}", self.prefix))
+    }
+}
+
+#[derive(Debug)]
+/// Uploads objects, retrying failures with exponential backoff
+struct Client {
+    endpoint: Endpoint,
+    store: AmazonS3,
+    /// Drives the store's requests on the uploader thread
+    runtime: Runtime,
+}
+
+impl Client {
+    /// Create a client for `endpoint` in `region`, signing requests if credentials are set
+    fn new(endpoint: Endpoint, region: &str) -> Result<Self> {
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(&endpoint.bucket)
+            .with_region(region)
+            .with_skip_signature(var_os("AWS_ACCESS_KEY_ID").is_none())
+            .with_client_options(ClientOptions::new().with_timeout(TIMEOUT))
+            .with_retry(RetryConfig {
+                backoff: BackoffConfig {
+                    init_backoff: BACKOFF,
+                    max_backoff: BACKOFF * 2u32.pow(ATTEMPTS as u32),
+                    base: 2.,
+                },
+                max_retries: ATTEMPTS - 1,
+                ..Default::default()
+            });
+
+        if let Some(server) = endpoint.server.as_ref() {
+            builder = builder
+                .with_endpoint(server)
+                .with_allow_http(server.starts_with("http://"))
+                .with_virtual_hosted_style_request(false);
+        }
+
+        Ok(Self {
+            store: builder.build()?,
+            endpoint,
+            runtime: Builder::new_current_thread().enable_all().build()?,
+        })
+    }
+
+    /// Upload `body` as the object `name`, returning an error once every attempt has failed
+    fn put_with_retry(&self, name: &str, body: &[u8]) -> Result<()> {
+        self.runtime
+            .block_on(
+                self.store
+                    .put(&self.endpoint.path(name), body.to_vec().into()),
+            )
+            .map_err(|e| anyhow!("Uploading {name} failed: {e}"))?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// An uploaded chunk
+pub struct Chunk {
+    pub name: String,
+    /// The size of the chunk before compression
+    pub size: usize,
+    pub compressed_size: usize,
+    /// The SHA-256 of the compressed chunk
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// Lists the chunks of a trace in object storage
+pub struct ChunkManifest {
+    pub version: u64,
+    /// The chunks uploaded, in order
+    pub chunks: Vec<Chunk>,
+    /// The chunks which could not be uploaded, leaving a gap in the trace
+    pub missing: Vec<String>,
+    /// Whether the trace was finished, rather than cut short
+    pub complete: bool,
+}
+
+/// Work for the uploader thread
+#[derive(Debug)]
+enum Upload {
+    /// The next chunk of the trace, and its index
+    Chunk(usize, Vec<u8>),
+    /// An object stored beside the trace, uploaded as is
+    Object(String, Vec<u8>),
+}
+
+/// Compress and upload each chunk received, and rewrite the manifest after each
+fn upload(client: Client, uploads: Receiver<Upload>) -> Result<()> {
+    let mut manifest = ChunkManifest {
+        version: VERSION,
+        ..Default::default()
+    };
+
+    for upload in uploads {
+        let (index, data) = match upload {
+            Upload::Chunk(index, data) => (index, data),
+            Upload::Object(name, data) => {
+                if let Err(e) = client.put_with_retry(&name, &data) {
+                    log(format!("Failed to upload {name}: {e}"));
+                }
+
+                continue;
+            }
+        };
+        let name = format!("chunk-{index:06}.cbor.zlib");
+        let compressed = compress_to_vec_zlib(&data, LEVEL);
+
+        match client.put_with_retry(&name, &compressed) {
+            Ok(()) => manifest.chunks.push(Chunk {
+                name,
+                size: data.len(),
+                compressed_size: compressed.len(),
+                sha256: hex(&Sha256::digest(&compressed)),
+            }),
+            Err(e) => {
+                log(format!("Failed to upload trace chunk {name}: {e}"));
+                manifest.missing.push(name);
+            }
+        }
+
+        client.put_with_retry(MANIFEST, &serde_json::to_vec_pretty(&manifest)?)?;
+    }
+
+    manifest.complete = true;
+    client.put_with_retry(MANIFEST, &serde_json::to_vec_pretty(&manifest)?)?;
+
+    if manifest.missing.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Failed to upload {} trace chunks",
+            manifest.missing.len()
+        ))
+    }
+}
+
+#[derive(Debug)]
+/// A sink uploading events to object storage in chunks
+pub struct ObjectSink {
+    buffer: Vec<u8>,
+    chunk_size: usize,
+    next: usize,
+    uploads: Option<SyncSender<Upload>>,
+    uploader: Option<JoinHandle<Result<()>>>,
+}
+
+impl ObjectSink {
+    /// Create a sink uploading to the path-style `url` in `region`, in chunks of
+    /// `chunk_size` bytes
+    pub fn create(url: &str, region: &str, chunk_size: usize) -> Result<Self> {
+        let client = Client::new(Endpoint::parse(url)?, region)?;
+        let (uploads, receiver) = sync_channel(QUEUE);
+
+        Ok(Self {
+            buffer: Vec::with_capacity(chunk_size),
+            chunk_size,
+            next: 0,
+            uploads: Some(uploads),
+            uploader: Some(spawn(move || upload(client, receiver))),
+        })
+    }
+
+    fn send(&self, upload: Upload) -> io::Result<()> {
+        self.uploads
+            .as_ref()
+            .ok_or_else(|| io::Error::other("Object sink is finished"))?
+            .send(upload)
+            .map_err(|_| io::Error::other("Trace uploader stopped"))
+    }
+
+    fn send_chunk(&mut self) -> io::Result<()> {
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.chunk_size));
+
+        self.send(Upload::Chunk(self.next, chunk))?;
+        self.next += 1;
+
+        Ok(())
+    }
+
+    /// Upload `data` as the object `name` beside the trace, such as its run manifest
+    pub fn put(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        Ok(self.send(Upload::Object(name.to_string(), data))?)
+    }
+
+    /// Upload the last chunk and a complete manifest, waiting for every upload to finish
+    pub fn finish(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.send_chunk()?;
+        }
+
+        self.uploads.take();
+
+        match self.uploader.take() {
+            Some(uploader) => uploader
+                .join()
+                .map_err(|_| anyhow!("Trace uploader panicked"))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for ObjectSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        if self.buffer.len() >= self.chunk_size {
+            self.send_chunk()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Read the events of a trace downloaded from object storage into `dir`, with its manifest
+/// and chunks, checking each chunk's hash
+pub fn read_chunks<P>(dir: P) -> Result<Vec<Event>>
+where
+    P: AsRef<Path>,
+{
+    let manifest: ChunkManifest =
+        serde_json::from_reader(File::open(dir.as_ref().join(MANIFEST))?)?;
+
+    if manifest.version != VERSION {
+        return Err(anyhow!(
+            "Unsupported chunk manifest version {}",
+            manifest.version
+        ));
+    }
+
+    if !manifest.missing.is_empty() {
+        return Err(anyhow!(
+            "Trace is missing chunks {}",
+            manifest.missing.join(", ")
+        ));
+    }
+
+    let mut data = Vec::new();
+
+    for chunk in &manifest.chunks {
+        let compressed = read(dir.as_ref().join(&chunk.name))?;
+
+        if hex(&Sha256::digest(&compressed)) != chunk.sha256 {
+            return Err(anyhow!("Chunk {} does not match its hash", chunk.name));
+        }
+
+        data.extend(
+            decompress_to_vec_zlib(&compressed)
+                .map_err(|e| anyhow!("Failed to decompress chunk {}: {e:?}", chunk.name))?,
+        );
+    }
+
+    Ok(Deserializer::from_slice(&data)
+        .into_iter::<Event>()
+        .collect::<std::result::Result<_, _>>()?)
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,166 @@
+//! Stub behaviors for MMIO registers, for bringing up firmware on a machine without models of
+//! its peripherals
+//!
+//! Registers are loaded from a JSON file containing a list of [`PeriphRegister`]s, each
+//! covering an inclusive range of physical addresses and answering reads with a constant, a
+//! sequence of values, or values scripted by what the firmware last wrote, as for a status
+//! register which reports ready once a command is written.
+//!
+//! As with fault injection, the plugin API does not yet expose register or memory writes, so
+//! the answers cannot be patched into the register a load writes. Each read of a stubbed
+//! register is instead reported as a [`PeriphEvent`] with the value the device returned and
+//! the value the stub would have, which shows where the firmware polls and what it expects.
+
+use anyhow::{anyhow, Result};
+use qemu_plugin::{MemValue, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{fs::read_to_string, path::Path};
+use typed_builder::TypedBuilder;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// How a stubbed register answers reads
+pub enum Behavior {
+    /// Every read returns the same value
+    Constant(u64),
+    /// Reads return each value in turn, then repeat the last, or start over if `repeat`
+    Sequence {
+        values: Vec<u64>,
+        #[serde(default)]
+        repeat: bool,
+    },
+    /// Reads return the value paired with the last value written, or `initial` until a
+    /// value with a response is written
+    Script {
+        responses: Vec<(u64, u64)>,
+        #[serde(default)]
+        initial: u64,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A stubbed MMIO register
+pub struct PeriphRegister {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// An inclusive range of physical addresses
+    pub address: (u64, u64),
+    pub behavior: Behavior,
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+pub struct PeriphEvent {
+    pub register: usize,
+    pub name: Option<String>,
+    pub pc: u64,
+    pub hwaddr: u64,
+    /// The value the device returned
+    pub observed: u64,
+    /// The value the stub answers with
+    pub stubbed: u64,
+    pub vcpu_index: VCPUIndex,
+    pub icount: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+struct RegisterState {
+    reads: usize,
+    /// The response to the last value written, for scripted registers
+    scripted: Option<u64>,
+}
+
+/// Returns a loaded or stored value, truncating 128-bit values
+pub fn mem_value(value: &MemValue) -> u64 {
+    match value {
+        MemValue::U8(v) => *v as u64,
+        MemValue::U16(v) => *v as u64,
+        MemValue::U32(v) => *v as u64,
+        MemValue::U64(v) => *v,
+        MemValue::U128(v) => *v as u64,
+    }
+}
+
+#[derive(Debug)]
+/// Answers accesses to stubbed MMIO registers
+pub struct Peripherals {
+    registers: Vec<PeriphRegister>,
+    state: Vec<RegisterState>,
+}
+
+impl Peripherals {
+    /// Load registers from a JSON file
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let registers: Vec<PeriphRegister> = serde_json::from_str(&read_to_string(path)?)?;
+
+        if let Some(i) = registers.iter().position(
+            |r| matches!(&r.behavior, Behavior::Sequence { values, .. } if values.is_empty()),
+        ) {
+            return Err(anyhow!("Peripheral register {i} has an empty sequence"));
+        }
+
+        Ok(Self {
+            state: vec![RegisterState::default(); registers.len()],
+            registers,
+        })
+    }
+
+    /// Record an access to physical address `hwaddr`. Writes update scripted registers, and
+    /// reads of a stubbed register return the event reporting the stub's answer.
+    pub fn on_access(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        pc: u64,
+        hwaddr: u64,
+        is_store: bool,
+        value: u64,
+        icount: u64,
+    ) -> Option<PeriphEvent> {
+        let i = self
+            .registers
+            .iter()
+            .position(|r| (r.address.0..=r.address.1).contains(&hwaddr))?;
+        let register = &self.registers[i];
+        let state = &mut self.state[i];
+
+        if is_store {
+            if let Behavior::Script { responses, .. } = &register.behavior {
+                if let Some((_, response)) = responses.iter().find(|(w, _)| *w == value) {
+                    state.scripted = Some(*response);
+                }
+            }
+
+            return None;
+        }
+
+        let stubbed = match &register.behavior {
+            Behavior::Constant(v) => *v,
+            Behavior::Sequence { values, repeat } => {
+                let n = if *repeat {
+                    state.reads % values.len()
+                } else {
+                    state.reads.min(values.len() - 1)
+                };
+
+                values[n]
+            }
+            Behavior::Script { initial, .. } => state.scripted.unwrap_or(*initial),
+        };
+
+        state.reads += 1;
+
+        Some(
+            PeriphEvent::builder()
+                .register(i)
+                .name(register.name.clone())
+                .pc(pc)
+                .hwaddr(hwaddr)
+                .observed(value)
+                .stubbed(stubbed)
+                .vcpu_index(vcpu_index)
+                .icount(icount)
+                .build(),
+        )
+    }
+}
@@ -0,0 +1,152 @@
+//! Resolution of calls through PLT stubs to the functions they import, so that traces of
+//! library calls name the function called rather than an anonymous stub in `.plt`
+//!
+//! With `resolve_plt`, the stubs of the program are read from its relocations when the
+//! plugin is installed, and those of each library as the guest maps it, when libraries are
+//! followed with `symbolize_libraries`. A block in a stub is named `<function>@plt`, and the
+//! API tracer takes a block at the start of a stub as the entry of the function it imports,
+//! so functions in dynamically linked libraries are traced by name.
+//!
+//! A stub jumps through a GOT slot which the dynamic linker fills with the function's address
+//! at startup, or on the first call when binding is lazy. A slot is read when its stub is
+//! called, and again when a block nothing names is translated after a call which found it
+//! unbound, as the function is once the linker binds it. From then on a block at the address
+//! the slot holds is named after the function, and calls through the stub report that
+//! address. A block translated before any call through its stub, as when the function is
+//! first called through a pointer, keeps the name it had.
+//!
+//! Only the PLTs of ELF files are read, so calls through the import thunks of PE images are
+//! not resolved.
+
+use crate::{
+    arch::Arch,
+    guest::read_pointer,
+    modules::{ModuleMap, PltStub},
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[derive(Debug)]
+/// The PLT stubs of the loaded modules, and the functions bound to them so far
+pub struct PltResolver {
+    arch: Arch,
+    /// The name and bias of each module whose stubs were added
+    modules: HashSet<(String, u64)>,
+    /// The stubs, by their start
+    stubs: BTreeMap<u64, PltStub>,
+    /// The PLT sections of the modules, which a slot points into until it is bound
+    plts: Vec<(u64, u64)>,
+    /// The address bound to each stub, by the stub's start
+    bound: HashMap<u64, u64>,
+    /// The starts of the stubs which were called before they were bound
+    pending: HashSet<u64>,
+    /// The name of the function bound at each address
+    functions: HashMap<u64, String>,
+}
+
+impl PltResolver {
+    /// Create a resolver reading the GOT slots of `arch`
+    pub fn new(arch: Arch) -> Self {
+        Self {
+            arch,
+            modules: HashSet::new(),
+            stubs: BTreeMap::new(),
+            plts: Vec::new(),
+            bound: HashMap::new(),
+            pending: HashSet::new(),
+            functions: HashMap::new(),
+        }
+    }
+
+    /// Add the stubs of a loaded module. A module mapped more than once is only added the
+    /// first time.
+    pub fn add_module(&mut self, module: &ModuleMap) {
+        if !self.modules.insert((module.name.clone(), module.bias)) {
+            return;
+        }
+
+        self.stubs
+            .extend(module.plt.iter().map(|stub| (stub.start, stub.clone())));
+        self.plts.extend(
+            module
+                .sections
+                .iter()
+                .filter(|s| s.name.starts_with(".plt"))
+                .map(|s| (s.start, s.end)),
+        );
+    }
+
+    /// Returns the stub containing `vaddr`, if any
+    pub fn stub(&self, vaddr: u64) -> Option<&PltStub> {
+        self.stubs
+            .range(..=vaddr)
+            .next_back()
+            .map(|(_, stub)| stub)
+            .filter(|stub| stub.contains(vaddr))
+    }
+
+    /// Returns the name of the function a stub starting at `vaddr` imports, if one does
+    pub fn import(&self, vaddr: u64) -> Option<&str> {
+        self.stubs.get(&vaddr).map(|stub| stub.name.as_str())
+    }
+
+    /// Returns the name of the code at `vaddr`: `<function>@plt` in a stub, and the
+    /// function's name at the address bound to a stub
+    pub fn symbol(&mut self, vaddr: u64) -> Option<String> {
+        if let Some(stub) = self.stub(vaddr) {
+            return Some(format!("{}@plt", stub.name));
+        }
+
+        if !self.functions.contains_key(&vaddr) {
+            let pending = self.pending.iter().copied().collect::<Vec<_>>();
+
+            for stub in pending {
+                if self.bind(stub).is_some() {
+                    self.pending.remove(&stub);
+                }
+            }
+        }
+
+        self.functions.get(&vaddr).cloned()
+    }
+
+    /// Record a call through the stub starting at `vaddr`. Returns the address bound to the
+    /// stub, or `None` until it is bound.
+    pub fn on_call(&mut self, vaddr: u64) -> Option<u64> {
+        if let Some(target) = self.bound.get(&vaddr) {
+            return Some(*target);
+        }
+
+        let target = self.bind(vaddr);
+
+        if target.is_none() && self.stubs.contains_key(&vaddr) {
+            self.pending.insert(vaddr);
+        }
+
+        target
+    }
+
+    /// Read the slot of the stub starting at `vaddr`, recording the function bound to it.
+    /// Returns the address bound, or `None` if it is not bound yet.
+    fn bind(&mut self, vaddr: u64) -> Option<u64> {
+        let stub = self.stubs.get(&vaddr)?;
+        let target = read_pointer(self.arch, stub.slot, self.arch.pointer_size()).ok()?;
+
+        // Until the dynamic linker binds it, a slot points back into the PLT, to the code
+        // which asks the linker to bind it
+        if target == 0
+            || self
+                .plts
+                .iter()
+                .any(|(start, end)| (*start..*end).contains(&target))
+        {
+            return None;
+        }
+
+        self.functions
+            .entry(target)
+            .or_insert_with(|| stub.name.clone());
+        self.bound.insert(vaddr, target);
+
+        Some(target)
+    }
+}
@@ -0,0 +1,173 @@
+//! Presets which set the tracer up for a common job in one call, for plugins built on this
+//! crate rather than loaded as `libtracer.so` with a long list of arguments
+//!
+//! A preset sets the plugin arguments its job needs. Arguments given to the plugin on the
+//! QEMU command line are applied after it, so a preset's outputs and defaults can still be
+//! changed or extended there. A complete plugin, built as a `cdylib`:
+//!
+//! ```rust,ignore
+//! use ctor::ctor;
+//!
+//! #[ctor]
+//! fn init() {
+//!     tracer::presets::coverage_drcov("coverage.drcov")
+//!         .install()
+//!         .expect("Failed to install tracer");
+//! }
+//! ```
+
+#[cfg(feature = "plugin-api-v4")]
+use crate::reference::{self, ReferenceModel};
+use crate::Tracer;
+use anyhow::{anyhow, Result};
+use qemu_plugin::{
+    install::{Args, Value},
+    plugin::{Plugin, PLUGIN},
+};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[derive(Clone, Debug, Default)]
+/// Plugin arguments set by a preset, as `key=value` pairs
+pub(crate) struct PresetArgs(Vec<(String, OsString)>);
+
+impl PresetArgs {
+    fn flag(mut self, key: &str) -> Self {
+        self.0.push((key.to_string(), "on".into()));
+        self
+    }
+
+    fn path<P>(mut self, key: &str, path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.0
+            .push((key.to_string(), path.as_ref().as_os_str().to_os_string()));
+        self
+    }
+
+    /// The arguments QEMU passed, preceded by the preset's, so that QEMU's take precedence
+    pub(crate) fn apply(&self, args: &Args) -> Args {
+        let raw_os = self.0.iter().map(|(key, value)| {
+            let mut argument = OsString::from(format!("{key}="));
+            argument.push(value);
+            argument
+        });
+        let parsed = self.0.iter().map(|(key, value)| {
+            let value = if value == "on" {
+                Value::Bool(true)
+            } else {
+                Value::String(value.to_string_lossy().into_owned())
+            };
+
+            (key.clone(), value)
+        });
+
+        Args {
+            raw: raw_os
+                .clone()
+                .map(|argument| argument.to_string_lossy().into_owned())
+                .chain(args.raw.iter().cloned())
+                .collect(),
+            raw_os: raw_os.chain(args.raw_os.iter().cloned()).collect(),
+            parsed: parsed
+                .chain(
+                    args.parsed
+                        .iter()
+                        .map(|(key, value)| (key.clone(), value.clone())),
+                )
+                .collect(),
+        }
+    }
+}
+
+/// A tracer set up by a preset
+pub struct Preset {
+    tracer: Tracer,
+}
+
+impl Preset {
+    fn new(args: PresetArgs) -> Self {
+        let mut tracer = Tracer::new();
+        tracer.preset = args;
+
+        Self { tracer }
+    }
+
+    /// Install the tracer as the plugin, replacing the default tracer if it was installed
+    /// first
+    pub fn install(self) -> Result<()> {
+        let mut tracer = Some(self.tracer);
+        let plugin = PLUGIN.get_or_init(|| {
+            Mutex::new(Box::new(tracer.take().expect("Tracer was taken")) as Box<dyn Plugin>)
+        });
+
+        if let Some(tracer) = tracer {
+            *plugin
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock plugin: {e}"))? = Box::new(tracer);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "plugin-api-v4")]
+    /// Check every instruction executed against `model`, in addition to any models already
+    /// registered
+    pub fn reference_model<M>(mut self, model: M) -> Result<Self>
+    where
+        M: ReferenceModel + 'static,
+    {
+        reference::register(&mut self.tracer.reference, Box::new(model))?;
+
+        Ok(self)
+    }
+
+    /// The tracer as a plugin, for installing by other means
+    pub fn into_plugin(self) -> Box<dyn Plugin> {
+        Box::new(self.tracer)
+    }
+}
+
+/// Block coverage of the program's main binary, written to `path` at exit in drcov format.
+/// No events are traced.
+pub fn coverage_drcov<P>(path: P) -> Preset
+where
+    P: Into<PathBuf>,
+{
+    Preset::new(
+        PresetArgs::default()
+            .path("drcov_path", path.into())
+            .flag("analysis_only"),
+    )
+}
+
+/// The program's syscalls, with its arguments and environment at startup, written to the
+/// trace file `path`
+pub fn strace<P>(path: P) -> Preset
+where
+    P: Into<PathBuf>,
+{
+    Preset::new(
+        PresetArgs::default()
+            .path("trace_path", path.into())
+            .flag("log_syscalls")
+            .flag("log_start"),
+    )
+}
+
+/// The address of each instruction the program executes, in the compact encoding, written to
+/// the trace file `path`
+pub fn exec_trace<P>(path: P) -> Preset
+where
+    P: Into<PathBuf>,
+{
+    Preset::new(
+        PresetArgs::default()
+            .path("trace_path", path.into())
+            .flag("log_pcs"),
+    )
+}
@@ -0,0 +1,162 @@
+//! Entropy and compressibility probes over guest buffers
+//!
+//! A probe is hooked at a pc and measures a buffer whose address and length are read from
+//! registers there, or given as constants. For example, `0x401a20:rdi:rdx` measures the
+//! buffer passed as the first argument of the function at `0x401a20`, with its length in the
+//! third. Each measurement logs the buffer's Shannon entropy in bits per byte and its
+//! compression ratio under DEFLATE: data which is both high in entropy and incompressible is
+//! likely encrypted or already compressed, so probes before and after a routine show whether
+//! it encrypts or compresses what it is given.
+//!
+//! [`entropy`] and [`compression_ratio`] are public, so the same measures can drive fuzzing
+//! feedback.
+
+use crate::{arch::Arch, heap::read_register, memmap::parse_addr};
+use anyhow::{anyhow, Result};
+use miniz_oxide::deflate::compress_to_vec;
+use qemu_plugin::{qemu_plugin_read_memory_vaddr, RegisterDescriptor, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use typed_builder::TypedBuilder;
+
+/// The most bytes of a buffer which are measured
+const MAX_LEN: usize = 64 << 10;
+
+/// The DEFLATE level buffers are compressed at, a balance of speed and ratio
+const LEVEL: u8 = 6;
+
+/// Returns the Shannon entropy of `data`, in bits per byte, from 0 for constant data to 8
+/// for uniformly random data
+pub fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+
+    counts
+        .iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f64 / data.len() as f64;
+
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Returns the size of `data` compressed with DEFLATE over its size, which is near or above
+/// 1 for incompressible data
+pub fn compression_ratio(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 1.0;
+    }
+
+    compress_to_vec(data, LEVEL).len() as f64 / data.len() as f64
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// Where a probe reads the address or length of its buffer from
+pub enum Operand {
+    Register(String),
+    Constant(u64),
+}
+
+impl FromStr for Operand {
+    type Err = anyhow::Error;
+
+    /// Parse a constant if the operand starts with a digit, and a register name otherwise
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        if s.starts_with(|c: char| c.is_ascii_digit()) {
+            Ok(Self::Constant(parse_addr(s)?))
+        } else if s.is_empty() {
+            Err(anyhow!("Empty probe operand"))
+        } else {
+            Ok(Self::Register(s.to_string()))
+        }
+    }
+}
+
+impl Operand {
+    fn read(&self, arch: Arch, registers: &[RegisterDescriptor<'static>]) -> Result<u64> {
+        match self {
+            Self::Register(name) => read_register(arch, registers, name),
+            Self::Constant(value) => Ok(*value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A buffer to measure whenever the guest executes an instruction
+pub struct EntropyProbe {
+    pub pc: u64,
+    pub address: Operand,
+    pub length: Operand,
+}
+
+impl FromStr for EntropyProbe {
+    type Err = anyhow::Error;
+
+    /// Parse a probe written as `pc:address:length`
+    fn from_str(s: &str) -> Result<Self> {
+        let [pc, address, length] = s.split(':').collect::<Vec<_>>()[..] else {
+            return Err(anyhow!("Invalid probe {s}, expected pc:address:length"));
+        };
+
+        Ok(Self {
+            pc: parse_addr(pc)?,
+            address: address.parse()?,
+            length: length.parse()?,
+        })
+    }
+}
+
+impl EntropyProbe {
+    /// Parse a list of probes separated by `;`
+    pub fn parse_list(list: &str) -> Result<Vec<Self>> {
+        list.split(';')
+            .filter(|p| !p.trim().is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
+    /// Measure the probe's buffer, on the vCPU whose registers are `registers`
+    pub fn measure(
+        &self,
+        arch: Arch,
+        vcpu_index: VCPUIndex,
+        registers: &[RegisterDescriptor<'static>],
+        icount: u64,
+    ) -> Result<ProbeEvent> {
+        let address = self.address.read(arch, registers)?;
+        let length = self.length.read(arch, registers)?;
+        let data = qemu_plugin_read_memory_vaddr(address, (length as usize).min(MAX_LEN))?;
+
+        Ok(ProbeEvent::builder()
+            .vcpu_index(vcpu_index)
+            .pc(self.pc)
+            .address(address)
+            .length(length)
+            .entropy(entropy(&data))
+            .compression_ratio(compression_ratio(&data))
+            .icount(icount)
+            .build())
+    }
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// A measurement of a guest buffer
+pub struct ProbeEvent {
+    pub vcpu_index: VCPUIndex,
+    pub pc: u64,
+    pub address: u64,
+    /// The length of the buffer, of which at most the first 64 KiB are measured
+    pub length: u64,
+    /// The Shannon entropy of the buffer, in bits per byte
+    pub entropy: f64,
+    /// The size of the buffer compressed with DEFLATE over its size
+    pub compression_ratio: f64,
+    pub icount: u64,
+}
@@ -0,0 +1,340 @@
+//! Import of QEMU's own `-d exec,in_asm` logs, and their alignment with traces, so that
+//! captures made with log-based workflows can be compared with the plugin's
+//!
+//! `in_asm` logs each block as it is translated, with the address and disassembly of its
+//! instructions, and `exec` logs each block as it is entered, with the vCPU and the block's
+//! address. QEMU only logs the blocks it enters from its main loop, so blocks reached by a
+//! chained jump are missing unless `nochain` is also given. Executed blocks are expanded into
+//! the addresses of their instructions from their latest translation, and aligned against the
+//! instruction addresses of a trace's instruction and pc events. Without a translation for
+//! every executed block, only block addresses are compared, against the trace's executions of
+//! those addresses.
+//!
+//! QEMU logs the addresses code ran at, so traces of programs loaded at a bias are compared
+//! when recorded with `absolute_pcs=true`.
+//!
+//! Alignment walks both sequences together and, where they disagree, resynchronizes at the
+//! nearest addresses on which they agree again, recording what each side executed in
+//! between. Traces of programs with several vCPUs interleave differently from the log, so are
+//! best compared one vCPU at a time.
+
+use crate::Event;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+/// How far ahead on each side a mismatch looks for addresses the sequences agree on again
+const RESYNC_WINDOW: usize = 64;
+/// The number of divergences an alignment lists
+const MAX_DIVERGENCES: usize = 100;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A line of a QEMU log which the importer understands
+pub enum LogEntry {
+    /// A block translated, from `in_asm`
+    Translation {
+        symbol: Option<String>,
+        /// The address and disassembly of each instruction
+        instructions: Vec<(u64, String)>,
+    },
+    /// A block entered, from `exec`
+    Execution {
+        vcpu_index: u32,
+        pc: u64,
+        symbol: Option<String>,
+    },
+}
+
+/// Parse an `exec` line: `Trace 0: 0x7f...  [00000000/0000000000401126/00000000/00000000] main`
+fn parse_execution(line: &str) -> Option<LogEntry> {
+    let rest = line
+        .strip_prefix("Trace ")
+        .or_else(|| line.strip_prefix("Chain "))?;
+    let (vcpu_index, rest) = rest.split_once(':')?;
+    let (_, rest) = rest.split_once('[')?;
+    let (fields, symbol) = rest.split_once(']')?;
+    // The pc follows the code segment base, and precedes the flags
+    let pc = fields.split('/').nth(1)?;
+    let symbol = symbol.trim();
+
+    Some(LogEntry::Execution {
+        vcpu_index: vcpu_index.trim().parse().ok()?,
+        pc: u64::from_str_radix(pc.trim_start_matches("0x"), 16).ok()?,
+        symbol: (!symbol.is_empty()).then(|| symbol.to_string()),
+    })
+}
+
+/// Parse an `in_asm` instruction line: `0x00401126:  55  pushq %rbp`
+fn parse_instruction(line: &str) -> Option<(u64, String)> {
+    let (address, disas) = line.strip_prefix("0x")?.split_once(':')?;
+
+    Some((
+        u64::from_str_radix(address.trim(), 16).ok()?,
+        disas.trim().to_string(),
+    ))
+}
+
+#[derive(Clone, Debug, Default)]
+/// The entries of a QEMU log, in order
+pub struct QemuLog {
+    pub entries: Vec<LogEntry>,
+}
+
+impl QemuLog {
+    /// Parse a log, skipping lines logged by other `-d` items
+    pub fn parse<R>(reader: R) -> Result<Self>
+    where
+        R: BufRead,
+    {
+        let mut entries = Vec::new();
+        // The translation being read, after its `IN:` line
+        let mut translation = None::<(Option<String>, Vec<(u64, String)>)>;
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim_end();
+
+            if let Some((symbol, instructions)) = translation.as_mut() {
+                if let Some(instruction) = parse_instruction(line) {
+                    instructions.push(instruction);
+                    continue;
+                }
+
+                // Lines such as `OBJD-T:` dumps may precede the instructions
+                if !instructions.is_empty() || line.is_empty() {
+                    entries.push(LogEntry::Translation {
+                        symbol: symbol.take(),
+                        instructions: std::mem::take(instructions),
+                    });
+                    translation = None;
+                }
+            }
+
+            if let Some(symbol) = line.strip_prefix("IN:") {
+                let symbol = symbol.trim();
+
+                translation = Some(((!symbol.is_empty()).then(|| symbol.to_string()), Vec::new()));
+            } else if let Some(execution) = parse_execution(line) {
+                entries.push(execution);
+            }
+        }
+
+        if let Some((symbol, instructions)) = translation.filter(|(_, i)| !i.is_empty()) {
+            entries.push(LogEntry::Translation {
+                symbol,
+                instructions,
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn read<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        Self::parse(BufReader::new(File::open(path).map_err(|e| {
+            anyhow!("Failed to open QEMU log {}: {e}", path.display())
+        })?))
+    }
+
+    /// The address of each block entered, in order
+    pub fn executions(&self) -> Vec<u64> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match entry {
+                LogEntry::Execution { pc, .. } => Some(*pc),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The addresses of the instructions executed, in order, or `None` if a block was entered
+    /// which the log has no translation of
+    pub fn instructions(&self) -> Option<Vec<u64>> {
+        let mut translations = HashMap::<u64, Vec<u64>>::new();
+        let mut pcs = Vec::new();
+
+        for entry in &self.entries {
+            match entry {
+                LogEntry::Translation { instructions, .. } => {
+                    if let Some((start, _)) = instructions.first() {
+                        translations
+                            .insert(*start, instructions.iter().map(|(pc, _)| *pc).collect());
+                    }
+                }
+                LogEntry::Execution { pc, .. } => pcs.extend(translations.get(pc)?),
+            }
+        }
+
+        Some(pcs)
+    }
+
+    /// Align the log with the instructions executed in a trace
+    pub fn align<'a, I>(&self, events: I) -> Result<Alignment>
+    where
+        I: IntoIterator<Item = &'a Event>,
+    {
+        let trace = trace_pcs(events)?;
+
+        Ok(match self.instructions() {
+            Some(log) => Alignment::new(Granularity::Instruction, &log, &trace),
+            None => {
+                let log = self.executions();
+                let starts = log.iter().copied().collect::<HashSet<_>>();
+                let trace = trace
+                    .into_iter()
+                    .filter(|pc| starts.contains(pc))
+                    .collect::<Vec<_>>();
+
+                Alignment::new(Granularity::Block, &log, &trace)
+            }
+        })
+    }
+}
+
+/// The addresses of the instructions executed in a trace, from its instruction and pc events
+pub fn trace_pcs<'a, I>(events: I) -> Result<Vec<u64>>
+where
+    I: IntoIterator<Item = &'a Event>,
+{
+    let mut pcs = Vec::new();
+
+    for event in events {
+        match event {
+            Event::Instruction { event, .. } => pcs.push(event.vaddr),
+            Event::Pcs(batch) => pcs.extend(batch.decode()?),
+            _ => {}
+        }
+    }
+
+    Ok(pcs)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// What the addresses of an alignment are the addresses of
+pub enum Granularity {
+    Instruction,
+    /// Blocks entered, as the log had no translation of some block
+    Block,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A stretch where the log and the trace executed different addresses
+pub struct Divergence {
+    /// The index in the log's addresses where the stretch starts
+    pub log_index: usize,
+    /// The index in the trace's addresses where the stretch starts
+    pub trace_index: usize,
+    /// The addresses only the log executed
+    pub log_pcs: Vec<u64>,
+    /// The addresses only the trace executed
+    pub trace_pcs: Vec<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// How closely a QEMU log and a trace agree on the addresses executed
+pub struct Alignment {
+    pub granularity: Granularity,
+    pub log_pcs: usize,
+    pub trace_pcs: usize,
+    /// The addresses executed in the same order in both
+    pub matched: usize,
+    pub log_only: usize,
+    pub trace_only: usize,
+    /// The first stretches where the two disagree, including any leading or trailing
+    /// addresses only one of them executed
+    pub divergences: Vec<Divergence>,
+}
+
+/// Returns the offsets into `log` and `trace`, with the smallest sum up to `window`, at
+/// which they next agree
+fn resync(log: &[u64], trace: &[u64], window: usize) -> Option<(usize, usize)> {
+    (1..=window).find_map(|distance| {
+        (0..=distance).find_map(|x| {
+            let y = distance - x;
+
+            (x < log.len() && y < trace.len() && log[x] == trace[y]).then_some((x, y))
+        })
+    })
+}
+
+impl Alignment {
+    /// Align the addresses executed according to a log and a trace
+    pub fn new(granularity: Granularity, log: &[u64], trace: &[u64]) -> Self {
+        let mut alignment = Self {
+            granularity,
+            log_pcs: log.len(),
+            trace_pcs: trace.len(),
+            matched: 0,
+            log_only: 0,
+            trace_only: 0,
+            divergences: Vec::new(),
+        };
+
+        // A trace may start later or earlier than the log, such as when it waits for a
+        // trigger, so the two are first synchronized at the first address they share
+        let start = match (log.first(), trace.first()) {
+            (Some(first), Some(_)) => {
+                let in_trace = trace.iter().position(|pc| pc == first);
+                let in_log = trace
+                    .first()
+                    .and_then(|first| log.iter().position(|pc| pc == first));
+
+                match (in_log, in_trace) {
+                    (Some(x), Some(y)) if x <= y => (x, 0),
+                    (_, Some(y)) => (0, y),
+                    (Some(x), None) => (x, 0),
+                    (None, None) => (log.len(), trace.len()),
+                }
+            }
+            _ => (log.len(), trace.len()),
+        };
+        let (mut i, mut j) = start;
+
+        alignment.diverge(0, 0, &log[..i], &trace[..j]);
+
+        while i < log.len() && j < trace.len() {
+            if log[i] == trace[j] {
+                alignment.matched += 1;
+                i += 1;
+                j += 1;
+                continue;
+            }
+
+            let (x, y) = resync(&log[i..], &trace[j..], RESYNC_WINDOW).unwrap_or((1, 1));
+
+            alignment.diverge(i, j, &log[i..i + x], &trace[j..j + y]);
+            i += x;
+            j += y;
+        }
+
+        alignment.diverge(i, j, &log[i..], &trace[j..]);
+        alignment
+    }
+
+    fn diverge(&mut self, log_index: usize, trace_index: usize, log: &[u64], trace: &[u64]) {
+        if log.is_empty() && trace.is_empty() {
+            return;
+        }
+
+        self.log_only += log.len();
+        self.trace_only += trace.len();
+
+        if self.divergences.len() < MAX_DIVERGENCES {
+            self.divergences.push(Divergence {
+                log_index,
+                trace_index,
+                log_pcs: log.iter().take(RESYNC_WINDOW).copied().collect(),
+                trace_pcs: trace.iter().take(RESYNC_WINDOW).copied().collect(),
+            });
+        }
+    }
+}
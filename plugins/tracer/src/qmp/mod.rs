@@ -0,0 +1,111 @@
+//! A minimal client for the QEMU Machine Protocol, for querying the emulator the plugin is
+//! loaded into
+//!
+//! QEMU must be started with a QMP socket, e.g. `-qmp unix:/tmp/qmp.sock,server=on,wait=off`.
+//! QMP is served from QEMU's main loop, so commands block until the main loop runs. They must
+//! not be issued from `qemu_plugin_install`, which runs before it starts, and are best issued
+//! from a thread of the plugin's own.
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+};
+
+#[derive(Debug)]
+/// A QMP connection which has completed capabilities negotiation
+pub struct Qmp {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Qmp {
+    /// Connect to the QMP socket at `path` and enter command mode
+    pub fn connect<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let writer = UnixStream::connect(path)?;
+        let mut qmp = Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        };
+
+        let greeting = qmp.read()?;
+
+        if greeting.get("QMP").is_none() {
+            return Err(anyhow!("Unexpected QMP greeting: {greeting}"));
+        }
+
+        qmp.execute("qmp_capabilities", None)?;
+
+        Ok(qmp)
+    }
+
+    fn read(&mut self) -> Result<Value> {
+        let mut line = String::new();
+
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(anyhow!("QMP connection closed"));
+        }
+
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    /// Execute a command and return its result. Asynchronous events received while waiting
+    /// for the response are discarded.
+    pub fn execute(&mut self, command: &str, arguments: Option<Value>) -> Result<Value> {
+        let mut request = json!({ "execute": command });
+
+        if let Some(arguments) = arguments {
+            request["arguments"] = arguments;
+        }
+
+        serde_json::to_writer(&mut self.writer, &request)?;
+        self.writer.write_all(b"\n")?;
+
+        loop {
+            let mut response = self.read()?;
+
+            if let Some(result) = response.get_mut("return") {
+                return Ok(result.take());
+            }
+
+            if let Some(error) = response.get("error") {
+                return Err(anyhow!("QMP command {command} failed: {error}"));
+            }
+        }
+    }
+
+    /// Run a human monitor (HMP) command, such as `info mtree -f`, and return its output
+    pub fn human_monitor_command(&mut self, command: &str) -> Result<String> {
+        self.execute(
+            "human-monitor-command",
+            Some(json!({ "command-line": command })),
+        )?
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| anyhow!("HMP command {command} returned a non-string result"))
+    }
+
+    /// Write all of guest RAM to `path` as an ELF core with `dump-guest-memory`. QEMU stops
+    /// the VM while dumping, so a caller running on a vCPU thread must `detach`, in which case
+    /// this returns before the dump is complete.
+    pub fn dump_guest_memory<P>(&mut self, path: P, detach: bool) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.execute(
+            "dump-guest-memory",
+            Some(json!({
+                "paging": false,
+                "protocol": format!("file:{}", path.as_ref().display()),
+                "detach": detach,
+            })),
+        )?;
+
+        Ok(())
+    }
+}
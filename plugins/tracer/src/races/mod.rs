@@ -0,0 +1,151 @@
+//! A heuristic data race detector
+//!
+//! Guest memory is shadowed in 8-byte granules, each remembering its last write and the
+//! last read by each thread since. An access conflicts with an earlier one by another thread
+//! if either is a write, and the pair is a potential race unless both threads performed a
+//! synchronization operation in between: the first after its access, as a release, and the
+//! second before its own, as an acquire. Synchronization operations are atomic instructions
+//! and `futex` syscalls. This is far weaker than tracking happens-before with vector clocks,
+//! and misses races between threads which synchronize through unrelated locks, but needs only
+//! a timestamp per thread.
+//!
+//! Guest threads are told apart by vCPU, which in user mode has one thread each. Each pair
+//! of racing instructions is reported once.
+
+use crate::arch::{Arch, Syscall};
+use qemu_plugin::VCPUIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+const GRANULE: u64 = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// One of the accesses of a potential race
+pub struct RaceAccess {
+    pub vcpu_index: VCPUIndex,
+    pub pc: u64,
+    pub vaddr: u64,
+    pub is_store: bool,
+    pub icount: u64,
+    /// The return addresses on the thread's shadow call stack, innermost first, if heap
+    /// tracking keeps one
+    pub call_stack: Vec<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// Two unsynchronized accesses to the same memory by different threads, at least one of
+/// which is a write
+pub struct RaceEvent {
+    pub first: RaceAccess,
+    pub second: RaceAccess,
+}
+
+#[derive(Clone, Debug)]
+struct Access {
+    access: RaceAccess,
+    /// The position of the access in the detector's order of operations
+    seq: u64,
+}
+
+#[derive(Debug, Default)]
+struct Granule {
+    write: Option<Access>,
+    /// The last read by each thread since the last write
+    reads: Vec<Access>,
+}
+
+#[derive(Debug)]
+/// Shadows guest memory to find accesses unordered by synchronization
+pub struct RaceDetector {
+    arch: Arch,
+    /// A counter ordering every access and synchronization the detector sees
+    seq: u64,
+    /// When each thread last synchronized
+    synced: HashMap<VCPUIndex, u64>,
+    granules: HashMap<u64, Granule>,
+    /// The pairs of instructions already reported
+    reported: HashSet<(u64, u64)>,
+}
+
+impl RaceDetector {
+    /// Create a detector which has seen no accesses
+    pub fn new(arch: Arch) -> Self {
+        Self {
+            arch,
+            seq: 0,
+            synced: HashMap::new(),
+            granules: HashMap::new(),
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Record that a thread performed a synchronization operation
+    pub fn on_sync(&mut self, vcpu_index: VCPUIndex) {
+        self.seq += 1;
+        self.synced.insert(vcpu_index, self.seq);
+    }
+
+    /// Record a syscall, which synchronizes the thread making it if it is `futex`
+    pub fn on_syscall(&mut self, vcpu_index: VCPUIndex, num: i64) {
+        if self.arch.syscall(num) == Some(Syscall::Futex) {
+            self.on_sync(vcpu_index);
+        }
+    }
+
+    /// Whether an earlier access is ordered before a later one by another thread
+    fn ordered(&self, earlier: &Access, later: VCPUIndex) -> bool {
+        let synced = |vcpu_index| self.synced.get(&vcpu_index).copied().unwrap_or_default();
+
+        synced(earlier.access.vcpu_index) > earlier.seq && synced(later) > earlier.seq
+    }
+
+    /// Record a non-atomic memory access. Returns the potential races it completes which
+    /// have not been reported before.
+    pub fn on_access(&mut self, access: RaceAccess) -> Vec<RaceEvent> {
+        self.seq += 1;
+
+        let seq = self.seq;
+        let key = access.vaddr & !(GRANULE - 1);
+        let conflicts = self
+            .granules
+            .get(&key)
+            .map(|granule| {
+                granule
+                    .write
+                    .iter()
+                    .chain(granule.reads.iter().filter(|_| access.is_store))
+                    .filter(|earlier| earlier.access.vcpu_index != access.vcpu_index)
+                    .filter(|earlier| !self.ordered(earlier, access.vcpu_index))
+                    .map(|earlier| earlier.access.clone())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let mut races = Vec::new();
+
+        for first in conflicts {
+            let pair = (first.pc.min(access.pc), first.pc.max(access.pc));
+
+            if self.reported.insert(pair) {
+                races.push(RaceEvent {
+                    first,
+                    second: access.clone(),
+                });
+            }
+        }
+
+        let vcpu_index = access.vcpu_index;
+        let access = Access { access, seq };
+        let granule = self.granules.entry(key).or_default();
+
+        if access.access.is_store {
+            granule.write = Some(access);
+            granule.reads.clear();
+        } else {
+            granule.reads.retain(|r| r.access.vcpu_index != vcpu_index);
+            granule.reads.push(access);
+        }
+
+        races
+    }
+}
@@ -0,0 +1,146 @@
+//! Recording of the randomness a guest consumes, via `getrandom` and reads of the random
+//! devices
+//!
+//! The plugin API cannot write guest memory, so the deterministic stream itself comes from
+//! QEMU: in user mode, running QEMU with `-seed` makes `getrandom` (and `AT_RANDOM`) return a
+//! seeded stream. Reads of `/dev/urandom` and `/dev/random` are served by the host and are
+//! recorded, but remain non-deterministic.
+
+use crate::{
+    arch::{Arch, Syscall},
+    guest::read_cstring,
+};
+use anyhow::Result;
+use qemu_plugin::{qemu_plugin_read_memory_vaddr, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use typed_builder::TypedBuilder;
+
+const RANDOM_DEVICES: &[&str] = &["/dev/urandom", "/dev/random"];
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RandomSource {
+    Getrandom,
+    Device(String),
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+pub struct RandomEvent {
+    pub source: RandomSource,
+    /// Whether the data came from QEMU's seeded stream, and is reproducible across runs
+    pub deterministic: bool,
+    pub vcpu_index: VCPUIndex,
+    pub icount: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+struct PendingSyscall {
+    syscall: Syscall,
+    args: [u64; 8],
+    path: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+/// Tracks descriptors open on random devices, producing an event for each read of random data
+pub struct RandomTracker {
+    seeded: bool,
+    devices: HashMap<u64, String>,
+    pending: HashMap<VCPUIndex, PendingSyscall>,
+}
+
+impl RandomTracker {
+    /// Create a tracker. `seeded` indicates QEMU was run with `-seed`.
+    pub fn new(seeded: bool) -> Self {
+        Self {
+            seeded,
+            ..Default::default()
+        }
+    }
+
+    /// Record the arguments of a syscall on entry
+    pub fn on_syscall(
+        &mut self,
+        arch: Arch,
+        vcpu_index: VCPUIndex,
+        num: i64,
+        args: [u64; 8],
+    ) -> Result<()> {
+        let Some(syscall) = arch.syscall(num) else {
+            return Ok(());
+        };
+
+        // A path which cannot be read, such as a NULL pointer, is not a random device
+        let path = match syscall {
+            Syscall::Open => read_cstring(args[0], 64).ok(),
+            Syscall::Openat => read_cstring(args[1], 64).ok(),
+            Syscall::Getrandom | Syscall::Read | Syscall::Pread64 | Syscall::Close => None,
+            _ => return Ok(()),
+        }
+        .map(|path| String::from_utf8_lossy(&path).into_owned());
+
+        self.pending.insert(
+            vcpu_index,
+            PendingSyscall {
+                syscall,
+                args,
+                path,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Returns an event if the returning syscall produced random data for the guest
+    pub fn on_syscall_return(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        ret: i64,
+        icount: u64,
+    ) -> Result<Option<RandomEvent>> {
+        let Some(pending) = self.pending.remove(&vcpu_index) else {
+            return Ok(None);
+        };
+
+        if ret < 0 {
+            return Ok(None);
+        }
+
+        let source = match pending.syscall {
+            Syscall::Open | Syscall::Openat => {
+                if let Some(path) = pending
+                    .path
+                    .filter(|p| RANDOM_DEVICES.contains(&p.as_str()))
+                {
+                    self.devices.insert(ret as u64, path);
+                }
+                return Ok(None);
+            }
+            Syscall::Close => {
+                self.devices.remove(&pending.args[0]);
+                return Ok(None);
+            }
+            Syscall::Getrandom => RandomSource::Getrandom,
+            Syscall::Read | Syscall::Pread64 => match self.devices.get(&pending.args[0]) {
+                Some(path) => RandomSource::Device(path.clone()),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+
+        let buf = match source {
+            RandomSource::Getrandom => pending.args[0],
+            RandomSource::Device(_) => pending.args[1],
+        };
+
+        Ok(Some(
+            RandomEvent::builder()
+                .deterministic(self.seeded && source == RandomSource::Getrandom)
+                .source(source)
+                .vcpu_index(vcpu_index)
+                .icount(icount)
+                .data(qemu_plugin_read_memory_vaddr(buf, ret as usize)?)
+                .build(),
+        ))
+    }
+}
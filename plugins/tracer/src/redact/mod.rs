@@ -0,0 +1,288 @@
+//! Redaction of traces, so they can be shared without the guest's data
+//!
+//! A redaction policy maps event fields to an action: `keep` them, `strip` them, replacing
+//! them with an empty value, or `hash` them, replacing strings and byte strings with a
+//! salted hash so that equal values can still be matched up. Fields are named as
+//! `Variant.field`, such as `Start.envp`, which applies to the field anywhere within events
+//! of that variant, or as just `field`, which applies in every event.
+//!
+//! The default policy strips guest memory contents, such as syscall buffers, console
+//! output, loaded and stored values, hypercall messages and register values, and hashes
+//! paths, arguments and the environment. Policies are read from TOML, or JSON for files
+//! ending in `.json`, and their fields are applied over the default policy's:
+//!
+//! ```toml
+//! salt = "shared-with-vendor"
+//!
+//! [fields]
+//! "Start.argv" = "keep"
+//! "Instruction.data" = "strip"
+//! ```
+//!
+//! Redaction works on the CBOR encoding of records, so the same policy applies to event
+//! streams, trace files and the records of sharded traces, and records are rewritten one
+//! at a time.
+
+use crate::tracefile::{Manifest, TraceFile, TraceReader};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_cbor::{Deserializer, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::{create_dir_all, read_to_string},
+    io::{Read, Write},
+    path::Path,
+};
+
+/// The bytes of a hash which replace a hashed value
+const HASH_LEN: usize = 8;
+
+/// The fields the default policy strips
+const STRIPPED: &[&str] = &[
+    "Syscall.buffers",
+    "Console.data",
+    "Uart.data",
+    "Random.data",
+    "Fault.value",
+    "Memory.value",
+    "MemoryRange.value",
+    "MemoryRange.values",
+    "Hypercall.data",
+    "Instruction.registers",
+    "Api.args",
+    "Api.ret",
+    "String.string",
+];
+
+/// The fields the default policy hashes
+const HASHED: &[&str] = &["Start.binary", "Start.argv", "Start.envp", "Dump.path"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+/// What is done to a field
+pub enum Action {
+    Keep,
+    Strip,
+    Hash,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The actions to take on event fields
+pub struct RedactionPolicy {
+    /// Mixed into every hash, so that hashes of short values cannot be looked up
+    #[serde(default)]
+    pub salt: String,
+    #[serde(default)]
+    pub fields: BTreeMap<String, Action>,
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> Self {
+        Self {
+            salt: String::new(),
+            fields: STRIPPED
+                .iter()
+                .map(|f| (f.to_string(), Action::Strip))
+                .chain(HASHED.iter().map(|f| (f.to_string(), Action::Hash)))
+                .collect(),
+        }
+    }
+}
+
+impl RedactionPolicy {
+    /// Load a policy from a TOML or JSON file, applied over the default policy
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::parse(
+            &read_to_string(path.as_ref())?,
+            path.as_ref().extension().is_some_and(|e| e == "json"),
+        )
+    }
+
+    /// Parse a policy in TOML, or in JSON if `json` is set, applied over the default policy
+    pub fn parse(data: &str, json: bool) -> Result<Self> {
+        let policy: Self = if json {
+            serde_json::from_str(data)?
+        } else {
+            toml::from_str(data)?
+        };
+        let mut fields = Self::default().fields;
+
+        fields.extend(policy.fields);
+
+        Ok(Self {
+            salt: policy.salt,
+            fields,
+        })
+    }
+
+    fn action(&self, variant: Option<&str>, field: &str) -> Option<Action> {
+        variant
+            .and_then(|v| self.fields.get(&format!("{v}.{field}")))
+            .or_else(|| self.fields.get(field))
+            .copied()
+    }
+
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        Sha256::new()
+            .chain_update(self.salt.as_bytes())
+            .chain_update(data)
+            .finalize()[..HASH_LEN]
+            .to_vec()
+    }
+
+    /// Redact a record in place
+    pub fn redact(&self, record: &mut Value) {
+        self.redact_in(record, None);
+    }
+
+    /// Redact `value`, which is within an event of `variant` if it is known
+    fn redact_in(&self, value: &mut Value, variant: Option<&str>) {
+        match value {
+            // Events are maps from their variant to their fields
+            Value::Map(map) if variant.is_none() && map.len() == 1 => {
+                if let Some((Value::Text(name), inner)) = map.iter_mut().next() {
+                    let variant = name.clone();
+
+                    self.redact_in(inner, Some(&variant));
+                }
+            }
+            Value::Map(map) => {
+                for (key, value) in map.iter_mut() {
+                    let action = match key {
+                        Value::Text(field) => self.action(variant, field),
+                        _ => None,
+                    };
+
+                    match action {
+                        Some(Action::Strip) => strip(value),
+                        Some(Action::Hash) => self.hash_value(value),
+                        Some(Action::Keep) => {}
+                        None => self.redact_in(value, variant),
+                    }
+                }
+            }
+            Value::Array(values) => {
+                for value in values {
+                    self.redact_in(value, variant);
+                }
+            }
+            Value::Tag(_, value) => self.redact_in(value, variant),
+            _ => {}
+        }
+    }
+
+    /// Replace the strings and byte strings in `value` with their hashes. Arrays of bytes,
+    /// as `Vec<u8>` fields are encoded, are hashed as a whole.
+    fn hash_value(&self, value: &mut Value) {
+        match value {
+            Value::Text(text) => *text = hex(&self.hash(text.as_bytes())),
+            Value::Bytes(bytes) => *bytes = self.hash(bytes),
+            Value::Array(values) => match bytes(values) {
+                Some(data) => {
+                    *values = self
+                        .hash(&data)
+                        .into_iter()
+                        .map(|b| Value::Integer(b as i128))
+                        .collect()
+                }
+                None => values.iter_mut().for_each(|v| self.hash_value(v)),
+            },
+            Value::Map(map) => map.values_mut().for_each(|v| self.hash_value(v)),
+            Value::Tag(_, value) => self.hash_value(value),
+            _ => {}
+        }
+    }
+
+    /// Redact every record read from `reader`, writing them to `writer`. Returns the number
+    /// of records.
+    pub fn filter<R, W>(&self, reader: R, mut writer: W) -> Result<usize>
+    where
+        R: Read,
+        W: Write,
+    {
+        let mut records = 0;
+
+        for record in Deserializer::from_reader(reader).into_iter::<Value>() {
+            let mut record = record?;
+
+            self.redact(&mut record);
+            serde_cbor::to_writer(&mut writer, &record)?;
+            records += 1;
+        }
+
+        writer.flush()?;
+
+        Ok(records)
+    }
+
+    /// Redact the trace file at `from`, writing it to `to`
+    pub fn redact_trace_file<P, Q>(&self, from: P, to: Q) -> Result<usize>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let mut trace = TraceFile::create(to)?;
+        let records = TraceReader::open(from)?.records::<Value>()?.records;
+        let count = records.len();
+
+        for mut record in records {
+            self.redact(&mut record);
+            trace.write_record(&record)?;
+        }
+
+        trace.finish()?;
+
+        Ok(count)
+    }
+
+    /// Redact the sharded trace in the directory `from`, writing it to the directory `to`
+    pub fn redact_shards<P, Q>(&self, from: P, to: Q) -> Result<usize>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let manifest = Manifest::read(from.as_ref())?;
+        let mut records = 0;
+
+        create_dir_all(to.as_ref())?;
+
+        for name in manifest.shards.values() {
+            records += self.redact_trace_file(from.as_ref().join(name), to.as_ref().join(name))?;
+        }
+
+        manifest.write(to)?;
+
+        Ok(records)
+    }
+}
+
+/// Replace `value` with an empty value of the same kind
+fn strip(value: &mut Value) {
+    *value = match value {
+        Value::Text(_) => Value::Text(String::new()),
+        Value::Bytes(_) => Value::Bytes(Vec::new()),
+        Value::Array(_) => Value::Array(Vec::new()),
+        Value::Map(_) => Value::Map(BTreeMap::new()),
+        Value::Integer(_) => Value::Integer(0),
+        _ => Value::Null,
+    };
+}
+
+/// Returns the bytes an array holds, if it holds only integers which fit in a byte
+fn bytes(values: &[Value]) -> Option<Vec<u8>> {
+    values
+        .iter()
+        .map(|v| match v {
+            Value::Integer(i) => u8::try_from(*i).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
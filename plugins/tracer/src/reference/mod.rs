@@ -0,0 +1,298 @@
+//! Verification of each executed instruction against a reference model, for instruction-set
+//! verification with QEMU as the golden model or as the model under test
+//!
+//! A [`ReferenceModel`] is Rust code registered on a preset by a plugin built on this crate:
+//!
+//! ```rust,ignore
+//! use ctor::ctor;
+//! use tracer::reference::{Capture, ReferenceModel, Step, Verdict};
+//!
+//! struct Model;
+//!
+//! impl ReferenceModel for Model {
+//!     fn name(&self) -> &str {
+//!         "model"
+//!     }
+//!
+//!     fn capture(&self) -> Capture {
+//!         Capture::Registers(vec!["rax".to_string()])
+//!     }
+//!
+//!     fn on_step(&mut self, step: &Step) -> Verdict {
+//!         // Execute `step.opcode` on the model from `step.before`, and compare the result
+//!         // with `step.after`
+//!         Verdict::Agree
+//!     }
+//! }
+//!
+//! #[ctor]
+//! fn init() {
+//!     tracer::presets::exec_trace("trace.bin")
+//!         .reference_model(Model)
+//!         .and_then(|preset| preset.install())
+//!         .expect("Failed to install tracer");
+//! }
+//! ```
+//!
+//! Each model is shown every instruction each vCPU executes, with the registers the models
+//! ask to capture as they were before the instruction and after it. The registers after an
+//! instruction are read when the vCPU's next instruction starts, so a model is shown an
+//! instruction once its successor executes, and the last instruction of each vCPU at exit,
+//! without the registers after it. An interrupt or exception taken after an instruction is
+//! part of its effects. A divergence a model flags is sent as a [`DivergenceEvent`], and one
+//! it vetoes also ends the run, with status 125.
+
+use crate::log;
+use anyhow::{anyhow, Result};
+use qemu_plugin::{RegisterDescriptor, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex},
+};
+use typed_builder::TypedBuilder;
+
+/// The status the run ends with when a model vetoes an instruction
+pub const VETO_STATUS: i32 = 125;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// The registers a model is shown
+pub enum Capture {
+    #[default]
+    None,
+    Registers(Vec<String>),
+    All,
+}
+
+/// Register values by name, as bytes in the guest's byte order
+pub type RegisterValues = HashMap<String, Vec<u8>>;
+
+#[derive(Clone, Copy, Debug)]
+/// An instruction a vCPU executed
+pub struct Step<'a> {
+    pub vcpu_index: VCPUIndex,
+    pub pc: u64,
+    /// The instruction's bytes
+    pub opcode: &'a [u8],
+    pub disas: &'a str,
+    /// The instruction count when the instruction executed
+    pub icount: u64,
+    /// The captured registers before the instruction, if any model captures registers
+    pub before: Option<&'a RegisterValues>,
+    /// The captured registers after the instruction, if any model captures registers and
+    /// the vCPU executed another instruction
+    pub after: Option<&'a RegisterValues>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A model's judgement of an instruction
+pub enum Verdict {
+    Agree,
+    /// The model disagrees, and the run continues
+    Diverge(String),
+    /// The model disagrees, and the run ends
+    Veto(String),
+}
+
+/// A model instructions are checked against
+pub trait ReferenceModel: Send {
+    fn name(&self) -> &str;
+
+    /// The registers the model needs to see
+    fn capture(&self) -> Capture {
+        Capture::None
+    }
+
+    fn on_step(&mut self, step: &Step) -> Verdict;
+
+    /// Called once the run finishes
+    fn on_exit(&mut self) {}
+}
+
+#[derive(TypedBuilder, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// An instruction a reference model disagreed with
+pub struct DivergenceEvent {
+    pub vcpu_index: VCPUIndex,
+    pub pc: u64,
+    pub model: String,
+    pub message: String,
+    /// Whether the model ended the run
+    pub vetoed: bool,
+    pub icount: u64,
+}
+
+#[derive(Debug)]
+/// An instruction awaiting the registers after it
+struct Pending {
+    pc: u64,
+    opcode: Arc<[u8]>,
+    disas: Arc<str>,
+    icount: u64,
+    before: Option<RegisterValues>,
+}
+
+#[derive(Default)]
+/// Shows each executed instruction to the registered models
+pub struct ReferenceChecker {
+    models: Vec<Box<dyn ReferenceModel>>,
+    pending: HashMap<VCPUIndex, Pending>,
+}
+
+impl Debug for ReferenceChecker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReferenceChecker")
+            .field(
+                "models",
+                &self.models.iter().map(|m| m.name()).collect::<Vec<_>>(),
+            )
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+impl ReferenceChecker {
+    pub fn add(&mut self, model: Box<dyn ReferenceModel>) {
+        self.models.push(model);
+    }
+
+    /// The registers read at each instruction, the union of those the models capture
+    pub fn registers(
+        &self,
+        registers: &[RegisterDescriptor<'static>],
+    ) -> Vec<RegisterDescriptor<'static>> {
+        let captures = self.models.iter().map(|m| m.capture()).collect::<Vec<_>>();
+
+        registers
+            .iter()
+            .filter(|register| {
+                captures.iter().any(|capture| match capture {
+                    Capture::None => false,
+                    Capture::Registers(names) => names.contains(&register.name),
+                    Capture::All => true,
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Record that a vCPU is about to execute an instruction, with the values of the
+    /// captured registers, and show its previous instruction to the models. Returns the
+    /// divergences they found.
+    pub fn on_instruction(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        pc: u64,
+        opcode: Arc<[u8]>,
+        disas: Arc<str>,
+        registers: &[RegisterDescriptor<'static>],
+        icount: u64,
+    ) -> Result<Vec<DivergenceEvent>> {
+        let values = if registers.is_empty() {
+            None
+        } else {
+            Some(
+                registers
+                    .iter()
+                    .map(|r| Ok((r.name.clone(), r.read()?)))
+                    .collect::<Result<RegisterValues>>()?,
+            )
+        };
+        let previous = self.pending.insert(
+            vcpu_index,
+            Pending {
+                pc,
+                opcode,
+                disas,
+                icount,
+                before: values.clone(),
+            },
+        );
+
+        Ok(match previous {
+            Some(previous) => self.check(vcpu_index, &previous, values.as_ref()),
+            None => Vec::new(),
+        })
+    }
+
+    fn check(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        pending: &Pending,
+        after: Option<&RegisterValues>,
+    ) -> Vec<DivergenceEvent> {
+        let step = Step {
+            vcpu_index,
+            pc: pending.pc,
+            opcode: &pending.opcode,
+            disas: &pending.disas,
+            icount: pending.icount,
+            before: pending.before.as_ref(),
+            after,
+        };
+
+        self.models
+            .iter_mut()
+            .filter_map(|model| {
+                let (message, vetoed) = match model.on_step(&step) {
+                    Verdict::Agree => return None,
+                    Verdict::Diverge(message) => (message, false),
+                    Verdict::Veto(message) => (message, true),
+                };
+
+                Some(
+                    DivergenceEvent::builder()
+                        .vcpu_index(vcpu_index)
+                        .pc(step.pc)
+                        .model(model.name().to_string())
+                        .message(message)
+                        .vetoed(vetoed)
+                        .icount(step.icount)
+                        .build(),
+                )
+            })
+            .collect()
+    }
+
+    /// Show the models each vCPU's last instruction and tell them the run finished. Returns
+    /// the divergences they found.
+    pub fn finish(&mut self) -> Vec<DivergenceEvent> {
+        let mut pending = self.pending.drain().collect::<Vec<_>>();
+        let mut events = Vec::new();
+
+        pending.sort_by_key(|(vcpu_index, _)| *vcpu_index);
+
+        for (vcpu_index, pending) in pending {
+            events.extend(self.check(vcpu_index, &pending, None));
+        }
+
+        for model in &mut self.models {
+            model.on_exit();
+        }
+
+        events
+    }
+}
+
+/// Print a vetoed divergence and end the run
+pub fn veto(event: &DivergenceEvent) -> ! {
+    log(format!(
+        "Reference model {} vetoed the instruction at {:#x} on vCPU {}: {}",
+        event.model, event.pc, event.vcpu_index, event.message
+    ));
+    std::process::exit(VETO_STATUS)
+}
+
+/// Add a model to a tracer's checker, creating the checker if it has none
+pub(crate) fn register(
+    checker: &mut Option<Arc<Mutex<ReferenceChecker>>>,
+    model: Box<dyn ReferenceModel>,
+) -> Result<()> {
+    checker
+        .get_or_insert_with(Default::default)
+        .lock()
+        .map_err(|e| anyhow!("Failed to lock reference checker: {e}"))?
+        .add(model);
+
+    Ok(())
+}
@@ -0,0 +1,258 @@
+//! Translation block flush, invalidation and retranslation statistics
+//!
+//! QEMU translates a block again after its translation is thrown away, either by a flush of
+//! the whole translation cache, which happens when the cache fills, when the guest changes
+//! some global translation state, or when a plugin asks for one, or by an invalidation of the
+//! blocks on a single page, which happens when the guest writes to a page it has executed
+//! code from or remaps it. Each translation is recorded with a hash of the block's code, so
+//! that a retranslation can be attributed to a flush, if one happened since the block was
+//! last translated, or otherwise to an invalidation, and so that blocks whose code changed
+//! are told apart from blocks retranslated unchanged.
+//!
+//! The report lists the blocks and code pages translated most often. Pages with many
+//! modified blocks usually hold self-modifying or JIT-compiled code, while many unchanged
+//! retranslations on a page which is not written point at code sharing a page with data.
+//! With `block_identity=content`, each block is also identified by its module, its offset in
+//! the module and the hash of its last translation, which stay the same across runs.
+
+use crate::coverage::{BlockId, Module};
+use anyhow::Result;
+use qemu_plugin::path::create_sink;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+/// Blocks are grouped into pages of this size, the smallest target page size
+const PAGE_SIZE: u64 = 0x1000;
+
+/// A hash of the code of a block, given as the data of each of its instructions
+pub fn code_hash<I>(instructions: I) -> u64
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    let mut hasher = DefaultHasher::new();
+
+    for data in instructions {
+        data.hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// How often a block or page was translated, and why it was translated again
+pub struct Churn {
+    pub translations: u64,
+    /// Retranslations with no flush since the previous translation
+    pub invalidations: u64,
+    /// Retranslations whose code differed from the previous translation
+    pub modifications: u64,
+}
+
+impl Churn {
+    fn retranslations(&self) -> u64 {
+        self.translations.saturating_sub(1)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct BlockState {
+    size: usize,
+    hash: u64,
+    /// The number of flushes when the block was last translated
+    epoch: u64,
+    churn: Churn,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A block translated more than once
+pub struct BlockChurn {
+    pub vaddr: u64,
+    pub size: usize,
+    /// The block's identity across runs, with content identity
+    #[serde(default)]
+    pub id: Option<BlockId>,
+    #[serde(flatten)]
+    pub churn: Churn,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A page of code translated more than once
+pub struct PageChurn {
+    pub page: u64,
+    /// The number of distinct blocks translated on the page
+    pub blocks: u64,
+    #[serde(flatten)]
+    pub churn: Churn,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A summary of translation cache churn over a run
+pub struct RetranslationReport {
+    pub translations: u64,
+    /// The number of distinct block addresses translated
+    pub blocks: u64,
+    pub retranslations: u64,
+    pub invalidations: u64,
+    pub modifications: u64,
+    /// The instruction count at each flush of the translation cache
+    pub flushes: Vec<u64>,
+    /// The blocks translated most often
+    pub hot_blocks: Vec<BlockChurn>,
+    /// The pages translated most often
+    pub hot_pages: Vec<PageChurn>,
+}
+
+#[derive(Debug)]
+/// Records every translation and flush
+pub struct RetranslationTracker {
+    /// How many blocks and pages the report includes
+    top: usize,
+    /// The modules blocks are identified in, with content identity
+    modules: Option<Vec<Module>>,
+    blocks: HashMap<u64, BlockState>,
+    flushes: Vec<u64>,
+    churn: Churn,
+}
+
+impl RetranslationTracker {
+    /// Create a tracker whose report includes the `top` blocks and pages translated most
+    pub fn new(top: usize) -> Self {
+        Self {
+            top,
+            modules: None,
+            blocks: HashMap::new(),
+            flushes: Vec::new(),
+            churn: Churn::default(),
+        }
+    }
+
+    /// Identify blocks in the report by their offset in one of `modules` and their code hash
+    pub fn with_block_ids(mut self, modules: Vec<Module>) -> Self {
+        self.modules = Some(modules);
+        self
+    }
+
+    /// Record the translation of a block of `size` bytes at `vaddr` with code hashed by
+    /// [`code_hash`]
+    pub fn on_translate(&mut self, vaddr: u64, size: usize, hash: u64) {
+        let epoch = self.flushes.len() as u64;
+
+        match self.blocks.get_mut(&vaddr) {
+            Some(block) => {
+                let invalidated = block.epoch == epoch;
+                let modified = block.hash != hash;
+
+                for churn in [&mut block.churn, &mut self.churn] {
+                    churn.translations += 1;
+                    churn.invalidations += invalidated as u64;
+                    churn.modifications += modified as u64;
+                }
+
+                block.size = size;
+                block.hash = hash;
+                block.epoch = epoch;
+            }
+            None => {
+                self.churn.translations += 1;
+                self.blocks.insert(
+                    vaddr,
+                    BlockState {
+                        size,
+                        hash,
+                        epoch,
+                        churn: Churn {
+                            translations: 1,
+                            ..Default::default()
+                        },
+                    },
+                );
+            }
+        }
+    }
+
+    /// Record a flush of the translation cache at instruction count `icount`
+    pub fn on_flush(&mut self, icount: u64) {
+        self.flushes.push(icount);
+    }
+
+    pub fn report(&self) -> RetranslationReport {
+        let mut hot_blocks = self
+            .blocks
+            .iter()
+            .filter(|(_, block)| block.churn.retranslations() > 0)
+            .map(|(vaddr, block)| BlockChurn {
+                vaddr: *vaddr,
+                size: block.size,
+                id: self
+                    .modules
+                    .as_ref()
+                    .map(|modules| BlockId::new(modules, *vaddr, block.hash)),
+                churn: block.churn.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        hot_blocks.sort_by_key(|b| {
+            (
+                std::cmp::Reverse(b.churn.translations),
+                std::cmp::Reverse(b.churn.modifications),
+                b.vaddr,
+            )
+        });
+        hot_blocks.truncate(self.top);
+
+        let mut pages = HashMap::<u64, PageChurn>::new();
+
+        for (vaddr, block) in &self.blocks {
+            let page = vaddr & !(PAGE_SIZE - 1);
+            let entry = pages.entry(page).or_insert(PageChurn {
+                page,
+                blocks: 0,
+                churn: Churn::default(),
+            });
+
+            entry.blocks += 1;
+            entry.churn.translations += block.churn.translations;
+            entry.churn.invalidations += block.churn.invalidations;
+            entry.churn.modifications += block.churn.modifications;
+        }
+
+        let mut hot_pages = pages
+            .into_values()
+            .filter(|page| page.churn.translations > page.blocks)
+            .collect::<Vec<_>>();
+
+        hot_pages.sort_by_key(|p| {
+            (
+                std::cmp::Reverse(p.churn.translations - p.blocks),
+                std::cmp::Reverse(p.churn.modifications),
+                p.page,
+            )
+        });
+        hot_pages.truncate(self.top);
+
+        RetranslationReport {
+            translations: self.churn.translations,
+            blocks: self.blocks.len() as u64,
+            retranslations: self.churn.translations - self.blocks.len() as u64,
+            invalidations: self.churn.invalidations,
+            modifications: self.churn.modifications,
+            flushes: self.flushes.clone(),
+            hot_blocks,
+            hot_pages,
+        }
+    }
+
+    /// Write the report to `path` as JSON
+    pub fn write_report<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        serde_json::to_writer_pretty(create_sink(path)?, &self.report())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+//! A heuristic detector for return-oriented programming
+//!
+//! A legitimate return lands just after a call, so the return addresses of every call QEMU
+//! has translated are recorded, and a return landing anywhere else is suspicious. A ROP
+//! chain also executes many short blocks ending in returns back to back: each gadget, a
+//! block of at most `gadget_len` instructions which ends in a return and is itself reached
+//! by a return, is suspicious too.
+//!
+//! Each vCPU keeps a score. Returns to targets not preceded by a call add `return_weight`,
+//! gadgets add `gadget_weight`, and every other block subtracts `decay`, so that isolated
+//! anomalies, such as `longjmp` or a signal handler returning, fade. When a score reaches
+//! `threshold`, a ROP event is sent with the recent control transfers of the vCPU and its
+//! shadow call stack, and the score is reset.
+
+use qemu_plugin::VCPUIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use typed_builder::TypedBuilder;
+
+#[derive(TypedBuilder, Clone, Copy, Debug, Deserialize, Serialize)]
+/// The weights and threshold of the scoring model
+pub struct RopConfig {
+    #[builder(default = 10)]
+    pub return_weight: u64,
+    #[builder(default = 4)]
+    pub gadget_weight: u64,
+    #[builder(default = 1)]
+    pub decay: u64,
+    #[builder(default = 30)]
+    pub threshold: u64,
+    /// The most instructions a block may have to count as a gadget
+    #[builder(default = 5)]
+    pub gadget_len: usize,
+    /// How many recent blocks are included in events
+    #[builder(default = 32)]
+    pub history: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A block executed before a detection
+pub struct Transfer {
+    pub vaddr: u64,
+    pub instructions: usize,
+    /// Whether the block ends in a return
+    pub returns: bool,
+    /// Whether the block was reached by a return to a target not preceded by a call
+    pub unexpected: bool,
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// Control flow whose score reached the threshold
+pub struct RopEvent {
+    pub vcpu_index: VCPUIndex,
+    /// The block executing when the threshold was reached
+    pub pc: u64,
+    pub score: u64,
+    /// The returns to targets not preceded by a call since the score was last reset
+    pub unexpected_returns: u64,
+    /// The gadgets executed since the score was last reset
+    pub gadgets: u64,
+    /// The most recent blocks, oldest first
+    pub history: Vec<Transfer>,
+    /// The return addresses on the shadow call stack, innermost first, when the heap
+    /// tracker keeps one
+    pub call_stack: Vec<u64>,
+    pub icount: u64,
+}
+
+#[derive(Debug, Default)]
+struct VcpuState {
+    score: u64,
+    unexpected_returns: u64,
+    gadgets: u64,
+    /// Whether the last block executed ended in a return
+    returning: bool,
+    history: VecDeque<Transfer>,
+}
+
+#[derive(Debug)]
+/// Scores each vCPU's control flow for signs of a ROP chain
+pub struct RopDetector {
+    config: RopConfig,
+    /// The return addresses of every call translated
+    call_sites: HashSet<u64>,
+    vcpus: HashMap<VCPUIndex, VcpuState>,
+}
+
+impl RopDetector {
+    pub fn new(config: RopConfig) -> Self {
+        Self {
+            config,
+            call_sites: HashSet::new(),
+            vcpus: HashMap::new(),
+        }
+    }
+
+    /// Record that a call whose return address is `return_addr` was translated
+    pub fn on_call_translated(&mut self, return_addr: u64) {
+        self.call_sites.insert(return_addr);
+    }
+
+    /// Record that a vCPU executed the block at `vaddr`, of `instructions` instructions and
+    /// ending in a return if `returns` is set. Returns an event if its score reached the
+    /// threshold. `call_stack` is only called then.
+    pub fn on_block(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        vaddr: u64,
+        instructions: usize,
+        returns: bool,
+        icount: u64,
+        call_stack: impl FnOnce() -> Vec<u64>,
+    ) -> Option<RopEvent> {
+        let config = self.config;
+        let state = self.vcpus.entry(vcpu_index).or_default();
+        let returned = std::mem::replace(&mut state.returning, returns);
+        let unexpected = returned && !self.call_sites.contains(&vaddr);
+        let gadget = returned && returns && instructions <= config.gadget_len;
+
+        if state.history.len() >= config.history {
+            state.history.pop_front();
+        }
+
+        state.history.push_back(Transfer {
+            vaddr,
+            instructions,
+            returns,
+            unexpected,
+        });
+
+        if unexpected {
+            state.score += config.return_weight;
+            state.unexpected_returns += 1;
+        }
+
+        if gadget {
+            state.score += config.gadget_weight;
+            state.gadgets += 1;
+        }
+
+        if !unexpected && !gadget {
+            state.score = state.score.saturating_sub(config.decay);
+
+            if state.score == 0 {
+                state.unexpected_returns = 0;
+                state.gadgets = 0;
+            }
+
+            return None;
+        }
+
+        if state.score < config.threshold {
+            return None;
+        }
+
+        let event = RopEvent::builder()
+            .vcpu_index(vcpu_index)
+            .pc(vaddr)
+            .score(state.score)
+            .unexpected_returns(state.unexpected_returns)
+            .gadgets(state.gadgets)
+            .history(state.history.iter().copied().collect())
+            .call_stack(call_stack())
+            .icount(icount)
+            .build();
+
+        state.score = 0;
+        state.unexpected_returns = 0;
+        state.gadgets = 0;
+
+        Some(event)
+    }
+}
@@ -0,0 +1,176 @@
+//! Periodic samples of guest memory, laid out for analysis with Volatility3
+//!
+//! Each sample is a physical memory ELF core written by QEMU's `dump-guest-memory`, which
+//! Volatility3 opens with its ELF layer. Next to it are a JSON description of the sample
+//! and a Volatility3 configuration file, so a sample can be analyzed with e.g.
+//! `vol -c sample-1.vol.json -s <symbols> linux.pslist`.
+//!
+//! Samples are requested over QMP from a thread of the plugin's own, so sampling requires a
+//! QMP socket and is only meaningful in system mode. The vCPUs are stopped while a sample is
+//! written.
+
+use crate::{log, memmap::MemoryMap, qmp::Qmp, stats::Stats};
+use anyhow::Result;
+use qemu_plugin::path::create_sink;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    fs::{canonicalize, create_dir_all},
+    path::PathBuf,
+    sync::Arc,
+    thread::{sleep, spawn},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use typed_builder::TypedBuilder;
+
+#[derive(TypedBuilder, Clone, Debug)]
+/// How often to sample, and where to write samples
+pub struct SampleConfig {
+    /// The directory samples are written to, as `sample-<n>.elf`
+    pub dir: PathBuf,
+    pub qmp_socket: PathBuf,
+    #[builder(default = Duration::from_secs(60))]
+    pub interval: Duration,
+    /// The maximum number of samples to take, or `None` to sample until QEMU exits
+    #[builder(default)]
+    pub limit: Option<usize>,
+    /// A directory of Volatility3 symbol tables matching the guest kernel
+    #[builder(default)]
+    pub symbols: Option<PathBuf>,
+    /// The virtual address the guest kernel is loaded at, if known
+    #[builder(default)]
+    pub kernel_base: Option<u64>,
+    #[builder(default)]
+    pub target_name: Option<String>,
+    #[builder(default)]
+    pub cpu_model: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A description of a sample, written next to it as `sample-<n>.json`
+pub struct SampleMetadata {
+    pub index: usize,
+    pub path: PathBuf,
+    pub timestamp_ms: u128,
+    pub icount: u64,
+    pub target_name: Option<String>,
+    pub cpu_model: Option<String>,
+    pub symbols: Option<PathBuf>,
+    pub kernel_base: Option<u64>,
+    /// The page table base (`CR3`) of each vCPU when the sample was taken. Only recovered
+    /// for x86 targets.
+    pub page_tables: Vec<u64>,
+    pub memory_map: Option<MemoryMap>,
+}
+
+/// Returns the page table base of each vCPU from the output of `info registers -a`, whose
+/// control register lines contain e.g. `CR3=0000000001a0c000`
+fn parse_page_tables(registers: &str) -> Vec<u64> {
+    registers
+        .split_whitespace()
+        .filter_map(|field| field.strip_prefix("CR3="))
+        .filter_map(|cr3| u64::from_str_radix(cr3, 16).ok())
+        .map(|cr3| cr3 & !0xfff)
+        .collect()
+}
+
+#[derive(Debug)]
+/// Takes samples over a QMP connection
+struct Sampler {
+    config: SampleConfig,
+    stats: Arc<Stats>,
+    qmp: Qmp,
+    memory_map: Option<MemoryMap>,
+}
+
+impl Sampler {
+    fn sample(&mut self, index: usize) -> Result<()> {
+        let path = self.config.dir.join(format!("sample-{index}.elf"));
+
+        // Registers are read first, as they keep changing until the dump stops the VM
+        let page_tables = parse_page_tables(
+            &self
+                .qmp
+                .human_monitor_command("info registers -a")
+                .unwrap_or_default(),
+        );
+        let icount = self.stats.icount();
+
+        self.qmp.dump_guest_memory(&path, false)?;
+
+        // Volatility3 resolves relative locations against its own working directory
+        let path = canonicalize(&path)?;
+
+        let metadata = SampleMetadata {
+            index,
+            path: path.clone(),
+            timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis(),
+            icount,
+            target_name: self.config.target_name.clone(),
+            cpu_model: self.config.cpu_model.clone(),
+            symbols: self.config.symbols.clone(),
+            kernel_base: self.config.kernel_base,
+            page_tables: page_tables.clone(),
+            memory_map: self.memory_map.clone(),
+        };
+
+        serde_json::to_writer_pretty(
+            create_sink(self.config.dir.join(format!("sample-{index}.json")))?,
+            &metadata,
+        )?;
+
+        let mut config = json!({
+            "automagic.LayerStacker.single_location": format!("file://{}", path.display()),
+        });
+
+        if let Some(dtb) = page_tables.first() {
+            config["kernel.layer_name.page_map_offset"] = json!(dtb);
+        }
+
+        serde_json::to_writer_pretty(
+            create_sink(self.config.dir.join(format!("sample-{index}.vol.json")))?,
+            &config,
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Start sampling on a thread of its own. The first sample is taken one interval after
+/// QEMU's main loop starts serving QMP.
+pub fn sample_periodically(config: SampleConfig, stats: Arc<Stats>) -> Result<()> {
+    create_dir_all(&config.dir)?;
+
+    spawn(move || {
+        let mut sampler = match Qmp::connect(&config.qmp_socket) {
+            Ok(mut qmp) => Sampler {
+                memory_map: qmp
+                    .human_monitor_command("info mtree -f")
+                    .and_then(|mtree| MemoryMap::parse_mtree(&mtree))
+                    .ok(),
+                config,
+                stats,
+                qmp,
+            },
+            Err(e) => {
+                log(format!("Failed to connect to QMP for memory sampling: {e}"));
+                return;
+            }
+        };
+
+        let mut index = 1;
+
+        while sampler.config.limit.is_none_or(|limit| index <= limit) {
+            sleep(sampler.config.interval);
+
+            if let Err(e) = sampler.sample(index) {
+                log(format!("Failed to take memory sample {index}: {e}"));
+                return;
+            }
+
+            index += 1;
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,163 @@
+//! A timeline of the guest threads each vCPU runs, recovered from changes to the thread
+//! pointer register
+//!
+//! Each guest thread has its own thread-local storage, whose base the C library keeps in a
+//! per-architecture register: `fs_base` on x86_64, `gs_base` on i386, `TPIDRURO` on ARM and
+//! `TPIDR_EL0` on AArch64. The register is read at the start of every block, and a change
+//! ends the running thread's slice of the vCPU. Threads are identified by their thread
+//! pointer, which is unique among live threads but may be reused once a thread exits.
+//!
+//! The timeline can be written as a Chrome trace, which Perfetto opens directly, with one
+//! track per vCPU and instruction counts in place of timestamps. Bookmarks in the trace are
+//! shown as markers on the tracks.
+
+use crate::{
+    arch::Arch,
+    bookmarks::{bookmarks, chrome_markers},
+    heap::read_register,
+    Event,
+};
+use anyhow::{anyhow, Result};
+use qemu_plugin::{path::create_sink, RegisterDescriptor, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::HashMap, path::Path};
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A span of instructions during which a vCPU ran one guest thread
+pub struct ScheduleEvent {
+    pub vcpu_index: VCPUIndex,
+    pub thread_pointer: u64,
+    /// The instruction count when the thread was switched to
+    pub on_icount: u64,
+    /// The instruction count when the thread was switched away from
+    pub off_icount: u64,
+}
+
+#[derive(Debug)]
+/// The thread running on each vCPU, and since when
+pub struct Scheduler {
+    arch: Arch,
+    /// The name of the thread pointer register, once the vCPU's registers are known
+    register: Option<&'static str>,
+    running: HashMap<VCPUIndex, (u64, u64)>,
+}
+
+impl Scheduler {
+    /// Create a scheduler with no threads running
+    pub fn new(arch: Arch) -> Self {
+        Self {
+            arch,
+            register: None,
+            running: HashMap::new(),
+        }
+    }
+
+    /// Find the thread pointer register among a vCPU's registers
+    pub fn find_register(&mut self, registers: &[RegisterDescriptor<'static>]) -> Result<()> {
+        let register = self
+            .arch
+            .thread_pointer_names()
+            .iter()
+            .find(|name| registers.iter().any(|r| r.name == **name))
+            .ok_or_else(|| anyhow!("QEMU does not expose the thread pointer register"))?;
+
+        self.register = Some(register);
+
+        Ok(())
+    }
+
+    /// Record that a block executed on a vCPU. Returns the slice of the previous thread if
+    /// the vCPU has switched threads since its last block.
+    pub fn on_block(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        registers: &[RegisterDescriptor<'static>],
+        icount: u64,
+    ) -> Result<Option<ScheduleEvent>> {
+        let register = self
+            .register
+            .ok_or_else(|| anyhow!("No thread pointer register"))?;
+        let thread_pointer = read_register(self.arch, registers, register)?;
+        let running = self
+            .running
+            .entry(vcpu_index)
+            .or_insert((thread_pointer, icount));
+
+        if running.0 == thread_pointer {
+            return Ok(None);
+        }
+
+        let (previous, on_icount) = std::mem::replace(running, (thread_pointer, icount));
+
+        Ok(Some(ScheduleEvent {
+            vcpu_index,
+            thread_pointer: previous,
+            on_icount,
+            off_icount: icount,
+        }))
+    }
+
+    /// End the slices of every running thread, for reporting at exit
+    pub fn finish(&mut self, icount: u64) -> Vec<ScheduleEvent> {
+        let mut slices = self
+            .running
+            .drain()
+            .map(|(vcpu_index, (thread_pointer, on_icount))| ScheduleEvent {
+                vcpu_index,
+                thread_pointer,
+                on_icount,
+                off_icount: icount,
+            })
+            .collect::<Vec<_>>();
+
+        slices.sort_by_key(|s| s.vcpu_index);
+        slices
+    }
+}
+
+/// Write a timeline as a Chrome trace, with a track for each vCPU, a slice for each span a
+/// thread ran on it, and a marker for each bookmark in `events`
+pub fn write_timeline<P>(path: P, slices: &[ScheduleEvent], events: &[Event]) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let mut vcpus = slices.iter().map(|s| s.vcpu_index).collect::<Vec<_>>();
+
+    vcpus.sort();
+    vcpus.dedup();
+
+    let names = vcpus.into_iter().map(|vcpu_index| {
+        json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": 0,
+            "tid": vcpu_index,
+            "args": { "name": format!("vCPU {vcpu_index}") },
+        })
+    });
+    let spans = slices.iter().map(|s| {
+        json!({
+            "name": format!("thread {:#x}", s.thread_pointer),
+            "cat": "schedule",
+            "ph": "X",
+            "pid": 0,
+            "tid": s.vcpu_index,
+            "ts": s.on_icount,
+            "dur": s.off_icount - s.on_icount,
+            "args": { "thread_pointer": format!("{:#x}", s.thread_pointer) },
+        })
+    });
+
+    let markers = chrome_markers(bookmarks(events).map(|(_, bookmark)| bookmark));
+
+    serde_json::to_writer(
+        create_sink(path)?,
+        &json!({
+            "traceEvents": names.chain(spans).chain(markers).collect::<Vec<_>>(),
+            "displayTimeUnit": "ns",
+        }),
+    )?;
+
+    Ok(())
+}
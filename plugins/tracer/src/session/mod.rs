@@ -0,0 +1,179 @@
+//! Run manifests, describing how a capture was made so that it can be reproduced
+//!
+//! A run manifest records the QEMU binary and version, the plugin API version, the target,
+//! the plugin's arguments and a hash of them, hashes of the guest binaries, and the capture's
+//! counters when it started and ended. It is written when the plugin is installed and again
+//! at exit, so a capture cut short still has one, without end counters.
+//!
+//! Where the manifest goes depends on the sink: next to a trace file as `<trace>.run.json`,
+//! into a sharded trace's directory or an object store prefix as `run.json`, to an aggregator
+//! as a report, and to `run.json` in the working directory otherwise, unless a path is given.
+
+use crate::{aslr::LoadedModule, stats::StatsSnapshot};
+use anyhow::Result;
+use qemu_plugin::{
+    install::{qemu_plugin_version, Args, Info},
+    path::create_sink,
+    qemu_plugin_path_to_binary,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::{read, read_link},
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const VERSION: u64 = 1;
+
+/// The name of the manifest in a directory or object store prefix
+pub const RUN_MANIFEST: &str = "run.json";
+
+/// Arguments which differ between otherwise identical runs, such as the random socket path
+/// the `tracer` binary listens on, and are left out of the configuration hash
+const EPHEMERAL_ARGS: &[&str] = &["socket_path"];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A file the guest ran, and its hash
+pub struct BinaryHash {
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl BinaryHash {
+    fn read<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let data = read(path.as_ref())?;
+
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            size: data.len() as u64,
+            sha256: hex(&Sha256::digest(&data)),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Describes a capture
+pub struct RunManifest {
+    pub version: u64,
+    /// The QEMU binary running the plugin
+    pub qemu: Option<PathBuf>,
+    /// The version QEMU reports, such as `qemu-x86_64 version 9.2.0`
+    pub qemu_version: Option<String>,
+    /// The plugin API version the plugin was built for
+    pub plugin_api_version: i64,
+    /// The current and minimum plugin API versions QEMU supports
+    pub qemu_api_version: (i64, i64),
+    /// The QEMU target, such as `x86_64-linux-user`
+    pub target: String,
+    pub system: bool,
+    /// The plugin's arguments
+    pub args: BTreeMap<String, String>,
+    /// A hash of the plugin's arguments, without ephemeral ones, which is the same for runs
+    /// made with the same configuration
+    pub config_hash: String,
+    /// The guest program in user mode, and any other files given to hash
+    pub binaries: Vec<BinaryHash>,
+    pub start: StatsSnapshot,
+    /// The counters at exit, if the capture finished
+    pub end: Option<StatsSnapshot>,
+    /// The modules loaded, with the biases their code addresses were normalized by
+    #[serde(default)]
+    pub modules: Vec<LoadedModule>,
+}
+
+impl RunManifest {
+    /// Describe a capture starting now, hashing the guest program and `binaries`
+    pub fn new(
+        args: &Args,
+        info: &Info,
+        binaries: &[PathBuf],
+        start: StatsSnapshot,
+    ) -> Result<Self> {
+        let args = args
+            .raw
+            .iter()
+            .map(|arg| match arg.split_once('=') {
+                Some((k, v)) => (k.to_string(), v.to_string()),
+                None => (arg.clone(), String::new()),
+            })
+            .collect::<BTreeMap<_, _>>();
+        let mut config = Sha256::new();
+
+        for (k, v) in args
+            .iter()
+            .filter(|(k, _)| !EPHEMERAL_ARGS.contains(&k.as_str()))
+        {
+            config.update(format!("{k}={v}\n").as_bytes());
+        }
+
+        let qemu = read_link("/proc/self/exe").ok();
+        let qemu_version = qemu.as_ref().and_then(|qemu| {
+            let output = Command::new(qemu).arg("--version").output().ok()?;
+
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .map(|line| line.trim().to_string())
+        });
+
+        Ok(Self {
+            version: VERSION,
+            qemu,
+            qemu_version,
+            plugin_api_version: qemu_plugin_version as i64,
+            qemu_api_version: (info.version.current, info.version.mininum),
+            target: info.target_name.clone(),
+            system: info.system.is_some(),
+            args,
+            config_hash: hex(&config.finalize()),
+            binaries: qemu_plugin_path_to_binary()?
+                .into_iter()
+                .chain(binaries.iter().cloned())
+                .map(BinaryHash::read)
+                .collect::<Result<_>>()?,
+            start,
+            end: None,
+            modules: Vec::new(),
+        })
+    }
+
+    /// Record the counters at exit
+    pub fn finish(&mut self, end: StatsSnapshot) {
+        self.end = Some(end);
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        let mut json = serde_json::to_vec_pretty(self)?;
+
+        json.push(b'\n');
+
+        Ok(json)
+    }
+
+    /// Write the manifest to `path`, replacing its contents
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(create_sink(path)?.write_all(&self.to_json()?)?)
+    }
+
+    /// Read a manifest written by [`RunManifest::write`]
+    pub fn read<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(serde_json::from_slice(&read(path)?)?)
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,127 @@
+//! A shadow stack integrity checker, a CFI-style monitor of the guest's returns
+//!
+//! Each call pushes its return address to a per-vCPU shadow stack, and each return is
+//! checked against the top of the stack once the block it lands on executes. A return to any
+//! other address, as when a return address on the guest's stack has been overwritten, is
+//! an integrity violation, reported with the expected and actual addresses and the shadow
+//! call stack. The shadow stack is left as it was, so that a signal handler returning to the
+//! kernel's restorer, which no call precedes, does not desynchronize it.
+//!
+//! A return landing on a deeper frame's return address unwinds the frames above it, as C++
+//! exception unwinding does. Such returns are only reported in strict mode.
+//!
+//! Stacks are kept per vCPU, which in user mode is per guest thread. In system mode, a guest
+//! kernel switching tasks on a vCPU switches stacks under the checker, so it is only
+//! meaningful within a single task.
+
+use qemu_plugin::VCPUIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use typed_builder::TypedBuilder;
+
+/// The deepest a shadow stack may grow before its oldest frames are dropped, bounding the
+/// cost of calls which never return
+const MAX_FRAMES: usize = 4096;
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// A return which did not land on the return address of the call it returned from
+pub struct IntegrityEvent {
+    pub vcpu_index: VCPUIndex,
+    /// The address of the return instruction
+    pub pc: u64,
+    /// The return address the matching call pushed
+    pub expected: u64,
+    /// The address the return landed on
+    pub actual: u64,
+    /// The frames the return unwound, when it landed on a deeper frame's return address
+    pub unwound: Option<usize>,
+    /// The return addresses on the shadow stack before the return, innermost first
+    pub call_stack: Vec<u64>,
+    pub icount: u64,
+}
+
+#[derive(Debug, Default)]
+struct VcpuState {
+    frames: Vec<u64>,
+    /// The address of a return which has just executed, until its target block runs
+    returning: Option<u64>,
+}
+
+#[derive(Debug)]
+/// Shadow stacks of each vCPU's calls, checked on every return
+pub struct ShadowStack {
+    /// Whether returns unwinding to a deeper frame are reported
+    strict: bool,
+    /// Return targets which are never reported, such as the kernel's signal restorer
+    allowed: HashSet<u64>,
+    vcpus: HashMap<VCPUIndex, VcpuState>,
+}
+
+impl ShadowStack {
+    pub fn new(strict: bool, allowed: Vec<u64>) -> Self {
+        Self {
+            strict,
+            allowed: allowed.into_iter().collect(),
+            vcpus: HashMap::new(),
+        }
+    }
+
+    /// Record that a call instruction whose return address is `return_addr` executed
+    pub fn on_call(&mut self, vcpu_index: VCPUIndex, return_addr: u64) {
+        let state = self.vcpus.entry(vcpu_index).or_default();
+
+        if state.frames.len() >= MAX_FRAMES {
+            state.frames.remove(0);
+        }
+
+        state.frames.push(return_addr);
+    }
+
+    /// Record that the return instruction at `pc` executed
+    pub fn on_return(&mut self, vcpu_index: VCPUIndex, pc: u64) {
+        self.vcpus.entry(vcpu_index).or_default().returning = Some(pc);
+    }
+
+    /// Record that the block at `vaddr` executed, returning a violation if it is the target
+    /// of a return which should have landed elsewhere
+    pub fn on_block(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        vaddr: u64,
+        icount: u64,
+    ) -> Option<IntegrityEvent> {
+        let state = self.vcpus.entry(vcpu_index).or_default();
+        let pc = state.returning.take()?;
+        // Frames entered before tracing began have no record to check against
+        let expected = *state.frames.last()?;
+
+        if vaddr == expected {
+            state.frames.pop();
+            return None;
+        }
+
+        let call_stack = state.frames.iter().rev().copied().collect::<Vec<_>>();
+        let unwound = state.frames.iter().rposition(|f| *f == vaddr).map(|i| {
+            let unwound = state.frames.len() - i - 1;
+
+            state.frames.truncate(i);
+            unwound
+        });
+
+        if self.allowed.contains(&vaddr) || (unwound.is_some() && !self.strict) {
+            return None;
+        }
+
+        Some(
+            IntegrityEvent::builder()
+                .vcpu_index(vcpu_index)
+                .pc(pc)
+                .expected(expected)
+                .actual(vaddr)
+                .unwound(unwound)
+                .call_stack(call_stack)
+                .icount(icount)
+                .build(),
+        )
+    }
+}
@@ -0,0 +1,181 @@
+//! Identification of guest code by byte signatures, matched as blocks are translated
+//!
+//! Signatures are loaded from a JSON file containing a list of [`Signature`]s, each a name
+//! and a pattern of hex bytes in which `?` matches any nibble, as in FLIRT pattern files:
+//!
+//! ```json
+//! [{ "name": "memcpy", "pattern": "2d e9 f0 4? 0? 46 ?? ?? 00 2a" }]
+//! ```
+//!
+//! Patterns are matched at the start of every instruction of each translated block, so a
+//! function is identified as soon as it first runs, which finds functions in stripped
+//! firmware without a disassembler. A pattern which runs past the end of its block is
+//! matched against the guest memory following it. Each match is reported once, as an
+//! identification event, when its block first executes.
+
+use anyhow::{anyhow, Result};
+use qemu_plugin::{qemu_plugin_read_memory_vaddr, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs::read_to_string, path::Path};
+use typed_builder::TypedBuilder;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "SignatureSpec")]
+/// A named byte pattern
+pub struct Signature {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    /// The bits of each byte which must match
+    pub mask: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct SignatureSpec {
+    name: String,
+    pattern: String,
+}
+
+impl TryFrom<SignatureSpec> for Signature {
+    type Error = anyhow::Error;
+
+    fn try_from(spec: SignatureSpec) -> Result<Self> {
+        Self::new(spec.name, &spec.pattern)
+    }
+}
+
+impl Signature {
+    /// Create a signature from a pattern of hex bytes, optionally separated by whitespace,
+    /// in which `?` matches any nibble
+    pub fn new(name: String, pattern: &str) -> Result<Self> {
+        let nibbles = pattern
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| match c {
+                '?' => Ok((0, 0)),
+                c => c
+                    .to_digit(16)
+                    .map(|d| (d as u8, 0xf))
+                    .ok_or_else(|| anyhow!("Invalid character {c:?} in signature {name}")),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if nibbles.is_empty() || nibbles.len() % 2 != 0 {
+            return Err(anyhow!("Signature {name} is not a whole number of bytes"));
+        }
+
+        let (bytes, mask) = nibbles
+            .chunks(2)
+            .map(|pair| (pair[0].0 << 4 | pair[1].0, pair[0].1 << 4 | pair[1].1))
+            .unzip();
+
+        Ok(Self::with_mask(name, bytes, mask))
+    }
+
+    /// Create a signature from bytes and the mask of their bits which must match
+    pub fn with_mask(name: String, bytes: Vec<u8>, mask: Vec<u8>) -> Self {
+        Self { name, bytes, mask }
+    }
+
+    /// Whether `data` starts with the bytes the signature matches. `data` may be shorter
+    /// than the signature, in which case only its bytes are compared.
+    fn matches_prefix(&self, data: &[u8]) -> bool {
+        self.bytes
+            .iter()
+            .zip(&self.mask)
+            .zip(data)
+            .all(|((byte, mask), data)| data & mask == *byte & mask)
+    }
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// Code identified by a signature
+pub struct SignatureEvent {
+    pub vcpu_index: VCPUIndex,
+    pub name: String,
+    /// The address the signature matched at
+    pub pc: u64,
+    pub icount: u64,
+}
+
+#[derive(Debug)]
+/// Matches signatures against translated code
+pub struct SignatureScanner {
+    signatures: Vec<Signature>,
+    /// The matches already reported, as `(pc, signature)`, which blocks translated again
+    /// would otherwise report again
+    reported: HashSet<(u64, usize)>,
+}
+
+impl SignatureScanner {
+    pub fn new(signatures: Vec<Signature>) -> Self {
+        Self {
+            signatures,
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Load signatures from a JSON file
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::new(serde_json::from_str(&read_to_string(path)?)?))
+    }
+
+    /// Returns the signatures matching at each of `starts`, the addresses of a block's
+    /// instructions, where `code` is the block's bytes starting at `base`, as
+    /// `(pc, signature)` pairs. Matches already reported are skipped.
+    pub fn scan(&self, base: u64, code: &[u8], starts: &[u64]) -> Vec<(u64, usize)> {
+        let mut matches = Vec::new();
+
+        for &pc in starts {
+            let data = &code[(pc - base) as usize..];
+
+            for (i, signature) in self.signatures.iter().enumerate() {
+                if self.reported.contains(&(pc, i)) || !signature.matches_prefix(data) {
+                    continue;
+                }
+
+                let rest = signature.bytes.len().saturating_sub(data.len());
+
+                // The pattern runs past the block, so the rest is read from guest memory
+                if rest > 0 {
+                    let after = pc + data.len() as u64;
+                    let tail = Signature::with_mask(
+                        String::new(),
+                        signature.bytes[data.len()..].to_vec(),
+                        signature.mask[data.len()..].to_vec(),
+                    );
+
+                    match qemu_plugin_read_memory_vaddr(after, rest) {
+                        Ok(bytes) if bytes.len() == rest && tail.matches_prefix(&bytes) => {}
+                        _ => continue,
+                    }
+                }
+
+                matches.push((pc, i));
+            }
+        }
+
+        matches
+    }
+
+    /// Record that a block containing the match of signature `index` at `pc` executed,
+    /// returning an event the first time
+    pub fn on_match(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        pc: u64,
+        index: usize,
+        icount: u64,
+    ) -> Option<SignatureEvent> {
+        self.reported.insert((pc, index)).then(|| {
+            SignatureEvent::builder()
+                .vcpu_index(vcpu_index)
+                .name(self.signatures[index].name.clone())
+                .pc(pc)
+                .icount(icount)
+                .build()
+        })
+    }
+}
@@ -0,0 +1,401 @@
+//! Counters describing a running capture, and facilities for dumping them on demand
+//!
+//! A snapshot can be requested by sending `SIGUSR1` to the QEMU process on Unix (when a stats
+//! path is configured) or by writing `stats` to the control channel, which is a Unix socket or,
+//! on any platform, a TCP listener. Note that in user mode QEMU forwards most host signals to
+//! the guest, so the control channel is the reliable option there. The control socket also accepts `bookmark <name> [note]`, which logs a bookmark,
+//! and `coverage`, which replies with the most recent coverage events, for monitors.
+
+use crate::{
+    coverage::CoverageEvent,
+    encoding::VCPU_SLOTS,
+    log,
+    utilization::{Utilization, VcpuTime},
+};
+use anyhow::{anyhow, Result};
+use qemu_plugin::{path::create_sink, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::spawn,
+    time::{SystemTime, UNIX_EPOCH},
+};
+#[cfg(unix)]
+use std::{fs::remove_file, os::unix::net::UnixListener};
+
+/// The number of recent coverage events kept for the `coverage` command
+const RECENT_COVERAGE: usize = 64;
+
+#[derive(Debug, Default)]
+#[repr(align(64))]
+/// A counter on its own cache line, so vCPUs updating neighboring slots do not share one
+struct Slot(AtomicU64);
+
+#[derive(Debug)]
+/// Per-vCPU counters, updated without locking by every vCPU with an index below
+/// [`VCPU_SLOTS`]
+struct VcpuCounters {
+    slots: Box<[Slot]>,
+    /// Counters of vCPUs whose index is past the last slot
+    overflow: Mutex<BTreeMap<VCPUIndex, u64>>,
+}
+
+impl Default for VcpuCounters {
+    fn default() -> Self {
+        Self {
+            slots: (0..VCPU_SLOTS).map(|_| Slot::default()).collect(),
+            overflow: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl VcpuCounters {
+    fn add(&self, vcpu_index: VCPUIndex, count: u64) -> Result<()> {
+        match self.slots.get(vcpu_index as usize) {
+            Some(Slot(counter)) => {
+                counter.fetch_add(count, Ordering::Relaxed);
+            }
+            None => {
+                *self
+                    .overflow
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock vcpu instructions: {e}"))?
+                    .entry(vcpu_index)
+                    .or_default() += count;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The counts of every vCPU which has counted anything
+    fn counts(&self) -> Result<BTreeMap<VCPUIndex, u64>> {
+        let mut counts = self
+            .slots
+            .iter()
+            .enumerate()
+            .map(|(vcpu_index, Slot(counter))| {
+                (vcpu_index as VCPUIndex, counter.load(Ordering::Relaxed))
+            })
+            .filter(|(_, count)| *count != 0)
+            .collect::<BTreeMap<_, _>>();
+
+        counts.extend(
+            self.overflow
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock vcpu instructions: {e}"))?
+                .iter(),
+        );
+
+        Ok(counts)
+    }
+}
+
+/// Reads the per-vCPU values of a scoreboard
+pub type ScoreboardFn = Box<dyn Fn() -> BTreeMap<VCPUIndex, u64> + Send + Sync>;
+
+#[derive(Default)]
+/// Live counters, updated from vCPU threads
+pub struct Stats {
+    /// Number of translation blocks translated
+    pub translated_blocks: AtomicU64,
+    /// Number of translation blocks executed
+    pub executed_blocks: AtomicU64,
+    /// Number of instructions executed across all vCPUs, counted at the granularity of
+    /// translation blocks. This is the instruction-count timestamp attached to events.
+    pub instructions: AtomicU64,
+    /// Number of memory accesses observed
+    pub memory_accesses: AtomicU64,
+    /// Number of syscalls observed
+    pub syscalls: AtomicU64,
+    /// Number of events written to the trace socket
+    pub events_sent: AtomicU64,
+    /// Number of events dropped by the throttle
+    pub events_dropped: AtomicU64,
+    /// Number of syscall events waiting for their return to be observed before being sent
+    pub pending_syscalls: AtomicU64,
+    /// Number of events being written or waiting to be written to the sink, which grows
+    /// while the sink cannot keep up
+    pub sink_waiting: AtomicU64,
+    /// Number of blocks and edges reached which the coverage baseline did not cover
+    pub new_coverage: AtomicU64,
+    /// The most recent coverage events
+    recent_coverage: Mutex<VecDeque<CoverageEvent>>,
+    /// Number of instructions executed, per vCPU
+    vcpu_instructions: VcpuCounters,
+    /// The scoreboards included in snapshots, by name
+    scoreboards: Mutex<BTreeMap<&'static str, ScoreboardFn>>,
+    /// Time each vCPU has spent busy and idle
+    pub utilization: Utilization,
+}
+
+impl std::fmt::Debug for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stats")
+            .field("translated_blocks", &self.translated_blocks)
+            .field("executed_blocks", &self.executed_blocks)
+            .field("instructions", &self.instructions)
+            .field("events_sent", &self.events_sent)
+            .field("events_dropped", &self.events_dropped)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A point-in-time copy of `Stats`
+pub struct StatsSnapshot {
+    /// Milliseconds since the Unix epoch at which the snapshot was taken
+    pub timestamp_ms: u128,
+    pub translated_blocks: u64,
+    pub executed_blocks: u64,
+    pub instructions: u64,
+    pub memory_accesses: u64,
+    pub syscalls: u64,
+    pub events_sent: u64,
+    pub events_dropped: u64,
+    pub pending_syscalls: u64,
+    #[serde(default)]
+    pub sink_waiting: u64,
+    #[serde(default)]
+    pub new_coverage: u64,
+    pub vcpu_instructions: BTreeMap<VCPUIndex, u64>,
+    #[serde(default)]
+    pub vcpu_time: BTreeMap<VCPUIndex, VcpuTime>,
+    /// The per-vCPU values of each registered scoreboard, by name
+    #[serde(default)]
+    pub scoreboards: BTreeMap<String, BTreeMap<VCPUIndex, u64>>,
+}
+
+impl Stats {
+    /// Increment a counter by one
+    pub fn bump(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the current instruction-count timestamp
+    pub fn icount(&self) -> u64 {
+        self.instructions.load(Ordering::Relaxed)
+    }
+
+    /// Add the instructions of an executed block to the total and per-vCPU instruction counts
+    pub fn add_instructions(&self, vcpu_index: VCPUIndex, count: u64) -> Result<()> {
+        self.instructions.fetch_add(count, Ordering::Relaxed);
+        self.vcpu_instructions.add(vcpu_index, count)
+    }
+
+    /// Include the values `read` returns in snapshots, under `name`
+    pub fn register_scoreboard(&self, name: &'static str, read: ScoreboardFn) -> Result<()> {
+        self.scoreboards
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock scoreboards: {e}"))?
+            .insert(name, read);
+        Ok(())
+    }
+
+    /// Count a coverage event and keep it among the most recent
+    pub fn on_new_coverage(&self, event: &CoverageEvent) -> Result<()> {
+        Self::bump(&self.new_coverage);
+
+        let mut recent = self
+            .recent_coverage
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock recent coverage: {e}"))?;
+
+        if recent.len() == RECENT_COVERAGE {
+            recent.pop_front();
+        }
+
+        recent.push_back(event.clone());
+        Ok(())
+    }
+
+    /// The most recent coverage events, oldest first
+    pub fn recent_coverage(&self) -> Result<Vec<CoverageEvent>> {
+        Ok(self
+            .recent_coverage
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock recent coverage: {e}"))?
+            .iter()
+            .cloned()
+            .collect())
+    }
+
+    /// Take a snapshot of the current counter values
+    pub fn snapshot(&self) -> Result<StatsSnapshot> {
+        Ok(StatsSnapshot {
+            timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis(),
+            translated_blocks: self.translated_blocks.load(Ordering::Relaxed),
+            executed_blocks: self.executed_blocks.load(Ordering::Relaxed),
+            instructions: self.instructions.load(Ordering::Relaxed),
+            memory_accesses: self.memory_accesses.load(Ordering::Relaxed),
+            syscalls: self.syscalls.load(Ordering::Relaxed),
+            events_sent: self.events_sent.load(Ordering::Relaxed),
+            events_dropped: self.events_dropped.load(Ordering::Relaxed),
+            pending_syscalls: self.pending_syscalls.load(Ordering::Relaxed),
+            sink_waiting: self.sink_waiting.load(Ordering::Relaxed),
+            new_coverage: self.new_coverage.load(Ordering::Relaxed),
+            vcpu_instructions: self.vcpu_instructions.counts()?,
+            vcpu_time: self.utilization.times()?,
+            scoreboards: self
+                .scoreboards
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock scoreboards: {e}"))?
+                .iter()
+                .map(|(name, read)| (name.to_string(), read()))
+                .collect(),
+        })
+    }
+
+    /// Write a JSON snapshot of the current counter values to `path`, replacing its contents
+    pub fn dump<P>(&self, path: P) -> Result<StatsSnapshot>
+    where
+        P: AsRef<Path>,
+    {
+        let snapshot = self.snapshot()?;
+        let mut file = create_sink(path)?;
+        serde_json::to_writer_pretty(&mut file, &snapshot)?;
+        file.write_all(b"\n")?;
+        Ok(snapshot)
+    }
+}
+
+/// Dump a snapshot to `path` every time the process receives `SIGUSR1`
+#[cfg(unix)]
+pub fn dump_on_signal(stats: Arc<Stats>, path: PathBuf) -> Result<()> {
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGUSR1])?;
+
+    spawn(move || {
+        for _ in signals.forever() {
+            if let Err(e) = stats.dump(&path) {
+                log(format!("Failed to dump stats to {}: {e}", path.display()));
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Logs a bookmark with a name and an optional note
+pub type BookmarkFn = Box<dyn Fn(String, Option<String>) -> Result<()> + Send>;
+
+fn handle_control<R, W>(
+    stats: &Stats,
+    reader: R,
+    mut writer: W,
+    path: Option<&Path>,
+    bookmark: &BookmarkFn,
+) -> Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    for line in BufReader::new(reader).lines() {
+        match line?.trim() {
+            "stats" => {
+                let snapshot = if let Some(path) = path {
+                    stats.dump(path)?
+                } else {
+                    stats.snapshot()?
+                };
+                serde_json::to_writer(&mut writer, &snapshot)?;
+            }
+            "coverage" => serde_json::to_writer(&mut writer, &stats.recent_coverage()?)?,
+            "" => continue,
+            command if command.starts_with("bookmark ") => {
+                let mut parts = command["bookmark ".len()..].trim().splitn(2, ' ');
+                let name = parts.next().unwrap_or_default().to_string();
+                let note = parts.next().map(|note| note.trim().to_string());
+
+                match bookmark(name.clone(), note) {
+                    Ok(()) => serde_json::to_writer(
+                        &mut writer,
+                        &serde_json::json!({ "bookmark": name }),
+                    )?,
+                    Err(e) => serde_json::to_writer(
+                        &mut writer,
+                        &serde_json::json!({ "error": e.to_string() }),
+                    )?,
+                }
+            }
+            command => {
+                serde_json::to_writer(
+                    &mut writer,
+                    &serde_json::json!({ "error": format!("Unknown command {command}") }),
+                )?;
+            }
+        }
+        writer.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+/// Where the control channel listens
+pub enum ControlAddress {
+    /// A Unix socket at a path, replacing any existing file
+    Unix(PathBuf),
+    /// A TCP address, such as `127.0.0.1:7000`, available on every platform
+    Tcp(String),
+}
+
+/// Listen on `address` for line-based commands. The `stats` command replies with a JSON
+/// snapshot, which is also written to `path` if one is given, and the `bookmark` command calls
+/// `bookmark`.
+pub fn serve_control(
+    stats: Arc<Stats>,
+    address: ControlAddress,
+    path: Option<PathBuf>,
+    bookmark: BookmarkFn,
+) -> Result<()> {
+    match address {
+        #[cfg(unix)]
+        ControlAddress::Unix(control_path) => {
+            if control_path.exists() {
+                remove_file(&control_path)?;
+            }
+
+            let listener = UnixListener::bind(&control_path)?;
+
+            spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    if let Err(e) = stream.try_clone().map_err(Into::into).and_then(|writer| {
+                        handle_control(&stats, stream, writer, path.as_deref(), &bookmark)
+                    }) {
+                        log(format!("Control connection failed: {e}"));
+                    }
+                }
+            });
+        }
+        #[cfg(not(unix))]
+        ControlAddress::Unix(control_path) => {
+            return Err(anyhow!(
+                "Unix control sockets such as {} are not supported on this platform, use a TCP \
+                 control address",
+                control_path.display()
+            ));
+        }
+        ControlAddress::Tcp(address) => {
+            let listener = TcpListener::bind(&address)?;
+
+            spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    if let Err(e) = stream.try_clone().map_err(Into::into).and_then(|writer| {
+                        handle_control(&stats, stream, writer, path.as_deref(), &bookmark)
+                    }) {
+                        log(format!("Control connection failed: {e}"));
+                    }
+                }
+            });
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! Extraction of the strings the guest's code references, as it executes
+//!
+//! As blocks are translated, the values each instruction uses, found by
+//! [`Arch::operand_values`], are checked against the program's read-only data sections, or
+//! ranges given instead, such as a firmware's flash. A value in one is a string reference
+//! unless it is a literal pool entry in code, in which case the pointer stored there is
+//! checked instead, as ARM compilers load string addresses. The first time an instruction
+//! referencing each address executes, the string there is read and, if it is printable,
+//! logged, producing a report of the strings the program actually uses.
+
+use crate::{
+    arch::Arch,
+    guest::{read_cstring, read_pointer},
+    modules::Section,
+};
+use qemu_plugin::VCPUIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use typed_builder::TypedBuilder;
+
+/// The most bytes of a string read
+const MAX_LEN: usize = 256;
+
+/// The fewest characters a string needs to be logged, which leaves out most byte constants
+const MIN_LEN: usize = 4;
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// A string referenced by an executed instruction
+pub struct StringEvent {
+    pub vcpu_index: VCPUIndex,
+    /// The instruction referencing the string
+    pub pc: u64,
+    pub address: u64,
+    /// The section or range the string is in
+    pub section: String,
+    pub string: String,
+    pub icount: u64,
+}
+
+#[derive(Debug)]
+/// Finds the strings referenced by translated code
+pub struct StringTracker {
+    arch: Arch,
+    /// The sections strings are taken from
+    regions: Vec<Section>,
+    /// Executable sections, whose literal pools may hold pointers to strings
+    code: Vec<Section>,
+    /// The addresses already checked for a string
+    seen: HashSet<u64>,
+}
+
+impl StringTracker {
+    /// Create a tracker taking strings from `regions`, and dereferencing literal pool entries
+    /// in `code`
+    pub fn new(arch: Arch, regions: Vec<Section>, code: Vec<Section>) -> Self {
+        Self {
+            arch,
+            regions,
+            code,
+            seen: HashSet::new(),
+        }
+    }
+
+    fn region(&self, vaddr: u64) -> Option<&Section> {
+        self.regions.iter().find(|r| r.contains(vaddr))
+    }
+
+    /// Returns the addresses of strings which an instruction references and which have not
+    /// been checked yet. `built` is passed to [`Arch::operand_values`].
+    pub fn references(
+        &self,
+        pc: u64,
+        len: usize,
+        disas: &str,
+        built: &mut HashMap<String, u64>,
+    ) -> Vec<u64> {
+        let mut references = Vec::new();
+
+        for value in self.arch.operand_values(pc, len, disas, built) {
+            let value = if self.region(value).is_some() {
+                value
+            } else if self.code.iter().any(|c| c.contains(value)) {
+                match read_pointer(self.arch, value, self.arch.pointer_size()) {
+                    Ok(pointer) if self.region(pointer).is_some() => pointer,
+                    _ => continue,
+                }
+            } else {
+                continue;
+            };
+
+            if !self.seen.contains(&value) && !references.contains(&value) {
+                references.push(value);
+            }
+        }
+
+        references
+    }
+
+    /// Record that an instruction referencing `address` executed, returning an event with
+    /// the string there the first time, if it is printable
+    pub fn on_reference(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        pc: u64,
+        address: u64,
+        icount: u64,
+    ) -> Option<StringEvent> {
+        if !self.seen.insert(address) {
+            return None;
+        }
+
+        let section = self.region(address)?.name.clone();
+        let string = String::from_utf8(read_cstring(address, MAX_LEN).ok()?).ok()?;
+
+        (string.chars().count() >= MIN_LEN
+            && string
+                .chars()
+                .all(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r')))
+        .then(|| {
+            StringEvent::builder()
+                .vcpu_index(vcpu_index)
+                .pc(pc)
+                .address(address)
+                .section(section)
+                .string(string)
+                .icount(icount)
+                .build()
+        })
+    }
+}
@@ -0,0 +1,83 @@
+//! Lock-free state shared by callbacks running on vCPU threads
+//!
+//! Most state is shared behind a `Mutex`, but a few cells are checked on every execution of
+//! an instruction and are kept lock-free. A mistake in their orderings does not crash, it
+//! silently records an event twice or not at all, so they are kept here, small enough to be
+//! checked exhaustively. Building with `--cfg tracer_loom` swaps the atomics for loom's, and
+//! the `sync` integration test, which only builds under loom or miri, explores the
+//! interleavings of each. The cfg is not the usual `loom`, which would also switch tokio's
+//! internals to loom.
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg tracer_loom" cargo test --release -p tracer --test sync
+//! cargo +nightly miri test -p tracer --test sync
+//! ```
+
+#[cfg(tracer_loom)]
+use loom::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(not(tracer_loom))]
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+#[derive(Debug, Default)]
+/// A flag which is tripped once, by whichever thread gets to it first
+pub struct Latch {
+    tripped: AtomicBool,
+}
+
+impl Latch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trip the latch, returning whether this call tripped it. Exactly one call returns
+    /// true, and it happens before every call which observes the latch tripped.
+    pub fn trip(&self) -> bool {
+        !self.tripped.swap(true, Ordering::AcqRel)
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.tripped.load(Ordering::Acquire)
+    }
+}
+
+#[derive(Debug)]
+/// A number of admissions shared by every thread, either taken one at a time or counted by
+/// ticks and checked against the limit
+pub struct Quota {
+    used: AtomicU64,
+    limit: u64,
+}
+
+impl Quota {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            used: AtomicU64::new(0),
+            limit,
+        }
+    }
+
+    /// Take one admission, returning whether one was left. Exactly `limit` calls succeed,
+    /// however many threads race.
+    pub fn take(&self) -> bool {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                (used < self.limit).then_some(used + 1)
+            })
+            .is_ok()
+    }
+
+    /// Count one use without checking the limit
+    pub fn tick(&self) {
+        self.used.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether the uses counted so far are within the limit
+    pub fn is_within(&self) -> bool {
+        self.used.load(Ordering::Relaxed) <= self.limit
+    }
+
+    /// Whether no admissions are left
+    pub fn is_exhausted(&self) -> bool {
+        self.used.load(Ordering::Relaxed) >= self.limit
+    }
+}
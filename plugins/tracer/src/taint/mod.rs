@@ -0,0 +1,283 @@
+//! Forward and backward taint queries over recorded traces
+//!
+//! [`TaintQuery`] follows data through the registers and memory of a recorded trace, such as
+//! one read with [`crate::tracefile::TraceFile::read_events`]. Queries name an instruction
+//! event by its index in the trace and a register or memory range, and ask either where the
+//! value it held when the instruction executed came from, or where it went.
+//!
+//! The registers each instruction uses and defines come from [`MicroInterp::def_use`], and
+//! the memory it accesses from the memory events following it, so the trace must be recorded
+//! with `log_insns=true`, and with `log_mem=true` to follow data through memory. Data flows
+//! from everything an instruction reads, except registers only used for addresses, to
+//! everything it writes. The events should come from a single vCPU, such as one shard of a
+//! sharded trace.
+
+use crate::{
+    arch::Arch,
+    emu::{DefUse, Location, MicroInterp},
+    Event,
+};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+#[derive(Clone, Debug)]
+/// An instruction in the trace and the data it accessed
+struct Step {
+    event: usize,
+    /// `None` if the instruction is not supported by the interpreter
+    def_use: Option<DefUse>,
+    loads: Vec<(u64, usize)>,
+    stores: Vec<(u64, usize)>,
+}
+
+impl Step {
+    fn bytes(accesses: &[(u64, usize)]) -> impl Iterator<Item = u64> + '_ {
+        accesses
+            .iter()
+            .flat_map(|(address, size)| (0..*size as u64).map(|i| address.wrapping_add(i)))
+    }
+
+    /// Whether the instruction accesses memory the trace does not record the addresses of
+    fn is_missing_memory(&self) -> bool {
+        self.def_use.as_ref().is_some_and(|def_use| {
+            (def_use.loads && self.loads.is_empty()) || (def_use.stores && self.stores.is_empty())
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// The locations holding tainted data
+struct Tainted {
+    registers: BTreeSet<String>,
+    memory: BTreeSet<u64>,
+}
+
+impl Tainted {
+    fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.memory.is_empty()
+    }
+
+    /// The tainted locations, with adjacent bytes of memory merged into ranges
+    fn locations(&self) -> Vec<Location> {
+        let mut locations = self
+            .registers
+            .iter()
+            .map(|name| Location::Register(name.clone()))
+            .collect::<Vec<_>>();
+        let mut range: Option<(u64, usize)> = None;
+
+        for address in &self.memory {
+            match range.as_mut() {
+                Some((start, size)) if start.wrapping_add(*size as u64) == *address => *size += 1,
+                _ => {
+                    if let Some((address, size)) = range.replace((*address, 1)) {
+                        locations.push(Location::Memory { address, size });
+                    }
+                }
+            }
+        }
+
+        if let Some((address, size)) = range {
+            locations.push(Location::Memory { address, size });
+        }
+
+        locations
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// The result of a taint query
+pub struct Taint {
+    /// The instruction events the data flowed through, in trace order
+    pub events: Vec<usize>,
+    /// For a backward query, the locations the data came from which were not written in the
+    /// trace before it was read. For a forward query, the locations still holding data
+    /// derived from it at the end of the trace.
+    pub locations: Vec<Location>,
+    /// Instruction events the query could not see through, because they are not supported
+    /// or their memory accesses were not recorded, and which are assumed not to move data
+    pub unknown: Vec<usize>,
+}
+
+#[derive(Clone, Debug)]
+/// Answers taint queries about a trace
+pub struct TaintQuery {
+    steps: Vec<Step>,
+}
+
+impl TaintQuery {
+    /// Prepare to query the trace `events`, recorded on `arch`
+    pub fn new(arch: Arch, events: &[Event]) -> Result<Self> {
+        let interp = MicroInterp::new(arch, Default::default())?;
+        let mut steps: Vec<Step> = Vec::new();
+
+        for (i, event) in events.iter().enumerate() {
+            match event {
+                Event::Instruction { event, .. } => steps.push(Step {
+                    event: i,
+                    def_use: interp.def_use(&event.data).ok(),
+                    loads: Vec::new(),
+                    stores: Vec::new(),
+                }),
+                Event::Memory(memory) => {
+                    if let Some(step) = steps.last_mut() {
+                        let access = (memory.vaddr, memory.size_bytes);
+
+                        if memory.is_store {
+                            step.stores.push(access);
+                        } else {
+                            step.loads.push(access);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self { steps })
+    }
+
+    /// The position of instruction event `event` among the instructions
+    fn position(&self, event: usize) -> Result<usize> {
+        self.steps
+            .binary_search_by_key(&event, |step| step.event)
+            .map_err(|_| anyhow!("Event {event} is not an instruction event"))
+    }
+
+    /// Find where the value `register` held when instruction event `event` executed came
+    /// from. `register` is the name of a full 64-bit register, such as `rax`.
+    pub fn taint_backward(&self, event: usize, register: &str) -> Result<Taint> {
+        self.backward(
+            event,
+            Tainted {
+                registers: BTreeSet::from([register.to_string()]),
+                memory: BTreeSet::new(),
+            },
+        )
+    }
+
+    /// Find where the `size` bytes of memory at `address` when instruction event `event`
+    /// executed came from
+    pub fn taint_backward_memory(&self, event: usize, address: u64, size: usize) -> Result<Taint> {
+        self.backward(
+            event,
+            Tainted {
+                registers: BTreeSet::new(),
+                memory: (0..size as u64).map(|i| address.wrapping_add(i)).collect(),
+            },
+        )
+    }
+
+    /// Find where the value `register` held when instruction event `event` executed went
+    pub fn taint_forward(&self, event: usize, register: &str) -> Result<Taint> {
+        self.forward(
+            event,
+            Tainted {
+                registers: BTreeSet::from([register.to_string()]),
+                memory: BTreeSet::new(),
+            },
+        )
+    }
+
+    /// Find where the `size` bytes of memory at `address` when instruction event `event`
+    /// executed went
+    pub fn taint_forward_memory(&self, event: usize, address: u64, size: usize) -> Result<Taint> {
+        self.forward(
+            event,
+            Tainted {
+                registers: BTreeSet::new(),
+                memory: (0..size as u64).map(|i| address.wrapping_add(i)).collect(),
+            },
+        )
+    }
+
+    fn backward(&self, event: usize, mut tainted: Tainted) -> Result<Taint> {
+        let mut taint = Taint::default();
+
+        for step in self.steps[..self.position(event)?].iter().rev() {
+            if tainted.is_empty() {
+                break;
+            }
+
+            let Some(def_use) = step.def_use.as_ref() else {
+                taint.unknown.push(step.event);
+                continue;
+            };
+
+            if step.is_missing_memory() {
+                taint.unknown.push(step.event);
+            }
+
+            let defines = def_use
+                .defs
+                .difference(&def_use.updates)
+                .any(|r| tainted.registers.contains(r))
+                || Step::bytes(&step.stores).any(|b| tainted.memory.contains(&b));
+
+            if !defines {
+                continue;
+            }
+
+            for register in def_use.defs.difference(&def_use.updates) {
+                tainted.registers.remove(register);
+            }
+
+            for byte in Step::bytes(&step.stores) {
+                tainted.memory.remove(&byte);
+            }
+
+            tainted.registers.extend(def_use.uses.iter().cloned());
+            tainted.memory.extend(Step::bytes(&step.loads));
+            taint.events.push(step.event);
+        }
+
+        taint.events.reverse();
+        taint.unknown.reverse();
+        taint.locations = tainted.locations();
+
+        Ok(taint)
+    }
+
+    fn forward(&self, event: usize, mut tainted: Tainted) -> Result<Taint> {
+        let mut taint = Taint::default();
+
+        for step in &self.steps[self.position(event)?..] {
+            if tainted.is_empty() {
+                break;
+            }
+
+            let Some(def_use) = step.def_use.as_ref() else {
+                taint.unknown.push(step.event);
+                continue;
+            };
+
+            if step.is_missing_memory() {
+                taint.unknown.push(step.event);
+            }
+
+            let uses = def_use.uses.iter().any(|r| tainted.registers.contains(r))
+                || Step::bytes(&step.loads).any(|b| tainted.memory.contains(&b));
+
+            if uses {
+                tainted
+                    .registers
+                    .extend(def_use.defs.difference(&def_use.updates).cloned());
+                tainted.memory.extend(Step::bytes(&step.stores));
+                taint.events.push(step.event);
+            } else {
+                for register in def_use.defs.difference(&def_use.updates) {
+                    tainted.registers.remove(register);
+                }
+
+                for byte in Step::bytes(&step.stores) {
+                    tainted.memory.remove(&byte);
+                }
+            }
+        }
+
+        taint.locations = tainted.locations();
+
+        Ok(taint)
+    }
+}
@@ -0,0 +1,424 @@
+//! Assertions about a guest's behavior, checked while it runs, so a run of a guest test under
+//! the tracer passes or fails on what the guest did rather than only on what it printed
+//!
+//! Expectations are declared in a TOML or JSON file:
+//!
+//! ```toml
+//! [[expect]]
+//! name = "parse is called with 3"
+//! called = "parse"
+//! args = { arg0 = 3 }
+//!
+//! [[expect]]
+//! name = "cleanup runs once"
+//! called = "0x401a20"
+//! at_least = 1
+//! at_most = 1
+//!
+//! [[expect]]
+//! name = "the config stays read-only"
+//! no_writes = { start = 0x601000, end = 0x602000 }
+//! ```
+//!
+//! A `called` expectation names a function by symbol or by the address it runs at, and
+//! counts the calls whose arguments equal those given in `args`, read as by the heap
+//! tracker. It passes when the count is at least `at_least`, one by default, and at most
+//! `at_most`, so `at_most = 0` alone asserts a function is never called. A `no_writes` expectation
+//! passes when no store overlaps its range, and watches every store while any is declared.
+//!
+//! At exit, the expectations which failed are printed, and each expectation's result is
+//! written to the expectation report as JSON.
+
+use crate::{arch::Arch, heap::read_argument, log, memmap::parse_addr};
+use anyhow::{anyhow, Result};
+use qemu_plugin::{path::create_sink, RegisterDescriptor, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::read_to_string,
+    path::Path,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// An address range, from `start` up to but not including `end`
+pub struct AddressRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// An expectation as declared, before it is checked for consistency
+pub struct Expectation {
+    pub name: String,
+    /// A function, by symbol or address, which is expected to be called
+    #[serde(default)]
+    pub called: Option<String>,
+    /// The values the arguments of matching calls have, as `argN = value`
+    #[serde(default)]
+    pub args: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub at_least: Option<u64>,
+    #[serde(default)]
+    pub at_most: Option<u64>,
+    /// A range no store is expected to overlap
+    #[serde(default)]
+    pub no_writes: Option<AddressRange>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// The expectations of an expectations file
+pub struct ExpectationFile {
+    #[serde(default)]
+    pub expect: Vec<Expectation>,
+}
+
+impl ExpectationFile {
+    /// Load an expectations file, which is JSON if its extension is `.json` and TOML
+    /// otherwise, and check each expectation is consistent
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let data = read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read expectations {}: {e}", path.display()))?;
+        let file: Self = if path.extension().is_some_and(|e| e == "json") {
+            serde_json::from_str(&data)?
+        } else {
+            toml::from_str(&data)?
+        };
+
+        for expectation in &file.expect {
+            Check::try_from(expectation)?;
+        }
+
+        Ok(file)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// How a function is found
+enum Target {
+    Symbol(String),
+    Address(u64),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// What an expectation checks
+enum Check {
+    Called {
+        target: Target,
+        /// Argument indices and the values they must have
+        args: Vec<(usize, u64)>,
+        at_least: u64,
+        at_most: Option<u64>,
+    },
+    NoWrites(AddressRange),
+}
+
+impl TryFrom<&Expectation> for Check {
+    type Error = anyhow::Error;
+
+    fn try_from(expectation: &Expectation) -> Result<Self> {
+        let name = &expectation.name;
+
+        match (expectation.called.as_deref(), expectation.no_writes) {
+            (Some(function), None) => {
+                let function = function.trim();
+                let target = if function.starts_with(|c: char| c.is_ascii_digit()) {
+                    Target::Address(parse_addr(function)?)
+                } else {
+                    Target::Symbol(function.to_string())
+                };
+                let args = expectation
+                    .args
+                    .iter()
+                    .map(|(arg, value)| {
+                        let index = arg
+                            .strip_prefix("arg")
+                            .and_then(|i| i.parse().ok())
+                            .ok_or_else(|| {
+                                anyhow!(
+                                    "Invalid argument {arg} in expectation {name}, expected argN"
+                                )
+                            })?;
+
+                        Ok((index, *value))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let at_least = expectation
+                    .at_least
+                    .unwrap_or(expectation.at_most.map_or(1, |at_most| at_most.min(1)));
+
+                if expectation
+                    .at_most
+                    .is_some_and(|at_most| at_most < at_least)
+                {
+                    return Err(anyhow!(
+                        "Expectation {name} has at_most below at_least, which no run can pass"
+                    ));
+                }
+
+                Ok(Self::Called {
+                    target,
+                    args,
+                    at_least,
+                    at_most: expectation.at_most,
+                })
+            }
+            (None, Some(range)) if range.start < range.end => {
+                if !expectation.args.is_empty()
+                    || expectation.at_least.is_some()
+                    || expectation.at_most.is_some()
+                {
+                    return Err(anyhow!(
+                        "Expectation {name} gives call counts or arguments for no_writes"
+                    ));
+                }
+
+                Ok(Self::NoWrites(range))
+            }
+            (None, Some(_)) => Err(anyhow!("Expectation {name} has an empty no_writes range")),
+            _ => Err(anyhow!(
+                "Expectation {name} must give exactly one of called and no_writes"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// Where an expectation was first broken
+pub struct Violation {
+    pub vcpu_index: VCPUIndex,
+    /// The address of the instruction, or of the function called
+    pub pc: u64,
+    /// The address stored to, for `no_writes`
+    pub vaddr: Option<u64>,
+    pub icount: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// The result of an expectation
+pub struct ExpectationResult {
+    pub name: String,
+    pub passed: bool,
+    /// The matching calls, or the stores into the range
+    pub count: u64,
+    /// Why the expectation failed
+    pub reason: Option<String>,
+    /// The first call past `at_most`, or the first store into the range
+    pub violation: Option<Violation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// The results of every expectation of a run
+pub struct ExpectationReport {
+    pub passed: bool,
+    pub results: Vec<ExpectationResult>,
+}
+
+impl ExpectationReport {
+    pub fn read<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(serde_json::from_str(&read_to_string(path)?)?)
+    }
+
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        serde_json::to_writer_pretty(create_sink(path)?, self)?;
+
+        Ok(())
+    }
+
+    /// The results of the expectations which failed
+    pub fn failures(&self) -> impl Iterator<Item = &ExpectationResult> {
+        self.results.iter().filter(|result| !result.passed)
+    }
+
+    pub fn print_failures(&self) {
+        for failure in self.failures() {
+            log(format!(
+                "Expectation failed: {}: {}",
+                failure.name,
+                failure.reason.as_deref().unwrap_or_default()
+            ));
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Checks a run against a set of expectations
+pub struct Expectations {
+    arch: Arch,
+    names: Vec<String>,
+    checks: Vec<Check>,
+    counts: Vec<u64>,
+    violations: Vec<Option<Violation>>,
+    /// Indices of `called` expectations by symbol
+    symbols: HashMap<String, Vec<usize>>,
+    /// Indices of `called` expectations by address
+    addresses: HashMap<u64, Vec<usize>>,
+    /// Indices of `no_writes` expectations
+    writes: Vec<usize>,
+}
+
+impl Expectations {
+    pub fn new(arch: Arch, file: ExpectationFile) -> Result<Self> {
+        let checks = file
+            .expect
+            .iter()
+            .map(Check::try_from)
+            .collect::<Result<Vec<_>>>()?;
+        let mut symbols = HashMap::<String, Vec<usize>>::new();
+        let mut addresses = HashMap::<u64, Vec<usize>>::new();
+        let mut writes = Vec::new();
+
+        for (i, check) in checks.iter().enumerate() {
+            match check {
+                Check::Called {
+                    target: Target::Symbol(symbol),
+                    ..
+                } => symbols.entry(symbol.clone()).or_default().push(i),
+                Check::Called {
+                    target: Target::Address(address),
+                    ..
+                } => addresses.entry(*address).or_default().push(i),
+                Check::NoWrites(_) => writes.push(i),
+            }
+        }
+
+        Ok(Self {
+            arch,
+            names: file.expect.into_iter().map(|e| e.name).collect(),
+            counts: vec![0; checks.len()],
+            violations: vec![None; checks.len()],
+            checks,
+            symbols,
+            addresses,
+            writes,
+        })
+    }
+
+    /// Returns the `called` expectations of the function a block starting at `vaddr` with
+    /// `symbol` is the entry of
+    pub fn calls(&self, vaddr: u64, symbol: Option<&str>) -> Vec<usize> {
+        self.addresses
+            .get(&vaddr)
+            .into_iter()
+            .chain(symbol.and_then(|s| self.symbols.get(s)))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// Whether any expectation watches stores
+    pub fn watches_writes(&self) -> bool {
+        !self.writes.is_empty()
+    }
+
+    /// Record a call to the function at `vaddr`, counting it against the expectations in
+    /// `calls` whose arguments it matches
+    pub fn on_call(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        vaddr: u64,
+        calls: &[usize],
+        registers: &[RegisterDescriptor<'static>],
+        icount: u64,
+    ) -> Result<()> {
+        for i in calls {
+            let Check::Called { args, at_most, .. } = &self.checks[*i] else {
+                continue;
+            };
+
+            let mut matches = true;
+
+            for (index, value) in args {
+                if read_argument(self.arch, registers, *index)? != *value {
+                    matches = false;
+                    break;
+                }
+            }
+
+            if !matches {
+                continue;
+            }
+
+            self.counts[*i] += 1;
+
+            if at_most.is_some_and(|at_most| self.counts[*i] > at_most) {
+                self.violations[*i].get_or_insert(Violation {
+                    vcpu_index,
+                    pc: vaddr,
+                    vaddr: None,
+                    icount,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record a store of `size` bytes at `vaddr` by the instruction at `pc`
+    pub fn on_write(&mut self, vcpu_index: VCPUIndex, pc: u64, vaddr: u64, size: u64, icount: u64) {
+        for i in &self.writes {
+            let Check::NoWrites(range) = &self.checks[*i] else {
+                continue;
+            };
+
+            if vaddr < range.end && vaddr.saturating_add(size) > range.start {
+                self.counts[*i] += 1;
+                self.violations[*i].get_or_insert(Violation {
+                    vcpu_index,
+                    pc,
+                    vaddr: Some(vaddr),
+                    icount,
+                });
+            }
+        }
+    }
+
+    pub fn report(&self) -> ExpectationReport {
+        let results = self
+            .checks
+            .iter()
+            .enumerate()
+            .map(|(i, check)| {
+                let count = self.counts[i];
+                let reason = match check {
+                    Check::Called { at_least, .. } if count < *at_least => Some(format!(
+                        "called {count} times, expected at least {at_least}"
+                    )),
+                    Check::Called {
+                        at_most: Some(at_most),
+                        ..
+                    } if count > *at_most => {
+                        Some(format!("called {count} times, expected at most {at_most}"))
+                    }
+                    Check::NoWrites(range) if count > 0 => Some(format!(
+                        "{count} stores to {:#x}-{:#x}",
+                        range.start, range.end
+                    )),
+                    _ => None,
+                };
+
+                ExpectationResult {
+                    name: self.names[i].clone(),
+                    passed: reason.is_none(),
+                    count,
+                    reason,
+                    violation: self.violations[i],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        ExpectationReport {
+            passed: results.iter().all(|result| result.passed),
+            results,
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Rate limits on the events sent by noisy sources, such as memory accesses in a `memcpy`
+//! loop, so that traces stay bounded without losing rare events
+//!
+//! Each event class can be given a token bucket, kept separately for each vCPU. Events
+//! arriving at an empty bucket are dropped and counted, and the count is reported in a
+//! [`DroppedEvent`] sent before the next event of that class the bucket admits, so a trace
+//! records exactly where and how many events are missing.
+
+use anyhow::{anyhow, Error, Result};
+use qemu_plugin::VCPUIndex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    sync::Mutex,
+    time::Instant,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+/// The kinds of event a rate can be set for
+pub enum EventClass {
+    Instruction,
+    Memory,
+    Syscall,
+    Console,
+    Start,
+    Pcs,
+    Random,
+    Fault,
+    Dump,
+    Schedule,
+    Race,
+    Periph,
+    Hang,
+    State,
+    Api,
+    Rop,
+    Integrity,
+    Signature,
+    Yara,
+    Probe,
+    Crypto,
+    String,
+    Bookmark,
+    Hypercall,
+    Mode,
+    Utilization,
+    Coverage,
+    Clock,
+    Divergence,
+    Translation,
+    Wx,
+    Crash,
+}
+
+const CLASSES: &[(EventClass, &str)] = &[
+    (EventClass::Instruction, "instruction"),
+    (EventClass::Memory, "memory"),
+    (EventClass::Syscall, "syscall"),
+    (EventClass::Console, "console"),
+    (EventClass::Start, "start"),
+    (EventClass::Pcs, "pcs"),
+    (EventClass::Random, "random"),
+    (EventClass::Fault, "fault"),
+    (EventClass::Dump, "dump"),
+    (EventClass::Schedule, "schedule"),
+    (EventClass::Race, "race"),
+    (EventClass::Periph, "periph"),
+    (EventClass::Hang, "hang"),
+    (EventClass::State, "state"),
+    (EventClass::Api, "api"),
+    (EventClass::Rop, "rop"),
+    (EventClass::Integrity, "integrity"),
+    (EventClass::Signature, "signature"),
+    (EventClass::Yara, "yara"),
+    (EventClass::Probe, "probe"),
+    (EventClass::Crypto, "crypto"),
+    (EventClass::String, "string"),
+    (EventClass::Bookmark, "bookmark"),
+    (EventClass::Hypercall, "hypercall"),
+    (EventClass::Mode, "mode"),
+    (EventClass::Utilization, "utilization"),
+    (EventClass::Coverage, "coverage"),
+    (EventClass::Clock, "clock"),
+    (EventClass::Divergence, "divergence"),
+    (EventClass::Translation, "translation"),
+    (EventClass::Wx, "wx"),
+    (EventClass::Crash, "crash"),
+];
+
+impl FromStr for EventClass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        CLASSES
+            .iter()
+            .find_map(|(class, name)| (*name == s).then_some(*class))
+            .ok_or_else(|| anyhow!("Unknown event class {s}"))
+    }
+}
+
+impl Display for EventClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = CLASSES
+            .iter()
+            .find_map(|(class, name)| (class == self).then_some(*name))
+            .unwrap_or_default();
+
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A sustained rate of events per second, and the number of events which may be sent in a
+/// burst above it
+pub struct Rate {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Records that events of a class were dropped on a vCPU since the last one sent
+pub struct DroppedEvent {
+    pub class: EventClass,
+    pub vcpu_index: VCPUIndex,
+    pub count: u64,
+    pub icount: u64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    /// Events dropped since the last event was admitted
+    dropped: u64,
+}
+
+#[derive(Debug)]
+/// Token buckets for each rate-limited event class and vCPU
+pub struct Throttle {
+    rates: HashMap<EventClass, Rate>,
+    buckets: Mutex<HashMap<(EventClass, VCPUIndex), Bucket>>,
+    /// Events dropped over the whole run, per class
+    totals: Mutex<BTreeMap<EventClass, u64>>,
+}
+
+impl Throttle {
+    /// Parse rates separated by `;`, each written as `class:per_second[:burst]`, e.g.
+    /// `memory:10000:1000;instruction:50000`. The burst defaults to one second of events.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let rates = spec
+            .split(';')
+            .filter(|r| !r.trim().is_empty())
+            .map(|rate| {
+                let mut parts = rate.trim().split(':');
+                let class = parts.next().unwrap_or_default().parse::<EventClass>()?;
+                let per_second = parts
+                    .next()
+                    .ok_or_else(|| anyhow!("No rate given for {class} events"))?
+                    .parse::<f64>()?;
+                let burst = parts
+                    .next()
+                    .map(|b| b.parse::<f64>())
+                    .transpose()?
+                    .unwrap_or(per_second);
+
+                if per_second <= 0.0 || burst < 1.0 {
+                    return Err(anyhow!("Invalid rate {rate} for {class} events"));
+                }
+
+                Ok((class, Rate { per_second, burst }))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        Ok(Self {
+            rates,
+            buckets: Mutex::new(HashMap::new()),
+            totals: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Take a token for an event. Returns `None` if the event must be dropped, otherwise the
+    /// number of events of its class dropped on this vCPU since the last one was admitted.
+    pub fn admit(&self, class: EventClass, vcpu_index: VCPUIndex) -> Result<Option<u64>> {
+        let Some(rate) = self.rates.get(&class) else {
+            return Ok(Some(0));
+        };
+
+        let now = Instant::now();
+        let mut buckets = self
+            .buckets
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock throttle buckets: {e}"))?;
+        let bucket = buckets.entry((class, vcpu_index)).or_insert(Bucket {
+            tokens: rate.burst,
+            refilled: now,
+            dropped: 0,
+        });
+
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.refilled).as_secs_f64() * rate.per_second)
+            .min(rate.burst);
+        bucket.refilled = now;
+
+        if bucket.tokens < 1.0 {
+            bucket.dropped += 1;
+            *self
+                .totals
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock throttle totals: {e}"))?
+                .entry(class)
+                .or_default() += 1;
+            return Ok(None);
+        }
+
+        bucket.tokens -= 1.0;
+
+        Ok(Some(std::mem::take(&mut bucket.dropped)))
+    }
+
+    /// Take the drop counts not yet reported, for reporting at exit
+    pub fn take_pending(&self) -> Result<Vec<(EventClass, VCPUIndex, u64)>> {
+        let mut pending = self
+            .buckets
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock throttle buckets: {e}"))?
+            .iter_mut()
+            .filter(|(_, bucket)| bucket.dropped > 0)
+            .map(|((class, vcpu_index), bucket)| {
+                (*class, *vcpu_index, std::mem::take(&mut bucket.dropped))
+            })
+            .collect::<Vec<_>>();
+
+        pending.sort_by_key(|(class, vcpu_index, _)| (*class, *vcpu_index));
+
+        Ok(pending)
+    }
+
+    /// Returns the number of events dropped over the whole run, per class
+    pub fn totals(&self) -> Result<BTreeMap<EventClass, u64>> {
+        Ok(self
+            .totals
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock throttle totals: {e}"))?
+            .clone())
+    }
+}
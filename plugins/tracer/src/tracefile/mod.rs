@@ -0,0 +1,569 @@
+//! Append-only trace files backed by memory-mapped, preallocated segments
+//!
+//! A trace file starts with a fixed-size header holding a magic value and the length of the
+//! committed data, followed by the serialized events. Events are copied into the mapping
+//! without a syscall per write, and the committed length in the header is only advanced once
+//! the data before it has been written, so a trace cut short by QEMU being killed is still
+//! readable up to the last commit.
+//!
+//! Each record is framed by its length, so a reader can skip records it cannot decode. The
+//! header records the event schema the writer used, [`SCHEMA`], and the oldest schema a
+//! reader must understand to make sense of the trace, [`COMPATIBLE_SCHEMA`]. A reader
+//! refuses traces which need a newer schema than its own, and otherwise skips the records
+//! it does not understand, such as events added by a newer build, so traces stay readable
+//! across builds in both directions. Fields added to existing events should therefore be
+//! `#[serde(default)]`, and [`COMPATIBLE_SCHEMA`] bumped only for changes older readers
+//! would misread rather than skip. Version 1 trace files, which are not framed, are still
+//! read.
+//!
+//! A sharded trace is a directory holding one trace file per vCPU and a manifest listing
+//! them. Each record carries the instruction count when it was written, so the shards can be
+//! merged back into a single ordered view.
+
+use crate::Event;
+use anyhow::{anyhow, Result};
+use memmap2::MmapMut;
+use qemu_plugin::{
+    path::{create_sink, host_path, sink_options},
+    VCPUIndex,
+};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Serialize,
+};
+use serde_cbor::Deserializer;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    fs::{create_dir_all, rename, File},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{fence, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+const MAGIC: &[u8; 8] = b"QRSTRACE";
+/// The version of the file format
+const VERSION: u64 = 2;
+/// The first version whose records are framed by their length
+const FRAMED_VERSION: u64 = 2;
+/// The size of the header, kept at a page so event data stays page aligned
+const HEADER_SIZE: usize = 4096;
+const COMMITTED_OFFSET: usize = 16;
+const SCHEMA_OFFSET: usize = 24;
+const COMPATIBLE_SCHEMA_OFFSET: usize = 32;
+/// The size of the length before each record
+const FRAME_SIZE: usize = 4;
+/// The version of the event schema this build reads and writes
+pub const SCHEMA: u64 = 1;
+/// The oldest event schema which can read traces this build writes
+pub const COMPATIBLE_SCHEMA: u64 = 1;
+/// The size the file grows by when the mapping is full
+const SEGMENT_SIZE: usize = 16 << 20;
+/// The amount of uncommitted data which triggers a commit
+const COMMIT_INTERVAL: usize = 1 << 20;
+/// The name of the manifest in a sharded trace directory
+const MANIFEST: &str = "manifest.json";
+
+#[derive(Debug)]
+/// A trace file open for appending
+pub struct TraceFile {
+    file: File,
+    map: MmapMut,
+    len: usize,
+    committed: usize,
+}
+
+impl TraceFile {
+    /// Create a trace file at `path`, replacing any existing file
+    pub fn create<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let file = sink_options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(host_path(path)?)?;
+
+        file.set_len((HEADER_SIZE + SEGMENT_SIZE) as u64)?;
+
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        map[..8].copy_from_slice(MAGIC);
+        map[8..16].copy_from_slice(&VERSION.to_le_bytes());
+        map[COMMITTED_OFFSET..COMMITTED_OFFSET + 8].copy_from_slice(&0u64.to_le_bytes());
+        map[SCHEMA_OFFSET..SCHEMA_OFFSET + 8].copy_from_slice(&SCHEMA.to_le_bytes());
+        map[COMPATIBLE_SCHEMA_OFFSET..COMPATIBLE_SCHEMA_OFFSET + 8]
+            .copy_from_slice(&COMPATIBLE_SCHEMA.to_le_bytes());
+
+        Ok(Self {
+            file,
+            map,
+            len: 0,
+            committed: 0,
+        })
+    }
+
+    /// Release the mapping of the file. Windows refuses to resize a file while any view of
+    /// it is mapped, so this must precede `set_len`.
+    fn unmap(&mut self) -> Result<()> {
+        self.map = MmapMut::map_anon(1)?;
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.map.len() - HEADER_SIZE
+    }
+
+    /// Grow the file by whole segments until `additional` more bytes fit
+    fn reserve(&mut self, additional: usize) -> Result<()> {
+        if self.len + additional <= self.capacity() {
+            return Ok(());
+        }
+
+        self.commit()?;
+
+        let segments = (self.len + additional - self.capacity()).div_ceil(SEGMENT_SIZE);
+        let len = self.map.len() + segments * SEGMENT_SIZE;
+        self.unmap()?;
+        self.file.set_len(len as u64)?;
+        self.map = unsafe { MmapMut::map_mut(&self.file)? };
+
+        Ok(())
+    }
+
+    /// Append a single serialized record
+    pub fn append(&mut self, data: &[u8]) -> Result<()> {
+        let len = u32::try_from(data.len())
+            .map_err(|_| anyhow!("Record of {} bytes is too large", data.len()))?;
+
+        self.reserve(FRAME_SIZE + data.len())?;
+
+        let start = HEADER_SIZE + self.len;
+        self.map[start..start + FRAME_SIZE].copy_from_slice(&len.to_le_bytes());
+        self.map[start + FRAME_SIZE..start + FRAME_SIZE + data.len()].copy_from_slice(data);
+        self.len += FRAME_SIZE + data.len();
+
+        if self.len - self.committed >= COMMIT_INTERVAL {
+            self.commit()?;
+        }
+
+        Ok(())
+    }
+
+    /// Serialize and append a record
+    pub fn write_record<T>(&mut self, record: &T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        self.append(&serde_cbor::to_vec(record)?)
+    }
+
+    /// Make all data appended so far visible to readers
+    pub fn commit(&mut self) -> Result<()> {
+        if self.committed == self.len {
+            return Ok(());
+        }
+
+        self.map
+            .flush_async_range(HEADER_SIZE + self.committed, self.len - self.committed)?;
+        // The data must land before the header that covers it
+        fence(Ordering::Release);
+        self.map[COMMITTED_OFFSET..COMMITTED_OFFSET + 8]
+            .copy_from_slice(&(self.len as u64).to_le_bytes());
+        self.map.flush_async_range(0, HEADER_SIZE)?;
+        self.committed = self.len;
+
+        Ok(())
+    }
+
+    /// Commit all data and truncate the preallocated space
+    pub fn finish(&mut self) -> Result<()> {
+        self.commit()?;
+        self.map.flush()?;
+        self.unmap()?;
+        self.file.set_len((HEADER_SIZE + self.len) as u64)?;
+        self.map = unsafe { MmapMut::map_mut(&self.file)? };
+        Ok(())
+    }
+
+    /// Read the committed events from a trace file, skipping events this build does not
+    /// understand
+    pub fn read_events<P>(path: P) -> Result<Vec<Event>>
+    where
+        P: AsRef<Path>,
+    {
+        Self::read_records(path)
+    }
+
+    /// Read the committed records from a trace file, skipping records this build does not
+    /// understand
+    pub fn read_records<P, T>(path: P) -> Result<Vec<T>>
+    where
+        P: AsRef<Path>,
+        T: DeserializeOwned,
+    {
+        Ok(TraceReader::open(path)?.records()?.records)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// The header of a trace file
+pub struct TraceHeader {
+    pub version: u64,
+    /// The length of the committed data following the header
+    pub committed: u64,
+    /// The event schema the trace was written with
+    pub schema: u64,
+    /// The oldest event schema which can read the trace
+    pub compatible_schema: u64,
+}
+
+impl TraceHeader {
+    fn field(data: &[u8], offset: usize) -> Result<u64> {
+        Ok(u64::from_le_bytes(data[offset..offset + 8].try_into()?))
+    }
+
+    /// Parse the header at the start of `data`, checking that this build can read the trace
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE || &data[..8] != MAGIC {
+            return Err(anyhow!("Not a trace file"));
+        }
+
+        let version = Self::field(data, 8)?;
+
+        if version == 0 || version > VERSION {
+            return Err(anyhow!("Unsupported trace file version {version}"));
+        }
+
+        // Version 1 traces predate schema versions, and were written with the first schema
+        let (schema, compatible_schema) = if version < FRAMED_VERSION {
+            (1, 1)
+        } else {
+            (
+                Self::field(data, SCHEMA_OFFSET)?,
+                Self::field(data, COMPATIBLE_SCHEMA_OFFSET)?,
+            )
+        };
+
+        if compatible_schema > SCHEMA {
+            return Err(anyhow!(
+                "Trace needs event schema {compatible_schema} or newer, but this build reads \
+                 schema {SCHEMA}"
+            ));
+        }
+
+        Ok(Self {
+            version,
+            committed: Self::field(data, COMMITTED_OFFSET)?,
+            schema,
+            compatible_schema,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Records decoded from a trace
+pub struct Records<T> {
+    pub records: Vec<T>,
+    /// The number of records skipped because this build could not decode them
+    pub skipped: usize,
+}
+
+#[derive(Clone, Debug)]
+/// The committed data of a trace file, split into records
+pub struct TraceReader {
+    header: TraceHeader,
+    data: Vec<u8>,
+}
+
+impl TraceReader {
+    /// Read the trace file at `path`, checking that this build can read it
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_bytes(std::fs::read(path)?)
+    }
+
+    /// Read a trace file from its contents, checking that this build can read it
+    pub fn from_bytes(mut data: Vec<u8>) -> Result<Self> {
+        let header = TraceHeader::parse(&data)?;
+        let committed = usize::try_from(header.committed)
+            .ok()
+            .filter(|committed| *committed <= data.len() - HEADER_SIZE)
+            .ok_or_else(|| anyhow!("Trace file is shorter than its committed length"))?;
+
+        data.truncate(HEADER_SIZE + committed);
+        data.drain(..HEADER_SIZE);
+
+        Ok(Self { header, data })
+    }
+
+    pub fn header(&self) -> &TraceHeader {
+        &self.header
+    }
+
+    /// The serialized records, without their framing
+    pub fn frames(&self) -> Result<Vec<&[u8]>> {
+        let mut frames = Vec::new();
+
+        if self.header.version < FRAMED_VERSION {
+            // Unframed records are split by skipping over each one's encoding
+            let mut records = Deserializer::from_slice(&self.data).into_iter::<IgnoredAny>();
+            let mut start = 0;
+
+            while let Some(record) = records.next() {
+                record?;
+                frames.push(&self.data[start..records.byte_offset()]);
+                start = records.byte_offset();
+            }
+
+            return Ok(frames);
+        }
+
+        let mut rest = self.data.as_slice();
+
+        while !rest.is_empty() {
+            if rest.len() < FRAME_SIZE {
+                return Err(anyhow!("Trace file ends within a record's length"));
+            }
+
+            let (len, data) = rest.split_at(FRAME_SIZE);
+            let len = u32::from_le_bytes(len.try_into()?) as usize;
+
+            if data.len() < len {
+                return Err(anyhow!("Trace file ends within a record"));
+            }
+
+            let (frame, next) = data.split_at(len);
+            frames.push(frame);
+            rest = next;
+        }
+
+        Ok(frames)
+    }
+
+    /// Decode every record, skipping those this build does not understand, such as events
+    /// added by a newer build
+    pub fn records<T>(&self) -> Result<Records<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let mut records = Records {
+            records: Vec::new(),
+            skipped: 0,
+        };
+
+        for frame in self.frames()? {
+            match serde_cbor::from_slice(frame) {
+                Ok(record) => records.records.push(record),
+                Err(_) => records.skipped += 1,
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// An event in a trace file shard, with the instruction count used to merge shards
+pub struct ShardRecord {
+    pub vcpu_index: VCPUIndex,
+    pub icount: u64,
+    pub event: Event,
+}
+
+#[derive(Serialize)]
+struct ShardRecordRef<'a> {
+    vcpu_index: VCPUIndex,
+    icount: u64,
+    event: &'a Event,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// Lists the shards in a sharded trace directory
+pub struct Manifest {
+    pub version: u64,
+    /// Shard file names, relative to the manifest, by vCPU
+    pub shards: BTreeMap<VCPUIndex, String>,
+}
+
+impl Manifest {
+    /// Read the manifest of the sharded trace in `dir`
+    pub fn read<P>(dir: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let manifest: Self = serde_json::from_reader(File::open(dir.as_ref().join(MANIFEST))?)?;
+
+        if manifest.version == 0 || manifest.version > VERSION {
+            return Err(anyhow!(
+                "Unsupported trace manifest version {}",
+                manifest.version
+            ));
+        }
+
+        Ok(manifest)
+    }
+
+    /// Write the manifest of the sharded trace in `dir`
+    pub fn write<P>(&self, dir: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        // Write then rename, so a reader never sees a partial manifest
+        let tmp = dir.as_ref().join(format!(".{MANIFEST}"));
+        serde_json::to_writer_pretty(create_sink(&tmp)?, self)?;
+        rename(tmp, dir.as_ref().join(MANIFEST))?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+/// A directory of trace files, one per vCPU, so vCPUs never contend on a single writer
+pub struct ShardedTraceFile {
+    dir: PathBuf,
+    shards: RwLock<BTreeMap<VCPUIndex, Arc<Mutex<TraceFile>>>>,
+}
+
+impl ShardedTraceFile {
+    /// Create the directory `dir` and an empty manifest in it
+    pub fn create<P>(dir: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        create_dir_all(dir.as_ref())?;
+
+        let shards = Self {
+            dir: dir.as_ref().to_path_buf(),
+            shards: RwLock::new(BTreeMap::new()),
+        };
+        shards.write_manifest(&BTreeMap::new())?;
+
+        Ok(shards)
+    }
+
+    fn write_manifest(&self, shards: &BTreeMap<VCPUIndex, Arc<Mutex<TraceFile>>>) -> Result<()> {
+        Manifest {
+            version: VERSION,
+            shards: shards
+                .keys()
+                .map(|vcpu_index| (*vcpu_index, Self::shard_name(*vcpu_index)))
+                .collect(),
+        }
+        .write(&self.dir)
+    }
+
+    fn shard_name(vcpu_index: VCPUIndex) -> String {
+        format!("vcpu-{vcpu_index}.trace")
+    }
+
+    fn shard(&self, vcpu_index: VCPUIndex) -> Result<Arc<Mutex<TraceFile>>> {
+        if let Some(shard) = self
+            .shards
+            .read()
+            .map_err(|e| anyhow!("Failed to lock shards: {e}"))?
+            .get(&vcpu_index)
+        {
+            return Ok(shard.clone());
+        }
+
+        let mut shards = self
+            .shards
+            .write()
+            .map_err(|e| anyhow!("Failed to lock shards: {e}"))?;
+
+        if let Some(shard) = shards.get(&vcpu_index) {
+            return Ok(shard.clone());
+        }
+
+        let shard = Arc::new(Mutex::new(TraceFile::create(
+            self.dir.join(Self::shard_name(vcpu_index)),
+        )?));
+        shards.insert(vcpu_index, shard.clone());
+        self.write_manifest(&shards)?;
+
+        Ok(shard)
+    }
+
+    /// Append an event to the shard for `vcpu_index`
+    pub fn write(&self, vcpu_index: VCPUIndex, icount: u64, event: &Event) -> Result<()> {
+        let shard = self.shard(vcpu_index)?;
+        let mut shard = shard
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock shard: {e}"))?;
+
+        shard.write_record(&ShardRecordRef {
+            vcpu_index,
+            icount,
+            event,
+        })
+    }
+
+    /// Commit and truncate every shard
+    pub fn finish(&self) -> Result<()> {
+        for shard in self
+            .shards
+            .read()
+            .map_err(|e| anyhow!("Failed to lock shards: {e}"))?
+            .values()
+        {
+            shard
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock shard: {e}"))?
+                .finish()?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the committed records of each shard listed in the manifest in `dir`
+    pub fn read_shards<P>(dir: P) -> Result<BTreeMap<VCPUIndex, Vec<ShardRecord>>>
+    where
+        P: AsRef<Path>,
+    {
+        Manifest::read(dir.as_ref())?
+            .shards
+            .into_iter()
+            .map(|(vcpu_index, name)| {
+                Ok((
+                    vcpu_index,
+                    TraceFile::read_records(dir.as_ref().join(name))?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Read every shard in `dir`, merged into a single sequence ordered by instruction count
+    pub fn read_merged<P>(dir: P) -> Result<Vec<ShardRecord>>
+    where
+        P: AsRef<Path>,
+    {
+        let mut shards = Self::read_shards(dir)?
+            .into_values()
+            .map(|records| records.into_iter().peekable())
+            .collect::<Vec<_>>();
+        let mut heap = shards
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, shard)| shard.peek().map(|r| Reverse((r.icount, i))))
+            .collect::<BinaryHeap<_>>();
+        let mut merged = Vec::new();
+
+        while let Some(Reverse((_, i))) = heap.pop() {
+            if let Some(record) = shards[i].next() {
+                merged.push(record);
+            }
+
+            if let Some(next) = shards[i].peek() {
+                heap.push(Reverse((next.icount, i)));
+            }
+        }
+
+        Ok(merged)
+    }
+}
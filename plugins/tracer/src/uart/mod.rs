@@ -0,0 +1,187 @@
+//! Reconstruction of console output from writes to the data registers of common UARTs, for
+//! system mode guests run without QEMU's own serial capture
+//!
+//! UARTs are given as `kind:address` pairs, or by kind alone to use the address QEMU's
+//! machines place it at: the PL011 of `virt` on ARM, the 16550 of `virt` on RISC-V and the
+//! first UART of `sifive_u`. Bytes written to a UART's transmit register are buffered and
+//! reported as a [`UartEvent`] at each newline, or once the buffer fills.
+
+use crate::memmap::parse_addr;
+use anyhow::{anyhow, Result};
+use qemu_plugin::VCPUIndex;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use typed_builder::TypedBuilder;
+
+/// The most bytes buffered before they are reported without a newline
+const MAX_LINE: usize = 256;
+
+/// The line control register's divisor latch access bit, which maps the 16550's divisor over
+/// its transmit register
+const NS16550_LCR_DLAB: u64 = 0x80;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+/// The UART models whose transmit registers are decoded
+pub enum UartKind {
+    /// ARM PrimeCell PL011, transmitting through `UARTDR` at offset 0
+    Pl011,
+    /// National Semiconductor 16550, transmitting through `THR` at offset 0 unless the
+    /// divisor latch is selected
+    Ns16550,
+    /// SiFive UART, transmitting through `txdata` at offset 0
+    Sifive,
+}
+
+impl FromStr for UartKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "pl011" => Ok(Self::Pl011),
+            "16550" | "ns16550" | "ns16550a" => Ok(Self::Ns16550),
+            "sifive" => Ok(Self::Sifive),
+            _ => Err(anyhow!(
+                "Unknown UART {s}, expected pl011, ns16550 or sifive"
+            )),
+        }
+    }
+}
+
+impl UartKind {
+    /// The address QEMU's machines place this UART at
+    pub fn default_address(&self) -> u64 {
+        match self {
+            Self::Pl011 => 0x0900_0000,
+            Self::Ns16550 => 0x1000_0000,
+            Self::Sifive => 0x1001_0000,
+        }
+    }
+
+    /// The size of the UART's register block
+    fn size(&self) -> u64 {
+        match self {
+            Self::Pl011 => 0x1000,
+            Self::Ns16550 => 0x8,
+            Self::Sifive => 0x1c,
+        }
+    }
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// Bytes transmitted by a UART, usually one line of console output
+pub struct UartEvent {
+    pub kind: UartKind,
+    pub address: u64,
+    pub data: Vec<u8>,
+    pub vcpu_index: VCPUIndex,
+    pub icount: u64,
+}
+
+#[derive(Debug)]
+struct Uart {
+    kind: UartKind,
+    address: u64,
+    /// Whether the 16550's divisor latch is selected
+    dlab: bool,
+    buffer: Vec<u8>,
+    /// The vCPU which transmitted the last byte buffered
+    vcpu_index: VCPUIndex,
+}
+
+#[derive(Debug)]
+/// Decodes writes to the transmit registers of a set of UARTs
+pub struct UartDecoder {
+    uarts: Vec<Uart>,
+}
+
+impl UartDecoder {
+    /// Create a decoder for UARTs given as `kind[:address]` separated by `;`, e.g.
+    /// `pl011;ns16550:0x3f8`
+    pub fn new(uarts: &str) -> Result<Self> {
+        let uarts = uarts
+            .split(';')
+            .filter(|u| !u.trim().is_empty())
+            .map(|u| {
+                let (kind, address) = match u.split_once(':') {
+                    Some((kind, address)) => {
+                        (kind.parse::<UartKind>()?, Some(parse_addr(address)?))
+                    }
+                    None => (u.parse::<UartKind>()?, None),
+                };
+
+                Ok(Uart {
+                    kind,
+                    address: address.unwrap_or_else(|| kind.default_address()),
+                    dlab: false,
+                    buffer: Vec::new(),
+                    vcpu_index: 0,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if uarts.is_empty() {
+            return Err(anyhow!("No UARTs given"));
+        }
+
+        Ok(Self { uarts })
+    }
+
+    /// Record an access to physical address `hwaddr`. Returns the bytes transmitted if a
+    /// write to a transmit register completed a line.
+    pub fn on_access(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        hwaddr: u64,
+        is_store: bool,
+        value: u64,
+        icount: u64,
+    ) -> Option<UartEvent> {
+        if !is_store {
+            return None;
+        }
+
+        let uart = self
+            .uarts
+            .iter_mut()
+            .find(|u| (u.address..u.address + u.kind.size()).contains(&hwaddr))?;
+
+        match (uart.kind, hwaddr - uart.address) {
+            (UartKind::Ns16550, 3) => {
+                uart.dlab = value & NS16550_LCR_DLAB != 0;
+                return None;
+            }
+            (UartKind::Ns16550, 0) if uart.dlab => return None,
+            (_, 0) => {}
+            _ => return None,
+        }
+
+        uart.vcpu_index = vcpu_index;
+        uart.buffer.push(value as u8);
+
+        if value as u8 == b'\n' || uart.buffer.len() >= MAX_LINE {
+            Self::take(uart, icount)
+        } else {
+            None
+        }
+    }
+
+    fn take(uart: &mut Uart, icount: u64) -> Option<UartEvent> {
+        (!uart.buffer.is_empty()).then(|| {
+            UartEvent::builder()
+                .kind(uart.kind)
+                .address(uart.address)
+                .data(std::mem::take(&mut uart.buffer))
+                .vcpu_index(uart.vcpu_index)
+                .icount(icount)
+                .build()
+        })
+    }
+
+    /// Returns the bytes still buffered for each UART, for reporting at exit
+    pub fn finish(&mut self, icount: u64) -> Vec<UartEvent> {
+        self.uarts
+            .iter_mut()
+            .filter_map(|uart| Self::take(uart, icount))
+            .collect()
+    }
+}
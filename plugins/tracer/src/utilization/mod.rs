@@ -0,0 +1,359 @@
+//! Per-vCPU utilization, from the time each vCPU spends running and idle
+//!
+//! QEMU reports when a vCPU goes idle, such as when the guest executes `hlt` or `wfi` with
+//! nothing to run, and when it resumes. The wall-clock time between these is accumulated
+//! for each vCPU in [`Stats`], and is included in every stats snapshot. Idle time is only
+//! reported in system mode. In user mode, vCPUs are guest threads and are always busy.
+//!
+//! With a sample interval, the difference between consecutive snapshots is sent as a
+//! [`UtilizationEvent`] for each vCPU. A vCPU which is not idle may still not be running,
+//! when its host thread is waiting for a host CPU or, without MTTCG, for the other vCPUs to
+//! take their turn, so each sample estimates this as stolen time: the busy time the vCPU
+//! would not have needed to execute its instructions at the rate of the fastest vCPU in the
+//! interval. A workload which scales keeps every vCPU busy with little stolen time.
+
+use crate::{
+    log,
+    stats::{Stats, StatsSnapshot},
+};
+use anyhow::{anyhow, Result};
+use qemu_plugin::{path::create_sink, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread::{sleep, spawn},
+    time::{Duration, Instant},
+};
+use typed_builder::TypedBuilder;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VcpuState {
+    Busy,
+    Idle,
+    Exited,
+}
+
+#[derive(Debug)]
+struct VcpuClock {
+    state: VcpuState,
+    since: Instant,
+    busy: Duration,
+    idle: Duration,
+    idles: u64,
+}
+
+impl VcpuClock {
+    /// End the current span at `now`, accumulating it, and start a span in `state`
+    fn switch(&mut self, state: VcpuState, now: Instant) {
+        self.elapse(now);
+        self.state = state;
+    }
+
+    fn elapse(&mut self, now: Instant) {
+        let span = now.saturating_duration_since(self.since);
+
+        match self.state {
+            VcpuState::Busy => self.busy += span,
+            VcpuState::Idle => self.idle += span,
+            VcpuState::Exited => {}
+        }
+
+        self.since = now;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The time a vCPU has spent busy and idle since it started
+pub struct VcpuTime {
+    pub busy_ms: u64,
+    pub idle_ms: u64,
+    /// The number of times the vCPU went idle
+    pub idles: u64,
+}
+
+#[derive(Debug)]
+/// The busy and idle time of each vCPU, updated from idle and resume callbacks
+pub struct Utilization {
+    started: Instant,
+    vcpus: Mutex<BTreeMap<VCPUIndex, VcpuClock>>,
+}
+
+impl Default for Utilization {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            vcpus: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Utilization {
+    fn switch(&self, vcpu_index: VCPUIndex, state: VcpuState) -> Result<()> {
+        let now = Instant::now();
+        let mut vcpus = self
+            .vcpus
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock vcpu clocks: {e}"))?;
+        let clock = vcpus.entry(vcpu_index).or_insert(VcpuClock {
+            state: VcpuState::Busy,
+            since: now,
+            busy: Duration::ZERO,
+            idle: Duration::ZERO,
+            idles: 0,
+        });
+
+        if state == VcpuState::Idle && clock.state != VcpuState::Idle {
+            clock.idles += 1;
+        }
+
+        clock.switch(state, now);
+
+        Ok(())
+    }
+
+    /// Start timing `vcpu_index` as busy
+    pub fn on_init(&self, vcpu_index: VCPUIndex) -> Result<()> {
+        self.switch(vcpu_index, VcpuState::Busy)
+    }
+
+    pub fn on_idle(&self, vcpu_index: VCPUIndex) -> Result<()> {
+        self.switch(vcpu_index, VcpuState::Idle)
+    }
+
+    pub fn on_resume(&self, vcpu_index: VCPUIndex) -> Result<()> {
+        self.switch(vcpu_index, VcpuState::Busy)
+    }
+
+    /// Stop timing `vcpu_index`
+    pub fn on_exit(&self, vcpu_index: VCPUIndex) -> Result<()> {
+        self.switch(vcpu_index, VcpuState::Exited)
+    }
+
+    /// The time since timing started
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// The busy and idle time of each vCPU up to now
+    pub fn times(&self) -> Result<BTreeMap<VCPUIndex, VcpuTime>> {
+        let now = Instant::now();
+
+        Ok(self
+            .vcpus
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock vcpu clocks: {e}"))?
+            .iter_mut()
+            .map(|(vcpu_index, clock)| {
+                clock.elapse(now);
+
+                (
+                    *vcpu_index,
+                    VcpuTime {
+                        busy_ms: clock.busy.as_millis() as u64,
+                        idle_ms: clock.idle.as_millis() as u64,
+                        idles: clock.idles,
+                    },
+                )
+            })
+            .collect())
+    }
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// The utilization of a vCPU over one sample interval
+pub struct UtilizationEvent {
+    pub vcpu_index: VCPUIndex,
+    /// The instruction count at the end of the interval
+    pub icount: u64,
+    pub interval_ms: u64,
+    /// The instructions the vCPU executed in the interval
+    pub instructions: u64,
+    pub busy_ms: u64,
+    pub idle_ms: u64,
+    /// The busy time the vCPU was estimated not to be running
+    pub steal_ms: u64,
+    /// The number of times the vCPU went idle in the interval
+    pub idles: u64,
+}
+
+/// The utilization of each vCPU between two snapshots
+pub fn sample(previous: &StatsSnapshot, current: &StatsSnapshot) -> Vec<UtilizationEvent> {
+    let interval_ms = current.timestamp_ms.saturating_sub(previous.timestamp_ms) as u64;
+    let deltas = current
+        .vcpu_time
+        .iter()
+        .map(|(vcpu_index, time)| {
+            let before = previous
+                .vcpu_time
+                .get(vcpu_index)
+                .copied()
+                .unwrap_or_default();
+            let instructions = current
+                .vcpu_instructions
+                .get(vcpu_index)
+                .copied()
+                .unwrap_or_default()
+                .saturating_sub(
+                    previous
+                        .vcpu_instructions
+                        .get(vcpu_index)
+                        .copied()
+                        .unwrap_or_default(),
+                );
+
+            (
+                *vcpu_index,
+                instructions,
+                VcpuTime {
+                    busy_ms: time.busy_ms.saturating_sub(before.busy_ms),
+                    idle_ms: time.idle_ms.saturating_sub(before.idle_ms),
+                    idles: time.idles.saturating_sub(before.idles),
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // Instructions per millisecond of the fastest vCPU, which is assumed to have run for
+    // all of its busy time
+    let rate = deltas
+        .iter()
+        .filter(|(_, _, time)| time.busy_ms > 0)
+        .map(|(_, instructions, time)| *instructions as f64 / time.busy_ms as f64)
+        .fold(0.0, f64::max);
+
+    deltas
+        .into_iter()
+        .map(|(vcpu_index, instructions, time)| {
+            let running_ms = if rate > 0.0 {
+                (instructions as f64 / rate) as u64
+            } else {
+                time.busy_ms
+            };
+
+            UtilizationEvent::builder()
+                .vcpu_index(vcpu_index)
+                .icount(current.instructions)
+                .interval_ms(interval_ms)
+                .instructions(instructions)
+                .busy_ms(time.busy_ms)
+                .idle_ms(time.idle_ms)
+                .steal_ms(time.busy_ms.saturating_sub(running_ms))
+                .idles(time.idles)
+                .build()
+        })
+        .collect()
+}
+
+/// Take a snapshot of `stats` every `interval`, calling `send` with the utilization of each
+/// vCPU since the last. Sampling stops if `send` fails.
+pub fn sample_periodically<F>(stats: Arc<Stats>, interval: Duration, send: F) -> Result<()>
+where
+    F: Fn(UtilizationEvent) -> Result<()> + Send + 'static,
+{
+    let mut previous = stats.snapshot()?;
+
+    spawn(move || loop {
+        sleep(interval);
+
+        let current = match stats.snapshot() {
+            Ok(current) => current,
+            Err(e) => {
+                log(format!("Failed to sample utilization: {e}"));
+                return;
+            }
+        };
+
+        if let Err(e) = sample(&previous, &current).into_iter().try_for_each(&send) {
+            log(format!("Failed to send utilization sample: {e}"));
+            return;
+        }
+
+        previous = current;
+    });
+
+    Ok(())
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The utilization of a vCPU over the whole run
+pub struct VcpuUtilization {
+    #[serde(flatten)]
+    pub time: VcpuTime,
+    pub instructions: u64,
+    /// The fraction of the vCPU's time it was busy
+    pub utilization: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A summary of how well a run used its vCPUs
+pub struct UtilizationReport {
+    pub wall_ms: u64,
+    pub vcpus: BTreeMap<VCPUIndex, VcpuUtilization>,
+    /// The average number of vCPUs busy at once
+    pub parallelism: f64,
+    /// The mean instructions executed per vCPU as a fraction of the most executed by one,
+    /// which is 1 when work is spread evenly
+    pub balance: f64,
+}
+
+impl UtilizationReport {
+    pub fn new(stats: &Stats) -> Result<Self> {
+        let snapshot = stats.snapshot()?;
+        let wall_ms = stats.utilization.elapsed().as_millis() as u64;
+        let vcpus = snapshot
+            .vcpu_time
+            .iter()
+            .map(|(vcpu_index, time)| {
+                let total = time.busy_ms + time.idle_ms;
+
+                (
+                    *vcpu_index,
+                    VcpuUtilization {
+                        time: *time,
+                        instructions: snapshot
+                            .vcpu_instructions
+                            .get(vcpu_index)
+                            .copied()
+                            .unwrap_or_default(),
+                        utilization: if total > 0 {
+                            time.busy_ms as f64 / total as f64
+                        } else {
+                            0.0
+                        },
+                    },
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+        let busy_ms = vcpus.values().map(|v| v.time.busy_ms).sum::<u64>();
+        let most = vcpus
+            .values()
+            .map(|v| v.instructions)
+            .max()
+            .unwrap_or_default();
+        let mean =
+            vcpus.values().map(|v| v.instructions).sum::<u64>() as f64 / vcpus.len().max(1) as f64;
+
+        Ok(Self {
+            wall_ms,
+            parallelism: if wall_ms > 0 {
+                busy_ms as f64 / wall_ms as f64
+            } else {
+                0.0
+            },
+            balance: if most > 0 { mean / most as f64 } else { 0.0 },
+            vcpus,
+        })
+    }
+
+    /// Write the report to `path` as JSON
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        serde_json::to_writer_pretty(create_sink(path)?, self)?;
+
+        Ok(())
+    }
+}
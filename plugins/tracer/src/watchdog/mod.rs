@@ -0,0 +1,114 @@
+//! A watchdog which reports a hang when an iteration of the guest executes more instructions
+//! than a budget allows
+//!
+//! Iterations are delimited by reset markers: blocks which start a new iteration, such as a
+//! fuzz harness's start marker or the top of a test loop. Every block adds its length to a
+//! per-vCPU counter with an inline operation, and reset markers store zero to it first, so
+//! counting costs no callbacks. A conditional callback on every block fires only once the
+//! counter reaches the budget, reports the hang, and re-arms the watchdog, so an iteration
+//! which never ends is reported again after each further budget.
+
+use qemu_plugin::{
+    qemu_plugin_num_vcpus, qemu_plugin_u64_get, qemu_plugin_u64_set, PluginCondition, PluginOp,
+    Scoreboard, TranslationBlock, VCPUIndex,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use typed_builder::TypedBuilder;
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// An iteration which exceeded the instruction budget
+pub struct HangEvent {
+    pub vcpu_index: VCPUIndex,
+    /// The block executing when the budget ran out
+    pub pc: u64,
+    /// The instructions the iteration had executed
+    pub instructions: u64,
+    pub budget: u64,
+}
+
+/// Counts the instructions each vCPU executes in its current iteration
+pub struct Watchdog {
+    counters: Scoreboard<'static, u64>,
+    budget: u64,
+    resets: Vec<u64>,
+}
+
+impl std::fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("budget", &self.budget)
+            .field("resets", &self.resets)
+            .finish()
+    }
+}
+
+impl Watchdog {
+    /// Create a watchdog allowing `budget` instructions between executions of the blocks
+    /// at `resets`
+    pub fn new(budget: u64, resets: Vec<u64>) -> Self {
+        Self {
+            counters: Scoreboard::new(),
+            budget,
+            resets,
+        }
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// The instructions each vCPU has executed in its current iteration
+    pub fn counts(&self) -> BTreeMap<VCPUIndex, u64> {
+        let counter = self.counters.entry(0);
+
+        (0..qemu_plugin_num_vcpus().unwrap_or_default().max(0) as VCPUIndex)
+            .map(|vcpu_index| (vcpu_index, qemu_plugin_u64_get(counter, vcpu_index)))
+            .collect()
+    }
+
+    /// Whether the block at `vaddr` starts a new iteration
+    pub fn resets(&self, vaddr: u64) -> bool {
+        self.resets.contains(&vaddr)
+    }
+
+    /// Instrument a block to count its instructions, calling `on_hang` when it executes
+    /// with the budget exhausted
+    pub fn instrument<F>(&self, tb: &TranslationBlock, on_hang: F)
+    where
+        F: FnMut(VCPUIndex) + Send + Sync + 'static,
+    {
+        let counter = self.counters.entry(0);
+
+        if self.resets(tb.vaddr()) {
+            tb.register_inline_per_vcpu(PluginOp::QEMU_PLUGIN_INLINE_STORE_U64, counter, 0);
+        }
+
+        tb.register_inline_per_vcpu(
+            PluginOp::QEMU_PLUGIN_INLINE_ADD_U64,
+            counter,
+            tb.size() as u64,
+        );
+        tb.register_conditional_execute_callback(
+            on_hang,
+            PluginCondition::QEMU_PLUGIN_COND_GE,
+            counter,
+            self.budget,
+        );
+    }
+
+    /// Report that a vCPU exhausted its budget in the block at `pc`, and re-arm it
+    pub fn on_hang(&self, vcpu_index: VCPUIndex, pc: u64) -> HangEvent {
+        let counter = self.counters.entry(0);
+        let instructions = qemu_plugin_u64_get(counter, vcpu_index);
+
+        qemu_plugin_u64_set(counter, vcpu_index, 0);
+
+        HangEvent::builder()
+            .vcpu_index(vcpu_index)
+            .pc(pc)
+            .instructions(instructions)
+            .budget(self.budget)
+            .build()
+    }
+}
@@ -0,0 +1,404 @@
+//! Detection of W^X violations: memory which is both written and executed, as JIT compilers,
+//! unpackers and injected shellcode do
+//!
+//! Each page's writes and executions are tracked, and a page is flagged the first time code
+//! runs from it after it was written, or it is written after code ran from it. In user mode,
+//! the protections `mmap` and `mprotect` give each range are followed too, and a range made
+//! writable and executable at once is flagged as it is mapped. Each violation is sent as a
+//! [`WxEvent`], and at exit, the report lists each flagged page with a timeline of its
+//! protection changes and of the switches between writing it and executing it.
+//!
+//! In system mode, the guest's page tables are not read. Pages are tracked by physical
+//! address, so a page written through one mapping and executed through another is still
+//! flagged, and protections are inferred from the accesses alone. A guest kernel loading a
+//! program writes its code before running it, so every page of code loaded at runtime is
+//! flagged there. In user mode, memory the loader mapped before the program started has no
+//! known protection until the program changes it.
+
+use crate::arch::{Arch, Syscall};
+use anyhow::Result;
+use qemu_plugin::{path::create_sink, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+/// The granularity writes and executions are tracked at
+pub const PAGE_SIZE: u64 = 4096;
+/// The number of entries kept in a page's timeline
+const MAX_TIMELINE: usize = 64;
+
+const PROT_READ: u64 = 1;
+const PROT_WRITE: u64 = 2;
+const PROT_EXEC: u64 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// The access `mmap` or `mprotect` allowed to a range
+pub struct Protection {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Protection {
+    fn from_prot(prot: u64) -> Self {
+        Self {
+            read: prot & PROT_READ != 0,
+            write: prot & PROT_WRITE != 0,
+            execute: prot & PROT_EXEC != 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// How memory broke W^X
+pub enum WxKind {
+    /// A range was mapped or protected writable and executable at once
+    WritableExecutable,
+    /// Code ran from a page after it was written
+    ExecutedAfterWrite,
+    /// A page was written after code ran from it
+    WrittenAfterExecute,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A range or page which broke W^X
+pub struct WxEvent {
+    pub vcpu_index: VCPUIndex,
+    pub kind: WxKind,
+    /// The start of the range, or the page, by physical address in system mode
+    pub addr: u64,
+    pub size: u64,
+    /// The instruction which wrote or executed the page, for page violations
+    pub pc: Option<u64>,
+    pub icount: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// What happened to a page
+pub enum Action {
+    Map,
+    Protect,
+    Unmap,
+    Write,
+    Execute,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// An entry of a page's timeline
+pub struct TimelineEntry {
+    pub action: Action,
+    pub vcpu_index: VCPUIndex,
+    /// The instruction which wrote or executed the page
+    pub pc: Option<u64>,
+    /// The page's protection after the action, if known
+    pub protection: Option<Protection>,
+    pub icount: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// A page which broke W^X, and what happened to it
+pub struct PageReport {
+    pub page: u64,
+    pub violations: Vec<WxKind>,
+    /// The page's first protection changes and switches between writes and executions
+    pub timeline: Vec<TimelineEntry>,
+    /// The timeline entries past those listed
+    pub timeline_omitted: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// The W^X violations of a run
+pub struct WxReport {
+    /// Whether pages are physical addresses, as in system mode
+    pub physical: bool,
+    /// The ranges mapped or protected writable and executable at once
+    pub ranges: Vec<WxEvent>,
+    /// The pages both written and executed
+    pub pages: Vec<PageReport>,
+}
+
+impl WxReport {
+    pub fn write<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        serde_json::to_writer_pretty(create_sink(path)?, self)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Access {
+    Write,
+    Execute,
+}
+
+#[derive(Debug, Default)]
+struct PageState {
+    /// The page's last access since it was mapped
+    last: Option<Access>,
+    written: bool,
+    executed: bool,
+    violations: Vec<WxKind>,
+    timeline: Vec<TimelineEntry>,
+    timeline_omitted: u64,
+}
+
+impl PageState {
+    fn record(&mut self, entry: TimelineEntry) {
+        if self.timeline.len() < MAX_TIMELINE {
+            self.timeline.push(entry);
+        } else {
+            self.timeline_omitted += 1;
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A `mmap`, `mprotect` or `munmap` awaiting its return
+struct PendingSyscall {
+    syscall: Syscall,
+    args: [u64; 8],
+}
+
+#[derive(Debug)]
+/// Tracks the writes, executions and protections of pages
+pub struct WxTracker {
+    arch: Arch,
+    system: bool,
+    /// Protected ranges by start, with their end
+    protections: BTreeMap<u64, (u64, Protection)>,
+    pages: BTreeMap<u64, PageState>,
+    pending: HashMap<VCPUIndex, PendingSyscall>,
+    ranges: Vec<WxEvent>,
+}
+
+impl WxTracker {
+    pub fn new(arch: Arch, system: bool) -> Self {
+        Self {
+            arch,
+            system,
+            protections: BTreeMap::new(),
+            pages: BTreeMap::new(),
+            pending: HashMap::new(),
+            ranges: Vec::new(),
+        }
+    }
+
+    /// The protection of the range containing `addr`, if one is known
+    fn protection(&self, addr: u64) -> Option<Protection> {
+        self.protections
+            .range(..=addr)
+            .next_back()
+            .filter(|(_, (end, _))| addr < *end)
+            .map(|(_, (_, protection))| *protection)
+    }
+
+    /// Set the protection of a range, or clear it when unmapping
+    fn protect(&mut self, start: u64, end: u64, protection: Option<Protection>) {
+        let overlapping = self
+            .protections
+            .range(..end)
+            .filter(|(s, (e, _))| **s < end && *e > start)
+            .map(|(s, (e, p))| (*s, *e, *p))
+            .collect::<Vec<_>>();
+
+        for (s, e, p) in overlapping {
+            self.protections.remove(&s);
+
+            if s < start {
+                self.protections.insert(s, (start, p));
+            }
+
+            if e > end {
+                self.protections.insert(end, (e, p));
+            }
+        }
+
+        if let Some(protection) = protection {
+            self.protections.insert(start, (end, protection));
+        }
+    }
+
+    /// Record a syscall a vCPU entered, if it maps, protects or unmaps memory
+    pub fn on_syscall(&mut self, vcpu_index: VCPUIndex, num: i64, args: [u64; 8]) {
+        if self.system {
+            return;
+        }
+
+        if let Some(syscall @ (Syscall::Mmap | Syscall::Mprotect | Syscall::Munmap)) =
+            self.arch.syscall(num)
+        {
+            self.pending
+                .insert(vcpu_index, PendingSyscall { syscall, args });
+        }
+    }
+
+    /// Update protections once a syscall returns. Returns an event if it made a range
+    /// writable and executable.
+    pub fn on_syscall_return(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        ret: i64,
+        icount: u64,
+    ) -> Option<WxEvent> {
+        let pending = self.pending.remove(&vcpu_index)?;
+
+        // Addresses returned by `mmap` may be negative, while errors are -1 to -4095
+        if (-4095..0).contains(&ret) {
+            return None;
+        }
+
+        let args = pending.args;
+        let (start, action) = match pending.syscall {
+            Syscall::Mmap => (ret as u64, Action::Map),
+            Syscall::Mprotect => (args[0], Action::Protect),
+            _ => (args[0], Action::Unmap),
+        };
+        let size = args[1].next_multiple_of(PAGE_SIZE);
+        let end = start.saturating_add(size);
+        let protection = (action != Action::Unmap).then(|| Protection::from_prot(args[2]));
+
+        self.protect(start, end, protection);
+
+        for (_, state) in self.pages.range_mut(start..end) {
+            // New memory starts over, with no writes or executions
+            if action != Action::Protect {
+                state.last = None;
+                state.written = false;
+                state.executed = false;
+            }
+
+            state.record(TimelineEntry {
+                action,
+                vcpu_index,
+                pc: None,
+                protection,
+                icount,
+            });
+        }
+
+        let protection = protection?;
+
+        (protection.write && protection.execute).then(|| {
+            let event = WxEvent {
+                vcpu_index,
+                kind: WxKind::WritableExecutable,
+                addr: start,
+                size,
+                pc: None,
+                icount,
+            };
+
+            self.ranges.push(event.clone());
+            event
+        })
+    }
+
+    /// Record an access to `addr` by the instruction at `pc`. Returns an event if it is the
+    /// page's first access of its kind since the page was last accessed the other way.
+    fn on_access(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        pc: u64,
+        addr: u64,
+        access: Access,
+        icount: u64,
+    ) -> Option<WxEvent> {
+        let page = addr & !(PAGE_SIZE - 1);
+
+        if self
+            .pages
+            .get(&page)
+            .is_some_and(|state| state.last == Some(access))
+        {
+            return None;
+        }
+
+        let protection = self.protection(page);
+        let state = self.pages.entry(page).or_default();
+        let (action, kind, broken) = match access {
+            Access::Write => {
+                state.written = true;
+                (Action::Write, WxKind::WrittenAfterExecute, state.executed)
+            }
+            Access::Execute => {
+                state.executed = true;
+                (Action::Execute, WxKind::ExecutedAfterWrite, state.written)
+            }
+        };
+
+        state.last = Some(access);
+        state.record(TimelineEntry {
+            action,
+            vcpu_index,
+            pc: Some(pc),
+            protection,
+            icount,
+        });
+
+        if !broken || state.violations.contains(&kind) {
+            return None;
+        }
+
+        state.violations.push(kind);
+
+        Some(WxEvent {
+            vcpu_index,
+            kind,
+            addr: page,
+            size: PAGE_SIZE,
+            pc: Some(pc),
+            icount,
+        })
+    }
+
+    /// Record a store to `addr`, its virtual address in user mode and its physical address
+    /// in system mode, by the instruction at `pc`
+    pub fn on_write(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        pc: u64,
+        addr: u64,
+        icount: u64,
+    ) -> Option<WxEvent> {
+        self.on_access(vcpu_index, pc, addr, Access::Write, icount)
+    }
+
+    /// Record that a block at `pc` ran from the page containing `addr`, its virtual address
+    /// in user mode and its physical address in system mode
+    pub fn on_execute(
+        &mut self,
+        vcpu_index: VCPUIndex,
+        pc: u64,
+        addr: u64,
+        icount: u64,
+    ) -> Option<WxEvent> {
+        self.on_access(vcpu_index, pc, addr, Access::Execute, icount)
+    }
+
+    /// Whether pages are tracked by physical address
+    pub fn system(&self) -> bool {
+        self.system
+    }
+
+    pub fn report(&self) -> WxReport {
+        WxReport {
+            physical: self.system,
+            ranges: self.ranges.clone(),
+            pages: self
+                .pages
+                .iter()
+                .filter(|(_, state)| !state.violations.is_empty())
+                .map(|(page, state)| PageReport {
+                    page: *page,
+                    violations: state.violations.clone(),
+                    timeline: state.timeline.clone(),
+                    timeline_omitted: state.timeline_omitted,
+                })
+                .collect(),
+        }
+    }
+}
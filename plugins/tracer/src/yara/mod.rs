@@ -0,0 +1,580 @@
+//! YARA-style rules run over guest memory when a trigger fires
+//!
+//! Rules are written in a subset of the YARA language, evaluated by the tracer itself so
+//! that no YARA library needs to be installed alongside QEMU:
+//!
+//! ```text
+//! rule upx_packed : packer {
+//!     meta:
+//!         author = "analyst"
+//!     strings:
+//!         $magic = "UPX!"
+//!         $stub = { 60 be ?? ?? ?? ?? 8d be }
+//!         $name = "upx" nocase wide
+//!     condition:
+//!         $magic and ($stub or $name)
+//! }
+//! ```
+//!
+//! Strings are text, with the `ascii`, `wide` and `nocase` modifiers, or hex patterns in
+//! which `?` matches any nibble. Conditions combine string identifiers and `any of them`,
+//! `all of them` or `N of them` with `and`, `or`, `not` and parentheses. Anything else, such
+//! as regular expressions, jumps in hex patterns or modules, is rejected when the rules are
+//! loaded rather than silently ignored.
+//!
+//! When the guest reaches a configured pc, or makes a configured syscall, every configured
+//! range of guest virtual memory is read and scanned, and each matching rule is reported as
+//! an event with the address of each string match. Ranges which cannot be read at the time,
+//! such as unmapped ones, are skipped.
+
+use crate::{
+    dump::{DumpRange, DumpTrigger},
+    signatures::Signature,
+};
+use anyhow::{anyhow, Result};
+use qemu_plugin::{qemu_plugin_read_memory_vaddr, VCPUIndex};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs::read_to_string, path::Path};
+use typed_builder::TypedBuilder;
+
+/// The most matches recorded for each string in each scan
+const MAX_MATCHES: usize = 16;
+
+#[derive(Clone, Debug)]
+enum Pattern {
+    Text { bytes: Vec<u8>, nocase: bool },
+    Hex(Signature),
+}
+
+impl Pattern {
+    fn len(&self) -> usize {
+        match self {
+            Self::Text { bytes, .. } => bytes.len(),
+            Self::Hex(signature) => signature.bytes.len(),
+        }
+    }
+
+    fn matches(&self, window: &[u8]) -> bool {
+        match self {
+            Self::Text {
+                bytes,
+                nocase: true,
+            } => window.eq_ignore_ascii_case(bytes),
+            Self::Text {
+                bytes,
+                nocase: false,
+            } => window == bytes.as_slice(),
+            Self::Hex(signature) => window
+                .iter()
+                .zip(&signature.bytes)
+                .zip(&signature.mask)
+                .all(|((data, byte), mask)| data & mask == byte & mask),
+        }
+    }
+
+    /// Returns the offsets in `data` the pattern matches at, up to `limit`
+    fn find(&self, data: &[u8], limit: usize) -> Vec<usize> {
+        let len = self.len();
+
+        if len == 0 || data.len() < len {
+            return Vec::new();
+        }
+
+        data.windows(len)
+            .enumerate()
+            .filter_map(|(offset, window)| self.matches(window).then_some(offset))
+            .take(limit)
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Condition {
+    Any,
+    All,
+    Count(usize),
+    String(String),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    fn eval(&self, matched: &HashMap<&str, Vec<u64>>, strings: usize) -> bool {
+        let count = matched.values().filter(|m| !m.is_empty()).count();
+
+        match self {
+            Self::Any => count > 0,
+            Self::All => count == strings,
+            Self::Count(n) => count >= *n,
+            Self::String(id) => matched.get(id.as_str()).is_some_and(|m| !m.is_empty()),
+            Self::Not(c) => !c.eval(matched, strings),
+            Self::And(a, b) => a.eval(matched, strings) && b.eval(matched, strings),
+            Self::Or(a, b) => a.eval(matched, strings) || b.eval(matched, strings),
+        }
+    }
+
+    /// Returns the first string identifier the condition uses which is not in `ids`
+    fn undefined<'a>(&'a self, ids: &[&str]) -> Option<&'a str> {
+        match self {
+            Self::Any | Self::All | Self::Count(_) => None,
+            Self::String(id) => (!ids.contains(&id.as_str())).then_some(id.as_str()),
+            Self::Not(c) => c.undefined(ids),
+            Self::And(a, b) | Self::Or(a, b) => a.undefined(ids).or_else(|| b.undefined(ids)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A parsed rule
+pub struct Rule {
+    pub name: String,
+    pub tags: Vec<String>,
+    strings: Vec<(String, Pattern)>,
+    condition: Condition,
+}
+
+/// A cursor over rule source, with the primitives of the rule grammar
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.pos..]
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T> {
+        let line = self.source[..self.pos].matches('\n').count() + 1;
+
+        Err(anyhow!("YARA rules line {line}: {message}"))
+    }
+
+    /// Skip whitespace and comments
+    fn skip(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+
+            self.pos += rest.len() - trimmed.len();
+
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                self.pos += trimmed.find("*/").map_or(trimmed.len(), |end| end + 2);
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip();
+        self.rest().is_empty()
+    }
+
+    fn peek(&mut self, token: &str) -> bool {
+        self.skip();
+
+        let rest = self.rest();
+
+        // Keywords must not be the prefix of a longer identifier
+        rest.starts_with(token)
+            && !(token.chars().all(|c| c.is_alphanumeric())
+                && rest[token.len()..].starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.peek(token);
+
+        if found {
+            self.pos += token.len();
+        }
+
+        found
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            self.error(&format!("expected `{token}`"))
+        }
+    }
+
+    /// An identifier, optionally prefixed with `$`
+    fn ident(&mut self) -> Result<String> {
+        self.skip();
+
+        let rest = self.rest();
+        let len = rest
+            .char_indices()
+            .find(|(i, c)| !(c.is_alphanumeric() || *c == '_' || (*i == 0 && *c == '$')))
+            .map_or(rest.len(), |(i, _)| i);
+
+        if len == 0 {
+            return self.error("expected an identifier");
+        }
+
+        self.pos += len;
+
+        Ok(rest[..len].to_string())
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        self.skip();
+
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let number = rest[..len].parse().ok()?;
+
+        self.pos += len;
+
+        Some(number)
+    }
+
+    /// A double-quoted string, with YARA's escapes
+    fn text(&mut self) -> Result<Vec<u8>> {
+        self.expect("\"")?;
+
+        let mut bytes = Vec::new();
+        let mut chars = self.rest().char_indices();
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(bytes);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => bytes.push(b'\n'),
+                    Some('t') => bytes.push(b'\t'),
+                    Some('r') => bytes.push(b'\r'),
+                    Some('"') => bytes.push(b'"'),
+                    Some('\\') => bytes.push(b'\\'),
+                    Some('x') => {
+                        let hex = chars.by_ref().take(2).map(|(_, c)| c).collect::<String>();
+
+                        match u8::from_str_radix(&hex, 16) {
+                            Ok(byte) => bytes.push(byte),
+                            Err(_) => return self.error("invalid \\x escape"),
+                        }
+                    }
+                    _ => return self.error("invalid escape"),
+                },
+                c => bytes.extend(c.to_string().as_bytes()),
+            }
+        }
+
+        self.error("unterminated string")
+    }
+
+    fn pattern(&mut self, id: &str) -> Result<Pattern> {
+        if self.eat("{") {
+            let rest = self.rest();
+            let Some(end) = rest.find('}') else {
+                return self.error("unterminated hex string");
+            };
+            let hex = &rest[..end];
+
+            if hex.contains(['[', '(', '|', '~']) {
+                return self.error("jumps, alternatives and negations are not supported");
+            }
+
+            self.pos += end + 1;
+
+            return Ok(Pattern::Hex(Signature::new(id.to_string(), hex)?));
+        }
+
+        if self.peek("/") {
+            return self.error("regular expressions are not supported");
+        }
+
+        let text = self.text()?;
+        let (mut ascii, mut wide, mut nocase) = (false, false, false);
+
+        loop {
+            if self.eat("ascii") {
+                ascii = true;
+            } else if self.eat("wide") {
+                wide = true;
+            } else if self.eat("nocase") {
+                nocase = true;
+            } else {
+                break;
+            }
+        }
+
+        if ascii && wide {
+            return self.error("strings matching both ascii and wide are not supported");
+        }
+
+        let bytes = if wide {
+            text.iter().flat_map(|b| [*b, 0]).collect()
+        } else {
+            text
+        };
+
+        Ok(Pattern::Text { bytes, nocase })
+    }
+
+    fn primary(&mut self) -> Result<Condition> {
+        if self.eat("not") {
+            return Ok(Condition::Not(Box::new(self.primary()?)));
+        }
+
+        if self.eat("(") {
+            let condition = self.condition()?;
+
+            self.expect(")")?;
+
+            return Ok(condition);
+        }
+
+        let quantity = if self.eat("any") {
+            Condition::Any
+        } else if self.eat("all") {
+            Condition::All
+        } else if let Some(n) = self.number() {
+            Condition::Count(n)
+        } else {
+            let id = self.ident()?;
+
+            if !id.starts_with('$') {
+                return self.error(&format!("unsupported condition `{id}`"));
+            }
+
+            return Ok(Condition::String(id));
+        };
+
+        self.expect("of")?;
+        self.expect("them")?;
+
+        Ok(quantity)
+    }
+
+    fn conjunction(&mut self) -> Result<Condition> {
+        let mut condition = self.primary()?;
+
+        while self.eat("and") {
+            condition = Condition::And(Box::new(condition), Box::new(self.primary()?));
+        }
+
+        Ok(condition)
+    }
+
+    fn condition(&mut self) -> Result<Condition> {
+        let mut condition = self.conjunction()?;
+
+        while self.eat("or") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.conjunction()?));
+        }
+
+        Ok(condition)
+    }
+
+    fn rule(&mut self) -> Result<Rule> {
+        while self.eat("private") || self.eat("global") {}
+
+        self.expect("rule")?;
+
+        let name = self.ident()?;
+        let mut tags = Vec::new();
+
+        if self.eat(":") {
+            while !self.peek("{") {
+                tags.push(self.ident()?);
+            }
+        }
+
+        self.expect("{")?;
+
+        if self.eat("meta") {
+            self.expect(":")?;
+
+            while !self.peek("strings") && !self.peek("condition") {
+                self.ident()?;
+                self.expect("=")?;
+
+                if self.peek("\"") {
+                    self.text()?;
+                } else if self.number().is_none() && !self.eat("true") && !self.eat("false") {
+                    return self.error("invalid meta value");
+                }
+            }
+        }
+
+        let mut strings = Vec::new();
+
+        if self.eat("strings") {
+            self.expect(":")?;
+
+            while self.peek("$") {
+                let id = self.ident()?;
+
+                self.expect("=")?;
+                strings.push((id.clone(), self.pattern(&id)?));
+            }
+        }
+
+        self.expect("condition")?;
+        self.expect(":")?;
+
+        let condition = self.condition()?;
+        let ids = strings
+            .iter()
+            .map(|(id, _)| id.as_str())
+            .collect::<Vec<_>>();
+
+        if let Some(id) = condition.undefined(&ids) {
+            return self.error(&format!("undefined string {id} in rule {name}"));
+        }
+
+        self.expect("}")?;
+
+        Ok(Rule {
+            name,
+            tags,
+            strings,
+            condition,
+        })
+    }
+}
+
+/// Parse rules from source in the supported subset of the YARA language
+pub fn parse_rules(source: &str) -> Result<Vec<Rule>> {
+    let mut parser = Parser { source, pos: 0 };
+    let mut rules = Vec::new();
+
+    while !parser.at_end() {
+        if parser.peek("import") || parser.peek("include") {
+            return parser.error("imports and includes are not supported");
+        }
+
+        rules.push(parser.rule()?);
+    }
+
+    Ok(rules)
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Where a string of a rule matched
+pub struct YaraMatch {
+    pub string: String,
+    pub address: u64,
+}
+
+#[derive(TypedBuilder, Clone, Debug, Deserialize, Serialize)]
+/// A rule which matched guest memory
+pub struct YaraEvent {
+    pub vcpu_index: VCPUIndex,
+    pub rule: String,
+    pub tags: Vec<String>,
+    pub trigger: DumpTrigger,
+    pub matches: Vec<YaraMatch>,
+    pub icount: u64,
+}
+
+#[derive(TypedBuilder, Clone, Debug)]
+/// The rules to run, the memory to run them over, and when to run them
+pub struct YaraConfig {
+    pub rules: Vec<Rule>,
+    pub ranges: Vec<DumpRange>,
+    #[builder(default)]
+    pub pcs: Vec<u64>,
+    #[builder(default)]
+    pub syscall: Option<i64>,
+}
+
+#[derive(Debug)]
+/// Scans guest memory with YARA rules when a trigger fires
+pub struct YaraScanner {
+    config: YaraConfig,
+}
+
+impl YaraScanner {
+    pub fn new(config: YaraConfig) -> Self {
+        Self { config }
+    }
+
+    /// Load rules from a file
+    pub fn load_rules<P>(path: P) -> Result<Vec<Rule>>
+    where
+        P: AsRef<Path>,
+    {
+        parse_rules(&read_to_string(path)?)
+    }
+
+    /// Whether executing the instruction at `pc` triggers a scan
+    pub fn wants_pc(&self, pc: u64) -> bool {
+        self.config.pcs.contains(&pc)
+    }
+
+    /// Returns the trigger fired by a syscall, if any
+    pub fn on_syscall(&self, num: i64) -> Option<DumpTrigger> {
+        (self.config.syscall == Some(num)).then_some(DumpTrigger::Syscall(num))
+    }
+
+    /// Scan the configured ranges, returning an event for each matching rule
+    pub fn scan(&self, vcpu_index: VCPUIndex, trigger: DumpTrigger, icount: u64) -> Vec<YaraEvent> {
+        let memory = self
+            .config
+            .ranges
+            .iter()
+            .filter_map(|range| {
+                qemu_plugin_read_memory_vaddr(range.start, (range.end - range.start) as usize)
+                    .ok()
+                    .map(|data| (range.start, data))
+            })
+            .collect::<Vec<_>>();
+
+        self.config
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                let matched = rule
+                    .strings
+                    .iter()
+                    .map(|(id, pattern)| {
+                        let addresses = memory
+                            .iter()
+                            .flat_map(|(start, data)| {
+                                pattern
+                                    .find(data, MAX_MATCHES)
+                                    .into_iter()
+                                    .map(move |offset| start + offset as u64)
+                            })
+                            .take(MAX_MATCHES)
+                            .collect::<Vec<_>>();
+
+                        (id.as_str(), addresses)
+                    })
+                    .collect::<HashMap<_, _>>();
+
+                rule.condition.eval(&matched, rule.strings.len()).then(|| {
+                    YaraEvent::builder()
+                        .vcpu_index(vcpu_index)
+                        .rule(rule.name.clone())
+                        .tags(rule.tags.clone())
+                        .trigger(trigger)
+                        .matches(
+                            rule.strings
+                                .iter()
+                                .flat_map(|(id, _)| {
+                                    matched[id.as_str()].iter().map(|address| YaraMatch {
+                                        string: id.clone(),
+                                        address: *address,
+                                    })
+                                })
+                                .collect(),
+                        )
+                        .icount(icount)
+                        .build()
+                })
+            })
+            .collect()
+    }
+}
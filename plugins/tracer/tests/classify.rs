@@ -0,0 +1,241 @@
+//! Cross-checks of the classifiers in `tracer::arch` against capstone, for random encodings
+//! which capstone accepts as valid. Capstone is the disassembler QEMU uses for these
+//! targets, so its mnemonics are what the text classifiers see at runtime.
+
+#![cfg(feature = "classifier-tests")]
+
+use capstone::{arch, prelude::*, InsnGroupType};
+use proptest::prelude::*;
+use tracer::arch::{Arch, Branch, Exclusive};
+
+fn aarch64() -> Capstone {
+    Capstone::new()
+        .arm64()
+        .mode(arch::arm64::ArchMode::Arm)
+        .detail(true)
+        .build()
+        .expect("Failed to create capstone")
+}
+
+fn arm(mode: arch::arm::ArchMode) -> Capstone {
+    Capstone::new()
+        .arm()
+        .mode(mode)
+        .extra_mode([arch::arm::ArchExtraMode::V8].iter().copied())
+        .detail(true)
+        .build()
+        .expect("Failed to create capstone")
+}
+
+fn x86_64() -> Capstone {
+    Capstone::new()
+        .x86()
+        .mode(arch::x86::ArchMode::Mode64)
+        .detail(true)
+        .build()
+        .expect("Failed to create capstone")
+}
+
+/// An instruction as capstone decodes it
+struct Decoded {
+    mnemonic: String,
+    operands: String,
+    groups: Vec<u32>,
+}
+
+impl Decoded {
+    /// Decode the first instruction of `code`, if it is valid and `len` bytes long
+    fn new(cs: &Capstone, code: &[u8], len: Option<usize>) -> Option<Self> {
+        let insns = cs.disasm_count(code, 0x1000, 1).ok()?;
+        let insn = insns.iter().next()?;
+
+        if len.is_some_and(|len| insn.bytes().len() != len) {
+            return None;
+        }
+
+        Some(Self {
+            mnemonic: insn.mnemonic().unwrap_or_default().to_string(),
+            operands: insn.op_str().unwrap_or_default().to_string(),
+            groups: cs
+                .insn_detail(&insn)
+                .map(|detail| detail.groups().map(|g| g.0 as u32).collect())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// The disassembly, as QEMU passes it to the tracer
+    fn disas(&self) -> String {
+        format!("{} {}", self.mnemonic, self.operands)
+    }
+
+    fn has_group(&self, group: InsnGroupType::Type) -> bool {
+        self.groups.contains(&group)
+    }
+
+    /// The number of the register named by the first operand
+    fn first_register(&self) -> u8 {
+        let operand = self.operands.split(',').next().unwrap_or_default().trim();
+
+        match operand {
+            "wzr" | "xzr" => 31,
+            "sb" => 9,
+            "sl" => 10,
+            "fp" => 11,
+            "ip" => 12,
+            "sp" => 13,
+            "lr" => 14,
+            "pc" => 15,
+            _ => operand[1..]
+                .parse()
+                .unwrap_or_else(|_| panic!("Unexpected register {operand}")),
+        }
+    }
+
+    /// The call or return capstone groups the instruction in
+    fn branch(&self) -> Option<Branch> {
+        if self.has_group(InsnGroupType::CS_GRP_CALL) {
+            Some(Branch::Call)
+        } else if self.has_group(InsnGroupType::CS_GRP_RET) {
+            Some(Branch::Return)
+        } else {
+            None
+        }
+    }
+}
+
+/// The exclusive classification capstone implies, from the mnemonic without its condition
+/// and width suffixes
+fn expected_exclusive(insn: &Decoded, loads: &[&str], stores: &[&str]) -> Option<Exclusive> {
+    let mnemonic = insn.mnemonic.split('.').next().unwrap_or_default();
+    let matches = |bases: &[&str]| {
+        bases.iter().any(|base| {
+            mnemonic.strip_prefix(base).is_some_and(|suffix| {
+                ["", "b", "h", "d", "p"]
+                    .iter()
+                    .flat_map(|width| CONDITIONS.iter().map(move |cond| (width, cond)))
+                    .any(|(width, cond)| suffix == format!("{width}{cond}"))
+            })
+        })
+    };
+
+    if mnemonic == "clrex" {
+        Some(Exclusive::Clear)
+    } else if matches(loads) {
+        Some(Exclusive::Load)
+    } else if matches(stores) {
+        Some(Exclusive::Store {
+            status: insn.first_register(),
+        })
+    } else {
+        None
+    }
+}
+
+const CONDITIONS: &[&str] = &[
+    "", "eq", "ne", "hs", "lo", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le", "al",
+];
+
+/// Words in the AArch64 load/store exclusive class half the time, and anywhere otherwise
+fn aarch64_words() -> impl Strategy<Value = u32> {
+    prop_oneof![
+        any::<u32>(),
+        any::<u32>().prop_map(|w| (w & !0x3f80_0000) | 0x0800_0000),
+    ]
+}
+
+/// A32 words in the synchronization primitive space half the time
+fn a32_words() -> impl Strategy<Value = u32> {
+    prop_oneof![
+        any::<u32>(),
+        any::<u32>().prop_map(|w| (w & !0x0f80_0cf0) | 0x0180_0c90),
+    ]
+}
+
+/// Thumb-2 words in the load/store exclusive space half the time
+fn thumb_words() -> impl Strategy<Value = u32> {
+    prop_oneof![
+        any::<u32>(),
+        any::<u32>().prop_map(|w| (w & !0xff80) | 0xe840),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(20_000))]
+
+    #[test]
+    fn aarch64_exclusive(word in aarch64_words()) {
+        let cs = aarch64();
+        let code = word.to_le_bytes();
+
+        if let Some(insn) = Decoded::new(&cs, &code, Some(4)) {
+            let expected = expected_exclusive(
+                &insn,
+                &["ldxr", "ldaxr", "ldxp", "ldaxp"],
+                &["stxr", "stlxr", "stxp", "stlxp"],
+            );
+
+            prop_assert_eq!(Arch::Aarch64.exclusive(&code, &insn.disas()), expected, "{}", insn.disas());
+        }
+    }
+
+    #[test]
+    fn a32_exclusive(word in a32_words()) {
+        let cs = arm(arch::arm::ArchMode::Arm);
+        let code = word.to_le_bytes();
+
+        if let Some(insn) = Decoded::new(&cs, &code, Some(4)) {
+            let expected = expected_exclusive(&insn, &["ldrex", "ldaex"], &["strex", "stlex"]);
+
+            prop_assert_eq!(Arch::Arm.exclusive(&code, &insn.disas()), expected, "{}", insn.disas());
+        }
+    }
+
+    #[test]
+    fn thumb_exclusive(word in thumb_words()) {
+        let cs = arm(arch::arm::ArchMode::Thumb);
+        let code = word.to_le_bytes();
+
+        if let Some(insn) = Decoded::new(&cs, &code, Some(4)) {
+            let expected = expected_exclusive(&insn, &["ldrex", "ldaex"], &["strex", "stlex"]);
+
+            prop_assert_eq!(Arch::Arm.exclusive(&code, &insn.disas()), expected, "{}", insn.disas());
+        }
+    }
+
+    #[test]
+    fn x86_64_branch(code in proptest::collection::vec(any::<u8>(), 15)) {
+        let cs = x86_64();
+
+        if let Some(insn) = Decoded::new(&cs, &code, None) {
+            prop_assert_eq!(Arch::X86_64.branch(&insn.disas()), insn.branch(), "{}", insn.disas());
+        }
+    }
+
+    #[test]
+    fn aarch64_branch(word in any::<u32>()) {
+        let cs = aarch64();
+        let code = word.to_le_bytes();
+
+        if let Some(insn) = Decoded::new(&cs, &code, Some(4)) {
+            prop_assert_eq!(Arch::Aarch64.branch(&insn.disas()), insn.branch(), "{}", insn.disas());
+        }
+    }
+
+    #[test]
+    fn a32_call(word in any::<u32>()) {
+        let cs = arm(arch::arm::ArchMode::Arm);
+        let code = word.to_le_bytes();
+
+        // Capstone has no return group for ARM, so only calls are checked
+        if let Some(insn) = Decoded::new(&cs, &code, Some(4)) {
+            let call = insn.has_group(InsnGroupType::CS_GRP_CALL);
+
+            prop_assert_eq!(
+                Arch::Arm.branch(&insn.disas()) == Some(Branch::Call),
+                call,
+                "{}",
+                insn.disas()
+            );
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Golden traces of a fixed guest under each plugin API version. The tracer is run on
+//! `golden/guest-x86_64`, which does the same thing on every run, and its trace is compared
+//! against `golden/guest.v<N>.jsonl` once the fields which vary between hosts and runs are
+//! removed. Requires `qemu-x86_64` built with plugin support on the path.
+//!
+//! ```sh
+//! cargo build -r -p tracer --no-default-features --features plugin-api-v4
+//! cargo test -r -p tracer --no-default-features --features plugin-api-v4,golden-tests --test golden
+//! ```
+//!
+//! A change to the trace which is intended is accepted by running the test again with
+//! `UPDATE_GOLDEN=1`, which rewrites the golden file of the API version under test. A missing
+//! golden file fails the test rather than passing it unchecked, so it is recorded the same
+//! way.
+
+#![cfg(feature = "golden-tests")]
+
+use serde_json::Value;
+use std::{
+    env::var_os,
+    fs::{read_to_string, remove_file, write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+#[cfg(feature = "plugin-api-v1")]
+const VERSION: u32 = 1;
+#[cfg(feature = "plugin-api-v2")]
+const VERSION: u32 = 2;
+#[cfg(feature = "plugin-api-v3")]
+const VERSION: u32 = 3;
+#[cfg(feature = "plugin-api-v4")]
+const VERSION: u32 = 4;
+
+/// Fields holding host addresses or guest registers, which depend on where QEMU and the
+/// guest stack were mapped
+const UNSTABLE_FIELDS: &[&str] = &["haddr", "registers"];
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+}
+
+/// Remove the unstable fields from an event, at any depth
+fn normalize(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|key, _| !UNSTABLE_FIELDS.contains(&key.as_str()));
+            map.values_mut().for_each(normalize);
+        }
+        Value::Array(values) => values.iter_mut().for_each(normalize),
+        _ => {}
+    }
+}
+
+/// Run the tracer on the guest and return its normalized trace, one event per line
+fn trace(guest: &str, flags: &[&str]) -> String {
+    let output = std::env::temp_dir().join(format!(
+        "tracer-golden-{guest}-v{VERSION}-{}.jsonl",
+        std::process::id()
+    ));
+    let _ = remove_file(&output);
+
+    // The tracer waits for the plugin to connect, so a missing QEMU would hang the test
+    assert!(
+        Command::new("qemu-x86_64")
+            .arg("--version")
+            .output()
+            .is_ok_and(|output| output.status.success()),
+        "qemu-x86_64 is not on the path"
+    );
+
+    let status = Command::new(env!("CARGO_BIN_EXE_tracer"))
+        .args(flags)
+        .arg("-O")
+        .arg(&output)
+        .arg(golden_dir().join(format!("{guest}-x86_64")))
+        .status()
+        .expect("Failed to run tracer");
+
+    assert!(status.success(), "Tracer exited with {status}");
+
+    let trace = read_to_string(&output).expect("Failed to read trace");
+    let _ = remove_file(&output);
+
+    trace
+        .lines()
+        .map(|line| {
+            let mut event = serde_json::from_str::<Value>(line).expect("Failed to parse event");
+            normalize(&mut event);
+            serde_json::to_string(&event).expect("Failed to serialize event") + "\n"
+        })
+        .collect()
+}
+
+/// Compare a trace against its golden file, or rewrite the golden file with `UPDATE_GOLDEN`
+fn check(guest: &str, trace: &str) {
+    let golden = golden_dir().join(format!("{guest}.v{VERSION}.jsonl"));
+
+    if var_os("UPDATE_GOLDEN").is_some() {
+        write(&golden, trace).expect("Failed to write golden trace");
+        return;
+    }
+
+    let expected = read_to_string(&golden).unwrap_or_else(|e| {
+        panic!(
+            "No golden trace at {} ({e}), run with UPDATE_GOLDEN=1 to record it",
+            golden.display()
+        )
+    });
+
+    if let Some((line, (expected, actual))) = expected
+        .lines()
+        .zip(trace.lines())
+        .enumerate()
+        .find(|(_, (expected, actual))| expected != actual)
+    {
+        panic!(
+            "Trace differs from {} at line {}\nexpected: {expected}\n  actual: {actual}",
+            golden.display(),
+            line + 1
+        );
+    }
+
+    assert_eq!(
+        expected.lines().count(),
+        trace.lines().count(),
+        "Trace has a different number of events than {}",
+        golden.display()
+    );
+}
+
+#[test]
+fn guest() {
+    check("guest", &trace("guest", &["-i", "-s", "-c"]));
+}
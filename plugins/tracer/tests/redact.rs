@@ -0,0 +1,86 @@
+//! The default redaction policy strips the guest data carried by each kind of event
+
+#![cfg(feature = "plugin-api-v4")]
+
+use serde_cbor::{from_slice, to_vec, value::from_value, Value};
+use tracer::{
+    coalesce::{CoalesceMode, MemoryRangeEvent},
+    hypercall::{Hypercall, HypercallEvent},
+    redact::RedactionPolicy,
+    Event, MemoryEvent,
+};
+
+/// Redact an event with the default policy, as it is encoded in a trace
+fn redact(event: &Event) -> Event {
+    let mut record = from_slice::<Value>(&to_vec(event).expect("Failed to encode event"))
+        .expect("Failed to decode event");
+
+    RedactionPolicy::default().redact(&mut record);
+    from_value(record).expect("Failed to decode redacted event")
+}
+
+fn store(value: u128) -> MemoryEvent {
+    MemoryEvent::builder()
+        .vaddr(0x1000)
+        .haddr(None)
+        .haddr_is_io(None)
+        .haddr_device_name(None)
+        .size_shift(3)
+        .size_bytes(8)
+        .sign_extended(false)
+        .is_store(true)
+        .big_endian(false)
+        .value(Some(value))
+        .build()
+}
+
+#[test]
+fn memory_value() {
+    let Event::Memory(event) = redact(&Event::Memory(store(0x4142_4344))) else {
+        panic!("Redaction changed the event's variant");
+    };
+
+    assert_eq!(event.value, Some(0));
+    assert_eq!(event.vaddr, 0x1000);
+}
+
+#[test]
+fn memory_range_values() {
+    let event = MemoryRangeEvent::builder()
+        .pc(0x400000)
+        .first(store(0x4142_4344))
+        .count(3)
+        .mode(CoalesceMode::Lossless)
+        .values(vec![0x4546_4748, 0x494a_4b4c])
+        .build();
+    let Event::MemoryRange(event) = redact(&Event::MemoryRange(event)) else {
+        panic!("Redaction changed the event's variant");
+    };
+
+    assert_eq!(event.first.value, Some(0));
+    assert!(event.values.is_empty());
+    assert_eq!(event.count, 3);
+}
+
+#[test]
+fn hypercall_data() {
+    let event = HypercallEvent::builder()
+        .vcpu_index(0)
+        .hypercall(Hypercall::Message {
+            kind: 7,
+            data: b"secret".to_vec(),
+        })
+        .icount(0)
+        .build();
+    let Event::Hypercall(event) = redact(&Event::Hypercall(event)) else {
+        panic!("Redaction changed the event's variant");
+    };
+
+    assert_eq!(
+        event.hypercall,
+        Hypercall::Message {
+            kind: 7,
+            data: Vec::new()
+        }
+    );
+}
@@ -0,0 +1,87 @@
+//! Interleavings of the lock-free cells in `tracer::sync`, explored by loom when built with
+//! `--cfg tracer_loom`, or run once on real threads under miri
+
+#![cfg(any(tracer_loom, miri))]
+
+#[cfg(tracer_loom)]
+use loom::{model, sync::Arc, thread};
+#[cfg(not(tracer_loom))]
+use std::{sync::Arc, thread};
+use tracer::sync::{Latch, Quota};
+
+#[cfg(not(tracer_loom))]
+fn model<F>(f: F)
+where
+    F: Fn(),
+{
+    f()
+}
+
+#[test]
+fn latch_trips_once() {
+    model(|| {
+        let latch = Arc::new(Latch::new());
+        let threads = (0..2)
+            .map(|_| {
+                let latch = latch.clone();
+                thread::spawn(move || latch.trip())
+            })
+            .collect::<Vec<_>>();
+        let tripped = threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .filter(|tripped| *tripped)
+            .count();
+
+        assert_eq!(tripped, 1);
+        assert!(latch.is_tripped());
+        assert!(!latch.trip());
+    });
+}
+
+#[test]
+fn quota_admits_limit() {
+    model(|| {
+        let quota = Arc::new(Quota::new(2));
+        let threads = (0..3)
+            .map(|_| {
+                let quota = quota.clone();
+                thread::spawn(move || quota.take())
+            })
+            .collect::<Vec<_>>();
+        let admitted = threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .filter(|admitted| *admitted)
+            .count();
+
+        assert_eq!(admitted, 2);
+        assert!(quota.is_exhausted());
+        assert!(quota.is_within());
+    });
+}
+
+#[test]
+fn quota_ticks_past_limit() {
+    model(|| {
+        let quota = Arc::new(Quota::new(1));
+        let threads = (0..2)
+            .map(|_| {
+                let quota = quota.clone();
+                thread::spawn(move || {
+                    quota.tick();
+                    quota.is_within()
+                })
+            })
+            .collect::<Vec<_>>();
+        let within = threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .filter(|within| *within)
+            .count();
+
+        // The first tick is within the limit, but a thread may observe both
+        assert!(within <= 1);
+        assert!(!quota.is_within());
+    });
+}
@@ -0,0 +1,99 @@
+//! Fixtures of the per-target classifiers in `tracer::arch`, for targets without a capstone
+//! cross-check or a golden trace. Each `targets/<target>.txt` is named for QEMU's target name
+//! and holds one check per line, blank lines and `#` comments aside:
+//!
+//! ```text
+//! branch  <call|return|none>  <disassembly>
+//! atomic  <yes|no>            <disassembly>
+//! crypto  <class|none>        <disassembly>
+//! syscall <name>              <number>
+//! insns   <count>             <little-endian words>
+//! ```
+//!
+//! Classes and syscalls are named in lower case, such as `aes` or `exitgroup`. `\n` and `\t`
+//! in disassembly stand for the newlines and tabs QEMU prints in multi-line packets.
+
+use std::{
+    fmt::Debug,
+    fs::{read_dir, read_to_string},
+    path::Path,
+};
+use tracer::arch::Arch;
+
+/// The lower-cased variant name of a classification, or `none`
+fn name<T: Debug>(value: Option<T>) -> String {
+    value
+        .map(|value| format!("{value:?}").to_lowercase())
+        .unwrap_or_else(|| "none".to_string())
+}
+
+/// Run every check of a fixture, returning a description of each which failed
+fn check(arch: Arch, fixture: &str) -> Vec<String> {
+    fixture
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let Some((kind, expected, input)) =
+                line.split_once(char::is_whitespace)
+                    .and_then(|(kind, rest)| {
+                        let (expected, input) =
+                            rest.trim_start().split_once(char::is_whitespace)?;
+                        Some((kind, expected, input.trim()))
+                    })
+            else {
+                return Some(format!("{arch:?}: malformed check {line}"));
+            };
+
+            let input = input.replace("\\n", "\n").replace("\\t", "\t");
+            let input = input.as_str();
+            let actual = match kind {
+                "branch" => name(arch.branch(input)),
+                "atomic" => if arch.is_atomic(input) { "yes" } else { "no" }.to_string(),
+                "crypto" => name(arch.crypto(input)),
+                "syscall" => input
+                    .parse()
+                    .map(|num| name(arch.syscall(num)))
+                    .unwrap_or_else(|_| format!("bad number {input}")),
+                "insns" => input
+                    .split_whitespace()
+                    .map(|word| u32::from_str_radix(word.trim_start_matches("0x"), 16))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|words| {
+                        let data = words
+                            .iter()
+                            .flat_map(|w| w.to_le_bytes())
+                            .collect::<Vec<_>>();
+                        arch.instruction_count(&data).to_string()
+                    })
+                    .unwrap_or_else(|_| format!("bad words {input}")),
+                _ => format!("unknown check {kind}"),
+            };
+
+            (actual != expected).then(|| format!("{arch:?}: {line}: got {actual}"))
+        })
+        .collect()
+}
+
+#[test]
+fn fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("targets");
+    let mut failures = Vec::new();
+
+    for entry in read_dir(&dir).expect("Failed to read fixtures") {
+        let path = entry.expect("Failed to read fixture").path();
+        let target = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .expect("Fixture names are UTF-8");
+        let arch = Arch::from_target_name(target)
+            .unwrap_or_else(|| panic!("No architecture for target {target}"));
+        let fixture = read_to_string(&path).expect("Failed to read fixture");
+
+        failures.extend(check(arch, &fixture));
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
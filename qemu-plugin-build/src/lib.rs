@@ -0,0 +1,125 @@
+//! Link settings for QEMU plugin crates, applied from their build script
+//!
+//! QEMU opens plugins with `dlopen`, which maps and relocates the whole library. Plugins built
+//! with debug info, including release builds of crates which enable it, are often tens of
+//! megabytes and slow QEMU's startup and deployment images. [`Build::emit`] passes link
+//! arguments for the crate's `cdylib` which strip the plugin as it is linked, or compress
+//! the debug info it keeps:
+//!
+//! ```rust,ignore
+//! // build.rs
+//! fn main() {
+//!     qemu_plugin_build::Build::new().emit();
+//! }
+//! ```
+//!
+//! A Rust `cdylib` already exports only its `#[no_mangle]` items. For a plugin these are
+//! `qemu_plugin_install`, `qemu_plugin_version` and, with the `unix-weak-link` feature of
+//! `qemu-plugin`, the weak definitions of QEMU's API, which must stay exported so that QEMU's
+//! own definitions take precedence when the plugin is loaded. Stripping removes the static
+//! symbol table and debug info, never these exports.
+//!
+//! LTO and codegen units cannot be changed from a build script and belong in the crate's
+//! release profile. [`Build::emit`] warns about release builds which carry debug info
+//! without stripping or compressing it:
+//!
+//! ```toml
+//! [profile.release]
+//! lto = true
+//! codegen-units = 1
+//! strip = true
+//! ```
+//!
+//! Link arguments are only passed when targeting Linux, whose linkers all accept them.
+
+#![deny(missing_docs)]
+
+use std::env::var;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// What the linker strips from the plugin
+pub enum Strip {
+    /// Keep everything
+    None,
+    /// Strip debug info, keeping the symbol table so that backtraces name functions
+    Debug,
+    /// Strip debug info and the symbol table
+    All,
+}
+
+#[derive(Clone, Debug, Default)]
+/// Link settings for a plugin crate
+pub struct Build {
+    strip: Option<Strip>,
+    compress_debug: bool,
+}
+
+impl Build {
+    /// Settings which strip debug info from release builds and leave debug builds as they are
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Strip the plugin as given, in every profile
+    pub fn strip(mut self, strip: Strip) -> Self {
+        self.strip = Some(strip);
+        self
+    }
+
+    /// Compress the debug info the plugin keeps, which shrinks it on disk without losing
+    /// debug info, at the cost of decompressing it when a debugger reads it
+    pub fn compress_debug(mut self, compress_debug: bool) -> Self {
+        self.compress_debug = compress_debug;
+        self
+    }
+
+    /// The strip setting for the profile being built
+    fn effective_strip(&self, release: bool) -> Strip {
+        self.strip
+            .unwrap_or(if release { Strip::Debug } else { Strip::None })
+    }
+
+    /// The link arguments for a profile, before any are emitted
+    pub fn link_args(&self, release: bool) -> Vec<&'static str> {
+        let mut args = Vec::new();
+
+        match self.effective_strip(release) {
+            Strip::None if self.compress_debug => {
+                args.push("-Wl,--compress-debug-sections=zlib");
+            }
+            Strip::None => {}
+            Strip::Debug => args.push("-Wl,--strip-debug"),
+            Strip::All => args.push("-Wl,--strip-all"),
+        }
+
+        args
+    }
+
+    /// Emit the settings to cargo. Must be called from a build script.
+    pub fn emit(&self) {
+        println!("cargo:rerun-if-changed=build.rs");
+
+        if var("CARGO_CFG_TARGET_OS").as_deref() != Ok("linux") {
+            return;
+        }
+
+        // Cargo sets this to `release` for every profile which inherits from `release`
+        let release = var("PROFILE").as_deref() == Ok("release");
+
+        for arg in self.link_args(release) {
+            println!("cargo:rustc-cdylib-link-arg={arg}");
+        }
+
+        if release
+            && var("DEBUG").is_ok_and(|debug| debug != "false" && debug != "0")
+            && self.effective_strip(release) == Strip::None
+            && !self.compress_debug
+        {
+            println!(
+                "cargo:warning=This release build of a QEMU plugin keeps its debug info \
+                 uncompressed, which makes it slower to load. Strip it, or compress it with \
+                 `Build::compress_debug`."
+            );
+        }
+    }
+}
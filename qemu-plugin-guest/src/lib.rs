@@ -0,0 +1,172 @@
+//! Hypercalls for guest programs to signal QEMU plugins in-band
+//!
+//! A hypercall is a syscall with number [`SYSCALL`], which no kernel implements, so it fails
+//! with `ENOSYS` and has no effect unless a plugin watching syscalls decodes it. The first
+//! argument is the [`Op`], and the rest are its arguments. Strings and data are passed as a
+//! pointer and a length, and plugins read at most [`MAX_LEN`] bytes of them.
+//!
+//! The crate has no dependencies and does not use `std`, so it can be linked into firmware
+//! and test harnesses as well as ordinary programs. Hypercalls are made on x86_64, AArch64
+//! and RISC-V 64. On other architectures they do nothing.
+//!
+//! ```rust,no_run
+//! use qemu_plugin_guest::{bookmark, coverage_hint, message};
+//!
+//! bookmark("parsed header");
+//! coverage_hint(0x10);
+//! message(1, b"checksum ok");
+//! ```
+
+#![no_std]
+
+/// The syscall number hypercalls are made with
+pub const SYSCALL: u64 = 0x5152;
+
+/// The most bytes of a string or message which plugins read
+pub const MAX_LEN: usize = 4096;
+
+/// The error number hypercalls fail with
+pub const ENOSYS: i64 = 38;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u64)]
+/// The operations a hypercall can request
+pub enum Op {
+    /// Log a named bookmark. The arguments are a pointer to the name and its length, then
+    /// a pointer to a note and its length, both zero if there is no note.
+    Bookmark = 1,
+    /// Record that the guest reached a point it identifies by number, such as a state of a
+    /// parser, as coverage. The argument is the number.
+    Coverage = 2,
+    /// Log a message. The arguments are a kind, whose meaning is up to the program, and a
+    /// pointer to the message's data and its length.
+    Message = 3,
+}
+
+impl Op {
+    /// Returns the operation with number `op`, if there is one
+    pub fn from_u64(op: u64) -> Option<Self> {
+        match op {
+            1 => Some(Self::Bookmark),
+            2 => Some(Self::Coverage),
+            3 => Some(Self::Message),
+            _ => None,
+        }
+    }
+}
+
+/// Make a hypercall, returning the syscall's result, which is `-ENOSYS` whether or not a
+/// plugin decoded it
+#[inline(always)]
+pub fn hypercall(op: Op, args: [u64; 4]) -> i64 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        let ret: i64;
+
+        // SAFETY: No kernel implements the syscall, so it only returns an error
+        unsafe {
+            core::arch::asm!(
+                "syscall",
+                inlateout("rax") SYSCALL as i64 => ret,
+                in("rdi") op as u64,
+                in("rsi") args[0],
+                in("rdx") args[1],
+                in("r10") args[2],
+                in("r8") args[3],
+                lateout("rcx") _,
+                lateout("r11") _,
+                options(nostack),
+            );
+        }
+
+        ret
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        let ret: i64;
+
+        // SAFETY: No kernel implements the syscall, so it only returns an error
+        unsafe {
+            core::arch::asm!(
+                "svc 0",
+                in("x8") SYSCALL,
+                inlateout("x0") op as u64 => ret,
+                in("x1") args[0],
+                in("x2") args[1],
+                in("x3") args[2],
+                in("x4") args[3],
+                options(nostack),
+            );
+        }
+
+        ret
+    }
+
+    #[cfg(target_arch = "riscv64")]
+    {
+        let ret: i64;
+
+        // SAFETY: No kernel implements the syscall, so it only returns an error
+        unsafe {
+            core::arch::asm!(
+                "ecall",
+                in("a7") SYSCALL,
+                inlateout("a0") op as u64 => ret,
+                in("a1") args[0],
+                in("a2") args[1],
+                in("a3") args[2],
+                in("a4") args[3],
+                options(nostack),
+            );
+        }
+
+        ret
+    }
+
+    #[cfg(not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )))]
+    {
+        let _ = (op, args);
+
+        -ENOSYS
+    }
+}
+
+/// Log a bookmark named `name`
+pub fn bookmark(name: &str) {
+    hypercall(
+        Op::Bookmark,
+        [name.as_ptr() as u64, name.len() as u64, 0, 0],
+    );
+}
+
+/// Log a bookmark named `name`, with a note
+pub fn bookmark_with_note(name: &str, note: &str) {
+    hypercall(
+        Op::Bookmark,
+        [
+            name.as_ptr() as u64,
+            name.len() as u64,
+            note.as_ptr() as u64,
+            note.len() as u64,
+        ],
+    );
+}
+
+/// Record that the program reached the point numbered `id` as coverage, so that reaching a
+/// new one counts as new coverage even if no new code ran
+pub fn coverage_hint(id: u64) {
+    hypercall(Op::Coverage, [id, 0, 0, 0]);
+}
+
+/// Log a message of `kind` holding `data`
+pub fn message(kind: u64, data: &[u8]) {
+    hypercall(
+        Op::Message,
+        [kind, data.as_ptr() as u64, data.len() as u64, 0],
+    );
+}
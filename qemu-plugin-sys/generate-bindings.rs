@@ -7,6 +7,8 @@ anyhow = "*"
 bindgen = "*"
 cargo_metadata = "*"
 reqwest = { version = "*", features = ["blocking"] }
+serde = { version = "*", features = ["derive"] }
+serde_json = "*"
 syn = "*"
 zip = "*"
 [lints.rust]
@@ -20,8 +22,10 @@ use bindgen::{
 };
 use cargo_metadata::MetadataCommand;
 use reqwest::blocking::get;
+use serde::Serialize;
 use syn::{File as RustFile, Item, ItemForeignMod, ForeignItem, ForeignItemFn, parse_str};
 use std::{
+    collections::{BTreeMap, BTreeSet},
     io::copy,
     fs::{create_dir_all, read_to_string, write, File, OpenOptions},
     path::{Path, PathBuf},
@@ -31,6 +35,8 @@ use zip::ZipArchive;
 const QEMU_GITHUB_URL_BASE: &str = "https://github.com/qemu/qemu/";
 
 const QEMU_VERSIONS: &[&str] = &[
+    // Plugin V0 is the 7.2 LTS release, which predates `qemu_plugin_entry_code`
+    "v7.2.0",
     // Plugin V1 is up until 8.2.4
     "1332b8dd434674480f0feb2cdf3bbaebb85b4240",
     // Plugin V2 is from 9.0.0
@@ -88,7 +94,56 @@ fn extract_zip(archive: &Path, destination: &Path) -> Result<()> {
     Ok(())
 }
 
-fn generate_bindings(qemu_plugin_header: &Path, bindings_path: &Path, def_path: &Path) -> Result<()> {
+#[derive(Serialize)]
+/// The API surface of a single plugin API version
+struct VersionManifest {
+    version: usize,
+    qemu: &'static str,
+    functions: Vec<String>,
+}
+
+#[derive(Serialize)]
+/// The API surface of every plugin API version, from which version gating can be generated
+struct ApiManifest {
+    versions: Vec<VersionManifest>,
+    /// Each function, mapped to the plugin API versions which export it
+    functions: BTreeMap<String, Vec<usize>>,
+}
+
+/// Read the names of the symbols QEMU exports to plugins from a `qemu-plugins.symbols`
+/// linker script, which lists them one per line as `name;` inside braces
+fn read_symbols(symbols_path: &Path) -> Result<BTreeSet<String>> {
+    Ok(read_to_string(symbols_path)?
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| line.strip_suffix(';'))
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && !name.starts_with('}'))
+        .map(|name| name.to_string())
+        .collect())
+}
+
+/// Fail if the functions in the generated bindings differ from the symbols QEMU exports
+fn verify_symbols(export_names: &[String], symbols_path: &Path) -> Result<()> {
+    let symbols = read_symbols(symbols_path)?;
+    let exports = export_names.iter().cloned().collect::<BTreeSet<_>>();
+
+    let missing = symbols.difference(&exports).cloned().collect::<Vec<_>>();
+    let extra = exports.difference(&symbols).cloned().collect::<Vec<_>>();
+
+    if !missing.is_empty() || !extra.is_empty() {
+        return Err(anyhow!(
+            "Generated bindings do not match {:?}:\n  missing from bindings: {:?}\n  not exported by QEMU: {:?}",
+            symbols_path,
+            missing,
+            extra
+        ));
+    }
+
+    Ok(())
+}
+
+fn generate_bindings(qemu_plugin_header: &Path, bindings_path: &Path, def_path: &Path) -> Result<Vec<String>> {
     let header_contents = read_to_string(qemu_plugin_header)?;
     let header_file_name = qemu_plugin_header.file_name().ok_or_else(|| anyhow!("Failed to get file name"))?.to_str().ok_or_else(|| anyhow!("Failed to convert file name to string"))?;
     let header_contents = header_contents.replace("#include <glib.h>", "");
@@ -159,20 +214,20 @@ fn generate_bindings(qemu_plugin_header: &Path, bindings_path: &Path, def_path:
 
     // Write to file using a single buffer
     let mut output = String::from("EXPORTS\n");
-    output.extend(export_names.into_iter().map(|name| format!("  {}\n", name)));
+    output.extend(export_names.iter().map(|name| format!("  {}\n", name)));
 
     write(&def_path, output)?;
 
-    Ok(())
+    Ok(export_names)
 }
 
-fn generate(tmp_dir: &Path, out_dir: &Path, version: usize) -> Result<()> {
+fn generate(tmp_dir: &Path, out_dir: &Path, version: usize) -> Result<VersionManifest> {
     println!("Generating bindings with tmp={:?} out={:?} version={}", tmp_dir, out_dir, version);
-    let src_archive = tmp_dir.join(format!("qemu-{}.zip", QEMU_VERSIONS[version - 1]));
-    let src_dir = tmp_dir.join(format!("qemu-{}", QEMU_VERSIONS[version - 1]));
+    let src_archive = tmp_dir.join(format!("qemu-{}.zip", QEMU_VERSIONS[version]));
+    let src_dir = tmp_dir.join(format!("qemu-{}", QEMU_VERSIONS[version]));
 
     if !src_archive.exists() {
-        let qemu_url = qemu_git_url(QEMU_VERSIONS[version - 1]);
+        let qemu_url = qemu_git_url(QEMU_VERSIONS[version]);
         println!("Downloading {} to {:?}", qemu_url, src_archive);
         download(&qemu_url, &src_archive)?;
     }
@@ -182,13 +237,19 @@ fn generate(tmp_dir: &Path, out_dir: &Path, version: usize) -> Result<()> {
         extract_zip(&src_archive, &src_dir)?;
     }
 
-    generate_bindings(
+    let functions = generate_bindings(
         &src_dir.join("include").join("qemu").join("qemu-plugin.h"),
         &out_dir.join(&format!("bindings_v{}.rs", version)),
         &out_dir.join(&format!("qemu_plugin_api_v{}.def", version))
     )?;
 
-    Ok(())
+    verify_symbols(&functions, &src_dir.join("plugins").join("qemu-plugins.symbols"))?;
+
+    Ok(VersionManifest {
+        version,
+        qemu: QEMU_VERSIONS[version],
+        functions,
+    })
 }
 
 fn main() -> Result<()> {
@@ -213,10 +274,24 @@ fn main() -> Result<()> {
         create_dir_all(&tmp_dir)?;
     }
 
-    generate(&tmp_dir, &out_dir, 1)?;
-    generate(&tmp_dir, &out_dir, 2)?;
-    generate(&tmp_dir, &out_dir, 3)?;
-    generate(&tmp_dir, &out_dir, 4)?;
+    let versions = (0..QEMU_VERSIONS.len())
+        .map(|version| generate(&tmp_dir, &out_dir, version))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut functions = BTreeMap::<String, Vec<usize>>::new();
+
+    for manifest in &versions {
+        for function in &manifest.functions {
+            functions.entry(function.clone()).or_default().push(manifest.version);
+        }
+    }
+
+    let manifest = ApiManifest { versions, functions };
+
+    write(
+        out_dir.join("qemu_plugin_api.json"),
+        serde_json::to_string_pretty(&manifest)? + "\n",
+    )?;
 
     Ok(())
 }
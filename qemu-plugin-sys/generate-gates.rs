@@ -0,0 +1,203 @@
+#!/usr/bin/env -S cargo +nightly-gnu -Z script
+---
+[package]
+edition = "2021"
+[dependencies]
+anyhow = "*"
+cargo_metadata = "*"
+serde = { version = "*", features = ["derive"] }
+serde_json = "*"
+syn = { version = "*", features = ["full", "printing"] }
+quote = "*"
+walkdir = "*"
+---
+
+//! Generate `#[cfg(feature = "plugin-api-vN")]` gate skeletons for the `qemu-plugin` crate
+//! from the API manifest written by `generate-bindings.rs`.
+//!
+//! For each function in the manifest which is not yet used by the `qemu-plugin` crate, the
+//! gate matching the versions which export it is written to `target/tmp/gates.rs`, guarding
+//! a `compile_error!` with the raw signature from the newest bindings which contain it. Until
+//! a wrapper replaces it, the file fails to build on exactly the versions missing one. Pass
+//! `--all` to emit a gate for every function instead.
+
+use anyhow::{anyhow, Result};
+use cargo_metadata::MetadataCommand;
+use quote::ToTokens;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env::args,
+    fs::{create_dir_all, read_to_string, write},
+    path::Path,
+};
+use syn::{parse_file, ForeignItem, Item, ItemForeignMod};
+use walkdir::WalkDir;
+
+#[derive(Deserialize)]
+struct VersionManifest {
+    version: usize,
+}
+
+#[derive(Deserialize)]
+struct ApiManifest {
+    versions: Vec<VersionManifest>,
+    functions: BTreeMap<String, Vec<usize>>,
+}
+
+fn feature(version: usize) -> String {
+    format!("feature = \"plugin-api-v{}\"", version)
+}
+
+fn any_of(versions: impl IntoIterator<Item = usize>) -> String {
+    let features = versions.into_iter().map(feature).collect::<Vec<_>>();
+
+    match features.as_slice() {
+        [feature] => feature.clone(),
+        _ => format!("any({})", features.join(", ")),
+    }
+}
+
+/// Build the gate for a function exported by `versions`, in the style used by `qemu-plugin`:
+/// functions added in a version and kept since are gated on not being any earlier version
+fn gate(versions: &[usize], all: &[usize]) -> Option<String> {
+    let first = *versions.first()?;
+    let last = *versions.last()?;
+    let newest = *all.last()?;
+
+    if versions == all {
+        None
+    } else if last == newest && versions.len() == newest - first + 1 {
+        Some(format!(
+            "#[cfg(not({}))]",
+            any_of(all.iter().copied().filter(|v| *v < first))
+        ))
+    } else {
+        Some(format!("#[cfg({})]", any_of(versions.iter().copied())))
+    }
+}
+
+/// Read the signatures of the extern functions in a bindings file
+fn signatures(bindings_path: &Path) -> Result<HashMap<String, String>> {
+    let parsed = parse_file(&read_to_string(bindings_path)?)?;
+
+    Ok(parsed
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::ForeignMod(ItemForeignMod { items, .. }) => Some(items),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|item| match item {
+            ForeignItem::Fn(f) => Some((f.sig.ident.to_string(), f.sig.to_token_stream().to_string())),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Whether `name` occurs in `source` as a whole identifier
+fn contains_ident(source: &str, name: &str) -> bool {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    source.match_indices(name).any(|(i, _)| {
+        !source[..i].ends_with(is_ident) && !source[i + name.len()..].starts_with(is_ident)
+    })
+}
+
+/// Find the `crate::sys` functions the `qemu-plugin` crate already uses, either called by
+/// path or imported. The weak link stubs define every function, so they are skipped.
+fn wrapped(src_dir: &Path, functions: &BTreeMap<String, Vec<usize>>) -> Result<Vec<String>> {
+    let mut sources = String::new();
+
+    for entry in WalkDir::new(src_dir) {
+        let entry = entry?;
+
+        if entry.path().extension().is_some_and(|e| e == "rs")
+            && !entry.path().components().any(|c| c.as_os_str() == "unix_weak_link")
+        {
+            sources.push_str(&read_to_string(entry.path())?);
+        }
+    }
+
+    Ok(functions
+        .keys()
+        .filter(|name| contains_ident(&sources, name))
+        .cloned()
+        .collect())
+}
+
+fn main() -> Result<()> {
+    let emit_all = args().any(|a| a == "--all");
+
+    let metadata = MetadataCommand::new().no_deps().exec()?;
+
+    let manifest_dir = |name: &str| {
+        metadata
+            .packages
+            .iter()
+            .find(|p| p.name == name)
+            .and_then(|p| p.manifest_path.parent())
+            .map(|p| p.to_path_buf().into_std_path_buf())
+            .ok_or_else(|| anyhow!("Failed to find package {}", name))
+    };
+
+    let sys_src = manifest_dir("qemu-plugin-sys")?.join("src");
+    let plugin_src = manifest_dir("qemu-plugin")?.join("src");
+
+    let manifest: ApiManifest = serde_json::from_str(&read_to_string(sys_src.join("qemu_plugin_api.json"))?)?;
+    let all = manifest.versions.iter().map(|v| v.version).collect::<Vec<_>>();
+
+    let signatures = all
+        .iter()
+        .map(|v| signatures(&sys_src.join(format!("bindings_v{}.rs", v))))
+        .collect::<Result<Vec<_>>>()?;
+
+    let wrapped = wrapped(&plugin_src, &manifest.functions)?;
+
+    let mut output = String::from(
+        "// Generated by qemu-plugin-sys/generate-gates.rs. Replace each `compile_error!` with a\n// safe wrapper in qemu-plugin, keeping its gate.\n",
+    );
+
+    let mut emitted = 0;
+
+    for (name, versions) in &manifest.functions {
+        if !emit_all && wrapped.contains(name) {
+            continue;
+        }
+
+        let newest = versions.last().ok_or_else(|| anyhow!("{} has no versions", name))?;
+        let signature = signatures[*newest]
+            .get(name)
+            .ok_or_else(|| anyhow!("{} missing from bindings_v{}.rs", name, newest))?;
+
+        output.push('\n');
+
+        if let Some(gate) = gate(versions, &all) {
+            output.push_str(&gate);
+            output.push('\n');
+        }
+
+        output.push_str(&format!(
+            "compile_error!({:?});\n",
+            format!("crate::sys::{name} is not wrapped. Raw signature: `{signature}`")
+        ));
+
+        emitted += 1;
+    }
+
+    let out_dir = metadata.target_directory.join("tmp").into_std_path_buf();
+    create_dir_all(&out_dir)?;
+    let out_path = out_dir.join("gates.rs");
+    write(&out_path, output)?;
+
+    println!(
+        "Wrote {} gates to {:?} ({} of {} functions already wrapped)",
+        emitted,
+        out_path,
+        wrapped.len(),
+        manifest.functions.len()
+    );
+
+    Ok(())
+}
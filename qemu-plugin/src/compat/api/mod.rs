@@ -0,0 +1,185 @@
+//! Checks of the plugin API a plugin is compiled against, for plugins which build one source
+//! tree with any of the `plugin-api-v*` features
+//!
+//! [`has_api!`](crate::has_api) evaluates to a `const bool` saying whether a part of the API
+//! is compiled in, and [`cfg_api!`](crate::cfg_api) keeps the code written for it only when it
+//! is, so that a plugin does not need to repeat the feature list of each part in its own
+//! `cfg` attributes. Each part is named after the [`Quirk`] of hosts which lack it:
+//!
+//! | Name                    | Quirk                             | Since API level |
+//! |-------------------------|-----------------------------------|-----------------|
+//! | `entry_code`            | [`Quirk::NoEntryCode`]            | 1               |
+//! | `scoreboards`           | [`Quirk::NoScoreboards`]          | 2               |
+//! | `registers`             | [`Quirk::NoRegisters`]            | 2               |
+//! | `conditional_callbacks` | [`Quirk::NoConditionalCallbacks`] | 3               |
+//! | `memory_read`           | [`Quirk::NoMemoryRead`]           | 4               |
+//!
+//! ```rust,ignore
+//! use qemu_plugin::{cfg_api, has_api};
+//!
+//! cfg_api! {
+//!     if registers {
+//!         fn pc() -> Option<Vec<u8>> {
+//!             let registers = qemu_plugin::qemu_plugin_get_registers().ok()?;
+//!             let pc = registers.iter().find(|r| r.name == "pc")?;
+//!             pc.read().ok()
+//!         }
+//!     } else {
+//!         fn pc() -> Option<Vec<u8>> {
+//!             None
+//!         }
+//!     }
+//! }
+//!
+//! const INLINE_COUNTS: bool = has_api!(scoreboards);
+//! ```
+//!
+//! Both check the API level the plugin is compiled for. A plugin may still be loaded by an
+//! older host, which [`Compatibility`](crate::compat::distro::Compatibility) detects at runtime.
+
+use crate::compat::distro::{Quirk, COMPILED_API_LEVEL};
+
+/// Whether the part of the API which hosts with `quirk` lack is compiled in
+#[allow(
+    clippy::absurd_extreme_comparisons,
+    reason = "COMPILED_API_LEVEL is 0 with plugin-api-v0, where only level 0 is compiled in"
+)]
+pub const fn has(quirk: Quirk) -> bool {
+    quirk.api_level() <= COMPILED_API_LEVEL
+}
+
+#[macro_export]
+/// Whether a part of the plugin API is compiled in, as a `const bool`. See
+/// [`compat::api`](crate::compat::api) for the names of the parts.
+macro_rules! has_api {
+    ($name:ident) => {
+        $crate::compat::api::has($crate::__api_quirk!($name))
+    };
+}
+
+#[macro_export]
+/// Keep code only if a part of the plugin API is compiled in, or keep the code of an `else`
+/// branch otherwise. Either branch may hold items or statements. See
+/// [`compat::api`](crate::compat::api) for the names of the parts.
+macro_rules! cfg_api {
+    (if $name:ident { $($yes:tt)* } else { $($no:tt)* }) => {
+        $crate::__cfg_api!($name { $($yes)* } { $($no)* });
+    };
+    (if $name:ident { $($yes:tt)* }) => {
+        $crate::__cfg_api!($name { $($yes)* } {});
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __api_quirk {
+    (entry_code) => {
+        $crate::compat::distro::Quirk::NoEntryCode
+    };
+    (scoreboards) => {
+        $crate::compat::distro::Quirk::NoScoreboards
+    };
+    (registers) => {
+        $crate::compat::distro::Quirk::NoRegisters
+    };
+    (conditional_callbacks) => {
+        $crate::compat::distro::Quirk::NoConditionalCallbacks
+    };
+    (memory_read) => {
+        $crate::compat::distro::Quirk::NoMemoryRead
+    };
+    ($other:ident) => {
+        compile_error!(concat!("Unknown plugin API `", stringify!($other), "`"))
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+/// Select a branch by the API level of a part, which must agree with [`Quirk::api_level`]
+macro_rules! __cfg_api {
+    (entry_code $yes:tt $no:tt) => {
+        $crate::__cfg_api_v1!($yes $no);
+    };
+    (scoreboards $yes:tt $no:tt) => {
+        $crate::__cfg_api_v2!($yes $no);
+    };
+    (registers $yes:tt $no:tt) => {
+        $crate::__cfg_api_v2!($yes $no);
+    };
+    (conditional_callbacks $yes:tt $no:tt) => {
+        $crate::__cfg_api_v3!($yes $no);
+    };
+    (memory_read $yes:tt $no:tt) => {
+        $crate::__cfg_api_v4!($yes $no);
+    };
+    ($other:ident $yes:tt $no:tt) => {
+        compile_error!(concat!("Unknown plugin API `", stringify!($other), "`"));
+    };
+}
+
+// The branch is chosen when this crate is compiled, with the same features as the plugin,
+// and the gates match those of the items of each level in this crate
+
+#[cfg(not(feature = "plugin-api-v0"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cfg_api_v1 {
+    ({ $($yes:tt)* } { $($no:tt)* }) => { $($yes)* };
+}
+
+#[cfg(feature = "plugin-api-v0")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cfg_api_v1 {
+    ({ $($yes:tt)* } { $($no:tt)* }) => { $($no)* };
+}
+
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cfg_api_v2 {
+    ({ $($yes:tt)* } { $($no:tt)* }) => { $($yes)* };
+}
+
+#[cfg(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cfg_api_v2 {
+    ({ $($yes:tt)* } { $($no:tt)* }) => { $($no)* };
+}
+
+#[cfg(not(any(
+    feature = "plugin-api-v0",
+    feature = "plugin-api-v1",
+    feature = "plugin-api-v2"
+)))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cfg_api_v3 {
+    ({ $($yes:tt)* } { $($no:tt)* }) => { $($yes)* };
+}
+
+#[cfg(any(
+    feature = "plugin-api-v0",
+    feature = "plugin-api-v1",
+    feature = "plugin-api-v2"
+))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cfg_api_v3 {
+    ({ $($yes:tt)* } { $($no:tt)* }) => { $($no)* };
+}
+
+#[cfg(feature = "plugin-api-v4")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cfg_api_v4 {
+    ({ $($yes:tt)* } { $($no:tt)* }) => { $($yes)* };
+}
+
+#[cfg(not(feature = "plugin-api-v4"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cfg_api_v4 {
+    ({ $($yes:tt)* } { $($no:tt)* }) => { $($no)* };
+}
@@ -0,0 +1,215 @@
+//! Mapping of QEMU version strings, including those of Debian and Ubuntu packages, to the
+//! plugin API level they provide and the quirks that come with it
+//!
+//! The plugin API level compiled into a plugin is chosen with a `plugin-api-v*` feature, but
+//! a plugin may be loaded by an older QEMU than it was built for. These helpers let a plugin
+//! check the host and fall back to safe code paths instead of failing at runtime.
+
+use crate::install::Info;
+use anyhow::{anyhow, Error, Result};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// A distribution which packages QEMU
+pub enum Distro {
+    /// Debian, whose packages are versioned like `1:7.2+dfsg-7`
+    Debian,
+    /// Ubuntu, whose packages are versioned like `1:8.2.2+ds-0ubuntu1`
+    Ubuntu,
+}
+
+impl Distro {
+    /// Identify the distribution from a package version. Ubuntu packages carry an `ubuntu`
+    /// revision; any other package version is assumed to come from Debian.
+    pub fn from_package(package: &str) -> Self {
+        if package.contains("ubuntu") {
+            Self::Ubuntu
+        } else {
+            Self::Debian
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A QEMU version, as printed by `qemu-system-* --version` or `qemu-* --version`
+pub struct QemuVersion {
+    /// The major version
+    pub major: u32,
+    /// The minor version
+    pub minor: u32,
+    /// The micro version
+    pub micro: u32,
+    /// The distribution which built this QEMU, if it could be identified
+    pub distro: Option<Distro>,
+    /// The distribution package version, e.g. `1:8.2.2+ds-0ubuntu1`
+    pub package: Option<String>,
+}
+
+impl QemuVersion {
+    fn parse_numbers(s: &str) -> Option<(u32, u32, u32)> {
+        let mut numbers = s
+            .split(|c: char| !c.is_ascii_digit())
+            .take_while(|n| !n.is_empty())
+            .map(|n| n.parse::<u32>().ok());
+        let major = numbers.next()??;
+        let minor = numbers.next()??;
+        let micro = numbers.next().flatten().unwrap_or(0);
+        Some((major, minor, micro))
+    }
+}
+
+impl FromStr for QemuVersion {
+    type Err = Error;
+
+    /// Parse a version string such as `QEMU emulator version 8.2.2 (Debian
+    /// 1:8.2.2+ds-0ubuntu1.4)`, `7.2.0`, or a bare package version such as
+    /// `1:7.2+dfsg-7+deb12u6`
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.lines().next().unwrap_or_default().trim();
+        let s = s
+            .strip_prefix("QEMU emulator version")
+            .map(str::trim)
+            .unwrap_or(s);
+
+        let (version, package) = match s.split_once('(') {
+            Some((version, package)) => (
+                version.trim(),
+                Some(package.trim_end_matches(')').trim().to_string()),
+            ),
+            None => (s, None),
+        };
+
+        // A bare package version carries an epoch before the upstream version
+        let (version, package) = match version.split_once(':') {
+            Some((_, upstream)) if package.is_none() => (upstream, Some(version.to_string())),
+            _ => (version, package),
+        };
+
+        let package = package.map(|p| {
+            p.strip_prefix("Debian")
+                .map(str::trim)
+                .unwrap_or(&p)
+                .to_string()
+        });
+
+        let distro = package.as_deref().map(Distro::from_package);
+
+        let (major, minor, micro) = Self::parse_numbers(version)
+            .ok_or_else(|| anyhow!("Failed to parse QEMU version from '{s}'"))?;
+
+        Ok(Self {
+            major,
+            minor,
+            micro,
+            distro,
+            package,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// A limitation of a QEMU host, relative to the latest plugin API
+pub enum Quirk {
+    /// QEMU before 4.2 has no plugin support at all
+    NoPlugins,
+    /// `qemu_plugin_entry_code` is not exported (before 8.0)
+    NoEntryCode,
+    /// Scoreboards and per-vCPU inline operations are unavailable (before 9.0)
+    NoScoreboards,
+    /// Registers cannot be read (before 9.0)
+    NoRegisters,
+    /// Conditional callbacks are unavailable (before 9.1)
+    NoConditionalCallbacks,
+    /// Guest memory cannot be read (before 9.2)
+    NoMemoryRead,
+}
+
+impl Quirk {
+    /// The lowest plugin API level without this quirk. Hosts without plugin support have no
+    /// API level, so [`Quirk::NoPlugins`] is absent from every level.
+    pub const fn api_level(self) -> u8 {
+        match self {
+            Self::NoPlugins => 0,
+            Self::NoEntryCode => 1,
+            Self::NoScoreboards | Self::NoRegisters => 2,
+            Self::NoConditionalCallbacks => 3,
+            Self::NoMemoryRead => 4,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The plugin API a QEMU host supports
+pub struct Compatibility {
+    /// The highest `plugin-api-v*` level the host can load
+    pub api_level: u8,
+    /// The features missing from the host, relative to the latest API level
+    pub quirks: Vec<Quirk>,
+}
+
+/// The API level this crate was compiled for
+pub const COMPILED_API_LEVEL: u8 = if cfg!(feature = "plugin-api-v0") {
+    0
+} else if cfg!(feature = "plugin-api-v1") {
+    1
+} else if cfg!(feature = "plugin-api-v2") {
+    2
+} else if cfg!(feature = "plugin-api-v3") {
+    3
+} else {
+    4
+};
+
+impl Compatibility {
+    fn for_level(api_level: u8) -> Self {
+        let quirks = [
+            Quirk::NoEntryCode,
+            Quirk::NoScoreboards,
+            Quirk::NoRegisters,
+            Quirk::NoConditionalCallbacks,
+            Quirk::NoMemoryRead,
+        ]
+        .into_iter()
+        .filter(|quirk| api_level < quirk.api_level())
+        .collect();
+
+        Self { api_level, quirks }
+    }
+
+    /// Determine compatibility from a QEMU version
+    pub fn from_version(version: &QemuVersion) -> Self {
+        match (version.major, version.minor) {
+            (major, minor) if major < 4 || (major == 4 && minor < 2) => Self {
+                api_level: 0,
+                quirks: vec![Quirk::NoPlugins],
+            },
+            (major, _) if major < 8 => Self::for_level(0),
+            (8, _) => Self::for_level(1),
+            (9, 0) => Self::for_level(2),
+            (9, 1) => Self::for_level(3),
+            _ => Self::for_level(4),
+        }
+    }
+
+    /// Determine compatibility from the information QEMU passes to a plugin on install. The
+    /// host reports only its plugin API version, and version 1 is shared by 7.2 and 8.x, so
+    /// a version 1 host is assumed to be the more limited of the two.
+    pub fn from_info(info: &Info) -> Self {
+        match info.version.current {
+            ..=1 => Self::for_level(0),
+            2 => Self::for_level(2),
+            3 => Self::for_level(3),
+            _ => Self::for_level(4),
+        }
+    }
+
+    /// Whether the host has a quirk
+    pub fn has(&self, quirk: Quirk) -> bool {
+        self.quirks.contains(&quirk)
+    }
+
+    /// Whether a plugin compiled with this crate's API level can be loaded by the host
+    pub fn supports_compiled_level(&self) -> bool {
+        !self.has(Quirk::NoPlugins) && self.api_level.cmp(&COMPILED_API_LEVEL).is_ge()
+    }
+}
@@ -0,0 +1,4 @@
+//! Helpers for working out which parts of the plugin API a QEMU host supports
+
+pub mod api;
+pub mod distro;
@@ -0,0 +1,59 @@
+//! Generations of plugin state, which stamp the userdata of callbacks handed to QEMU
+//!
+//! Execution and memory callbacks are registered on translation blocks, and QEMU keeps
+//! calling them with the userdata they were registered with for as long as the block stays
+//! translated. When the plugin is reset or the translation cache is flushed, the state these
+//! callbacks close over is typically rebuilt, but a block translated before then may still
+//! be running on another vCPU. Each userdata pointer is stamped with the generation current
+//! when its callback was registered, and the trampolines which receive it from QEMU skip
+//! the callback if the generation has advanced since.
+//!
+//! The generation advances when a reset requested by [`crate::qemu_plugin_reset`] completes,
+//! and when QEMU flushes the translation cache of a plugin using [`crate::plugin::Register`].
+//! Plugins registering their own flush callback can call [`advance`] from it.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// The current generation
+pub fn current() -> u64 {
+    GENERATION.load(Ordering::Acquire)
+}
+
+/// Advance the generation, so that callbacks registered before now are no longer called.
+/// Returns the new generation.
+pub fn advance() -> u64 {
+    GENERATION.fetch_add(1, Ordering::AcqRel) + 1
+}
+
+/// A callback stamped with the generation it was registered in, as passed to QEMU as
+/// userdata
+pub(crate) struct Stamped<F> {
+    generation: u64,
+    callback: F,
+}
+
+impl<F> Stamped<F> {
+    /// Stamp `callback` with the current generation and leak it as a userdata pointer
+    pub(crate) fn into_userdata(callback: F) -> *mut std::ffi::c_void {
+        Box::into_raw(Box::new(Self {
+            generation: current(),
+            callback,
+        })) as *mut std::ffi::c_void
+    }
+
+    /// The callback behind a userdata pointer, if it was registered in the current
+    /// generation
+    ///
+    /// # Safety
+    ///
+    /// `userdata` must have been returned by [`Stamped::into_userdata`] with the same `F`.
+    /// The allocation is never freed, so the pointer stays valid even after the generation
+    /// advances.
+    pub(crate) unsafe fn current<'a>(userdata: *mut std::ffi::c_void) -> Option<&'a mut F> {
+        let stamped = unsafe { &mut *(userdata as *mut Self) };
+
+        (stamped.generation == current()).then_some(&mut stamped.callback)
+    }
+}
@@ -0,0 +1,106 @@
+//! Guards which disarm callbacks when dropped
+//!
+//! QEMU cannot unregister a single execution or memory callback: it runs for as long as the
+//! translation block it was registered on stays translated, which may be long after the
+//! state the callback works on is gone. A [`CallbackGuard`] wraps callbacks so that they do
+//! nothing once the guard is dropped or disarmed, which makes instrumentation that should
+//! only be active for a window of execution safe to register.
+//!
+//! ```rust,ignore
+//! use qemu_plugin::guard::CallbackGuard;
+//!
+//! // Inside `on_translation_block_translate`, while a window is open
+//! let guard = CallbackGuard::new();
+//!
+//! for insn in tb.instructions() {
+//!     insn.register_execute_callback(guard.wrap(move |vcpu_index| {
+//!         println!("{vcpu_index}: {:#x}", vaddr);
+//!     }));
+//! }
+//!
+//! // Stored until the window closes, when dropping it disarms every callback above
+//! self.window = Some(guard);
+//! ```
+//!
+//! Disarmed callbacks are still called by QEMU, and the closures they wrap are not freed,
+//! but they return immediately without running.
+
+use crate::{MemoryInfo, VCPUIndex};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+#[derive(Debug)]
+#[must_use = "callbacks wrapped by a guard are disarmed when it is dropped"]
+/// A handle keeping the callbacks it wraps armed until it is dropped
+pub struct CallbackGuard {
+    armed: Arc<AtomicBool>,
+}
+
+impl CallbackGuard {
+    /// Create an armed guard
+    pub fn new() -> Self {
+        Self {
+            armed: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Whether the callbacks wrapped by this guard still run
+    pub fn is_armed(&self) -> bool {
+        self.armed.load(Ordering::Acquire)
+    }
+
+    /// Stop the callbacks wrapped by this guard from running, as dropping it does
+    pub fn disarm(&self) {
+        self.armed.store(false, Ordering::Release);
+    }
+
+    /// Keep the callbacks wrapped by this guard armed for as long as QEMU calls them
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+
+    /// Wrap an execution callback so that it only runs while this guard is armed
+    pub fn wrap<F>(&self, mut cb: F) -> impl FnMut(VCPUIndex) + Send + Sync + 'static
+    where
+        F: FnMut(VCPUIndex) + Send + Sync + 'static,
+    {
+        let armed = self.armed.clone();
+
+        move |vcpu_index| {
+            if armed.load(Ordering::Acquire) {
+                cb(vcpu_index)
+            }
+        }
+    }
+
+    /// Wrap a memory access callback so that it only runs while this guard is armed
+    pub fn wrap_memory<F>(
+        &self,
+        mut cb: F,
+    ) -> impl FnMut(VCPUIndex, MemoryInfo, u64) + Send + Sync + 'static
+    where
+        F: FnMut(VCPUIndex, MemoryInfo, u64) + Send + Sync + 'static,
+    {
+        let armed = self.armed.clone();
+
+        move |vcpu_index, info, vaddr| {
+            if armed.load(Ordering::Acquire) {
+                cb(vcpu_index, info, vaddr)
+            }
+        }
+    }
+}
+
+impl Default for CallbackGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for CallbackGuard {
+    fn drop(&mut self) {
+        self.disarm();
+    }
+}
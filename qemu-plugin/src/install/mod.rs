@@ -6,10 +6,11 @@ use qemu_plugin_sys::{
 };
 use std::{
     collections::HashMap,
-    ffi::{c_char, c_int, CStr, CString},
+    ffi::{c_char, c_int, CStr, CString, OsStr, OsString},
+    path::PathBuf,
 };
 
-use crate::{error::Error, plugin::PLUGIN};
+use crate::{error::Error, path::os_string_from_bytes, plugin::PLUGIN};
 
 #[no_mangle]
 /// The version of the plugin API that this plugin is compatible with
@@ -18,6 +19,8 @@ pub static qemu_plugin_version: c_int = QEMU_PLUGIN_VERSION as c_int;
 /// Code returned from `qemu_plugin_install` to indicate successful installation
 pub const PLUGIN_INSTALL_SUCCESS: c_int = 0;
 
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// A value passed to a QEMU plugin via the command line
 pub enum Value {
     /// A boolean argument to a QEMU plugin, for example `val=true` or `val=on`
@@ -48,10 +51,15 @@ impl Value {
     }
 }
 
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Arguments to QEMU as passed to `qemu_plugin_install`
 pub struct Args {
     /// Arguments to the QEMU plugin as passed in by QEMU
     pub raw: Vec<String>,
+    /// Arguments to the QEMU plugin as passed in by QEMU, without replacing bytes which are
+    /// not valid UTF-8
+    pub raw_os: Vec<OsString>,
     /// Arguments to the QEMU plugin, parsed into valid argument types and value
     /// types
     pub parsed: HashMap<String, Value>,
@@ -66,6 +74,10 @@ impl Args {
                 .map(|i| unsafe { CStr::from_ptr(*value.offset(i as isize)) })
                 .map(|cstr| cstr.to_string_lossy().into_owned())
                 .collect::<Vec<_>>(),
+            raw_os: (0..argc)
+                .map(|i| unsafe { CStr::from_ptr(*value.offset(i as isize)) })
+                .map(|cstr| os_string_from_bytes(cstr.to_bytes()))
+                .collect::<Vec<_>>(),
             parsed: (0..argc)
                 .map(|i| unsafe { CStr::from_ptr(*value.offset(i as isize)) })
                 .map(|cstr| cstr.to_string_lossy().into_owned())
@@ -84,8 +96,29 @@ impl Args {
                 .collect::<HashMap<_, _>>(),
         })
     }
+
+    /// Returns the value of the last argument named `key`, without replacing bytes which
+    /// are not valid UTF-8
+    pub fn get_os(&self, key: &str) -> Option<&OsStr> {
+        self.raw_os.iter().rev().find_map(|argument| {
+            let value = argument
+                .as_encoded_bytes()
+                .strip_prefix(key.as_bytes())?
+                .strip_prefix(b"=")?;
+            // NOTE: This is safe because `value` is split from a valid `OsStr` directly after
+            // an ASCII `=`
+            Some(unsafe { OsStr::from_encoded_bytes_unchecked(value) })
+        })
+    }
+
+    /// Returns the value of the last argument named `key` as a host path. Unlike a lookup
+    /// in `parsed`, values which look like integers or booleans are still returned as paths.
+    pub fn get_path(&self, key: &str) -> Option<PathBuf> {
+        self.get_os(key).map(PathBuf::from)
+    }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// The version specification of the QEMU plugin API
 pub struct Version {
     /// Current plugin API version
@@ -103,6 +136,7 @@ impl From<&qemu_info_t__bindgen_ty_1> for Version {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Information about the system, present if the emulator is running in full
 /// system emulation mode
 pub struct System {
@@ -121,6 +155,7 @@ impl From<&qemu_info_t__bindgen_ty_2__bindgen_ty_1> for System {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Information about the simulation
 pub struct Info {
     /// The target name of the simulation (e.g. `x86_64-softmmu`)
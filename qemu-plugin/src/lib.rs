@@ -81,9 +81,15 @@ mod unix_weak_link;
 mod win_link_hook;
 
 use crate::error::{Error, Result};
+use crate::generation::Stamped;
+use crate::guard::CallbackGuard;
 #[cfg(feature = "num-traits")]
 use num_traits::{FromBytes, PrimInt};
-#[cfg(not(any(feature = "plugin-api-v1", feature = "plugin-api-v2")))]
+#[cfg(not(any(
+    feature = "plugin-api-v0",
+    feature = "plugin-api-v1",
+    feature = "plugin-api-v2"
+)))]
 use qemu_plugin_sys::qemu_plugin_cond;
 use qemu_plugin_sys::{
     qemu_plugin_cb_flags, qemu_plugin_hwaddr, qemu_plugin_id_t, qemu_plugin_insn,
@@ -93,7 +99,7 @@ use qemu_plugin_sys::{
 };
 #[cfg(feature = "plugin-api-v4")]
 use qemu_plugin_sys::{qemu_plugin_mem_value, qemu_plugin_mem_value_type};
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 use qemu_plugin_sys::{
     qemu_plugin_read_register, qemu_plugin_reg_descriptor, qemu_plugin_register,
     qemu_plugin_scoreboard, qemu_plugin_u64, GArray, GByteArray,
@@ -104,25 +110,53 @@ use std::{
     path::PathBuf,
     sync::{Mutex, OnceLock},
 };
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 use std::{
     fmt::{Debug, Formatter},
     mem::MaybeUninit,
 };
 
+pub mod compat;
 pub mod error;
+pub mod generation;
+pub mod guard;
 pub mod install;
+pub mod path;
 pub mod plugin;
+#[cfg(feature = "self-profile")]
+pub mod profile;
 pub mod sys;
+pub mod target;
 pub mod version;
 
+/// Evaluate an expression, timing it as a named profile section when the `self-profile`
+/// feature is enabled
+macro_rules! profiled {
+    ($name:literal, $body:expr) => {{
+        #[cfg(feature = "self-profile")]
+        {
+            static SECTION: $crate::profile::Section = $crate::profile::Section::new($name);
+            SECTION.measure(|| $body)
+        }
+        #[cfg(not(feature = "self-profile"))]
+        {
+            $body
+        }
+    }};
+}
+
+pub(crate) use profiled;
+
 #[cfg(not(windows))]
 extern "C" {
     /// glib g_free is provided by the QEMU program we are being linked into
     fn g_free(mem: *mut c_void);
 }
 
-#[cfg(all(not(windows), not(feature = "plugin-api-v1")))]
+#[cfg(all(
+    not(windows),
+    not(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))
+))]
 extern "C" {
     /// glib g_byte_array_new is provided by the QEMU program we are being linked into
     fn g_byte_array_new() -> *mut GByteArray;
@@ -145,7 +179,10 @@ lazy_static::lazy_static! {
     };
 }
 
-#[cfg(all(windows, not(feature = "plugin-api-v1")))]
+#[cfg(all(
+    windows,
+    not(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))
+))]
 lazy_static::lazy_static! {
     static ref G_BYTE_ARRAY_NEW: libloading::os::windows::Symbol<unsafe extern fn() -> *mut GByteArray> = {
         let lib =
@@ -158,7 +195,10 @@ lazy_static::lazy_static! {
     };
 }
 
-#[cfg(all(windows, not(feature = "plugin-api-v1")))]
+#[cfg(all(
+    windows,
+    not(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))
+))]
 lazy_static::lazy_static! {
     static ref G_BYTE_ARRAY_FREE: libloading::os::windows::Symbol<unsafe extern fn(*mut c_void, bool) -> *mut u8> = {
         let lib =
@@ -171,7 +211,10 @@ lazy_static::lazy_static! {
     };
 }
 
-#[cfg(all(windows, not(feature = "plugin-api-v1")))]
+#[cfg(all(
+    windows,
+    not(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))
+))]
 lazy_static::lazy_static! {
     static ref G_ARRAY_FREE: libloading::os::windows::Symbol<unsafe extern fn(*mut c_void, bool) -> *mut u8> = {
         let lib =
@@ -197,7 +240,10 @@ unsafe fn g_free(mem: *mut c_void) {
     unsafe { G_FREE(mem) }
 }
 
-#[cfg(all(windows, not(feature = "plugin-api-v1")))]
+#[cfg(all(
+    windows,
+    not(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))
+))]
 /// Define g_byte_array_new, because on Windows we cannot delay link it
 ///
 /// # Safety
@@ -209,7 +255,10 @@ unsafe fn g_byte_array_new() -> *mut GByteArray {
     unsafe { G_BYTE_ARRAY_NEW() }
 }
 
-#[cfg(all(windows, not(feature = "plugin-api-v1")))]
+#[cfg(all(
+    windows,
+    not(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))
+))]
 /// Define g_byte_array_free, because on Windows we cannot delay link it
 ///
 /// # Safety
@@ -222,7 +271,10 @@ unsafe fn g_byte_array_free(array: *mut GByteArray, free_segment: bool) -> *mut
     unsafe { G_BYTE_ARRAY_FREE(array as *mut c_void, free_segment) }
 }
 
-#[cfg(all(windows, not(feature = "plugin-api-v1")))]
+#[cfg(all(
+    windows,
+    not(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))
+))]
 /// Define g_array_free, because on Windows we cannot delay link it
 ///
 /// # Safety
@@ -236,7 +288,7 @@ unsafe fn g_array_free(array: *mut GArray, free_segment: bool) -> *mut u8 {
 
 /// The index of a vCPU
 pub type VCPUIndex = c_uint;
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 /// u64 member of an entry in a scoreboard, allows access to a specific u64 member in
 /// one given entry, located at a specified offset. Inline operations expect this as an
 /// entry.
@@ -245,7 +297,11 @@ pub type PluginU64 = qemu_plugin_u64;
 pub type CallbackFlags = qemu_plugin_cb_flags;
 /// Memory read/write flags
 pub type MemRW = qemu_plugin_mem_rw;
-#[cfg(not(any(feature = "plugin-api-v1", feature = "plugin-api-v2")))]
+#[cfg(not(any(
+    feature = "plugin-api-v0",
+    feature = "plugin-api-v1",
+    feature = "plugin-api-v2"
+)))]
 /// A condition for a callback to be run
 pub type PluginCondition = qemu_plugin_cond;
 /// Plugin operations for inline operations
@@ -396,14 +452,23 @@ impl<'a> TranslationBlock<'a> {
         self.register_execute_callback_flags(cb, CallbackFlags::QEMU_PLUGIN_CB_NO_REGS);
     }
 
+    /// Register a callback to be run on execution of this translation block until the
+    /// returned guard is dropped
+    pub fn register_execute_callback_scoped<F>(&self, cb: F) -> CallbackGuard
+    where
+        F: FnMut(VCPUIndex) + Send + Sync + 'static,
+    {
+        let guard = CallbackGuard::new();
+        self.register_execute_callback(guard.wrap(cb));
+        guard
+    }
+
     /// Register a callback to be run on execution of this translation block
     pub fn register_execute_callback_flags<F>(&self, cb: F, flags: CallbackFlags)
     where
         F: FnMut(VCPUIndex) + Send + Sync + 'static,
     {
-        let callback = Box::new(cb);
-        let callback_box = Box::new(callback);
-        let userdata = Box::into_raw(callback_box) as *mut c_void;
+        let userdata = Stamped::into_userdata(cb);
 
         unsafe {
             crate::sys::qemu_plugin_register_vcpu_tb_exec_cb(
@@ -415,7 +480,25 @@ impl<'a> TranslationBlock<'a> {
         };
     }
 
-    #[cfg(not(any(feature = "plugin-api-v1", feature = "plugin-api-v2")))]
+    #[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
+    /// Register an inline operation on a scoreboard entry to be run on execution of this
+    /// translation block, by the vCPU executing it
+    pub fn register_inline_per_vcpu(&self, op: PluginOp, entry: PluginU64, imm: u64) {
+        unsafe {
+            crate::sys::qemu_plugin_register_vcpu_tb_exec_inline_per_vcpu(
+                self.translation_block as *mut qemu_plugin_tb,
+                op,
+                entry,
+                imm,
+            )
+        };
+    }
+
+    #[cfg(not(any(
+        feature = "plugin-api-v0",
+        feature = "plugin-api-v1",
+        feature = "plugin-api-v2"
+    )))]
     /// Register a callback to be conditionally run on execution of this translation
     /// block
     pub fn register_conditional_execute_callback<F>(
@@ -436,7 +519,11 @@ impl<'a> TranslationBlock<'a> {
         )
     }
 
-    #[cfg(not(any(feature = "plugin-api-v1", feature = "plugin-api-v2")))]
+    #[cfg(not(any(
+        feature = "plugin-api-v0",
+        feature = "plugin-api-v1",
+        feature = "plugin-api-v2"
+    )))]
     /// Register a callback to be conditionally run on execution of this translation
     /// block
     pub fn register_conditional_execute_callback_flags<F>(
@@ -449,9 +536,7 @@ impl<'a> TranslationBlock<'a> {
     ) where
         F: FnMut(VCPUIndex) + Send + Sync + 'static,
     {
-        let callback = Box::new(cb);
-        let callback_box = Box::new(callback);
-        let userdata = Box::into_raw(callback_box) as *mut c_void;
+        let userdata = Stamped::into_userdata(cb);
 
         unsafe {
             crate::sys::qemu_plugin_register_vcpu_tb_exec_cond_cb(
@@ -503,6 +588,22 @@ pub struct Instruction<'a> {
     marker: PhantomData<&'a ()>,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Owned metadata of a translated instruction, which outlives the translation callback
+pub struct InstructionInfo {
+    /// The virtual address of the instruction
+    pub vaddr: u64,
+    /// The hardware (physical) address of the instruction
+    pub haddr: u64,
+    /// The instruction's bytes
+    pub data: Vec<u8>,
+    /// The textual disassembly of the instruction, if QEMU provided one
+    pub disas: Option<String>,
+    /// The symbol associated with the instruction, if any
+    pub symbol: Option<String>,
+}
+
 impl<'a> Instruction<'a> {
     fn new(translation_block: &'a TranslationBlock<'a>, insn: *mut qemu_plugin_insn) -> Self {
         Self {
@@ -514,7 +615,11 @@ impl<'a> Instruction<'a> {
 }
 
 impl<'a> Instruction<'a> {
-    #[cfg(any(feature = "plugin-api-v1", feature = "plugin-api-v2"))]
+    #[cfg(any(
+        feature = "plugin-api-v0",
+        feature = "plugin-api-v1",
+        feature = "plugin-api-v2"
+    ))]
     /// Returns the data for this instruction. This method may only be called inside the
     /// callback in which the instruction is obtained, but the resulting data is owned.
     pub fn data(&self) -> Vec<u8> {
@@ -534,7 +639,11 @@ impl<'a> Instruction<'a> {
         data
     }
 
-    #[cfg(not(any(feature = "plugin-api-v1", feature = "plugin-api-v2")))]
+    #[cfg(not(any(
+        feature = "plugin-api-v0",
+        feature = "plugin-api-v1",
+        feature = "plugin-api-v2"
+    )))]
     /// Reads the data for this instruction returning number of bytes read. This method may only be
     /// called inside the callback in which the instruction is obtained.
     pub fn read_data(&self, data: &mut [u8]) -> usize {
@@ -548,7 +657,11 @@ impl<'a> Instruction<'a> {
         }
     }
 
-    #[cfg(not(any(feature = "plugin-api-v1", feature = "plugin-api-v2")))]
+    #[cfg(not(any(
+        feature = "plugin-api-v0",
+        feature = "plugin-api-v1",
+        feature = "plugin-api-v2"
+    )))]
     /// Returns the data for this instruction. This method may only be called inside the
     /// callback in which the instruction is obtained, but the resulting data is owned.
     pub fn data(&self) -> Vec<u8> {
@@ -610,6 +723,18 @@ impl<'a> Instruction<'a> {
         }
     }
 
+    /// Returns the owned metadata of this instruction. This method may only be called
+    /// inside the callback in which the instruction is obtained.
+    pub fn info(&self) -> InstructionInfo {
+        InstructionInfo {
+            vaddr: self.vaddr(),
+            haddr: self.haddr(),
+            data: self.data(),
+            disas: self.disas().ok(),
+            symbol: self.symbol().ok().flatten(),
+        }
+    }
+
     /// Register a callback to be run on execution of this instruction
     pub fn register_execute_callback<F>(&self, cb: F)
     where
@@ -618,14 +743,23 @@ impl<'a> Instruction<'a> {
         self.register_execute_callback_flags(cb, CallbackFlags::QEMU_PLUGIN_CB_NO_REGS)
     }
 
+    /// Register a callback to be run on execution of this instruction until the returned
+    /// guard is dropped
+    pub fn register_execute_callback_scoped<F>(&self, cb: F) -> CallbackGuard
+    where
+        F: FnMut(VCPUIndex) + Send + Sync + 'static,
+    {
+        let guard = CallbackGuard::new();
+        self.register_execute_callback(guard.wrap(cb));
+        guard
+    }
+
     /// Register a callback to be run on execution of this instruction
     pub fn register_execute_callback_flags<F>(&self, cb: F, flags: CallbackFlags)
     where
         F: FnMut(VCPUIndex) + Send + Sync + 'static,
     {
-        let callback = Box::new(cb);
-        let callback_box = Box::new(callback);
-        let userdata = Box::into_raw(callback_box) as *mut c_void;
+        let userdata = Stamped::into_userdata(cb);
 
         unsafe {
             crate::sys::qemu_plugin_register_vcpu_insn_exec_cb(
@@ -638,7 +772,11 @@ impl<'a> Instruction<'a> {
     }
 
     /// Register a callback to be conditionally run on execution of this instruction
-    #[cfg(not(any(feature = "plugin-api-v1", feature = "plugin-api-v2")))]
+    #[cfg(not(any(
+        feature = "plugin-api-v0",
+        feature = "plugin-api-v1",
+        feature = "plugin-api-v2"
+    )))]
     pub fn register_conditional_execute_callback<F>(
         &self,
         cb: F,
@@ -658,7 +796,11 @@ impl<'a> Instruction<'a> {
     }
 
     /// Register a callback to be conditionally run on execution of this instruction
-    #[cfg(not(any(feature = "plugin-api-v1", feature = "plugin-api-v2")))]
+    #[cfg(not(any(
+        feature = "plugin-api-v0",
+        feature = "plugin-api-v1",
+        feature = "plugin-api-v2"
+    )))]
     pub fn register_conditional_execute_callback_flags<F>(
         &self,
         cb: F,
@@ -669,9 +811,7 @@ impl<'a> Instruction<'a> {
     ) where
         F: FnMut(VCPUIndex) + Send + Sync + 'static,
     {
-        let callback = Box::new(cb);
-        let callback_box = Box::new(callback);
-        let userdata = Box::into_raw(callback_box) as *mut c_void;
+        let userdata = Stamped::into_userdata(cb);
 
         unsafe {
             crate::sys::qemu_plugin_register_vcpu_insn_exec_cond_cb(
@@ -699,6 +839,22 @@ impl<'a> Instruction<'a> {
         self.register_memory_access_callback_flags(cb, rw, CallbackFlags::QEMU_PLUGIN_CB_NO_REGS)
     }
 
+    /// Register a callback to be run on memory access of this instruction until the
+    /// returned guard is dropped
+    ///
+    /// # Arguments
+    ///
+    /// - `cb`: The callback to be run
+    /// - `rw`: The type of memory access to trigger the callback on
+    pub fn register_memory_access_callback_scoped<F>(&self, cb: F, rw: MemRW) -> CallbackGuard
+    where
+        F: FnMut(VCPUIndex, MemoryInfo, u64) + Send + Sync + 'static,
+    {
+        let guard = CallbackGuard::new();
+        self.register_memory_access_callback(guard.wrap_memory(cb), rw);
+        guard
+    }
+
     /// Register a callback to be run on memory access of this instruction
     ///
     /// # Arguments
@@ -709,9 +865,7 @@ impl<'a> Instruction<'a> {
     where
         F: FnMut(VCPUIndex, MemoryInfo, u64) + Send + Sync + 'static,
     {
-        let callback = Box::new(cb);
-        let callback_box = Box::new(callback);
-        let userdata = Box::into_raw(callback_box) as *mut c_void;
+        let userdata = Stamped::into_userdata(cb);
 
         unsafe {
             crate::sys::qemu_plugin_register_vcpu_mem_cb(
@@ -769,7 +923,7 @@ impl<'a> MemoryInfo<'a> {
 
     /// Return a handle to query details about the physical address backing the virtual address
     /// in system emulation. In user-mode, this method always returns `None`.
-    pub fn hwaddr(&self, vaddr: u64) -> Option<HwAddr> {
+    pub fn hwaddr(&self, vaddr: u64) -> Option<HwAddr<'_>> {
         let hwaddr = unsafe { crate::sys::qemu_plugin_get_hwaddr(self.memory_info, vaddr) };
         if hwaddr.is_null() {
             None
@@ -788,6 +942,7 @@ impl<'a> MemoryInfo<'a> {
 
 #[cfg(feature = "plugin-api-v4")]
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Memory value loaded/stored (in memory callback)
 ///
 /// Wrapper structure for a `qemu_plugin_mem_value`
@@ -823,26 +978,33 @@ impl From<qemu_plugin_mem_value> for MemValue {
     }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 /// Wrapper structure for a `qemu_plugin_register_descriptor`
 ///
 /// # Safety
 ///
 /// This structure is safe to use as long as the pointer is valid. The pointer is
 /// always opaque, and therefore may not be dereferenced.
+///
+/// With the `serde` feature, descriptors can be serialized (the name and feature are
+/// emitted, the handle is not). They cannot be deserialized, because a handle is only
+/// obtainable from QEMU and a descriptor without one cannot be read.
 pub struct RegisterDescriptor<'a> {
     /// Opaque handle to the register for retrieving the value with
     /// qemu_plugin_read_register
+    #[cfg_attr(feature = "serde", serde(skip))]
     handle: usize,
     /// The register name
     pub name: String,
     /// Optional feature descriptor
     pub feature: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     marker: PhantomData<&'a ()>,
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 impl<'a> From<qemu_plugin_reg_descriptor> for RegisterDescriptor<'a> {
     fn from(descriptor: qemu_plugin_reg_descriptor) -> Self {
         let name = unsafe { CStr::from_ptr(descriptor.name) }
@@ -870,7 +1032,7 @@ impl<'a> From<qemu_plugin_reg_descriptor> for RegisterDescriptor<'a> {
     }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 impl<'a> Debug for RegisterDescriptor<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("RegisterDescriptor")
@@ -880,7 +1042,7 @@ impl<'a> Debug for RegisterDescriptor<'a> {
     }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 impl<'a> RegisterDescriptor<'a> {
     /// Read a register value
     ///
@@ -997,7 +1159,7 @@ impl<'a> HwAddr<'a> {
     }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 /// A wrapper structure for a `qemu_plugin_scoreboard *`. This is a way of having one
 /// entry per VCPU, the count of which is managed automatically by QEMU. Keep in mind
 /// that additional entries *and* existing entries will be allocated and reallocated by
@@ -1011,7 +1173,7 @@ where
     marker: PhantomData<&'a T>,
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 impl<'a, T> Scoreboard<'a, T> {
     /// Allocate a new scoreboard object. This must be freed by calling
     /// `qemu_plugin_scoreboard_free` (or by being dropped).
@@ -1025,6 +1187,15 @@ impl<'a, T> Scoreboard<'a, T> {
         }
     }
 
+    /// Returns the `u64` at `offset` bytes into each entry of the scoreboard, for use with
+    /// inline operations and conditional callbacks
+    pub fn entry(&self, offset: usize) -> PluginU64 {
+        PluginU64 {
+            score: self.handle as *mut qemu_plugin_scoreboard,
+            offset,
+        }
+    }
+
     /// Returns a reference to entry of a scoreboard matching a given vcpu index. This address
     /// is only valid until the next call to `get` or `set`.
     pub fn find<'b>(&mut self, vcpu_index: VCPUIndex) -> &'b mut MaybeUninit<T> {
@@ -1037,14 +1208,14 @@ impl<'a, T> Scoreboard<'a, T> {
     }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 impl<'a, T> Default for Scoreboard<'a, T> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 impl<'a, T> Drop for Scoreboard<'a, T> {
     fn drop(&mut self) {
         unsafe {
@@ -1086,7 +1257,11 @@ extern "C" fn handle_qemu_plugin_uninstall_callback(id: qemu_plugin_id_t) {
 /// Handle the invocation of the reset callback by calling the stored
 /// callback closure, if one exists.
 extern "C" fn handle_qemu_plugin_reset_callback(id: qemu_plugin_id_t) {
-    if let Some(callback) = UNINSTALL_CALLBACK.get() {
+    // QEMU has removed every callback of the plugin, so any still held by blocks running
+    // on other vCPUs belong to the previous generation
+    generation::advance();
+
+    if let Some(callback) = RESET_CALLBACK.get() {
         if let Ok(mut callback) = callback.lock() {
             if let Some(callback) = callback.take() {
                 callback(id);
@@ -1229,9 +1404,11 @@ extern "C" fn handle_qemu_plugin_register_vcpu_tb_exec_cb<F>(
 ) where
     F: FnMut(VCPUIndex) + Send + Sync + 'static,
 {
-    let mut cb: Box<Box<F>> = unsafe { Box::from_raw(userdata as *mut _) };
-    cb(vcpu_index);
-    Box::leak(cb);
+    // NOTE: This memory is never freed, because QEMU may call this callback until the
+    // block is flushed
+    if let Some(cb) = unsafe { Stamped::<F>::current(userdata) } {
+        profiled!("translation_block_execute", cb(vcpu_index));
+    }
 }
 
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
@@ -1253,7 +1430,11 @@ where
     tb.register_execute_callback_flags(cb, flags);
 }
 
-#[cfg(not(any(feature = "plugin-api-v1", feature = "plugin-api-v2")))]
+#[cfg(not(any(
+    feature = "plugin-api-v0",
+    feature = "plugin-api-v1",
+    feature = "plugin-api-v2"
+)))]
 /// Register a callback to be conditionally called when a translation block is executed.
 ///
 /// # Arguments
@@ -1281,7 +1462,7 @@ pub fn qemu_plugin_register_vcpu_tb_exec_cond_cb<F>(
     tb.register_conditional_execute_callback_flags(cb, flags, cond, entry, immediate);
 }
 
-#[cfg(feature = "plugin-api-v1")]
+#[cfg(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 /// Register an inline callback to be called when a translation block is executed.
 ///
@@ -1307,7 +1488,7 @@ pub fn qemu_plugin_register_vcpu_tb_exec_inline(
     }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 /// Register an inline callback to be called when a translation block is executed.
 ///
@@ -1339,10 +1520,11 @@ extern "C" fn handle_qemu_plugin_register_vcpu_insn_exec_cb<F>(
 ) where
     F: FnMut(VCPUIndex) + Send + Sync + 'static,
 {
-    let mut cb: Box<Box<F>> = unsafe { Box::from_raw(userdata as *mut _) };
-    cb(vcpu_index);
-    // NOTE: This memory will be freed on plugin exit
-    Box::leak(cb);
+    // NOTE: This memory is never freed, because QEMU may call this callback until the
+    // block is flushed
+    if let Some(cb) = unsafe { Stamped::<F>::current(userdata) } {
+        profiled!("instruction_execute", cb(vcpu_index));
+    }
 }
 
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
@@ -1359,7 +1541,11 @@ where
     insn.register_execute_callback_flags(cb, flags);
 }
 
-#[cfg(not(any(feature = "plugin-api-v1", feature = "plugin-api-v2")))]
+#[cfg(not(any(
+    feature = "plugin-api-v0",
+    feature = "plugin-api-v1",
+    feature = "plugin-api-v2"
+)))]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 /// Register a callback to be conditionally called when an instruction is executed.
 ///
@@ -1383,7 +1569,7 @@ pub fn qemu_plugin_register_vcpu_insn_exec_cond_cb<F>(
     insn.register_conditional_execute_callback_flags(cb, flags, cond, entry, immediate);
 }
 
-#[cfg(feature = "plugin-api-v1")]
+#[cfg(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 /// Register an inline callback to be called when an instruction is executed.
 ///
@@ -1409,7 +1595,7 @@ pub fn qemu_plugin_register_vcpu_insn_exec_inline(
     }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 /// Register an inline callback to be called when an instruction is executed.
 ///
@@ -1443,11 +1629,12 @@ extern "C" fn handle_qemu_plugin_register_vcpu_mem_cb<F>(
 ) where
     F: FnMut(VCPUIndex, MemoryInfo, u64) + Send + Sync + 'static,
 {
-    let mut cb: Box<Box<F>> = unsafe { Box::from_raw(userdata as *mut _) };
-    let meminfo = MemoryInfo::from(meminfo);
-    cb(vcpu_index, meminfo, vaddr);
-    // NOTE: This memory will be freed on plugin exit
-    Box::leak(cb);
+    // NOTE: This memory is never freed, because QEMU may call this callback until the
+    // block is flushed
+    if let Some(cb) = unsafe { Stamped::<F>::current(userdata) } {
+        let meminfo = MemoryInfo::from(meminfo);
+        profiled!("memory_access", cb(vcpu_index, meminfo, vaddr));
+    }
 }
 
 /// Register a callback for every memory transaction of a particular instruction. If the
@@ -1469,7 +1656,7 @@ pub fn qemu_plugin_register_vcpu_mem_cb<F>(
     insn.register_memory_access_callback_flags(cb, rw, flags);
 }
 
-#[cfg(feature = "plugin-api-v1")]
+#[cfg(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 /// Register an inline callback for every memory transaction of a particular instruction.
 ///
@@ -1498,7 +1685,7 @@ pub fn qemu_plugin_register_vcpu_mem_inline(
     }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 /// Register an inline callback for every memory transaction of a particular instruction.
 ///
@@ -1532,7 +1719,10 @@ where
     F: FnOnce(qemu_plugin_id_t) + Send + Sync + 'static,
 {
     let cb: Box<Box<F>> = unsafe { Box::from_raw(userdata as *mut _) };
-    cb(id);
+    profiled!("atexit", cb(id));
+
+    #[cfg(feature = "self-profile")]
+    eprint!("{}", profile::report());
     // NOTE: This memory is not leaked because this is the last callback to be called
     // and it can only be called once, so we allow it to drop
 }
@@ -1634,14 +1824,15 @@ where
 }
 
 /// Return the path to the binary file being executed if running in user mode,
-/// or None if running in System mode. Return an error if the path cannot be
-/// converted to a string.
+/// or None if running in System mode. Bytes in the path are kept as-is on Unix hosts.
 pub fn qemu_plugin_path_to_binary() -> Result<Option<PathBuf>> {
     let path_str = unsafe { crate::sys::qemu_plugin_path_to_binary() };
     if path_str.is_null() {
         Ok(None)
     } else {
-        let path = unsafe { PathBuf::from(CStr::from_ptr(path_str).to_str()?) };
+        let path = PathBuf::from(path::os_string_from_bytes(unsafe {
+            CStr::from_ptr(path_str).to_bytes()
+        }));
         unsafe { g_free(path_str as *mut _) };
         Ok(Some(path))
     }
@@ -1679,6 +1870,7 @@ pub fn qemu_plugin_end_code() -> Option<u64> {
 /// running in user mode, or None if running in System mode. If not running in
 /// system mode, `None` may be interpreted as zero by callers, but the caller
 /// must take care to ensure the plugin is not running in a system mode context.
+#[cfg(not(feature = "plugin-api-v0"))]
 pub fn qemu_plugin_entry_code() -> Option<u64> {
     let entry = unsafe { crate::sys::qemu_plugin_entry_code() };
 
@@ -1689,7 +1881,7 @@ pub fn qemu_plugin_entry_code() -> Option<u64> {
     }
 }
 
-#[cfg(feature = "plugin-api-v1")]
+#[cfg(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))]
 /// Return the number of vCPUs, if running in system mode
 pub fn qemu_plugin_n_vcpus() -> Option<i32> {
     let vcpus = unsafe { crate::sys::qemu_plugin_n_vcpus() };
@@ -1701,7 +1893,7 @@ pub fn qemu_plugin_n_vcpus() -> Option<i32> {
     }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 /// Return the number of vCPUs, if running in system mode
 pub fn qemu_plugin_num_vcpus() -> Option<i32> {
     let vcpus = unsafe { crate::sys::qemu_plugin_num_vcpus() };
@@ -1713,7 +1905,7 @@ pub fn qemu_plugin_num_vcpus() -> Option<i32> {
     }
 }
 
-#[cfg(feature = "plugin-api-v1")]
+#[cfg(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))]
 /// Return the maximum number of vCPUs, if running in system mode
 pub fn qemu_plugin_n_max_vcpus() -> Option<i32> {
     let max_cpus = unsafe { crate::sys::qemu_plugin_n_max_vcpus() };
@@ -1725,7 +1917,7 @@ pub fn qemu_plugin_n_max_vcpus() -> Option<i32> {
     }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 /// Returns a potentially empty list of registers. This should be used from a
 /// qemu_plugin_register_vcpu_init_cb callback after the vcpu has been initialized.
 pub fn qemu_plugin_get_registers<'a>() -> Result<Vec<RegisterDescriptor<'a>>> {
@@ -1754,6 +1946,7 @@ pub fn qemu_plugin_get_registers<'a>() -> Result<Vec<RegisterDescriptor<'a>>> {
 }
 
 #[cfg(not(any(
+    feature = "plugin-api-v0",
     feature = "plugin-api-v1",
     feature = "plugin-api-v2",
     feature = "plugin-api-v3"
@@ -1775,26 +1968,26 @@ pub fn qemu_plugin_read_memory_vaddr(addr: u64, len: usize) -> Result<Vec<u8>> {
     }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 /// Add a value to a `PluginU64` for a given VCPU
 pub fn qemu_plugin_u64_add(entry: PluginU64, vcpu_index: VCPUIndex, added: u64) -> Result<()> {
     unsafe { crate::sys::qemu_plugin_u64_add(entry, vcpu_index, added) };
     Ok(())
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 /// Get the value of a `PluginU64` for a given VCPU
 pub fn qemu_plugin_u64_get(entry: PluginU64, vcpu_index: VCPUIndex) -> u64 {
     unsafe { crate::sys::qemu_plugin_u64_get(entry, vcpu_index) }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 /// Set the value of a `PluginU64` for a given VCPU
 pub fn qemu_plugin_u64_set(entry: PluginU64, vcpu_index: VCPUIndex, value: u64) {
     unsafe { crate::sys::qemu_plugin_u64_set(entry, vcpu_index, value) }
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 /// Get the sum of all VCPU entries in a scoreboard
 pub fn qemu_plugin_scoreboard_sum(entry: PluginU64) -> u64 {
     unsafe { crate::sys::qemu_plugin_u64_sum(entry) }
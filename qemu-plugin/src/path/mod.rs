@@ -0,0 +1,187 @@
+//! Conversion of paths between guest and host encodings, and creation of output files which
+//! behave the same on Windows and Unix hosts
+//!
+//! Paths read from guest memory are raw bytes in the guest's encoding: arbitrary bytes for
+//! POSIX guests and UTF-16LE for Windows guests. Plugin arguments are raw bytes from QEMU's
+//! command line. The helpers here turn both into host paths without losing bytes where the
+//! host can represent them, and open files for sinks with consistent sharing semantics.
+
+use std::{
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// The longest path Windows APIs accept without the `\\?\` prefix
+pub const WINDOWS_MAX_PATH: usize = 260;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// The path conventions of a guest or host
+pub enum PathStyle {
+    /// `/`-separated paths of arbitrary bytes
+    Posix,
+    /// `\`-separated paths of UTF-16 code units
+    Windows,
+}
+
+impl PathStyle {
+    /// The style of the host this plugin is running on
+    pub const fn host() -> Self {
+        if cfg!(windows) {
+            Self::Windows
+        } else {
+            Self::Posix
+        }
+    }
+
+    /// The preferred separator between path components
+    pub const fn separator(&self) -> char {
+        match self {
+            Self::Posix => '/',
+            Self::Windows => '\\',
+        }
+    }
+
+    /// Decode a NUL-terminated path read from guest memory in this style. Bytes after the
+    /// first NUL, and any trailing odd byte of a UTF-16 string, are ignored.
+    pub fn decode(&self, bytes: &[u8]) -> OsString {
+        match self {
+            Self::Posix => {
+                let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                os_string_from_bytes(&bytes[..end])
+            }
+            Self::Windows => OsString::from(decode_wide(bytes)),
+        }
+    }
+
+    /// Encode a path for writing to guest memory in this style, including the NUL terminator
+    pub fn encode(&self, path: &str) -> Vec<u8> {
+        match self {
+            Self::Posix => path.bytes().chain([0]).collect(),
+            Self::Windows => encode_wide(path),
+        }
+    }
+}
+
+/// Decode a NUL-terminated UTF-16LE string, as used by Windows guests. Unpaired surrogates
+/// are replaced with U+FFFD.
+pub fn decode_wide(bytes: &[u8]) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|u| *u != 0);
+
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Encode a string as NUL-terminated UTF-16LE, as used by Windows guests
+pub fn encode_wide(s: &str) -> Vec<u8> {
+    s.encode_utf16()
+        .chain([0])
+        .flat_map(|u| u.to_le_bytes())
+        .collect()
+}
+
+/// Convert raw bytes, such as a plugin argument or a path from a POSIX guest, to an
+/// `OsString`. Unix hosts keep the bytes as-is. Windows hosts expect UTF-8, as QEMU uses
+/// for its command line there, and replace invalid sequences.
+pub fn os_string_from_bytes(bytes: &[u8]) -> OsString {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        std::ffi::OsStr::from_bytes(bytes).to_os_string()
+    }
+
+    #[cfg(not(unix))]
+    {
+        OsString::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// Translate a guest path in `style` to a host path by replacing its separators with the
+/// host's. Drive letters and other prefixes are kept, so the result is only meaningful on
+/// the host if the guest's file system is mirrored there.
+pub fn guest_to_host(guest: &str, style: PathStyle) -> PathBuf {
+    let host = PathStyle::host();
+
+    if style == host {
+        PathBuf::from(guest)
+    } else {
+        PathBuf::from(guest.replace(style.separator(), &host.separator().to_string()))
+    }
+}
+
+/// Translate a host path to a guest path in `style` by replacing its separators
+pub fn host_to_guest(path: &Path, style: PathStyle) -> String {
+    let path = path.to_string_lossy();
+    let host = PathStyle::host();
+
+    if style == host {
+        path.into_owned()
+    } else {
+        path.replace(host.separator(), &style.separator().to_string())
+    }
+}
+
+/// Prepare a path for opening on the host. On Windows, paths at least `WINDOWS_MAX_PATH`
+/// long are made absolute and given the `\\?\` prefix, which lifts the length limit; on
+/// other hosts the path is returned unchanged.
+pub fn host_path<P>(path: P) -> io::Result<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+
+    if !cfg!(windows) || path.as_os_str().len() < WINDOWS_MAX_PATH {
+        return Ok(path.to_path_buf());
+    }
+
+    let path = std::path::absolute(path)?;
+    let path = path.to_string_lossy();
+
+    if path.starts_with(r"\\?\") {
+        return Ok(PathBuf::from(path.into_owned()));
+    }
+
+    // Verbatim paths are not normalized, so separators must already be backslashes
+    let path = path.replace('/', "\\");
+
+    Ok(PathBuf::from(match path.strip_prefix(r"\\") {
+        Some(unc) => format!(r"\\?\UNC\{unc}"),
+        None => format!(r"\\?\{path}"),
+    }))
+}
+
+/// Options for opening a file written by a sink. On Windows, other processes may read or
+/// delete the file while it is open, so a trace can be inspected while QEMU runs, but may
+/// not write to it. Unix hosts do not enforce sharing, so the options are left as they are.
+pub fn sink_options() -> OpenOptions {
+    #[allow(unused_mut)]
+    let mut options = OpenOptions::new();
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        // FILE_SHARE_READ | FILE_SHARE_DELETE
+        options.share_mode(0x1 | 0x4);
+    }
+
+    options
+}
+
+/// Create a file for a sink, replacing any existing file
+pub fn create_sink<P>(path: P) -> io::Result<File>
+where
+    P: AsRef<Path>,
+{
+    sink_options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(host_path(path)?)
+}
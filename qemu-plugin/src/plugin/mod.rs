@@ -4,7 +4,7 @@ use std::sync::{Mutex, OnceLock};
 
 use crate::{
     install::{Args, Info},
-    PluginId, TranslationBlock, VCPUIndex,
+    profiled, PluginId, TranslationBlock, VCPUIndex,
 };
 use crate::{
     qemu_plugin_register_flush_cb, qemu_plugin_register_vcpu_exit_cb,
@@ -22,9 +22,12 @@ extern "C" fn handle_qemu_plugin_register_vcpu_init_cb(id: PluginId, vcpu_id: VC
         panic!("Failed to lock plugin");
     };
 
-    plugin
-        .on_vcpu_init(id, vcpu_id)
-        .expect("Failed running callback on_vcpu_init");
+    profiled!(
+        "on_vcpu_init",
+        plugin
+            .on_vcpu_init(id, vcpu_id)
+            .expect("Failed running callback on_vcpu_init")
+    );
 }
 
 extern "C" fn handle_qemu_plugin_register_vcpu_exit_cb(id: PluginId, vcpu_id: VCPUIndex) {
@@ -36,9 +39,12 @@ extern "C" fn handle_qemu_plugin_register_vcpu_exit_cb(id: PluginId, vcpu_id: VC
         panic!("Failed to lock plugin");
     };
 
-    plugin
-        .on_vcpu_exit(id, vcpu_id)
-        .expect("Failed running callback on_vcpu_exit");
+    profiled!(
+        "on_vcpu_exit",
+        plugin
+            .on_vcpu_exit(id, vcpu_id)
+            .expect("Failed running callback on_vcpu_exit")
+    );
 }
 
 extern "C" fn handle_qemu_plugin_register_vcpu_idle_cb(id: PluginId, vcpu_id: VCPUIndex) {
@@ -50,9 +56,12 @@ extern "C" fn handle_qemu_plugin_register_vcpu_idle_cb(id: PluginId, vcpu_id: VC
         panic!("Failed to lock plugin");
     };
 
-    plugin
-        .on_vcpu_idle(id, vcpu_id)
-        .expect("Failed running callback on_vcpu_idle");
+    profiled!(
+        "on_vcpu_idle",
+        plugin
+            .on_vcpu_idle(id, vcpu_id)
+            .expect("Failed running callback on_vcpu_idle")
+    );
 }
 
 extern "C" fn handle_qemu_plugin_register_vcpu_resume_cb(id: PluginId, vcpu_id: VCPUIndex) {
@@ -64,9 +73,12 @@ extern "C" fn handle_qemu_plugin_register_vcpu_resume_cb(id: PluginId, vcpu_id:
         panic!("Failed to lock plugin");
     };
 
-    plugin
-        .on_vcpu_resume(id, vcpu_id)
-        .expect("Failed running callback on_vcpu_resume");
+    profiled!(
+        "on_vcpu_resume",
+        plugin
+            .on_vcpu_resume(id, vcpu_id)
+            .expect("Failed running callback on_vcpu_resume")
+    );
 }
 
 extern "C" fn handle_qemu_plugin_register_vcpu_tb_trans_cb(
@@ -83,12 +95,19 @@ extern "C" fn handle_qemu_plugin_register_vcpu_tb_trans_cb(
 
     let tb = TranslationBlock::from(tb);
 
-    plugin
-        .on_translation_block_translate(id, tb)
-        .expect("Failed running callback on_translation_block_translate");
+    profiled!(
+        "on_translation_block_translate",
+        plugin
+            .on_translation_block_translate(id, tb)
+            .expect("Failed running callback on_translation_block_translate")
+    );
 }
 
 extern "C" fn handle_qemu_plugin_register_flush_cb(id: PluginId) {
+    // Blocks translated before the flush are gone, so state rebuilt in `on_flush` is not
+    // reachable from callbacks registered on them
+    crate::generation::advance();
+
     let Some(plugin) = PLUGIN.get() else {
         panic!("Plugin not set");
     };
@@ -97,9 +116,12 @@ extern "C" fn handle_qemu_plugin_register_flush_cb(id: PluginId) {
         panic!("Failed to lock plugin");
     };
 
-    plugin
-        .on_flush(id)
-        .expect("Failed running callback on_flush");
+    profiled!(
+        "on_flush",
+        plugin
+            .on_flush(id)
+            .expect("Failed running callback on_flush")
+    );
 }
 
 extern "C" fn handle_qemu_plugin_register_syscall_cb(
@@ -123,9 +145,12 @@ extern "C" fn handle_qemu_plugin_register_syscall_cb(
         panic!("Failed to lock plugin");
     };
 
-    plugin
-        .on_syscall(id, vcpu_index, num, a1, a2, a3, a4, a5, a6, a7, a8)
-        .expect("Failed running callback on_syscall");
+    profiled!(
+        "on_syscall",
+        plugin
+            .on_syscall(id, vcpu_index, num, a1, a2, a3, a4, a5, a6, a7, a8)
+            .expect("Failed running callback on_syscall")
+    );
 }
 
 extern "C" fn handle_qemu_plugin_register_syscall_ret_cb(
@@ -142,9 +167,12 @@ extern "C" fn handle_qemu_plugin_register_syscall_ret_cb(
         panic!("Failed to lock plugin");
     };
 
-    plugin
-        .on_syscall_return(id, vcpu_index, num, ret)
-        .expect("Failed running callback on_syscall_return");
+    profiled!(
+        "on_syscall_return",
+        plugin
+            .on_syscall_return(id, vcpu_index, num, ret)
+            .expect("Failed running callback on_syscall_return")
+    );
 }
 
 /// Trait which implemenents registering the callbacks implemented on a struct which
@@ -257,7 +285,8 @@ pub trait HasCallbacks: Send + Sync + 'static {
     }
 
     #[allow(unused)]
-    /// Callback triggered on flush
+    /// Callback triggered on flush. Execution and memory callbacks registered before the
+    /// flush are no longer called, see [`crate::generation`].
     ///
     /// # Arguments
     ///
@@ -0,0 +1,124 @@
+//! Self-profiling of the time spent inside plugin callbacks
+//!
+//! When the `self-profile` feature is enabled, every callback dispatched by this crate is
+//! timed, and a breakdown is printed to stderr when the plugin's atexit callback finishes.
+//! Plugins can time their own code, such as output sinks, by declaring a static [`Section`]
+//! and running the code with [`Section::measure`].
+
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, Once,
+    },
+    time::{Duration, Instant},
+};
+
+static SECTIONS: Mutex<Vec<&'static Section>> = Mutex::new(Vec::new());
+
+/// A named section of code whose calls and total run time are accumulated
+///
+/// ```rust,ignore
+/// use qemu_plugin::profile::Section;
+///
+/// static SINK: Section = Section::new("sink");
+///
+/// let value = SINK.measure(|| 1 + 1);
+/// ```
+pub struct Section {
+    name: &'static str,
+    calls: AtomicU64,
+    nanos: AtomicU64,
+    registered: Once,
+}
+
+impl Section {
+    /// Create a new section with a name shown in the report
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            calls: AtomicU64::new(0),
+            nanos: AtomicU64::new(0),
+            registered: Once::new(),
+        }
+    }
+
+    /// Run `f`, adding its run time to this section
+    pub fn measure<T>(&'static self, f: impl FnOnce() -> T) -> T {
+        self.registered.call_once(|| {
+            if let Ok(mut sections) = SECTIONS.lock() {
+                sections.push(self);
+            }
+        });
+
+        let start = Instant::now();
+        let result = f();
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.nanos
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        result
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The accumulated measurements of a single section
+pub struct SectionReport {
+    /// The section name
+    pub name: &'static str,
+    /// The number of times the section ran
+    pub calls: u64,
+    /// The total time spent in the section
+    pub total: Duration,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// A breakdown of the time spent in each section, most expensive first
+pub struct Report(pub Vec<SectionReport>);
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<40} {:>14} {:>14} {:>12}",
+            "section", "calls", "total (ms)", "mean (ns)"
+        )?;
+
+        for section in &self.0 {
+            writeln!(
+                f,
+                "{:<40} {:>14} {:>14.3} {:>12}",
+                section.name,
+                section.calls,
+                section.total.as_secs_f64() * 1000.0,
+                section
+                    .total
+                    .as_nanos()
+                    .checked_div(section.calls as u128)
+                    .unwrap_or_default()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Collect the measurements of every section which has run at least once
+pub fn report() -> Report {
+    let mut sections = SECTIONS
+        .lock()
+        .map(|sections| {
+            sections
+                .iter()
+                .map(|s| SectionReport {
+                    name: s.name,
+                    calls: s.calls.load(Ordering::Relaxed),
+                    total: Duration::from_nanos(s.nanos.load(Ordering::Relaxed)),
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    sections.sort_by_key(|s| std::cmp::Reverse(s.total));
+
+    Report(sections)
+}
@@ -0,0 +1,139 @@
+//! Information about the guest CPU the emulator was configured with
+//!
+//! The plugin API does not report the CPU model directly, so [`TargetInfo::cpu`] recovers
+//! it from the same places QEMU reads it: the `-cpu` option on the emulator's command line,
+//! or the `QEMU_CPU` environment variable in user mode. Feature flags given alongside the
+//! model (e.g. `-cpu Skylake-Client,+avx512f,-sse4a,pdpe1gb=off`) are recorded too.
+//!
+//! Once a vCPU is initialized, the feature descriptors of its registers are a second source
+//! of truth and can be merged in with [`Cpu::add_registers`].
+
+use crate::install::Info;
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
+use crate::RegisterDescriptor;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env::var,
+    fs::read,
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// The configured guest CPU model, as far as it can be determined
+pub struct Cpu {
+    /// The CPU model name (e.g. `Skylake-Client` or `cortex-a72`), if one was given
+    pub model: Option<String>,
+    /// Feature flags given with the model, mapped to whether they were enabled or disabled
+    pub flags: BTreeMap<String, bool>,
+    /// Register feature descriptors reported by the vCPU (e.g. `org.gnu.gdb.i386.avx`)
+    pub features: BTreeSet<String>,
+}
+
+impl Cpu {
+    /// Parse a `-cpu` option value of the form `model[,+flag|,-flag|,flag=on|off...]`
+    pub fn parse(value: &str) -> Self {
+        let mut parts = value.split(',');
+        let model = parts
+            .next()
+            .filter(|model| !model.is_empty())
+            .map(str::to_string);
+        let flags = parts
+            .filter_map(|part| {
+                if let Some(flag) = part.strip_prefix('+') {
+                    Some((flag.to_string(), true))
+                } else if let Some(flag) = part.strip_prefix('-') {
+                    Some((flag.to_string(), false))
+                } else if let Some((flag, value)) = part.split_once('=') {
+                    match value {
+                        "on" | "true" | "yes" => Some((flag.to_string(), true)),
+                        "off" | "false" | "no" => Some((flag.to_string(), false)),
+                        // Properties such as `family=6` are not feature flags
+                        _ => None,
+                    }
+                } else if part.is_empty() {
+                    None
+                } else {
+                    Some((part.to_string(), true))
+                }
+            })
+            .collect();
+
+        Self {
+            model,
+            flags,
+            features: BTreeSet::new(),
+        }
+    }
+
+    /// Whether a flag was explicitly enabled with the model
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.get(flag).copied().unwrap_or(false)
+    }
+
+    /// Whether the vCPU reported a register feature descriptor
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+
+    #[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
+    /// Record the feature descriptors of a vCPU's registers
+    pub fn add_registers(&mut self, registers: &[RegisterDescriptor<'_>]) {
+        self.features.extend(
+            registers
+                .iter()
+                .filter_map(|register| register.feature.clone()),
+        );
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Information about the emulated target
+pub struct TargetInfo {
+    /// The target name of the simulation (e.g. `x86_64-softmmu`)
+    pub target_name: String,
+    /// Whether the emulator is running in full system emulation mode
+    pub system: bool,
+}
+
+impl From<&Info> for TargetInfo {
+    fn from(value: &Info) -> Self {
+        Self {
+            target_name: value.target_name.clone(),
+            system: value.system.is_some(),
+        }
+    }
+}
+
+impl TargetInfo {
+    /// The configured guest CPU, from the emulator's `-cpu` option or, in user mode, the
+    /// `QEMU_CPU` environment variable. If neither is set, QEMU uses its default model for
+    /// the target and an empty [`Cpu`] is returned.
+    pub fn cpu(&self) -> Cpu {
+        Self::cpu_option()
+            .or_else(|| (!self.system).then(|| var("QEMU_CPU").ok()).flatten())
+            .map(|value| Cpu::parse(&value))
+            .unwrap_or_default()
+    }
+
+    /// The value of the last `-cpu` option on the emulator's command line
+    fn cpu_option() -> Option<String> {
+        let cmdline = read("/proc/self/cmdline").ok()?;
+        let args = cmdline
+            .split(|byte| *byte == 0)
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect::<Vec<_>>();
+
+        args.windows(2)
+            .rev()
+            .find_map(|pair| match pair[0].as_str() {
+                "-cpu" | "--cpu" => Some(pair[1].clone()),
+                _ => None,
+            })
+            .or_else(|| {
+                args.iter()
+                    .rev()
+                    .find_map(|arg| arg.strip_prefix("-cpu=").map(str::to_string))
+            })
+    }
+}
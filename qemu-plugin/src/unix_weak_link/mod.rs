@@ -62,7 +62,25 @@ pub extern "C" fn qemu_plugin_register_vcpu_tb_exec_cb(
 ) {
 }
 
-#[cfg(feature = "plugin-api-v1")]
+#[cfg(not(any(
+    feature = "plugin-api-v0",
+    feature = "plugin-api-v1",
+    feature = "plugin-api-v2"
+)))]
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn qemu_plugin_register_vcpu_tb_exec_cond_cb(
+    _: *mut qemu_plugin_tb,
+    _: qemu_plugin_vcpu_udata_cb_t,
+    _: qemu_plugin_cb_flags,
+    _: qemu_plugin_cond,
+    _: qemu_plugin_u64,
+    _: u64,
+    _: *mut ::std::os::raw::c_void,
+) {
+}
+
+#[cfg(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_register_vcpu_tb_exec_inline(
@@ -73,7 +91,7 @@ pub extern "C" fn qemu_plugin_register_vcpu_tb_exec_inline(
 ) {
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_register_vcpu_tb_exec_inline_per_vcpu(
@@ -94,7 +112,7 @@ pub extern "C" fn qemu_plugin_register_vcpu_insn_exec_cb(
 ) {
 }
 
-#[cfg(feature = "plugin-api-v1")]
+#[cfg(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_register_vcpu_insn_exec_inline(
@@ -105,7 +123,7 @@ pub extern "C" fn qemu_plugin_register_vcpu_insn_exec_inline(
 ) {
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_register_vcpu_insn_exec_inline_per_vcpu(
@@ -229,7 +247,7 @@ pub extern "C" fn qemu_plugin_register_vcpu_mem_cb(
 ) {
 }
 
-#[cfg(feature = "plugin-api-v1")]
+#[cfg(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_register_vcpu_mem_inline(
@@ -241,7 +259,7 @@ pub extern "C" fn qemu_plugin_register_vcpu_mem_inline(
 ) {
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_register_vcpu_mem_inline_per_vcpu(
@@ -302,21 +320,21 @@ pub extern "C" fn qemu_plugin_register_atexit_cb(
 ) {
 }
 
-#[cfg(feature = "plugin-api-v1")]
+#[cfg(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_n_vcpus() -> ::std::os::raw::c_int {
     0
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_num_vcpus() -> ::std::os::raw::c_int {
     0
 }
 
-#[cfg(feature = "plugin-api-v1")]
+#[cfg(any(feature = "plugin-api-v0", feature = "plugin-api-v1"))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_n_max_vcpus() -> ::std::os::raw::c_int {
@@ -355,13 +373,14 @@ pub extern "C" fn qemu_plugin_end_code() -> u64 {
     0
 }
 
+#[cfg(not(feature = "plugin-api-v0"))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_entry_code() -> u64 {
     0
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_get_registers() -> *mut GArray {
@@ -369,6 +388,7 @@ pub extern "C" fn qemu_plugin_get_registers() -> *mut GArray {
 }
 
 #[cfg(not(any(
+    feature = "plugin-api-v0",
     feature = "plugin-api-v1",
     feature = "plugin-api-v2",
     feature = "plugin-api-v3"
@@ -379,7 +399,22 @@ pub extern "C" fn qemu_plugin_read_memory_vaddr(_: u64, _: *mut GByteArray, _: u
     false
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(
+    feature = "plugin-api-v0",
+    feature = "plugin-api-v1",
+    feature = "plugin-api-v2",
+    feature = "plugin-api-v3"
+)))]
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn qemu_plugin_mem_get_value(_: qemu_plugin_meminfo_t) -> qemu_plugin_mem_value {
+    qemu_plugin_mem_value {
+        type_: qemu_plugin_mem_value_type::QEMU_PLUGIN_MEM_VALUE_U8,
+        data: qemu_plugin_mem_value__bindgen_ty_1 { u8_: 0 },
+    }
+}
+
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_read_register(
@@ -389,19 +424,19 @@ pub extern "C" fn qemu_plugin_read_register(
     0
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_scoreboard_new(_: usize) -> *mut qemu_plugin_scoreboard {
     null_mut()
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_scoreboard_free(_: *mut qemu_plugin_scoreboard) {}
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_scoreboard_find(
@@ -411,24 +446,24 @@ pub extern "C" fn qemu_plugin_scoreboard_find(
     null_mut()
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_u64_add(_: qemu_plugin_u64, _: ::std::os::raw::c_uint, _: u64) {}
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_u64_get(_: qemu_plugin_u64, _: ::std::os::raw::c_uint) -> u64 {
     0
 }
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_u64_set(_: qemu_plugin_u64, _: ::std::os::raw::c_uint, _: u64) {}
 
-#[cfg(not(feature = "plugin-api-v1"))]
+#[cfg(not(any(feature = "plugin-api-v0", feature = "plugin-api-v1")))]
 #[no_mangle]
 #[linkage = "weak"]
 pub extern "C" fn qemu_plugin_u64_sum(_: qemu_plugin_u64) {}